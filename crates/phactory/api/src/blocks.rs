@@ -1,12 +1,17 @@
 use alloc::vec::Vec;
 use core::convert::TryFrom;
+use derive_more::Display;
 use parity_scale_codec::{Decode, Encode, FullCodec};
 use scale_info::TypeInfo;
+use sp_consensus_grandpa::GrandpaJustification;
 pub use sp_consensus_grandpa::{AuthorityList, ConsensusLog, GRANDPA_ENGINE_ID, ScheduledChange, SetId};
 
 pub use phala_trie_storage::ser::StorageChanges;
-use sp_core::U256;
-use sp_runtime::{generic::Header, traits::Hash as HashT};
+use sp_core::{twox_128, U256};
+use sp_runtime::{
+    generic::Header,
+    traits::{Hash as HashT, Header as HeaderT},
+};
 
 pub type StorageProof = Vec<Vec<u8>>;
 pub type StorageState = Vec<(Vec<u8>, Vec<u8>)>;
@@ -102,6 +107,185 @@ pub struct DispatchBlockReq {
     pub blocks: Vec<BlockHeaderWithChanges>,
 }
 
+/// Why a sync payload is rejected by the validated constructors.
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum PayloadError {
+    /// No header or block in the payload
+    Empty,
+    /// The authority set has no authority
+    EmptyAuthoritySet,
+    /// The storage proof has no node
+    EmptyProof,
+    /// The justification doesn't decode as a GRANDPA justification
+    InvalidJustification,
+    /// The justification finalizes another block than the header it comes with
+    #[display(fmt = "JustificationTargetMismatch(header={header}, target={target})")]
+    JustificationTargetMismatch {
+        header: chain::BlockNumber,
+        target: chain::BlockNumber,
+    },
+    /// The header or block numbers are not consecutive
+    #[display(fmt = "NonConsecutive(expected={expected}, actual={actual})")]
+    NonConsecutive {
+        expected: chain::BlockNumber,
+        actual: chain::BlockNumber,
+    },
+    /// A header's parent hash doesn't match the previous header
+    #[display(fmt = "ParentHashMismatch({_0})")]
+    ParentHashMismatch(chain::BlockNumber),
+    /// The authority set change comes without a justification on the last header
+    MissingJustification,
+    /// A header before the last one changes the authority set
+    #[display(fmt = "AuthoritySetChangeBeforeEnd({_0})")]
+    AuthoritySetChangeBeforeEnd(chain::BlockNumber),
+    /// The authority set change comes while the last header doesn't change the set
+    UnexpectedAuthoritySetChange,
+}
+
+impl AuthoritySetChange {
+    pub fn new(
+        authority_set: AuthoritySet,
+        authority_proof: StorageProof,
+    ) -> Result<Self, PayloadError> {
+        if authority_set.list.is_empty() {
+            return Err(PayloadError::EmptyAuthoritySet);
+        }
+        if authority_proof.is_empty() {
+            return Err(PayloadError::EmptyProof);
+        }
+        Ok(Self {
+            authority_set,
+            authority_proof,
+        })
+    }
+}
+
+impl HeaderToSync {
+    /// Pairs the header with its justification, which must finalize the header itself.
+    pub fn new(header: BlockHeader, justification: Option<Vec<u8>>) -> Result<Self, PayloadError> {
+        if let Some(justification) = &justification {
+            let justification =
+                GrandpaJustification::<BlockHeader>::decode(&mut &justification[..])
+                    .or(Err(PayloadError::InvalidJustification))?;
+            let commit = justification.commit;
+            if commit.target_number != header.number || commit.target_hash != header.hash() {
+                return Err(PayloadError::JustificationTargetMismatch {
+                    header: header.number,
+                    target: commit.target_number,
+                });
+            }
+        }
+        Ok(Self {
+            header,
+            justification,
+        })
+    }
+}
+
+/// The pallets writing their bookkeeping to the storage in every block, e.g. the block number and
+/// the timestamp.
+const BOOKKEEPING_PALLETS: &[&[u8]] = &[b"System", b"Timestamp"];
+
+impl BlockHeaderWithChanges {
+    pub fn new(block_header: BlockHeader, storage_changes: StorageChanges) -> Self {
+        Self {
+            block_header,
+            storage_changes,
+        }
+    }
+
+    /// Whether the block changes nothing but the bookkeeping of the System and Timestamp pallets,
+    /// leaving pRuntime nothing to do but move on to the next block. Such blocks can be coalesced
+    /// into one dispatch.
+    ///
+    /// The accounts in `System.Account` are not bookkeeping, since they hold the balances.
+    pub fn is_bookkeeping_only(&self) -> bool {
+        let changes = &self.storage_changes;
+        let accounts = [twox_128(b"System"), twox_128(b"Account")].concat();
+        let is_bookkeeping = |key: &[u8]| {
+            !key.starts_with(&accounts)
+                && BOOKKEEPING_PALLETS
+                    .iter()
+                    .any(|pallet| key.starts_with(&twox_128(pallet)))
+        };
+        changes.child_storage_changes.is_empty()
+            && changes
+                .main_storage_changes
+                .iter()
+                .all(|(key, _)| is_bookkeeping(key))
+    }
+}
+
+/// Checks the headers follow each other, by number and parent hash.
+pub fn validate_header_chain<'a>(
+    headers: impl IntoIterator<Item = &'a BlockHeader>,
+) -> Result<(), PayloadError> {
+    let mut prev: Option<&BlockHeader> = None;
+    for header in headers {
+        if let Some(prev) = prev {
+            if header.number != prev.number + 1 {
+                return Err(PayloadError::NonConsecutive {
+                    expected: prev.number + 1,
+                    actual: header.number,
+                });
+            }
+            if header.parent_hash != prev.hash() {
+                return Err(PayloadError::ParentHashMismatch(header.number));
+            }
+        }
+        prev = Some(header);
+    }
+    if prev.is_none() {
+        return Err(PayloadError::Empty);
+    }
+    Ok(())
+}
+
+/// Checks the relaychain headers to sync in a request, along with the authority set change.
+///
+/// The headers must form a chain, and the set change can only come with the justification of
+/// the last header, where pRuntime verifies it. Only the last header can schedule a set change,
+/// since the justifications of the blocks after it are signed by the new set.
+pub fn validate_headers_to_sync(
+    headers: &[HeaderToSync],
+    authority_set_change: Option<&AuthoritySetChange>,
+) -> Result<(), PayloadError> {
+    validate_header_chain(headers.iter().map(|h| &h.header))?;
+    let (last, init) = headers.split_last().ok_or(PayloadError::Empty)?;
+    if let Some(h) = init
+        .iter()
+        .find(|h| find_scheduled_change(&h.header).is_some())
+    {
+        return Err(PayloadError::AuthoritySetChangeBeforeEnd(h.header.number));
+    }
+    if authority_set_change.is_some() {
+        if last.justification.is_none() {
+            return Err(PayloadError::MissingJustification);
+        }
+        if find_scheduled_change(&last.header).is_none() {
+            return Err(PayloadError::UnexpectedAuthoritySetChange);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the blocks to dispatch in a request have consecutive numbers.
+///
+/// The parent hashes are not checked, since only the numbers of the headers are used while
+/// dispatching the blocks.
+pub fn validate_blocks(blocks: &[BlockHeaderWithChanges]) -> Result<(), PayloadError> {
+    let first = blocks.first().ok_or(PayloadError::Empty)?;
+    for (block, expected) in blocks.iter().zip(first.block_header.number..) {
+        if block.block_header.number != expected {
+            return Err(PayloadError::NonConsecutive {
+                expected,
+                actual: block.block_header.number,
+            });
+        }
+    }
+    Ok(())
+}
+
 pub mod compat {
     use alloc::string::String;
     use alloc::vec::Vec;
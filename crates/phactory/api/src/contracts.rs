@@ -68,3 +68,67 @@ pub enum QueryType {
 pub enum Response {
     Payload(Vec<u8>),
 }
+
+/// The max number of queries allowed in a single batch.
+pub const MAX_BATCH_QUERIES: usize = 64;
+
+/// The plain text of a batched query, carried in the encrypted data of a
+/// `ContractQueryBatchRequest`.
+#[derive(Debug, Encode, Decode)]
+pub struct BatchQuery {
+    /// A random byte array generated by the client, echoed back in the response.
+    pub nonce: [u8; 32],
+    /// The queries to be executed. At most `MAX_BATCH_QUERIES` entries.
+    pub queries: Vec<BatchQueryEntry>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct BatchQueryEntry {
+    /// The target contract id.
+    pub contract_id: [u8; 32],
+    pub query: Query,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub enum BatchQueryError {
+    /// The batch contains more than `MAX_BATCH_QUERIES` queries. It is reported for every entry
+    /// and none of them is executed.
+    TooManyQueries,
+    /// The target contract of the entry does not exist on this worker.
+    ContractNotFound,
+    /// The query failed to execute.
+    Query(QueryError),
+}
+
+impl std::error::Error for BatchQueryError {}
+impl std::fmt::Display for BatchQueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatchQueryError::TooManyQueries => write!(f, "Too many queries in the batch"),
+            BatchQueryError::ContractNotFound => write!(f, "Contract not found"),
+            BatchQueryError::Query(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// The plain text of a batched query response, carried in the encrypted data of a
+/// `ContractQueryBatchResponse`.
+///
+/// `results[i]` is the result of `queries[i]` in the request. A failed entry doesn't affect the
+/// others.
+#[derive(Debug, Encode, Decode)]
+pub struct BatchQueryResponse {
+    /// The nonce from the client.
+    pub nonce: [u8; 32],
+    pub results: Vec<Result<Response, BatchQueryError>>,
+}
+
+impl BatchQuery {
+    /// Returns `TooManyQueries` if the batch exceeds `MAX_BATCH_QUERIES`.
+    pub fn validate(&self) -> Result<(), BatchQueryError> {
+        if self.queries.len() > MAX_BATCH_QUERIES {
+            return Err(BatchQueryError::TooManyQueries);
+        }
+        Ok(())
+    }
+}
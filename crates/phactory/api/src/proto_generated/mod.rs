@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 mod protos_codec_extensions;
 #[allow(clippy::derive_partial_eq_without_eq, clippy::let_unit_value)]
 mod pruntime_rpc;
@@ -28,7 +30,67 @@ pub struct Info<'a> {
     pub ras: &'a str,
 }
 
+/// The optional features a pRuntime can advertise in [`PhactoryInfo::capabilities`], for the
+/// clients to adapt to without sniffing the version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// `SyncCombinedHeaders` is served.
+    CombinedHeaderSync,
+    /// `GetEgressMessagesPaged` is served.
+    PagedEgress,
+    /// Long runs of blocks without storage changes can be dispatched at a time.
+    CoalesceEmptyBlocks,
+    /// The sync rejections carry a `SyncErrorCode`.
+    TypedSyncErrors,
+    /// `GetCheckpointInfo` is served.
+    CheckpointInfo,
+    /// `LoadStorageProofAt` is served.
+    StorageProofAt,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::CombinedHeaderSync,
+        Capability::PagedEgress,
+        Capability::CoalesceEmptyBlocks,
+        Capability::TypedSyncErrors,
+        Capability::CheckpointInfo,
+        Capability::StorageProofAt,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::CombinedHeaderSync => "combined-header-sync",
+            Capability::PagedEgress => "paged-egress",
+            Capability::CoalesceEmptyBlocks => "coalesce-empty-blocks",
+            Capability::TypedSyncErrors => "typed-sync-errors",
+            Capability::CheckpointInfo => "checkpoint-info",
+            Capability::StorageProofAt => "storage-proof-at",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|cap| cap.as_str() == name)
+    }
+}
+
 impl PhactoryInfo {
+    /// Whether the pRuntime advertises the capability. Always false for the ones older than the
+    /// capability negotiation.
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities
+            .iter()
+            .any(|name| name == capability.as_str())
+    }
+
+    /// The known capabilities advertised, the unknown ones skipped.
+    pub fn known_capabilities(&self) -> Vec<Capability> {
+        self.capabilities
+            .iter()
+            .filter_map(|name| Capability::from_name(name))
+            .collect()
+    }
+
     pub fn debug_info(&self) -> Info {
         let mem = self.memory_usage.clone().unwrap_or_default();
         Info {
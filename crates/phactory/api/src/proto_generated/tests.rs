@@ -11,3 +11,17 @@ fn make_sure_do_not_store_attestation() {
     let response: InitRuntimeResponse = serde_json::from_str(&json).unwrap();
     assert_eq!(response.attestation, None);
 }
+
+#[test]
+fn unknown_capabilities_are_ignored() {
+    let info = PhactoryInfo {
+        capabilities: vec!["paged-egress".into(), "from-the-future".into()],
+        ..Default::default()
+    };
+    assert!(info.has_capability(Capability::PagedEgress));
+    assert!(!info.has_capability(Capability::CombinedHeaderSync));
+    assert_eq!(info.known_capabilities(), vec![Capability::PagedEgress]);
+    for capability in Capability::ALL {
+        assert_eq!(Capability::from_name(capability.as_str()), Some(capability));
+    }
+}
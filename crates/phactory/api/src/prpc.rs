@@ -1,5 +1,14 @@
 pub use crate::proto_generated::*;
 use alloc::vec::Vec;
+use parity_scale_codec::{Decode, Encode};
 use phala_types::messaging::{MessageOrigin, SignedMessage};
 pub use prpc::{client, server, Message};
 pub type EgressMessages = Vec<(MessageOrigin, Vec<SignedMessage>)>;
+
+/// The continuation token of the paged egress messages: the sender and the sequence of the first
+/// message of the next page. The senders are paged in their order.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct EgressCursor {
+    pub sender: MessageOrigin,
+    pub sequence: u64,
+}
@@ -1,8 +1,11 @@
 use alloc::boxed::Box;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use log::info;
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 use crate::prpc::{
     client::{Error as ClientError, RequestClient},
@@ -21,9 +24,110 @@ pub fn new_pruntime_client_no_log(base_url: String) -> PhactoryApiClient<RpcRequ
     PhactoryApiClient::new(RpcRequest::new(base_url).disable_log())
 }
 
+/// Creates a client connecting to pRuntime with the TLS settings.
+pub fn new_pruntime_client_with_tls(
+    base_url: String,
+    tls: &TlsConfig,
+) -> Result<PhactoryApiClient<RpcRequest>> {
+    Ok(PhactoryApiClient::new(RpcRequest::with_tls(base_url, tls)?))
+}
+
+/// Verifies the certificate of the pRuntime server, given in DER.
+///
+/// The certificate is checked in the TLS handshake, so that no request is sent to an unverified
+/// server. A certificate not verified yet fails the handshake, and is then verified out of it,
+/// e.g. fetching the collateral of its quote, the request retried once it passes.
+#[async_trait::async_trait]
+pub trait CertVerifier: Send + Sync {
+    /// Whether the certificate passed [`verify`](Self::verify) already. Called in the handshake.
+    fn is_verified(&self, cert: &[u8]) -> bool;
+    /// Verifies the certificate, to be accepted by [`is_verified`](Self::is_verified) once passed.
+    async fn verify(&self, cert: &[u8]) -> Result<()>;
+}
+
+/// Accepts the server certificates in the TLS handshake as told by a [`CertVerifier`], keeping
+/// the last one rejected for being unverified.
+struct HandshakeVerifier {
+    verifier: Arc<dyn CertVerifier>,
+    unverified: Mutex<Option<Vec<u8>>>,
+}
+
+impl HandshakeVerifier {
+    fn unverified(&self) -> Option<Vec<u8>> {
+        self.unverified.lock().unwrap().clone()
+    }
+}
+
+impl rustls::client::ServerCertVerifier for HandshakeVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        if self.verifier.is_verified(&end_entity.0) {
+            return Ok(rustls::client::ServerCertVerified::assertion());
+        }
+        *self.unverified.lock().unwrap() = Some(end_entity.0.clone());
+        Err(rustls::Error::General(
+            "The pRuntime certificate is not verified yet".into(),
+        ))
+    }
+}
+
+/// The rustls config verifying the server certificate with `verifier`, in place of the CAs.
+fn rustls_config(
+    verifier: Arc<HandshakeVerifier>,
+    client_identity: Option<&[u8]>,
+) -> Result<rustls::ClientConfig> {
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier);
+    let Some(pem) = client_identity else {
+        return Ok(builder.with_no_client_auth());
+    };
+    let mut certs = vec![];
+    let mut key = None;
+    for item in rustls_pemfile::read_all(&mut &pem[..]).context("Invalid client identity")? {
+        match item {
+            rustls_pemfile::Item::X509Certificate(cert) => certs.push(rustls::Certificate(cert)),
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) => key = Some(rustls::PrivateKey(der)),
+            _ => (),
+        }
+    }
+    let key = key.ok_or_else(|| anyhow!("No private key in the client identity"))?;
+    builder
+        .with_client_auth_cert(certs, key)
+        .context("Invalid client identity")
+}
+
+/// TLS settings of the https connections to pRuntime.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    /// PEM encoded CA certificate to trust besides the built-in roots.
+    pub ca_cert: Option<Vec<u8>>,
+    /// PEM encoded private key and certificate chain to authenticate the client with.
+    pub client_identity: Option<Vec<u8>>,
+    /// Verifies the server certificate in place of the CAs, e.g. a self-signed RA-TLS certificate.
+    pub cert_verifier: Option<Arc<dyn CertVerifier>>,
+}
+
+/// Records the requests sent to pRuntime, e.g. to replay them later.
+pub trait RequestRecorder: Send + Sync {
+    fn record(&self, path: &str, body: &[u8]);
+}
+
 pub struct RpcRequest {
     base_url: String,
     disable_log: bool,
+    client: reqwest::Client,
+    cert_verifier: Option<Arc<HandshakeVerifier>>,
+    recorder: Option<Arc<dyn RequestRecorder>>,
 }
 
 impl RpcRequest {
@@ -31,13 +135,53 @@ impl RpcRequest {
         Self {
             base_url,
             disable_log: false,
+            client: reqwest::Client::new(),
+            cert_verifier: None,
+            recorder: None,
+        }
+    }
+
+    pub fn with_tls(base_url: String, tls: &TlsConfig) -> Result<Self> {
+        let mut builder = reqwest::Client::builder();
+        let cert_verifier = tls.cert_verifier.clone().map(|verifier| {
+            Arc::new(HandshakeVerifier {
+                verifier,
+                unverified: Mutex::new(None),
+            })
+        });
+        if let Some(verifier) = &cert_verifier {
+            let config = rustls_config(verifier.clone(), tls.client_identity.as_deref())?;
+            builder = builder.use_preconfigured_tls(config);
+        } else {
+            if let Some(pem) = &tls.ca_cert {
+                let cert = reqwest::Certificate::from_pem(pem).context("Invalid CA certificate")?;
+                builder = builder.add_root_certificate(cert);
+            }
+            if let Some(pem) = &tls.client_identity {
+                let identity =
+                    reqwest::Identity::from_pem(pem).context("Invalid client identity")?;
+                builder = builder.identity(identity);
+            }
         }
+        Ok(Self {
+            base_url,
+            disable_log: false,
+            client: builder.build()?,
+            cert_verifier,
+            recorder: None,
+        })
     }
 
     pub fn disable_log(mut self) -> Self {
         self.disable_log = true;
         self
     }
+
+    /// Records every request before it's sent.
+    pub fn with_recorder(mut self, recorder: Arc<dyn RequestRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -47,14 +191,32 @@ impl RequestClient for RpcRequest {
             ClientError::RpcError(err.to_string())
         }
 
+        if let Some(recorder) = &self.recorder {
+            recorder.record(path, &body);
+        }
         let url = alloc::format!("{}/prpc/{path}", self.base_url);
-        let res = reqwest::Client::new()
+        let request = self
+            .client
             .post(url)
             .header("Connection", "close")
-            .body(body)
-            .send()
-            .await
-            .map_err(from_display)?;
+            .body(body);
+        // Shares the body, sent again only if the handshake failed, so never sent twice.
+        let retry = request.try_clone();
+        let res = match (request.send().await, &self.cert_verifier, retry) {
+            (Ok(res), _, _) => res,
+            (Err(err), Some(verifier), Some(retry)) if err.is_connect() => {
+                let Some(cert) = verifier.unverified() else {
+                    return Err(from_display(err));
+                };
+                verifier
+                    .verifier
+                    .verify(&cert)
+                    .await
+                    .map_err(|err| from_display(alloc::format!("{err:#}")))?;
+                retry.send().await.map_err(from_display)?
+            }
+            (Err(err), _, _) => return Err(from_display(err)),
+        };
 
         if !self.disable_log {
             info!("{path}: {}", res.status());
@@ -1,5 +1,6 @@
 use super::blocks::{
-    AuthoritySetChange, BlockHeaderWithChanges, HeaderToSync, RuntimeHasher, StorageProof,
+    validate_headers_to_sync, AuthoritySetChange, BlockHeaderWithChanges, HeaderToSync,
+    RuntimeHasher, StorageProof,
 };
 
 use alloc::string::String;
@@ -32,6 +33,8 @@ pub enum Error {
     HeaderHashMismatch,
     /// The end block number mismatch the expecting next block number
     BlockNumberMismatch,
+    /// The block has already been dispatched
+    BlockTooOld,
     /// No state root to validate the storage changes
     NoStateRoot,
     /// Invalid storage changes that cause the state root mismatch
@@ -48,13 +51,100 @@ pub enum Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    pub fn code(&self) -> SyncErrorCode {
+        match self {
+            Error::EmptyRequest | Error::ChainModeMismatch | Error::CannotLoadStateAfterSyncing => {
+                SyncErrorCode::InvalidRequest
+            }
+            Error::MissingJustification | Error::HeaderValidateFailed(_) => {
+                SyncErrorCode::JustificationInvalid
+            }
+            Error::StorageProofFailed(_) => SyncErrorCode::StorageProofInvalid,
+            Error::RelaychainHeaderNotSynced | Error::BlockNumberMismatch | Error::NoStateRoot => {
+                SyncErrorCode::OutOfOrder
+            }
+            Error::BlockTooOld => SyncErrorCode::HeaderTooOld,
+            Error::HeaderHashMismatch => SyncErrorCode::HeaderHashMismatch,
+            Error::StateRootMismatch { .. } => SyncErrorCode::StateRootMismatch,
+        }
+    }
+}
+
+/// Typed reasons of the sync rejections, carried as the code of the prpc errors so that the
+/// syncers can decide whether to retry, skip or rollback without matching the messages.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SyncErrorCode {
+    /// The request is malformed or not applicable to the chain mode
+    InvalidRequest = 1,
+    /// The headers or blocks are behind what pRuntime has synced
+    HeaderTooOld = 2,
+    /// The headers or blocks don't start at the next number pRuntime expects
+    OutOfOrder = 3,
+    /// The justification is missing or fails the GRANDPA validation
+    JustificationInvalid = 4,
+    /// The storage proof doesn't match the relaychain state
+    StorageProofInvalid = 5,
+    /// A header's parent hash doesn't match the previous header
+    HeaderHashMismatch = 6,
+    /// Applying the storage changes doesn't give the state root in the header
+    StateRootMismatch = 7,
+    /// The request is rejected because pRuntime is in safe mode
+    SafeMode = 8,
+    /// pRuntime is not initialized yet
+    NotInitialized = 9,
+    /// pRuntime is busy, e.g. in the middle of a RCU dispatching
+    Busy = 10,
+}
+
+impl SyncErrorCode {
+    pub fn from_u32(code: u32) -> Option<Self> {
+        use SyncErrorCode::*;
+        [
+            InvalidRequest,
+            HeaderTooOld,
+            OutOfOrder,
+            JustificationInvalid,
+            StorageProofInvalid,
+            HeaderHashMismatch,
+            StateRootMismatch,
+            SafeMode,
+            NotInitialized,
+            Busy,
+        ]
+        .iter()
+        .copied()
+        .find(|c| *c as u32 == code)
+    }
+
+    /// Extracts the code from an error returned by a pRuntime client.
+    pub fn from_client_error(err: &prpc::client::Error) -> Option<Self> {
+        match err {
+            prpc::client::Error::ServerError(err) => Self::from_u32(err.code),
+            _ => None,
+        }
+    }
+
+    /// Whether the request can succeed after the syncer re-reads the pRuntime progress.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            SyncErrorCode::HeaderTooOld | SyncErrorCode::OutOfOrder | SyncErrorCode::Busy
+        )
+    }
+}
+
 pub trait BlockValidator {
+    /// Verifies the justification of `header`, and moves to the next authority set if the header
+    /// schedules a change, checked against the proof of the new set if given.
     fn submit_finalized_headers(
         &mut self,
         bridge_id: u64,
         header: chain::Header,
         ancestry_proof: Vec<chain::Header>,
         grandpa_proof: Vec<u8>,
+        authority_set_change: Option<AuthoritySetChange>,
     ) -> Result<()>;
 
     fn validate_storage_proof(
@@ -133,7 +223,7 @@ where
     pub fn sync_header(
         &mut self,
         mut headers: Vec<HeaderToSync>,
-        _authority_set_change: Option<AuthoritySetChange>,
+        authority_set_change: Option<AuthoritySetChange>,
         state_roots: &mut VecDeque<Hash>,
         skip_blocks_below: chain::BlockNumber,
     ) -> Result<chain::BlockNumber> {
@@ -170,19 +260,24 @@ where
                     return Err(Error::HeaderHashMismatch);
                 }
             }
-            // 3. generate accenstor proof
+            // 3. only the last header can change the authority set, and the proof of the new set
+            // comes with it
+            validate_headers_to_sync(&headers, authority_set_change.as_ref())
+                .map_err(|err| Error::HeaderValidateFailed(err.to_string()))?;
+            // 4. generate accenstor proof
             let mut accenstor_proof: Vec<_> = headers[0..headers.len() - 1]
                 .iter()
                 .map(|h| h.header.clone())
                 .collect();
             accenstor_proof.reverse(); // from high to low
-                                       // 4. submit to light client
+                                       // 5. submit to light client
             let bridge_id = self.main_bridge;
             self.validator.submit_finalized_headers(
                 bridge_id,
                 last_header,
                 accenstor_proof,
                 justification,
+                authority_set_change,
             )?;
         }
 
@@ -207,6 +302,9 @@ where
         storage: &mut Storage,
         drop_proofs: bool,
     ) -> Result<()> {
+        if block.block_header.number < self.block_number_next {
+            return Err(Error::BlockTooOld);
+        }
         if block.block_header.number != self.block_number_next {
             return Err(Error::BlockNumberMismatch);
         }
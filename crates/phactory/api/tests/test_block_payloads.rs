@@ -0,0 +1,280 @@
+//! Property tests of the validated constructors of the sync payloads, over random chains.
+
+use parity_scale_codec::Encode;
+use phactory_api::blocks::{
+    validate_blocks, validate_header_chain, validate_headers_to_sync, AuthoritySet,
+    AuthoritySetChange, BlockHeader, BlockHeaderWithChanges, HeaderToSync, PayloadError,
+    StorageChanges,
+};
+use proptest::{collection::vec, prelude::*, sample::Index};
+use sp_consensus_grandpa::{
+    AuthorityId, Commit, ConsensusLog, GrandpaJustification, ScheduledChange, GRANDPA_ENGINE_ID,
+};
+use sp_core::{ed25519, twox_128, H256};
+use sp_runtime::{traits::Header as _, DigestItem};
+
+/// Chains of 1 to 31 headers linked up by their parent hashes.
+fn chains() -> impl Strategy<Value = Vec<BlockHeader>> {
+    (
+        1u32..1_000_000,
+        any::<[u8; 32]>(),
+        vec((any::<[u8; 32]>(), any::<[u8; 32]>()), 1..32),
+    )
+        .prop_map(|(first, parent_hash, roots)| {
+            let mut parent_hash = H256(parent_hash);
+            roots
+                .into_iter()
+                .zip(first..)
+                .map(|((state_root, extrinsics_root), number)| {
+                    let header = BlockHeader {
+                        number,
+                        parent_hash,
+                        state_root: H256(state_root),
+                        extrinsics_root: H256(extrinsics_root),
+                        digest: Default::default(),
+                    };
+                    parent_hash = header.hash();
+                    header
+                })
+                .collect()
+        })
+}
+
+fn justification(header: &BlockHeader) -> Vec<u8> {
+    GrandpaJustification::<BlockHeader> {
+        round: 1,
+        commit: Commit::<BlockHeader> {
+            target_hash: header.hash(),
+            target_number: header.number,
+            precommits: vec![],
+        },
+        votes_ancestries: vec![],
+    }
+    .encode()
+}
+
+fn headers_to_sync(chain: &[BlockHeader]) -> Vec<HeaderToSync> {
+    let last = chain.len() - 1;
+    chain
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            let justification = (i == last).then(|| justification(header));
+            HeaderToSync::new(header.clone(), justification).unwrap()
+        })
+        .collect()
+}
+
+fn authority_set_change() -> AuthoritySetChange {
+    let authority = AuthorityId::from(ed25519::Public::from_raw([1; 32]));
+    AuthoritySetChange::new(
+        AuthoritySet {
+            list: vec![(authority, 1)],
+            id: 1,
+        },
+        vec![vec![0]],
+    )
+    .unwrap()
+}
+
+/// Makes the `i`th header schedule an authority set change, linking the headers after it again.
+fn schedule_change(chain: &mut [BlockHeader], i: usize) {
+    let change = ConsensusLog::<u32>::ScheduledChange(ScheduledChange {
+        next_authorities: authority_set_change().authority_set.list,
+        delay: 0,
+    });
+    chain[i]
+        .digest
+        .push(DigestItem::Consensus(GRANDPA_ENGINE_ID, change.encode()));
+    let mut parent_hash = chain[i].hash();
+    for header in &mut chain[i + 1..] {
+        header.parent_hash = parent_hash;
+        parent_hash = header.hash();
+    }
+}
+
+proptest! {
+    #[test]
+    fn accepts_chains(mut chain in chains()) {
+        prop_assert_eq!(validate_header_chain(&chain), Ok(()));
+        let headers = headers_to_sync(&chain);
+        prop_assert_eq!(validate_headers_to_sync(&headers, None), Ok(()));
+
+        schedule_change(&mut chain, chain.len() - 1);
+        let headers = headers_to_sync(&chain);
+        prop_assert_eq!(validate_headers_to_sync(&headers, None), Ok(()));
+        prop_assert_eq!(
+            validate_headers_to_sync(&headers, Some(&authority_set_change())),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_authority_set_changes_before_the_end(
+        mut chain in chains().prop_filter("too short", |c| c.len() >= 2),
+        i in any::<Index>(),
+    ) {
+        let i = i.index(chain.len() - 1);
+        let number = chain[i].number;
+        schedule_change(&mut chain, i);
+        let headers = headers_to_sync(&chain);
+        prop_assert_eq!(validate_header_chain(&chain), Ok(()));
+        prop_assert_eq!(
+            validate_headers_to_sync(&headers, None),
+            Err(PayloadError::AuthoritySetChangeBeforeEnd(number))
+        );
+    }
+
+    #[test]
+    fn rejects_authority_set_change_not_scheduled(chain in chains()) {
+        let headers = headers_to_sync(&chain);
+        prop_assert_eq!(
+            validate_headers_to_sync(&headers, Some(&authority_set_change())),
+            Err(PayloadError::UnexpectedAuthoritySetChange)
+        );
+    }
+
+    #[test]
+    fn rejects_gaps(
+        mut chain in chains().prop_filter("too short", |c| c.len() >= 3),
+        i in any::<Index>(),
+    ) {
+        let removed = chain.remove(1 + i.index(chain.len() - 2));
+        prop_assert_eq!(
+            validate_header_chain(&chain),
+            Err(PayloadError::NonConsecutive {
+                expected: removed.number,
+                actual: removed.number + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_forks(
+        mut chain in chains().prop_filter("too short", |c| c.len() >= 2),
+        i in any::<Index>(),
+    ) {
+        let i = 1 + i.index(chain.len() - 1);
+        chain[i].parent_hash.0[0] ^= 1;
+        let number = chain[i].number;
+        prop_assert_eq!(
+            validate_header_chain(&chain),
+            Err(PayloadError::ParentHashMismatch(number))
+        );
+    }
+
+    #[test]
+    fn rejects_justifications_of_other_headers(
+        chain in chains(),
+        i in any::<Index>(),
+        garbage in vec(any::<u8>(), 0..8),
+    ) {
+        let header = i.get(&chain);
+        let mut other = header.clone();
+        other.state_root.0[0] ^= 1;
+        prop_assert_eq!(
+            HeaderToSync::new(header.clone(), Some(justification(&other))).unwrap_err(),
+            PayloadError::JustificationTargetMismatch {
+                header: header.number,
+                target: header.number,
+            }
+        );
+        prop_assert_eq!(
+            HeaderToSync::new(header.clone(), Some(garbage)).unwrap_err(),
+            PayloadError::InvalidJustification
+        );
+    }
+
+    #[test]
+    fn requires_justification_for_authority_set_change(chain in chains()) {
+        let headers = chain
+            .iter()
+            .map(|header| HeaderToSync::new(header.clone(), None).unwrap())
+            .collect::<Vec<_>>();
+        prop_assert_eq!(validate_headers_to_sync(&headers, None), Ok(()));
+        prop_assert_eq!(
+            validate_headers_to_sync(&headers, Some(&authority_set_change())),
+            Err(PayloadError::MissingJustification)
+        );
+    }
+
+    #[test]
+    fn validates_block_numbers(chain in chains(), i in any::<Index>()) {
+        let mut blocks = chain
+            .into_iter()
+            .map(|header| BlockHeaderWithChanges::new(header, Default::default()))
+            .collect::<Vec<_>>();
+        prop_assert_eq!(validate_blocks(&blocks), Ok(()));
+        if blocks.len() >= 2 {
+            let i = i.index(blocks.len() - 1);
+            blocks.swap(i, i + 1);
+            prop_assert!(matches!(
+                validate_blocks(&blocks),
+                Err(PayloadError::NonConsecutive { .. })
+            ));
+        }
+    }
+}
+
+#[test]
+fn rejects_empty_payloads() {
+    assert_eq!(
+        validate_header_chain(&[] as &[BlockHeader]),
+        Err(PayloadError::Empty)
+    );
+    assert_eq!(
+        validate_headers_to_sync(&[], None),
+        Err(PayloadError::Empty)
+    );
+    assert_eq!(validate_blocks(&[]), Err(PayloadError::Empty));
+
+    let set = authority_set_change().authority_set;
+    assert_eq!(
+        AuthoritySetChange::new(set, vec![]).unwrap_err(),
+        PayloadError::EmptyProof
+    );
+    assert_eq!(
+        AuthoritySetChange::new(
+            AuthoritySet {
+                list: vec![],
+                id: 1
+            },
+            vec![vec![0]]
+        )
+        .unwrap_err(),
+        PayloadError::EmptyAuthoritySet
+    );
+}
+
+#[test]
+fn tells_blocks_only_changing_the_bookkeeping() {
+    let key = |pallet: &str, item: &str| {
+        [twox_128(pallet.as_bytes()), twox_128(item.as_bytes())].concat()
+    };
+    let block = |keys: &[Vec<u8>]| {
+        let header = BlockHeader {
+            number: 1,
+            parent_hash: H256::zero(),
+            state_root: H256::zero(),
+            extrinsics_root: H256::zero(),
+            digest: Default::default(),
+        };
+        let changes = StorageChanges {
+            main_storage_changes: keys.iter().map(|k| (k.clone(), Some(vec![1]))).collect(),
+            child_storage_changes: vec![],
+        };
+        BlockHeaderWithChanges::new(header, changes)
+    };
+    let number = key("System", "Number");
+    let now = key("Timestamp", "Now");
+
+    assert!(block(&[]).is_bookkeeping_only());
+    assert!(block(&[number.clone(), now.clone()]).is_bookkeeping_only());
+    let account = [key("System", "Account"), vec![0; 48]].concat();
+    assert!(!block(&[number.clone(), account]).is_bookkeeping_only());
+    assert!(!block(&[now.clone(), key("Balances", "TotalIssuance")]).is_bookkeeping_only());
+
+    let mut with_child = block(&[number, now]);
+    with_child.storage_changes.child_storage_changes = vec![(b"child".to_vec(), vec![])];
+    assert!(!with_child.is_bookkeeping_only());
+}
@@ -150,6 +150,8 @@ pub struct Contract {
     pub sidevm_info: Option<SidevmInfo>,
     weight: u32,
     on_block_end: Option<OnBlockEnd>,
+    #[serde(default)]
+    schedules: Schedules,
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize, ::scale_info::TypeInfo)]
@@ -175,6 +177,7 @@ impl Contract {
             sidevm_info: None,
             weight: 0,
             on_block_end: None,
+            schedules: Default::default(),
         }
     }
 
@@ -225,7 +228,18 @@ impl Contract {
         else {
             return Ok(None);
         };
+        self.call_hook(env, selector, gas_limit)
+    }
 
+    /// Calls the selectors due at the current block, see [`Schedules::take_due`].
+    pub(crate) fn run_schedules(&mut self, env: &mut ExecuteEnv) -> Vec<TransactionResult> {
+        let due = self.schedules.take_due(env.block.block_number);
+        due.into_iter()
+            .map(|schedule| self.call_hook(env, schedule.selector, schedule.gas_limit))
+            .collect()
+    }
+
+    fn call_hook(&self, env: &mut ExecuteEnv, selector: u32, gas_limit: u64) -> TransactionResult {
         let input_data = selector.to_be_bytes();
         let tx_args = TransactionArguments {
             origin: self.address.clone(),
@@ -252,6 +266,17 @@ impl Contract {
         });
     }
 
+    pub(crate) fn set_schedule(
+        &mut self,
+        selector: u32,
+        interval: BlockNumber,
+        gas_limit: u64,
+        current_block: BlockNumber,
+    ) -> Result<()> {
+        self.schedules
+            .set(selector, interval, gas_limit, current_block)
+    }
+
     pub(crate) fn start_sidevm(
         &mut self,
         spawner: &sidevm::service::Spawner,
@@ -499,6 +524,8 @@ impl Contract {
                     },
                 }
             }),
+            stats: None,
+            instantiated_at: 0,
         }
     }
 }
@@ -701,4 +728,6 @@ pub fn block_on_run_module(
 }
 
 pub use keeper::*;
+pub use scheduler::*;
 mod keeper;
+mod scheduler;
@@ -1,20 +1,296 @@
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, bail, Context, Result};
+use once_cell::sync::OnceCell;
+use phala_mq::{
+    checkpoint_helper::{global_send_mq, subscribe_default},
+    ChannelState, MessageDispatcher, MessageOrigin, MessageSendQueue, TypedReceiver,
+};
+use phala_types::contract::{command_topic, ConvertTo};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sidevm::service::Spawner;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::Duration,
+};
 
+use phactory_api::prpc as pb;
 use pink_loader::{
     local_cache,
-    types::{AccountId, BlockNumber},
+    types::{AccountId, BlockNumber, Hash},
 };
 
+use super::RawData;
 use crate::{
     contracts::Contract,
     im_helpers::{ordmap_for_each_mut, OrdMap},
+    secret_channel::Payload,
 };
 
 // size_of::<Contract>() == 1064, if we don't box it, it would exceed the stack capacity
 // when inserting data, even if we have an 8MB stack size. Not sure why the OrdMap::insert
 // increases the size so significantly.
-type ContractMap = OrdMap<AccountId, Box<Contract>>;
+type ContractMap = OrdMap<AccountId, LazyContract>;
+
+/// A contract in the keeper.
+///
+/// The contracts restored from a checkpoint are kept serialized until first accessed, so that
+/// loading a checkpoint doesn't take time proportional to the state of all the contracts while
+/// most of them are idle.
+///
+/// A contract failing to decode is kept as restored, to be saved back to the checkpoints as is,
+/// but left out of the keeper as if it didn't exist.
+#[derive(Clone)]
+struct LazyContract {
+    decoded: OnceCell<Result<Box<Contract>, String>>,
+    encoded: Option<Arc<EncodedContract>>,
+}
+
+/// The state of a contract restored from a checkpoint, along with what is needed to decode it
+/// outside of the checkpoint loading.
+struct EncodedContract {
+    summary: ContractSummary,
+    data: Vec<u8>,
+    send_mq: MessageSendQueue,
+    /// Subscribed on load, so that no command is missed before the contract is decoded.
+    cmd_receiver: TypedReceiver<Payload<RawData>>,
+}
+
+/// What the keeper needs to know about a contract without decoding it.
+#[derive(Serialize, Deserialize, Clone)]
+struct ContractSummary {
+    address: AccountId,
+    weight: u32,
+    on_block_end: bool,
+    sidevm: bool,
+    #[serde(default)]
+    next_schedule: Option<BlockNumber>,
+}
+
+impl ContractSummary {
+    fn of(contract: &Contract) -> Self {
+        Self {
+            address: contract.address.clone(),
+            weight: contract.weight,
+            on_block_end: contract.on_block_end.is_some(),
+            sidevm: contract.sidevm_info.is_some(),
+            next_schedule: contract.schedules.next_due(),
+        }
+    }
+}
+
+impl LazyContract {
+    fn new(contract: Contract) -> Self {
+        Self {
+            decoded: OnceCell::with_value(Ok(Box::new(contract))),
+            encoded: None,
+        }
+    }
+
+    /// Decodes the contract on first access, the failure being remembered.
+    fn get(&self) -> Result<&Contract> {
+        let decoded = self.decoded.get_or_init(|| {
+            let Some(encoded) = &self.encoded else {
+                return Err("BUG: contract neither decoded nor encoded".into());
+            };
+            let address = &encoded.summary.address;
+            match encoded.decode() {
+                Ok(contract) => {
+                    debug!("Decoded contract {address:?} restored from checkpoint");
+                    Ok(Box::new(contract))
+                }
+                Err(err) => {
+                    error!("Failed to decode contract {address:?}, leaving it out: {err:?}");
+                    Err(format!("{err:?}"))
+                }
+            }
+        });
+        match decoded {
+            Ok(contract) => Ok(contract),
+            Err(err) => Err(anyhow!("Failed to decode the contract: {err}")),
+        }
+    }
+
+    fn get_mut(&mut self) -> Result<&mut Contract> {
+        self.get()?;
+        self.encoded = None;
+        match self.decoded.get_mut() {
+            Some(Ok(contract)) => Ok(contract),
+            _ => Err(anyhow!("BUG: contract not decoded")),
+        }
+    }
+
+    fn into_inner(mut self) -> Result<Contract> {
+        self.get()?;
+        match self.decoded.take() {
+            Some(Ok(contract)) => Ok(*contract),
+            _ => Err(anyhow!("BUG: contract not decoded")),
+        }
+    }
+
+    /// The contract if decoded already.
+    fn decoded(&self) -> Option<&Contract> {
+        self.decoded.get()?.as_deref().ok()
+    }
+
+    /// The contract still to be decoded, if any.
+    fn pending(&self) -> Option<&EncodedContract> {
+        match self.decoded.get() {
+            Some(_) => None,
+            None => self.encoded.as_deref(),
+        }
+    }
+
+    fn weight(&self) -> u32 {
+        match (self.decoded(), self.pending()) {
+            (Some(contract), _) => contract.weight,
+            (_, Some(encoded)) => encoded.summary.weight,
+            _ => 0,
+        }
+    }
+
+    fn has_on_block_end(&self) -> bool {
+        match (self.decoded(), self.pending()) {
+            (Some(contract), _) => contract.on_block_end.is_some(),
+            (_, Some(encoded)) => encoded.summary.on_block_end,
+            _ => false,
+        }
+    }
+
+    fn next_schedule(&self) -> Option<BlockNumber> {
+        match (self.decoded(), self.pending()) {
+            (Some(contract), _) => contract.schedules.next_due(),
+            (_, Some(encoded)) => encoded.summary.next_schedule,
+            _ => None,
+        }
+    }
+
+    fn has_sidevm(&self) -> bool {
+        match (self.decoded(), self.pending()) {
+            (Some(contract), _) => contract.sidevm_info.is_some(),
+            (_, Some(encoded)) => encoded.summary.sidevm,
+            _ => false,
+        }
+    }
+}
+
+impl EncodedContract {
+    fn decode(&self) -> Result<Contract> {
+        let mut send_mq = self.send_mq.clone();
+        // The command receiver was subscribed on load, the one subscribed here is replaced.
+        let mut dispatcher = MessageDispatcher::new();
+        let mut contract: Contract =
+            phala_mq::checkpoint_helper::using_dispatcher(&mut dispatcher, || {
+                phala_mq::checkpoint_helper::using_send_mq(&mut send_mq, || {
+                    serde_cbor::from_slice(&self.data)
+                })
+            })?;
+        contract.cmd_rcv_mq.rebind(self.cmd_receiver.clone());
+        Ok(contract)
+    }
+}
+
+/// Serialized byte string of a contract.
+struct Bytes<T>(T);
+
+impl<T: AsRef<[u8]>> Serialize for Bytes<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0.as_ref())
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes<Vec<u8>> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> de::Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a byte string")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(v)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor).map(Bytes)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedContract<B> {
+    summary: ContractSummary,
+    data: Bytes<B>,
+}
+
+/// Checkpoints made before the contracts were kept serialized hold the contracts as is.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LazyContractRepr {
+    Serialized(SerializedContract<Vec<u8>>),
+    Plain(Box<Contract>),
+}
+
+impl Serialize for LazyContract {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match (self.decoded(), &self.encoded) {
+            (Some(contract), _) => {
+                let data = serde_cbor::to_vec(contract).map_err(serde::ser::Error::custom)?;
+                SerializedContract {
+                    summary: ContractSummary::of(contract),
+                    data: Bytes(data),
+                }
+                .serialize(serializer)
+            }
+            // Not decoded yet, or failed to.
+            (None, Some(encoded)) => SerializedContract {
+                summary: encoded.summary.clone(),
+                data: Bytes(&encoded.data[..]),
+            }
+            .serialize(serializer),
+            (None, None) => Err(serde::ser::Error::custom(
+                "BUG: contract neither decoded nor encoded",
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LazyContract {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match LazyContractRepr::deserialize(deserializer)? {
+            LazyContractRepr::Serialized(SerializedContract { summary, data }) => {
+                let topic = command_topic(summary.address.convert_to());
+                let encoded = EncodedContract {
+                    cmd_receiver: subscribe_default(topic).into(),
+                    send_mq: global_send_mq(),
+                    summary,
+                    data: data.0,
+                };
+                Ok(Self {
+                    decoded: OnceCell::new(),
+                    encoded: Some(Arc::new(encoded)),
+                })
+            }
+            LazyContractRepr::Plain(contract) => Ok(Self::new(*contract)),
+        }
+    }
+}
+
+// Transparent like the boxed contract it replaced, the checkpoint holds the same contract type.
+impl ::scale_info::TypeInfo for LazyContract {
+    type Identity = Contract;
+
+    fn type_info() -> ::scale_info::Type {
+        <Contract as ::scale_info::TypeInfo>::type_info()
+    }
+}
 
 #[derive(Default, Serialize, Deserialize, Clone, ::scale_info::TypeInfo)]
 pub struct ContractsKeeper {
@@ -23,26 +299,359 @@ pub struct ContractsKeeper {
     #[codec(skip)]
     #[serde(skip)]
     pub(crate) weight_changed: bool,
+    #[codec(skip)]
+    #[serde(default)]
+    stats: ContractStatsTable,
+    #[codec(skip)]
+    #[serde(default)]
+    routes: RoutingTable,
+    #[codec(skip)]
+    #[serde(default)]
+    metadata: ContractMetadataTable,
+}
+
+/// The contracts subscribed to each mq topic, so that a message only wakes up the contracts
+/// subscribed to its destination instead of all of them.
+#[derive(Default, Serialize, Deserialize, Clone)]
+struct RoutingTable(OrdMap<Vec<u8>, BTreeSet<AccountId>>);
+
+impl RoutingTable {
+    fn subscribe(&mut self, topic: Vec<u8>, id: &AccountId) {
+        self.0.entry(topic).or_default().insert(id.clone());
+    }
+
+    fn unsubscribe(&mut self, topic: &[u8], id: &AccountId) {
+        if let Some(ids) = self.0.get_mut(topic) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.0.remove(topic);
+            }
+        }
+    }
+
+    fn unsubscribe_all(&mut self, id: &AccountId) {
+        let topics: Vec<_> = self
+            .0
+            .iter()
+            .filter(|(_, ids)| ids.contains(id))
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in topics {
+            self.unsubscribe(&topic, id);
+        }
+    }
+
+    fn subscribers(&self, topic: &[u8]) -> Vec<AccountId> {
+        self.0
+            .get(topic)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Runtime counters of a contract on this worker.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractStats {
+    /// Number of queries served.
+    pub queries: u64,
+    /// Number of queries that returned an error.
+    pub query_errors: u64,
+    /// Number of commands processed.
+    pub commands: u64,
+    /// Total execution time of queries and commands in microseconds.
+    pub exec_time_us: u64,
+    /// The block number of the latest query or command.
+    pub last_activity_block: BlockNumber,
+}
+
+impl From<ContractStats> for pb::ContractStats {
+    fn from(stats: ContractStats) -> Self {
+        Self {
+            queries: stats.queries,
+            query_errors: stats.query_errors,
+            commands: stats.commands,
+            exec_time_ms: stats.exec_time_us / 1000,
+            last_activity_block: stats.last_activity_block,
+        }
+    }
+}
+
+/// The stats table is shared among the clones of a keeper, so that the counters can be updated
+/// from the query futures which only hold a snapshot of the keeper.
+#[derive(Default, Clone)]
+pub struct ContractStatsTable(Arc<Mutex<BTreeMap<AccountId, ContractStats>>>);
+
+impl ContractStatsTable {
+    fn update(&self, id: &AccountId, block: BlockNumber, f: impl FnOnce(&mut ContractStats)) {
+        let mut table = self.0.lock().unwrap();
+        let stats = table.entry(id.clone()).or_default();
+        stats.last_activity_block = stats.last_activity_block.max(block);
+        f(stats);
+    }
+
+    pub fn record_query(&self, id: &AccountId, ok: bool, elapsed: Duration, block: BlockNumber) {
+        self.update(id, block, |stats| {
+            stats.queries += 1;
+            if !ok {
+                stats.query_errors += 1;
+            }
+            stats.exec_time_us = stats.exec_time_us.saturating_add(elapsed.as_micros() as u64);
+        });
+    }
+
+    pub fn record_command(&self, id: &AccountId, elapsed: Duration, block: BlockNumber) {
+        self.update(id, block, |stats| {
+            stats.commands += 1;
+            stats.exec_time_us = stats.exec_time_us.saturating_add(elapsed.as_micros() as u64);
+        });
+    }
+
+    pub fn get(&self, id: &AccountId) -> Option<ContractStats> {
+        self.0.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn all(&self) -> BTreeMap<AccountId, ContractStats> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Serialize for ContractStatsTable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.lock().unwrap().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ContractStatsTable {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = BTreeMap::deserialize(deserializer)?;
+        Ok(Self(Arc::new(Mutex::new(table))))
+    }
+}
+
+/// What the public info queries tell about a contract.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractMetadata {
+    /// The code hash of the contract, None if it's not instantiated on this worker.
+    pub code_hash: Option<Hash>,
+    /// The block the contract is instantiated at, None if it's not instantiated on this worker.
+    pub instantiated_at: Option<BlockNumber>,
+    pub weight: u32,
+}
+
+type MetadataMap = BTreeMap<AccountId, ContractMetadata>;
+
+/// The metadata of the contracts, kept aside the contracts so that listing them doesn't decode the
+/// contracts restored from a checkpoint, nor borrow the keeper.
+///
+/// Shared among the clones of a keeper like the stats table.
+#[derive(Default, Clone)]
+struct ContractMetadataTable(Arc<RwLock<MetadataMap>>);
+
+impl Serialize for ContractMetadataTable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.read().unwrap().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ContractMetadataTable {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = BTreeMap::deserialize(deserializer)?;
+        Ok(Self(Arc::new(RwLock::new(table))))
+    }
+}
+
+/// Reads the metadata of the contracts of a keeper, see [`ContractsKeeper::metadata_reader`].
+///
+/// It only holds a weak reference to the table, so a reader handed out to serve the info queries
+/// doesn't keep the metadata of a dropped keeper alive, it reads nothing instead.
+#[derive(Clone)]
+pub struct ContractMetadataReader(Weak<RwLock<MetadataMap>>);
+
+impl ContractMetadataReader {
+    pub fn get(&self, id: &AccountId) -> Option<ContractMetadata> {
+        self.0.upgrade()?.read().unwrap().get(id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<(AccountId, ContractMetadata)> {
+        let Some(table) = self.0.upgrade() else {
+            return vec![];
+        };
+        let table = table.read().unwrap();
+        table
+            .iter()
+            .map(|(id, metadata)| (id.clone(), metadata.clone()))
+            .collect()
+    }
+}
+
+/// The state of a single contract, as exported by [`ContractsKeeper::export_contract`].
+#[derive(Serialize, Deserialize)]
+struct ContractSnapshot<'a> {
+    version: u32,
+    contract: Cow<'a, Contract>,
+    /// The egress channel of the contract, including its next sequence and the pending messages.
+    egress: Option<ChannelState>,
+    stats: Option<ContractStats>,
+    #[serde(default)]
+    metadata: Option<ContractMetadata>,
 }
 
 impl ContractsKeeper {
     pub fn insert(&mut self, contract: Contract) {
-        self.contracts
-            .insert(contract.address().clone(), Box::new(contract));
+        let metadata = ContractMetadata {
+            weight: contract.weight(),
+            ..Default::default()
+        };
+        self.insert_with_metadata(contract, metadata);
+    }
+
+    /// Inserts a contract just instantiated in the cluster.
+    pub fn insert_instantiated(
+        &mut self,
+        contract: Contract,
+        code_hash: Option<Hash>,
+        block: BlockNumber,
+    ) {
+        let metadata = ContractMetadata {
+            code_hash,
+            instantiated_at: Some(block),
+            weight: contract.weight(),
+        };
+        self.insert_with_metadata(contract, metadata);
+    }
+
+    fn insert_with_metadata(&mut self, contract: Contract, metadata: ContractMetadata) {
+        let id = contract.address().clone();
+        self.routes.subscribe(command_topic(id.convert_to()), &id);
+        self.metadata
+            .0
+            .write()
+            .unwrap()
+            .insert(id.clone(), metadata);
+        self.contracts.insert(id, LazyContract::new(contract));
     }
 
+    pub fn remove(&mut self, id: &AccountId) -> Option<Contract> {
+        self.routes.unsubscribe_all(id);
+        self.metadata.0.write().unwrap().remove(id);
+        self.contracts.remove(id)?.into_inner().ok()
+    }
+
+    /// Sets the weight of the contract `id`, returning false if there is no such contract.
+    pub fn set_weight(&mut self, id: &AccountId, weight: u32) -> bool {
+        let Some(contract) = self.get_mut(id) else {
+            return false;
+        };
+        contract.set_weight(weight);
+        if let Some(metadata) = self.metadata.0.write().unwrap().get_mut(id) {
+            metadata.weight = weight;
+        }
+        self.weight_changed = true;
+        true
+    }
+
+    /// Returns a reader of the metadata of the contracts, cheap to call and to read from without
+    /// holding the keeper, e.g. to serve the public info queries.
+    pub fn metadata_reader(&self) -> ContractMetadataReader {
+        ContractMetadataReader(Arc::downgrade(&self.metadata.0))
+    }
+
+    /// Adds the metadata missing from a checkpoint made before the metadata table was introduced.
+    pub fn fill_missing_metadata(&mut self) {
+        let mut table = self.metadata.0.write().unwrap();
+        if table.len() == self.contracts.len() {
+            return;
+        }
+        for (id, contract) in self.contracts.iter() {
+            table.entry(id.clone()).or_insert_with(|| ContractMetadata {
+                weight: contract.weight(),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Routes the messages sent to `topic` to the contract `id` as well.
+    pub fn subscribe(&mut self, id: &AccountId, topic: Vec<u8>) {
+        self.routes.subscribe(topic, id);
+    }
+
+    pub fn unsubscribe(&mut self, id: &AccountId, topic: &[u8]) {
+        self.routes.unsubscribe(topic, id);
+    }
+
+    /// Returns the contracts subscribed to `topic`.
+    pub fn subscribers(&mut self, topic: &[u8]) -> Vec<AccountId> {
+        if self.routes.0.is_empty() && !self.contracts.is_empty() {
+            // Restored from a checkpoint made before the routing table was introduced.
+            self.rebuild_routes();
+        }
+        self.routes.subscribers(topic)
+    }
+
+    fn rebuild_routes(&mut self) {
+        let mut routes = RoutingTable::default();
+        for id in self.contracts.keys() {
+            routes.subscribe(command_topic(id.convert_to()), id);
+        }
+        self.routes = routes;
+    }
+
+    /// Returns the ids of the contracts, in ascending order.
     pub fn keys(&self) -> impl Iterator<Item = &AccountId> {
         self.contracts.keys()
     }
 
+    /// Lists up to `limit` contracts following the contract `start_after`, or from the first
+    /// contract if it's None.
+    ///
+    /// The contracts are listed in ascending order of their ids, as [`Self::keys`] and
+    /// [`Self::iter`] do, whatever order they were inserted or restored in. So a listing can be
+    /// resumed from the last id of the previous page, e.g. blocks later without holding the
+    /// keeper in between, seeing the contracts inserted after the cursor and none listed twice.
+    pub fn list(
+        &self,
+        start_after: Option<&AccountId>,
+        limit: usize,
+    ) -> impl Iterator<Item = (&AccountId, &Contract)> {
+        let start = match start_after {
+            Some(id) => Bound::Excluded(id),
+            None => Bound::Unbounded,
+        };
+        self.contracts
+            .range((start, Bound::Unbounded))
+            .filter_map(|(k, v)| Some((k, v.get().ok()?)))
+            .take(limit)
+    }
+
+    /// Returns the contract `id`, None if there is no such contract or it failed to decode.
     pub fn get_mut(&mut self, id: &AccountId) -> Option<&mut Contract> {
-        let boxed = self.contracts.get_mut(id)?;
-        Some(boxed)
+        self.contracts.get_mut(id)?.get_mut().ok()
     }
 
     pub fn get(&self, id: &AccountId) -> Option<&Contract> {
-        let boxed = self.contracts.get(id)?;
-        Some(boxed)
+        self.contracts.get(id)?.get().ok()
+    }
+
+    /// Returns the contracts having an on-block-end handler.
+    pub fn with_on_block_end(&self) -> Vec<AccountId> {
+        self.contracts
+            .iter()
+            .filter(|(_, contract)| contract.has_on_block_end())
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Returns the contracts having periodic calls due at `block`.
+    pub fn with_due_schedules(&self, block: BlockNumber) -> Vec<AccountId> {
+        self.contracts
+            .iter()
+            .filter(|(_, contract)| matches!(contract.next_schedule(), Some(next) if next <= block))
+            .map(|(id, _)| id.clone())
+            .collect()
     }
 
     #[allow(clippy::len_without_is_empty)]
@@ -52,21 +661,93 @@ impl ContractsKeeper {
 
     pub fn try_restart_sidevms(&mut self, spawner: &Spawner, current_block: BlockNumber) {
         ordmap_for_each_mut(&mut self.contracts, |(_k, contract)| {
+            if !contract.has_sidevm() {
+                return;
+            }
+            let Ok(contract) = contract.get_mut() else {
+                return;
+            };
             if let Err(err) = contract.restart_sidevm_if_needed(spawner, current_block) {
                 error!("Failed to restart sidevm instance: {:?}", err);
             }
         });
     }
 
+    pub fn stats(&self) -> &ContractStatsTable {
+        &self.stats
+    }
+
     pub fn drain(&mut self) -> impl Iterator<Item = Contract> {
+        self.stats.clear();
+        self.routes = Default::default();
+        self.metadata.0.write().unwrap().clear();
         #[allow(clippy::iter_kv_map)]
         std::mem::take(&mut self.contracts)
             .into_iter()
-            .map(|(_, v)| *v)
+            .filter_map(|(_, v)| v.into_inner().ok())
     }
 
+    /// Iterates over the contracts, in ascending order of their ids.
     pub fn iter(&self) -> impl Iterator<Item = (&AccountId, &Contract)> {
-        self.contracts.iter().map(|(k, v)| (k, &**v))
+        self.contracts
+            .iter()
+            .filter_map(|(k, v)| Some((k, v.get().ok()?)))
+    }
+
+    /// Serializes the full state of the contract `id`, including its mq channel cursors, so that it
+    /// can be imported to another worker with [`Self::import_contract`].
+    pub fn export_contract(&self, id: &AccountId, send_mq: &MessageSendQueue) -> Result<Vec<u8>> {
+        let contract = self.get(id).ok_or_else(|| anyhow!("Contract not found"))?;
+        let snapshot = ContractSnapshot {
+            version: crate::CHECKPOINT_VERSION,
+            contract: Cow::Borrowed(contract),
+            egress: send_mq.dump_state(&contract_mq_sender(id)),
+            stats: self.stats.get(id),
+            metadata: self.metadata.0.read().unwrap().get(id).cloned(),
+        };
+        serde_cbor::to_vec(&snapshot).context("Failed to serialize the contract")
+    }
+
+    /// Restores a contract exported by [`Self::export_contract`], re-subscribing its command
+    /// channel on `recv_mq` and restoring its egress channel in `send_mq`.
+    pub fn import_contract(
+        &mut self,
+        data: &[u8],
+        send_mq: &mut MessageSendQueue,
+        recv_mq: &mut MessageDispatcher,
+    ) -> Result<AccountId> {
+        let snapshot: ContractSnapshot = {
+            let send_mq = &mut *send_mq;
+            phala_mq::checkpoint_helper::using_dispatcher(recv_mq, move || {
+                phala_mq::checkpoint_helper::using_send_mq(send_mq, || serde_cbor::from_slice(data))
+            })
+            .context("Failed to deserialize the contract")?
+        };
+        if snapshot.version != crate::CHECKPOINT_VERSION {
+            bail!(
+                "Incompatible contract state version {}, expected {}",
+                snapshot.version,
+                crate::CHECKPOINT_VERSION
+            );
+        }
+        let contract = snapshot.contract.into_owned();
+        let id = contract.address().clone();
+        if self.contracts.contains_key(&id) {
+            bail!("Contract already exists");
+        }
+        if let Some(egress) = snapshot.egress {
+            send_mq.load_state(&contract_mq_sender(&id), egress);
+        }
+        if let Some(stats) = snapshot.stats {
+            self.stats.0.lock().unwrap().insert(id.clone(), stats);
+        }
+        let metadata = ContractMetadata {
+            weight: contract.weight(),
+            ..snapshot.metadata.unwrap_or_default()
+        };
+        self.insert_with_metadata(contract, metadata);
+        self.weight_changed = true;
+        Ok(id)
     }
 
     pub fn apply_local_cache_quotas(&self) {
@@ -74,14 +755,18 @@ impl ContractsKeeper {
     }
 }
 
+fn contract_mq_sender(id: &AccountId) -> MessageOrigin {
+    MessageOrigin::Contract(id.convert_to())
+}
+
 const TOTAL_MEMORY: u64 = 1024 * 1024 * 20;
 pub(super) trait ToWeight {
     fn to_weight(&self) -> u32;
 }
 
-impl ToWeight for Box<Contract> {
+impl ToWeight for LazyContract {
     fn to_weight(&self) -> u32 {
-        self.weight
+        self.weight()
     }
 }
 
@@ -169,6 +854,362 @@ mod tests {
         );
     }
 
+    #[test]
+    fn stats_shared_among_clones() {
+        let keeper = ContractsKeeper::default();
+        let snapshot = keeper.clone();
+        let id = AccountId::new([1; 32]);
+        snapshot
+            .stats()
+            .record_query(&id, true, Duration::from_micros(10), 5);
+        snapshot
+            .stats()
+            .record_query(&id, false, Duration::from_micros(20), 3);
+        keeper
+            .stats()
+            .record_command(&id, Duration::from_micros(30), 7);
+        assert_eq!(
+            keeper.stats().get(&id),
+            Some(ContractStats {
+                queries: 2,
+                query_errors: 1,
+                commands: 1,
+                exec_time_us: 60,
+                last_activity_block: 7,
+            })
+        );
+    }
+
+    #[test]
+    fn stats_survive_serialization() {
+        let keeper = ContractsKeeper::default();
+        let id = AccountId::new([2; 32]);
+        keeper
+            .stats()
+            .record_command(&id, Duration::from_micros(1), 1);
+        let encoded = serde_cbor::to_vec(&keeper).unwrap();
+        let decoded: ContractsKeeper = serde_cbor::from_slice(&encoded).unwrap();
+        assert_eq!(decoded.stats().all(), keeper.stats().all());
+    }
+
+    fn new_contract(
+        id: &AccountId,
+        send_mq: &MessageSendQueue,
+        recv_mq: &mut MessageDispatcher,
+    ) -> Contract {
+        use crate::secret_channel::SecretReceiver;
+        use sp_core::Pair;
+
+        let signer = sp_core::sr25519::Pair::from_seed(&[1; 32]);
+        let mq = send_mq.channel(contract_mq_sender(id), signer.into());
+        let ecdh_key = phala_crypto::ecdh::EcdhKey::create(&[2; 32]);
+        let cmd_mq = SecretReceiver::new_secret(
+            recv_mq.subscribe(command_topic(id.convert_to())).into(),
+            ecdh_key.clone(),
+        );
+        Contract::new(mq, cmd_mq, ecdh_key, Default::default(), id.clone())
+    }
+
+    #[test]
+    fn export_import_contract_works() {
+        use phala_mq::traits::MessageChannel;
+
+        let id = AccountId::new([3; 32]);
+        let sender = contract_mq_sender(&id);
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        let contract = new_contract(&id, &send_mq, &mut recv_mq);
+        contract.send_mq.push_data(vec![1], "foo");
+        keeper.insert(contract);
+        keeper
+            .stats()
+            .record_command(&id, Duration::from_micros(1), 1);
+        let exported = keeper.export_contract(&id, &send_mq).unwrap();
+
+        let mut send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        let imported = keeper
+            .import_contract(&exported, &mut send_mq, &mut recv_mq)
+            .unwrap();
+        assert_eq!(imported, id);
+        assert!(keeper.get(&id).is_some());
+        assert_eq!(keeper.stats().get(&id).map(|s| s.commands), Some(1));
+        assert_eq!(send_mq.messages(&sender).len(), 1);
+
+        // The egress sequence continues from the exported one.
+        keeper.get(&id).unwrap().send_mq.push_data(vec![2], "foo");
+        let sequences: Vec<_> = send_mq
+            .messages(&sender)
+            .iter()
+            .map(|m| m.sequence)
+            .collect();
+        assert_eq!(sequences, vec![0, 1]);
+
+        assert!(keeper
+            .import_contract(&exported, &mut send_mq, &mut recv_mq)
+            .is_err());
+    }
+
+    #[test]
+    fn routes_messages_to_subscribers() {
+        let id1 = AccountId::new([4; 32]);
+        let id2 = AccountId::new([5; 32]);
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        keeper.insert(new_contract(&id1, &send_mq, &mut recv_mq));
+        keeper.insert(new_contract(&id2, &send_mq, &mut recv_mq));
+
+        let topic1 = command_topic(id1.convert_to());
+        assert_eq!(keeper.subscribers(&topic1), vec![id1.clone()]);
+        assert!(keeper.subscribers(b"foo").is_empty());
+
+        keeper.subscribe(&id1, b"foo".to_vec());
+        keeper.subscribe(&id2, b"foo".to_vec());
+        assert_eq!(keeper.subscribers(b"foo"), vec![id1.clone(), id2.clone()]);
+        keeper.unsubscribe(&id2, b"foo");
+        assert_eq!(keeper.subscribers(b"foo"), vec![id1.clone()]);
+
+        assert!(keeper.remove(&id1).is_some());
+        assert!(keeper.subscribers(&topic1).is_empty());
+        assert!(keeper.subscribers(b"foo").is_empty());
+    }
+
+    #[test]
+    fn routes_survive_serialization() {
+        let id = AccountId::new([6; 32]);
+        let mut keeper = ContractsKeeper::default();
+        keeper.subscribe(&id, b"foo".to_vec());
+        let encoded = serde_cbor::to_vec(&keeper).unwrap();
+        let mut decoded: ContractsKeeper = serde_cbor::from_slice(&encoded).unwrap();
+        assert_eq!(decoded.subscribers(b"foo"), vec![id]);
+    }
+
+    fn restore(
+        encoded: &[u8],
+        send_mq: &mut MessageSendQueue,
+        recv_mq: &mut MessageDispatcher,
+    ) -> ContractsKeeper {
+        phala_mq::checkpoint_helper::using_dispatcher(recv_mq, move || {
+            phala_mq::checkpoint_helper::using_send_mq(send_mq, || {
+                serde_cbor::from_slice(encoded).unwrap()
+            })
+        })
+    }
+
+    #[test]
+    fn restored_contracts_are_decoded_on_access() {
+        let id = AccountId::new([7; 32]);
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        keeper.insert(new_contract(&id, &send_mq, &mut recv_mq));
+        let encoded = serde_cbor::to_vec(&keeper).unwrap();
+
+        let mut send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = restore(&encoded, &mut send_mq, &mut recv_mq);
+        assert!(keeper.contracts[&id].decoded.get().is_none());
+        assert!(keeper.with_on_block_end().is_empty());
+        assert!(keeper.with_due_schedules(BlockNumber::MAX).is_empty());
+        // Saved again as is while not decoded.
+        assert_eq!(serde_cbor::to_vec(&keeper).unwrap(), encoded);
+
+        // The commands sent before the contract is decoded are kept for it.
+        let topic = command_topic(id.convert_to());
+        let message = phala_mq::Message::new(MessageOrigin::Gatekeeper, topic, vec![]);
+        assert_eq!(recv_mq.dispatch(message), 1);
+        let contract = keeper.get_mut(&id).unwrap();
+        assert_eq!(contract.address(), &id);
+        assert_eq!(contract.cmd_rcv_mq.peek_ind().unwrap(), Some(0));
+        assert!(keeper.contracts[&id].encoded.is_none());
+    }
+
+    #[test]
+    fn contracts_failing_to_decode_are_left_out() {
+        let id = AccountId::new([9; 32]);
+        let mut recv_mq = MessageDispatcher::new();
+        let encoded = EncodedContract {
+            summary: ContractSummary {
+                address: id.clone(),
+                weight: 1,
+                on_block_end: true,
+                sidevm: false,
+                next_schedule: Some(5),
+            },
+            data: b"garbage".to_vec(),
+            send_mq: MessageSendQueue::new(),
+            cmd_receiver: recv_mq.subscribe(command_topic(id.convert_to())).into(),
+        };
+        let mut keeper = ContractsKeeper::default();
+        keeper.contracts.insert(
+            id.clone(),
+            LazyContract {
+                decoded: OnceCell::new(),
+                encoded: Some(Arc::new(encoded)),
+            },
+        );
+        let saved = serde_cbor::to_vec(&keeper).unwrap();
+        assert_eq!(keeper.with_due_schedules(5), vec![id.clone()]);
+
+        assert!(keeper.get(&id).is_none());
+        assert!(keeper.get_mut(&id).is_none());
+        assert_eq!(keeper.iter().count(), 0);
+        assert!(keeper.with_on_block_end().is_empty());
+        assert!(keeper.with_due_schedules(BlockNumber::MAX).is_empty());
+        // Kept in the checkpoints as restored.
+        assert_eq!(serde_cbor::to_vec(&keeper).unwrap(), saved);
+        assert!(keeper.remove(&id).is_none());
+    }
+
+    #[test]
+    fn restores_checkpoints_with_plain_contracts() {
+        #[derive(Serialize)]
+        struct PlainKeeper<'a> {
+            contracts: BTreeMap<AccountId, &'a Contract>,
+        }
+
+        let id = AccountId::new([8; 32]);
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let contract = new_contract(&id, &send_mq, &mut recv_mq);
+        let encoded = serde_cbor::to_vec(&PlainKeeper {
+            contracts: [(id.clone(), &contract)].into_iter().collect(),
+        })
+        .unwrap();
+
+        let mut send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let keeper = restore(&encoded, &mut send_mq, &mut recv_mq);
+        assert!(keeper.contracts[&id].decoded.get().is_some());
+        assert_eq!(keeper.get(&id).map(|c| c.address()), Some(&id));
+    }
+
+    #[test]
+    fn metadata_kept_in_sync() {
+        let id1 = AccountId::new([21; 32]);
+        let id2 = AccountId::new([22; 32]);
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        let reader = keeper.metadata_reader();
+        let code_hash = Hash::repeat_byte(1);
+        keeper.insert_instantiated(
+            new_contract(&id1, &send_mq, &mut recv_mq),
+            Some(code_hash),
+            5,
+        );
+        keeper.insert(new_contract(&id2, &send_mq, &mut recv_mq));
+        assert_eq!(
+            reader.get(&id1),
+            Some(ContractMetadata {
+                code_hash: Some(code_hash),
+                instantiated_at: Some(5),
+                weight: 0,
+            })
+        );
+        assert_eq!(reader.list().len(), 2);
+
+        assert!(keeper.set_weight(&id1, 10));
+        assert_eq!(reader.get(&id1).map(|m| m.weight), Some(10));
+        assert!(!keeper.set_weight(&AccountId::new([23; 32]), 10));
+
+        assert!(keeper.remove(&id2).is_some());
+        assert_eq!(reader.get(&id2), None);
+
+        let snapshot = keeper.clone();
+        drop(keeper);
+        assert_eq!(reader.list().len(), 1);
+        drop(snapshot);
+        // The reader doesn't keep the table of the dropped keepers alive.
+        assert!(reader.list().is_empty());
+    }
+
+    #[test]
+    fn metadata_survives_checkpoints_and_export() {
+        let id = AccountId::new([24; 32]);
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        let code_hash = Some(Hash::repeat_byte(2));
+        keeper.insert_instantiated(new_contract(&id, &send_mq, &mut recv_mq), code_hash, 7);
+        let expected = keeper.metadata_reader().get(&id);
+        assert!(expected.is_some());
+
+        let encoded = serde_cbor::to_vec(&keeper).unwrap();
+        let mut send_mq2 = MessageSendQueue::new();
+        let mut recv_mq2 = MessageDispatcher::new();
+        let restored = restore(&encoded, &mut send_mq2, &mut recv_mq2);
+        assert_eq!(restored.metadata_reader().get(&id), expected);
+        // Read without decoding the contract.
+        assert!(restored.contracts[&id].decoded.get().is_none());
+
+        let exported = keeper.export_contract(&id, &send_mq).unwrap();
+        let mut imported = ContractsKeeper::default();
+        imported
+            .import_contract(&exported, &mut send_mq2, &mut recv_mq2)
+            .unwrap();
+        assert_eq!(imported.metadata_reader().get(&id), expected);
+    }
+
+    #[test]
+    fn fills_metadata_missing_from_old_checkpoints() {
+        let id = AccountId::new([25; 32]);
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        keeper.insert(new_contract(&id, &send_mq, &mut recv_mq));
+        keeper.metadata.0.write().unwrap().clear();
+        let encoded = serde_cbor::to_vec(&keeper).unwrap();
+
+        let mut send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut restored = restore(&encoded, &mut send_mq, &mut recv_mq);
+        let reader = restored.metadata_reader();
+        assert_eq!(reader.get(&id), None);
+        restored.fill_missing_metadata();
+        assert_eq!(reader.get(&id), Some(ContractMetadata::default()));
+        assert!(restored.contracts[&id].decoded.get().is_none());
+    }
+
+    #[test]
+    fn lists_contracts_in_pages() {
+        let ids: Vec<_> = [33, 31, 32]
+            .iter()
+            .map(|b| AccountId::new([*b; 32]))
+            .collect();
+        let send_mq = MessageSendQueue::new();
+        let mut recv_mq = MessageDispatcher::new();
+        let mut keeper = ContractsKeeper::default();
+        for id in &ids {
+            keeper.insert(new_contract(id, &send_mq, &mut recv_mq));
+        }
+        let page = |keeper: &ContractsKeeper, start_after: Option<&AccountId>| -> Vec<AccountId> {
+            keeper
+                .list(start_after, 2)
+                .map(|(id, _)| id.clone())
+                .collect()
+        };
+
+        let first = page(&keeper, None);
+        assert_eq!(first, vec![ids[1].clone(), ids[2].clone()]);
+        assert_eq!(first, keeper.keys().take(2).cloned().collect::<Vec<_>>());
+
+        // Resumed from the cursor after the keeper changed.
+        let cursor = first.last().unwrap().clone();
+        assert!(keeper.remove(&cursor).is_some());
+        let id = AccountId::new([34; 32]);
+        keeper.insert(new_contract(&id, &send_mq, &mut recv_mq));
+        assert_eq!(
+            page(&keeper, Some(&cursor)),
+            vec![ids[0].clone(), id.clone()]
+        );
+        assert!(page(&keeper, Some(&id)).is_empty());
+        assert!(keeper.list(None, 0).next().is_none());
+    }
+
     fn sorted<T: Ord>(mut v: Vec<T>) -> Vec<T> {
         v.sort();
         v
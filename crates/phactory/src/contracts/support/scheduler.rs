@@ -0,0 +1,142 @@
+use anyhow::{bail, Result};
+use runtime::BlockNumber;
+use serde::{Deserialize, Serialize};
+
+/// The most selectors a contract can have called periodically.
+pub const MAX_SCHEDULES_PER_CONTRACT: usize = 8;
+
+/// The most gas the periodic calls of a contract can be given in a block. The due calls over it
+/// are postponed to the next block.
+pub const SCHEDULE_GAS_BUDGET_PER_BLOCK: u64 = 1_000_000_000_000;
+
+/// A selector of a contract called every `interval` blocks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ::scale_info::TypeInfo)]
+pub struct Schedule {
+    pub selector: u32,
+    pub interval: BlockNumber,
+    pub gas_limit: u64,
+    /// The block at the end of which the selector is due to be called.
+    pub next_block: BlockNumber,
+}
+
+/// The periodic calls of a contract, saved along with it in the checkpoints.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ::scale_info::TypeInfo)]
+pub struct Schedules(Vec<Schedule>);
+
+impl Schedules {
+    /// Calls `selector` every `interval` blocks from `current_block` on, replacing the previous
+    /// schedule of the selector. An `interval` of 0 cancels it.
+    pub fn set(
+        &mut self,
+        selector: u32,
+        interval: BlockNumber,
+        gas_limit: u64,
+        current_block: BlockNumber,
+    ) -> Result<()> {
+        if interval == 0 {
+            self.0.retain(|schedule| schedule.selector != selector);
+            return Ok(());
+        }
+        if gas_limit > SCHEDULE_GAS_BUDGET_PER_BLOCK {
+            bail!("Gas limit {gas_limit} exceeds the budget {SCHEDULE_GAS_BUDGET_PER_BLOCK}");
+        }
+        let schedule = Schedule {
+            selector,
+            interval,
+            gas_limit,
+            next_block: current_block.saturating_add(interval),
+        };
+        match self.0.iter_mut().find(|s| s.selector == selector) {
+            Some(existing) => *existing = schedule,
+            None if self.0.len() >= MAX_SCHEDULES_PER_CONTRACT => {
+                bail!("Too many schedules, at most {MAX_SCHEDULES_PER_CONTRACT} allowed")
+            }
+            None => self.0.push(schedule),
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The earliest block a call is due at.
+    pub fn next_due(&self) -> Option<BlockNumber> {
+        self.0.iter().map(|schedule| schedule.next_block).min()
+    }
+
+    /// Takes the calls due at `block`, the longest overdue first, as long as they fit in the gas
+    /// budget. The taken ones are rescheduled `interval` blocks later, the missed periods skipped.
+    pub fn take_due(&mut self, block: BlockNumber) -> Vec<Schedule> {
+        let mut due: Vec<_> = self
+            .0
+            .iter_mut()
+            .filter(|schedule| schedule.next_block <= block)
+            .collect();
+        due.sort_by_key(|schedule| (schedule.next_block, schedule.selector));
+        let mut budget = SCHEDULE_GAS_BUDGET_PER_BLOCK;
+        let mut taken = vec![];
+        for schedule in due {
+            if schedule.gas_limit > budget {
+                continue;
+            }
+            budget -= schedule.gas_limit;
+            taken.push(schedule.clone());
+            schedule.next_block = block.saturating_add(schedule.interval);
+        }
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF_BUDGET: u64 = SCHEDULE_GAS_BUDGET_PER_BLOCK / 2;
+
+    fn selectors(schedules: &[Schedule]) -> Vec<u32> {
+        schedules.iter().map(|schedule| schedule.selector).collect()
+    }
+
+    #[test]
+    fn calls_every_interval() {
+        let mut schedules = Schedules::default();
+        schedules.set(1, 3, 100, 10).unwrap();
+        assert_eq!(schedules.next_due(), Some(13));
+        assert!(schedules.take_due(12).is_empty());
+        assert_eq!(selectors(&schedules.take_due(13)), vec![1]);
+        assert!(schedules.take_due(14).is_empty());
+        // The missed periods are skipped.
+        assert_eq!(selectors(&schedules.take_due(30)), vec![1]);
+        assert_eq!(schedules.next_due(), Some(33));
+
+        schedules.set(1, 0, 100, 30).unwrap();
+        assert!(schedules.is_empty());
+    }
+
+    #[test]
+    fn postpones_the_calls_over_budget() {
+        let mut schedules = Schedules::default();
+        schedules.set(1, 2, HALF_BUDGET, 0).unwrap();
+        schedules.set(2, 1, HALF_BUDGET, 0).unwrap();
+        schedules.set(3, 1, HALF_BUDGET, 0).unwrap();
+        assert_eq!(selectors(&schedules.take_due(1)), vec![2, 3]);
+        assert_eq!(selectors(&schedules.take_due(2)), vec![1, 2]);
+        assert_eq!(selectors(&schedules.take_due(3)), vec![3, 2]);
+        assert!(schedules
+            .set(4, 1, SCHEDULE_GAS_BUDGET_PER_BLOCK + 1, 3)
+            .is_err());
+    }
+
+    #[test]
+    fn limits_the_schedules_per_contract() {
+        let mut schedules = Schedules::default();
+        for selector in 0..MAX_SCHEDULES_PER_CONTRACT as u32 {
+            schedules.set(selector, 1, 100, 0).unwrap();
+        }
+        assert!(schedules.set(100, 1, 100, 0).is_err());
+        // Rescheduling doesn't count as a new one.
+        schedules.set(0, 5, 100, 0).unwrap();
+        assert_eq!(schedules.next_due(), Some(1));
+    }
+}
@@ -35,7 +35,10 @@ use std::{
     collections::BTreeMap,
     future::Future,
     str::FromStr,
-    sync::{Arc, Mutex, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
 };
 use std::{fs::File, io::ErrorKind, path::PathBuf};
 use std::{io::Write, marker::PhantomData};
@@ -221,6 +224,17 @@ fn remove_outdated_checkpoints(
     Ok(())
 }
 
+/// The completed checkpoints, the latest first.
+fn completed_checkpoints(basedir: &str) -> Result<Vec<(chain::BlockNumber, PathBuf)>> {
+    Ok(glob_checkpoint_files_sorted(basedir)?
+        .into_iter()
+        .filter(|(_, filename)| {
+            let info_filename = checkpoint_info_filename_for(&filename.display().to_string());
+            Path::new(&info_filename).exists()
+        })
+        .collect())
+}
+
 fn remove_checkpoint(filename: &Path) -> Result<()> {
     std::fs::remove_file(filename).context("Failed to remove checkpoint file")?;
     let info_filename = checkpoint_info_filename_for(&filename.display().to_string());
@@ -259,6 +273,22 @@ impl PersistentRuntimeData {
     }
 }
 
+/// Clears the flag when dropped.
+struct InProgress(Arc<AtomicBool>);
+
+impl InProgress {
+    fn enter(flag: Arc<AtomicBool>) -> Self {
+        flag.store(true, Ordering::Relaxed);
+        Self(flag)
+    }
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
 #[derive(Encode, Decode, Clone, Debug)]
 enum RuntimeDataSeal {
     V1(PersistentRuntimeData),
@@ -334,6 +364,12 @@ pub struct Phactory<Platform> {
     #[serde(skip)]
     pub(crate) cluster_state_to_apply: Option<ClusterState<'static>>,
 
+    /// Shared with the clones dispatching the blocks, so that a checkpoint taken during an RCU
+    /// dispatch can be seen.
+    #[codec(skip)]
+    #[serde(skip)]
+    checkpoint_in_progress: Arc<AtomicBool>,
+
     #[codec(skip)]
     #[serde(skip)]
     #[serde(default = "sidevm_helper::create_sidevm_service_default")]
@@ -421,6 +457,7 @@ impl<Platform: pal::Platform> Phactory<Platform> {
             pending_effects: Vec::new(),
             started_at: Instant::now(),
             cluster_state_to_apply: None,
+            checkpoint_in_progress: Default::default(),
             sidevm_spawner: sidevm_helper::create_sidevm_service(
                 args.cores as _,
                 create_sidevm_outgoing_channel(weak_self),
@@ -810,6 +847,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         if self.args.safe_mode_level > 0 {
             anyhow::bail!("Checkpoint is disabled in safe mode");
         }
+        let _in_progress = InProgress::enter(self.checkpoint_in_progress.clone());
         let (current_block, _) = self.current_block()?;
         let key = self
             .system
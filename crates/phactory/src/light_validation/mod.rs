@@ -63,7 +63,7 @@ use sp_core::H256;
 use sp_runtime::traits::{Block as BlockT, Header, NumberFor};
 use sp_runtime::EncodedJustification;
 
-pub use types::{AuthoritySet, BlockHeader, find_scheduled_change};
+pub use types::{AuthoritySet, AuthoritySetChange, BlockHeader, find_scheduled_change};
 
 #[derive(Encode, Decode, Clone, PartialEq, Serialize, Deserialize, ::scale_info::TypeInfo)]
 pub struct BridgeInfo<T: Config> {
@@ -153,6 +153,7 @@ where
         header: BlockHeader,
         ancestry_proof: Vec<BlockHeader>,
         grandpa_proof: EncodedJustification,
+        authority_set_change: Option<AuthoritySetChange>,
     ) -> Result<()> {
         let bridge = self
             .tracked_bridges
@@ -185,12 +186,28 @@ where
                     if scheduled_change.delay != 0 {
                         return Err(anyhow::Error::msg(Error::UnsupportedScheduledChangeDelay));
                     }
-
-                    // Commit
-                    bridge_info.current_set = AuthoritySet {
+                    let next_set = AuthoritySet {
                         list: scheduled_change.next_authorities,
                         id: voter_set_id + 1,
+                    };
+
+                    // The proof of the new set, if any, must agree with the digest
+                    if let Some(change) = authority_set_change {
+                        if change.authority_set != next_set {
+                            return Err(anyhow::Error::msg(Error::ValidatorSetMismatch));
+                        }
+                        Self::check_validator_set_proof(
+                            header.state_root(),
+                            change.authority_proof,
+                            &next_set.list,
+                            next_set.id,
+                        )?;
                     }
+
+                    // Commit
+                    bridge_info.current_set = next_set;
+                } else if authority_set_change.is_some() {
+                    return Err(anyhow::Error::msg(Error::UnexpectedAuthoritySetChange));
                 }
                 bridge_info.last_finalized_block_header = header;
             }
@@ -234,6 +251,7 @@ pub enum Error {
     // UnexpectedValidatorSetId,
     StorageValueMismatch,
     UnsupportedScheduledChangeDelay,
+    UnexpectedAuthoritySetChange,
 }
 
 impl fmt::Display for Error {
@@ -249,6 +267,7 @@ impl fmt::Display for Error {
             // Error::UnexpectedValidatorSetId => write!(f, "unexpected validator set id"),
             Error::StorageValueMismatch => write!(f, "storage value mismatch"),
             Error::UnsupportedScheduledChangeDelay => write!(f, "scheduled change should not have delay"),
+            Error::UnexpectedAuthoritySetChange => write!(f, "authority set change on a header not scheduling it"),
         }
     }
 }
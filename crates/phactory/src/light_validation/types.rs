@@ -1,3 +1,3 @@
-pub use phactory_api::blocks::{find_scheduled_change, AuthoritySet};
+pub use phactory_api::blocks::{find_scheduled_change, AuthoritySet, AuthoritySetChange};
 
 pub type BlockHeader = sp_runtime::generic::Header<u32, sp_runtime::traits::BlakeTwo256>;
@@ -17,6 +17,7 @@ use pb::{
     server::Error as RpcError,
 };
 use phactory_api::blocks::StorageState;
+use phactory_api::storage_sync::{self, SyncErrorCode};
 use phactory_api::{blocks, crypto, endpoints::EndpointType, prpc as pb};
 use phala_crypto::ecdh::{self, EcdhPublicKey};
 use phala_crypto::{
@@ -39,6 +40,9 @@ use tracing::{error, info};
 
 type RpcResult<T> = Result<T, RpcError>;
 
+/// The size of a page of egress messages if the client doesn't specify one.
+const DEFAULT_EGRESS_PAGE_BYTES: usize = 1024 * 1024;
+
 fn from_display(e: impl core::fmt::Display) -> RpcError {
     RpcError::AppError(e.to_string())
 }
@@ -47,6 +51,14 @@ fn from_debug(e: impl core::fmt::Debug) -> RpcError {
     RpcError::AppError(format!("{e:?}"))
 }
 
+fn from_sync_error(e: storage_sync::Error) -> RpcError {
+    with_code(e.code(), e)
+}
+
+fn with_code(code: SyncErrorCode, e: impl core::fmt::Display) -> RpcError {
+    RpcError::AppErrorWithCode(code as u32, e.to_string())
+}
+
 fn now() -> u64 {
     use std::time::SystemTime;
     let now = SystemTime::now()
@@ -59,13 +71,13 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
     fn runtime_state(&mut self) -> RpcResult<&mut RuntimeState> {
         self.runtime_state
             .as_mut()
-            .ok_or_else(|| from_display("Runtime not initialized"))
+            .ok_or_else(|| with_code(SyncErrorCode::NotInitialized, "Runtime not initialized"))
     }
 
     fn system(&mut self) -> RpcResult<&mut System<Platform>> {
         self.system
             .as_mut()
-            .ok_or_else(|| from_display("Runtime not initialized"))
+            .ok_or_else(|| with_code(SyncErrorCode::NotInitialized, "Runtime not initialized"))
     }
 
     pub(crate) fn current_block(&mut self) -> RpcResult<(BlockNumber, u64)> {
@@ -164,6 +176,11 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             supported_attestation_methods: self.platform.supported_attestation_methods(),
             live_sidevm_instances: sidevm::vm_count() as u32,
             query_timeout: self.args.query_timeout as _,
+            can_coalesce_empty_blocks: true,
+            capabilities: pb::Capability::ALL
+                .iter()
+                .map(|capability| capability.as_str().into())
+                .collect(),
         }
     }
 
@@ -185,7 +202,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             .runtime_state()?
             .storage_synchronizer
             .sync_header(headers, authority_set_change)
-            .map_err(from_display)?;
+            .map_err(from_sync_error)?;
 
         Ok(pb::SyncedTo {
             synced_to: last_header,
@@ -216,7 +233,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         let last_header = state
             .storage_synchronizer
             .sync_parachain_header(headers, proof, &storage_key)
-            .map_err(from_display)?;
+            .map_err(from_sync_error)?;
 
         Ok(pb::SyncedTo {
             synced_to: last_header,
@@ -282,12 +299,17 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             state
                 .storage_synchronizer
                 .feed_block(&block, state.chain_storage.inner_mut(), drop_proofs)
-                .map_err(from_display)?;
+                .map_err(from_sync_error)?;
             if safe_mode_level > 0 {
                 continue;
             }
             info!("State synced");
-            state.purge_mq();
+            // A block only changing the bookkeeping leaves the mq sequences and the requirements
+            // as they were, so there is nothing to purge or check again.
+            let unchanged = block.is_bookkeeping_only();
+            if !unchanged {
+                state.purge_mq();
+            }
             let now_ms = state.chain_storage.timestamp_now();
             let chain_storage = state.chain_storage.snapshot();
             let block_number = block.block_header.number;
@@ -303,7 +325,9 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
                 self.sidevm_spawner.event_tx(),
                 None, // Not allowed in TX
             );
-            self.check_requirements();
+            if !unchanged {
+                self.check_requirements();
+            }
             contracts::pink::context::using(&mut context, || {
                 self.handle_inbound_messages(block_number)
             })?;
@@ -602,6 +626,20 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         Ok(messages)
     }
 
+    fn get_egress_messages_paged(
+        &mut self,
+        cursor: Option<pb::EgressCursor>,
+        max_bytes: usize,
+    ) -> RpcResult<(pb::EgressMessages, Option<pb::EgressCursor>)> {
+        let Some(state) = self.runtime_state.as_ref() else {
+            return Ok((vec![], None));
+        };
+        let start = cursor.as_ref().map(|c| (&c.sender, c.sequence));
+        let (page, next) = state.send_mq.messages_page(start, max_bytes);
+        let next = next.map(|(sender, sequence)| pb::EgressCursor { sender, sequence });
+        Ok((page, next))
+    }
+
     fn contract_query(
         &mut self,
         req_id: u64,
@@ -749,9 +787,10 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
                     message.sender, message.destination
                 );
             }
+            let topic = message.destination.path().clone();
             block.recv_mq.dispatch(message);
 
-            system.process_messages(&mut block);
+            system.process_messages(&mut block, &topic);
         }
         system.did_process_block(&mut block);
 
@@ -858,12 +897,24 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             .contract_cluster
             .as_ref()
             .ok_or_else(|| from_display("No cluster found"))?;
+        let metadata = system.contracts.metadata_reader();
         let contracts = if contract_ids.is_empty() {
-            // TODO.kevin: reject to query all for performance reason
-            system
-                .contracts
-                .iter()
-                .map(|(_, contract)| contract.info(cluster))
+            // From the metadata table, not to decode all the contracts restored from a checkpoint.
+            metadata
+                .list()
+                .into_iter()
+                .map(|(id, metadata)| pb::ContractInfo {
+                    id: hex(&id),
+                    code_hash: metadata
+                        .code_hash
+                        .or_else(|| cluster.code_hash(&id))
+                        .map(hex)
+                        .unwrap_or_default(),
+                    weight: metadata.weight,
+                    instantiated_at: metadata.instantiated_at.unwrap_or_default(),
+                    stats: system.contracts.stats().get(&id).map(Into::into),
+                    ..Default::default()
+                })
                 .collect()
         } else {
             let mut contracts = vec![];
@@ -872,13 +923,20 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
                     .map_err(|_| from_display("Invalid contract id"))?
                     .try_into()
                     .map_err(|_| from_display("Invalid contract id"))?;
-                let contract = system.contracts.get(&raw.into());
+                let id = AccountId::from(raw);
+                let contract = system.contracts.get(&id);
                 // TODO: use `let else`.
                 let contract = match contract {
                     None => continue,
                     Some(contract) => contract,
                 };
-                contracts.push(contract.info(cluster));
+                let mut info = contract.info(cluster);
+                info.stats = system.contracts.stats().get(&id).map(Into::into);
+                info.instantiated_at = metadata
+                    .get(&id)
+                    .and_then(|metadata| metadata.instantiated_at)
+                    .unwrap_or_default();
+                contracts.push(info);
             }
             contracts
         };
@@ -967,6 +1025,25 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         Ok(())
     }
 
+    pub fn get_chain_state(&mut self) -> RpcResult<(chain::BlockNumber, StorageState)> {
+        if self.args.safe_mode_level >= 2 {
+            return Err(from_display(
+                "Can not export chain state when safe_mode_level >= 2",
+            ));
+        }
+        if self.system()?.registered() {
+            return Err(from_display(
+                "Can not export chain state from a registered worker",
+            ));
+        }
+        let (block, _) = self.current_block()?;
+        if block == 0 {
+            return Err(from_display("No block has been dispatched yet"));
+        }
+        let state = self.runtime_state()?.chain_storage.inner().pairs(b"");
+        Ok((block, state))
+    }
+
     pub fn stop(&self, remove_checkpoints: bool) -> RpcResult<()> {
         info!("Requested to stop remove_checkpoints={remove_checkpoints}");
         if remove_checkpoints {
@@ -975,6 +1052,40 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         std::process::abort()
     }
 
+    pub fn get_checkpoint_info(&self) -> RpcResult<pb::CheckpointInfo> {
+        use std::time::SystemTime;
+
+        let checkpoints =
+            crate::completed_checkpoints(&self.args.storage_path).map_err(from_debug)?;
+        let last = match checkpoints.first() {
+            Some((block_number, filename)) => {
+                let info_filename =
+                    crate::checkpoint_info_filename_for(&filename.display().to_string());
+                let size = std::fs::metadata(filename).map_err(from_debug)?.len();
+                let completed_at = std::fs::metadata(info_filename)
+                    .and_then(|metadata| metadata.modified())
+                    .map_err(from_debug)?;
+                let timestamp = completed_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                Some(pb::CheckpointMeta {
+                    block_number: *block_number,
+                    size,
+                    timestamp,
+                })
+            }
+            None => None,
+        };
+        Ok(pb::CheckpointInfo {
+            enabled: self.args.enable_checkpoint,
+            interval: self.args.checkpoint_interval,
+            in_progress: self.checkpoint_in_progress.load(Ordering::Relaxed),
+            last,
+            count: checkpoints.len() as u32,
+        })
+    }
+
     fn load_storage_proof(&mut self, proof: Vec<Vec<u8>>) -> RpcResult<()> {
         if self.args.safe_mode_level < 2 {
             return Err(from_display(
@@ -988,6 +1099,57 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         Ok(())
     }
 
+    fn load_storage_proof_at(
+        &mut self,
+        block: chain::BlockNumber,
+        header: blocks::BlockHeader,
+        keys: Vec<Vec<u8>>,
+        proof: Vec<Vec<u8>>,
+    ) -> RpcResult<()> {
+        use crate::light_validation::storage_proof::StorageProofChecker;
+        use hash_db::Hasher as _;
+
+        let safe_mode_level = self.args.safe_mode_level;
+        let state = self.runtime_state()?;
+        let current_block = state
+            .storage_synchronizer
+            .counters()
+            .next_block_number
+            .saturating_sub(1);
+        if block != current_block || header.number != block {
+            return Err(from_display(format!(
+                "The proof is at block {block} with the header of {}, but the current block is \
+                 {current_block}",
+                header.number
+            )));
+        }
+        let root = *state.chain_storage.root();
+        // Read at another block with the same number, e.g. on another fork.
+        if header.state_root != root {
+            return Err(from_display(format!(
+                "The proof is at block {:?}, whose state root is not the current one",
+                header.hash()
+            )));
+        }
+        if !proof.iter().any(|node| RuntimeHasher::hash(node) == root) {
+            return Err(from_display(
+                "The proof does not match the current state root",
+            ));
+        }
+        let checker =
+            StorageProofChecker::<RuntimeHasher>::new(root, proof.clone()).map_err(from_display)?;
+        for key in &keys {
+            checker.read_value(key).map_err(|_| {
+                from_display(format!("Key 0x{} is not covered by the proof", hex(key)))
+            })?;
+        }
+        // The full storage is available unless in safe mode level 2, nothing to load.
+        if safe_mode_level >= 2 {
+            state.chain_storage.inner_mut().add_proof(proof);
+        }
+        Ok(())
+    }
+
     fn save_cluster_state(
         &self,
         min_block_number: BlockNumber,
@@ -1260,6 +1422,9 @@ where
                             (400, ProtoError::new(format!("DecodeError({err:?})")))
                         }
                         Error::AppError(msg) => (500, ProtoError::new(msg)),
+                        Error::AppErrorWithCode(code, msg) => {
+                            (500, ProtoError::with_code(code, msg))
+                        }
                         Error::ContractQueryError(msg) => (500, ProtoError::new(msg)),
                     };
                     if json {
@@ -1316,12 +1481,16 @@ impl<Platform: pal::Platform> RpcService<Platform> {
         debug!(target: "phactory::lock", "Locked phactory");
         if !allow_rcu && guard.rcu_dispatching {
             warn!(target: "phactory::lock", "RCU in progress, returning error");
-            return Err(from_display(
+            return Err(with_code(
+                SyncErrorCode::Busy,
                 "RCU in progress, please try the request again later",
             ));
         }
         if !allow_safemode && guard.args.safe_mode_level > 0 {
-            return Err(from_display("This RPC is disabled in safe mode"));
+            return Err(with_code(
+                SyncErrorCode::SafeMode,
+                "This RPC is disabled in safe mode",
+            ));
         }
         Ok(LogOnDrop {
             inner: guard,
@@ -1483,6 +1652,24 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             .map(pb::GetEgressMessagesResponse::new)
     }
 
+    async fn get_egress_messages_paged(
+        &mut self,
+        request: pb::GetEgressMessagesPagedRequest,
+    ) -> RpcResult<pb::GetEgressMessagesPagedResponse> {
+        let cursor = request.decode_cursor()?;
+        let max_bytes = match request.max_bytes {
+            0 => DEFAULT_EGRESS_PAGE_BYTES,
+            max_bytes => max_bytes as usize,
+        };
+        let (messages, next_cursor) = self
+            .lock_phactory(true, false)?
+            .get_egress_messages_paged(cursor, max_bytes)?;
+        Ok(pb::GetEgressMessagesPagedResponse::new(
+            messages,
+            next_cursor,
+        ))
+    }
+
     async fn contract_query(
         &mut self,
         request: pb::ContractQueryRequest,
@@ -2199,6 +2386,10 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             .load_chain_state(request.block_number, request.decode_state()?)
             .map_err(from_display)
     }
+    async fn get_chain_state(&mut self, _req: ()) -> Result<pb::ChainState, prpc::server::Error> {
+        let (block_number, state) = self.lock_phactory(false, false)?.get_chain_state()?;
+        Ok(pb::ChainState::new(block_number, state))
+    }
     async fn stop(&mut self, request: pb::StopOptions) -> Result<(), prpc::server::Error> {
         self.lock_phactory(true, true)?
             .stop(request.remove_checkpoints)
@@ -2211,6 +2402,19 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             .load_storage_proof(req.proof)?;
         Ok(())
     }
+    async fn load_storage_proof_at(
+        &mut self,
+        req: pb::StorageProofAt,
+    ) -> Result<(), prpc::server::Error> {
+        let header = req.decode_header()?;
+        self.lock_phactory(false, true)?.load_storage_proof_at(
+            req.block_number,
+            header,
+            req.keys,
+            req.proof,
+        )?;
+        Ok(())
+    }
     async fn take_checkpoint(&mut self, _req: ()) -> Result<pb::SyncedTo, prpc::server::Error> {
         let synced_to = self
             .lock_phactory(false, false)?
@@ -2218,6 +2422,9 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> PhactoryApi for Rpc
             .map_err(from_debug)?;
         Ok(pb::SyncedTo { synced_to })
     }
+    async fn get_checkpoint_info(&mut self, _req: ()) -> RpcResult<pb::CheckpointInfo> {
+        self.lock_phactory(true, true)?.get_checkpoint_info()
+    }
     async fn statistics(
         &mut self,
         request: pb::StatisticsReqeust,
@@ -232,6 +232,11 @@ mod receiver {
         pub fn peek_ind(&self) -> Result<Option<u64>, ReceiveError> {
             self.receiver.peek_ind()
         }
+
+        /// Replaces the underlying receiver, keeping the peeler.
+        pub fn rebind(&mut self, receiver: TypedReceiver<Wrp>) {
+            self.receiver = receiver;
+        }
     }
 }
 
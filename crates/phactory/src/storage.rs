@@ -1,4 +1,4 @@
-use crate::light_validation::{storage_proof::StorageProof, LightValidation};
+use crate::light_validation::{storage_proof::StorageProof, AuthoritySetChange, LightValidation};
 use phactory_api::storage_sync::{BlockValidator, Error as SyncError, Result};
 use std::string::ToString;
 
@@ -11,9 +11,16 @@ impl BlockValidator for LightValidation<chain::Runtime> {
         header: chain::Header,
         ancestry_proof: Vec<chain::Header>,
         grandpa_proof: Vec<u8>,
+        authority_set_change: Option<AuthoritySetChange>,
     ) -> Result<()> {
-        self.submit_finalized_headers(bridge_id, header, ancestry_proof, grandpa_proof)
-            .map_err(|e| SyncError::HeaderValidateFailed(e.to_string()))
+        self.submit_finalized_headers(
+            bridge_id,
+            header,
+            ancestry_proof,
+            grandpa_proof,
+            authority_set_change,
+        )
+        .map_err(|e| SyncError::HeaderValidateFailed(e.to_string()))
     }
 
     fn validate_storage_proof(
@@ -17,7 +17,7 @@ use pink_loader::{
     capi::v1::ecall::{ClusterSetupConfig, ECalls},
     local_cache,
     types::{
-        AccountId, ExecSideEffects, ExecutionMode, HookPoint, PinkEvent, TransactionArguments,
+        AccountId, ExecSideEffects, ExecutionMode, Hash, HookPoint, PinkEvent, TransactionArguments,
     },
 };
 use runtime::BlockNumber;
@@ -588,11 +588,20 @@ impl<Platform: pal::Platform> System<Platform> {
         let query = deopaque_query::<Query>(&query)?;
         let contract_id = contract_id.clone();
         let contracts = self.contracts.clone();
+        let stats = self.contracts.stats().clone();
+        let block_number = self.block_number;
         Ok(async move {
             let query_type = query.query_type();
+            let started_at = std::time::Instant::now();
             let result = cluster
                 .handle_query(&contract_id, origin.as_ref(), query, context, contracts)
                 .await;
+            stats.record_query(
+                &contract_id,
+                result.is_ok(),
+                started_at.elapsed(),
+                block_number,
+            );
             let (result, effects) = match result {
                 Ok((reply, effects)) => (Ok(reply), effects),
                 Err(err) => {
@@ -640,7 +649,8 @@ impl<Platform: pal::Platform> System<Platform> {
         }
     }
 
-    pub fn process_messages(&mut self, block: &mut BlockInfo) {
+    /// Processes the messages just dispatched to `topic`.
+    pub fn process_messages(&mut self, block: &mut BlockInfo, topic: &[u8]) {
         loop {
             match self.process_next_message(block) {
                 Err(err) => {
@@ -653,19 +663,19 @@ impl<Platform: pal::Platform> System<Platform> {
                 }
             }
         }
-        self.process_contract_messages(block);
+        self.process_contract_messages(block, topic);
         if let Some(gatekeeper) = &mut self.gatekeeper {
             gatekeeper.process_messages(block);
         }
     }
 
-    fn process_contract_messages(&mut self, block: &mut BlockInfo) {
-        // Iterate over all contracts to handle their incoming commands.
+    fn process_contract_messages(&mut self, block: &mut BlockInfo, topic: &[u8]) {
+        // Iterate over the contracts subscribed to the topic to handle their incoming commands.
         //
         // Since the wasm contracts can instantiate new contracts, it means that it will mutate the `self.contracts`.
         // So we can not directly iterate over the self.contracts.values_mut() which would keep borrowing on `self.contracts`
         // in the scope of entire `for loop` body.
-        let contract_ids: Vec<_> = self.contracts.keys().cloned().collect();
+        let contract_ids = self.contracts.subscribers(topic);
         'next_contract: for key in contract_ids {
             // Inner loop to handle commands. One command per iteration and apply the command side-effects to make it
             // availabe for next command.
@@ -683,10 +693,14 @@ impl<Platform: pal::Platform> System<Platform> {
                     contract_cluster: cluster,
                     log_handler: log_handler.clone(),
                 };
+                let started_at = std::time::Instant::now();
                 let result = match contract.process_next_message(&mut env) {
                     Some(result) => result,
                     None => break,
                 };
+                self.contracts
+                    .stats()
+                    .record_command(&key, started_at.elapsed(), block.block_number);
                 handle_contract_command_result(
                     self.identity_key.public(),
                     result,
@@ -719,7 +733,7 @@ impl<Platform: pal::Platform> System<Platform> {
         );
         let log_handler = self.get_system_message_handler();
         if let Some(cluster) = self.contract_cluster.as_mut() {
-            let contract_ids: Vec<_> = self.contracts.keys().cloned().collect();
+            let contract_ids = self.contracts.with_on_block_end();
             'next_contract: for key in contract_ids {
                 let contract = match self.contracts.get_mut(&key) {
                     None => continue 'next_contract,
@@ -742,6 +756,28 @@ impl<Platform: pal::Platform> System<Platform> {
                     block.storage,
                 );
             }
+            for key in self.contracts.with_due_schedules(block.block_number) {
+                let Some(contract) = self.contracts.get_mut(&key) else {
+                    continue;
+                };
+                let mut env = ExecuteEnv {
+                    block,
+                    contract_cluster: cluster,
+                    log_handler: log_handler.clone(),
+                };
+                for result in contract.run_schedules(&mut env) {
+                    handle_contract_command_result(
+                        self.identity_key.public(),
+                        result,
+                        &mut self.contracts,
+                        cluster,
+                        block,
+                        &self.egress,
+                        log_handler.clone(),
+                        block.storage,
+                    );
+                }
+            }
         }
         if self.contracts.weight_changed {
             self.contracts.weight_changed = false;
@@ -1573,6 +1609,7 @@ impl<Platform: pal::Platform> System<Platform> {
 
 impl<P: pal::Platform> System<P> {
     pub fn on_restored(&mut self, safe_mode_level: u8, sidevm_spawner: &Spawner) -> Result<()> {
+        self.contracts.fill_missing_metadata();
         if safe_mode_level > 0 {
             return Ok(());
         }
@@ -1604,6 +1641,7 @@ impl<P: pal::Platform> System<P> {
             cluster,
             sidevm_spawner,
             chain_storage,
+            self.block_number,
         );
     }
 
@@ -1683,6 +1721,7 @@ pub fn apply_pink_side_effects(
         cluster,
         block.sidevm_spawner,
         chain_storage,
+        block.block_number,
     );
     apply_ink_side_effects(ink_events, block, log_handler);
 }
@@ -1698,9 +1737,11 @@ fn apply_instantiating_events(
         let contract_id = ContractId::from(address.as_ref());
         let contract_key = get_contract_key(&cluster.key(), &contract_id);
         let ecdh_key = contract_key.derive_ecdh_key();
+        let code_hash = cluster.code_hash(&address);
         let result = install_contract(
             contracts,
             address,
+            code_hash,
             contract_key.clone(),
             ecdh_key.clone(),
             block,
@@ -1735,6 +1776,7 @@ pub(crate) fn apply_pink_events(
     cluster: &mut Cluster,
     spawner: &Spawner,
     _chain_storage: &ChainStorage,
+    block_number: BlockNumber,
 ) {
     for (origin, event) in pink_events {
         macro_rules! get_contract {
@@ -1770,10 +1812,8 @@ pub(crate) fn apply_pink_events(
             } => {
                 ensure_system!();
                 let contract = get_contract!(&target_contract);
-                match hook {
-                    HookPoint::OnBlockEnd => {
-                        contract.set_on_block_end_selector(selector, gas_limit);
-                    }
+                if let Err(err) = set_hook(contract, hook, selector, gas_limit, block_number) {
+                    error!("Failed to set hook for contract {target_contract:?}: {err:?}");
                 }
             }
             PinkEvent::DeploySidevmTo {
@@ -1832,9 +1872,9 @@ pub(crate) fn apply_pink_events(
             PinkEvent::SetContractWeight { contract, weight } => {
                 ensure_system!();
                 info!("Set contract weight for {contract:?} to {weight:?}");
-                let contract = get_contract!(&contract);
-                contract.set_weight(weight);
-                contracts.weight_changed = true;
+                if !contracts.set_weight(&contract.convert_to(), weight) {
+                    error!("Unknown contract to set weight, address={:?}", contract);
+                }
             }
             PinkEvent::UpgradeRuntimeTo { version } => {
                 ensure_system!();
@@ -1893,6 +1933,23 @@ pub(crate) fn apply_pink_events(
     }
 }
 
+/// Hooks `selector` of the contract, the periodic hooks counting their interval from `block_number`.
+fn set_hook(
+    contract: &mut contracts::Contract,
+    hook: HookPoint,
+    selector: u32,
+    gas_limit: u64,
+    block_number: BlockNumber,
+) -> Result<()> {
+    match hook {
+        HookPoint::OnBlockEnd => contract.set_on_block_end_selector(selector, gas_limit),
+        HookPoint::Periodic { interval } => {
+            contract.set_schedule(selector, interval, gas_limit, block_number)?
+        }
+    }
+    Ok(())
+}
+
 fn apply_ink_side_effects(
     ink_events: Vec<(AccountId, Vec<crate::H256>, Vec<u8>)>,
     block: &mut BlockInfo,
@@ -1919,6 +1976,7 @@ fn apply_ink_side_effects(
 pub fn install_contract(
     contracts: &mut ContractsKeeper,
     address: AccountId,
+    code_hash: Option<Hash>,
     contract_key: sr25519::Pair,
     ecdh_key: EcdhKey,
     block: &mut BlockInfo,
@@ -1937,7 +1995,7 @@ pub fn install_contract(
         ecdh_key.clone(),
     );
     let wrapped = contracts::Contract::new(mq, cmd_mq, ecdh_key, cluster_id, address);
-    contracts.insert(wrapped);
+    contracts.insert_instantiated(wrapped, code_hash, block.block_number);
     Ok(())
 }
 
@@ -1966,3 +2024,41 @@ pub mod chain_state {
         chain_storage.gatekeepers().contains(pubkey)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_contract(address: &AccountId, recv_mq: &mut MessageDispatcher) -> contracts::Contract {
+        let send_mq = MessageSendQueue::new();
+        let signer = sr25519::Pair::from_seed(&[1; 32]);
+        let mq = send_mq.channel(MessageOrigin::Contract(address.convert_to()), signer.into());
+        let ecdh_key = EcdhKey::create(&[2; 32]);
+        let cmd_mq = SecretReceiver::new_secret(
+            recv_mq
+                .subscribe(contract::command_topic(address.convert_to()))
+                .into(),
+            ecdh_key.clone(),
+        );
+        contracts::Contract::new(mq, cmd_mq, ecdh_key, Default::default(), address.clone())
+    }
+
+    #[test]
+    fn periodic_hook_is_due_at_the_expected_block() {
+        let address = AccountId::new([3; 32]);
+        let mut recv_mq = MessageDispatcher::new();
+        let mut contracts = ContractsKeeper::default();
+        contracts.insert(new_contract(&address, &mut recv_mq));
+        let periodic = |interval| HookPoint::Periodic { interval };
+
+        let contract = contracts.get_mut(&address).unwrap();
+        set_hook(contract, periodic(5), 0x1234, 100, 10).unwrap();
+        assert!(contracts.with_due_schedules(14).is_empty());
+        assert_eq!(contracts.with_due_schedules(15), vec![address.clone()]);
+
+        // Cancelled with an interval of 0.
+        let contract = contracts.get_mut(&address).unwrap();
+        set_hook(contract, periodic(0), 0x1234, 100, 20).unwrap();
+        assert!(contracts.with_due_schedules(BlockNumber::MAX).is_empty());
+    }
+}
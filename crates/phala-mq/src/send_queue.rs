@@ -2,6 +2,8 @@ use crate::{
     Message, MessageOrigin, MessageSigner, Mutex, SenderId, SignedMessage, SigningMessage,
 };
 use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::ops::Bound::{Included, Unbounded};
+use parity_scale_codec::Encode;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Serialize, Deserialize, Clone)]
@@ -103,6 +105,51 @@ impl MessageSendQueue {
             .collect()
     }
 
+    /// Takes a page of the messages in the order of the senders, starting from the message of
+    /// `start` (a sender and a sequence), or from the beginning if None.
+    ///
+    /// The page is filled up to `max_bytes` of encoded messages but holds at least one message.
+    /// Only the messages in the page are cloned. Returns the page along with where the next page
+    /// starts, None if this is the last page.
+    pub fn messages_page(
+        &self,
+        start: Option<(&SenderId, u64)>,
+        max_bytes: usize,
+    ) -> (Vec<(SenderId, Vec<SignedMessage>)>, Option<(SenderId, u64)>) {
+        let inner = self.inner.lock();
+        let channels = match start {
+            Some((sender, _)) => inner.range::<SenderId, _>((Included(sender), Unbounded)),
+            None => inner.range::<SenderId, _>(..),
+        };
+        let mut page = Vec::new();
+        let mut page_size = 0;
+        for (sender, channel) in channels {
+            let min_sequence = match start {
+                Some((start, sequence)) if start == sender => sequence,
+                _ => 0,
+            };
+            let mut taken = Vec::new();
+            for message in channel.messages.iter() {
+                if message.sequence < min_sequence {
+                    continue;
+                }
+                let size = message.encoded_size();
+                if page_size > 0 && page_size + size > max_bytes {
+                    if !taken.is_empty() {
+                        page.push((sender.clone(), taken));
+                    }
+                    return (page, Some((sender.clone(), message.sequence)));
+                }
+                page_size += size;
+                taken.push(message.clone());
+            }
+            if !taken.is_empty() {
+                page.push((sender.clone(), taken));
+            }
+        }
+        (page, None)
+    }
+
     pub fn messages(&self, sender: &SenderId) -> Vec<SignedMessage> {
         let inner = self.inner.lock();
         inner
@@ -304,6 +351,55 @@ mod tests {
         assert_eq!(mq.count_messages(), 1);
     }
 
+    #[test]
+    fn pages_the_messages_in_order() {
+        let mq = MessageSendQueue::new();
+        for sender in [MessageOrigin::Reserved, MessageOrigin::Gatekeeper] {
+            let ch = msg_channel::MessageChannel::new(mq.clone(), sender, TestSigner);
+            for _ in 0..5 {
+                ch.push_message(&TestMessage(b"hello".to_vec()));
+            }
+        }
+        let size = mq.all_messages()[0].encoded_size();
+
+        let mut pages = Vec::new();
+        let mut start = None;
+        loop {
+            let (page, next) = mq.messages_page(
+                start
+                    .as_ref()
+                    .map(|(sender, seq): &(SenderId, u64)| (sender, *seq)),
+                size * 3,
+            );
+            let count: usize = page.iter().map(|(_, messages)| messages.len()).sum();
+            assert!(count <= 3);
+            pages.push(page);
+            match next {
+                Some(next) => start = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(pages.len(), 4);
+        // A sender spans over the pages.
+        assert_eq!(pages[1].len(), 2);
+        let mut all: BTreeMap<SenderId, Vec<SignedMessage>> = BTreeMap::new();
+        for (sender, messages) in pages.into_iter().flatten() {
+            all.entry(sender).or_default().extend(messages);
+        }
+        assert_eq!(all, mq.all_messages_grouped());
+
+        // At least one message in a page, however small.
+        let (page, next) = mq.messages_page(Some((&MessageOrigin::Reserved, 4)), 1);
+        assert_eq!(
+            page,
+            vec![(
+                MessageOrigin::Reserved,
+                mq.messages(&MessageOrigin::Reserved)[4..].to_vec()
+            )]
+        );
+        assert_eq!(next, None);
+    }
+
     #[test]
     fn dump_type_info() {
         insta::assert_display_snapshot!(type_info_stringify::<
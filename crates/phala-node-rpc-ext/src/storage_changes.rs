@@ -1,6 +1,8 @@
 use super::*;
 pub use ext_types::*;
+use sp_core::storage::{well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX, ChildInfo};
 use sp_runtime::StateVersion;
+use sp_state_machine::IterArgs;
 
 /// State RPC errors.
 #[derive(Debug, thiserror::Error)]
@@ -129,17 +131,36 @@ where
                     .state_at(hash)
                     .map_err(|e| Error::invalid_block(id, e))?;
                 let state_root = get_state_root!(&state);
+                let pairs = |child_info: Option<ChildInfo>| -> StorageCollection<_, _> {
+                    let args = IterArgs {
+                        child_info,
+                        ..Default::default()
+                    };
+                    state
+                        .pairs(args)
+                        .expect("Should get the pairs iter")
+                        .map(|pair| {
+                            let (k, v) = pair.expect("Should get the key and value");
+                            (StorageKey(k), Some(StorageKey(v)))
+                        })
+                        .collect()
+                };
+                let main_storage_changes = pairs(None);
+                // The child tries are only referenced by their roots in the main trie, so they
+                // have to be dumped one by one, keyed by the unprefixed child storage key as in
+                // the storage changes of the other blocks.
+                let child_storage_changes = main_storage_changes
+                    .iter()
+                    .filter_map(|(k, _)| k.0.strip_prefix(DEFAULT_CHILD_STORAGE_KEY_PREFIX))
+                    .map(|child_key| {
+                        let child_info = ChildInfo::new_default(child_key);
+                        (StorageKey(child_key.to_vec()), pairs(Some(child_info)))
+                    })
+                    .collect();
                 return Ok(StorageChangesWithRoot {
                     changes: StorageChanges {
-                        main_storage_changes: state
-                            .pairs(Default::default())
-                            .expect("Should get the pairs iter")
-                            .map(|pair| {
-                                let (k, v) = pair.expect("Should get the key and value");
-                                (StorageKey(k), Some(StorageKey(v)))
-                            })
-                            .collect(),
-                        child_storage_changes: vec![],
+                        main_storage_changes,
+                        child_storage_changes,
                     },
                     state_root,
                 });
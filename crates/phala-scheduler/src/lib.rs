@@ -1,4 +1,7 @@
-pub use request_scheduler::RequestScheduler;
+pub use request_scheduler::{
+    AdmissionPolicy, Aging, BurstCredit, FlowSnapshot, QueueObserver, RequestScheduler,
+    ResourceCost, ResourceWeights,
+};
 pub use task_scheduler::TaskScheduler;
 
 mod request_scheduler;
@@ -34,8 +34,171 @@ pub struct DumpInfo<FlowId> {
 pub enum AcquireError {
     #[error("fair queue overloaded")]
     Overloaded,
+    #[error("rejected by the admission policy")]
+    Rejected,
     #[error("canceled while acquiring slot from the fair queue")]
     Canceled,
+    #[error("no serving slot available in the fair queue")]
+    Busy,
+}
+
+/// A snapshot of the flow stats, passed to the [`AdmissionPolicy`] on each acquire.
+pub struct FlowSnapshot<'a, FlowId> {
+    pub flow_id: &'a FlowId,
+    pub weight: u32,
+    /// The moving average cost of the recent requests of the flow.
+    pub recent_cost: VirtualTime,
+    /// Number of requests of the flow waiting in the backlog.
+    pub queued: usize,
+    /// Number of requests of all flows waiting in the backlog.
+    pub backlog: usize,
+    pub backlog_cap: usize,
+    pub serving: u32,
+    pub depth: u32,
+    pub counters: &'a Counters,
+}
+
+impl<FlowId> FlowSnapshot<'_, FlowId> {
+    /// The share of the backlog occupied by the flow, in range [0, 1].
+    pub fn queue_share(&self) -> f64 {
+        if self.backlog == 0 {
+            return 0.0;
+        }
+        self.queued as f64 / self.backlog as f64
+    }
+}
+
+/// Decays the finish tag of an idle flow toward the global virtual time.
+///
+/// A flow that burst runs far ahead of the virtual time and, without aging, would wait until all
+/// the other flows catch up before being served again, even after it slows down. Once the flow has
+/// had nothing in the backlog for at least a `half_life`, its lead over the virtual time is halved
+/// per `half_life` of idleness and capped at `max_lead`, which bounds the wait of any request
+/// entering an idle flow. A flow sending back to back keeps its lead untouched.
+#[derive(Clone, Copy, Debug)]
+pub struct Aging {
+    pub half_life: Duration,
+    pub max_lead: VirtualTime,
+}
+
+impl Default for Aging {
+    fn default() -> Self {
+        Self {
+            half_life: Duration::from_secs(10),
+            // 1 second of serving time, in the scale of the request cost.
+            max_lead: 1_000_000_000 << 32,
+        }
+    }
+}
+
+impl Aging {
+    fn decay(&self, lead: VirtualTime, idle: Duration) -> VirtualTime {
+        let half_lives = idle.as_nanos() / self.half_life.as_nanos().max(1);
+        if half_lives == 0 {
+            return lead;
+        }
+        let lead = if half_lives >= VirtualTime::BITS as u128 {
+            0
+        } else {
+            lead >> half_lives
+        };
+        lead.min(self.max_lead)
+    }
+}
+
+/// Discounts the first requests of a flow that has been idle for a while.
+///
+/// The cost of a request is estimated from the moving average of the recent requests of its flow,
+/// so an interactive flow waking up with a burst of queries is charged by the stale average of its
+/// last activity. Once a flow has been idle for at least `idle`, its next `requests` requests are
+/// charged `cost_percent` percent of the estimated cost. The actual costs still feed the average,
+/// and the credits are only regained by going idle again, which keeps the long-run shares fair.
+#[derive(Clone, Copy, Debug)]
+pub struct BurstCredit {
+    pub idle: Duration,
+    pub requests: u32,
+    pub cost_percent: u32,
+}
+
+impl BurstCredit {
+    fn discount(&self, cost: VirtualTime) -> VirtualTime {
+        cost * self.cost_percent.min(100) as VirtualTime / 100
+    }
+}
+
+/// The resources used by a request, reported when it finishes serving.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceCost {
+    /// The serving time, in the scale of [`ServingGuard::set_cost`].
+    pub time: VirtualTime,
+    /// The peak memory used, in bytes.
+    pub memory: u64,
+}
+
+/// Combines the resources used by a request into its cost, by weighted dominant resource fairness.
+///
+/// The memory is brought to the scale of the serving time, `memory_per_sec` bytes weighing as much
+/// as a second of serving, and each resource is scaled by its weight in percent. The request is
+/// charged by its dominant resource, i.e. the largest of them, so that memory-heavy queries can't
+/// take more than the share of their flow by being quick.
+#[derive(Clone, Copy, Debug)]
+pub struct ResourceWeights {
+    pub time_percent: u32,
+    pub memory_percent: u32,
+    pub memory_per_sec: u64,
+}
+
+impl Default for ResourceWeights {
+    fn default() -> Self {
+        Self {
+            time_percent: 100,
+            memory_percent: 100,
+            memory_per_sec: 256 << 20,
+        }
+    }
+}
+
+impl ResourceWeights {
+    fn combine(&self, cost: &ResourceCost) -> VirtualTime {
+        // 1 second of serving time, in the scale of the request cost.
+        const SECOND: VirtualTime = 1_000_000_000 << 32;
+        let time = cost.time * self.time_percent as VirtualTime / 100;
+        let memory = cost.memory as VirtualTime * SECOND
+            / self.memory_per_sec.max(1) as VirtualTime
+            * self.memory_percent as VirtualTime
+            / 100;
+        time.max(memory)
+    }
+}
+
+/// A pluggable policy to shed load before a request enters the fair queue.
+///
+/// The scheduler itself only rejects requests when the backlog is full. A policy can reject
+/// earlier, e.g. with per-flow rate limits or when the serving latency is too high.
+pub trait AdmissionPolicy<FlowId>: Send {
+    /// Returns whether the request should be admitted.
+    fn admit(&mut self, snapshot: &FlowSnapshot<FlowId>) -> bool;
+
+    /// Called when a request of the flow finishes serving.
+    fn on_release(&mut self, _flow_id: &FlowId, _cost: VirtualTime, _elapsed: Duration) {}
+}
+
+/// Callbacks on the lifecycle of the requests, e.g. to feed the queue telemetry into metrics.
+///
+/// The callbacks are invoked with the scheduler locked, so they should be cheap and must not call
+/// back into the scheduler.
+pub trait QueueObserver<FlowId>: Send {
+    /// A request has been admitted, with `backlog` requests of all flows waiting ahead of it.
+    fn on_enqueue(&mut self, _flow_id: &FlowId, _backlog: usize) {}
+
+    /// A request starts serving after waiting in the backlog for `waited`.
+    fn on_dispatch(&mut self, _flow_id: &FlowId, _waited: Duration) {}
+
+    /// A request has been refused, or dropped from the backlog by an overload.
+    fn on_reject(&mut self, _flow_id: &FlowId, _reason: &AcquireError) {}
+
+    /// A request finishes serving.
+    fn on_complete(&mut self, _flow_id: &FlowId, _cost: VirtualTime, _elapsed: Duration) {}
 }
 
 impl<FlowId: FlowIdType> RequestScheduler<FlowId> {
@@ -47,16 +210,86 @@ impl<FlowId: FlowIdType> RequestScheduler<FlowId> {
         }
     }
 
+    pub fn with_admission_policy(
+        backlog_cap: usize,
+        depth: u32,
+        policy: impl AdmissionPolicy<FlowId> + 'static,
+    ) -> Self {
+        let me = Self::new(backlog_cap, depth);
+        me.set_admission_policy(Some(Box::new(policy)));
+        me
+    }
+
+    pub fn set_admission_policy(&self, policy: Option<Box<dyn AdmissionPolicy<FlowId>>>) {
+        self.inner.lock().unwrap().admission_policy = policy;
+    }
+
+    pub fn with_observer(
+        backlog_cap: usize,
+        depth: u32,
+        observer: impl QueueObserver<FlowId> + 'static,
+    ) -> Self {
+        let me = Self::new(backlog_cap, depth);
+        me.set_observer(Some(Box::new(observer)));
+        me
+    }
+
+    pub fn set_observer(&self, observer: Option<Box<dyn QueueObserver<FlowId>>>) {
+        self.inner.lock().unwrap().observer = observer;
+    }
+
+    /// Sets the aging of the idle flows, or disables it with `None`. Disabled unless set.
+    pub fn set_aging(&self, aging: Option<Aging>) {
+        self.inner.lock().unwrap().aging = aging;
+    }
+
+    /// Sets the burst credits of the recently idle flows, or disables them with `None`. Disabled
+    /// unless set.
+    pub fn set_burst_credit(&self, burst_credit: Option<BurstCredit>) {
+        self.inner.lock().unwrap().burst_credit = burst_credit;
+    }
+
+    /// Sets the weights to charge the requests by both the serving time and the memory reported
+    /// with [`ServingGuard::set_memory`], or charges the serving time only with `None`. Disabled
+    /// unless set.
+    pub fn set_resource_weights(&self, weights: Option<ResourceWeights>) {
+        self.inner.lock().unwrap().resource_weights = weights;
+    }
+
     pub async fn acquire(
         &self,
         flow_id: FlowId,
         weight: u32,
     ) -> Result<ServingGuard<FlowId>, AcquireError> {
         // Don't merge the following 2 lines of code into one line or you would get a deadlock.
-        let rx = self.inner.lock().unwrap().acquire(flow_id, weight)?;
+        let rx = self.inner.lock().unwrap().acquire(flow_id, weight, true)?;
         rx.await.or(Err(AcquireError::Canceled))
     }
 
+    /// Blocking version of [`acquire`](Self::acquire), for callers without an async runtime.
+    ///
+    /// Panics if called within an async execution context.
+    pub fn acquire_sync(
+        &self,
+        flow_id: FlowId,
+        weight: u32,
+    ) -> Result<ServingGuard<FlowId>, AcquireError> {
+        // Don't merge the following 2 lines of code into one line or you would get a deadlock.
+        let rx = self.inner.lock().unwrap().acquire(flow_id, weight, true)?;
+        rx.blocking_recv().or(Err(AcquireError::Canceled))
+    }
+
+    /// Acquires a serving slot only if one is available right now, without entering the
+    /// backlog. Returns [`AcquireError::Busy`] otherwise.
+    pub fn try_acquire(
+        &self,
+        flow_id: FlowId,
+        weight: u32,
+    ) -> Result<ServingGuard<FlowId>, AcquireError> {
+        let mut rx = self.inner.lock().unwrap().acquire(flow_id, weight, false)?;
+        rx.try_recv().or(Err(AcquireError::Canceled))
+    }
+
     pub fn purge_inactive_flows(&self, duration: Duration) {
         self.inner.lock().unwrap().purge_inactive_flows(duration);
     }
@@ -110,6 +343,9 @@ struct Flow {
     previous_finish_tag: VirtualTime,
     average_cost: VirtualTime,
     recent_active_time: Instant,
+    queued: usize,
+    /// Number of the following requests to be charged with a discount.
+    burst_credits: u32,
     counters: Counters,
 }
 
@@ -118,6 +354,8 @@ pub struct Counters {
     pub total: u64,
     pub dropped: u64,
     pub time: VirtualTime,
+    /// Sum of the peak memory reported by the requests, in bytes.
+    pub memory: u64,
 }
 
 impl Counters {
@@ -135,6 +373,7 @@ struct Request<FlowId: FlowIdType> {
     flow_id: FlowId,
     start_tag: VirtualTime,
     cost: VirtualTime,
+    enqueued_at: Instant,
     start_signal: Sender<ServingGuard<FlowId>>,
 }
 
@@ -143,20 +382,31 @@ pub struct ServingGuard<FlowId: FlowIdType> {
     flow_id: FlowId,
     start_time: Instant,
     actual_cost: Option<VirtualTime>,
+    memory: u64,
+    /// Set if the slot is released by the scheduler itself, as the requester has gone.
+    detached: bool,
 }
 
 impl<FlowId: FlowIdType> Drop for ServingGuard<FlowId> {
     fn drop(&mut self) {
-        let actual_cost = self.actual_cost.unwrap_or_else(|| {
-            let cost = self.start_time.elapsed().as_nanos() as VirtualTime;
+        if self.detached {
+            return;
+        }
+        let elapsed = self.start_time.elapsed();
+        let time = self.actual_cost.unwrap_or_else(|| {
+            let cost = elapsed.as_nanos() as VirtualTime;
             // Scale it in order to avoid underflow while dividing the cost by the weight.
             cost << 32
         });
+        let cost = ResourceCost {
+            time,
+            memory: self.memory,
+        };
         self.queue
             .inner
             .lock()
             .unwrap()
-            .release(&self.flow_id, actual_cost);
+            .release(&self.flow_id, cost, elapsed);
     }
 }
 
@@ -164,6 +414,16 @@ impl<FlowId: FlowIdType> ServingGuard<FlowId> {
     pub fn set_cost(&mut self, cost: VirtualTime) {
         self.actual_cost = Some(cost);
     }
+
+    /// Reports the peak memory used by the request, charged if the resource weights are set.
+    pub fn set_memory(&mut self, bytes: u64) {
+        self.memory = bytes;
+    }
+
+    pub fn set_resource_cost(&mut self, cost: ResourceCost) {
+        self.actual_cost = Some(cost.time);
+        self.memory = cost.memory;
+    }
 }
 
 struct SchedulerInner<FlowId: FlowIdType> {
@@ -175,6 +435,11 @@ struct SchedulerInner<FlowId: FlowIdType> {
     serving: u32,
     virtual_time: VirtualTime,
     counters: Counters,
+    admission_policy: Option<Box<dyn AdmissionPolicy<FlowId>>>,
+    observer: Option<Box<dyn QueueObserver<FlowId>>>,
+    aging: Option<Aging>,
+    burst_credit: Option<BurstCredit>,
+    resource_weights: Option<ResourceWeights>,
 }
 
 unsafe impl<T: FlowIdType> Send for SchedulerInner<T> {}
@@ -190,30 +455,86 @@ impl<FlowId: FlowIdType> SchedulerInner<FlowId> {
             serving: 0,
             virtual_time: 0,
             counters: Counters::default(),
+            admission_policy: None,
+            observer: None,
+            aging: None,
+            burst_credit: None,
+            resource_weights: None,
         }
     }
 
+    /// Enqueues a request. If `wait` is false, the request is refused unless it can be served
+    /// immediately.
     fn acquire(
         &mut self,
         flow_id: FlowId,
         weight: u32,
+        wait: bool,
     ) -> Result<Receiver<ServingGuard<FlowId>>, AcquireError> {
+        if !wait && self.serving >= self.depth {
+            return Err(self.reject(&flow_id, AcquireError::Busy));
+        }
         let flow = self.flows.entry(flow_id.clone()).or_insert_with(|| Flow {
             previous_finish_tag: 0,
             average_cost: 0,
             recent_active_time: Instant::now(),
+            queued: 0,
+            burst_credits: 0,
             counters: Counters::default(),
         });
 
+        flow.counters.total += 1;
+        self.counters.total += 1;
+
+        let now = Instant::now();
+        let idle = now.saturating_duration_since(flow.recent_active_time);
+        if let Some(aging) = &self.aging {
+            // Requests of the flow still in the backlog hold their tags, only an idle flow ages.
+            if flow.queued == 0 && flow.previous_finish_tag > self.virtual_time {
+                let lead = flow.previous_finish_tag - self.virtual_time;
+                flow.previous_finish_tag = self.virtual_time + aging.decay(lead, idle);
+            }
+        }
+        if let Some(burst_credit) = &self.burst_credit {
+            if flow.queued == 0 && idle >= burst_credit.idle {
+                flow.burst_credits = burst_credit.requests;
+            }
+        }
+        flow.recent_active_time = now;
+
+        if let Some(policy) = &mut self.admission_policy {
+            let snapshot = FlowSnapshot {
+                flow_id: &flow_id,
+                weight,
+                recent_cost: flow.average_cost,
+                queued: flow.queued,
+                backlog: self.backlog.len(),
+                backlog_cap: self.backlog_cap,
+                serving: self.serving,
+                depth: self.depth,
+                counters: &flow.counters,
+            };
+            if !policy.admit(&snapshot) {
+                flow.counters.dropped += 1;
+                self.counters.dropped += 1;
+                return Err(self.reject(&flow_id, AcquireError::Rejected));
+            }
+        }
+
         let start_tag = self.virtual_time.max(flow.previous_finish_tag);
-        let cost = flow.average_cost / weight.max(1) as VirtualTime;
+        let mut cost = flow.average_cost / weight.max(1) as VirtualTime;
+        let discounted = match &self.burst_credit {
+            Some(burst_credit) if flow.burst_credits > 0 => {
+                flow.burst_credits -= 1;
+                cost = burst_credit.discount(cost);
+                true
+            }
+            _ => false,
+        };
         let cost = cost.max(1);
         let finish_tag = start_tag + cost;
         flow.previous_finish_tag = finish_tag;
 
-        flow.counters.total += 1;
-        self.counters.total += 1;
-
         if self.backlog.len() >= self.backlog_cap {
             let (max_start_tag, _) = self
                 .backlog
@@ -221,58 +542,104 @@ impl<FlowId: FlowIdType> SchedulerInner<FlowId> {
                 .expect("Get the latest request from non-empty backlog should not fail");
             if start_tag >= *max_start_tag {
                 flow.previous_finish_tag -= cost;
+                if discounted {
+                    flow.burst_credits += 1;
+                }
                 flow.counters.dropped += 1;
                 self.counters.dropped += 1;
-                return Err(AcquireError::Overloaded);
+                return Err(self.reject(&flow_id, AcquireError::Overloaded));
             }
             // Drop the previous low priority request. This would cancel the corresponding
             // `async acquire`.
             if let Some((_, req)) = self.backlog.pop_last() {
                 if let Some(flow) = self.flows.get_mut(&req.flow_id) {
                     flow.previous_finish_tag -= req.cost;
+                    flow.queued = flow.queued.saturating_sub(1);
                     flow.counters.dropped += 1;
                     self.counters.dropped += 1;
                 }
+                self.reject(&req.flow_id, AcquireError::Overloaded);
             }
         }
 
+        if let Some(observer) = &mut self.observer {
+            observer.on_enqueue(&flow_id, self.backlog.len());
+        }
+
         let (tx, rx) = channel();
 
         let request = Request {
             flow_id,
             start_tag,
             cost,
+            enqueued_at: now,
             start_signal: tx,
         };
 
         if self.serving < self.depth {
             self.dispatch(request);
         } else {
+            if let Some(flow) = self.flows.get_mut(&request.flow_id) {
+                flow.queued += 1;
+            }
             self.backlog.insert(start_tag, request);
         }
 
         Ok(rx)
     }
 
-    fn release(&mut self, flow: &FlowId, actual_cost: VirtualTime) {
-        if let Some(flow) = self.flows.get_mut(flow) {
+    fn release(&mut self, flow_id: &FlowId, cost: ResourceCost, elapsed: Duration) {
+        let actual_cost = match &self.resource_weights {
+            Some(weights) => weights.combine(&cost),
+            None => cost.time,
+        };
+        if let Some(flow) = self.flows.get_mut(flow_id) {
             flow.average_cost = (flow.average_cost * 4 + actual_cost) / 5;
             flow.counters.time += actual_cost;
+            flow.counters.memory = flow.counters.memory.saturating_add(cost.memory);
+        }
+        if let Some(policy) = &mut self.admission_policy {
+            policy.on_release(flow_id, actual_cost, elapsed);
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.on_complete(flow_id, actual_cost, elapsed);
         }
         self.counters.time += actual_cost;
+        self.counters.memory = self.counters.memory.saturating_add(cost.memory);
         self.serving -= 1;
         self.try_pickup_next();
     }
 
     fn try_pickup_next(&mut self) {
-        if let Some((_, request)) = self.backlog.pop_first() {
-            self.dispatch(request)
+        while let Some((_, request)) = self.backlog.pop_first() {
+            if let Some(flow) = self.flows.get_mut(&request.flow_id) {
+                flow.queued = flow.queued.saturating_sub(1);
+            }
+            // Skip the requests canceled while waiting in the backlog.
+            if request.start_signal.is_closed() {
+                continue;
+            }
+            if self.dispatch(request) {
+                break;
+            }
         }
     }
 
-    fn dispatch(&mut self, request: Request<FlowId>) {
+    /// Notifies the observer of the rejection, returning the reason back.
+    fn reject(&mut self, flow_id: &FlowId, reason: AcquireError) -> AcquireError {
+        if let Some(observer) = &mut self.observer {
+            observer.on_reject(flow_id, &reason);
+        }
+        reason
+    }
+
+    /// Hands a serving slot to the request, returns false if the requester has gone.
+    fn dispatch(&mut self, request: Request<FlowId>) -> bool {
         self.serving += 1;
         self.virtual_time = request.start_tag;
+        if let Some(observer) = &mut self.observer {
+            observer.on_dispatch(&request.flow_id, request.enqueued_at.elapsed());
+        }
         let guard = ServingGuard {
             queue: RequestScheduler {
                 inner: self
@@ -283,11 +650,19 @@ impl<FlowId: FlowIdType> SchedulerInner<FlowId> {
             flow_id: request.flow_id,
             start_time: Instant::now(),
             actual_cost: None,
+            memory: 0,
+            detached: false,
         };
-
-        // If the receiver side has been dropped, the ServingGuard would be dropped here
-        // and would further try to pickup next request.
-        let _ = request.start_signal.send(guard);
+        match request.start_signal.send(guard) {
+            Ok(()) => true,
+            Err(mut guard) => {
+                // Dropping the guard as is would lock the scheduler again to release the slot,
+                // deadlocking, so it's released here instead.
+                guard.detached = true;
+                self.serving -= 1;
+                false
+            }
+        }
     }
 
     fn purge_inactive_flows(&mut self, duration: Duration) {
@@ -300,6 +675,7 @@ impl<FlowId: FlowIdType> SchedulerInner<FlowId> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
     use tokio::sync::mpsc;
 
     fn spawn_task(
@@ -325,6 +701,522 @@ mod test {
         tokio::time::sleep(Duration::from_millis(t)).await;
     }
 
+    struct MaxQueued {
+        max: usize,
+        released: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl AdmissionPolicy<u32> for MaxQueued {
+        fn admit(&mut self, snapshot: &FlowSnapshot<u32>) -> bool {
+            snapshot.queued < self.max
+        }
+
+        fn on_release(&mut self, flow_id: &u32, _cost: VirtualTime, _elapsed: Duration) {
+            self.released.lock().unwrap().push(*flow_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admission_policy() {
+        let released = Arc::new(Mutex::new(vec![]));
+        let policy = MaxQueued {
+            max: 1,
+            released: released.clone(),
+        };
+        let queue = RequestScheduler::with_admission_policy(10, 1, policy);
+
+        let serving = queue.acquire(1, 1).await.unwrap();
+        let queued1 = queue.inner.lock().unwrap().acquire(1, 1, true).unwrap();
+        let queued2 = queue.inner.lock().unwrap().acquire(2, 1, true).unwrap();
+        assert!(matches!(
+            queue.acquire(1, 1).await,
+            Err(AcquireError::Rejected)
+        ));
+        assert_eq!(queue.stats_for(&1).dropped, 1);
+        assert_eq!(queue.stats_for(&2).dropped, 0);
+
+        // Flow 2 has the smaller start tag, so it is served first.
+        drop(serving);
+        let guard2 = queued2.await.unwrap();
+        assert!(matches!(
+            queue.acquire(1, 1).await,
+            Err(AcquireError::Rejected)
+        ));
+
+        drop(guard2);
+        let guard1 = queued1.await.unwrap();
+        let queued3 = queue.inner.lock().unwrap().acquire(1, 1, true).unwrap();
+        drop(guard1);
+        drop(queued3.await.unwrap());
+
+        assert_eq!(*released.lock().unwrap(), vec![1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_canceled_waiters_are_skipped_without_deadlock() {
+        // Picking up a waiter gone while queued used to drop its guard under the scheduler lock,
+        // which the guard locks again to release the slot.
+        let queue = RequestScheduler::<u32>::new(10, 1);
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let q = queue.clone();
+        std::thread::spawn(move || {
+            let serving = q.try_acquire(1, 1).unwrap();
+            let canceled = q.inner.lock().unwrap().acquire(2, 1, true).unwrap();
+            let mut queued = q.inner.lock().unwrap().acquire(3, 1, true).unwrap();
+            drop(canceled);
+            drop(serving);
+            let picked = queued.try_recv().is_ok();
+            done_tx.send(picked).unwrap();
+        });
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+        let dump = queue.dump();
+        assert!(dump.backlog.is_empty());
+        assert_eq!(dump.serving, 0);
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl QueueObserver<u32> for Recorder {
+        fn on_enqueue(&mut self, flow_id: &u32, backlog: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("enqueue {flow_id} {backlog}"));
+        }
+
+        fn on_dispatch(&mut self, flow_id: &u32, _waited: Duration) {
+            self.0.lock().unwrap().push(format!("dispatch {flow_id}"));
+        }
+
+        fn on_reject(&mut self, flow_id: &u32, reason: &AcquireError) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("reject {flow_id} {reason:?}"));
+        }
+
+        fn on_complete(&mut self, flow_id: &u32, _cost: VirtualTime, _elapsed: Duration) {
+            self.0.lock().unwrap().push(format!("complete {flow_id}"));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let recorder = Recorder::default();
+        let queue = RequestScheduler::<u32>::with_observer(1, 1, recorder.clone());
+        let serving = queue.try_acquire(1, 1).unwrap();
+        assert!(matches!(queue.try_acquire(2, 1), Err(AcquireError::Busy)));
+        let queued = queue.inner.lock().unwrap().acquire(2, 1, true).unwrap();
+        assert!(queue.inner.lock().unwrap().acquire(3, 1, true).is_err());
+        drop(serving);
+        drop(queued.blocking_recv().unwrap());
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "enqueue 1 0",
+                "dispatch 1",
+                "reject 2 Busy",
+                "enqueue 2 0",
+                "reject 3 Overloaded",
+                "complete 1",
+                "dispatch 2",
+                "complete 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_acquire_sync() {
+        let queue = RequestScheduler::<u32>::new(10, 1);
+        let serving = queue.acquire_sync(1, 1).unwrap();
+        let waiter = std::thread::spawn({
+            let queue = queue.clone();
+            move || queue.acquire_sync(2, 1).is_ok()
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.dump().backlog.len(), 1);
+        drop(serving);
+        assert!(waiter.join().unwrap());
+        assert_eq!(queue.dump().serving, 0);
+        assert_eq!(queue.stats_global().total, 2);
+    }
+
+    #[test]
+    fn test_try_acquire() {
+        let queue = RequestScheduler::<u32>::new(10, 1);
+        let serving = queue.try_acquire(1, 1).unwrap();
+        assert!(matches!(queue.try_acquire(2, 1), Err(AcquireError::Busy)));
+        assert!(queue.dump().backlog.is_empty());
+        drop(serving);
+        let _serving = queue.try_acquire(2, 1).unwrap();
+        assert_eq!(queue.stats_global().total, 2);
+        assert_eq!(queue.stats_global().dropped, 0);
+    }
+
+    /// Returns the number of requests of a busy flow served before the single request of a flow
+    /// that burst earlier, then stayed idle for `idle`.
+    fn wait_after_burst(aging: Option<Aging>, idle: Duration) -> usize {
+        const UNIT: VirtualTime = 1 << 32;
+        let queue = RequestScheduler::<u32>::new(64, 1);
+        queue.set_aging(aging);
+
+        queue.try_acquire(2, 1).unwrap().set_cost(5 * UNIT);
+        // Flow 1 bursts with expensive requests, running far ahead of the virtual time.
+        for _ in 0..10 {
+            let mut guard = queue.try_acquire(1, 1).unwrap();
+            guard.set_cost(100 * UNIT);
+        }
+        std::thread::sleep(idle);
+        // Flow 2 keeps the queue busy with cheap requests.
+        let mut serving = queue.try_acquire(2, 1).unwrap();
+        serving.set_cost(UNIT);
+        let mut pending: Vec<_> = (0..40)
+            .map(|_| (2, queue.inner.lock().unwrap().acquire(2, 1, true).unwrap()))
+            .collect();
+        pending.push((1, queue.inner.lock().unwrap().acquire(1, 1, true).unwrap()));
+
+        let mut served = 0;
+        loop {
+            drop(serving);
+            let (i, guard) = pending
+                .iter_mut()
+                .enumerate()
+                .find_map(|(i, (_, rx))| Some((i, rx.try_recv().ok()?)))
+                .expect("A request should be dispatched");
+            let (flow_id, _) = pending.swap_remove(i);
+            if flow_id == 1 {
+                return served;
+            }
+            served += 1;
+            serving = guard;
+            serving.set_cost(UNIT);
+        }
+    }
+
+    #[test]
+    fn test_aging_bounds_waiting() {
+        const UNIT: VirtualTime = 1 << 32;
+        let aging = Aging {
+            half_life: Duration::from_millis(10),
+            max_lead: 5 * UNIT,
+        };
+        let idle = Duration::from_millis(20);
+        // Without aging, flow 1 waits until flow 2 catches up with its burst.
+        assert!(wait_after_burst(None, idle) > 20);
+        // The lead of flow 1 is capped to 5 requests of flow 2.
+        assert!(wait_after_burst(Some(aging), idle) <= 6);
+    }
+
+    #[test]
+    fn test_aging_decay() {
+        let aging = Aging {
+            half_life: Duration::from_secs(10),
+            max_lead: 1000,
+        };
+        assert_eq!(aging.decay(800, Duration::from_secs(5)), 800);
+        assert_eq!(aging.decay(800, Duration::from_secs(25)), 200);
+        assert_eq!(aging.decay(5000, Duration::from_secs(10)), 1000);
+        assert_eq!(aging.decay(800, Duration::from_secs(100000)), 0);
+    }
+
+    #[test]
+    fn test_aging_keeps_the_lead_without_idle_time() {
+        let aging = Aging {
+            half_life: Duration::from_secs(10),
+            max_lead: 1000,
+        };
+        assert_eq!(aging.decay(5000, Duration::ZERO), 5000);
+        assert_eq!(aging.decay(5000, Duration::from_secs(9)), 5000);
+
+        // The scheduling order of the flows sending back to back is untouched.
+        const UNIT: VirtualTime = 1 << 32;
+        let aging = Aging {
+            half_life: Duration::from_secs(3600),
+            max_lead: UNIT,
+        };
+        assert_eq!(
+            wait_after_burst(Some(aging), Duration::ZERO),
+            wait_after_burst(None, Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_aging_off_by_default() {
+        let queue = RequestScheduler::<u32>::new(64, 1);
+        assert!(queue.inner.lock().unwrap().aging.is_none());
+    }
+
+    #[test]
+    fn test_burst_credit() {
+        const UNIT: VirtualTime = 1 << 32;
+        let queue = RequestScheduler::<u32>::new(64, 1);
+        queue.set_burst_credit(Some(BurstCredit {
+            idle: Duration::from_millis(50),
+            requests: 2,
+            cost_percent: 10,
+        }));
+        let flow_1 = |queue: &RequestScheduler<u32>| {
+            let flows = queue.dump().flows;
+            let (_, average_cost, finish_tag) = flows.into_iter().find(|f| f.0 == 1).unwrap();
+            (average_cost, finish_tag)
+        };
+
+        // Busy requests are charged by the average cost.
+        for _ in 0..3 {
+            queue.try_acquire(1, 1).unwrap().set_cost(100 * UNIT);
+        }
+        let (average_cost, before) = flow_1(&queue);
+        let serving = queue.try_acquire(1, 1).unwrap();
+        assert_eq!(flow_1(&queue).1 - before, average_cost);
+        drop(serving);
+
+        // Requests after an idle period are discounted, until the credits run out.
+        std::thread::sleep(Duration::from_millis(100));
+        let _blocker = queue.try_acquire(2, 1).unwrap();
+        let (average_cost, mut before) = flow_1(&queue);
+        let mut charged = vec![];
+        let mut pending = vec![];
+        for _ in 0..3 {
+            pending.push(queue.inner.lock().unwrap().acquire(1, 1, true).unwrap());
+            let (_, after) = flow_1(&queue);
+            charged.push(after - before);
+            before = after;
+        }
+        assert_eq!(
+            charged,
+            vec![average_cost / 10, average_cost / 10, average_cost]
+        );
+    }
+
+    #[test]
+    fn test_resource_weights_combine() {
+        const SECOND: VirtualTime = 1_000_000_000 << 32;
+        let weights = ResourceWeights {
+            time_percent: 100,
+            memory_percent: 50,
+            memory_per_sec: 1 << 20,
+        };
+        let cost = |time, memory| weights.combine(&ResourceCost { time, memory });
+        // The dominant resource is charged.
+        assert_eq!(cost(3 * SECOND, 1 << 20), 3 * SECOND);
+        assert_eq!(cost(SECOND, 8 << 20), 4 * SECOND);
+        assert_eq!(cost(0, 0), 0);
+    }
+
+    #[test]
+    fn test_memory_heavy_flow_charged_more() {
+        const UNIT: VirtualTime = 1 << 32;
+        let queue = RequestScheduler::<u32>::new(64, 1);
+        let average_cost = |queue: &RequestScheduler<u32>, flow_id| {
+            let flows = queue.dump().flows;
+            flows.into_iter().find(|f| f.0 == flow_id).unwrap().1
+        };
+        let run = |queue: &RequestScheduler<u32>| {
+            for _ in 0..10 {
+                queue.try_acquire(1, 1).unwrap().set_cost(UNIT);
+                queue
+                    .try_acquire(2, 1)
+                    .unwrap()
+                    .set_resource_cost(ResourceCost {
+                        time: UNIT,
+                        memory: 1 << 30,
+                    });
+            }
+        };
+
+        // Without the weights, only the serving time is charged.
+        run(&queue);
+        assert_eq!(average_cost(&queue, 1), average_cost(&queue, 2));
+        assert_eq!(queue.stats_for(&2).memory, 10 << 30);
+
+        queue.set_resource_weights(Some(ResourceWeights::default()));
+        run(&queue);
+        assert!(average_cost(&queue, 2) > 100 * average_cost(&queue, 1));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Acquire { flow_id: u32, weight: u32 },
+        TryAcquire { flow_id: u32, weight: u32 },
+        Release { index: usize, cost: u32 },
+        Cancel { index: usize },
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..4u32, 1..4u32).prop_map(|(flow_id, weight)| Op::Acquire { flow_id, weight }),
+            (0..4u32, 1..4u32).prop_map(|(flow_id, weight)| Op::TryAcquire { flow_id, weight }),
+            (any::<usize>(), 1..100u32).prop_map(|(index, cost)| Op::Release { index, cost }),
+            any::<usize>().prop_map(|index| Op::Cancel { index }),
+        ]
+    }
+
+    /// The number of dispatched and rejected requests of each flow.
+    #[derive(Clone, Default)]
+    struct Outcomes(Arc<Mutex<HashMap<u32, (u64, u64)>>>);
+
+    impl QueueObserver<u32> for Outcomes {
+        fn on_dispatch(&mut self, flow_id: &u32, _waited: Duration) {
+            self.0.lock().unwrap().entry(*flow_id).or_default().0 += 1;
+        }
+
+        fn on_reject(&mut self, flow_id: &u32, _reason: &AcquireError) {
+            self.0.lock().unwrap().entry(*flow_id).or_default().1 += 1;
+        }
+    }
+
+    /// Takes the outcomes of the pending requests, moving the dispatched ones to `serving`.
+    fn settle(
+        pending: &mut Vec<(u32, Receiver<ServingGuard<u32>>)>,
+        serving: &mut Vec<ServingGuard<u32>>,
+        outcomes: &mut HashMap<u32, (u64, u64)>,
+    ) {
+        use tokio::sync::oneshot::error::TryRecvError;
+        pending.retain_mut(|(flow_id, rx)| match rx.try_recv() {
+            Ok(guard) => {
+                outcomes.entry(*flow_id).or_default().0 += 1;
+                serving.push(guard);
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Closed) => {
+                outcomes.entry(*flow_id).or_default().1 += 1;
+                false
+            }
+        });
+    }
+
+    proptest! {
+        #[test]
+        fn prop_invariants_under_random_interleavings(
+            backlog_cap in 1..8usize,
+            depth in 1..4u32,
+            ops in proptest::collection::vec(op(), 1..200),
+        ) {
+            const UNIT: VirtualTime = 1 << 32;
+            let observed = Outcomes::default();
+            let queue = RequestScheduler::<u32>::with_observer(backlog_cap, depth, observed.clone());
+            let mut pending = vec![];
+            let mut serving = vec![];
+            let mut outcomes = HashMap::<u32, (u64, u64)>::new();
+            let mut canceled = HashMap::<u32, u64>::new();
+            let mut virtual_time = 0;
+
+            for op in ops {
+                match op {
+                    Op::Acquire { flow_id, weight } => {
+                        let result = queue.inner.lock().unwrap().acquire(flow_id, weight, true);
+                        match result {
+                            Ok(rx) => pending.push((flow_id, rx)),
+                            Err(_) => outcomes.entry(flow_id).or_default().1 += 1,
+                        }
+                    }
+                    Op::TryAcquire { flow_id, weight } => match queue.try_acquire(flow_id, weight) {
+                        Ok(guard) => {
+                            outcomes.entry(flow_id).or_default().0 += 1;
+                            serving.push(guard);
+                        }
+                        Err(_) => outcomes.entry(flow_id).or_default().1 += 1,
+                    },
+                    Op::Release { index, cost } => {
+                        if !serving.is_empty() {
+                            let mut guard = serving.swap_remove(index % serving.len());
+                            guard.set_cost(cost as VirtualTime * UNIT);
+                        }
+                    }
+                    Op::Cancel { index } => {
+                        if !pending.is_empty() {
+                            let (flow_id, _) = pending.swap_remove(index % pending.len());
+                            *canceled.entry(flow_id).or_default() += 1;
+                        }
+                    }
+                }
+                settle(&mut pending, &mut serving, &mut outcomes);
+
+                let dump = queue.dump();
+                prop_assert!(dump.serving <= depth);
+                prop_assert_eq!(dump.serving as usize, serving.len());
+                prop_assert!(dump.backlog.len() <= backlog_cap);
+                // Work conserving, nothing waits while a slot is free.
+                prop_assert!(dump.backlog.is_empty() || dump.serving == depth);
+                prop_assert!(dump.virtual_time >= virtual_time);
+                virtual_time = dump.virtual_time;
+            }
+
+            // Every request still waiting is served once the slots are released.
+            while let Some(guard) = serving.pop() {
+                drop(guard);
+                settle(&mut pending, &mut serving, &mut outcomes);
+            }
+            prop_assert!(pending.is_empty());
+            let dump = queue.dump();
+            prop_assert_eq!(dump.serving, 0);
+            prop_assert!(dump.backlog.is_empty());
+
+            // No request is both rejected and dispatched. The canceled requests might be rejected
+            // by an overload after they are gone, but are never dispatched.
+            let observed = observed.0.lock().unwrap();
+            for flow_id in 0..4 {
+                let (dispatched, rejected) = outcomes.get(&flow_id).copied().unwrap_or_default();
+                let (observed_dispatched, observed_rejected) =
+                    observed.get(&flow_id).copied().unwrap_or_default();
+                let canceled = canceled.get(&flow_id).copied().unwrap_or_default();
+                prop_assert_eq!(observed_dispatched, dispatched);
+                prop_assert!(observed_rejected >= rejected);
+                prop_assert!(observed_rejected <= rejected + canceled);
+            }
+        }
+    }
+
+    /// Races the releases against the waiters giving up from several threads, failing if a slot
+    /// is lost or the scheduler deadlocks instead of handing the slot over.
+    #[test]
+    fn test_release_under_contention() {
+        let queue = RequestScheduler::<u32>::new(16, 2);
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::spawn({
+            let queue = queue.clone();
+            move || {
+                let rt = tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(4)
+                    .enable_time()
+                    .build()
+                    .unwrap();
+                rt.block_on(async move {
+                    let tasks: Vec<_> = (0..16u32)
+                        .map(|i| {
+                            let queue = queue.clone();
+                            tokio::spawn(async move {
+                                for round in 0..200u32 {
+                                    let acquire = queue.acquire(i % 4, 1);
+                                    if (i + round) % 3 == 0 {
+                                        // Give up early, leaving the request in the backlog.
+                                        let timeout = Duration::from_micros(50);
+                                        let _ = tokio::time::timeout(timeout, acquire).await;
+                                    } else if let Ok(guard) = acquire.await {
+                                        tokio::task::yield_now().await;
+                                        drop(guard);
+                                    }
+                                }
+                            })
+                        })
+                        .collect();
+                    for task in tasks {
+                        task.await.unwrap();
+                    }
+                });
+                let _ = done_tx.send(());
+            }
+        });
+        done_rx
+            .recv_timeout(Duration::from_secs(60))
+            .expect("The scheduler deadlocked or lost a wakeup");
+        let dump = queue.dump();
+        assert_eq!(dump.serving, 0);
+        assert!(dump.backlog.is_empty());
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_eq_cost_eq_weight_normal() {
@@ -8,7 +8,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::iter::FromIterator;
 
 use parity_scale_codec::Codec;
-use sp_core::storage::ChildInfo;
+use sp_core::storage::{well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX, ChildInfo};
 use sp_core::Hasher;
 use sp_state_machine::{Backend, IterArgs, TrieBackend, TrieBackendBuilder};
 use sp_trie::{trie_types::TrieDBMutBuilderV0 as TrieDBMutBuilder, TrieMut};
@@ -123,6 +123,27 @@ where
         let _ = core::mem::replace(&mut self.0, trie);
     }
 
+    /// Same as `load`, along with the default child tries, each given by its storage key without
+    /// the `:child_storage:default:` prefix.
+    ///
+    /// The child roots in `pairs` are skipped, they are computed from the child tries instead.
+    pub fn load_with_children(
+        &mut self,
+        pairs: impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>,
+        children: impl Iterator<Item = (StorageKey, Vec<(StorageKey, StorageValue)>)>,
+    ) where
+        H::Out: From<[u8; 32]>,
+    {
+        self.load(
+            pairs.filter(|(key, _)| !key.as_ref().starts_with(DEFAULT_CHILD_STORAGE_KEY_PREFIX)),
+        );
+        let children: ChildStorageCollection = children
+            .map(|(key, pairs)| (key, pairs.into_iter().map(|(k, v)| (k, Some(v))).collect()))
+            .collect();
+        let (root, transaction) = self.calc_root_if_changes(&Vec::new(), &children);
+        self.apply_changes(root, transaction);
+    }
+
     /// Calculate the new state root given storage changes. Returns the new root and a transaction to apply.
     #[allow(clippy::ptr_arg)]
     pub fn calc_root_if_changes<'a>(
@@ -202,6 +223,17 @@ where
         let _ = core::mem::replace(&mut self.0, TrieBackendBuilder::new(storage, root).build());
     }
 
+    /// Add the given proof nodes into the storage, keeping the existing nodes and the root.
+    pub fn add_proof(&mut self, proof: Vec<Vec<u8>>) {
+        use hash_db::HashDB as _;
+        let root = *self.root();
+        let mut storage = core::mem::take(self).0.into_storage();
+        for value in proof {
+            storage.insert(hash_db::EMPTY_PREFIX, &value);
+        }
+        let _ = core::mem::replace(&mut self.0, TrieBackendBuilder::new(storage, root).build());
+    }
+
     pub fn load_proof(&mut self, proof: Vec<Vec<u8>>) {
         use hash_db::HashDB as _;
         let root = *self.root();
@@ -125,3 +125,36 @@ fn test_apply_main_changes() {
         assert_eq!(format!("{:?}", trie.root()), roots[number + 1]);
     }
 }
+
+#[test]
+fn test_load_with_children() {
+    let mut trie = load_genesis_trie();
+    let child_pairs = vec![
+        (b"a".to_vec(), b"1".to_vec()),
+        (b"b".to_vec(), b"2".to_vec()),
+    ];
+    let child_changes = vec![(
+        b"child".to_vec(),
+        child_pairs
+            .iter()
+            .map(|(k, v)| (k.clone(), Some(v.clone())))
+            .collect(),
+    )];
+    let (root, trans) = trie.calc_root_if_changes(&vec![], &child_changes);
+    trie.apply_changes(root, trans);
+
+    let mut loaded: TrieStorage<NativeBlakeTwo256> = Default::default();
+    loaded.load_with_children(
+        trie.pairs(b"").into_iter(),
+        vec![(b"child".to_vec(), child_pairs)].into_iter(),
+    );
+    assert_eq!(loaded.root(), trie.root());
+
+    // The child trie is there to apply the later changes to.
+    let child_changes = vec![(b"child".to_vec(), vec![(b"a".to_vec(), None)])];
+    let (root, trans) = trie.calc_root_if_changes(&vec![], &child_changes);
+    trie.apply_changes(root, trans);
+    let (root, trans) = loaded.calc_root_if_changes(&vec![], &child_changes);
+    loaded.apply_changes(root, trans);
+    assert_eq!(loaded.root(), trie.root());
+}
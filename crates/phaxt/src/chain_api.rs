@@ -5,10 +5,37 @@ use subxt::ext::scale_value::At;
 use subxt::rpc::types::{BlockNumber as SubxtBlockNumber, NumberOrHex};
 use subxt::storage::Storage;
 
+use hash_db::{HashDB, EMPTY_PREFIX};
 use parity_scale_codec::{Decode, Encode};
 use phala_types::{VersionedWorkerEndpoints, WorkerPublicKey};
+use sp_core::Blake2Hasher;
+use sp_trie::trie_types::TrieDBBuilder;
+use sp_trie::{MemoryDB, Trie};
 
-use crate::{BlockNumber, ChainApi, Config, Hash, RpcClient};
+use crate::dynamic::events::{filter_events, TransactionFeePaid};
+use crate::{AccountId, BlockNumber, ChainApi, Config, Hash, RpcClient, StorageProof};
+
+/// The values of some storage items at a block, along with a single proof covering all of them.
+#[derive(Debug, Clone)]
+pub struct StorageSnapshot {
+    pub hash: Hash,
+    /// The keys with their values, None for the absent ones
+    pub entries: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    pub proof: StorageProof,
+}
+
+impl StorageSnapshot {
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        self.entries.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn value(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, value)| value.as_deref())
+    }
+}
 
 impl ChainApi {
     async fn storage_at(&self, hash: Option<Hash>) -> Result<Storage<Config, RpcClient>> {
@@ -112,6 +139,31 @@ impl ChainApi {
         Ok(registered)
     }
 
+    /// Sums the fees `account` paid in the blocks `from..to`, from the `TransactionFeePaid`
+    /// events.
+    pub async fn fees_paid(
+        &self,
+        account: &AccountId,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<u128> {
+        let mut fees = 0;
+        for block_number in from..to {
+            let hash = self
+                .rpc()
+                .block_hash(Some(block_number.into()))
+                .await?
+                .ok_or_else(|| anyhow!("Block number not found"))?;
+            let events = self.events().at(hash).await?;
+            for paid in filter_events::<TransactionFeePaid>(events.iter())? {
+                if &paid.who == account {
+                    fees += paid.actual_fee;
+                }
+            }
+        }
+        Ok(fees)
+    }
+
     pub async fn worker_added_at(&self, worker: &[u8]) -> Result<Option<BlockNumber>> {
         let worker = Value::from_bytes(worker);
         let address = subxt::dynamic::storage("PhalaRegistry", "WorkerAddedAt", vec![worker]);
@@ -132,7 +184,8 @@ impl ChainApi {
         Ok(Some(block_number as _))
     }
 
-    async fn fetch<K: Encode, V: Decode>(
+    /// Fetches and decodes the storage item at the latest block, keyed by `key` if it's a map.
+    pub async fn fetch<K: Encode, V: Decode>(
         &self,
         pallet: &str,
         name: &str,
@@ -150,7 +203,7 @@ impl ChainApi {
             .await?
             .fetch(&address)
             .await
-            .context("Failed to get worker endpoints")?
+            .with_context(|| format!("Failed to get {pallet}.{name}"))?
         else {
             return Ok(None);
         };
@@ -174,6 +227,62 @@ impl ChainApi {
         Ok(endpoints)
     }
 
+    pub async fn get_gatekeepers(&self) -> Result<Vec<WorkerPublicKey>> {
+        let result = self
+            .fetch::<(), _>("PhalaRegistry", "Gatekeeper", None)
+            .await?;
+        Ok(result.unwrap_or_default())
+    }
+
+    /// Returns the hashes of the relaychain blocks allowed on chain as the genesis of the workers.
+    pub async fn relaychain_genesis_allowlist(&self) -> Result<Vec<Hash>> {
+        let result = self
+            .fetch::<(), _>("PhalaRegistry", "RelaychainGenesisBlockHashAllowList", None)
+            .await?;
+        Ok(result.unwrap_or_default())
+    }
+
+    /// Returns the hashes of the pRuntime builds allowed on chain to register workers.
+    pub async fn pruntime_allowlist(&self) -> Result<Vec<Vec<u8>>> {
+        let result = self
+            .fetch::<(), _>("PhalaRegistry", "PRuntimeAllowList", None)
+            .await?;
+        Ok(result.unwrap_or_default())
+    }
+
+    /// Whether the chain checks the pRuntime builds of the workers against the allowlist.
+    pub fn verify_pruntime(&self) -> Result<bool> {
+        let address = subxt::dynamic::constant("PhalaRegistry", "VerifyPRuntime");
+        self.constants()
+            .at(&address)?
+            .to_value()?
+            .as_bool()
+            .ok_or_else(|| anyhow!("Invalid constant PhalaRegistry.VerifyPRuntime"))
+    }
+
+    /// Returns the free balance of `account` at the latest block, zero if the account doesn't exist.
+    pub async fn free_balance(&self, account: &AccountId) -> Result<u128> {
+        let account = Value::from_bytes(account.encode());
+        let address = subxt::dynamic::storage("System", "Account", vec![account]);
+        let Some(info) = self
+            .storage()
+            .at_latest()
+            .await?
+            .fetch(&address)
+            .await
+            .context("Failed to get account info")?
+        else {
+            return Ok(0);
+        };
+        let free = info
+            .to_value()?
+            .at("data")
+            .and_then(|data| data.at("free"))
+            .and_then(|free| free.as_u128())
+            .ok_or_else(|| anyhow!("Invalid account info"))?;
+        Ok(free)
+    }
+
     pub async fn storage_keys(&self, prefix: &[u8], hash: Option<Hash>) -> Result<Vec<Vec<u8>>> {
         let page = 100;
         let mut keys: Vec<Vec<u8>> = vec![];
@@ -192,6 +301,58 @@ impl ChainApi {
         Ok(keys)
     }
 
+    async fn hash_or_finalized(&self, hash: Option<Hash>) -> Result<Hash> {
+        match hash {
+            Some(hash) => Ok(hash),
+            None => self
+                .rpc()
+                .finalized_head()
+                .await
+                .context("Failed to get finalized head"),
+        }
+    }
+
+    /// Reads the storage items at the block `hash`, or at the finalized head if None, along with
+    /// a single proof of all of them. The values are read out of the proof.
+    pub async fn storage_snapshot(
+        &self,
+        hash: Option<Hash>,
+        keys: impl IntoIterator<Item = &[u8]>,
+    ) -> Result<StorageSnapshot> {
+        let hash = self.hash_or_finalized(hash).await?;
+        let keys: Vec<&[u8]> = keys.into_iter().collect();
+        let (header, read_proof) = futures::try_join!(
+            self.rpc().header(Some(hash)),
+            self.rpc().read_proof(keys.iter().copied(), Some(hash)),
+        )
+        .context("Failed to read storage snapshot")?;
+        let state_root = header
+            .ok_or_else(|| anyhow!("Header of block {hash:?} not found"))?
+            .state_root;
+        let proof: StorageProof = read_proof.proof.into_iter().map(|p| p.0).collect();
+        let entries = read_proof_values(&state_root, &proof, &keys)?;
+        Ok(StorageSnapshot {
+            hash,
+            entries,
+            proof,
+        })
+    }
+
+    /// Same as `storage_snapshot`, but covers all the storage items under the given prefixes.
+    pub async fn storage_snapshot_with_prefixes(
+        &self,
+        hash: Option<Hash>,
+        prefixes: impl IntoIterator<Item = &[u8]>,
+    ) -> Result<StorageSnapshot> {
+        let hash = self.hash_or_finalized(hash).await?;
+        let mut keys = vec![];
+        for prefix in prefixes {
+            keys.extend(self.storage_keys(prefix, Some(hash)).await?);
+        }
+        self.storage_snapshot(Some(hash), keys.iter().map(|k| &k[..]))
+            .await
+    }
+
     pub async fn latest_finalized_block_number(&self) -> Result<BlockNumber> {
         let latest_block_hash = self
             .rpc()
@@ -208,3 +369,66 @@ impl ChainApi {
         Ok(block)
     }
 }
+
+/// Reads the values of `keys` out of `proof`, fails if the proof doesn't cover them under
+/// `state_root`.
+fn read_proof_values(
+    state_root: &Hash,
+    proof: &StorageProof,
+    keys: &[&[u8]],
+) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+    let mut mdb = MemoryDB::<Blake2Hasher>::default();
+    for node in proof {
+        mdb.insert(EMPTY_PREFIX, node);
+    }
+    let trie = TrieDBBuilder::new(&mdb, state_root).build();
+    keys.iter()
+        .map(|key| {
+            let value = trie
+                .get(key)
+                .map_err(|err| anyhow!("Failed to read storage value from the proof: {err:?}"))?;
+            Ok((key.to_vec(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_trie::{trie_types::TrieDBMutBuilderV0 as TrieDBMutBuilder, TrieMut};
+
+    /// A proof of the whole trie built from `pairs`, along with its root.
+    fn proof_of(pairs: &[(&[u8], &[u8])]) -> (Hash, StorageProof) {
+        let mut mdb = MemoryDB::<Blake2Hasher>::default();
+        let mut root = Hash::default();
+        {
+            let mut trie = TrieDBMutBuilder::new(&mut mdb, &mut root).build();
+            for (key, value) in pairs {
+                trie.insert(key, value).unwrap();
+            }
+        }
+        let proof = mdb.drain().into_values().map(|(node, _)| node).collect();
+        (root, proof)
+    }
+
+    #[test]
+    fn test_read_proof_values() {
+        let (root, proof) = proof_of(&[(b"alice", b"1"), (b"bob", &[2; 64])]);
+        let entries = read_proof_values(&root, &proof, &[b"alice", b"bob", b"carol"]).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (b"alice".to_vec(), Some(b"1".to_vec())),
+                (b"bob".to_vec(), Some(vec![2; 64])),
+                (b"carol".to_vec(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_proof_values_rejects_proof_of_another_root() {
+        let (_, proof) = proof_of(&[(b"alice", b"1")]);
+        let (other_root, _) = proof_of(&[(b"alice", b"2")]);
+        assert!(read_proof_values(&other_root, &proof, &[b"alice"]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{ChainApi, Hash};
+
+/// The block time assumed if the chain doesn't expose it in its constants.
+const DEFAULT_BLOCK_TIME: Duration = Duration::from_secs(12);
+
+/// Chain constants that clients depend on, fetched once per chain.
+#[derive(Debug, Clone)]
+pub struct ChainInfo {
+    /// The para id of the chain, `None` if it is not a parachain.
+    pub para_id: Option<u32>,
+    /// The expected block time.
+    pub block_time: Duration,
+    pub ss58_prefix: u16,
+    pub token_symbol: String,
+    pub token_decimals: u32,
+    pub existential_deposit: u128,
+}
+
+/// The values assumed when the chain doesn't tell.
+impl Default for ChainInfo {
+    fn default() -> Self {
+        Self {
+            para_id: None,
+            block_time: DEFAULT_BLOCK_TIME,
+            ss58_prefix: 42,
+            token_symbol: "UNIT".into(),
+            token_decimals: 12,
+            existential_deposit: 0,
+        }
+    }
+}
+
+impl ChainInfo {
+    /// Renders a balance in the unit of the native token, e.g. `1.5 PHA`.
+    pub fn format_balance(&self, amount: u128) -> String {
+        let unit = 10u128.saturating_pow(self.token_decimals);
+        let integer = amount / unit;
+        let fraction = amount % unit;
+        if fraction == 0 {
+            return format!("{integer} {}", self.token_symbol);
+        }
+        let fraction = format!("{fraction:0width$}", width = self.token_decimals as usize);
+        format!(
+            "{integer}.{} {}",
+            fraction.trim_end_matches('0'),
+            self.token_symbol
+        )
+    }
+}
+
+/// Returns the chain constants of the connected chain.
+///
+/// The result is cached by genesis hash, so it's cheap to call it repeatedly.
+pub async fn chain_info(api: &ChainApi) -> Result<Arc<ChainInfo>> {
+    static CACHE: Mutex<BTreeMap<Hash, Arc<ChainInfo>>> = Mutex::new(BTreeMap::new());
+
+    let genesis_hash = api.genesis_hash();
+    if let Some(info) = CACHE.lock().unwrap().get(&genesis_hash) {
+        return Ok(info.clone());
+    }
+    let info = Arc::new(fetch_chain_info(api).await?);
+    CACHE.lock().unwrap().insert(genesis_hash, info.clone());
+    Ok(info)
+}
+
+async fn fetch_chain_info(api: &ChainApi) -> Result<ChainInfo> {
+    let properties = api
+        .rpc()
+        .system_properties()
+        .await
+        .context("Failed to get the system properties")?;
+    // Some chains report a list of tokens, the first one is the native token.
+    let property = |name: &str| match properties.get(name) {
+        Some(serde_json::Value::Array(values)) => values.first().cloned(),
+        value => value.cloned(),
+    };
+    let token_symbol = property("tokenSymbol")
+        .and_then(|v| v.as_str().map(String::from))
+        .unwrap_or_else(|| "UNIT".into());
+    let token_decimals = property("tokenDecimals")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(12) as u32;
+
+    let ss58_prefix = match constant_u128(api, "System", "SS58Prefix") {
+        Ok(prefix) => prefix as u16,
+        Err(_) => property("ss58Format")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(42) as u16,
+    };
+    let existential_deposit = constant_u128(api, "Balances", "ExistentialDeposit").unwrap_or(0);
+    let block_time = match constant_u128(api, "Babe", "ExpectedBlockTime") {
+        Ok(ms) => Duration::from_millis(ms as u64),
+        Err(_) => match constant_u128(api, "Timestamp", "MinimumPeriod") {
+            // The block time is twice the minimum period of the timestamp pallet.
+            Ok(ms) => Duration::from_millis(ms as u64 * 2),
+            Err(_) => DEFAULT_BLOCK_TIME,
+        },
+    };
+    let para_id = api.get_paraid(None).await.ok();
+
+    Ok(ChainInfo {
+        para_id,
+        block_time,
+        ss58_prefix,
+        token_symbol,
+        token_decimals,
+        existential_deposit,
+    })
+}
+
+fn constant_u128(api: &ChainApi, pallet: &str, name: &str) -> Result<u128> {
+    let address = subxt::dynamic::constant(pallet, name);
+    api.constants()
+        .at(&address)?
+        .to_value()?
+        .as_u128()
+        .ok_or_else(|| anyhow!("Invalid constant {pallet}.{name}"))
+}
@@ -1,3 +1,4 @@
+pub mod events;
 pub mod tx;
 
 pub fn storage_key(pallet: &str, entry: &str) -> Vec<u8> {
@@ -0,0 +1,138 @@
+//! Typed events of the Phala pallets.
+//!
+//! The events are decoded from the SCALE encoded fields, so each type here must match the field
+//! layout of the event in the runtime.
+//!
+//! Note that PhalaMq deposits no events. The outcome of a message is only visible from the
+//! `Utility` batch item events or the ingress sequence in its storage.
+
+use anyhow::Result;
+use futures::{stream, Stream, StreamExt};
+use parity_scale_codec::Decode;
+use phala_types::{AttestationProvider, WorkerPublicKey};
+use subxt::events::EventDetails;
+
+use crate::{AccountId, Config, Hash, RpcClient};
+
+/// An event of a Phala pallet, identified by its pallet and variant name.
+pub trait PhalaEvent: Decode {
+    const PALLET: &'static str;
+    const EVENT: &'static str;
+}
+
+macro_rules! phala_event {
+    ($pallet: literal, $name: ident { $($field: ident: $ty: ty),* $(,)? }) => {
+        #[derive(Decode, Clone, Debug, PartialEq, Eq)]
+        pub struct $name {
+            $(pub $field: $ty,)*
+        }
+
+        impl PhalaEvent for $name {
+            const PALLET: &'static str = $pallet;
+            const EVENT: &'static str = stringify!($name);
+        }
+    };
+}
+
+phala_event!("PhalaRegistry", WorkerAdded {
+    pubkey: WorkerPublicKey,
+    attestation_provider: Option<AttestationProvider>,
+    confidence_level: u8,
+});
+
+phala_event!("PhalaRegistry", WorkerUpdated {
+    pubkey: WorkerPublicKey,
+    attestation_provider: Option<AttestationProvider>,
+    confidence_level: u8,
+});
+
+phala_event!(
+    "PhalaComputation",
+    SessionBound {
+        session: AccountId,
+        worker: WorkerPublicKey,
+    }
+);
+
+phala_event!(
+    "PhalaComputation",
+    SessionUnbound {
+        session: AccountId,
+        worker: WorkerPublicKey,
+    }
+);
+
+phala_event!(
+    "PhalaComputation",
+    WorkerStarted {
+        session: AccountId,
+        init_v: u128,
+        init_p: u32,
+    }
+);
+
+phala_event!("PhalaComputation", WorkerStopped { session: AccountId });
+
+phala_event!(
+    "PhalaStakePoolv2",
+    PoolWorkerAdded {
+        pid: u64,
+        worker: WorkerPublicKey,
+        session: AccountId,
+    }
+);
+
+// Not of a Phala pallet, but paid by every extrinsic the workers submit.
+phala_event!(
+    "TransactionPayment",
+    TransactionFeePaid {
+        who: AccountId,
+        actual_fee: u128,
+        tip: u128,
+    }
+);
+
+/// Decodes the event as `E`, None if it's another event.
+pub fn decode_event<E: PhalaEvent>(event: &EventDetails<Config>) -> Result<Option<E>> {
+    if event.pallet_name() != E::PALLET || event.variant_name() != E::EVENT {
+        return Ok(None);
+    }
+    let decoded = E::decode(&mut event.field_bytes())
+        .map_err(|err| anyhow::anyhow!("Failed to decode {}.{}: {err}", E::PALLET, E::EVENT))?;
+    Ok(Some(decoded))
+}
+
+/// Picks the events of type `E`, from either the events of a block or of an extrinsic.
+pub fn filter_events<E: PhalaEvent>(
+    events: impl IntoIterator<Item = Result<EventDetails<Config>, subxt::Error>>,
+) -> Result<Vec<E>> {
+    let mut found = vec![];
+    for event in events {
+        if let Some(event) = decode_event(&event?)? {
+            found.push(event);
+        }
+    }
+    Ok(found)
+}
+
+/// Streams the events of type `E` with the hashes of the finalized blocks they are in.
+pub async fn watch_finalized<E: PhalaEvent>(
+    api: &RpcClient,
+) -> Result<impl Stream<Item = Result<(Hash, E)>>> {
+    let blocks = api.blocks().subscribe_finalized().await?;
+    let events = blocks
+        .then(|block| async move {
+            let block = block?;
+            let events = block.events().await?;
+            let found = filter_events::<E>(events.iter())?;
+            Ok(found.into_iter().map(|e| (block.hash(), e)).collect())
+        })
+        .flat_map(|found: Result<Vec<_>>| {
+            let items: Vec<_> = match found {
+                Ok(found) => found.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            };
+            stream::iter(items)
+        });
+    Ok(events)
+}
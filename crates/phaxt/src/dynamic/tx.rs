@@ -25,13 +25,7 @@ impl TxPayload for EncodedPayload {
         metadata: &subxt::Metadata,
         out: &mut Vec<u8>,
     ) -> Result<(), subxt::Error> {
-        let pallet = metadata.pallet_by_name_err(self.pallet_name)?;
-        let call = pallet.call_variant_by_name(self.call_name).ok_or_else(|| {
-            subxt::error::MetadataError::CallNameNotFound((*self.call_name).to_owned())
-        })?;
-
-        let pallet_index = pallet.index();
-        let call_index = call.index;
+        let (pallet_index, call_index) = call_index(metadata, self.pallet_name, self.call_name)?;
 
         pallet_index.encode_to(out);
         call_index.encode_to(out);
@@ -40,6 +34,28 @@ impl TxPayload for EncodedPayload {
     }
 }
 
+/// The (pallet, call) pairs of the extrinsics constructed in this module.
+pub const CALLS: &[(&str, &str)] = &[
+    ("PhalaRegistry", "register_worker"),
+    ("PhalaRegistry", "register_worker_v2"),
+    ("PhalaRegistry", "update_worker_endpoint"),
+    ("PhalaMq", "sync_offchain_message"),
+    ("Utility", "force_batch"),
+];
+
+/// Looks up the pallet index and call index of the given call in the metadata.
+pub fn call_index(
+    metadata: &subxt::Metadata,
+    pallet_name: &str,
+    call_name: &str,
+) -> Result<(u8, u8), subxt::Error> {
+    let pallet = metadata.pallet_by_name_err(pallet_name)?;
+    let call = pallet
+        .call_variant_by_name(call_name)
+        .ok_or_else(|| subxt::error::MetadataError::CallNameNotFound(call_name.to_owned()))?;
+    Ok((pallet.index(), call.index))
+}
+
 pub fn register_worker(pruntime_info: Vec<u8>, attestation: Vec<u8>, v2: bool) -> EncodedPayload {
     let call_name = if v2 {
         "register_worker_v2"
@@ -62,3 +78,21 @@ pub fn sync_offchain_message(message: SignedMessage) -> EncodedPayload {
     let args = message.encode();
     EncodedPayload::new("PhalaMq", "sync_offchain_message", args)
 }
+
+/// Packs the calls into a `Utility.force_batch`, which dispatches all of them even if some fail.
+pub fn force_batch(
+    metadata: &subxt::Metadata,
+    calls: &[EncodedPayload],
+) -> Result<EncodedPayload, subxt::Error> {
+    let mut inner_calls = Vec::with_capacity(calls.len());
+    for call in calls {
+        let mut encoded = Vec::new();
+        call.encode_call_data_to(metadata, &mut encoded)?;
+        inner_calls.push(Encoded(encoded));
+    }
+    Ok(EncodedPayload::new(
+        "Utility",
+        "force_batch",
+        inner_calls.encode(),
+    ))
+}
@@ -7,9 +7,14 @@ use scale_info::TypeInfo;
 use subxt::config::polkadot::{PolkadotExtrinsicParams, PolkadotExtrinsicParamsBuilder};
 
 mod chain_api;
+mod chain_info;
 pub mod dynamic;
+mod reconnect;
 pub mod rpc;
 
+pub use chain_api::StorageSnapshot;
+pub use chain_info::{chain_info, ChainInfo};
+pub use reconnect::{ConnectionState, ReconnectingRpcClient};
 pub use sp_core;
 
 #[derive(Encode, Decode, Clone, PartialEq, Eq, TypeInfo, PartialOrd, Ord, Debug, EncodeAsType)]
@@ -74,6 +79,20 @@ use jsonrpsee::{
 
 pub async fn connect(uri: &str) -> Result<ChainApi> {
     let rpc_client = ws_client(uri).await?;
+    from_rpc_client(rpc_client).await
+}
+
+/// Same as `connect`, but the connection is re-established transparently if lost. The returned
+/// receiver watches the state of the connection.
+pub async fn connect_reconnecting(
+    uri: &str,
+) -> Result<(ChainApi, tokio::sync::watch::Receiver<ConnectionState>)> {
+    let rpc_client = ReconnectingRpcClient::connect(uri).await?;
+    let state = rpc_client.connection_state();
+    Ok((from_rpc_client(rpc_client).await?, state))
+}
+
+async fn from_rpc_client(rpc_client: impl subxt::rpc::RpcClientT) -> Result<ChainApi> {
     let client = RpcClient::from_rpc_client(Arc::new(rpc_client))
         .await
         .context("Failed to connect to substrate")?;
@@ -0,0 +1,233 @@
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use jsonrpsee::async_client::Client;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use subxt::{
+    error::RpcError,
+    rpc::{RawRpcFuture, RawRpcSubscription, RawValue, RpcClientT},
+};
+use tokio::sync::{mpsc, watch, Mutex, RwLock};
+
+const MIN_REDIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_REDIAL_DELAY: Duration = Duration::from_secs(30);
+/// Requests fail if the node is not back within this time.
+const REDIAL_TIMEOUT: Duration = Duration::from_secs(120);
+const SUBSCRIPTION_BUFFER: usize = 64;
+
+type RawRpcStream = Pin<Box<dyn Stream<Item = Result<Box<RawValue>, RpcError>> + Send + 'static>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting,
+    /// The node could not be reached within the redial timeout, it is dialed again on the next
+    /// request.
+    Disconnected,
+}
+
+struct Inner {
+    url: String,
+    client: RwLock<Arc<Client>>,
+    /// Serializes the redials, so that the requests failed together trigger only one.
+    redial: Mutex<()>,
+    state: watch::Sender<ConnectionState>,
+}
+
+impl Inner {
+    /// Returns a connected client, redialing the node if the connection is lost.
+    async fn connected(&self) -> Result<Arc<Client>, RpcError> {
+        let client = self.client.read().await.clone();
+        if client.is_connected() {
+            return Ok(client);
+        }
+        let _redial = self.redial.lock().await;
+        let client = self.client.read().await.clone();
+        if client.is_connected() {
+            return Ok(client);
+        }
+
+        self.state.send_replace(ConnectionState::Reconnecting);
+        let deadline = Instant::now() + REDIAL_TIMEOUT;
+        let mut delay = MIN_REDIAL_DELAY;
+        loop {
+            match crate::ws_client(&self.url).await {
+                Ok(client) => {
+                    let client = Arc::new(client);
+                    *self.client.write().await = client.clone();
+                    self.state.send_replace(ConnectionState::Connected);
+                    return Ok(client);
+                }
+                Err(err) if Instant::now() + delay >= deadline => {
+                    self.state.send_replace(ConnectionState::Disconnected);
+                    return Err(RpcError::ClientError(err.into()));
+                }
+                Err(_) => {
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_REDIAL_DELAY);
+                }
+            }
+        }
+    }
+}
+
+/// Whether a request or subscription cut by a disconnection can be sent again.
+///
+/// The `author_*` methods submit extrinsics or touch the keystore, and might have reached the node
+/// before the connection dropped. Sending them again could submit the same extrinsic twice, or
+/// make a watched extrinsic look like it was rejected, so the caller gets the error instead.
+fn is_replayable(method: &str) -> bool {
+    !method.starts_with("author_")
+}
+
+/// A websocket RPC client that redials the node when the connection is lost.
+///
+/// A request failed by the disconnection is sent again once reconnected, and the subscriptions
+/// are resubscribed with their original parameters, so the subscribers only see a gap in the
+/// notifications. The `author_*` methods are never replayed, as they might have reached the node
+/// already. The connection state can be watched with [`Self::connection_state`].
+#[derive(Clone)]
+pub struct ReconnectingRpcClient {
+    inner: Arc<Inner>,
+}
+
+impl ReconnectingRpcClient {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = crate::ws_client(url).await?;
+        let (state, _) = watch::channel(ConnectionState::Connected);
+        Ok(Self {
+            inner: Arc::new(Inner {
+                url: url.into(),
+                client: RwLock::new(Arc::new(client)),
+                redial: Mutex::new(()),
+                state,
+            }),
+        })
+    }
+
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.inner.state.subscribe()
+    }
+}
+
+impl RpcClientT for ReconnectingRpcClient {
+    fn request_raw<'a>(
+        &'a self,
+        method: &'a str,
+        params: Option<Box<RawValue>>,
+    ) -> RawRpcFuture<'a, Box<RawValue>> {
+        Box::pin(async move {
+            let client = self.inner.connected().await?;
+            match client.request_raw(method, params.clone()).await {
+                Err(_) if !client.is_connected() && is_replayable(method) => {
+                    let client = self.inner.connected().await?;
+                    client.request_raw(method, params).await
+                }
+                result => result,
+            }
+        })
+    }
+
+    fn subscribe_raw<'a>(
+        &'a self,
+        sub: &'a str,
+        params: Option<Box<RawValue>>,
+        unsub: &'a str,
+    ) -> RawRpcFuture<'a, RawRpcSubscription> {
+        Box::pin(async move {
+            let client = self.inner.connected().await?;
+            let subscription = client.subscribe_raw(sub, params.clone(), unsub).await?;
+            let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUFFER);
+            tokio::spawn(resume_subscription(
+                self.inner.clone(),
+                client,
+                subscription.stream,
+                (sub.to_string(), params, unsub.to_string()),
+                tx,
+            ));
+            let stream = futures::stream::unfold(rx, |mut rx| async move {
+                rx.recv().await.map(|item| (item, rx))
+            });
+            Ok(RawRpcSubscription {
+                stream: Box::pin(stream),
+                id: subscription.id,
+            })
+        })
+    }
+}
+
+/// Forwards the notifications of a subscription, resubscribing whenever the connection is lost.
+async fn resume_subscription(
+    inner: Arc<Inner>,
+    mut client: Arc<Client>,
+    mut stream: RawRpcStream,
+    (sub, params, unsub): (String, Option<Box<RawValue>>, String),
+    tx: mpsc::Sender<Result<Box<RawValue>, RpcError>>,
+) {
+    loop {
+        loop {
+            tokio::select! {
+                item = stream.next() => match item {
+                    Some(item) => {
+                        if tx.send(item).await.is_err() {
+                            return;
+                        }
+                    }
+                    None => break,
+                },
+                _ = tx.closed() => return,
+            }
+        }
+        if client.is_connected() {
+            // Closed by the node rather than by a disconnection.
+            return;
+        }
+        if !is_replayable(&sub) {
+            let err = anyhow::anyhow!("Connection lost, not resubscribing to {sub}");
+            let _ = tx.send(Err(RpcError::ClientError(err.into()))).await;
+            return;
+        }
+        let resubscribed = async {
+            let client = inner.connected().await?;
+            let subscription = client.subscribe_raw(&sub, params.clone(), &unsub).await?;
+            Ok::<_, RpcError>((client, subscription.stream))
+        };
+        match resubscribed.await {
+            Ok((new_client, new_stream)) => {
+                client = new_client;
+                stream = new_stream;
+            }
+            Err(err) => {
+                let _ = tx.send(Err(err)).await;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_replayable;
+
+    #[test]
+    fn replays_only_the_reads() {
+        for method in [
+            "chain_getBlockHash",
+            "state_getStorage",
+            "chain_subscribeFinalizedHeads",
+            "system_accountNextIndex",
+        ] {
+            assert!(is_replayable(method), "{method}");
+        }
+        for method in [
+            "author_submitExtrinsic",
+            "author_submitAndWatchExtrinsic",
+            "author_insertKey",
+        ] {
+            assert!(!is_replayable(method), "{method}");
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use parity_scale_codec::{Decode, Encode, Error as CodecError, Input, Output};
 use phala_node_rpc_ext_types::{GetStorageChangesResponse, GetStorageChangesResponseWithRoot};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::to_value as to_json_value;
@@ -7,6 +8,10 @@ use subxt::{
 };
 
 pub use sp_core::storage::{StorageData, StorageKey};
+use sp_core::Bytes;
+
+/// The number of the child storage keys and values fetched in a request.
+const CHILD_STORAGE_ENTRIES_BATCH: usize = 1000;
 
 pub trait ExtraRpcExt {
     type Config: Config;
@@ -66,6 +71,55 @@ impl<'a, T: Config> ExtraRpcClient<'a, T> {
         Ok(data)
     }
 
+    /// Returns the pairs of the child trie at `child_key`, the full storage key of the child
+    /// trie with the `:child_storage:default:` prefix
+    ///
+    /// The keys are listed page by page, so that a large child trie doesn't exceed the response
+    /// size limit of the node.
+    pub async fn child_storage_pairs(
+        &self,
+        child_key: StorageKey,
+        hash: Option<T::Hash>,
+    ) -> Result<Vec<(StorageKey, StorageData)>, Error> {
+        let mut pairs = vec![];
+        let mut start_key: Option<StorageKey> = None;
+        loop {
+            let params = rpc_params![
+                to_json_value(&child_key)?,
+                to_json_value(StorageKey(vec![]))?,
+                to_json_value(CHILD_STORAGE_ENTRIES_BATCH)?,
+                to_json_value(&start_key)?,
+                to_json_value(hash)?
+            ];
+            let keys: Vec<StorageKey> = self
+                .client
+                .request("childstate_getKeysPaged", params)
+                .await?;
+            if keys.is_empty() {
+                return Ok(pairs);
+            }
+            let params = rpc_params![
+                to_json_value(&child_key)?,
+                to_json_value(&keys)?,
+                to_json_value(hash)?
+            ];
+            let values: Vec<Option<StorageData>> = self
+                .client
+                .request("childstate_getStorageEntries", params)
+                .await?;
+            pairs.extend(
+                keys.iter()
+                    .cloned()
+                    .zip(values)
+                    .filter_map(|(key, value)| Some((key, value?))),
+            );
+            if keys.len() < CHILD_STORAGE_ENTRIES_BATCH {
+                return Ok(pairs);
+            }
+            start_key = keys.last().cloned();
+        }
+    }
+
     /// Fetch block syncing status
     pub async fn system_sync_state(&self) -> Result<SyncState, Error> {
         self.client.request("system_syncState", rpc_params![]).await
@@ -82,6 +136,55 @@ impl<'a, T: Config> ExtraRpcClient<'a, T> {
             .request("system_accountNextIndex", rpc_params![&account_id])
             .await
     }
+
+    /// Applies the signed extrinsic on top of the block `at`, or the best block, without
+    /// submitting it
+    pub async fn system_dry_run(
+        &self,
+        encoded_extrinsic: &[u8],
+        at: Option<T::Hash>,
+    ) -> Result<DryRunResult, Error> {
+        let params = rpc_params![Bytes(encoded_extrinsic.to_vec()), to_json_value(at)?];
+        let result: Bytes = self.client.request("system_dryRun", params).await?;
+        Ok(DryRunResult::decode(&mut &result[..])?)
+    }
+
+    /// Calls the runtime API `method` with the SCALE encoded `data`, returns the SCALE encoded
+    /// result
+    pub async fn state_call(
+        &self,
+        method: &str,
+        data: &[u8],
+        at: Option<T::Hash>,
+    ) -> Result<Vec<u8>, Error> {
+        let params = rpc_params![method, Bytes(data.to_vec()), to_json_value(at)?];
+        let result: Bytes = self.client.request("state_call", params).await?;
+        Ok(result.0)
+    }
+
+    /// Checks whether the txpool would accept the signed extrinsic on top of the block `at`, via
+    /// the `TaggedTransactionQueue_validate_transaction` runtime API
+    pub async fn validate_transaction(
+        &self,
+        source: TransactionSource,
+        encoded_extrinsic: &[u8],
+        at: T::Hash,
+    ) -> Result<TransactionValidity, Error>
+    where
+        T::Hash: Encode,
+    {
+        let mut data = source.encode();
+        data.extend_from_slice(encoded_extrinsic);
+        at.encode_to(&mut data);
+        let result = self
+            .state_call(
+                "TaggedTransactionQueue_validate_transaction",
+                &data,
+                Some(at),
+            )
+            .await?;
+        Ok(TransactionValidity::decode(&mut &result[..])?)
+    }
 }
 
 impl<'a, T: Config> ExtraRpcClient<'a, T>
@@ -96,6 +199,108 @@ where
     }
 }
 
+/// The outcome of `system_dryRun`, i.e. the `ApplyExtrinsicResult` of the runtime.
+///
+/// The errors are kept SCALE encoded, they can be decoded against the metadata, e.g. with
+/// `subxt::error::DispatchError::decode_from`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DryRunResult {
+    Success,
+    /// The extrinsic would be included but fail, with the encoded `DispatchError`.
+    DispatchError(Vec<u8>),
+    /// The extrinsic would be refused, with the encoded `TransactionValidityError`.
+    TransactionValidityError(Vec<u8>),
+}
+
+/// Reads the rest of the input, i.e. an error kept encoded.
+fn read_rest<I: Input>(input: &mut I) -> Result<Vec<u8>, CodecError> {
+    let mut bytes = vec![0u8; input.remaining_len()?.unwrap_or_default()];
+    input.read(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl Decode for DryRunResult {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, CodecError> {
+        match (u8::decode(input)?, u8::decode(input)?) {
+            (0, 0) => Ok(Self::Success),
+            (0, 1) => Ok(Self::DispatchError(read_rest(input)?)),
+            (1, kind) => {
+                let mut error = vec![kind];
+                error.extend(read_rest(input)?);
+                Ok(Self::TransactionValidityError(error))
+            }
+            _ => Err("Invalid ApplyExtrinsicResult".into()),
+        }
+    }
+}
+
+impl Encode for DryRunResult {
+    fn encode_to<W: Output + ?Sized>(&self, dest: &mut W) {
+        match self {
+            Self::Success => dest.write(&[0, 0]),
+            Self::DispatchError(error) => {
+                dest.write(&[0, 1]);
+                dest.write(error);
+            }
+            Self::TransactionValidityError(error) => {
+                dest.push_byte(1);
+                dest.write(error);
+            }
+        }
+    }
+}
+
+/// Where a transaction comes from, see `sp_runtime::transaction_validity::TransactionSource`.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionSource {
+    InBlock,
+    Local,
+    External,
+}
+
+/// A transaction accepted by the txpool, see `sp_runtime::transaction_validity::ValidTransaction`.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct ValidTransaction {
+    pub priority: u64,
+    pub requires: Vec<Vec<u8>>,
+    pub provides: Vec<Vec<u8>>,
+    pub longevity: u64,
+    pub propagate: bool,
+}
+
+/// The result of `TaggedTransactionQueue_validate_transaction`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionValidity {
+    Valid(ValidTransaction),
+    /// The extrinsic would be refused, with the encoded `TransactionValidityError`.
+    Invalid(Vec<u8>),
+}
+
+impl Decode for TransactionValidity {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, CodecError> {
+        match u8::decode(input)? {
+            0 => Ok(Self::Valid(ValidTransaction::decode(input)?)),
+            1 => Ok(Self::Invalid(read_rest(input)?)),
+            _ => Err("Invalid TransactionValidity".into()),
+        }
+    }
+}
+
+impl Encode for TransactionValidity {
+    fn encode_to<W: Output + ?Sized>(&self, dest: &mut W) {
+        match self {
+            Self::Valid(valid) => {
+                dest.push_byte(0);
+                valid.encode_to(dest);
+            }
+            Self::Invalid(error) => {
+                dest.push_byte(1);
+                dest.write(error);
+            }
+        }
+    }
+}
+
 /// System sync state for a Substrate-based runtime
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 #[serde(rename_all = "camelCase")]
@@ -108,3 +313,72 @@ pub struct SyncState {
     #[serde(default = "Default::default")]
     pub highest_block: Option<u64>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> ValidTransaction {
+        ValidTransaction {
+            priority: 1,
+            requires: vec![],
+            provides: vec![vec![1]],
+            longevity: 64,
+            propagate: true,
+        }
+    }
+
+    #[test]
+    fn decodes_the_apply_extrinsic_results() {
+        // Ok(Ok(())), Ok(Err(DispatchError::BadOrigin)) and
+        // Err(TransactionValidityError::Invalid(InvalidTransaction::Payment)).
+        let cases = [
+            (vec![0, 0], DryRunResult::Success),
+            (vec![0, 1, 2], DryRunResult::DispatchError(vec![2])),
+            (
+                vec![1, 0, 1],
+                DryRunResult::TransactionValidityError(vec![0, 1]),
+            ),
+        ];
+        for (encoded, result) in cases {
+            assert_eq!(DryRunResult::decode(&mut &encoded[..]).unwrap(), result);
+            assert_eq!(result.encode(), encoded);
+        }
+    }
+
+    #[test]
+    fn decodes_the_transaction_validities() {
+        let mut encoded = vec![0];
+        encoded.extend(1u64.to_le_bytes());
+        encoded.extend([0, 4, 4, 1]);
+        encoded.extend(64u64.to_le_bytes());
+        encoded.push(1);
+        let validity = TransactionValidity::Valid(valid());
+        assert_eq!(
+            TransactionValidity::decode(&mut &encoded[..]).unwrap(),
+            validity
+        );
+        assert_eq!(validity.encode(), encoded);
+
+        // Err(TransactionValidityError::Unknown(UnknownTransaction::NoUnsignedValidator)).
+        let validity = TransactionValidity::Invalid(vec![1, 1]);
+        assert_eq!(validity.encode(), vec![1, 1, 1]);
+        assert_eq!(
+            TransactionValidity::decode(&mut &[1, 1, 1][..]).unwrap(),
+            validity
+        );
+    }
+
+    #[test]
+    fn rejects_the_malformed_results() {
+        let malformed: [&[u8]; 4] = [&[], &[0], &[0, 2], &[2, 0]];
+        for mut encoded in malformed {
+            DryRunResult::decode(&mut encoded).unwrap_err();
+        }
+        let truncated = TransactionValidity::Valid(valid()).encode();
+        let malformed: [&[u8]; 3] = [&[], &[2], &truncated[..truncated.len() - 1]];
+        for mut encoded in malformed {
+            TransactionValidity::decode(&mut encoded).unwrap_err();
+        }
+    }
+}
@@ -48,6 +48,19 @@ mod sidevm_deployer {
         workers: BTreeMap<WorkerId, Vec<BlockNumber>>,
     }
 
+    #[derive(Encode, Decode, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct DeploymentStatus {
+        /// Workers hosting the paid instance.
+        pub workers: Vec<WorkerId>,
+        /// The instance is stopped after this block.
+        pub deadline: BlockNumber,
+        /// Price of the instance per block on all the workers.
+        pub price: Balance,
+        /// Prepaid value for the blocks after the current one, refundable on redeploy.
+        pub remaining_balance: Balance,
+    }
+
     #[derive(Encode, Decode, Debug)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, StorageLayout))]
     struct ContractInstances {
@@ -144,6 +157,36 @@ mod sidevm_deployer {
             }
         }
 
+        /// Returns the paid sidevm instance of the given contract, if it is not expired.
+        #[ink(message)]
+        pub fn deployment_status(&self, contract: AccountId) -> Option<DeploymentStatus> {
+            let instances = self.paid_instances_by_contracts.get(contract)?;
+            if instances.deadline < self.env().block_number() {
+                return None;
+            }
+            Some(DeploymentStatus {
+                remaining_balance: self.remaining_time_to_value(&contract),
+                workers: instances.workers,
+                deadline: instances.deadline,
+                price: instances.price,
+            })
+        }
+
+        /// Returns the contracts hosting a paid sidevm instance on the given worker, with their
+        /// deadlines.
+        #[ink(message)]
+        pub fn deployments_on_worker(&self, worker: WorkerId) -> Vec<(AccountId, BlockNumber)> {
+            let now = self.env().block_number();
+            self.contracts_running_sidevm
+                .iter()
+                .filter_map(|contract| {
+                    let instances = self.paid_instances_by_contracts.get(contract)?;
+                    (instances.deadline >= now && instances.workers.contains(&worker))
+                        .then_some((*contract, instances.deadline))
+                })
+                .collect()
+        }
+
         /// For self upgrade.
         #[ink(message)]
         pub fn set_code(&mut self, code_hash: pink::Hash) -> Result<()> {
@@ -688,6 +731,22 @@ mod sidevm_deployer {
                 assert_eq!(contracts, vec![contract0.into()]);
                 insta::assert_debug_snapshot!(info);
 
+                let status =
+                    with_callee(SIDEVMOP_ADDR, || driver.deployment_status(contract0.into()));
+                assert_eq!(
+                    status,
+                    Some(DeploymentStatus {
+                        workers: workers0.clone(),
+                        deadline: blocks_to_live,
+                        price,
+                        remaining_balance: price * (blocks_to_live - 1) as Balance,
+                    })
+                );
+                let deployments = with_callee(SIDEVMOP_ADDR, || {
+                    driver.deployments_on_worker(workers0[0].into())
+                });
+                assert_eq!(deployments, vec![(contract0.into(), blocks_to_live)]);
+
                 required
             });
 
@@ -753,6 +812,9 @@ mod sidevm_deployer {
                 ink::env::test::advance_block::<PinkEnvironment>();
             }
 
+            let status = with_callee(SIDEVMOP_ADDR, || driver.deployment_status(contract0.into()));
+            assert_eq!(status, None, "Expired instances should not be reported");
+
             // Should be able to deploy to worker group 0 using contract2 after the instance of contract0 terminated
             assume_inside(contract2, || {
                 set_value_transferred(required);
@@ -58,6 +58,9 @@ where
 pub enum HookPoint {
     /// When all events in a block are processed.
     OnBlockEnd,
+    /// Every `interval` blocks after the hook is set, once all events in the block are processed.
+    /// An `interval` of 0 cancels the hook for the selector.
+    Periodic { interval: BlockNumber },
 }
 
 /// System Event used to communicate between the contract and the runtime.
@@ -318,6 +321,9 @@ impl PinkEvent {
 ///
 /// # Supported Hook Points
 ///  - `OnBlockEnd`: The receiver contract will be invoked once all events in a Phala chain block have been processed.
+///  - `Periodic`: The receiver contract will be invoked every `interval` blocks, in the same way as `OnBlockEnd`.
+///    A contract can have up to 8 selectors hooked this way, and the invocations due at a block are limited by a gas
+///    budget per contract, the ones over it being postponed to the next block.
 ///
 /// # Arguments
 ///
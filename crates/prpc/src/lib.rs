@@ -24,6 +24,10 @@ pub mod server {
         DecodeError(DecodeError),
         /// Some error occurred when handling the request
         AppError(String),
+        /// Some error occurred when handling the request, with an application defined code for
+        /// the clients to branch on
+        #[display(fmt = "{_1}")]
+        AppErrorWithCode(u32, String),
         /// Error for contract query
         #[display(fmt = "ContractQueryError({_0})")]
         ContractQueryError(String),
@@ -59,15 +63,27 @@ pub mod server {
 
     /// The final Error type of RPCs to be serialized to protobuf.
     #[derive(Display, Message)]
+    #[display(fmt = "{message}")]
     pub struct ProtoError {
         #[prost(string, tag = "1")]
         pub message: ::prost::alloc::string::String,
+        /// The application defined error code, 0 if not specified.
+        #[prost(uint32, tag = "2")]
+        pub code: u32,
     }
 
     impl ProtoError {
         pub fn new(message: impl Into<String>) -> ProtoError {
             ProtoError {
                 message: message.into(),
+                code: 0,
+            }
+        }
+
+        pub fn with_code(code: u32, message: impl Into<String>) -> ProtoError {
+            ProtoError {
+                message: message.into(),
+                code,
             }
         }
     }
@@ -1,7 +1,10 @@
-use crate::BlockNumber;
+use crate::{cache::BlockInfo, BlockNumber};
+use pherry::headers_cache::{item_hash, range_hash, CacheKind, CachedRange};
 
 use anyhow::Result;
-use rocksdb::DB;
+use log::warn;
+use rocksdb::{Direction, IteratorMode, DB};
+use scale::{Decode, Encode};
 use std::{mem::size_of, sync::Arc};
 
 use serde::{Deserialize, Serialize};
@@ -83,9 +86,83 @@ impl CacheDB {
     }
 
     pub fn put_header(&self, block: BlockNumber, value: &[u8]) -> Result<()> {
+        match BlockInfo::decode(&mut &value[..]) {
+            Ok(info) => {
+                if let Some(record) = info.authority_set_change_record() {
+                    self.put(b'a', block, &record.encode())?;
+                }
+            }
+            Err(_) => warn!("Failed to decode header {block}, authority set change not indexed"),
+        }
         self.put(b'h', block, value)
     }
 
+    /// Returns the encoded authority set change records at or after `block`, at most `limit`.
+    pub fn get_authority_set_changes(&self, block: BlockNumber, limit: usize) -> Vec<Vec<u8>> {
+        self.0
+            .iterator(IteratorMode::From(&mk_key(b'a', block), Direction::Forward))
+            .map_while(|item| item.ok())
+            .take_while(|(key, _)| key.first() == Some(&b'a'))
+            .take(limit)
+            .map(|(_, value)| value.into_vec())
+            .collect()
+    }
+
+    /// Returns the runs of consecutive blocks of `kind` stored within `from..=to`.
+    pub fn get_available_ranges(
+        &self,
+        kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Vec<CachedRange> {
+        let prefix = match kind {
+            CacheKind::Headers => b'h',
+            CacheKind::ParachainHeaders => b'p',
+            CacheKind::StorageChanges => b'c',
+        };
+        let mut ranges = vec![];
+        let mut run: Option<(BlockNumber, BlockNumber, Vec<[u8; 32]>)> = None;
+        let items = self
+            .0
+            .iterator(IteratorMode::From(
+                &mk_key(prefix, from),
+                Direction::Forward,
+            ))
+            .map_while(|item| item.ok())
+            .take_while(|(key, _)| key.first() == Some(&prefix))
+            .filter_map(|(key, value)| {
+                let number = BlockNumber::from_be_bytes(key[1..].try_into().ok()?);
+                Some((number, value))
+            })
+            .take_while(|(number, _)| *number <= to);
+        for (number, value) in items {
+            match &mut run {
+                Some((_, end, hashes)) if *end + 1 == number => {
+                    *end = number;
+                    hashes.push(item_hash(&value));
+                }
+                _ => {
+                    if let Some((from, to, hashes)) = run.take() {
+                        ranges.push(CachedRange {
+                            from,
+                            to,
+                            hash: range_hash(&hashes),
+                        });
+                    }
+                    run = Some((number, number, vec![item_hash(&value)]));
+                }
+            }
+        }
+        if let Some((from, to, hashes)) = run {
+            ranges.push(CachedRange {
+                from,
+                to,
+                hash: range_hash(&hashes),
+            });
+        }
+        ranges
+    }
+
     pub fn get_para_header(&self, block: BlockNumber) -> Option<Vec<u8>> {
         self.get(b'p', block)
     }
@@ -110,6 +187,14 @@ impl CacheDB {
         self.put(b'g', block_number, value)
     }
 
+    pub fn get_genesis_state(&self) -> Option<Vec<u8>> {
+        self.0.get(b"m-genesis-state").ok().flatten()
+    }
+
+    pub fn put_genesis_state(&self, value: &[u8]) -> Result<()> {
+        self.0.put(b"m-genesis-state", value).map_err(Into::into)
+    }
+
     pub fn get_metadata(&self) -> Result<Option<Metadata>> {
         let metadata = self
             .0
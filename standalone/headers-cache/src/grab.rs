@@ -88,6 +88,14 @@ impl<'c> Crawler<'c> {
             db.put_metadata(metadata)?;
             info!("Got genesis at {}", config.genesis_block);
         }
+        if config.grab_storage_changes && db.get_genesis_state().is_none() {
+            info!("Fetching parachain genesis state");
+            let genesis_state = pherry::chain_client::fetch_genesis_storage(&para_api)
+                .await
+                .context("Failed to fetch genesis state")?;
+            db.put_genesis_state(&genesis_state.encode())?;
+            info!("Got genesis state, {} entries", genesis_state.len());
+        }
         Self {
             config,
             db,
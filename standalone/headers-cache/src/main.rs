@@ -47,6 +47,12 @@ enum Import {
         #[arg(default_value = "genesis.bin")]
         input: String,
     },
+    /// Import parachain genesis state from given file to database.
+    GenesisState {
+        /// The grabbed genesis state file to read from
+        #[arg(default_value = "genesis-state.bin")]
+        input: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -116,6 +122,15 @@ enum Grab {
         #[arg(default_value = "genesis.bin")]
         output: String,
     },
+    /// Grab the parachain genesis state and dump it to a file
+    GenesisState {
+        /// The parachain RPC endpoint
+        #[arg(long, default_value = "ws://localhost:9944")]
+        para_node_uri: String,
+        /// The file to write the result to
+        #[arg(default_value = "genesis-state.bin")]
+        output: String,
+    },
 }
 #[derive(Args, Clone)]
 struct Serve {
@@ -125,6 +140,9 @@ struct Serve {
     /// If set, it will sync headers from the given mirror cache
     #[clap(long)]
     mirror: Option<String>,
+    /// Credentials of the mirror cache, token:<token> or hmac:<hex key>
+    #[arg(long, env = "HEADERS_CACHE_MIRROR_AUTH", hide_env_values = true)]
+    mirror_auth: Option<cache::CacheAuth>,
     /// The genesis block bo be synced
     #[clap(long, default_value_t = 8325311)]
     genesis_block: BlockNumber,
@@ -157,6 +175,13 @@ struct Serve {
     /// Token for uploading APIs.
     #[arg(long)]
     token: Option<String>,
+    /// Token required to read from the cache. Reading is open to anyone if neither the read
+    /// token nor the HMAC key is set
+    #[arg(long, env = "HEADERS_CACHE_READ_TOKEN", hide_env_values = true)]
+    read_token: Option<String>,
+    /// Hex encoded key to verify the HMAC signed read requests with
+    #[arg(long, env = "HEADERS_CACHE_HMAC_KEY", hide_env_values = true)]
+    hmac_key: Option<String>,
     /// The max batch size to check headers
     #[clap(long, default_value_t = 100000)]
     check_batch: BlockNumber,
@@ -271,8 +296,14 @@ async fn serve(config: Serve) -> anyhow::Result<()> {
         }
         let db = db.clone();
         tokio::spawn(async move {
-            let result =
-                web_api::sync_from(db, &upstream, config.interval, config.genesis_block).await;
+            let result = web_api::sync_from(
+                db,
+                &upstream,
+                config.mirror_auth.clone(),
+                config.interval,
+                config.genesis_block,
+            )
+            .await;
             if let Err(err) = result {
                 error!("The mirror task exited with error: {}", err);
             }
@@ -474,6 +505,16 @@ async fn grab(what: Grab) -> anyhow::Result<()> {
             let info = cache::fetch_genesis_info(&api, from_block).await?;
             output.write_all(info.encode().as_ref())?;
         }
+        Grab::GenesisState {
+            para_node_uri,
+            output,
+        } => {
+            let para_api = pherry::subxt_connect(&para_node_uri).await?;
+            let mut output = File::create(output)?;
+            let state = pherry::chain_client::fetch_genesis_storage(&para_api).await?;
+            output.write_all(state.encode().as_ref())?;
+            println!("{} entries written", state.len());
+        }
     }
     Ok(())
 }
@@ -545,6 +586,13 @@ async fn import(db: String, what: Import) -> anyhow::Result<()> {
             cache.put_metadata(&metadata)?;
             println!("genesis at {} put", info.block_header.number);
         }
+        Import::GenesisState { input } => {
+            let data = std::fs::read(input)?;
+            let state = <Vec<(Vec<u8>, Vec<u8>)>>::decode(&mut &data[..])
+                .context("Failed to decode the genesis state")?;
+            cache.put_genesis_state(&data)?;
+            println!("genesis state with {} entries put", state.len());
+        }
     }
     cache.flush()?;
     Ok(())
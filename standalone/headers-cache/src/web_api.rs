@@ -3,7 +3,9 @@ use std::pin::pin;
 use anyhow::{bail, Context, Result};
 use log::{debug, error, info};
 use pherry::{
-    headers_cache::{read_items_stream, BlockInfo},
+    headers_cache::{
+        read_items_stream, signed_path, AuthoritySetChangeRecord, BlockInfo, CacheAuth, CacheKind,
+    },
     types::Header,
 };
 use rand::Rng;
@@ -19,7 +21,7 @@ use scale::{Decode, Encode};
 
 use super::Serve as ServeConfig;
 use crate::{db::CacheDB, BlockNumber};
-use auth::Authorized;
+use auth::{Authorized, ReadAuth, ReadAuthorized};
 
 mod auth;
 
@@ -29,46 +31,80 @@ struct App {
 }
 
 #[get("/state")]
-fn state(app: &State<App>) -> String {
+fn state(_auth: ReadAuthorized, app: &State<App>) -> String {
     let metadata = app.db.get_metadata().ok().flatten().unwrap_or_default();
     serde_json::to_string_pretty(&metadata).unwrap_or("{}".into())
 }
 
 #[get("/genesis/<block_number>")]
-fn get_genesis(app: &State<App>, block_number: BlockNumber) -> Result<Vec<u8>, NotFound<String>> {
+fn get_genesis(
+    _auth: ReadAuthorized,
+    app: &State<App>,
+    block_number: BlockNumber,
+) -> Result<Vec<u8>, NotFound<String>> {
     app.db
         .get_genesis(block_number)
         .ok_or_else(|| NotFound("genesis not found".into()))
 }
 
+#[get("/genesis-state")]
+fn get_genesis_state(_auth: ReadAuthorized, app: &State<App>) -> Result<Vec<u8>, NotFound<String>> {
+    app.db
+        .get_genesis_state()
+        .ok_or_else(|| NotFound("genesis state not found".into()))
+}
+
 #[get("/header/<block_number>")]
-fn get_header(app: &State<App>, block_number: BlockNumber) -> Result<Vec<u8>, NotFound<String>> {
+fn get_header(
+    _auth: ReadAuthorized,
+    app: &State<App>,
+    block_number: BlockNumber,
+) -> Result<Vec<u8>, NotFound<String>> {
     app.db
         .get_header(block_number)
         .ok_or_else(|| NotFound("header not found".into()))
 }
 
-#[get("/headers/<start>")]
-fn get_headers(app: &State<App>, start: BlockNumber) -> Result<Vec<u8>, NotFound<()>> {
+/// Returns the headers from `start` to the next justification.
+///
+/// If `until` is given, the headers across multiple justifications are returned, ending at the
+/// last justified header not beyond `until`, or at the first authority set change.
+#[get("/headers/<start>?<until>")]
+fn get_headers(
+    _auth: ReadAuthorized,
+    app: &State<App>,
+    start: BlockNumber,
+    until: Option<BlockNumber>,
+) -> Result<Vec<u8>, NotFound<()>> {
     let latest_just = crate::grab::latest_justification();
     if start > latest_just {
         log::debug!("No more justification yet");
         return Err(NotFound(()));
     }
     let mut headers = vec![];
-    for block in start..start + 10000 {
+    let mut justified = 0;
+    for block in start..start.saturating_add(10000) {
         match app.db.get_header(block) {
             Some(data) => {
                 let info = crate::cache::BlockInfo::decode(&mut &data[..]).map_err(|_| {
                     log::error!("Failed to decode block fetched from db");
                     NotFound(())
                 })?;
-                let end = info.justification.is_some();
-                headers.push(info);
-                if end {
-                    break;
+                if info.justification.is_some() {
+                    if justified > 0 && until.map_or(false, |until| block > until) {
+                        break;
+                    }
+                    let is_set_change = info.authority_set_change.is_some();
+                    headers.push(info);
+                    justified = headers.len();
+                    if is_set_change || until.map_or(true, |until| block >= until) {
+                        break;
+                    }
+                } else {
+                    headers.push(info);
                 }
             }
+            None if justified > 0 => break,
             None => {
                 if start >= crate::grab::genesis_block() {
                     crate::grab::update_404_block(start);
@@ -83,12 +119,31 @@ fn get_headers(app: &State<App>, start: BlockNumber) -> Result<Vec<u8>, NotFound
             }
         }
     }
+    if justified > 0 {
+        headers.truncate(justified);
+    }
     log::info!("Got {} headers", headers.len());
     Ok(headers.encode())
 }
 
+#[get("/authority-set-changes/<start>")]
+fn get_authority_set_changes(
+    _auth: ReadAuthorized,
+    app: &State<App>,
+    start: BlockNumber,
+) -> Vec<u8> {
+    let records = app
+        .db
+        .get_authority_set_changes(start, 16)
+        .into_iter()
+        .filter_map(|data| AuthoritySetChangeRecord::decode(&mut &data[..]).ok())
+        .collect::<Vec<_>>();
+    records.encode()
+}
+
 #[get("/parachain-headers/<start>/<count>")]
 fn get_parachain_headers(
+    _auth: ReadAuthorized,
     app: &State<App>,
     start: BlockNumber,
     count: BlockNumber,
@@ -113,6 +168,7 @@ fn get_parachain_headers(
 
 #[get("/storage-changes/<start>/<count>")]
 fn get_storage_changes(
+    _auth: ReadAuthorized,
     app: &State<App>,
     start: BlockNumber,
     count: BlockNumber,
@@ -135,6 +191,27 @@ fn get_storage_changes(
     Ok(changes.encode())
 }
 
+/// Reports the runs of consecutive blocks of `kind` available in `start..start + count`, with the
+/// hash of each run to check the items fetched later against.
+#[get("/availability/<kind>/<start>/<count>")]
+fn get_availability(
+    _auth: ReadAuthorized,
+    app: &State<App>,
+    kind: &str,
+    start: BlockNumber,
+    count: BlockNumber,
+) -> Result<String, BadRequest<String>> {
+    let kind =
+        CacheKind::from_path(kind).ok_or_else(|| BadRequest(format!("Unknown kind {kind}")))?;
+    if count == 0 {
+        return Ok("[]".into());
+    }
+    let ranges = app
+        .db
+        .get_available_ranges(kind, start, start.saturating_add(count - 1));
+    serde_json::to_string(&ranges).map_err(|e| BadRequest(e.to_string()))
+}
+
 async fn process_items(
     app: &State<App>,
     data: Data<'_>,
@@ -242,21 +319,36 @@ pub(crate) async fn serve(db: CacheDB, config: ServeConfig, token: Option<String
         log::warn!("No token provided, generated a random one: {}", token);
         token
     });
+    let hmac_key = match &config.hmac_key {
+        Some(key) => Some(hex::decode(key.trim_start_matches("0x")).context("Invalid HMAC key")?),
+        None => None,
+    };
+    let read_auth = ReadAuth {
+        token: config.read_token.clone(),
+        hmac_key,
+    };
+    if read_auth.token.is_none() && read_auth.hmac_key.is_none() {
+        log::warn!("No read token or HMAC key provided, the cache is open to anyone");
+    }
     let _ = rocket::build()
         .manage(App {
             db: db.clone(),
             config: config.clone(),
         })
         .manage(auth::Token { value: token })
+        .manage(read_auth)
         .mount(
             "/",
             routes![
                 state,
                 get_genesis,
+                get_genesis_state,
                 get_header,
                 get_headers,
+                get_authority_set_changes,
                 get_parachain_headers,
                 get_storage_changes,
+                get_availability,
                 put_headers,
                 put_parachain_headers,
                 put_storage_changes,
@@ -269,8 +361,17 @@ pub(crate) async fn serve(db: CacheDB, config: ServeConfig, token: Option<String
     Ok(())
 }
 
-async fn http_get(client: &reqwest::Client, url: &str) -> Result<Option<Vec<u8>>> {
-    let response = client.get(url).send().await?;
+async fn http_get(
+    client: &reqwest::Client,
+    auth: Option<&CacheAuth>,
+    base_uri: &str,
+    url: &str,
+) -> Result<Option<Vec<u8>>> {
+    let mut request = client.get(url);
+    if let Some(auth) = auth {
+        request = auth.authorize(request, &signed_path(base_uri, url));
+    }
+    let response = request.send().await?;
     if response.status() == 404 {
         return Ok(None);
     }
@@ -284,6 +385,7 @@ async fn http_get(client: &reqwest::Client, url: &str) -> Result<Option<Vec<u8>>
 pub(crate) async fn sync_from(
     db: CacheDB,
     base_uri: &str,
+    auth: Option<CacheAuth>,
     check_interval: u64,
     genesis_block: BlockNumber,
 ) -> Result<()> {
@@ -300,7 +402,7 @@ pub(crate) async fn sync_from(
     'sync_genesis: {
         if metadata.genesis.is_empty() {
             let url = format!("{base_uri}/genesis/{genesis_block}");
-            let body = match http_get(&http_client, &url).await {
+            let body = match http_get(&http_client, auth.as_ref(), base_uri, &url).await {
                 Ok(Some(body)) => body,
                 Ok(None) => {
                     info!("Genesis {genesis_block} not found in upstream cache");
@@ -320,12 +422,25 @@ pub(crate) async fn sync_from(
         }
     }
 
+    if db.get_genesis_state().is_none() {
+        let url = format!("{base_uri}/genesis-state");
+        match http_get(&http_client, auth.as_ref(), base_uri, &url).await {
+            Ok(Some(body)) => {
+                db.put_genesis_state(&body)
+                    .context("Failed to put genesis state")?;
+                info!("Synced genesis state");
+            }
+            Ok(None) => info!("Genesis state not found in upstream cache"),
+            Err(err) => error!("Failed to sync genesis state from {url}: {err:?}"),
+        }
+    }
+
     let mut next_block = highest + 1;
     loop {
         loop {
             info!("Syncing {next_block}");
             let url = format!("{base_uri}/headers/{next_block}");
-            let body = match http_get(&http_client, &url).await {
+            let body = match http_get(&http_client, auth.as_ref(), base_uri, &url).await {
                 Ok(Some(body)) => body,
                 Ok(None) => {
                     debug!("Block {next_block} not found in upstream cache");
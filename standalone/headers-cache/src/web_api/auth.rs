@@ -1,3 +1,6 @@
+use pherry::headers_cache::{
+    verify_request, verify_token, SIGNATURE_HEADER, TIMESTAMP_HEADER, TOKEN_HEADER,
+};
 use rocket::http::Status;
 use rocket::request::{self, FromRequest};
 use rocket::Request;
@@ -20,11 +23,61 @@ impl<'r> FromRequest<'r> for Authorized {
         if token.value.is_empty() {
             return request::Outcome::Success(Authorized);
         }
-        match request.headers().get_one("X-Token") {
-            Some(header_token) if header_token == token.value => {
+        match request.headers().get_one(TOKEN_HEADER) {
+            Some(header_token) if verify_token(&token.value, header_token) => {
                 request::Outcome::Success(Authorized)
             }
             _ => request::Outcome::Error((Status::Forbidden, ())),
         }
     }
 }
+
+/// The credentials required to read from the cache, reading is open if none is set.
+pub struct ReadAuth {
+    pub token: Option<String>,
+    pub hmac_key: Option<Vec<u8>>,
+}
+
+pub struct ReadAuthorized;
+
+impl ReadAuth {
+    fn verify(&self, request: &Request<'_>) -> bool {
+        if self.token.is_none() && self.hmac_key.is_none() {
+            return true;
+        }
+        let headers = request.headers();
+        if let (Some(token), Some(header_token)) = (&self.token, headers.get_one(TOKEN_HEADER)) {
+            if verify_token(token, header_token) {
+                return true;
+            }
+        }
+        let Some(key) = &self.hmac_key else {
+            return false;
+        };
+        let timestamp = headers
+            .get_one(TIMESTAMP_HEADER)
+            .and_then(|t| t.parse::<u64>().ok());
+        let (Some(timestamp), Some(signature)) = (timestamp, headers.get_one(SIGNATURE_HEADER))
+        else {
+            return false;
+        };
+        verify_request(key, timestamp, &request.uri().to_string(), signature)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadAuthorized {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let auth = request
+            .rocket()
+            .state::<ReadAuth>()
+            .expect("ReadAuth state not available.");
+        if auth.verify(request) {
+            request::Outcome::Success(ReadAuthorized)
+        } else {
+            request::Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
@@ -32,16 +32,17 @@ pub async fn get_authority_with_proof_at(
     api: &RelaychainApi,
     header: &Header,
 ) -> Result<AuthoritySetChange> {
-    let authority_proof = crate::chain_client::read_proofs(
-        api,
-        Some(header.hash()),
-        vec![
-            StorageKeys::authorities_v0(),
-            &StorageKeys::current_set_id(),
-            &StorageKeys::authorities_v1(),
-        ],
-    )
-    .await?;
+    let authority_proof = api
+        .storage_snapshot(
+            Some(header.hash()),
+            [
+                StorageKeys::authorities_v0(),
+                &StorageKeys::current_set_id(),
+                &StorageKeys::authorities_v1(),
+            ],
+        )
+        .await?
+        .proof;
     let mut mdb = MemoryDB::<sp_core::Blake2Hasher>::default();
     for value in authority_proof.iter() {
         mdb.insert(EMPTY_PREFIX, value);
@@ -75,10 +76,8 @@ pub async fn get_authority_with_proof_at(
         bail!("Check grandpa set id failed");
     };
     let id: SetId = Decode::decode(&mut id_value.as_slice()).context("Failed to decode set id")?;
-    Ok(AuthoritySetChange {
-        authority_set: AuthoritySet { list, id },
-        authority_proof,
-    })
+    AuthoritySetChange::new(AuthoritySet { list, id }, authority_proof)
+        .map_err(|err| anyhow!("Invalid authority set change: {err}"))
 }
 
 pub async fn verify(api: &RelaychainApi, header: &Header, justifications: &[u8]) -> Result<()> {
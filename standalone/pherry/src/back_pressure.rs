@@ -0,0 +1,84 @@
+use crate::types::BlockNumber;
+use log::info;
+use phactory_api::prpc::MemoryUsage;
+
+/// Scales the number of blocks per dispatch by the memory pressure reported by pRuntime.
+///
+/// Below the low watermark the blocks are dispatched in full batches, above the high watermark in
+/// batches of the min size, and in between the batch size shrinks linearly with the pressure.
+pub struct BackPressure {
+    min_blocks: BlockNumber,
+    max_blocks: BlockNumber,
+    low_watermark: u8,
+    high_watermark: u8,
+    current: BlockNumber,
+}
+
+impl BackPressure {
+    pub fn new(
+        min_blocks: BlockNumber,
+        max_blocks: BlockNumber,
+        low_watermark: u8,
+        high_watermark: u8,
+    ) -> Self {
+        let max_blocks = max_blocks.max(1);
+        let high_watermark = high_watermark.min(100);
+        Self {
+            min_blocks: min_blocks.clamp(1, max_blocks),
+            max_blocks,
+            low_watermark: low_watermark.min(high_watermark),
+            high_watermark,
+            current: max_blocks,
+        }
+    }
+
+    /// Returns the number of blocks to dispatch in the next batch.
+    pub fn sync_blocks(&mut self, usage: Option<&MemoryUsage>) -> BlockNumber {
+        let blocks = match usage.and_then(used_percent) {
+            Some(used) => self.blocks_at(used),
+            // No memory stats outside of SGX.
+            None => self.max_blocks,
+        };
+        if blocks != self.current {
+            info!(
+                "Memory pressure {:?}%, dispatching {} blocks per batch",
+                usage.and_then(used_percent),
+                blocks
+            );
+            self.current = blocks;
+        }
+        blocks
+    }
+
+    /// Scales the number of blocks coalesced beyond a batch along with the batch size, from
+    /// `blocks` in full batches down to none in batches of the min size.
+    pub fn coalesced_blocks(&self, blocks: BlockNumber) -> BlockNumber {
+        if self.max_blocks == self.min_blocks {
+            return blocks;
+        }
+        let range = (self.max_blocks - self.min_blocks) as u64;
+        let over = (self.current - self.min_blocks) as u64;
+        (blocks as u64 * over / range) as BlockNumber
+    }
+
+    fn blocks_at(&self, used: u8) -> BlockNumber {
+        if used <= self.low_watermark {
+            return self.max_blocks;
+        }
+        if used >= self.high_watermark {
+            return self.min_blocks;
+        }
+        let range = (self.max_blocks - self.min_blocks) as u64;
+        let over = (used - self.low_watermark) as u64;
+        let span = (self.high_watermark - self.low_watermark) as u64;
+        self.max_blocks - (range * over / span) as BlockNumber
+    }
+}
+
+fn used_percent(usage: &MemoryUsage) -> Option<u8> {
+    let total = usage.rust_used.checked_add(usage.free)?;
+    if total == 0 {
+        return None;
+    }
+    Some((usage.rust_used as u128 * 100 / total as u128) as u8)
+}
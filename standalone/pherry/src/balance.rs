@@ -0,0 +1,193 @@
+use crate::types::{BalanceStatus, ParachainApi, SrSigner};
+use anyhow::{bail, Result};
+use log::{info, warn};
+use phaxt::ChainInfo;
+use std::fmt;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Checks `--min-balance-abort` is below `--min-balance-warn`, unless the warning is disabled.
+pub fn validate_thresholds(warn_below: u128, abort_below: u128) -> Result<()> {
+    if warn_below > 0 && abort_below >= warn_below {
+        bail!(
+            "Option --min-balance-abort ({abort_below}) must be below --min-balance-warn ({warn_below})"
+        );
+    }
+    Ok(())
+}
+
+/// The error the bridge exits with when the balance drops below `--min-balance-abort`.
+///
+/// Restarting doesn't bring the funds back, so it's not retried by `--auto-restart`.
+#[derive(Debug)]
+pub struct BalanceTooLow {
+    pub account: String,
+    pub free: String,
+    pub abort_below: String,
+}
+
+impl fmt::Display for BalanceTooLow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Balance of the controller account {} is {}, below --min-balance-abort {}",
+            self.account, self.free, self.abort_below
+        )
+    }
+}
+
+impl std::error::Error for BalanceTooLow {}
+
+/// Where a balance stands against the thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceLevel {
+    Ok,
+    /// Below the warn threshold.
+    Low,
+    /// Below the abort threshold.
+    Abort,
+}
+
+/// Watches the free balance of the controller account, which pays for the registration and the
+/// egress message transactions.
+///
+/// The balance is checked at startup and then periodically at the chain tip. Below the warn
+/// threshold it is reported as low, below the abort threshold pherry refuses to go on rather than
+/// having its transactions fail one after another. The status of each check is also written to
+/// the metrics file if given, in the Prometheus text format.
+pub struct BalanceMonitor {
+    warn_below: u128,
+    abort_below: u128,
+    interval: Duration,
+    metrics_file: Option<String>,
+    last_check: Option<Instant>,
+    status: Option<BalanceStatus>,
+}
+
+impl BalanceMonitor {
+    pub fn new(warn_below: u128, abort_below: u128, interval: Duration) -> Self {
+        Self {
+            warn_below,
+            abort_below,
+            interval,
+            metrics_file: None,
+            last_check: None,
+            status: None,
+        }
+    }
+
+    /// Writes the status of each check to `file`, for the Prometheus textfile collector. Not
+    /// written if empty.
+    pub fn with_metrics_file(mut self, file: &str) -> Self {
+        self.metrics_file = (!file.is_empty()).then(|| file.to_string());
+        self
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.warn_below > 0 || self.abort_below > 0
+    }
+
+    /// The result of the last check, `None` if never checked.
+    pub fn status(&self) -> Option<BalanceStatus> {
+        self.status.clone()
+    }
+
+    /// Records the free balance checked, returns where it stands against the thresholds.
+    pub fn observe(&mut self, free: u128) -> BalanceLevel {
+        let low = free < self.warn_below;
+        self.status = Some(BalanceStatus { free, low });
+        if free < self.abort_below {
+            BalanceLevel::Abort
+        } else if low {
+            BalanceLevel::Low
+        } else {
+            BalanceLevel::Ok
+        }
+    }
+
+    /// The last status in the Prometheus text format, empty if never checked.
+    pub fn render_metrics(&self, account: &str) -> String {
+        let mut out = String::new();
+        let Some(status) = &self.status else {
+            return out;
+        };
+        let gauges = [
+            (
+                "pherry_controller_free_balance",
+                "Free balance of the controller account, in the smallest unit",
+                status.free,
+            ),
+            (
+                "pherry_controller_balance_low",
+                "Whether the balance of the controller account is below --min-balance-warn",
+                status.low as u128,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name}{{account=\"{account}\"}} {value}");
+        }
+        out
+    }
+
+    fn write_metrics(&self, file: &str, account: &str) -> Result<()> {
+        let file = Path::new(file);
+        let tmp_file = file.with_extension(format!("tmp.{}", std::process::id()));
+        std::fs::write(&tmp_file, self.render_metrics(account))?;
+        std::fs::rename(&tmp_file, file)?;
+        Ok(())
+    }
+
+    /// Checks the balance if the interval has passed since the last check, fails with
+    /// [`BalanceTooLow`] if it's below the abort threshold.
+    pub async fn check(
+        &mut self,
+        para_api: &ParachainApi,
+        signer: &SrSigner,
+        chain_info: &ChainInfo,
+    ) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        if matches!(self.last_check, Some(last) if last.elapsed() < self.interval) {
+            return Ok(());
+        }
+        self.last_check = Some(Instant::now());
+        let free = match para_api.free_balance(signer.account_id()).await {
+            Ok(free) => free,
+            Err(err) => {
+                warn!("Failed to get the balance of the controller account: {err:?}");
+                return Ok(());
+            }
+        };
+        let level = self.observe(free);
+        let account = signer.account_id().to_string();
+        if let Some(file) = &self.metrics_file {
+            if let Err(err) = self.write_metrics(file, &account) {
+                warn!("Failed to write the metrics to {file}: {err:?}");
+            }
+        }
+        match level {
+            BalanceLevel::Abort => {
+                return Err(BalanceTooLow {
+                    account,
+                    free: chain_info.format_balance(free),
+                    abort_below: chain_info.format_balance(self.abort_below),
+                }
+                .into());
+            }
+            BalanceLevel::Low => warn!(
+                "Balance of the controller account {account} is low: {}, below --min-balance-warn {}",
+                chain_info.format_balance(free),
+                chain_info.format_balance(self.warn_below)
+            ),
+            BalanceLevel::Ok => info!(
+                "Balance of the controller account: {}",
+                chain_info.format_balance(free)
+            ),
+        }
+        Ok(())
+    }
+}
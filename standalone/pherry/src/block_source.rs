@@ -0,0 +1,400 @@
+//! The sources of the data to sync by block number, e.g. the headers cache and the chain nodes.
+//!
+//! The sources are layered: each part of a block range is taken from the first source serving it,
+//! the parts a source misses or fails to serve falling through to the next one.
+
+use anyhow::Result;
+use futures::{stream, StreamExt};
+use log::{info, warn};
+use phactory_api::blocks::BlockHeaderWithChanges;
+use phaxt::{BlockNumber, ParachainApi, RelaychainApi};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::headers_cache::{BlockInfo, CacheKind, Client as CacheClient, PlannedRange};
+use crate::types::{Header, SourceQuality};
+
+/// A source of the data to sync.
+#[async_trait::async_trait]
+pub trait BlockSource: Send + Sync {
+    /// The name of the source in the logs and the quality report.
+    fn name(&self) -> &'static str;
+
+    /// Splits `from..=to` into the ranges of `kind` served by this source, those with `cached`
+    /// set, and the ones it misses.
+    async fn plan(&self, kind: CacheKind, from: BlockNumber, to: BlockNumber) -> Vec<PlannedRange>;
+
+    /// The storage changes of a range planned to be served by this source.
+    async fn storage_changes(
+        &self,
+        range: &PlannedRange,
+        with_root: bool,
+    ) -> Result<Vec<BlockHeaderWithChanges>>;
+
+    /// The parachain headers of a range planned to be served by this source, stopping before the
+    /// first block the source doesn't have yet.
+    async fn parachain_headers(&self, range: &PlannedRange) -> Result<Vec<Header>>;
+
+    /// The number of the parachain header finalized at the relaychain block and its proof, None
+    /// if unknown to this source.
+    async fn parachain_header_at(
+        &self,
+        relay_number: BlockNumber,
+    ) -> Result<Option<(BlockNumber, Vec<Vec<u8>>)>>;
+
+    /// The relaychain headers from `from` to the next authority set change, ready to be synced,
+    /// None if this source doesn't serve them.
+    async fn headers_to_next_set_change(
+        &self,
+        _from: BlockNumber,
+    ) -> Result<Option<Vec<BlockInfo>>> {
+        Ok(None)
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockSource for CacheClient {
+    fn name(&self) -> &'static str {
+        "headers-cache"
+    }
+
+    async fn plan(&self, kind: CacheKind, from: BlockNumber, to: BlockNumber) -> Vec<PlannedRange> {
+        CacheClient::plan(self, kind, from, to).await
+    }
+
+    async fn storage_changes(
+        &self,
+        range: &PlannedRange,
+        _with_root: bool,
+    ) -> Result<Vec<BlockHeaderWithChanges>> {
+        self.get_verified_storage_changes(range).await
+    }
+
+    async fn parachain_headers(&self, range: &PlannedRange) -> Result<Vec<Header>> {
+        self.get_verified_parachain_headers(range).await
+    }
+
+    async fn parachain_header_at(
+        &self,
+        relay_number: BlockNumber,
+    ) -> Result<Option<(BlockNumber, Vec<Vec<u8>>)>> {
+        let headers = self.get_headers(relay_number).await?;
+        Ok(match &headers[..] {
+            [BlockInfo {
+                para_header: Some(para_header),
+                ..
+            }] => Some((para_header.fin_header_num, para_header.proof.clone())),
+            _ => None,
+        })
+    }
+
+    async fn headers_to_next_set_change(
+        &self,
+        from: BlockNumber,
+    ) -> Result<Option<Vec<BlockInfo>>> {
+        self.get_verified_headers_to_next_set_change(from).await
+    }
+}
+
+/// The chain nodes, serving whatever they have.
+pub struct NodeSource {
+    para_api: ParachainApi,
+    relay_api: Option<RelaychainApi>,
+    concurrency: usize,
+}
+
+impl NodeSource {
+    pub fn new(para_api: ParachainApi) -> Self {
+        Self {
+            para_api,
+            relay_api: None,
+            concurrency: 1,
+        }
+    }
+
+    /// Sets the relaychain node to get the finalized parachain headers from.
+    pub fn with_relaychain(mut self, relay_api: RelaychainApi) -> Self {
+        self.relay_api = Some(relay_api);
+        self
+    }
+
+    /// Sets the number of parachain headers in flight.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockSource for NodeSource {
+    fn name(&self) -> &'static str {
+        "node"
+    }
+
+    async fn plan(
+        &self,
+        _kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Vec<PlannedRange> {
+        vec![PlannedRange {
+            from,
+            to,
+            cached: Some(String::new()),
+        }]
+    }
+
+    async fn storage_changes(
+        &self,
+        range: &PlannedRange,
+        with_root: bool,
+    ) -> Result<Vec<BlockHeaderWithChanges>> {
+        crate::fetch_storage_changes_from_node(&self.para_api, range.from, range.to, with_root)
+            .await
+    }
+
+    async fn parachain_headers(&self, range: &PlannedRange) -> Result<Vec<Header>> {
+        let headers = stream::iter(range.from..=range.to)
+            .map(|b| crate::fetch_parachain_header(&self.para_api, b))
+            .buffered(self.concurrency);
+        futures::pin_mut!(headers);
+        let mut para_headers = vec![];
+        while let Some(header) = headers.next().await {
+            match header? {
+                Some(header) => para_headers.push(header),
+                None => break,
+            }
+        }
+        Ok(para_headers)
+    }
+
+    async fn parachain_header_at(
+        &self,
+        relay_number: BlockNumber,
+    ) -> Result<Option<(BlockNumber, Vec<Vec<u8>>)>> {
+        let Some(relay_api) = &self.relay_api else {
+            return Ok(None);
+        };
+        let hash = crate::get_header_hash(relay_api, Some(relay_number)).await?;
+        let header = crate::get_finalized_header(relay_api, &self.para_api, hash).await?;
+        Ok(header.map(|(header, proof)| (header.number, proof)))
+    }
+}
+
+/// The layered sources, with the quality of each of them accounted.
+#[derive(Clone)]
+pub struct BlockSources {
+    sources: Vec<Arc<dyn BlockSource>>,
+    quality: Arc<Mutex<Vec<SourceQuality>>>,
+}
+
+impl BlockSources {
+    /// Layers the sources in the order given, the last one is expected to serve everything.
+    pub fn new(sources: Vec<Arc<dyn BlockSource>>) -> Self {
+        let quality = sources
+            .iter()
+            .map(|source| SourceQuality {
+                name: source.name().into(),
+                ..Default::default()
+            })
+            .collect();
+        Self {
+            sources,
+            quality: Arc::new(Mutex::new(quality)),
+        }
+    }
+
+    /// The headers cache if given, then the nodes.
+    pub fn with_cache(cache: Option<CacheClient>, node: NodeSource) -> Self {
+        let mut sources: Vec<Arc<dyn BlockSource>> = vec![];
+        if let Some(cache) = cache {
+            sources.push(Arc::new(cache));
+        }
+        sources.push(Arc::new(node));
+        Self::new(sources)
+    }
+
+    /// The quality of each source since they were layered.
+    pub fn quality(&self) -> Vec<SourceQuality> {
+        self.quality.lock().unwrap().clone()
+    }
+
+    fn record(&self, index: usize, served: Option<usize>, elapsed: Duration) {
+        let mut quality = self.quality.lock().unwrap();
+        let quality = &mut quality[index];
+        quality.requests += 1;
+        quality.busy_ms += elapsed.as_millis() as u64;
+        match served {
+            Some(items) => quality.items += items as u64,
+            None => quality.failures += 1,
+        }
+    }
+
+    fn is_last(&self, index: usize) -> bool {
+        index + 1 == self.sources.len()
+    }
+
+    /// Fetches the items of `from..=to` part by part from the first source serving each part.
+    ///
+    /// The items are returned in order, stopping before the first part not served in full.
+    async fn fetch_range<T, F, Fut>(
+        &self,
+        kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+        fetch: F,
+    ) -> Result<Vec<T>>
+    where
+        F: Fn(Arc<dyn BlockSource>, PlannedRange) -> Fut,
+        Fut: Future<Output = Result<Vec<T>>>,
+    {
+        if to < from {
+            return Ok(vec![]);
+        }
+        // The items fetched, by the first block of the part, along with the size of the part.
+        let mut parts = BTreeMap::new();
+        let mut missed = vec![(from, to)];
+        for (index, source) in self.sources.iter().enumerate() {
+            for (gap_from, gap_to) in std::mem::take(&mut missed) {
+                for range in source.plan(kind, gap_from, gap_to).await {
+                    if range.cached.is_none() {
+                        missed.push((range.from, range.to));
+                        continue;
+                    }
+                    let start = Instant::now();
+                    match fetch(source.clone(), range.clone()).await {
+                        Ok(items) => {
+                            self.record(index, Some(items.len()), start.elapsed());
+                            if !self.is_last(index) {
+                                info!(
+                                    "Got {} {} ({}-{}) from {}",
+                                    items.len(),
+                                    kind.path(),
+                                    range.from,
+                                    range.to,
+                                    source.name()
+                                );
+                            }
+                            parts.insert(range.from, (range.count(), items));
+                        }
+                        Err(err) => {
+                            self.record(index, None, start.elapsed());
+                            if self.is_last(index) {
+                                return Err(err);
+                            }
+                            warn!(
+                                "Failed to get {} ({}-{}) from {}, falling back: {err:?}",
+                                kind.path(),
+                                range.from,
+                                range.to,
+                                source.name()
+                            );
+                            missed.push((range.from, range.to));
+                        }
+                    }
+                }
+            }
+            if missed.is_empty() {
+                break;
+            }
+        }
+
+        let mut fetched = vec![];
+        let mut next = from;
+        for (part_from, (count, items)) in parts {
+            if part_from != next {
+                break;
+            }
+            let complete = items.len() == count as usize;
+            fetched.extend(items);
+            if !complete {
+                break;
+            }
+            next += count;
+        }
+        Ok(fetched)
+    }
+
+    pub async fn storage_changes(
+        &self,
+        from: BlockNumber,
+        to: BlockNumber,
+        with_root: bool,
+    ) -> Result<Vec<BlockHeaderWithChanges>> {
+        self.fetch_range(
+            CacheKind::StorageChanges,
+            from,
+            to,
+            |source, range| async move { source.storage_changes(&range, with_root).await },
+        )
+        .await
+    }
+
+    /// The parachain headers of `from..=to` in order, stopping before the first block none of the
+    /// sources has yet.
+    pub async fn parachain_headers(
+        &self,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<Header>> {
+        self.fetch_range(
+            CacheKind::ParachainHeaders,
+            from,
+            to,
+            |source, range| async move { source.parachain_headers(&range).await },
+        )
+        .await
+    }
+
+    pub async fn parachain_header_at(
+        &self,
+        relay_number: BlockNumber,
+    ) -> Result<Option<(BlockNumber, Vec<Vec<u8>>)>> {
+        for (index, source) in self.sources.iter().enumerate() {
+            let start = Instant::now();
+            match source.parachain_header_at(relay_number).await {
+                Ok(Some(header)) => {
+                    self.record(index, Some(1), start.elapsed());
+                    return Ok(Some(header));
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    self.record(index, None, start.elapsed());
+                    if self.is_last(index) {
+                        return Err(err);
+                    }
+                    warn!(
+                        "Failed to get the parachain header at {relay_number} from {}: {err:?}",
+                        source.name()
+                    );
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The relaychain headers from `from` to the next authority set change from the first source
+    /// serving them, None if no source does.
+    pub async fn headers_to_next_set_change(&self, from: BlockNumber) -> Option<Vec<BlockInfo>> {
+        for (index, source) in self.sources.iter().enumerate() {
+            let start = Instant::now();
+            match source.headers_to_next_set_change(from).await {
+                Ok(Some(headers)) => {
+                    self.record(index, Some(headers.len()), start.elapsed());
+                    return Some(headers);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    self.record(index, None, start.elapsed());
+                    warn!(
+                        "Failed to get the headers from {from} from {}: {err:?}",
+                        source.name()
+                    );
+                }
+            }
+        }
+        None
+    }
+}
@@ -1,11 +1,10 @@
 use crate::{
-    types::{utils::raw_proof, Hash, ParachainApi, RelaychainApi, StorageKey},
+    types::{ConvertTo as _, Hash, ParachainApi, StorageKey},
     Error,
 };
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use codec::Decode;
 use codec::Encode;
-use phactory_api::blocks::StorageProof;
 use phala_node_rpc_ext::MakeInto as _;
 use phala_trie_storage::ser::StorageChanges;
 use phala_types::messaging::MessageOrigin;
@@ -17,38 +16,6 @@ pub use sp_core::{twox_128, twox_64};
 
 use crate::types::SrSigner;
 
-/// Gets a storage proof for a single storage item
-pub async fn read_proof(
-    api: &RelaychainApi,
-    hash: Option<Hash>,
-    storage_key: &[u8],
-) -> Result<StorageProof> {
-    api.rpc()
-        .read_proof(vec![storage_key], hash)
-        .await
-        .map(raw_proof)
-        .map_err(Into::into)
-}
-
-/// Gets a storage proof for a storage items
-pub async fn read_proofs(
-    api: &RelaychainApi,
-    hash: Option<Hash>,
-    storage_keys: impl IntoIterator<Item = &[u8]>,
-) -> Result<StorageProof> {
-    let mut keys = vec![];
-    // Retrieve the actual storage keys in case they are prefixed
-    for prefix in storage_keys {
-        let full_keys = api.storage_keys(prefix, hash).await?;
-        keys.extend(full_keys);
-    }
-    api.rpc()
-        .read_proof(keys.iter().map(|k| &k[..]), hash)
-        .await
-        .map(raw_proof)
-        .map_err(Into::into)
-}
-
 // Storage functions
 
 /// Fetch storage changes made by given block.
@@ -77,6 +44,44 @@ pub async fn fetch_genesis_storage(api: &ParachainApi) -> Result<Vec<(Vec<u8>, V
     fetch_storage_at(api, hash).await
 }
 
+/// Verifies the given genesis storage against the state root of the on-chain genesis header.
+pub async fn verify_genesis_storage(
+    api: &ParachainApi,
+    storage: &[(Vec<u8>, Vec<u8>)],
+) -> Result<()> {
+    verify_storage_at(api, api.genesis_hash(), storage).await
+}
+
+/// Verifies the given storage against the state root of the header of the given block.
+///
+/// The state might be built with either state version, so both trie layouts are tried.
+pub async fn verify_storage_at(
+    api: &ParachainApi,
+    hash: sp_core::H256,
+    storage: &[(Vec<u8>, Vec<u8>)],
+) -> Result<()> {
+    use sp_runtime::traits::BlakeTwo256;
+    use sp_trie::{LayoutV0, LayoutV1, TrieConfiguration};
+
+    let header: crate::types::Header = api
+        .rpc()
+        .header(Some(hash))
+        .await?
+        .ok_or_else(|| anyhow!("Header {hash} not found"))?
+        .convert_to();
+    let expected_root = header.state_root;
+    let pairs = || storage.iter().map(|(k, v)| (&k[..], &v[..]));
+    let root_v1 = LayoutV1::<BlakeTwo256>::trie_root(pairs());
+    if root_v1 == expected_root {
+        return Ok(());
+    }
+    let root_v0 = LayoutV0::<BlakeTwo256>::trie_root(pairs());
+    if root_v0 == expected_root {
+        return Ok(());
+    }
+    bail!("State root mismatch, expected {expected_root:?}, got {root_v1:?}(v1) or {root_v0:?}(v0)");
+}
+
 pub async fn fetch_storage_at(
     api: &ParachainApi,
     hash: Option<sp_core::H256>,
@@ -93,6 +98,29 @@ pub async fn fetch_storage_at(
     Ok(storage)
 }
 
+/// Fetches the default child tries of the storage `pairs` at `hash`, each with its storage key
+/// without the `:child_storage:default:` prefix.
+pub async fn fetch_child_storage_at(
+    api: &ParachainApi,
+    hash: sp_core::H256,
+    pairs: &[(Vec<u8>, Vec<u8>)],
+) -> Result<Vec<(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)>> {
+    let prefix = sp_core::storage::well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX;
+    let mut children = vec![];
+    for (key, _) in pairs {
+        let Some(child_key) = key.strip_prefix(prefix) else {
+            continue;
+        };
+        let child_pairs = api
+            .extra_rpc()
+            .child_storage_pairs(StorageKey(key.clone()), Some(hash))
+            .await?;
+        let child_pairs = child_pairs.into_iter().map(|(k, v)| (k.0, v.0)).collect();
+        children.push((child_key.to_vec(), child_pairs));
+    }
+    Ok(children)
+}
+
 /// Fetch best next sequence for given sender considering the txpool
 pub async fn mq_next_sequence(
     api: &ParachainApi,
@@ -0,0 +1,155 @@
+//! Loads the options from a TOML config file given by `--config`.
+//!
+//! Each key of the file is the long name of an option, in either `snake_case` or `kebab-case`.
+//! The options from the file are placed ahead of the command line, so that the command line takes
+//! precedence. Flags are booleans, the options taking a list are arrays, and the others are strings
+//! or numbers. The items of a list in the file are added to those given on the command line.
+
+use crate::Args;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches};
+use std::ffi::OsString;
+use std::fmt::Write as _;
+
+/// Options that `print-config` doesn't reveal.
+const SECRET_OPTIONS: &[&str] = &["mnemonic", "inject-key", "headers-cache-auth"];
+
+/// Parses the command line with the options from the config file filled in.
+///
+/// The matches are returned as well, to render the effective config.
+pub fn parse_args<I, T>(cli: I) -> Result<(Args, ArgMatches)>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString>,
+{
+    let cli: Vec<OsString> = cli.into_iter().map(Into::into).collect();
+    let (program, cli) = cli
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty command line"))?;
+    let mut argv = vec![program.clone()];
+    if let Some(path) = config_path(cli) {
+        argv.extend(load_config(&path)?.into_iter().map(OsString::from));
+    }
+    argv.extend(cli.iter().cloned());
+    let matches = Args::command()
+        .args_override_self(true)
+        .try_get_matches_from(argv)?;
+    let args = Args::from_arg_matches(&matches)?;
+    Ok((args, matches))
+}
+
+/// Finds the value of `--config` on the command line.
+fn config_path(cli: &[OsString]) -> Option<String> {
+    let mut path = None;
+    let mut cli = cli.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = cli.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            path = cli.next().map(|path| path.into_owned());
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            path = Some(value.to_string());
+        }
+    }
+    path
+}
+
+/// Loads the config file as command line options.
+fn load_config(path: &str) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {path}"))?;
+    let table: toml::value::Table =
+        toml::from_str(&content).with_context(|| format!("Failed to parse config file {path}"))?;
+    let command = Args::command();
+    let mut args = vec![];
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| {
+                arg.get_long() == Some(long.as_str())
+                    || arg
+                        .get_all_aliases()
+                        .map_or(false, |aliases| aliases.contains(&long.as_str()))
+            })
+            .ok_or_else(|| anyhow!("Unknown option `{key}` in config file {path}"))?;
+        if long == "config" {
+            bail!("Option `config` is not allowed in config file {path}");
+        }
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            match value {
+                toml::Value::Boolean(true) => args.push(format!("--{long}")),
+                toml::Value::Boolean(false) => (),
+                _ => bail!("Option `{key}` in config file {path} must be a boolean"),
+            }
+            continue;
+        }
+        if matches!(arg.get_action(), ArgAction::Append) {
+            let values = match value {
+                toml::Value::Array(values) => values,
+                value => vec![value],
+            };
+            for value in values {
+                args.push(format!("--{long}={}", option_value(&key, path, value)?));
+            }
+            continue;
+        }
+        args.push(format!("--{long}={}", option_value(&key, path, value)?));
+    }
+    Ok(args)
+}
+
+fn option_value(key: &str, path: &str, value: toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(value) => value,
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        _ => bail!("Option `{key}` in config file {path} must be a string or a number"),
+    })
+}
+
+/// Renders the effective options as a config file, the secrets left out.
+pub fn render_config(matches: &ArgMatches) -> String {
+    let mut out = String::new();
+    for arg in Args::command().get_arguments() {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        let id = arg.get_id().as_str();
+        let key = long.replace('-', "_");
+        match arg.get_action() {
+            _ if long == "config" => (),
+            ArgAction::SetTrue => {
+                let _ = writeln!(out, "{key} = {}", matches.get_flag(id));
+            }
+            ArgAction::Set => {
+                let Some(value) = matches.get_raw(id).and_then(|mut values| values.next()) else {
+                    continue;
+                };
+                let value = value.to_string_lossy();
+                if SECRET_OPTIONS.contains(&long) {
+                    if !value.is_empty() {
+                        let _ = writeln!(out, "# {key} is set but not shown");
+                    }
+                } else if value.parse::<i64>().is_ok() {
+                    let _ = writeln!(out, "{key} = {value}");
+                } else {
+                    let _ = writeln!(out, "{key} = {}", toml::Value::String(value.into_owned()));
+                }
+            }
+            ArgAction::Append => {
+                let values = matches
+                    .get_raw(id)
+                    .into_iter()
+                    .flatten()
+                    .map(|value| toml::Value::String(value.to_string_lossy().into_owned()))
+                    .collect();
+                let _ = writeln!(out, "{key} = {}", toml::Value::Array(values));
+            }
+            _ => (),
+        }
+    }
+    out
+}
@@ -1,24 +1,32 @@
 use crate::{
     chain_client,
-    types::{ParachainApi, PrClient, SrSigner},
+    era::EraCache,
+    types::{EndpointDrift, ParachainApi, PrClient, SrSigner},
     Args,
 };
-use anyhow::{anyhow, Result};
-use log::{error, info};
+use anyhow::{anyhow, bail, Context, Result};
+use codec::Decode;
+use log::{error, info, warn};
+use phactory_api::pruntime_client;
+use phala_types::{VersionedWorkerEndpoints, WorkerEndpointPayload};
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 
 async fn update_worker_endpoint(
     para_api: &ParachainApi,
     encoded_endpoint_payload: Vec<u8>,
     signature: Vec<u8>,
     signer: &mut SrSigner,
+    era_cache: &mut EraCache,
     args: &Args,
 ) -> Result<bool> {
     chain_client::update_signer_nonce(para_api, signer).await?;
-    let params = crate::mk_params(para_api, args.longevity, args.tip).await?;
+    let params = era_cache.mk_params(para_api, args.tip).await?;
     let tx = phaxt::dynamic::tx::update_worker_endpoint(encoded_endpoint_payload, signature);
-    let ret = para_api
-        .tx()
-        .create_signed_with_nonce(&tx, &signer.signer, signer.nonce(), params)?
+    let ret = signer
+        .create_signed(para_api, &tx, params)
+        .await?
         .submit_and_watch()
         .await;
     if ret.is_err() {
@@ -33,6 +41,7 @@ pub async fn try_update_worker_endpoint(
     pr: &PrClient,
     para_api: &ParachainApi,
     signer: &mut SrSigner,
+    era_cache: &mut EraCache,
     args: &Args,
 ) -> Result<bool> {
     let info = pr.get_endpoint_info(()).await?;
@@ -43,6 +52,168 @@ pub async fn try_update_worker_endpoint(
     let signature = info
         .signature
         .ok_or_else(|| anyhow!("No endpoint signature"))?;
+    if args.endpoint_probe {
+        probe_endpoints(&encoded_endpoint_payload, &args.endpoint_checker_url)
+            .await
+            .context("Worker endpoint is not reachable")?;
+    }
     info!("Binding worker's endpoint...");
-    update_worker_endpoint(para_api, encoded_endpoint_payload, signature, signer, args).await
+    update_worker_endpoint(
+        para_api,
+        encoded_endpoint_payload,
+        signature,
+        signer,
+        era_cache,
+        args,
+    )
+    .await
+}
+
+/// Checks that each endpoint in the payload answers the pRuntime info RPC of the worker.
+///
+/// If a checker url is given, the reachability is checked by the external service instead, which
+/// tells whether the endpoint is reachable from the public internet rather than from pherry.
+async fn probe_endpoints(encoded_endpoint_payload: &[u8], checker_url: &str) -> Result<()> {
+    let payload = WorkerEndpointPayload::decode(&mut &encoded_endpoint_payload[..])
+        .context("Failed to decode the endpoint payload")?;
+    let VersionedWorkerEndpoints::V1(endpoints) = payload.versioned_endpoints;
+    if endpoints.is_empty() {
+        bail!("No endpoint configured");
+    }
+    let pubkey = hex::encode(payload.pubkey);
+    for endpoint in endpoints {
+        let result = if checker_url.is_empty() {
+            probe_endpoint(&endpoint, &pubkey).await
+        } else {
+            check_endpoint(checker_url, &endpoint).await
+        };
+        result.with_context(|| format!("Probing endpoint {endpoint}"))?;
+        info!("Endpoint {endpoint} is healthy");
+    }
+    Ok(())
+}
+
+async fn probe_endpoint(endpoint: &str, pubkey: &str) -> Result<()> {
+    let client = pruntime_client::new_pruntime_client(endpoint.to_string());
+    let info = tokio::time::timeout(PROBE_TIMEOUT, client.get_info(()))
+        .await
+        .map_err(|_| anyhow!("Timed out"))??;
+    match info.public_key {
+        Some(key) if key == pubkey => Ok(()),
+        key => Err(anyhow!("Serving worker {key:?}, expected {pubkey}")),
+    }
+}
+
+async fn check_endpoint(checker_url: &str, endpoint: &str) -> Result<()> {
+    let response = reqwest::Client::new()
+        .get(checker_url)
+        .query(&[("endpoint", endpoint)])
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("Checker responded {}", response.status());
+    }
+    Ok(())
+}
+
+/// Probes the worker endpoint periodically once bound, to alert on broken endpoints, and checks
+/// that the endpoints bound on chain are still the ones pRuntime is configured with.
+pub struct EndpointMonitor {
+    interval: Duration,
+    last_probe: Option<Instant>,
+    drift_check_interval: Duration,
+    last_drift_check: Option<Instant>,
+    drift: Option<EndpointDrift>,
+}
+
+impl EndpointMonitor {
+    pub fn new(interval: Duration, drift_check_interval: Duration) -> Self {
+        Self {
+            interval,
+            last_probe: None,
+            drift_check_interval,
+            last_drift_check: None,
+            drift: None,
+        }
+    }
+
+    /// The drift found by the last check, until the endpoints are bound again.
+    pub fn drift(&self) -> Option<EndpointDrift> {
+        self.drift.clone()
+    }
+
+    /// Marks the endpoints bound again, to be checked after the interval once the binding is on
+    /// chain.
+    pub fn rebound(&mut self) {
+        self.last_drift_check = Some(Instant::now());
+    }
+
+    /// Returns whether the endpoints bound on chain differ from the configured ones, in which
+    /// case they should be bound again.
+    pub async fn check_drift(&mut self, pr: &PrClient, para_api: &ParachainApi) -> bool {
+        if self.drift_check_interval.is_zero() {
+            return false;
+        }
+        if matches!(self.last_drift_check, Some(last) if last.elapsed() < self.drift_check_interval)
+        {
+            return false;
+        }
+        self.last_drift_check = Some(Instant::now());
+        match find_drift(pr, para_api).await {
+            Ok(drift) => {
+                if let Some(drift) = &drift {
+                    warn!(
+                        "Worker endpoint changed from {:?} bound on chain to {:?}, binding it again",
+                        drift.bound, drift.configured
+                    );
+                } else if self.drift.is_some() {
+                    info!("Worker endpoint bound on chain is up to date");
+                }
+                self.drift = drift;
+                self.drift.is_some()
+            }
+            Err(err) => {
+                warn!("Failed to check the worker endpoint bound on chain: {err:?}");
+                false
+            }
+        }
+    }
+
+    pub async fn check(&mut self, pr: &PrClient, args: &Args) {
+        if self.interval.is_zero() || !args.endpoint_probe {
+            return;
+        }
+        if matches!(self.last_probe, Some(last) if last.elapsed() < self.interval) {
+            return;
+        }
+        self.last_probe = Some(Instant::now());
+        let result = async {
+            let info = pr.get_endpoint_info(()).await?;
+            let Some(payload) = info.encoded_endpoint_payload else {
+                return Ok(());
+            };
+            probe_endpoints(&payload, &args.endpoint_checker_url).await
+        };
+        if let Err(err) = result.await {
+            warn!("Worker endpoint is unhealthy: {err:?}");
+        }
+    }
+}
+
+async fn find_drift(pr: &PrClient, para_api: &ParachainApi) -> Result<Option<EndpointDrift>> {
+    let info = pr.get_endpoint_info(()).await?;
+    let Some(payload) = info.encoded_endpoint_payload else {
+        return Ok(None);
+    };
+    let payload = WorkerEndpointPayload::decode(&mut &payload[..])
+        .context("Failed to decode the endpoint payload")?;
+    let VersionedWorkerEndpoints::V1(mut configured) = payload.versioned_endpoints;
+    let mut bound = para_api.get_endpoints(&payload.pubkey).await?;
+    configured.sort();
+    bound.sort();
+    if configured == bound {
+        return Ok(None);
+    }
+    Ok(Some(EndpointDrift { bound, configured }))
 }
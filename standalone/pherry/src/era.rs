@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use std::time::{Duration, Instant};
+use subxt::config::{substrate::Era, Header as _};
+
+use crate::types::{Hash, ParachainApi};
+
+struct Checkpoint {
+    number: u64,
+    hash: Hash,
+    pinned_at: Instant,
+}
+
+/// Pins the mortality checkpoint of the extrinsics for a number of blocks.
+///
+/// Without pinning, each submission fetches the latest header to build its era, which causes a
+/// burst of RPC calls when syncing many messages at once.
+pub struct EraCache {
+    longevity: u64,
+    block_time: Duration,
+    pin_blocks: u64,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl EraCache {
+    /// `block_time` is the expected block time of the parachain, used to estimate the age of
+    /// the checkpoint.
+    pub fn new(longevity: u64, pin_blocks: u64, block_time: Duration) -> Self {
+        // Leave at least half of the era window to the extrinsics signed at the end of pinning.
+        let pin_blocks = pin_blocks.min(longevity / 2);
+        Self {
+            longevity,
+            block_time,
+            pin_blocks,
+            checkpoint: None,
+        }
+    }
+
+    fn pinned(&self) -> Option<&Checkpoint> {
+        let checkpoint = self.checkpoint.as_ref()?;
+        let pin_duration = self.block_time * self.pin_blocks as u32;
+        (checkpoint.pinned_at.elapsed() < pin_duration).then_some(checkpoint)
+    }
+
+    pub async fn mk_params(
+        &mut self,
+        api: &ParachainApi,
+        tip: u128,
+    ) -> Result<phaxt::ExtrinsicParamsBuilder> {
+        if self.pin_blocks == 0 {
+            return crate::mk_params(api, self.longevity, tip).await;
+        }
+        if self.pinned().is_none() {
+            let header = api
+                .rpc()
+                .header(<Option<Hash>>::None)
+                .await?
+                .ok_or_else(|| anyhow!("No header"))?;
+            info!(
+                "pin era checkpoint: block={}, period={}, pin_blocks={}",
+                header.number, self.longevity, self.pin_blocks
+            );
+            self.checkpoint = Some(Checkpoint {
+                number: header.number as u64,
+                hash: header.hash(),
+                pinned_at: Instant::now(),
+            });
+        }
+        let checkpoint = self
+            .checkpoint
+            .as_ref()
+            .expect("checkpoint should be pinned");
+        let era = Era::Mortal(self.longevity, checkpoint.number % self.longevity);
+        Ok(phaxt::ExtrinsicParamsBuilder::new()
+            .tip(tip)
+            .era(era, checkpoint.hash))
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Context, Result};
+use codec::Decode;
+use futures::StreamExt;
+use log::{debug, info, warn};
+use phactory_api::blocks::HeaderToSync;
+use sc_consensus_grandpa::GrandpaJustification;
+use sp_consensus_grandpa::ConsensusLog;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subxt::rpc::rpc_params;
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::types::{BlockNumber, ConvertTo, Hash, Header, RelaychainApi, UnsigedBlock};
+use crate::GRANDPA_ENGINE_ID;
+
+/// Headers older than this many blocks behind the latest justification are discarded.
+const MAX_BUFFERED_HEADERS: usize = 4096;
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Shared {
+    headers: Mutex<VecDeque<HeaderToSync>>,
+    notify: Notify,
+}
+
+/// Assembles the relaychain headers to sync from the GRANDPA justification stream of the node.
+///
+/// Each justification received from `grandpa_subscribeJustifications` is buffered together with
+/// the headers since the previous one, so the headers are ready to sync as soon as they get
+/// finalized, without asking the node for a finality proof.
+pub struct FinalityStream {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+impl FinalityStream {
+    pub fn subscribe(api: RelaychainApi) -> Self {
+        let shared = Arc::new(Shared::default());
+        let task = tokio::spawn(run(api, shared.clone()));
+        Self { shared, task }
+    }
+
+    /// Returns the buffered headers starting from `from`, with the last one justified.
+    ///
+    /// The batch stops at the first header enacting an authority set change, since the headers
+    /// after it are justified by the new set. Returns `None` if `from` isn't covered by the buffer,
+    /// in which case the caller should fall back to `prove_finality`.
+    pub fn take_headers(&self, from: BlockNumber) -> Option<Vec<HeaderToSync>> {
+        let mut headers = self.shared.headers.lock().unwrap();
+        while headers.front().map_or(false, |h| h.header.number < from) {
+            headers.pop_front();
+        }
+        if headers.front()?.header.number != from {
+            return None;
+        }
+        let mut end = None;
+        for (i, h) in headers.iter().enumerate() {
+            if h.justification.is_some() {
+                end = Some(i);
+            }
+            // Checked on every header, an unjustified change ends the batch at the last justified
+            // header before it.
+            if has_authority_set_change(&h.header) {
+                break;
+            }
+        }
+        Some(headers.range(..=end?).cloned().collect())
+    }
+
+    /// Waits until a new justification arrives or the timeout elapses.
+    pub async fn wait_justification(&self, timeout: Duration) {
+        let _ = tokio::time::timeout(timeout, self.shared.notify.notified()).await;
+    }
+}
+
+impl Drop for FinalityStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(api: RelaychainApi, shared: Arc<Shared>) {
+    loop {
+        if let Err(err) = subscribe_justifications(&api, &shared).await {
+            warn!("Justification subscription failed: {err:?}");
+        }
+        tokio::time::sleep(RESUBSCRIBE_INTERVAL).await;
+    }
+}
+
+async fn subscribe_justifications(api: &RelaychainApi, shared: &Shared) -> Result<()> {
+    let mut subscription = api
+        .rpc()
+        .subscribe::<sp_core::Bytes>(
+            "grandpa_subscribeJustifications",
+            rpc_params![],
+            "grandpa_unsubscribeJustifications",
+        )
+        .await
+        .context("Failed to subscribe justifications")?;
+    info!("Subscribed to the GRANDPA justification stream");
+    while let Some(justification) = subscription.next().await {
+        let justification = justification?.0;
+        let decoded = GrandpaJustification::<UnsigedBlock>::decode(&mut &justification[..])
+            .context("Failed to decode justification")?;
+        let commit = &decoded.justification.commit;
+        let headers = fetch_new_headers(api, shared, commit.target_number, commit.target_hash)
+            .await
+            .context("Failed to fetch the justified headers")?;
+        let Some(last_number) = headers.last().map(|h| h.number) else {
+            continue;
+        };
+        {
+            let mut buffer = shared.headers.lock().unwrap();
+            if buffer
+                .back()
+                .map_or(false, |h| h.header.number + 1 != headers[0].number)
+            {
+                buffer.clear();
+            }
+            buffer.extend(headers.into_iter().map(|header| HeaderToSync {
+                header,
+                justification: None,
+            }));
+            if let Some(last) = buffer.back_mut() {
+                last.justification = Some(justification);
+            }
+            while buffer.len() > MAX_BUFFERED_HEADERS {
+                buffer.pop_front();
+            }
+        }
+        debug!("Received justification at {last_number}");
+        shared.notify.notify_waiters();
+    }
+    Err(anyhow!("Justification subscription closed"))
+}
+
+/// Fetches the headers between the last buffered one and the justified target, by walking the
+/// parent hashes back from the target.
+async fn fetch_new_headers(
+    api: &RelaychainApi,
+    shared: &Shared,
+    target_number: BlockNumber,
+    target_hash: Hash,
+) -> Result<Vec<Header>> {
+    let last_number = shared
+        .headers
+        .lock()
+        .unwrap()
+        .back()
+        .map(|h| h.header.number);
+    let from = match last_number {
+        Some(number) if number >= target_number => return Ok(vec![]),
+        Some(number) if ((target_number - number) as usize) < MAX_BUFFERED_HEADERS => number + 1,
+        // Too far behind, start over from the target.
+        _ => target_number,
+    };
+    let mut headers = Vec::with_capacity((target_number - from + 1) as usize);
+    let mut hash = target_hash;
+    loop {
+        let header: Header = api
+            .rpc()
+            .header(Some(hash))
+            .await?
+            .ok_or_else(|| anyhow!("Header {hash} not found"))?
+            .convert_to();
+        hash = header.parent_hash;
+        let number = header.number;
+        headers.push(header);
+        if number <= from {
+            break;
+        }
+    }
+    headers.reverse();
+    Ok(headers)
+}
+
+pub(crate) fn has_authority_set_change(header: &Header) -> bool {
+    header.digest.logs().iter().any(|log| {
+        matches!(
+            log.consensus_try_to(&GRANDPA_ENGINE_ID),
+            Some(ConsensusLog::<BlockNumber>::ScheduledChange(_))
+                | Some(ConsensusLog::<BlockNumber>::ForcedChange(_, _))
+        )
+    })
+}
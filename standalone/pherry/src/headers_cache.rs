@@ -2,10 +2,16 @@ use crate::types::ConvertTo;
 use crate::{types::Header, GRANDPA_ENGINE_ID};
 use anyhow::{anyhow, Result};
 use codec::{Decode, Encode};
+use hmac::{Hmac, Mac};
 use phaxt::{BlockNumber, ParachainApi, RelaychainApi};
-use reqwest::Response;
+use reqwest::{RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::borrow::Cow;
+use std::fmt;
 use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use futures::stream::Stream;
 use log::{debug, error, info, warn};
@@ -21,6 +27,140 @@ pub struct BlockInfo {
     pub authority_set_change: Option<AuthoritySetChange>,
 }
 
+/// An authority set change, recorded at the block enacting the new set.
+#[derive(Decode, Encode, Debug, Clone)]
+pub struct AuthoritySetChangeRecord {
+    /// The block enacting the new authority set. It always comes with a justification.
+    pub block_number: BlockNumber,
+    pub change: AuthoritySetChange,
+}
+
+impl AuthoritySetChangeRecord {
+    /// The id of the new authority set.
+    pub fn set_id(&self) -> u64 {
+        self.change.authority_set.id
+    }
+}
+
+impl BlockInfo {
+    pub fn authority_set_change_record(&self) -> Option<AuthoritySetChangeRecord> {
+        Some(AuthoritySetChangeRecord {
+            block_number: self.header.number,
+            change: self.authority_set_change.clone()?,
+        })
+    }
+}
+
+/// The kinds of items served by the cache by block number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Headers,
+    ParachainHeaders,
+    StorageChanges,
+}
+
+impl CacheKind {
+    pub fn path(&self) -> &'static str {
+        match self {
+            CacheKind::Headers => "headers",
+            CacheKind::ParachainHeaders => "parachain-headers",
+            CacheKind::StorageChanges => "storage-changes",
+        }
+    }
+
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "headers" => Some(CacheKind::Headers),
+            "parachain-headers" => Some(CacheKind::ParachainHeaders),
+            "storage-changes" => Some(CacheKind::StorageChanges),
+            _ => None,
+        }
+    }
+}
+
+/// A run of consecutive blocks available in the cache.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedRange {
+    pub from: BlockNumber,
+    pub to: BlockNumber,
+    /// The [`range_hash`] of the items in the range, hex encoded.
+    pub hash: String,
+}
+
+/// A part of a requested block range, to be served by the cache if `cached` is set to the hash of
+/// its items, or over RPC otherwise.
+///
+/// A cache not reporting its availability has the whole range planned with [`UNPLANNED`] as the
+/// hash, taken as served without the integrity check, and falling back to RPC on failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedRange {
+    pub from: BlockNumber,
+    pub to: BlockNumber,
+    pub cached: Option<String>,
+}
+
+/// The hash of a range planned without knowing what the cache holds.
+pub const UNPLANNED: &str = "";
+
+impl PlannedRange {
+    pub fn count(&self) -> BlockNumber {
+        self.to + 1 - self.from
+    }
+}
+
+/// The hash of a cached item, the blake2_256 of its SCALE encoding.
+pub fn item_hash(encoded: &[u8]) -> [u8; 32] {
+    sp_core::blake2_256(encoded)
+}
+
+/// The hash of the items of a block range, the blake2_256 of their concatenated [`item_hash`]es.
+pub fn range_hash(item_hashes: &[[u8; 32]]) -> String {
+    hex::encode(sp_core::blake2_256(&item_hashes.concat()))
+}
+
+fn items_range_hash<T: Encode>(items: &[T]) -> String {
+    let item_hashes: Vec<_> = items.iter().map(|item| item_hash(&item.encode())).collect();
+    range_hash(&item_hashes)
+}
+
+/// Splits `from..=to` into the ranges covered by `available` and the gaps between them.
+///
+/// A cached range is only taken as is, since its hash covers exactly its own blocks.
+pub fn plan_ranges(
+    available: &[CachedRange],
+    from: BlockNumber,
+    to: BlockNumber,
+) -> Vec<PlannedRange> {
+    let mut planned = vec![];
+    let mut next = from;
+    for range in available {
+        if range.from < next || range.to > to || range.from > range.to {
+            continue;
+        }
+        if range.from > next {
+            planned.push(PlannedRange {
+                from: next,
+                to: range.from - 1,
+                cached: None,
+            });
+        }
+        planned.push(PlannedRange {
+            from: range.from,
+            to: range.to,
+            cached: Some(range.hash.clone()),
+        });
+        next = range.to + 1;
+    }
+    if next <= to {
+        planned.push(PlannedRange {
+            from: next,
+            to,
+            cached: None,
+        });
+    }
+    planned
+}
+
 #[derive(Decode, Encode, Debug, Clone)]
 pub struct ParaHeader {
     /// Finalized parachain header number
@@ -436,10 +576,142 @@ pub async fn fetch_genesis_info(
     })
 }
 
+/// The header carrying the shared token of the cache server.
+pub const TOKEN_HEADER: &str = "X-Token";
+/// The header carrying the unix time in seconds when the request was signed.
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+/// The header carrying the hex encoded HMAC-SHA256 signature of the request.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+/// Max difference in seconds between the timestamp of a signed request and the server time.
+pub const MAX_SIGNATURE_AGE_SECS: u64 = 300;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn signer(key: &[u8], timestamp: u64, path: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b"\n");
+    mac.update(b"/");
+    mac.update(path.trim_start_matches('/').as_bytes());
+    mac
+}
+
+/// The path a request to `url` is signed for: the path below the base URI of the cache server,
+/// with the query.
+///
+/// A reverse proxy serving the cache under a path prefix strips the prefix, so the server sees the
+/// same path as long as the prefix is part of the base URI.
+pub fn signed_path(base_uri: &str, url: &str) -> String {
+    let (Ok(base), Ok(url)) = (reqwest::Url::parse(base_uri), reqwest::Url::parse(url)) else {
+        return url.to_string();
+    };
+    let path = match url.path().strip_prefix(base.path().trim_end_matches('/')) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => url.path(),
+    };
+    match url.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    }
+}
+
+/// Signs the request of `path`, including the query, at `timestamp`. The leading slashes of the
+/// path don't matter.
+pub fn sign_request(key: &[u8], timestamp: u64, path: &str) -> String {
+    hex::encode(signer(key, timestamp, path).finalize().into_bytes())
+}
+
+/// Verifies the signature of the request of `path` signed at `timestamp`, which must be within
+/// `MAX_SIGNATURE_AGE_SECS` from now.
+pub fn verify_request(key: &[u8], timestamp: u64, path: &str, signature: &str) -> bool {
+    if unix_now().abs_diff(timestamp) > MAX_SIGNATURE_AGE_SECS {
+        return false;
+    }
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    signer(key, timestamp, path)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+/// Checks the token of a request in constant time. Both are compared through their HMAC keyed by
+/// the expected token, so neither the content nor the length of it leaks through the timing.
+pub fn verify_token(expected: &str, token: &str) -> bool {
+    let mac = |data: &str| {
+        let mut mac =
+            HmacSha256::new_from_slice(expected.as_bytes()).expect("HMAC accepts keys of any size");
+        mac.update(data.as_bytes());
+        mac
+    };
+    let expected_tag = mac(expected).finalize().into_bytes();
+    mac(token).verify_slice(&expected_tag).is_ok()
+}
+
+/// The credentials to access a cache server, given as `token:<token>` or `hmac:<hex key>`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum CacheAuth {
+    /// Sends the token as is with each request.
+    Token(String),
+    /// Signs each request with the key, so the key never goes over the wire.
+    Hmac(Vec<u8>),
+}
+
+impl FromStr for CacheAuth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("token", token)) if !token.is_empty() => Ok(Self::Token(token.into())),
+            Some(("hmac", key)) => {
+                let key = hex::decode(key.trim_start_matches("0x"))
+                    .map_err(|err| anyhow!("Invalid HMAC key: {err}"))?;
+                if key.is_empty() {
+                    anyhow::bail!("Empty HMAC key");
+                }
+                Ok(Self::Hmac(key))
+            }
+            _ => anyhow::bail!("Expected token:<token> or hmac:<hex key>"),
+        }
+    }
+}
+
+impl fmt::Debug for CacheAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Token(_) => f.write_str("Token(..)"),
+            Self::Hmac(_) => f.write_str("Hmac(..)"),
+        }
+    }
+}
+
+impl CacheAuth {
+    /// Adds the credentials to the request of `path`, see [`signed_path`].
+    pub fn authorize(&self, request: RequestBuilder, path: &str) -> RequestBuilder {
+        match self {
+            Self::Token(token) => request.header(TOKEN_HEADER, token),
+            Self::Hmac(key) => {
+                let timestamp = unix_now();
+                request
+                    .header(TIMESTAMP_HEADER, timestamp.to_string())
+                    .header(SIGNATURE_HEADER, sign_request(key, timestamp, path))
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Client {
     base_uri: String,
     http_client: reqwest::Client,
+    auth: Option<CacheAuth>,
 }
 
 impl Client {
@@ -447,16 +719,19 @@ impl Client {
         Self {
             base_uri: uri.to_string(),
             http_client: reqwest::Client::new(),
+            auth: None,
         }
     }
 
+    /// Sends the credentials with each request to the cache server.
+    pub fn with_auth(mut self, auth: Option<CacheAuth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
     async fn request(&self, url: &str) -> Result<Response> {
-        let response = self.http_client.get(url).send().await.map_err(|err| {
-            warn!("Failed to fetch data from cache: {err}");
-            err
-        })?;
+        let response = self.send(url).await?;
         let status = response.status();
-        info!("Requested cache from {url} ({})", status.as_u16());
         if !status.is_success() {
             anyhow::bail!(
                 "Failed to fetch data from cache with status={}",
@@ -466,6 +741,23 @@ impl Client {
         Ok(response)
     }
 
+    /// Sends a request to the cache, whatever the status of the response.
+    async fn send(&self, url: &str) -> Result<Response> {
+        let mut request = self.http_client.get(url);
+        if let Some(auth) = &self.auth {
+            request = auth.authorize(request, &signed_path(&self.base_uri, url));
+        }
+        let response = request.send().await.map_err(|err| {
+            warn!("Failed to fetch data from cache: {err}");
+            err
+        })?;
+        info!(
+            "Requested cache from {url} ({})",
+            response.status().as_u16()
+        );
+        Ok(response)
+    }
+
     async fn request_scale<T: Decode>(&self, url: &str) -> Result<T> {
         let response = self.request(url).await?;
         let body = response.bytes().await.map_err(|err| {
@@ -503,6 +795,197 @@ impl Client {
         self.request_scale(&url).await
     }
 
+    /// Get the headers from `block_number` across multiple justifications, ending at the last
+    /// justified header not beyond `until` or at the next authority set change.
+    pub async fn get_headers_until(
+        &self,
+        block_number: BlockNumber,
+        until: BlockNumber,
+    ) -> Result<Vec<BlockInfo>> {
+        let url = format!("{}/headers/{block_number}?until={until}", self.base_uri);
+        self.request_scale(&url).await
+    }
+
+    /// Get the authority set changes at or after `block_number`, in ascending order.
+    pub async fn get_authority_set_changes(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Vec<AuthoritySetChangeRecord>> {
+        let url = format!("{}/authority-set-changes/{block_number}", self.base_uri);
+        self.request_scale(&url).await
+    }
+
+    /// Get the headers from `block_number` batched precisely up to the next authority set
+    /// change, or as many as the cache serves in one request if no change is known ahead.
+    pub async fn get_headers_to_next_set_change(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Vec<BlockInfo>> {
+        let next_change = match self.get_authority_set_changes(block_number).await {
+            Ok(changes) => changes.into_iter().next(),
+            Err(err) => {
+                // Older caches don't serve the records.
+                debug!("Failed to get authority set changes from cache: {err}");
+                return self.get_headers(block_number).await;
+            }
+        };
+        let until = match next_change {
+            Some(change) => {
+                info!(
+                    "Next authority set change at {}, set_id={}",
+                    change.block_number,
+                    change.set_id()
+                );
+                change.block_number
+            }
+            None => BlockNumber::MAX,
+        };
+        self.get_headers_until(block_number, until).await
+    }
+
+    /// Get the ranges of `kind` available in the cache within `from..=to`, None if the cache is
+    /// too old to report them.
+    pub async fn get_available_ranges(
+        &self,
+        kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Option<Vec<CachedRange>>> {
+        let url = format!(
+            "{}/availability/{}/{from}/{}",
+            self.base_uri,
+            kind.path(),
+            to + 1 - from
+        );
+        let response = self.send(&url).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!(
+                "Failed to fetch data from cache with status={}",
+                status.as_u16()
+            );
+        }
+        let body = response.bytes().await?;
+        Ok(Some(serde_json::from_slice(&body)?))
+    }
+
+    /// Plans which parts of `from..=to` to take from the cache. The whole range is tried without
+    /// a plan if the cache is too old to report its availability, and goes to RPC if the
+    /// availability failed to be told.
+    pub async fn plan(
+        &self,
+        kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Vec<PlannedRange> {
+        match self.get_available_ranges(kind, from, to).await {
+            Ok(Some(available)) => plan_ranges(&available, from, to),
+            Ok(None) => vec![PlannedRange {
+                from,
+                to,
+                cached: Some(UNPLANNED.into()),
+            }],
+            Err(err) => {
+                warn!(
+                    "Failed to get the {} available in cache: {err}",
+                    kind.path()
+                );
+                plan_ranges(&[], from, to)
+            }
+        }
+    }
+
+    async fn request_verified<T: Decode + Encode>(&self, url: &str, hash: &str) -> Result<Vec<T>> {
+        let items: Vec<T> = self.request_scale(url).await?;
+        if hash == UNPLANNED {
+            return Ok(items);
+        }
+        let actual = items_range_hash(&items);
+        if actual != hash {
+            anyhow::bail!("Cache response hash mismatch, expected {hash}, got {actual}");
+        }
+        Ok(items)
+    }
+
+    /// Get the parachain headers of a range planned to be served by the cache, checked against
+    /// the hash reported by the cache.
+    pub async fn get_verified_parachain_headers(
+        &self,
+        range: &PlannedRange,
+    ) -> Result<Vec<Header>> {
+        let hash = range
+            .cached
+            .as_deref()
+            .ok_or_else(|| anyhow!("Range not cached"))?;
+        let url = format!(
+            "{}/parachain-headers/{}/{}",
+            self.base_uri,
+            range.from,
+            range.count()
+        );
+        self.request_verified(&url, hash).await
+    }
+
+    /// Get the storage changes of a range planned to be served by the cache, checked against the
+    /// hash reported by the cache.
+    pub async fn get_verified_storage_changes(
+        &self,
+        range: &PlannedRange,
+    ) -> Result<Vec<BlockHeaderWithChanges>> {
+        let hash = range
+            .cached
+            .as_deref()
+            .ok_or_else(|| anyhow!("Range not cached"))?;
+        let url = format!(
+            "{}/storage-changes/{}/{}",
+            self.base_uri,
+            range.from,
+            range.count()
+        );
+        self.request_verified(&url, hash).await
+    }
+
+    /// Like [`Self::get_headers_to_next_set_change`], but only asks the cache if it holds the
+    /// header at `block_number`, returning None otherwise, and checks the headers against the hash
+    /// reported by the cache.
+    pub async fn get_verified_headers_to_next_set_change(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<Vec<BlockInfo>>> {
+        let available = self
+            .get_available_ranges(CacheKind::Headers, block_number, block_number)
+            .await?;
+        let Some(available) = available else {
+            // Taken unchecked from the caches too old to tell.
+            return self
+                .get_headers_to_next_set_change(block_number)
+                .await
+                .map(Some);
+        };
+        if available.is_empty() {
+            return Ok(None);
+        }
+        let headers = self.get_headers_to_next_set_change(block_number).await?;
+        let last = match headers.last() {
+            Some(info) => info.header.number,
+            None => return Ok(Some(headers)),
+        };
+        let expected = self
+            .get_available_ranges(CacheKind::Headers, block_number, last)
+            .await?
+            .unwrap_or_default();
+        let actual = items_range_hash(&headers);
+        match &expected[..] {
+            [range] if range.from == block_number && range.to == last && range.hash == actual => {
+                Ok(Some(headers))
+            }
+            _ => anyhow::bail!("Cached headers ({block_number}-{last}) failed the integrity check"),
+        }
+    }
+
     pub async fn get_parachain_headers(
         &self,
         start_number: BlockNumber,
@@ -525,4 +1008,24 @@ impl Client {
         let url = format!("{}/genesis/{block_number}", self.base_uri);
         self.request_scale(&url).await
     }
+
+    /// Get the relaychain blocks the cache has the genesis info of.
+    pub async fn get_genesis_blocks(&self) -> Result<Vec<BlockNumber>> {
+        #[derive(Deserialize)]
+        struct State {
+            #[serde(default)]
+            genesis: Vec<BlockNumber>,
+        }
+        let url = format!("{}/state", self.base_uri);
+        let body = self.request(&url).await?.bytes().await?;
+        let state: State = serde_json::from_slice(&body)?;
+        Ok(state.genesis)
+    }
+
+    /// Get the parachain genesis storage. The result is unverified, use
+    /// `chain_client::verify_genesis_storage` to check it against the chain.
+    pub async fn get_genesis_state(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let url = format!("{}/genesis-state", self.base_uri);
+        self.request_scale(&url).await
+    }
 }
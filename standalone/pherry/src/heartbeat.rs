@@ -0,0 +1,56 @@
+use crate::notify_client::NotifyClient;
+use crate::types::{Heartbeat, SignedHeartbeat, SrSigner};
+use anyhow::Result;
+use log::{info, warn};
+use phactory_api::prpc::PhactoryInfo;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Posts a heartbeat signed with the controller key to the notify endpoint every `interval`.
+pub struct HeartbeatSender {
+    interval: Duration,
+    last_sent: Option<Instant>,
+}
+
+impl HeartbeatSender {
+    /// A zero `interval` disables the heartbeats.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: None,
+        }
+    }
+
+    /// Posts a heartbeat of the pRuntime state if the interval has passed since the last one.
+    pub async fn maybe_send(&mut self, nc: &NotifyClient, signer: &SrSigner, info: &PhactoryInfo) {
+        if self.interval.is_zero()
+            || matches!(self.last_sent, Some(at) if at.elapsed() < self.interval)
+        {
+            return;
+        }
+        // There is nothing to attest to before pRuntime is initialized.
+        let Some(worker_pubkey) = &info.public_key else {
+            return;
+        };
+        self.last_sent = Some(Instant::now());
+        match sign(signer, worker_pubkey, info) {
+            Ok(heartbeat) => match nc.notify_heartbeat(&heartbeat).await {
+                Ok(()) => info!(
+                    "Posted heartbeat at header {}, block {}",
+                    info.headernum, info.blocknum
+                ),
+                Err(err) => warn!("Failed to post heartbeat: {err:?}"),
+            },
+            Err(err) => warn!("Failed to sign heartbeat: {err:?}"),
+        }
+    }
+}
+
+fn sign(signer: &SrSigner, worker_pubkey: &str, info: &PhactoryInfo) -> Result<SignedHeartbeat> {
+    let heartbeat = Heartbeat {
+        worker_pubkey: worker_pubkey.to_string(),
+        headernum: info.headernum,
+        blocknum: info.blocknum,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+    SignedHeartbeat::sign(heartbeat, signer)
+}
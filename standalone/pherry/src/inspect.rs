@@ -0,0 +1,312 @@
+use anyhow::{anyhow, Context, Result};
+use codec::Decode;
+use phactory_api::prpc::{GatekeeperRole, PhactoryInfo};
+use phactory_api::pruntime_client;
+use phala_types::{VersionedWorkerEndpoints, WorkerEndpointPayload, WorkerPublicKey};
+use std::io::IsTerminal;
+
+use crate::{
+    chain_client::mq_next_sequence,
+    subxt_connect,
+    types::{BlockNumber, ParachainApi, PrClient},
+    Args,
+};
+
+/// Headers behind the chain tip more than this are reported as lagging.
+const LAG_THRESHOLD: BlockNumber = 100;
+
+#[derive(Clone, Copy)]
+enum Level {
+    Ok,
+    Warn,
+    Error,
+}
+
+struct Finding {
+    level: Level,
+    message: String,
+    hint: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct Report {
+    findings: Vec<Finding>,
+}
+
+impl Report {
+    fn push(&mut self, level: Level, message: impl Into<String>, hint: Option<&str>) {
+        self.findings.push(Finding {
+            level,
+            message: message.into(),
+            hint: hint.map(Into::into),
+        });
+    }
+
+    pub(crate) fn ok(&mut self, message: impl Into<String>) {
+        self.push(Level::Ok, message, None);
+    }
+
+    pub(crate) fn warn(&mut self, message: impl Into<String>, hint: &str) {
+        self.push(Level::Warn, message, Some(hint));
+    }
+
+    pub(crate) fn error(&mut self, message: impl Into<String>, hint: &str) {
+        self.push(Level::Error, message, Some(hint));
+    }
+
+    pub(crate) fn has_error(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|f| matches!(f.level, Level::Error))
+    }
+
+    pub(crate) fn print(&self) {
+        let colored = std::io::stdout().is_terminal();
+        for finding in &self.findings {
+            let (tag, color) = match finding.level {
+                Level::Ok => ("  OK ", "\x1b[32m"),
+                Level::Warn => ("WARN ", "\x1b[33m"),
+                Level::Error => ("ERROR", "\x1b[31m"),
+            };
+            if colored {
+                println!("{color}[{tag}]\x1b[0m {}", finding.message);
+            } else {
+                println!("[{tag}] {}", finding.message);
+            }
+            if let Some(hint) = &finding.hint {
+                println!("        hint: {hint}");
+            }
+        }
+    }
+}
+
+/// Checks the consistency between pRuntime and the chain without syncing anything, and prints
+/// the result to stdout. Returns an error if any inconsistency is found.
+pub async fn inspect_state(args: &Args) -> Result<()> {
+    let pr = pruntime_client::new_pruntime_client(args.pruntime_endpoint.clone());
+    let api = subxt_connect(&args.relaychain_ws_endpoint).await?;
+    let para_api = if args.parachain {
+        subxt_connect(&args.parachain_ws_endpoint).await?
+    } else {
+        api.clone()
+    };
+
+    let mut report = Report::default();
+    let info = pr
+        .get_info(())
+        .await
+        .context("Failed to get pRuntime info")?;
+    check_sync_progress(&mut report, &info, &api, &para_api, args.parachain).await?;
+    if let Some(pubkey) = worker_pubkey(&info)? {
+        check_registration(&mut report, &info, &para_api, &pubkey).await?;
+        check_endpoints(&mut report, &pr, &para_api, &pubkey).await?;
+        check_gatekeeper(&mut report, &info, &para_api, &pubkey).await?;
+        check_mq(&mut report, &pr, &para_api).await?;
+    }
+    report.print();
+    if report.has_error() {
+        return Err(anyhow!(
+            "Inconsistency found between pRuntime and the chain"
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn worker_pubkey(info: &PhactoryInfo) -> Result<Option<WorkerPublicKey>> {
+    let Some(system) = &info.system else {
+        return Ok(None);
+    };
+    let raw: [u8; 32] = hex::decode(system.public_key.trim_start_matches("0x"))
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| anyhow!("pRuntime returned an invalid pubkey"))?;
+    Ok(Some(WorkerPublicKey::from_raw(raw)))
+}
+
+async fn check_sync_progress(
+    report: &mut Report,
+    info: &PhactoryInfo,
+    api: &ParachainApi,
+    para_api: &ParachainApi,
+    is_parachain: bool,
+) -> Result<()> {
+    if !info.initialized {
+        report.error(
+            "pRuntime is not initialized",
+            "run pherry without --no-init to initialize it",
+        );
+        return Ok(());
+    }
+    report.ok(format!(
+        "pRuntime {} ({}) initialized",
+        info.version, info.git_revision
+    ));
+
+    let relay_tip = api.latest_finalized_block_number().await?;
+    check_progress(
+        report,
+        "Header",
+        info.headernum.saturating_sub(1),
+        relay_tip,
+    );
+    let para_tip = para_api.latest_finalized_block_number().await?;
+    if is_parachain {
+        check_progress(
+            report,
+            "Parachain header",
+            info.para_headernum.saturating_sub(1),
+            para_tip,
+        );
+        if info.waiting_for_paraheaders {
+            report.warn(
+                "pRuntime is waiting for parachain headers",
+                "keep pherry running, the parachain headers will be synced in the next round",
+            );
+        }
+    }
+    check_progress(report, "Block", info.blocknum.saturating_sub(1), para_tip);
+    Ok(())
+}
+
+fn check_progress(report: &mut Report, what: &str, synced: BlockNumber, tip: BlockNumber) {
+    if synced > tip {
+        report.error(
+            format!("{what} synced to {synced}, ahead of the finalized chain tip {tip}"),
+            "pRuntime might be connected to a different chain, check the endpoints",
+        );
+    } else if tip - synced > LAG_THRESHOLD {
+        report.warn(
+            format!(
+                "{what} synced to {synced}, {} behind the chain tip {tip}",
+                tip - synced
+            ),
+            "the worker is still syncing, keep pherry running",
+        );
+    } else {
+        report.ok(format!("{what} synced to {synced}, chain tip {tip}"));
+    }
+}
+
+async fn check_registration(
+    report: &mut Report,
+    info: &PhactoryInfo,
+    para_api: &ParachainApi,
+    pubkey: &WorkerPublicKey,
+) -> Result<()> {
+    let registered_in_pruntime = info.system.as_ref().map_or(false, |s| s.registered);
+    let added_at = para_api.worker_added_at(pubkey.as_ref()).await?;
+    match (added_at, registered_in_pruntime) {
+        (Some(block), true) => report.ok(format!("Worker registered on-chain at block {block}")),
+        (Some(block), false) if info.blocknum <= block => report.warn(
+            format!("Worker registered on-chain at block {block}, not synced by pRuntime yet"),
+            "keep pherry running until pRuntime reaches the registration block",
+        ),
+        (Some(block), false) => report.error(
+            format!("Worker registered on-chain at block {block}, but pRuntime is unaware of it"),
+            "the worker might be registered with another pRuntime instance, re-register it",
+        ),
+        (None, true) => report.error(
+            "pRuntime claims registered but the worker is not found on-chain",
+            "pRuntime might be connected to a different chain, check the endpoints",
+        ),
+        (None, false) => report.warn(
+            "Worker not registered on-chain",
+            "run pherry without --no-register to register the worker",
+        ),
+    }
+    Ok(())
+}
+
+async fn check_endpoints(
+    report: &mut Report,
+    pr: &PrClient,
+    para_api: &ParachainApi,
+    pubkey: &WorkerPublicKey,
+) -> Result<()> {
+    let onchain = para_api.get_endpoints(pubkey).await?;
+    let local = match pr.get_endpoint_info(()).await?.encoded_endpoint_payload {
+        Some(payload) => {
+            let payload = WorkerEndpointPayload::decode(&mut &payload[..])
+                .context("Failed to decode the endpoint payload")?;
+            let VersionedWorkerEndpoints::V1(endpoints) = payload.versioned_endpoints;
+            endpoints
+        }
+        None => vec![],
+    };
+    if local.is_empty() {
+        report.warn(
+            "No endpoint configured in pRuntime",
+            "set the public endpoint of pRuntime to allow it to be bound on-chain",
+        );
+    } else if onchain.is_empty() {
+        report.warn(
+            "Worker endpoint not bound on-chain",
+            "run pherry without --no-bind to bind the endpoint",
+        );
+    } else if onchain != local {
+        report.warn(
+            format!("On-chain endpoints {onchain:?} differ from pRuntime's {local:?}"),
+            "run pherry without --no-bind to update the endpoint",
+        );
+    } else {
+        report.ok(format!("Worker endpoints bound on-chain: {onchain:?}"));
+    }
+    Ok(())
+}
+
+async fn check_gatekeeper(
+    report: &mut Report,
+    info: &PhactoryInfo,
+    para_api: &ParachainApi,
+    pubkey: &WorkerPublicKey,
+) -> Result<()> {
+    let is_onchain_gk = para_api.get_gatekeepers().await?.contains(pubkey);
+    let role = info
+        .system
+        .as_ref()
+        .and_then(|s| s.gatekeeper.as_ref())
+        .map_or(GatekeeperRole::None as i32, |gk| gk.role);
+    let is_local_gk = role != GatekeeperRole::None as i32;
+    match (is_onchain_gk, is_local_gk) {
+        (true, true) => report.ok("Worker is a gatekeeper"),
+        (false, false) => report.ok("Worker is not a gatekeeper"),
+        (true, false) => report.warn(
+            "Worker is a gatekeeper on-chain, but pRuntime hasn't turned into gatekeeper mode",
+            "keep pherry running until pRuntime syncs to the latest block",
+        ),
+        (false, true) => report.error(
+            "pRuntime runs as a gatekeeper, but the worker is not in the on-chain gatekeeper list",
+            "the worker might have been removed from gatekeepers, check the chain state",
+        ),
+    }
+    Ok(())
+}
+
+async fn check_mq(report: &mut Report, pr: &PrClient, para_api: &ParachainApi) -> Result<()> {
+    let messages = crate::msg_sync::get_all_egress_messages(pr).await?;
+    for (sender, messages) in messages {
+        let next_seq = mq_next_sequence(para_api, &sender).await?;
+        let Some(first) = messages.first().map(|m| m.sequence) else {
+            continue;
+        };
+        let pending = messages.iter().filter(|m| m.sequence >= next_seq).count();
+        if first > next_seq {
+            report.error(
+                format!(
+                    "Egress of {sender} starts from seq {first}, but the chain expects {next_seq}"
+                ),
+                "some messages are lost, the worker state might be corrupted",
+            );
+        } else if pending > 0 {
+            report.warn(
+                format!("{pending} messages of {sender} pending, on-chain next seq {next_seq}"),
+                "run pherry without --no-msg-submit to submit them",
+            );
+        } else {
+            report.ok(format!(
+                "Egress of {sender} aligned, on-chain next seq {next_seq}"
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! A directory of controller keys, one file per worker, for the operators running many workers
+//! with distinct controller accounts off the same config file.
+//!
+//! The key of a worker is the mnemonic, seed or derive path in the file named after its public
+//! key in hex, or else after its pRuntime endpoint, see [`endpoint_file_name`].
+//!
+//! A worker has no public key before its pRuntime is initialized, so a new worker needs the file
+//! named after its endpoint to start. The file named after its public key is picked up right after
+//! the initialization if there is one.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// The file name of the key of the worker at `endpoint`: the endpoint without the scheme and the
+/// trailing slash, the characters other than ASCII alphanumerics, `.` and `-` replaced with `_`.
+///
+/// For example, `10.0.0.1_8000` for `http://10.0.0.1:8000/`.
+pub fn endpoint_file_name(endpoint: &str) -> String {
+    let endpoint = endpoint
+        .split_once("://")
+        .map_or(endpoint, |(_, rest)| rest)
+        .trim_end_matches('/');
+    endpoint
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '.' || c == '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// The files the key of a worker is looked up in, the first existing one used.
+pub fn candidates(dir: &Path, endpoint: &str, pubkey: Option<&str>) -> Vec<PathBuf> {
+    let pubkey = pubkey.map(|pubkey| pubkey.trim_start_matches("0x").to_lowercase());
+    pubkey
+        .into_iter()
+        .chain(std::iter::once(endpoint_file_name(endpoint)))
+        .map(|name| dir.join(name))
+        .collect()
+}
+
+/// Reads the key of the worker at `endpoint`, with its public key if known yet. Returns the file
+/// read along with the key.
+pub fn read_key(dir: &str, endpoint: &str, pubkey: Option<&str>) -> Result<(PathBuf, String)> {
+    let dir = Path::new(dir);
+    if !dir.is_dir() {
+        bail!("The keystore {} is not a directory", dir.display());
+    }
+    let candidates = candidates(dir, endpoint, pubkey);
+    let Some(file) = candidates.iter().find(|file| file.is_file()) else {
+        let names: Vec<_> = candidates
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        let hint = if pubkey.is_none() {
+            ", the key of a worker not initialized yet goes in the file named after its endpoint"
+        } else {
+            ""
+        };
+        bail!(
+            "No key for the worker in the keystore, looked up {}{hint}",
+            names.join(", ")
+        );
+    };
+    let key = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read the key in {}", file.display()))?;
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("The key in {} is empty", file.display());
+    }
+    Ok((file.clone(), key.to_string()))
+}
@@ -2,22 +2,24 @@ use anyhow::{anyhow, Context, Result};
 use log::{debug, error, info, warn};
 use phala_node_rpc_ext::MakeInto;
 use phala_trie_storage::ser::StorageChanges;
-use sgx_attestation::dcap::report::get_collateral;
 use sc_consensus_grandpa::FinalityProof;
 use sp_core::{crypto::AccountId32, H256};
 use std::convert::TryFrom;
-use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
 
 use codec::{Decode, Encode};
 use phala_pallets::pallet_registry::Attestation;
 use phaxt::{
-    dynamic::storage_key,
+    dynamic::{
+        events::{filter_events, WorkerAdded, WorkerUpdated},
+        storage_key,
+    },
     rpc::ExtraRpcExt as _,
-    sp_core::{crypto::Pair, sr25519},
     subxt::{self, tx::TxPayload},
-    RpcClient,
+    ChainApi, ChainInfo, ConnectionState, RpcClient,
 };
 use sp_consensus_grandpa::SetId;
 use subxt::config::{substrate::Era, Header as _};
@@ -26,34 +28,80 @@ pub use authority::get_authority_with_proof_at;
 pub use authority::verify_with_prev_authority_set;
 
 mod authority;
+mod back_pressure;
+pub mod config;
 mod endpoint;
+mod era;
 mod error;
+mod finality_stream;
+mod heartbeat;
+mod inspect;
+mod msg_state;
 mod msg_sync;
 mod notify_client;
+mod phase_timing;
 mod prefetcher;
-
+mod reload;
+mod round_summary;
+mod runtime_compat;
+mod setup;
+mod stall;
+mod storage_verifier;
+mod sync_eta;
+mod warp_sync;
+mod worker_info;
+mod worker_key;
+
+pub mod balance;
+pub mod block_source;
 pub mod chain_client;
 pub mod headers_cache;
+pub mod keystore;
+pub mod notify_spool;
+pub mod pccs;
+pub mod ra_tls;
+pub mod request_log;
+pub mod signer;
+pub mod topology;
 pub mod types;
 
+use crate::balance::BalanceTooLow;
+use crate::block_source::{BlockSources, NodeSource};
+use crate::era::EraCache;
 use crate::error::Error;
+use crate::finality_stream::FinalityStream;
+use crate::msg_state::SubmittedMessages;
+use crate::phase_timing::{Phase, PhaseStats};
+use crate::request_log::RequestLog;
+use crate::round_summary::RoundTracker;
+use crate::signer::{KeyScheme, SignerKind};
+use crate::stall::{StallAction, StallDetector, Stalled};
 use crate::types::{
     Block, BlockNumber, ConvertTo, Hash, Header, NotifyReq, NumberOrHex, ParachainApi, PrClient,
     RelaychainApi, SrSigner, SyncOperation,
 };
+use crate::worker_key::WorkerKey;
 use phactory_api::blocks::{
-    self, BlockHeader, BlockHeaderWithChanges, HeaderToSync, StorageProof,
+    self, AuthoritySetChange, BlockHeader, BlockHeaderWithChanges, HeaderToSync, StorageProof,
 };
+use phactory_api::prpc::phactory_api_client::PhactoryApiClient;
 use phactory_api::prpc::{self, InitRuntimeResponse, PhactoryInfo};
-use phactory_api::pruntime_client;
+use phactory_api::pruntime_client::{self, RpcRequest, TlsConfig};
+use phactory_api::storage_sync::SyncErrorCode;
 
-use clap::Parser;
-use headers_cache::{fetch_genesis_info, Client as CacheClient};
+use clap::{Parser, Subcommand};
+use headers_cache::{fetch_genesis_info, CacheAuth, Client as CacheClient};
 use msg_sync::{Error as MsgSyncError, Receiver, Sender};
 use notify_client::NotifyClient;
+use pccs::CollateralFetcher;
 use phala_types::{AttestationProvider, AttestationReport, Collateral};
 
 pub use phaxt::connect as subxt_connect;
+pub use prefetcher::PrefetchClient;
+pub use storage_verifier::StorageChangesVerifier;
+
+/// The controller key of the dev chains, the default of `--mnemonic`.
+const DEV_MNEMONIC: &str = "//Alice";
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -62,6 +110,15 @@ pub use phaxt::connect as subxt_connect;
     author
 )]
 pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        long,
+        help = "Load the options from a TOML config file, keyed by the long option names. The options on the command line take precedence"
+    )]
+    config: Option<String>,
+
     #[arg(
         long,
         help = "Dev mode (equivalent to `--use-dev-key --mnemonic='//Alice'`)"
@@ -88,16 +145,37 @@ pub struct Args {
 
     #[arg(
         long,
+        conflicts_with_all = ["no_sync", "no_register"],
+        help = "Sync until the worker is registered and its endpoint bound on chain, then exit. For provisioning workers whose sync is taken over by another service afterwards."
+    )]
+    until_registered: bool,
+
+    #[arg(
+        long,
+        help = "Check the consistency between pRuntime and the chain, print a report and exit without syncing."
+    )]
+    inspect_state: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["inject_key", "inject_key_file"],
         help = "Inject dev key (0x1) to pRuntime. Cannot be used with remote attestation enabled."
     )]
     use_dev_key: bool,
 
     #[arg(
-        default_value = "",
         long = "inject-key",
-        help = "Inject key to pRuntime."
+        env = "PHERRY_INJECT_KEY",
+        help = "Inject the 32 bytes key in hex to pRuntime. Cannot be used with remote attestation enabled."
     )]
-    inject_key: String,
+    inject_key: Option<WorkerKey>,
+
+    #[arg(
+        long,
+        conflicts_with = "inject_key",
+        help = "Inject the key in hex read from the file to pRuntime. Cannot be used with remote attestation enabled."
+    )]
+    inject_key_file: Option<String>,
 
     #[arg(
         default_value = "ws://localhost:9944",
@@ -122,23 +200,194 @@ pub struct Args {
     )]
     pruntime_endpoint: String,
 
+    #[arg(
+        long,
+        help = "pRuntime http endpoint to sync the headers through, defaults to --pruntime-endpoint. Must serve the same worker"
+    )]
+    pruntime_header_endpoint: Option<String>,
+
+    #[arg(
+        long,
+        help = "pRuntime http endpoint to dispatch the blocks through, defaults to --pruntime-endpoint. Must serve the same worker"
+    )]
+    pruntime_block_endpoint: Option<String>,
+
     #[arg(
         long,
         help = "pRuntime http endpoint to handover the key. The handover will only happen when the old pRuntime is synced."
     )]
     next_pruntime_endpoint: Option<String>,
 
+    #[arg(
+        long,
+        help = "PEM file of the CA certificate to trust for the https pRuntime endpoints, besides the built-in roots"
+    )]
+    pruntime_tls_ca: Option<String>,
+
+    #[arg(
+        long,
+        requires = "pruntime_client_key",
+        help = "PEM file of the client certificate to authenticate to the https pRuntime endpoints with"
+    )]
+    pruntime_client_cert: Option<String>,
+
+    #[arg(
+        long,
+        requires = "pruntime_client_cert",
+        help = "PEM file of the private key of --pruntime-client-cert"
+    )]
+    pruntime_client_key: Option<String>,
+
+    #[arg(
+        long,
+        help = "Verify the RA-TLS certificates of the https pRuntime endpoints against the DCAP quotes they carry, instead of the CAs. Requires --pccs-url"
+    )]
+    pruntime_ra_tls: bool,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Hashes of the pRuntime builds accepted with --pruntime-ra-tls, in hex, separated by commas"
+    )]
+    pruntime_allowed_hash: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Check that the worker endpoint is reachable before binding it, and keep probing it at the chain tip every --endpoint-probe-interval"
+    )]
+    endpoint_probe: bool,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "External service to check that the worker endpoint is reachable from the public internet, requested as GET <url>?endpoint=<endpoint>. The endpoint is probed directly if empty"
+    )]
+    endpoint_checker_url: String,
+
+    #[arg(
+        long,
+        default_value = "600",
+        help = "Interval to probe the bound worker endpoint at the chain tip with --endpoint-probe, 0 to disable. unit: second"
+    )]
+    endpoint_probe_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "600",
+        help = "Interval to compare the worker endpoint bound on chain with the one pRuntime is configured with at the chain tip, binding it again if they differ, 0 to disable. unit: second"
+    )]
+    endpoint_drift_check_interval: u64,
+
+    #[arg(
+        default_value = "0",
+        long,
+        help = "Warn and report to the notify endpoint when the balance of the controller account drops below it, 0 to disable. unit: balance"
+    )]
+    min_balance_warn: u128,
+
+    #[arg(
+        default_value = "0",
+        long,
+        help = "Exit with code 4 when the balance of the controller account drops below it, even with --auto-restart, 0 to disable. Must be below --min-balance-warn if that is set. unit: balance"
+    )]
+    min_balance_abort: u128,
+
+    #[arg(
+        long,
+        default_value = "600",
+        help = "Interval to check the balance of the controller account at the chain tip. unit: second"
+    )]
+    balance_check_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "File to write the controller account balance to at each check, in the Prometheus text format for the node exporter textfile collector. Disabled if empty"
+    )]
+    metrics_file: String,
+
     #[arg(default_value = "", long, help = "notify endpoint")]
     notify_endpoint: String,
 
     #[arg(
-        default_value = "//Alice",
+        long,
+        default_value = "3600",
+        help = "Interval to post the summary of the work done to the notify endpoint, at the chain tip. 0 to disable. unit: second"
+    )]
+    notify_summary_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Interval to post a heartbeat of the worker status signed with the controller key to the notify endpoint, for the fleet services to authenticate the reports. Not supported with --signer remote. 0 to disable. unit: second"
+    )]
+    heartbeat_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "File to persist the notifications not delivered yet while the notify endpoint is down, to replay them after a restart. Kept in memory only if empty"
+    )]
+    notify_spool_file: String,
+
+    #[arg(
+        long,
+        default_value = "1000",
+        help = "Max number of the notifications kept while the notify endpoint is down, the oldest dropped first. 0 to disable"
+    )]
+    notify_spool_size: usize,
+
+    #[arg(
+        long,
+        help = "Directory to record the requests changing the pRuntime state to, one file per session, to replay them with the replay subcommand. The key injected on init ends up in the log"
+    )]
+    record_requests: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "100",
+        help = "Number of the recent sync rounds to report the p50/p95 of the fetch, decode and dispatch time over"
+    )]
+    timing_window: usize,
+
+    #[arg(
+        default_value = DEV_MNEMONIC,
         short = 'm',
         long = "mnemonic",
-        help = "Controller SR25519 private key mnemonic, private key seed, or derive path"
+        help = "Controller private key mnemonic, private key seed, or derive path. Reloaded from the config file on SIGHUP, along with --scheme, --signer and --signer-url"
     )]
     mnemonic: String,
 
+    #[arg(
+        long,
+        conflicts_with = "mnemonic",
+        help = "Directory of the controller keys, one per worker, in the file named after the worker public key in hex or else after the pRuntime endpoint (e.g. 10.0.0.1_8000). A worker not initialized yet has no public key, so it needs the file named after the endpoint to start. Used instead of --mnemonic"
+    )]
+    keystore: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = KeyScheme::Sr25519,
+        help = "Signature scheme of the controller key given by --mnemonic"
+    )]
+    scheme: KeyScheme,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SignerKind::Local,
+        help = "Where to sign the extrinsics: with --mnemonic, or by the remote signing service at --signer-url"
+    )]
+    signer: SignerKind,
+
+    #[arg(
+        default_value = "",
+        long,
+        help = "URL of the remote signing service, a JSON-RPC endpoint holding the controller key"
+    )]
+    signer_url: String,
+
     #[arg(
         default_value = "1000",
         long = "fetch-blocks",
@@ -146,6 +395,13 @@ pub struct Args {
     )]
     fetch_blocks: u32,
 
+    #[arg(
+        default_value_t = DEFAULT_PARA_HEADER_CONCURRENCY,
+        long,
+        help = "Max number of parachain headers fetched from the node concurrently"
+    )]
+    para_header_concurrency: usize,
+
     #[arg(
         default_value = "4",
         long = "sync-blocks",
@@ -153,21 +409,75 @@ pub struct Args {
     )]
     sync_blocks: BlockNumber,
 
+    #[arg(
+        default_value = "0",
+        long,
+        help = "Max number of consecutive blocks only changing the System and Timestamp bookkeeping dispatched beyond the --sync-blocks batch, if pRuntime supports it, fewer under memory pressure. 0 to disable"
+    )]
+    coalesce_empty_blocks: BlockNumber,
+
+    #[arg(
+        default_value = "268435456",
+        long,
+        help = "Max bytes of the fetched storage changes buffered ahead of the dispatch, the blocks beyond are dropped and fetched again once reached. 0 for no limit"
+    )]
+    prefetch_memory_limit: usize,
+
+    #[arg(
+        long,
+        help = "Drop the buffered storage changes after each dispatch round instead of keeping them for the next round, to release the memory on small hosts"
+    )]
+    prune_cache_after_dispatch: bool,
+
+    #[arg(
+        default_value = "1",
+        long = "min-sync-blocks",
+        help = "The min batch size to sync blocks to pRuntime when it runs short of memory."
+    )]
+    min_sync_blocks: BlockNumber,
+
+    #[arg(
+        default_value = "60",
+        long,
+        help = "The pRuntime memory usage in percent below which blocks are synced in batches of --sync-blocks."
+    )]
+    memory_low_watermark: u8,
+
+    #[arg(
+        default_value = "85",
+        long,
+        help = "The pRuntime memory usage in percent above which blocks are synced in batches of --min-sync-blocks."
+    )]
+    memory_high_watermark: u8,
+
     #[arg(
         long = "operator",
-        help = "The operator account to set the miner for the worker."
+        help = "The operator account to set the miner for the worker. Reloaded from the config file on SIGHUP, registering the worker again if changed"
     )]
     operator: Option<String>,
 
     #[arg(long = "parachain", help = "Parachain mode")]
     parachain: bool,
 
+    #[arg(
+        long,
+        help = "Don't detect the parachain mode from the chains of the endpoints. By default, it's enabled if --parachain is forgotten, and contradicting flags are refused."
+    )]
+    no_auto_detect: bool,
+
     #[arg(
         long,
         help = "The first parent header to be synced, default to auto-determine"
     )]
     start_header: Option<BlockNumber>,
 
+    #[arg(
+        long,
+        conflicts_with = "warp_sync",
+        help = "Resolve the start header from the relaychain genesis blocks allowed on chain, refusing to initialize at any other block"
+    )]
+    start_header_from_chain: bool,
+
     #[arg(long, help = "Don't wait the substrate nodes to sync blocks")]
     no_wait: bool,
 
@@ -192,6 +502,13 @@ pub struct Args {
     )]
     longevity: u64,
 
+    #[arg(
+        default_value = "2",
+        long,
+        help = "Reuse the era checkpoint of the transactions for this number of blocks, capped at half of the longevity. 0 to disable. unit: block"
+    )]
+    era_pin_blocks: u64,
+
     #[arg(
         default_value = "200",
         long,
@@ -199,6 +516,37 @@ pub struct Args {
     )]
     max_sync_msgs_per_round: u64,
 
+    #[arg(
+        default_value = "1",
+        long,
+        help = "Max number of messages packed into one Utility.force_batch extrinsic. 1 to submit each message in its own extrinsic"
+    )]
+    msg_batch_size: u64,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "File to persist the sequences of the submitted messages, to avoid resubmitting the messages pending in the tx pool after a restart. Disabled if empty"
+    )]
+    msg_state_file: String,
+
+    #[arg(
+        long,
+        help = "Verify the storage changes against the state roots of the on-chain headers before dispatching them. The parachain state is replayed locally, fetched from the node at the block before the first one dispatched, which the node must still hold"
+    )]
+    verify_storage_changes: bool,
+
+    #[arg(
+        default_value = "1800",
+        long,
+        help = "Consider the sync stalled if pRuntime makes no progress on the dispatched headers or blocks for this long, 0 to disable. unit: second"
+    )]
+    stall_timeout: u64,
+
+    /// What to do when the sync stalls, besides logging and notifying
+    #[arg(long, value_enum, default_value_t = StallAction::Warn)]
+    stall_action: StallAction,
+
     #[arg(long, help = "Auto restart self after an error occurred")]
     auto_restart: bool,
 
@@ -216,6 +564,26 @@ pub struct Args {
     #[arg(default_value = "")]
     headers_cache_uri: String,
 
+    #[arg(
+        long,
+        env = "PHERRY_HEADERS_CACHE_AUTH",
+        hide_env_values = true,
+        help = "Credentials of the headers cache, token:<token> or hmac:<hex key>"
+    )]
+    headers_cache_auth: Option<CacheAuth>,
+
+    #[arg(
+        long,
+        help = "Sync relaychain headers from the GRANDPA justification stream of the node, falling back to prove_finality for the gaps"
+    )]
+    finality_stream: bool,
+
+    #[arg(
+        long,
+        help = "Initialize pRuntime at the latest relaychain authority set change instead of the parachain genesis, loading the parachain state there like --fast-sync, falling back to full header sync if not possible"
+    )]
+    warp_sync: bool,
+
     #[arg(long, help = "Stop when synced to given parachain block")]
     #[arg(default_value_t = BlockNumber::MAX)]
     to_block: BlockNumber,
@@ -249,22 +617,80 @@ pub struct Args {
     #[arg(long)]
     load_handover_proof: bool,
 
-    /// The URL of the PCCS server.
+    /// The URL of the PCCS server. Multiple servers separated by commas are tried in order.
     #[arg(long, default_value = "")]
     pccs_url: String,
 
     /// Timeout in seconds for connecting to PCCS server.
     #[arg(long, default_value = "30")]
     pccs_timeout: u64,
+
+    /// The directory to cache the collateral fetched from PCCS. Caching is disabled if empty.
+    #[arg(long, default_value = "")]
+    pccs_cache_dir: String,
+
+    /// How long in seconds the cached collateral is valid for.
+    #[arg(long, default_value = "3600")]
+    pccs_cache_ttl: u64,
 }
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the effective config merged from the config file and the command line, then exit.
+    PrintConfig,
+    /// Probe the node and pRuntime endpoints and write a ready-to-run config file, asking for
+    /// the options on the terminal.
+    Setup {
+        /// The config file to write.
+        #[arg(long, default_value = "pherry.toml")]
+        output: String,
+        /// Take the options given on the command line without asking.
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Print the on-chain state of a worker: its registration, endpoints, session and stake,
+    /// then exit.
+    WorkerInfo {
+        /// The public key of the worker in hex.
+        #[arg(long)]
+        pubkey: String,
+    },
+    /// Send the requests recorded with --record-requests to the pRuntime at --pruntime-endpoint
+    /// in order, then exit.
+    Replay {
+        /// The session file to replay.
+        file: String,
+        /// Go on past the requests failing, e.g. the ones rejected in the recorded session too.
+        #[arg(long)]
+        keep_going: bool,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
 enum RaOption {
     None,
     Ias,
     Dcap,
 }
 
+impl Args {
+    /// The key to inject into pRuntime on init, if any. Fails if the key is unreadable or remote
+    /// attestation is enabled, pRuntime would reject it late otherwise.
+    pub fn worker_key(&self) -> Result<Option<WorkerKey>> {
+        let key = match (&self.inject_key, &self.inject_key_file) {
+            (Some(key), _) => Some(key.clone()),
+            (None, Some(path)) => Some(WorkerKey::read_file(path)?),
+            (None, None) => self.use_dev_key.then(WorkerKey::dev),
+        };
+        if key.is_some() && self.attestation_provider != RaOption::None {
+            anyhow::bail!(
+                "Injecting a key is not allowed with remote attestation enabled, add `--attestation-provider none` or use `--dev`"
+            );
+        }
+        Ok(key)
+    }
+}
+
 impl From<RaOption> for Option<AttestationProvider> {
     fn from(other: RaOption) -> Self {
         match other {
@@ -279,6 +705,16 @@ struct RunningFlags {
     worker_registered: bool,
     endpoint_registered: bool,
     restart_failure_count: u32,
+    /// The transient sync rejections retried since the last progress, not counted as failures.
+    transient_retries: u32,
+    round: RoundTracker,
+    credentials: tokio::sync::watch::Receiver<reload::Credentials>,
+    /// Kept over the restarts, along with the notifications spooled.
+    notify: NotifyClient,
+    /// Kept over the restarts, to record a session to a single file.
+    request_log: Option<Arc<RequestLog>>,
+    /// Kept over the restarts, to resume from the blocks fetched but not dispatched yet.
+    fetcher: PrefetchClient,
 }
 
 pub struct BlockSyncState {
@@ -358,19 +794,18 @@ pub async fn fetch_storage_changes_with_root_or_not(
     with_root: bool,
 ) -> Result<Vec<BlockHeaderWithChanges>> {
     log::info!("fetch_storage_changes with_root={with_root}, ({from}-{to})");
-    if to < from {
-        return Ok(vec![]);
-    }
-    if let Some(cache) = cache {
-        let count = to + 1 - from;
-        if let Ok(changes) = cache.get_storage_changes(from, count).await {
-            log::info!(
-                "Got {} storage changes from cache server ({from}-{to})",
-                changes.len()
-            );
-            return Ok(changes);
-        }
-    }
+    let node = NodeSource::new(phaxt::ChainApi(client.clone()));
+    BlockSources::with_cache(cache.cloned(), node)
+        .storage_changes(from, to, with_root)
+        .await
+}
+
+async fn fetch_storage_changes_from_node(
+    client: &RpcClient,
+    from: BlockNumber,
+    to: BlockNumber,
+    with_root: bool,
+) -> Result<Vec<BlockHeaderWithChanges>> {
     let from_hash = get_header_hash(client, Some(from)).await?;
     let to_hash = get_header_hash(client, Some(to)).await?;
 
@@ -397,53 +832,129 @@ pub async fn fetch_storage_changes_with_root_or_not(
             .map(|changes| (changes, Default::default()))
             .collect::<Vec<_>>()
     };
-    let storage_changes = changes
-        .into_iter()
-        .enumerate()
-        .map(|(offset, (storage_changes, state_root))| {
-            BlockHeaderWithChanges {
-                // Headers are synced separately. Only the `number` is used in pRuntime while syncing blocks.
-                block_header: BlockHeader {
-                    number: from + offset as BlockNumber,
-                    parent_hash: Default::default(),
-                    state_root,
-                    extrinsics_root: Default::default(),
-                    digest: Default::default(),
-                },
-                storage_changes: StorageChanges {
-                    main_storage_changes: storage_changes.main_storage_changes.into_(),
-                    child_storage_changes: storage_changes.child_storage_changes.into_(),
-                },
-            }
-        })
-        .collect();
+    let storage_changes = phase_timing::measure_sync(Phase::Decode, || {
+        changes
+            .into_iter()
+            .enumerate()
+            .map(|(offset, (storage_changes, state_root))| {
+                BlockHeaderWithChanges::new(
+                    // Headers are synced separately. Only the `number` is used in pRuntime while syncing blocks.
+                    BlockHeader {
+                        number: from + offset as BlockNumber,
+                        parent_hash: Default::default(),
+                        state_root,
+                        extrinsics_root: Default::default(),
+                        digest: Default::default(),
+                    },
+                    StorageChanges {
+                        main_storage_changes: storage_changes.main_storage_changes.into_(),
+                        child_storage_changes: storage_changes.child_storage_changes.into_(),
+                    },
+                )
+            })
+            .collect()
+    });
     Ok(storage_changes)
 }
 
 pub async fn batch_sync_storage_changes(
     pr: &PrClient,
     api: &ParachainApi,
-    cache: Option<&CacheClient>,
+    sources: &BlockSources,
+    mut verifier: Option<&mut StorageChangesVerifier>,
+    fetcher: &mut PrefetchClient,
     from: BlockNumber,
     to: BlockNumber,
     batch_size: BlockNumber,
+    max_coalesced: BlockNumber,
 ) -> Result<()> {
     info!(
         "batch syncing from {from} to {to} ({} blocks)",
         to as i64 - from as i64 + 1
     );
 
-    let mut fetcher = prefetcher::PrefetchClient::new();
-
-    for from in (from..=to).step_by(batch_size as _) {
-        let to = to.min(from.saturating_add(batch_size - 1));
-        let storage_changes = fetcher.fetch_storage_changes(api, cache, from, to).await?;
+    let mut next = from;
+    while next <= to {
+        let batch_to = to.min(next.saturating_add(batch_size - 1));
+        let mut storage_changes = phase_timing::measure(
+            Phase::Fetch,
+            fetcher.fetch_storage_changes(sources, next, batch_to),
+        )
+        .await?;
+        if max_coalesced > 0 {
+            phase_timing::measure(
+                Phase::Fetch,
+                coalesce_empty_blocks(
+                    fetcher,
+                    sources,
+                    &mut storage_changes,
+                    to,
+                    batch_size,
+                    max_coalesced,
+                ),
+            )
+            .await?;
+        }
+        next = match storage_changes.last() {
+            Some(last) => last.block_header.number + 1,
+            None => batch_to + 1,
+        };
+        if let Some(verifier) = verifier.as_deref_mut() {
+            phase_timing::measure(Phase::Decode, verifier.verify(api, &storage_changes))
+                .await
+                .context("Refused to dispatch the storage changes")?;
+        }
         let r = req_dispatch_block(pr, storage_changes).await?;
         log::debug!("  ..dispatch_block: {:?}", r);
     }
     Ok(())
 }
 
+fn has_storage_changes(block: &BlockHeaderWithChanges) -> bool {
+    !block.is_bookkeeping_only()
+}
+
+/// Extends the run of blocks without storage changes at the end of `blocks` with the ones
+/// following it, up to `max_coalesced` blocks beyond the batch, so that the run is dispatched at
+/// a time.
+async fn coalesce_empty_blocks(
+    fetcher: &mut PrefetchClient,
+    sources: &BlockSources,
+    blocks: &mut Vec<BlockHeaderWithChanges>,
+    to: BlockNumber,
+    batch_size: BlockNumber,
+    max_coalesced: BlockNumber,
+) -> Result<()> {
+    let mut coalesced = 0;
+    loop {
+        let Some(last) = blocks.last() else {
+            return Ok(());
+        };
+        let next = last.block_header.number + 1;
+        if has_storage_changes(last) || next > to || coalesced >= max_coalesced {
+            break;
+        }
+        let batch_to = to.min(next.saturating_add(batch_size.min(max_coalesced - coalesced) - 1));
+        let mut fetched = fetcher.fetch_storage_changes(sources, next, batch_to).await?;
+        let empty = fetched
+            .iter()
+            .position(has_storage_changes)
+            .unwrap_or(fetched.len());
+        let rest = fetched.split_off(empty);
+        let done = !rest.is_empty() || fetched.is_empty();
+        coalesced += fetched.len() as BlockNumber;
+        blocks.extend(fetched);
+        fetcher.put_back(rest);
+        if done {
+            break;
+        }
+    }
+    if coalesced > 0 {
+        info!("coalesced {coalesced} blocks without storage changes into the dispatch");
+    }
+    Ok(())
+}
+
 async fn try_load_handover_proof(pr: &PrClient, api: &ParachainApi) -> Result<()> {
     let info = pr.get_info(()).await?;
     if info.safe_mode_level < 2 {
@@ -453,33 +964,49 @@ async fn try_load_handover_proof(pr: &PrClient, api: &ParachainApi) -> Result<()
         return Ok(());
     }
     let current_block = info.blocknum - 1;
-    let hash = get_header_hash(api, Some(current_block)).await?;
-    let proof = chain_client::read_proofs(
-        api,
-        Some(hash),
-        vec![
-            &storage_key("PhalaRegistry", "PRuntimeAddedAt")[..],
-            &storage_key("PhalaRegistry", "PRuntimeAllowList")[..],
-            &storage_key("Timestamp", "Now")[..],
-        ],
-    )
-    .await
-    .context("Failed to get handover proof")?;
+    let (header, hash) = get_header_at(api, Some(current_block)).await?;
+    let snapshot = api
+        .storage_snapshot_with_prefixes(
+            Some(hash),
+            vec![
+                &storage_key("PhalaRegistry", "PRuntimeAddedAt")[..],
+                &storage_key("PhalaRegistry", "PRuntimeAllowList")[..],
+                &storage_key("Timestamp", "Now")[..],
+            ],
+        )
+        .await
+        .context("Failed to get handover proof")?;
+    let keys = snapshot.keys();
+    let proof = snapshot.proof;
     info!("Loading handover proof at {current_block}");
     for p in &proof {
         info!("key=0x{}", hex::encode(sp_core::blake2_256(p)));
     }
-    pr.load_storage_proof(prpc::StorageProof { proof }).await?;
+    if info.has_capability(prpc::Capability::StorageProofAt) {
+        pr.load_storage_proof_at(prpc::StorageProofAt {
+            block_number: current_block,
+            keys,
+            proof,
+            encoded_header: header.encode(),
+        })
+        .await?;
+    } else {
+        // Older pRuntimes load the proof unverified.
+        pr.load_storage_proof(prpc::StorageProof { proof }).await?;
+    }
     Ok(())
 }
 
 async fn req_sync_header(
     pr: &PrClient,
     headers: Vec<HeaderToSync>,
+    authority_set_change: Option<AuthoritySetChange>,
 ) -> Result<prpc::SyncedTo> {
-    let resp = pr
-        .sync_header(prpc::HeadersToSync::new(headers, None))
-        .await?;
+    let resp = phase_timing::measure(
+        Phase::Dispatch,
+        pr.sync_header(prpc::HeadersToSync::new(headers, authority_set_change)),
+    )
+    .await?;
     Ok(resp)
 }
 
@@ -488,9 +1015,11 @@ async fn req_sync_para_header(
     headers: blocks::Headers,
     proof: StorageProof,
 ) -> Result<prpc::SyncedTo> {
-    let resp = pr
-        .sync_para_header(prpc::ParaHeadersToSync::new(headers, proof))
-        .await?;
+    let resp = phase_timing::measure(
+        Phase::Dispatch,
+        pr.sync_para_header(prpc::ParaHeadersToSync::new(headers, proof)),
+    )
+    .await?;
     Ok(resp)
 }
 
@@ -498,7 +1027,11 @@ async fn req_dispatch_block(
     pr: &PrClient,
     blocks: Vec<BlockHeaderWithChanges>,
 ) -> Result<prpc::SyncedTo> {
-    let resp = pr.dispatch_blocks(prpc::Blocks::new(blocks)).await?;
+    let resp = phase_timing::measure(
+        Phase::Dispatch,
+        pr.dispatch_blocks(prpc::Blocks::new(blocks)),
+    )
+    .await?;
     Ok(resp)
 }
 
@@ -520,18 +1053,15 @@ pub async fn get_finalized_header_with_paraid(
 ) -> Result<Option<(Header, Vec<Vec<u8>> /*proof*/)>> {
     let para_head_storage_key = api.paras_heads_key(para_id)?;
 
-    let raw_header = api
-        .rpc()
-        .storage(&para_head_storage_key, Some(last_header_hash))
+    let snapshot = api
+        .storage_snapshot(Some(last_header_hash), [&para_head_storage_key[..]])
         .await?;
 
-    let raw_header = if let Some(hdr) = raw_header {
-        hdr.0
-    } else {
+    let Some(raw_header) = snapshot.value(&para_head_storage_key) else {
         return Ok(None);
     };
 
-    let para_fin_header_data = chain_client::decode_parachain_heads(raw_header.clone())?;
+    let para_fin_header_data = chain_client::decode_parachain_heads(raw_header.to_vec())?;
 
     let para_fin_header =
         sp_runtime::generic::Header::<BlockNumber, sp_runtime::traits::BlakeTwo256>::decode(
@@ -539,131 +1069,233 @@ pub async fn get_finalized_header_with_paraid(
         )
         .or(Err(Error::FailedToDecode))?;
 
-    let header_proof =
-        chain_client::read_proof(api, Some(last_header_hash), &para_head_storage_key).await?;
-    Ok(Some((para_fin_header, header_proof)))
-}
-
-pub async fn get_parachain_header_from_relaychain_at(
-    relay_api: &RelaychainApi,
-    para_api: &ParachainApi,
-    cache_client: &Option<CacheClient>,
-    block_number: BlockNumber,
-) -> Result<(u32, Vec<Vec<u8>>)> {
-    if let Some(cache) = &cache_client {
-        let cached_headers = cache
-            .get_headers(block_number)
-            .await
-            .unwrap_or_default();
-        if cached_headers.len() == 1 {
-            let para_header = &cached_headers
-                .first()
-                .unwrap()
-                .para_header;
-            if let Some(para_header) = para_header {
-                return Ok((para_header.fin_header_num, para_header.proof.clone()))
-            }
-        }
-    }
-
-    let hash = get_header_hash(relay_api, Some(block_number)).await?;
-    let header = get_finalized_header(relay_api, para_api, hash).await?;
-    if let Some((header, proof)) = header {
-        return Ok((header.number, proof));
-    }
-
-    Err(anyhow!("No parachain header was found at {}", block_number))
+    Ok(Some((para_fin_header, snapshot.proof)))
 }
 
 pub async fn get_headers(
     api: &RelaychainApi,
     from: BlockNumber,
 ) -> Result<Vec<HeaderToSync>> {
-    let first_header = get_header_at(api, Some(from)).await?;
-    let mut headers = vec![
-        HeaderToSync {
-            header: first_header.0.clone(), 
-            justification: None
-        },
-    ];
-
-    let encoded_finality_proof = prove_finality_at(api, from).await?;
-    let finality_proof : FinalityProof<Header> = Decode::decode(&mut encoded_finality_proof.as_slice())?;
-    headers.extend(
-        finality_proof.unknown_headers
-            .iter()
-            .map(|h| HeaderToSync {
-                header: h.clone(),
-                justification: None,
+    let (first_header, encoded_finality_proof) = phase_timing::measure(Phase::Fetch, async {
+        let first_header = get_header_at(api, Some(from)).await?;
+        let encoded_finality_proof = prove_finality_at(api, from).await?;
+        Ok::<_, anyhow::Error>((first_header, encoded_finality_proof))
+    })
+    .await?;
+
+    phase_timing::measure_sync(Phase::Decode, || {
+        let finality_proof: FinalityProof<Header> =
+            Decode::decode(&mut encoded_finality_proof.as_slice())?;
+        let mut chain = vec![first_header.0];
+        chain.extend(finality_proof.unknown_headers);
+
+        let last = chain.len() - 1;
+        let mut justification = Some(finality_proof.justification);
+        let headers = chain
+            .into_iter()
+            .enumerate()
+            .map(|(i, header)| {
+                let justification = if i == last {
+                    justification.take()
+                } else {
+                    None
+                };
+                HeaderToSync::new(header, justification)
             })
-    );
-
-    let last_header = headers.last_mut().expect("Already filled at least one header");
-    last_header.justification = Some(finality_proof.justification);
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|headers| {
+                blocks::validate_header_chain(headers.iter().map(|h| &h.header))?;
+                Ok(headers)
+            })
+            .map_err(|err| anyhow!("Invalid finality proof of block {from}: {err}"))?;
+        Ok(headers)
+    })
+}
 
-    Ok(headers)
+/// Cuts the headers at the first one scheduling an authority set change, justifying it if needed,
+/// and gets the proof of the new set to sync along with them.
+///
+/// pRuntime verifies the justification of the last header of a batch with the set it knows, and
+/// only moves to the next set at the end of the batch, checking it against the proof.
+async fn cut_at_authority_set_change(
+    api: &RelaychainApi,
+    mut headers: Vec<HeaderToSync>,
+) -> Result<(Vec<HeaderToSync>, Option<AuthoritySetChange>)> {
+    let Some(at) = headers
+        .iter()
+        .position(|h| blocks::find_scheduled_change(&h.header).is_some())
+    else {
+        return Ok((headers, None));
+    };
+    headers.truncate(at + 1);
+    let last = headers.last_mut().expect("The headers are not empty");
+    let number = last.header.number;
+    if last.justification.is_none() {
+        let (block, _) =
+            phase_timing::measure(Phase::Fetch, get_block_at(api, Some(number))).await?;
+        let justification = block
+            .justifications
+            .and_then(|justifications| justifications.into_justification(GRANDPA_ENGINE_ID))
+            .ok_or_else(|| {
+                anyhow!("No justification for block {number} changing the authority set")
+            })?;
+        *last = HeaderToSync::new(last.header.clone(), Some(justification))
+            .map_err(|err| anyhow!("Invalid justification of block {number}: {err}"))?;
+    }
+    let change = phase_timing::measure(
+        Phase::Fetch,
+        get_authority_with_proof_at(api, &last.header),
+    )
+    .await?;
+    info!(
+        "Authority set changes to {} at block {number}, cutting the headers there",
+        change.authority_set.id
+    );
+    Ok((headers, Some(change)))
 }
 
 async fn sync_headers(
     pr: &PrClient,
     api: &RelaychainApi,
+    finality_stream: Option<&FinalityStream>,
     from: BlockNumber,
 ) -> Result<()> {
-    let headers = get_headers(api, from).await?;
+    let headers = match finality_stream.and_then(|stream| stream.take_headers(from)) {
+        Some(headers) => headers,
+        None => get_headers(api, from).await?,
+    };
+    let (headers, authority_set_change) = cut_at_authority_set_change(api, headers).await?;
+    phase_timing::measure_sync(Phase::Decode, || {
+        blocks::validate_headers_to_sync(&headers, authority_set_change.as_ref())
+    })
+    .map_err(|err| anyhow!("Invalid headers to sync: {err}"))?;
 
     info!("sending a batch of {} headers (last: {})", headers.len(), headers.last().unwrap().header.number);
-    let relay_synced_to = req_sync_header(pr, headers).await?;
+    let relay_synced_to = req_sync_header(pr, headers, authority_set_change).await?;
     info!("  ..sync_header: {:?}", relay_synced_to);
 
     Ok(())
 }
 
+/// The default number of parachain headers fetched from the node concurrently.
+pub const DEFAULT_PARA_HEADER_CONCURRENCY: usize = 16;
+
+/// Fetches the header of parachain block `b`, None if the node doesn't have it yet.
+async fn fetch_parachain_header(
+    para_api: &ParachainApi,
+    b: BlockNumber,
+) -> Result<Option<Header>> {
+    info!("fetching parachain header {}", b);
+    let num = subxt::rpc::types::BlockNumber::from(NumberOrHex::Number(b.into()));
+    let hash = para_api.rpc().block_hash(Some(num)).await?;
+    let hash = match hash {
+        Some(hash) => hash,
+        None => {
+            info!("Hash not found for block {}, fetch it next turn", b);
+            return Ok(None);
+        }
+    };
+    let header = para_api
+        .rpc()
+        .header(Some(hash))
+        .await?
+        .ok_or(Error::BlockNotFound)?;
+    Ok(Some(header.convert_to()))
+}
+
+/// Gets the parachain headers of `from..=to`, from the cache if possible, otherwise from the node
+/// with up to `concurrency` headers in flight. The headers are returned in order, stopping before
+/// the first block the node doesn't have yet.
 pub async fn get_parachain_headers(
     para_api: &ParachainApi,
-    cache: Option<&CacheClient>,
+    cache: Option<&CacheClient>,
+    from: BlockNumber,
+    to: BlockNumber,
+    concurrency: usize,
+) -> Result<Vec<Header>> {
+    let node = NodeSource::new(para_api.clone()).with_concurrency(concurrency);
+    BlockSources::with_cache(cache.cloned(), node)
+        .parachain_headers(from, to)
+        .await
+}
+
+/// Returns the hash of the parachain head finalized at the relaychain block `relay_number`.
+async fn finalized_parachain_head_hash(
+    api: &RelaychainApi,
+    para_api: &ParachainApi,
+    relay_number: BlockNumber,
+) -> Result<Option<Hash>> {
+    let relay_hash = get_header_hash(api, Some(relay_number)).await?;
+    let para_id = para_api.get_paraid(None).await?;
+    let raw_header = api
+        .rpc()
+        .storage(&api.paras_heads_key(para_id)?, Some(relay_hash))
+        .await?;
+    let Some(raw_header) = raw_header else {
+        return Ok(None);
+    };
+    let header = chain_client::decode_parachain_header(raw_header.0)?;
+    Ok(Some(header.hash()))
+}
+
+/// Checks the parachain headers link up by their parent hashes to the finalized head, returns
+/// why not if they don't.
+///
+/// The headers synced already are finalized, as pRuntime checks the headers against the finalized
+/// head proof, so the headers linking up to the finalized head also extend what was synced.
+pub fn check_parachain_headers(headers: &[Header], finalized_hash: Hash) -> Result<(), String> {
+    for pair in headers.windows(2) {
+        if pair[1].parent_hash != pair[0].hash() {
+            return Err(format!(
+                "header {} is not the parent of header {}",
+                pair[0].number, pair[1].number
+            ));
+        }
+    }
+    match headers.last() {
+        Some(last) if last.hash() != finalized_hash => Err(format!(
+            "header {} is {:?}, expected the finalized {finalized_hash:?}",
+            last.number,
+            last.hash()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Fetches the parachain headers from `from` up to the finalized head by their parent hashes, so
+/// that only the finalized chain is followed whatever the node serves by block number.
+async fn fetch_finalized_parachain_headers(
+    para_api: &ParachainApi,
     from: BlockNumber,
-    to: BlockNumber,
+    finalized_hash: Hash,
 ) -> Result<Vec<Header>> {
-    let mut para_headers = if let Some(cache) = cache {
-        let count = to - from + 1;
-        cache
-            .get_parachain_headers(from, count)
-            .await
-            .unwrap_or_default()
-    } else {
-        vec![]
-    };
-    if para_headers.is_empty() {
-        info!("parachain headers not found in cache");
-        for b in from..=to {
-            info!("fetching parachain header {}", b);
-            let num = subxt::rpc::types::BlockNumber::from(NumberOrHex::Number(b.into()));
-            let hash = para_api.rpc().block_hash(Some(num)).await?;
-            let hash = match hash {
-                Some(hash) => hash,
-                None => {
-                    info!("Hash not found for block {}, fetch it next turn", b);
-                    return Ok(para_headers);
-                }
-            };
-            let header = para_api
-                .rpc()
-                .header(Some(hash))
-                .await?
-                .ok_or(Error::BlockNotFound)?;
-            para_headers.push(header.convert_to());
+    let mut headers = vec![];
+    let mut hash = finalized_hash;
+    loop {
+        let header: Header = para_api
+            .rpc()
+            .header(Some(hash))
+            .await?
+            .ok_or(Error::BlockNotFound)?
+            .convert_to();
+        let number = header.number;
+        hash = header.parent_hash;
+        headers.push(header);
+        if number <= from {
+            break;
         }
-    } else {
-        info!("Got {} parachain headers from cache", para_headers.len());
     }
-    Ok(para_headers)
-
+    headers.reverse();
+    Ok(headers)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn sync_parachain_header(
     pr: &PrClient,
+    api: &RelaychainApi,
     para_api: &ParachainApi,
-    cache: Option<&CacheClient>,
+    sources: &BlockSources,
+    relay_number: BlockNumber,
     para_fin_block_number: BlockNumber,
     next_headernum: BlockNumber,
     header_proof: Vec<Vec<u8>>,
@@ -675,10 +1307,37 @@ async fn sync_parachain_header(
     if next_headernum > para_fin_block_number {
         return Ok(next_headernum - 1);
     }
-    let para_headers = get_parachain_headers(para_api, cache, next_headernum, para_fin_block_number).await?;
+    let mut para_headers = phase_timing::measure(
+        Phase::Fetch,
+        sources.parachain_headers(next_headernum, para_fin_block_number),
+    )
+    .await?;
     if para_headers.is_empty() {
         return Ok(next_headernum - 1)
     }
+    // A partial batch stops before the finalized head, there is nothing to check it against.
+    let complete = para_headers.last().map(|h| h.number) == Some(para_fin_block_number);
+    if complete {
+        let finalized_hash = phase_timing::measure(
+            Phase::Fetch,
+            finalized_parachain_head_hash(api, para_api, relay_number),
+        )
+        .await?
+        .ok_or_else(|| anyhow!("No parachain head at relaychain block {relay_number}"))?;
+        let check = || check_parachain_headers(&para_headers, finalized_hash);
+        if let Err(reason) = phase_timing::measure_sync(Phase::Decode, check) {
+            warn!("Parachain headers off the finalized chain ({reason}), the node might have served a reorged view, refetching by the parent hashes");
+            para_headers = phase_timing::measure(
+                Phase::Fetch,
+                fetch_finalized_parachain_headers(para_api, next_headernum, finalized_hash),
+            )
+            .await?;
+            let check = || check_parachain_headers(&para_headers, finalized_hash);
+            if let Err(reason) = phase_timing::measure_sync(Phase::Decode, check) {
+                anyhow::bail!("Failed to fetch the finalized parachain headers: {reason}");
+            }
+        }
+    }
     let r = req_sync_para_header(pr, para_headers, header_proof).await?;
     info!("..req_sync_para_header: {:?}", r);
     Ok(r.synced_to)
@@ -704,6 +1363,88 @@ async fn resolve_start_header(
     Ok((number - 1) as BlockNumber)
 }
 
+/// Resolves the starting block header among the relaychain genesis blocks allowed by
+/// `PhalaRegistry.RelaychainGenesisBlockHashAllowList`, as a worker initialized at any other
+/// block would fail to register.
+///
+/// The specified `start_header` is only checked against the allowlist. Otherwise the latest
+/// allowed block the headers cache has the genesis info of is picked, or the latest allowed block
+/// if there is none of them in the cache.
+async fn resolve_allowed_start_header(
+    api: &RelaychainApi,
+    para_api: &ParachainApi,
+    cache: Option<&CacheClient>,
+    start_header: Option<BlockNumber>,
+) -> Result<BlockNumber> {
+    let allowlist = para_api.relaychain_genesis_allowlist().await?;
+    if allowlist.is_empty() {
+        anyhow::bail!("No relaychain genesis block is allowed on chain");
+    }
+    if let Some(start_header) = start_header {
+        let hash = api
+            .rpc()
+            .block_hash(Some(start_header.into()))
+            .await?
+            .ok_or_else(|| anyhow!("Relaychain block {start_header} not found"))?;
+        if !allowlist.contains(&hash) {
+            anyhow::bail!(
+                "Relaychain block {start_header} ({hash:?}) is not an allowed genesis block"
+            );
+        }
+        return Ok(start_header);
+    }
+    let mut allowed = vec![];
+    for hash in &allowlist {
+        match api.rpc().header(Some(*hash)).await? {
+            Some(header) => allowed.push(header.number),
+            None => warn!("Allowed genesis block {hash:?} not found on the relaychain"),
+        }
+    }
+    let cached = match cache {
+        Some(cache) => cache.get_genesis_blocks().await.unwrap_or_else(|err| {
+            warn!("Failed to get the genesis blocks of the headers cache: {err:?}");
+            vec![]
+        }),
+        None => vec![],
+    };
+    let in_cache = allowed.iter().filter(|n| cached.contains(*n)).max();
+    match in_cache.or_else(|| allowed.iter().max()) {
+        Some(&number) => {
+            info!("Picked allowed genesis block {number} among {allowed:?}");
+            Ok(number)
+        }
+        None => anyhow::bail!("None of the allowed genesis blocks found on the relaychain"),
+    }
+}
+
+/// Fetches the genesis storage from the headers cache and verifies it against the on-chain
+/// genesis state root. Returns None if it is unavailable or invalid.
+async fn fetch_genesis_storage_from_cache(
+    cache: &CacheClient,
+    para_api: &ParachainApi,
+) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let genesis_state = match cache.get_genesis_state().await {
+        Ok(genesis_state) => genesis_state,
+        Err(err) => {
+            info!("Genesis state not available in headers cache: {err:?}");
+            return None;
+        }
+    };
+    match chain_client::verify_genesis_storage(para_api, &genesis_state).await {
+        Ok(()) => {
+            info!(
+                "Loaded genesis state from headers cache, {} entries",
+                genesis_state.len()
+            );
+            Some(genesis_state)
+        }
+        Err(err) => {
+            warn!("Invalid genesis state from headers cache, fallback to RPC: {err:?}");
+            None
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn init_runtime(
     cache: &Option<CacheClient>,
@@ -711,8 +1452,7 @@ async fn init_runtime(
     para_api: &ParachainApi,
     pr: &PrClient,
     attestation_provider: Option<AttestationProvider>,
-    use_dev_key: bool,
-    inject_key: &str,
+    worker_key: Option<WorkerKey>,
     operator: Option<AccountId32>,
     is_parachain: bool,
     start_header: BlockNumber,
@@ -726,19 +1466,18 @@ async fn init_runtime(
         Some(genesis_info) => genesis_info,
         None => fetch_genesis_info(api, start_header).await?,
     };
-    let genesis_state = chain_client::fetch_genesis_storage(para_api).await?;
-    let mut debug_set_key = None;
-    if !inject_key.is_empty() {
-        if inject_key.len() != 64 {
-            panic!("inject-key must be 32 bytes hex");
-        } else {
-            info!("Inject key {}", inject_key);
-        }
-        debug_set_key = Some(hex::decode(inject_key).expect("Invalid dev key"));
-    } else if use_dev_key {
-        info!("Inject key {}", DEV_KEY);
-        debug_set_key = Some(hex::decode(DEV_KEY).expect("Invalid dev key"));
+    let genesis_state = match cache {
+        Some(cache) => fetch_genesis_storage_from_cache(cache, para_api).await,
+        None => None,
+    };
+    let genesis_state = match genesis_state {
+        Some(genesis_state) => genesis_state,
+        None => chain_client::fetch_genesis_storage(para_api).await?,
+    };
+    if let Some(key) = &worker_key {
+        info!("Injecting the key of worker 0x{}", key.public());
     }
+    let debug_set_key = worker_key.map(|key| key.to_vec());
 
     let resp = pr
         .init_runtime(prpc::InitRuntimeRequest::new(
@@ -756,8 +1495,7 @@ async fn init_runtime(
 
 pub async fn attestation_to_report(
     attestation: prpc::Attestation,
-    pccs_url: &str,
-    pccs_timeout_secs: u64,
+    collateral_fetcher: &CollateralFetcher,
 ) -> Result<Vec<u8>> {
     info!(
         "Processing attestation report, provider={}",
@@ -777,11 +1515,10 @@ pub async fn attestation_to_report(
                 collateral: None,
             })) = report
             {
-                if pccs_url.is_empty() {
+                if collateral_fetcher.is_empty() {
                     anyhow::bail!("pccs_url is required when using dcap");
                 }
-                let timeout = Duration::from_secs(pccs_timeout_secs);
-                let collateral = get_collateral(pccs_url, &quote, timeout).await?;
+                let collateral = collateral_fetcher.get_collateral(&quote).await?;
                 let collateral = Some(Collateral::SgxV30(collateral));
                 Some(AttestationReport::SgxDcap { quote, collateral }).encode()
             } else {
@@ -792,17 +1529,86 @@ pub async fn attestation_to_report(
     Ok(report)
 }
 
+/// Extracts the pRuntime hash the chain checks against `PhalaRegistry.PRuntimeAllowList`, i.e.
+/// `mr_enclave ++ isv_prod_id ++ isv_svn ++ mr_signer` of the enclave. None if the attestation
+/// carries no enclave measurement.
+fn pruntime_hash(attestation: &prpc::Attestation) -> Result<Option<Vec<u8>>> {
+    let ias_hash = |ra_report: &[u8]| -> Result<Option<Vec<u8>>> {
+        let report: sgx_attestation::ias::RaReport =
+            serde_json::from_slice(ra_report).context("Failed to parse the IAS report")?;
+        let quote = report
+            .decode_quote()
+            .map_err(|err| anyhow!("Failed to decode the IAS quote: {err:?}"))?;
+        let mut hash = quote.mr_enclave.to_vec();
+        hash.extend_from_slice(&quote.isv_prod_id);
+        hash.extend_from_slice(&quote.isv_svn);
+        hash.extend_from_slice(&quote.mr_signer);
+        Ok(Some(hash))
+    };
+    if let Some(payload) = &attestation.payload {
+        return ias_hash(payload.report.as_bytes());
+    }
+    let report = Option::<AttestationReport>::decode(&mut &attestation.encoded_report[..])
+        .context("Failed to decode the attestation report")?;
+    match report {
+        Some(AttestationReport::SgxIas { ra_report, .. }) => ias_hash(&ra_report),
+        Some(AttestationReport::SgxDcap { quote, .. }) => {
+            let quote = sgx_attestation::dcap::Quote::decode(&mut &quote[..])
+                .context("Failed to decode the DCAP quote")?;
+            let mut hash = quote.report.mr_enclave.to_vec();
+            hash.extend_from_slice(&quote.report.isv_prod_id.to_be_bytes());
+            hash.extend_from_slice(&quote.report.isv_svn.to_be_bytes());
+            hash.extend_from_slice(&quote.report.mr_signer);
+            Ok(Some(hash))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Checks the pRuntime build is allowed on chain, so that a doomed register_worker is not
+/// submitted.
+async fn ensure_pruntime_allowed(
+    para_api: &ParachainApi,
+    attestation: &prpc::Attestation,
+) -> Result<()> {
+    if !para_api.verify_pruntime().unwrap_or(true) {
+        return Ok(());
+    }
+    let Some(hash) = pruntime_hash(attestation)? else {
+        return Ok(());
+    };
+    let allowlist = para_api.pruntime_allowlist().await?;
+    if !allowlist.contains(&hash) {
+        let allowed: Vec<_> = allowlist
+            .iter()
+            .map(|hash| format!("0x{}", hex::encode(hash)))
+            .collect();
+        anyhow::bail!(
+            "pRuntime build not allowlisted: 0x{}, expected one of {allowed:?}",
+            hex::encode(&hash)
+        );
+    }
+    Ok(())
+}
+
+/// How long to wait for the registration to be confirmed by the events in its block.
+const REGISTER_CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+
 async fn register_worker(
     para_api: &ParachainApi,
     encoded_runtime_info: Vec<u8>,
     attestation: prpc::Attestation,
     signer: &mut SrSigner,
+    era_cache: &mut EraCache,
     args: &Args,
 ) -> Result<()> {
+    ensure_pruntime_allowed(para_api, &attestation).await?;
     chain_client::update_signer_nonce(para_api, signer).await?;
-    let params = mk_params(para_api, args.longevity, args.tip).await?;
+    let params = era_cache.mk_params(para_api, args.tip).await?;
     let v2 = attestation.payload.is_none();
-    let attestation = attestation_to_report(attestation, &args.pccs_url, args.pccs_timeout).await?;
+    let collateral_fetcher = CollateralFetcher::new(&args.pccs_url, args.pccs_timeout)
+        .with_cache(&args.pccs_cache_dir, Duration::from_secs(args.pccs_cache_ttl));
+    let attestation = attestation_to_report(attestation, &collateral_fetcher).await?;
     let tx = phaxt::dynamic::tx::register_worker(encoded_runtime_info, attestation, v2);
 
     let encoded_call_data = tx
@@ -810,16 +1616,38 @@ async fn register_worker(
         .expect("should encoded");
     debug!("register_worker call: 0x{}", hex::encode(encoded_call_data));
 
-    let ret = para_api
-        .tx()
-        .create_signed_with_nonce(&tx, &signer.signer, signer.nonce(), params)?
-        .submit_and_watch()
-        .await;
-    if ret.is_err() {
-        error!("FailedToCallRegisterWorker: {:?}", ret);
-        return Err(anyhow!(Error::FailedToCallRegisterWorker));
+    let extrinsic = signer.create_signed(para_api, &tx, params).await?;
+    let chain_info = chain_info_or_default(para_api).await;
+    match extrinsic.partial_fee_estimate().await {
+        Ok(fee) => info!(
+            "register_worker estimated fee: {}, tip: {}",
+            chain_info.format_balance(fee),
+            chain_info.format_balance(args.tip)
+        ),
+        Err(err) => warn!("Failed to estimate the fee of register_worker: {err:?}"),
     }
+    let progress = match extrinsic.submit_and_watch().await {
+        Ok(progress) => progress,
+        Err(err) => {
+            error!("FailedToCallRegisterWorker: {:?}", err);
+            return Err(anyhow!(Error::FailedToCallRegisterWorker));
+        }
+    };
     signer.increment_nonce();
+    tokio::spawn(async move {
+        let fut = async {
+            let events = progress.wait_for_in_block().await?.wait_for_success().await?;
+            let added = filter_events::<WorkerAdded>(events.iter())?;
+            let updated = filter_events::<WorkerUpdated>(events.iter())?;
+            Ok::<_, anyhow::Error>(added.len() + updated.len())
+        };
+        match tokio::time::timeout(REGISTER_CONFIRM_TIMEOUT, fut).await {
+            Ok(Ok(0)) => warn!("register_worker included without registering the worker"),
+            Ok(Ok(_)) => info!("Worker registration confirmed on chain"),
+            Ok(Err(err)) => error!("register_worker failed: {err:?}"),
+            Err(_) => warn!("register_worker not included in {REGISTER_CONFIRM_TIMEOUT:?}"),
+        }
+    });
     Ok(())
 }
 
@@ -827,6 +1655,7 @@ async fn try_register_worker(
     pr: &PrClient,
     paraclient: &ParachainApi,
     signer: &mut SrSigner,
+    era_cache: &mut EraCache,
     operator: Option<AccountId32>,
     args: &Args,
 ) -> Result<bool> {
@@ -840,6 +1669,7 @@ async fn try_register_worker(
             info.encoded_runtime_info,
             attestation,
             signer,
+            era_cache,
             args,
         )
         .await?;
@@ -849,7 +1679,26 @@ async fn try_register_worker(
     }
 }
 
-async fn try_load_chain_state(pr: &PrClient, para_api: &ParachainApi, args: &Args) -> Result<()> {
+/// Whether the worker is registered on chain, with its endpoint bound unless `--no-bind`.
+async fn registration_completed(
+    para_api: &ParachainApi,
+    info: &PhactoryInfo,
+    args: &Args,
+) -> Result<bool> {
+    let Some(pubkey) = inspect::worker_pubkey(info)? else {
+        return Ok(false);
+    };
+    if para_api.worker_added_at(pubkey.as_ref()).await?.is_none() {
+        return Ok(false);
+    }
+    Ok(args.no_bind || !para_api.get_endpoints(&pubkey).await?.is_empty())
+}
+
+async fn try_load_chain_state(
+    pr: &PrClient,
+    para_api: &ParachainApi,
+    prefer_genesis_at_block: Option<BlockNumber>,
+) -> Result<()> {
     let info = pr.get_info(()).await?;
     info!("info: {info:#?}");
     if !info.can_load_chain_state {
@@ -864,7 +1713,7 @@ async fn try_load_chain_state(pr: &PrClient, para_api: &ParachainApi, args: &Arg
     let (block_number, state) = chain_client::search_suitable_genesis_for_worker(
         para_api,
         &pubkey,
-        args.prefer_genesis_at_block,
+        prefer_genesis_at_block,
     )
     .await
     .context("Failed to search suitable genesis state for worker")?;
@@ -873,8 +1722,6 @@ async fn try_load_chain_state(pr: &PrClient, para_api: &ParachainApi, args: &Arg
     Ok(())
 }
 
-const DEV_KEY: &str = "0000000000000000000000000000000000000000000000000000000000000001";
-
 async fn wait_until_synced(client: &phaxt::RpcClient) -> Result<()> {
     loop {
         let state = client.extra_rpc().system_sync_state().await?;
@@ -893,8 +1740,7 @@ async fn wait_until_synced(client: &phaxt::RpcClient) -> Result<()> {
 
 async fn get_sync_operation(
     relay_api: &RelaychainApi,
-    para_api: &ParachainApi,
-    cache_client: &Option<CacheClient>,
+    sources: &BlockSources,
     info: &PhactoryInfo,
     is_parachain: bool,
 ) -> Result<SyncOperation> {
@@ -908,23 +1754,19 @@ async fn get_sync_operation(
     }
 
     if is_parachain {
-        let (para_number, proof) = get_parachain_header_from_relaychain_at(
-            relay_api,
-            para_api,
-            cache_client,
-            info.headernum - 1
-        ).await?;
+        let relay_number = info.headernum - 1;
+        let (para_number, proof) = sources
+            .parachain_header_at(relay_number)
+            .await?
+            .ok_or_else(|| anyhow!("No parachain header was found at {}", relay_number))?;
 
         if para_number > 0 && info.para_headernum <= para_number {
             return Ok(SyncOperation::ParachainHeader((para_number, proof)));
         }
     }
 
-    if let Some(cache) = cache_client {
-        let cached_headers = cache.get_headers(info.headernum).await;
-        if let Ok(cached_headers) = cached_headers {
-            return Ok(SyncOperation::CachedRelaychainHeader(cached_headers));
-        }
+    if let Some(cached_headers) = sources.headers_to_next_set_change(info.headernum).await {
+        return Ok(SyncOperation::CachedRelaychainHeader(cached_headers));
     }
 
     let latest_header = get_header_at(relay_api, None).await?.0;
@@ -940,6 +1782,117 @@ async fn get_sync_operation(
     }
 }
 
+/// Connects to a node that is redialed if the connection is lost, so that a restart of the node
+/// doesn't abort the bridge.
+async fn connect_node(uri: &str, name: &'static str) -> Result<ChainApi> {
+    let (api, mut state) = phaxt::connect_reconnecting(uri).await?;
+    tokio::spawn(async move {
+        while state.changed().await.is_ok() {
+            let current = *state.borrow();
+            match current {
+                ConnectionState::Connected => info!("Reconnected to {name} node"),
+                ConnectionState::Reconnecting => {
+                    warn!("Lost connection to {name} node, reconnecting")
+                }
+                ConnectionState::Disconnected => error!("Failed to reconnect to {name} node"),
+            }
+        }
+    });
+    Ok(api)
+}
+
+/// The TLS settings of the connections to the pRuntime endpoints.
+fn pruntime_tls_config(args: &Args) -> Result<TlsConfig> {
+    fn read(path: &str) -> Result<Vec<u8>> {
+        std::fs::read(path).with_context(|| format!("Failed to read {path}"))
+    }
+    let mut tls = TlsConfig::default();
+    if let Some(path) = &args.pruntime_tls_ca {
+        tls.ca_cert = Some(read(path)?);
+    }
+    if let (Some(cert), Some(key)) = (&args.pruntime_client_cert, &args.pruntime_client_key) {
+        let mut identity = read(key)?;
+        identity.push(b'\n');
+        identity.extend(read(cert)?);
+        tls.client_identity = Some(identity);
+    }
+    if args.pruntime_ra_tls {
+        let collateral_fetcher = CollateralFetcher::new(&args.pccs_url, args.pccs_timeout)
+            .with_cache(&args.pccs_cache_dir, Duration::from_secs(args.pccs_cache_ttl));
+        let allowed_hashes = args
+            .pruntime_allowed_hash
+            .iter()
+            .map(|hash| {
+                hex::decode(hash.trim_start_matches("0x"))
+                    .with_context(|| format!("Invalid pRuntime hash {hash}"))
+            })
+            .collect::<Result<_>>()?;
+        tls.cert_verifier = Some(Arc::new(ra_tls::RaTlsVerifier::new(
+            collateral_fetcher,
+            allowed_hashes,
+        )?));
+    }
+    Ok(tls)
+}
+
+/// Feeds the requests recorded in `file` into the pRuntime at `--pruntime-endpoint`.
+async fn replay_requests(args: &Args, file: &str, keep_going: bool) -> Result<()> {
+    let pr_tls = pruntime_tls_config(args)?;
+    let client = RpcRequest::with_tls(args.pruntime_endpoint.clone(), &pr_tls)?.disable_log();
+    let (replayed, failed) = request_log::replay(file, &client, keep_going).await?;
+    info!("Replayed {replayed} requests from {file}, {failed} failed");
+    let info = PhactoryApiClient::new(client).get_info(()).await?;
+    info!(
+        "pRuntime synced to header {}, para header {}, block {}",
+        info.headernum, info.para_headernum, info.blocknum
+    );
+    Ok(())
+}
+
+/// Returns the client of the pRuntime at `endpoint`, recording the requests to `request_log` if
+/// set.
+fn recording_pruntime_client(
+    endpoint: &str,
+    tls: &TlsConfig,
+    request_log: &Option<Arc<RequestLog>>,
+) -> Result<PrClient> {
+    let client = RpcRequest::with_tls(endpoint.into(), tls)?;
+    Ok(PhactoryApiClient::new(match request_log {
+        Some(log) => client.with_recorder(log.clone()),
+        None => client,
+    }))
+}
+
+/// Returns the client to sync through `endpoint` if given.
+///
+/// The endpoint must serve the same worker as `pr`, otherwise the headers and the blocks would be
+/// fed to different pRuntimes. The requests are recorded to `request_log` as the ones to `pr`.
+async fn split_pruntime_client(
+    pr: &PrClient,
+    endpoint: &Option<String>,
+    name: &str,
+    tls: &TlsConfig,
+    request_log: &Option<Arc<RequestLog>>,
+) -> Result<Option<PrClient>> {
+    let Some(endpoint) = endpoint else {
+        return Ok(None);
+    };
+    let split_pr = recording_pruntime_client(endpoint, tls, request_log)?;
+    let expected = pr.get_info(()).await?.public_key;
+    let actual = split_pr
+        .get_info(())
+        .await
+        .with_context(|| format!("Failed to get info from the {name} pRuntime endpoint"))?
+        .public_key;
+    if expected != actual {
+        return Err(anyhow!(
+            "The {name} pRuntime endpoint {endpoint} serves worker {actual:?}, expected {expected:?}"
+        ));
+    }
+    info!("Syncing {name}s through pRuntime endpoint {endpoint}");
+    Ok(Some(split_pr))
+}
+
 async fn bridge(
     args: &Args,
     flags: &mut RunningFlags,
@@ -947,7 +1900,7 @@ async fn bridge(
 ) -> Result<()> {
     // Connect to substrate
 
-    let api: RelaychainApi = subxt_connect(&args.relaychain_ws_endpoint).await?;
+    let api: RelaychainApi = connect_node(&args.relaychain_ws_endpoint, "relaychain").await?;
     info!(
         "Connected to relaychain at: {}",
         args.relaychain_ws_endpoint
@@ -958,8 +1911,15 @@ async fn bridge(
     } else {
         &args.relaychain_ws_endpoint
     };
-    let para_api: ParachainApi = subxt_connect(para_uri).await?;
+    let para_api: ParachainApi = connect_node(para_uri, "parachain").await?;
     info!("Connected to parachain node at: {para_uri}");
+    let mut runtime_watcher = runtime_compat::RuntimeWatcher::new(&para_api);
+    let finality_stream = args
+        .finality_stream
+        .then(|| FinalityStream::subscribe(api.clone()));
+    let chain_info = chain_info_or_default(&para_api).await;
+    info!("Parachain info: {chain_info:?}");
+    check_tx_params(args, &chain_info);
 
     if !args.no_wait {
         // Don't start our worker until the substrate node is synced
@@ -971,50 +1931,114 @@ async fn bridge(
     }
 
     let cache_client = if !args.headers_cache_uri.is_empty() {
-        Some(CacheClient::new(&args.headers_cache_uri))
+        let client = CacheClient::new(&args.headers_cache_uri);
+        Some(client.with_auth(args.headers_cache_auth.clone()))
     } else {
         None
     };
+    let node = NodeSource::new(para_api.clone())
+        .with_relaychain(api.clone())
+        .with_concurrency(args.para_header_concurrency);
+    let sources = BlockSources::with_cache(cache_client.clone(), node);
 
     // Other initialization
-    let pr = pruntime_client::new_pruntime_client(args.pruntime_endpoint.clone());
-    let pair = <sr25519::Pair as Pair>::from_string(&args.mnemonic, None)
-        .expect("Bad privkey derive path");
-    let mut signer = SrSigner::new(pair);
-    let nc = NotifyClient::new(&args.notify_endpoint);
+    let pr_tls = pruntime_tls_config(args)?;
+    let pr = recording_pruntime_client(&args.pruntime_endpoint, &pr_tls, &flags.request_log)?;
+    // The latest reloaded ones if any, to keep them over the restarts.
+    let credentials = flags.credentials.borrow_and_update().clone();
+    let info = pr.get_info(()).await?;
+    let mut signer = credentials
+        .signer(args, info.public_key.as_deref())
+        .await?;
+    let mut era_cache =
+        EraCache::new(args.longevity, args.era_pin_blocks, chain_info.block_time);
+    let submitted_msgs = SubmittedMessages::load(
+        &args.msg_state_file,
+        msg_state_ttl(args.longevity, chain_info.block_time),
+    );
+    let nc = &flags.notify;
+    let mut heartbeat = heartbeat::HeartbeatSender::new(if args.notify_endpoint.is_empty() {
+        Duration::ZERO
+    } else {
+        Duration::from_secs(args.heartbeat_interval)
+    });
+    let mut balance_monitor = balance::BalanceMonitor::new(
+        args.min_balance_warn,
+        args.min_balance_abort,
+        Duration::from_secs(args.balance_check_interval),
+    )
+    .with_metrics_file(&args.metrics_file);
+    balance_monitor.check(&para_api, &signer, &chain_info).await?;
+    let mut storage_verifier = args
+        .verify_storage_changes
+        .then(StorageChangesVerifier::default);
     let mut pruntime_initialized = false;
     let mut pruntime_new_init = false;
     let mut initial_sync_finished = false;
 
     // Try to initialize pRuntime and register on-chain
-    let info = pr.get_info(()).await?;
-    let operator = match args.operator.clone() {
-        None => None,
-        Some(operator) => {
-            let parsed_operator = AccountId32::from_str(&operator)
-                .map_err(|e| anyhow!("Failed to parse operator address: {}", e))?;
-            Some(parsed_operator)
-        }
-    };
+    let mut operator = credentials.operator()?;
     if !args.no_init {
         if !info.initialized {
             info!("pRuntime not initialized. Requesting init...");
-            let start_header =
-                resolve_start_header(&para_api, args.parachain, args.start_header).await?;
-            info!("Resolved start header at {}", start_header);
-            let runtime_info = init_runtime(
-                &cache_client,
-                &api,
-                &para_api,
-                &pr,
-                args.attestation_provider.into(),
-                args.use_dev_key,
-                &args.inject_key,
-                operator.clone(),
-                args.parachain,
-                start_header,
-            )
-            .await?;
+            let start_header = if args.start_header_from_chain {
+                resolve_allowed_start_header(
+                    &api,
+                    &para_api,
+                    cache_client.as_ref(),
+                    args.start_header,
+                )
+                .await?
+            } else {
+                resolve_start_header(&para_api, args.parachain, args.start_header).await?
+            };
+            let warp_start = if args.warp_sync && args.start_header.is_none() {
+                warp_sync::find_start_header(
+                    &api,
+                    args.parachain,
+                    info.can_load_chain_state,
+                    start_header,
+                )
+                .await
+            } else {
+                None
+            };
+            let mut runtime_info = None;
+            let mut warped = false;
+            for start_header in warp_start.into_iter().chain(std::iter::once(start_header)) {
+                info!("Resolved start header at {}", start_header);
+                let result = init_runtime(
+                    &cache_client,
+                    &api,
+                    &para_api,
+                    &pr,
+                    args.attestation_provider.into(),
+                    args.worker_key()?,
+                    operator.clone(),
+                    args.parachain,
+                    start_header,
+                )
+                .await;
+                match result {
+                    Ok(resp) => {
+                        runtime_info = Some(resp);
+                        warped = warp_start == Some(start_header);
+                        break;
+                    }
+                    Err(err) if warp_start == Some(start_header) => {
+                        warn!("Failed to init pRuntime for warp sync, fallback to full header sync: {err:?}");
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            let runtime_info = runtime_info.expect("the full sync start header is always tried");
+            if let Some(relay_number) = warp_start.filter(|_| warped) {
+                let para_number = warp_sync::para_block_at(&api, &para_api, relay_number).await?;
+                info!("Loading the parachain state at {para_number} for warp sync");
+                try_load_chain_state(&pr, &para_api, Some(para_number))
+                    .await
+                    .context("Failed to load the parachain state for warp sync")?;
+            }
             // STATUS: pruntime_initialized = true
             // STATUS: pruntime_new_init = true
             pruntime_initialized = true;
@@ -1025,10 +2049,21 @@ async fn bridge(
                 pruntime_initialized,
                 pruntime_new_init,
                 initial_sync_finished,
+                sync_progress: None,
+                balance: balance_monitor.status(),
+                stall: None,
+                timing: None,
+                endpoint_drift: None,
+                sources: Vec::new(),
             })
             .await
             .ok();
             info!("runtime_info: {:?}", runtime_info);
+            if credentials.keyed_by_worker() {
+                // The key of the worker may be in the keystore under the public key it just got.
+                let pubkey = pr.get_info(()).await?.public_key;
+                signer = credentials.signer(args, pubkey.as_deref()).await?;
+            }
         } else {
             info!("pRuntime already initialized.");
             // STATUS: pruntime_initialized = true
@@ -1041,26 +2076,41 @@ async fn bridge(
                 pruntime_initialized,
                 pruntime_new_init,
                 initial_sync_finished,
+                sync_progress: None,
+                balance: balance_monitor.status(),
+                stall: None,
+                timing: None,
+                endpoint_drift: None,
+                sources: Vec::new(),
             })
             .await
             .ok();
         }
 
         if args.fast_sync {
-            try_load_chain_state(&pr, &para_api, args).await?;
+            try_load_chain_state(&pr, &para_api, args.prefer_genesis_at_block).await?;
         }
     }
 
     if args.no_sync {
         if !args.no_register {
             let registered =
-                try_register_worker(&pr, &para_api, &mut signer, operator, args).await?;
+                try_register_worker(&pr, &para_api, &mut signer, &mut era_cache, operator, args)
+                    .await?;
             flags.worker_registered = registered;
         }
         // Try bind worker endpoint
         if !args.no_bind && info.public_key.is_some() {
             // Here the reason we dont directly report errors when `try_update_worker_endpoint` fails is that we want the endpoint can be registered anytime (e.g. days after the pherry initialization)
-            match endpoint::try_update_worker_endpoint(&pr, &para_api, &mut signer, args).await {
+            match endpoint::try_update_worker_endpoint(
+                &pr,
+                &para_api,
+                &mut signer,
+                &mut era_cache,
+                args,
+            )
+            .await
+            {
                 Ok(registered) => {
                     flags.endpoint_registered = registered;
                 }
@@ -1073,14 +2123,87 @@ async fn bridge(
         return Ok(());
     }
 
+    let header_pr = split_pruntime_client(
+        &pr,
+        &args.pruntime_header_endpoint,
+        "header",
+        &pr_tls,
+        &flags.request_log,
+    )
+    .await?;
+    let header_pr = header_pr.as_ref().unwrap_or(&pr);
+    let block_pr = split_pruntime_client(
+        &pr,
+        &args.pruntime_block_endpoint,
+        "block",
+        &pr_tls,
+        &flags.request_log,
+    )
+    .await?;
+    let block_pr = block_pr.as_ref().unwrap_or(&pr);
+
+    let mut sync_eta = sync_eta::SyncEta::new();
+    let mut phase_stats = PhaseStats::new(args.timing_window);
+    let mut endpoint_monitor = endpoint::EndpointMonitor::new(
+        Duration::from_secs(args.endpoint_probe_interval),
+        Duration::from_secs(args.endpoint_drift_check_interval),
+    );
+    let mut back_pressure = back_pressure::BackPressure::new(
+        args.min_sync_blocks,
+        args.sync_blocks,
+        args.memory_low_watermark,
+        args.memory_high_watermark,
+    );
+    let mut stall_detector = StallDetector::new(Duration::from_secs(args.stall_timeout));
     loop {
+        if let Err(err) = runtime_watcher.check(&para_api).await {
+            warn!("Failed to check parachain runtime upgrade: {err:?}");
+        }
+        if flags.credentials.has_changed().unwrap_or(false) {
+            let credentials = flags.credentials.borrow_and_update().clone();
+            let pubkey = pr.get_info(()).await?.public_key;
+            match (
+                credentials.signer(args, pubkey.as_deref()).await,
+                credentials.operator(),
+            ) {
+                (Ok(new_signer), Ok(new_operator)) => {
+                    if new_operator != operator {
+                        // Registered again at the chain tip to bind the new operator.
+                        flags.worker_registered = false;
+                    }
+                    signer = new_signer;
+                    operator = new_operator;
+                    info!("Switched to the reloaded controller key and operator");
+                }
+                (Err(err), _) | (_, Err(err)) => {
+                    warn!("Failed to switch to the reloaded controller key: {err:?}");
+                }
+            }
+        }
+
         // update the latest pRuntime state
         let info = pr.get_info(()).await?;
         info!("pRuntime get_info response: {:#?}", info);
+        flags.round.observe(&info);
         if info.blocknum >= args.to_block {
             info!("Reached target block: {}", args.to_block);
             return Ok(());
         }
+        if args.until_registered && registration_completed(&para_api, &info, args).await? {
+            info!("Worker registered on chain, exiting");
+            return Ok(());
+        }
+        let sync_progress = match sync_eta.update(&para_api, info.blocknum).await {
+            Ok(progress) => Some(progress),
+            Err(err) => {
+                warn!("Failed to estimate the sync progress: {err:?}");
+                None
+            }
+        };
+        let stalled = stall_detector.observe(&info);
+        if let Some(status) = &stalled {
+            stall::log_diagnosis(status);
+        }
 
         // STATUS: header_synced = info.headernum
         // STATUS: block_synced = info.blocknum
@@ -1090,29 +2213,47 @@ async fn bridge(
             pruntime_initialized,
             pruntime_new_init,
             initial_sync_finished,
+            sync_progress: sync_progress.clone(),
+            balance: balance_monitor.status(),
+            stall: stalled.clone(),
+            timing: phase_stats.report(),
+            endpoint_drift: endpoint_monitor.drift(),
+            sources: sources.quality(),
         })
         .await
         .ok();
+        heartbeat.maybe_send(nc, &signer, &info).await;
+        if let Some(status) = stalled {
+            if args.stall_action != StallAction::Warn {
+                return Err(Stalled(status).into());
+            }
+        }
 
         let sync_operation = get_sync_operation(
             &api,
-            &para_api,
-            &cache_client,
+            &sources,
             &info,
             args.parachain,
         ).await?;
+        match &sync_operation {
+            SyncOperation::ReachedChainTip => stall_detector.reset(),
+            operation => stall_detector.dispatched(operation),
+        }
+        let operation = sync_operation.to_string();
         match sync_operation {
             SyncOperation::RelaychainHeader => {
-                sync_headers(&pr, &api, info.headernum).await?;
+                sync_headers(header_pr, &api, finality_stream.as_ref(), info.headernum).await?;
             },
             SyncOperation::CachedRelaychainHeader(cached_headers) => {
-                sync_with_cached_headers(&pr, cached_headers).await?;
+                sync_with_cached_headers(header_pr, cached_headers).await?;
             },
             SyncOperation::ParachainHeader((para_fin_block_number, proof)) => {
                 sync_parachain_header(
-                    &pr,
+                    header_pr,
+                    &api,
                     &para_api,
-                    cache_client.as_ref(),
+                    &sources,
+                    info.headernum - 1,
                     para_fin_block_number,
                     info.para_headernum,
                     proof,
@@ -1126,16 +2267,27 @@ async fn bridge(
                     info.headernum
                 };
                 batch_sync_storage_changes(
-                    &pr,
+                    block_pr,
                     &para_api,
-                    cache_client.as_ref(),
+                    &sources,
+                    storage_verifier.as_mut(),
+                    &mut flags.fetcher,
                     info.blocknum,
                     next_headernum - 1,
-                    args.sync_blocks,
+                    back_pressure.sync_blocks(info.memory_usage.as_ref()),
+                    if info.can_coalesce_empty_blocks {
+                        back_pressure.coalesced_blocks(args.coalesce_empty_blocks)
+                    } else {
+                        0
+                    },
                 )
                 .await?;
+                if args.prune_cache_after_dispatch {
+                    flags.fetcher.prune();
+                }
             },
             SyncOperation::ReachedChainTip => {
+                balance_monitor.check(&para_api, &signer, &chain_info).await?;
                 if args.load_handover_proof {
                     try_load_handover_proof(&pr, &para_api)
                         .await
@@ -1143,21 +2295,45 @@ async fn bridge(
                 }
                 if !args.no_register && !flags.worker_registered {
                     flags.worker_registered =
-                        try_register_worker(&pr, &para_api, &mut signer, operator.clone(), args)
-                            .await?;
+                        try_register_worker(
+                            &pr,
+                            &para_api,
+                            &mut signer,
+                            &mut era_cache,
+                            operator.clone(),
+                            args,
+                        )
+                        .await?;
                 }
 
                 if !args.no_bind && !flags.endpoint_registered && info.public_key.is_some() {
                     // Here the reason we dont directly report errors when `try_update_worker_endpoint` fails is that we want the endpoint can be registered anytime (e.g. days after the pherry initialization)
-                    match endpoint::try_update_worker_endpoint(&pr, &para_api, &mut signer, args).await
+                    match endpoint::try_update_worker_endpoint(
+                        &pr,
+                        &para_api,
+                        &mut signer,
+                        &mut era_cache,
+                        args,
+                    )
+                    .await
                     {
                         Ok(registered) => {
                             flags.endpoint_registered = registered;
+                            if registered {
+                                endpoint_monitor.rebound();
+                            }
                         }
                         Err(e) => {
                             error!("FailedToCallBindWorkerEndpoint: {:?}", e);
                         }
                     }
+                } else if flags.endpoint_registered {
+                    if endpoint_monitor.check_drift(&pr, &para_api).await {
+                        // Bound again in the next round at the chain tip.
+                        flags.endpoint_registered = false;
+                    } else {
+                        endpoint_monitor.check(&pr, args).await;
+                    }
                 }
 
                 // STATUS: initial_sync_finished = true
@@ -1168,38 +2344,58 @@ async fn bridge(
                     pruntime_initialized,
                     pruntime_new_init,
                     initial_sync_finished,
+                    sync_progress: sync_progress.clone(),
+                    balance: balance_monitor.status(),
+                    stall: None,
+                    timing: phase_stats.report(),
+                    endpoint_drift: endpoint_monitor.drift(),
+                    sources: sources.quality(),
                 })
                 .await
                 .ok();
 
                 // Now we are idle. Let's try to sync the egress messages.
                 if !args.no_msg_submit {
-                    msg_sync::maybe_sync_mq_egress(
+                    let submitted = msg_sync::maybe_sync_mq_egress(
                         &para_api,
                         &pr,
                         &mut signer,
+                        &mut era_cache,
+                        &submitted_msgs,
                         args.tip,
-                        args.longevity,
                         args.max_sync_msgs_per_round,
+                        args.msg_batch_size,
                         err_report.clone(),
                     )
                     .await?;
+                    flags.round.record_messages_submitted(submitted);
+                }
+                if let Some(summary) = flags.round.maybe_finish(&para_api, &signer, &info).await {
+                    nc.notify_round_summary(&summary).await.ok();
                 }
                 flags.restart_failure_count = 0;
+                flags.transient_retries = 0;
                 info!("Waiting for new blocks");
 
                 // Launch key handover if required only when the old pRuntime is up-to-date
                 if args.next_pruntime_endpoint.is_some() {
-                    let next_pr = pruntime_client::new_pruntime_client(
+                    let next_pr = pruntime_client::new_pruntime_client_with_tls(
                         args.next_pruntime_endpoint.clone().unwrap(),
-                    );
+                        &pr_tls,
+                    )?;
                     handover_worker_key(&pr, &next_pr).await?;
                 }
 
-                sleep(Duration::from_millis(args.dev_wait_block_ms)).await;
+                let wait = Duration::from_millis(args.dev_wait_block_ms);
+                match &finality_stream {
+                    // Wake up as soon as a new block gets finalized.
+                    Some(stream) => stream.wait_justification(wait).await,
+                    None => sleep(wait).await,
+                }
                 continue;
             },
         };
+        phase_stats.push(&operation, phase_timing::take());
     }
 }
 
@@ -1209,7 +2405,7 @@ fn preprocess_args(args: &mut Args) {
     }
     if args.dev {
         args.use_dev_key = true;
-        args.mnemonic = String::from("//Alice");
+        args.mnemonic = String::from(DEV_MNEMONIC);
         args.attestation_provider = RaOption::None;
     }
     if args.longevity > 0 {
@@ -1222,13 +2418,69 @@ fn preprocess_args(args: &mut Args) {
     }
 }
 
+/// Returns the chain constants, or the defaults if the node fails to tell, since they only serve
+/// the logs and the sanity checks.
+pub(crate) async fn chain_info_or_default(api: &ChainApi) -> Arc<ChainInfo> {
+    match phaxt::chain_info(api).await {
+        Ok(info) => info,
+        Err(err) => {
+            warn!("Failed to get the chain info, assuming the defaults: {err:?}");
+            Default::default()
+        }
+    }
+}
+
+/// Transactions expiring sooner than this are likely to be dropped before being included.
+const MIN_TX_LIFETIME: Duration = Duration::from_secs(30);
+
+/// Warns about the transaction options that don't make sense on the connected chain.
+fn check_tx_params(args: &Args, chain_info: &ChainInfo) {
+    info!("Transaction tip: {}", chain_info.format_balance(args.tip));
+    let one_token = 10u128.saturating_pow(chain_info.token_decimals);
+    if args.tip >= one_token {
+        warn!(
+            "Option --tip is {} per transaction, note that its unit is the smallest unit of {}",
+            chain_info.format_balance(args.tip),
+            chain_info.token_symbol
+        );
+    }
+    if args.longevity > 0 {
+        let lifetime = chain_info.block_time * args.longevity.min(u32::MAX as u64) as u32;
+        if lifetime < MIN_TX_LIFETIME {
+            warn!(
+                "Option --longevity={} only lasts {lifetime:?} with a block time of {:?}, transactions might expire before being included",
+                args.longevity, chain_info.block_time
+            );
+        }
+        if args.longevity > 65536 {
+            warn!("Option --longevity is capped at 65536 blocks by the era encoding");
+        }
+    }
+}
+
+/// How long the immortal transactions are assumed to stay in the tx pool.
+const IMMORTAL_TX_POOL_TTL: Duration = Duration::from_secs(3600);
+
+/// How long the submitted messages are skipped before trusting the on-chain sequence again.
+fn msg_state_ttl(longevity: u64, block_time: Duration) -> Duration {
+    if longevity == 0 {
+        return IMMORTAL_TX_POOL_TTL;
+    }
+    block_time * longevity.min(65536) as u32
+}
+
 async fn collect_async_errors(
     mut threshold: Option<u64>,
     mut err_receiver: Receiver<MsgSyncError>,
+    errors: Arc<AtomicU64>,
 ) {
     let threshold_bak = threshold.unwrap_or_default();
     loop {
-        match err_receiver.recv().await {
+        let error = err_receiver.recv().await;
+        if error.is_some() {
+            errors.fetch_add(1, Ordering::Relaxed);
+        }
+        match error {
             Some(error) => match error {
                 MsgSyncError::BadSignature => {
                     warn!("tx received bad signature, restarting...");
@@ -1295,30 +2547,156 @@ pub async fn pherry_main() {
         .parse_default_env()
         .init();
 
-    let mut args = Args::parse();
+    let (mut args, matches) = match config::parse_args(std::env::args_os()) {
+        Ok(parsed) => parsed,
+        Err(err) => match err.downcast::<clap::Error>() {
+            Ok(err) => err.exit(),
+            Err(err) => {
+                error!("{err:?}");
+                std::process::exit(1);
+            }
+        },
+    };
+    if let Some(Command::PrintConfig) = args.command {
+        print!("{}", config::render_config(&matches));
+        return;
+    }
+    if let Some(Command::Setup { output, yes }) = &args.command {
+        if let Err(err) = setup::setup(&args, output, *yes).await {
+            error!("{err:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
     preprocess_args(&mut args);
+    // Only talks to pRuntime, the worker key and the nodes are none of its business.
+    if let Some(Command::Replay { file, keep_going }) = &args.command {
+        if let Err(err) = replay_requests(&args, file, *keep_going).await {
+            error!("{err:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Err(err) = args.worker_key() {
+        error!("{err:?}");
+        std::process::exit(1);
+    }
+    if let Err(err) = balance::validate_thresholds(args.min_balance_warn, args.min_balance_abort) {
+        error!("{err:?}");
+        std::process::exit(1);
+    }
+    if let Err(err) = topology::detect_mode(&mut args).await {
+        error!("{err:?}");
+        std::process::exit(1);
+    }
+
+    if args.inspect_state {
+        if let Err(err) = inspect::inspect_state(&args).await {
+            error!("{err:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::WorkerInfo { pubkey }) = &args.command {
+        if let Err(err) = worker_info::print_worker_info(&args, pubkey).await {
+            error!("{err:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let code = run_bridge(&args).await;
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+/// The exit code when the sync stalls with `--stall-action halt`.
+pub const STALL_EXIT_CODE: i32 = 3;
+
+/// The exit code when the balance drops below `--min-balance-abort`.
+pub const LOW_BALANCE_EXIT_CODE: i32 = 4;
+
+/// The transient sync rejections retried in a row before counting them as failures.
+const MAX_TRANSIENT_RETRIES: u32 = 8;
 
+/// Backs off exponentially from 2s, up to a minute.
+fn transient_retry_delay(retries: u32) -> Duration {
+    Duration::from_secs(2u64.saturating_pow(retries + 1).min(60))
+}
+
+/// Runs the bridge until it reaches `--to-block` or gives up, restarting it on errors if
+/// `--auto-restart` is set. Returns the exit code of the process.
+pub async fn run_bridge(args: &Args) -> i32 {
+    let request_log = match args.record_requests.as_deref().map(RequestLog::create) {
+        Some(Ok(log)) => Some(Arc::new(log)),
+        Some(Err(err)) => {
+            error!("{err:?}");
+            return 2;
+        }
+        None => None,
+    };
     let mut flags = RunningFlags {
         worker_registered: false,
         endpoint_registered: false,
         restart_failure_count: 0,
+        transient_retries: 0,
+        round: RoundTracker::new(if args.notify_endpoint.is_empty() {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(args.notify_summary_interval)
+        }),
+        credentials: reload::listen(args),
+        notify: NotifyClient::new(&args.notify_endpoint)
+            .with_spool(&args.notify_spool_file, args.notify_spool_size),
+        request_log,
+        fetcher: PrefetchClient::new(args.verify_storage_changes, args.prefetch_memory_limit),
     };
 
     loop {
         let (sender, receiver) = msg_sync::create_report_channel();
         let threshold = args.restart_on_rpc_error_threshold;
+        let errors = flags.round.error_counter();
         tokio::select! {
-            res = bridge(&args, &mut flags, sender) => {
+            res = phase_timing::scope(bridge(args, &mut flags, sender)) => {
                 if let Err(err) = res {
                     info!("bridge() exited with error: {:?}", err);
+                    flags.round.record_error();
+                    match sync_error_code(&err) {
+                        Some(code)
+                            if code.is_transient()
+                                && args.auto_restart
+                                && flags.transient_retries < MAX_TRANSIENT_RETRIES =>
+                        {
+                            // The pRuntime progress moved under us, re-read it without counting
+                            // a failure.
+                            let delay = transient_retry_delay(flags.transient_retries);
+                            flags.transient_retries += 1;
+                            info!("Sync rejected as {code}, retrying in {delay:?}...");
+                            sleep(delay).await;
+                            continue;
+                        }
+                        Some(SyncErrorCode::SafeMode) => {
+                            error!("pRuntime is in safe mode, restarting won't help");
+                            return if flags.worker_registered { 1 } else { 2 };
+                        }
+                        _ => (),
+                    }
+                    if err.is::<Stalled>() && args.stall_action == StallAction::Halt {
+                        return STALL_EXIT_CODE;
+                    }
+                    if err.is::<BalanceTooLow>() {
+                        return LOW_BALANCE_EXIT_CODE;
+                    }
                 } else {
-                    break;
+                    return 0;
                 }
             }
-            () = collect_async_errors(threshold, receiver) => ()
+            () = collect_async_errors(threshold, receiver, errors) => ()
         };
         if !args.auto_restart || flags.restart_failure_count > args.max_restart_retries {
-            std::process::exit(if flags.worker_registered { 1 } else { 2 });
+            return if flags.worker_registered { 1 } else { 2 };
         }
         flags.restart_failure_count += 1;
         sleep(Duration::from_secs(2)).await;
@@ -1326,19 +2704,36 @@ pub async fn pherry_main() {
     }
 }
 
+/// Returns the typed rejection reason if the error comes from pRuntime.
+fn sync_error_code(err: &anyhow::Error) -> Option<SyncErrorCode> {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<prpc::client::Error>())
+        .and_then(SyncErrorCode::from_client_error)
+}
 
 async fn sync_with_cached_headers(
     pr: &PrClient,
     headers: Vec<headers_cache::BlockInfo>,
 ) -> Result<()> {
-    let headers = headers
-        .into_iter()
-        .map(|info| blocks::HeaderToSync {
-            header: info.header,
-            justification: info.justification,
-        })
-        .collect();
-    let r = req_sync_header(pr, headers).await?;
+    let authority_set_change = headers
+        .last()
+        .and_then(|info| info.authority_set_change.clone());
+    let headers = phase_timing::measure_sync(Phase::Decode, || {
+        headers
+            .into_iter()
+            .map(|info| blocks::HeaderToSync::new(info.header, info.justification))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|headers| {
+                blocks::validate_headers_to_sync(&headers, authority_set_change.as_ref())?;
+                Ok(headers)
+            })
+    })
+    .map_err(|err| anyhow!("Invalid cached headers: {err}"))?;
+    let r = phase_timing::measure(
+        Phase::Dispatch,
+        pr.sync_header(prpc::HeadersToSync::new(headers, authority_set_change)),
+    )
+    .await?;
     info!("  ..sync_header: {:?}", r);
 
     Ok(())
@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use phala_types::messaging::MessageOrigin;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Submitted {
+    next_sequence: u64,
+    /// Unix time in seconds
+    submitted_at: u64,
+}
+
+/// The egress messages submitted to the tx pool, by sender, persisted across restarts.
+///
+/// The on-chain sequence only moves once the messages are included, so after a restart the
+/// messages still sitting in the tx pool would be submitted again. An entry is trusted until the
+/// submitted extrinsics could have expired, after which the on-chain sequence is the only source
+/// of truth again. Without a file to persist them to, nothing is tracked, the on-chain sequence
+/// being all there is to go by within a run anyway.
+///
+/// The clones share the state, for the submissions to record their messages once accepted.
+#[derive(Clone)]
+pub struct SubmittedMessages {
+    file: Option<PathBuf>,
+    ttl: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    senders: BTreeMap<String, Submitted>,
+    dirty: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl SubmittedMessages {
+    /// Loads the state from `file`, nothing is persisted if it is empty.
+    pub fn load(file: &str, ttl: Duration) -> Self {
+        let file = (!file.is_empty()).then(|| PathBuf::from(file));
+        let senders = match &file {
+            Some(file) if file.exists() => match Self::read(file) {
+                Ok(senders) => {
+                    info!("Loaded submitted message sequences from {}", file.display());
+                    senders
+                }
+                Err(err) => {
+                    warn!("Ignoring the message state file: {err:?}");
+                    Default::default()
+                }
+            },
+            _ => Default::default(),
+        };
+        Self {
+            file,
+            ttl,
+            state: Arc::new(Mutex::new(State {
+                senders,
+                dirty: false,
+            })),
+        }
+    }
+
+    fn read(file: &PathBuf) -> Result<BTreeMap<String, Submitted>> {
+        let data = std::fs::read(file)?;
+        serde_json::from_slice(&data).context("Failed to decode the message state file")
+    }
+
+    /// The next sequence of `sender` not yet submitted, if recently submitted.
+    pub fn next_sequence(&self, sender: &MessageOrigin) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        let submitted = state.senders.get(&sender.to_string())?;
+        let age = now().saturating_sub(submitted.submitted_at);
+        (age < self.ttl.as_secs()).then_some(submitted.next_sequence)
+    }
+
+    /// Records the message as accepted by the node, to be called only once it's in the tx pool.
+    pub fn record(&self, sender: &MessageOrigin, sequence: u64) {
+        if self.file.is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let submitted = state
+            .senders
+            .entry(sender.to_string())
+            .or_insert(Submitted {
+                next_sequence: 0,
+                submitted_at: 0,
+            });
+        // The submissions may complete out of order.
+        submitted.next_sequence = submitted.next_sequence.max(sequence + 1);
+        submitted.submitted_at = now();
+        state.dirty = true;
+    }
+
+    /// Writes the state back to the file if anything was recorded since the last save.
+    pub fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut state = self.state.lock().unwrap();
+        if !state.dirty {
+            return Ok(());
+        }
+        let ttl = self.ttl.as_secs();
+        let now = now();
+        state
+            .senders
+            .retain(|_, submitted| now.saturating_sub(submitted.submitted_at) < ttl);
+        if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_file = file.with_extension(format!("tmp.{}", std::process::id()));
+        std::fs::write(&tmp_file, serde_json::to_vec(&state.senders)?)?;
+        std::fs::rename(&tmp_file, file)?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    /// Records the messages accepted by the node and saves the state.
+    pub fn record_accepted(&self, messages: &[(MessageOrigin, u64)]) {
+        for (sender, sequence) in messages {
+            self.record(sender, *sequence);
+        }
+        if let Err(err) = self.save() {
+            warn!("Failed to save the message state file: {err:?}");
+        }
+    }
+}
@@ -1,117 +1,324 @@
-use anyhow::Result;
-use log::{error, info};
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use phactory_api::prpc::{EgressCursor, EgressMessages, GetEgressMessagesPagedRequest};
+use phala_types::messaging::{MessageOrigin, SignedMessage};
+use phaxt::subxt::{
+    error::{DispatchError, RpcError},
+    Error as SubxtError,
+};
 use std::time::Duration;
 
 use crate::{
     chain_client::{mq_next_sequence, update_signer_nonce},
+    era::EraCache,
+    msg_state::SubmittedMessages,
     types::{ParachainApi, PrClient, SrSigner},
 };
 
 pub use tokio::sync::mpsc::{channel, Receiver, Sender};
 
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(120);
+
 pub enum Error {
     BadSignature, // Might due to runtime updated.
     OtherRpcError,
 }
 
+impl From<&SubxtError> for Error {
+    fn from(err: &SubxtError) -> Self {
+        match err {
+            SubxtError::Rpc(RpcError::ClientError(err))
+                if err.to_string().contains("bad signature") =>
+            {
+                Error::BadSignature
+            }
+            _ => Error::OtherRpcError,
+        }
+    }
+}
+
 pub fn create_report_channel() -> (Sender<Error>, Receiver<Error>) {
     channel(1024)
 }
 
+/// Size of the egress message pages requested from pRuntime.
+const EGRESS_PAGE_BYTES: u32 = 1024 * 1024;
+
+/// Gets a page of the egress messages starting from `cursor`, with the cursor of the next page.
+///
+/// Falls back to fetching all the messages at once if pRuntime doesn't serve the paged ones.
+pub async fn get_egress_messages_page(
+    pr: &PrClient,
+    cursor: Option<EgressCursor>,
+) -> Result<(EgressMessages, Option<EgressCursor>)> {
+    let first_page = cursor.is_none();
+    let request = GetEgressMessagesPagedRequest::new(cursor, EGRESS_PAGE_BYTES);
+    match pr.get_egress_messages_paged(request).await {
+        Ok(response) => Ok((response.decode_messages()?, response.decode_next_cursor()?)),
+        Err(err) if first_page => {
+            warn!("Failed to get paged egress messages, fallback to get all at once: {err:?}");
+            let messages = pr.get_egress_messages(()).await?.decode_messages()?;
+            Ok((messages, None))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Gets all the egress messages page by page.
+pub async fn get_all_egress_messages(pr: &PrClient) -> Result<EgressMessages> {
+    let mut all: EgressMessages = vec![];
+    let mut cursor = None;
+    loop {
+        let (messages, next_cursor) = get_egress_messages_page(pr, cursor).await?;
+        for (sender, messages) in messages {
+            match all.last_mut() {
+                // A sender might span over pages.
+                Some((last, last_messages)) if *last == sender => last_messages.extend(messages),
+                _ => all.push((sender, messages)),
+            }
+        }
+        match next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(all),
+        }
+    }
+}
+
+/// Submits the pending egress messages, returns the number of the messages submitted.
+#[allow(clippy::too_many_arguments)]
 pub async fn maybe_sync_mq_egress(
     api: &ParachainApi,
     pr: &PrClient,
     signer: &mut SrSigner,
+    era_cache: &mut EraCache,
+    submitted: &SubmittedMessages,
     tip: u128,
-    longevity: u64,
     max_sync_msgs_per_round: u64,
+    batch_size: u64,
     err_report: Sender<Error>,
-) -> Result<()> {
-    // Send the query
-    let messages = pr.get_egress_messages(()).await?.decode_messages()?;
+) -> Result<usize> {
+    let mut pending = vec![];
+    // The next sequence of the sender spanning over the pages.
+    let mut last_sender: Option<(MessageOrigin, u64)> = None;
+    let mut cursor = None;
+
+    // Fetch the messages page by page, no more than needed for this round.
+    'sync_outer: loop {
+        let (messages, next_cursor) = get_egress_messages_page(pr, cursor).await?;
+        for (sender, messages) in messages {
+            if messages.is_empty() {
+                continue;
+            }
+            let min_seq = match &last_sender {
+                Some((last, min_seq)) if *last == sender => *min_seq,
+                _ => {
+                    let chain_seq = mq_next_sequence(api, &sender).await?;
+                    // The messages submitted before a restart might still be pending in the tx pool.
+                    let min_seq = match submitted.next_sequence(&sender) {
+                        Some(seq) if seq > chain_seq => {
+                            info!("Next seq for {} is {}, {} on chain", sender, seq, chain_seq);
+                            seq
+                        }
+                        _ => {
+                            info!("Next seq for {} is {}", sender, chain_seq);
+                            chain_seq
+                        }
+                    };
+                    last_sender = Some((sender.clone(), min_seq));
+                    min_seq
+                }
+            };
+
+            for message in messages {
+                if message.sequence < min_seq {
+                    info!("{} has been submitted. Skipping...", message.sequence);
+                    continue;
+                }
+                let msg_info = format!(
+                    "sender={} seq={} dest={}",
+                    sender,
+                    message.sequence,
+                    String::from_utf8_lossy(&message.message.destination.path()[..]),
+                );
+                pending.push((msg_info, message));
+                if pending.len() as u64 >= max_sync_msgs_per_round {
+                    info!("Synced {} messages, take a break", pending.len());
+                    break 'sync_outer;
+                }
+            }
+        }
+        match next_cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
 
     // No pending message. We are done.
-    if messages.is_empty() {
-        return Ok(());
+    if pending.is_empty() {
+        return Ok(0);
     }
 
     update_signer_nonce(api, signer).await?;
+    let count = pending.len();
 
-    let mut sync_msgs_count = 0;
-
-    'sync_outer: for (sender, messages) in messages {
-        if messages.is_empty() {
-            continue;
+    if batch_size <= 1 {
+        for (msg_info, message) in pending {
+            submit_message(
+                api,
+                signer,
+                era_cache,
+                tip,
+                msg_info,
+                message,
+                submitted,
+                &err_report,
+            )
+            .await?;
+        }
+    } else {
+        let mut pending = pending.into_iter().peekable();
+        while pending.peek().is_some() {
+            let batch: Vec<_> = pending.by_ref().take(batch_size as usize).collect();
+            submit_batch(api, signer, era_cache, tip, batch, submitted, &err_report).await?;
         }
-        let min_seq = mq_next_sequence(api, &sender).await?;
+    }
+    Ok(count)
+}
 
-        info!("Next seq for {} is {}", sender, min_seq);
+/// Submits the message in the background, recorded as submitted once the node accepts it.
+#[allow(clippy::too_many_arguments)]
+async fn submit_message(
+    api: &ParachainApi,
+    signer: &mut SrSigner,
+    era_cache: &mut EraCache,
+    tip: u128,
+    msg_info: String,
+    message: SignedMessage,
+    submitted: &SubmittedMessages,
+    err_report: &Sender<Error>,
+) -> Result<()> {
+    let msg_info = format!("{msg_info} nonce={:?}", signer.nonce());
+    let sequence = (message.message.sender.clone(), message.sequence);
+    info!("Submitting message: {}", msg_info);
 
-        for message in messages {
-            if message.sequence < min_seq {
-                info!("{} has been submitted. Skipping...", message.sequence);
-                continue;
+    let params = era_cache.mk_params(api, tip).await?;
+    let tx = phaxt::dynamic::tx::sync_offchain_message(message);
+    let extrinsic = signer
+        .create_signed(api, &tx, params)
+        .await
+        .context("Failed to sign the message")?;
+    signer.increment_nonce();
+    let api = api.clone();
+    let submitted = submitted.clone();
+    let err_report = err_report.clone();
+    let extrinsic = crate::subxt::utils::Encoded(extrinsic.encoded().to_vec());
+    tokio::spawn(async move {
+        let fut = api.rpc().submit_extrinsic(extrinsic);
+        let result = tokio::time::timeout(SUBMIT_TIMEOUT, fut).await;
+        match result {
+            Err(_) => {
+                error!("Submit message timed out: {}", msg_info);
+                let _ = err_report.send(Error::OtherRpcError).await;
             }
-            let msg_info = format!(
-                "sender={} seq={} dest={} nonce={:?}",
-                sender,
-                message.sequence,
-                String::from_utf8_lossy(&message.message.destination.path()[..]),
-                signer.nonce()
-            );
-            info!("Submitting message: {}", msg_info);
-
-            let params = crate::mk_params(api, longevity, tip).await?;
-            let tx = phaxt::dynamic::tx::sync_offchain_message(message);
-            let extrinsic =
-                api.tx()
-                    .create_signed_with_nonce(&tx, &signer.signer, signer.nonce(), params);
-            signer.increment_nonce();
-            match extrinsic {
-                Ok(extrinsic) => {
-                    let api = api.clone();
-                    let err_report = err_report.clone();
-                    let extrinsic = crate::subxt::utils::Encoded(extrinsic.encoded().to_vec());
-                    tokio::spawn(async move {
-                        const TIMEOUT: u64 = 120;
-                        let fut = api.rpc().submit_extrinsic(extrinsic);
-                        let result = tokio::time::timeout(Duration::from_secs(TIMEOUT), fut).await;
-                        match result {
-                            Err(_) => {
-                                error!("Submit message timed out: {}", msg_info);
-                                let _ = err_report.send(Error::OtherRpcError).await;
-                            }
-                            Ok(Err(err)) => {
-                                error!("Error submitting message {}: {:?}", msg_info, err);
-                                use phaxt::subxt::{error::RpcError, Error as SubxtError};
-                                let report = match err {
-                                    SubxtError::Rpc(RpcError::ClientError(err)) => {
-                                        if err.to_string().contains("bad signature") {
-                                            Error::BadSignature
-                                        } else {
-                                            Error::OtherRpcError
-                                        }
-                                    }
-                                    _ => Error::OtherRpcError,
-                                };
-                                let _ = err_report.send(report).await;
-                            }
-                            Ok(Ok(hash)) => {
-                                info!("Message submited: {} xt-hash={:?}", msg_info, hash);
-                            }
-                        }
-                    });
+            Ok(Err(err)) => {
+                error!("Error submitting message {}: {:?}", msg_info, err);
+                let _ = err_report.send((&err).into()).await;
+            }
+            Ok(Ok(hash)) => {
+                info!("Message submited: {} xt-hash={:?}", msg_info, hash);
+                submitted.record_accepted(&[sequence]);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Submits the messages in a single `Utility.force_batch` extrinsic, and reports the result of
+/// each message from the events once the extrinsic gets into a block. The messages are recorded
+/// as submitted once the node accepts the extrinsic.
+async fn submit_batch(
+    api: &ParachainApi,
+    signer: &mut SrSigner,
+    era_cache: &mut EraCache,
+    tip: u128,
+    batch: Vec<(String, SignedMessage)>,
+    submitted: &SubmittedMessages,
+    err_report: &Sender<Error>,
+) -> Result<()> {
+    let sequences: Vec<_> = batch
+        .iter()
+        .map(|(_, message)| (message.message.sender.clone(), message.sequence))
+        .collect();
+    let (infos, calls): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|(info, message)| (info, phaxt::dynamic::tx::sync_offchain_message(message)))
+        .unzip();
+    let batch_info = format!("{} messages nonce={:?}", infos.len(), signer.nonce());
+    info!("Submitting batch: {}", batch_info);
+    for msg_info in &infos {
+        info!("  {}", msg_info);
+    }
+
+    let params = era_cache.mk_params(api, tip).await?;
+    let tx = phaxt::dynamic::tx::force_batch(&api.metadata(), &calls)?;
+    let extrinsic = signer
+        .create_signed(api, &tx, params)
+        .await
+        .context("Failed to sign the batch")?;
+    signer.increment_nonce();
+
+    let api = api.clone();
+    let submitted = submitted.clone();
+    let err_report = err_report.clone();
+    tokio::spawn(async move {
+        let fut = async {
+            let progress = extrinsic.submit_and_watch().await?;
+            submitted.record_accepted(&sequences);
+            let events = progress.wait_for_in_block().await?.fetch_events().await?;
+            let mut results = vec![];
+            for event in events.iter() {
+                let event = event?;
+                if event.pallet_name() != "Utility" {
+                    continue;
                 }
-                Err(err) => {
-                    panic!("Failed to sign the call: {:?}", err);
+                match event.variant_name() {
+                    "ItemCompleted" => results.push(Ok(())),
+                    "ItemFailed" => {
+                        let err = DispatchError::decode_from(event.field_bytes(), api.metadata())?;
+                        results.push(Err(err));
+                    }
+                    _ => {}
                 }
             }
-            sync_msgs_count += 1;
-            if sync_msgs_count >= max_sync_msgs_per_round {
-                info!("Synced {} messages, take a break", sync_msgs_count);
-                break 'sync_outer;
+            Ok::<_, SubxtError>(results)
+        };
+        match tokio::time::timeout(SUBMIT_TIMEOUT, fut).await {
+            Err(_) => {
+                error!("Submit batch timed out: {}", batch_info);
+                let _ = err_report.send(Error::OtherRpcError).await;
+            }
+            Ok(Err(err)) => {
+                error!("Error submitting batch {}: {:?}", batch_info, err);
+                let _ = err_report.send((&err).into()).await;
+            }
+            Ok(Ok(results)) if results.len() != infos.len() => {
+                error!(
+                    "Batch {} included with {} item results, expected {}",
+                    batch_info,
+                    results.len(),
+                    infos.len()
+                );
+                let _ = err_report.send(Error::OtherRpcError).await;
+            }
+            Ok(Ok(results)) => {
+                info!("Batch included: {}", batch_info);
+                for (msg_info, result) in infos.iter().zip(results) {
+                    if let Err(err) = result {
+                        warn!("Message failed in batch: {} error={}", msg_info, err);
+                    }
+                }
             }
         }
-    }
+    });
     Ok(())
 }
@@ -1,38 +1,110 @@
 use anyhow::Result;
+use futures::FutureExt;
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
 
-use crate::types::NotifyReq;
+use crate::notify_spool::{NotifySpool, Rejected};
+use crate::types::{NotifyReq, RoundSummary, SignedHeartbeat};
 
 pub struct NotifyClient {
     base_url: String,
+    spool: Option<NotifySpool>,
 }
 
 impl NotifyClient {
     pub fn new(base_url: &str) -> Self {
         NotifyClient {
             base_url: base_url.to_string(),
+            spool: None,
         }
     }
 
-    pub async fn notify(&self, param: &NotifyReq) -> Result<()> {
-        if self.base_url.is_empty() {
-            return Ok(());
+    /// Keeps up to `capacity` status updates and round summaries while the endpoint is down,
+    /// persisted to `file` if not empty, and replays them in order once it recovers. Disabled if
+    /// `capacity` is 0.
+    pub fn with_spool(mut self, file: &str, capacity: usize) -> Self {
+        if self.base_url.is_empty() || capacity == 0 {
+            return self;
         }
+        let base_url = self.base_url.clone();
+        let spool = NotifySpool::start(
+            file,
+            capacity,
+            Box::new(move |notification: Value| {
+                let base_url = base_url.clone();
+                async move { post(&base_url, &notification).await }.boxed()
+            }),
+        );
+        self.spool = Some(spool);
+        self
+    }
 
-        let client = reqwest::Client::new();
+    pub async fn notify(&self, param: &NotifyReq) -> Result<()> {
+        self.post_spooled(param).await
+    }
 
-        let body_json = serde_json::to_string(param).unwrap();
+    /// Posts the summary of a round as `{"round_summary": {...}}`, to tell it apart from the
+    /// status pings.
+    pub async fn notify_round_summary(&self, summary: &RoundSummary) -> Result<()> {
+        self.post_spooled(&serde_json::json!({ "round_summary": summary }))
+            .await
+    }
 
-        let res = client
-            .post(&self.base_url)
-            .header("content-type", "application/json")
-            .body(body_json)
-            .send()
-            .await?;
+    /// Posts a heartbeat as `{"heartbeat": {...}}`. Never spooled, a late heartbeat is of no use.
+    pub async fn notify_heartbeat(&self, heartbeat: &SignedHeartbeat) -> Result<()> {
+        post(
+            &self.base_url,
+            &serde_json::json!({ "heartbeat": heartbeat }),
+        )
+        .await
+    }
 
-        if res.status().is_success() {
-            Ok(())
-        } else {
-            Err(anyhow::Error::msg(res.status()))
+    async fn post_spooled(&self, param: &impl Serialize) -> Result<()> {
+        match &self.spool {
+            Some(spool) => {
+                spool.push(serde_json::to_value(param)?);
+                Ok(())
+            }
+            None => post(&self.base_url, param).await,
         }
     }
 }
+
+/// How long to wait for the endpoint, so that a hanging one doesn't hold up the spool forever.
+const POST_TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn post(base_url: &str, param: &impl Serialize) -> Result<()> {
+    if base_url.is_empty() {
+        return Ok(());
+    }
+
+    let client = reqwest::Client::builder().timeout(POST_TIMEOUT).build()?;
+
+    let body_json = serde_json::to_string(param).unwrap();
+
+    let res = client
+        .post(base_url)
+        .header("content-type", "application/json")
+        .body(body_json)
+        .send()
+        .await?;
+
+    let status = res.status();
+    if status.is_success() {
+        Ok(())
+    } else if is_retryable(status) {
+        Err(anyhow::Error::msg(status))
+    } else {
+        Err(Rejected(status.to_string()).into())
+    }
+}
+
+/// Whether the endpoint might accept the same notification later. The other client errors are
+/// about the notification itself.
+fn is_retryable(status: StatusCode) -> bool {
+    !status.is_client_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
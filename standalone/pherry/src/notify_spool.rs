@@ -0,0 +1,220 @@
+//! Keeps the notifications while the notify endpoint is down and replays them in order once it
+//! recovers, so that the monitoring systems don't miss the lifecycle transitions.
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::{sync::Notify, task::JoinHandle};
+
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Posts a notification to the endpoint. Failing with [`Rejected`] drops the notification
+/// instead of retrying it.
+pub type Post = Box<dyn Fn(Value) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// The endpoint refused the notification itself, posting it again won't help.
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The notification was rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+#[derive(Default)]
+struct Queue {
+    /// The notifications not delivered yet, the oldest first, each with its sequence number.
+    pending: VecDeque<(u64, Value)>,
+    next_seq: u64,
+    dropped: u64,
+    rejected: u64,
+}
+
+impl Queue {
+    fn push(&mut self, notification: Value, capacity: usize) {
+        self.pending.push_back((self.next_seq, notification));
+        self.next_seq += 1;
+        while self.pending.len() > capacity {
+            self.pending.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// Removes the notification `seq` if it is still at the front, it might have been dropped
+    /// while being posted.
+    fn remove_front(&mut self, seq: u64) -> bool {
+        if self.pending.front().map(|(front, _)| *front) == Some(seq) {
+            self.pending.pop_front();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct Shared {
+    file: Option<PathBuf>,
+    capacity: usize,
+    queue: Mutex<Queue>,
+    notify: Notify,
+}
+
+impl Shared {
+    fn save(&self, queue: &Queue) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if let Err(err) = write(file, queue) {
+            warn!("Failed to save the notify spool: {err:?}");
+        }
+    }
+}
+
+/// A bounded queue of the notifications to post, persisted to a file if given.
+///
+/// The notifications are posted one at a time in order by a retry task, which backs off
+/// exponentially while the endpoint is down. A notification the endpoint [`Rejected`] is dropped
+/// rather than holding up the later ones. Once the queue is full, the oldest notifications are
+/// dropped to make room for the new ones.
+pub struct NotifySpool {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+impl NotifySpool {
+    /// Starts the retry task with the notifications left in `file`, nothing is persisted if it is
+    /// empty.
+    pub fn start(file: &str, capacity: usize, post: Post) -> Self {
+        let file = (!file.is_empty()).then(|| PathBuf::from(file));
+        let mut queue = Queue::default();
+        match &file {
+            Some(file) if file.exists() => match read(file) {
+                Ok(notifications) => {
+                    info!(
+                        "Loaded {} spooled notifications from {}",
+                        notifications.len(),
+                        file.display()
+                    );
+                    for notification in notifications {
+                        queue.push(notification, capacity);
+                    }
+                }
+                Err(err) => warn!("Ignoring the notify spool file: {err:?}"),
+            },
+            _ => (),
+        }
+        let shared = Arc::new(Shared {
+            file,
+            capacity,
+            queue: Mutex::new(queue),
+            notify: Notify::new(),
+        });
+        let task = tokio::spawn(run(shared.clone(), post));
+        Self { shared, task }
+    }
+
+    /// Queues the notification to be posted after the pending ones.
+    pub fn push(&self, notification: Value) {
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.push(notification, self.shared.capacity);
+            self.shared.save(&queue);
+        }
+        self.shared.notify.notify_one();
+    }
+
+    /// The number of the notifications not delivered yet.
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().pending.len()
+    }
+
+    /// The number of the notifications dropped for the spool being full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.lock().unwrap().dropped
+    }
+
+    /// The number of the notifications dropped for the endpoint rejecting them.
+    pub fn rejected(&self) -> u64 {
+        self.shared.queue.lock().unwrap().rejected
+    }
+}
+
+impl Drop for NotifySpool {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(shared: Arc<Shared>, post: Post) {
+    let mut retry_interval = MIN_RETRY_INTERVAL;
+    loop {
+        let front = shared.queue.lock().unwrap().pending.front().cloned();
+        let Some((seq, notification)) = front else {
+            shared.notify.notified().await;
+            continue;
+        };
+        match post(notification).await {
+            Ok(()) => {
+                let mut queue = shared.queue.lock().unwrap();
+                queue.remove_front(seq);
+                shared.save(&queue);
+                if retry_interval > MIN_RETRY_INTERVAL {
+                    info!(
+                        "The notify endpoint recovered, {} spooled notifications to replay",
+                        queue.pending.len()
+                    );
+                }
+                retry_interval = MIN_RETRY_INTERVAL;
+            }
+            Err(err) if err.downcast_ref::<Rejected>().is_some() => {
+                let mut queue = shared.queue.lock().unwrap();
+                if queue.remove_front(seq) {
+                    queue.rejected += 1;
+                }
+                shared.save(&queue);
+                warn!(
+                    "Dropped notification {seq}, {} pending: {err:?}",
+                    queue.pending.len()
+                );
+                retry_interval = MIN_RETRY_INTERVAL;
+            }
+            Err(err) => {
+                let (pending, dropped) = {
+                    let queue = shared.queue.lock().unwrap();
+                    (queue.pending.len(), queue.dropped)
+                };
+                warn!(
+                    "Failed to notify, {pending} pending and {dropped} dropped, retrying in {retry_interval:?}: {err:?}"
+                );
+                tokio::time::sleep(retry_interval).await;
+                retry_interval = (retry_interval * 2).min(MAX_RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+fn read(file: &PathBuf) -> Result<Vec<Value>> {
+    let data = std::fs::read(file)?;
+    serde_json::from_slice(&data).context("Failed to decode the notify spool file")
+}
+
+fn write(file: &PathBuf, queue: &Queue) -> Result<()> {
+    if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let notifications: Vec<&Value> = queue.pending.iter().map(|(_, n)| n).collect();
+    let tmp_file = file.with_extension(format!("tmp.{}", std::process::id()));
+    std::fs::write(&tmp_file, serde_json::to_vec(&notifications)?)?;
+    std::fs::rename(&tmp_file, file)?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, bail, Context, Result};
+use codec::{Decode, Encode};
+use log::{info, warn};
+use sgx_attestation::dcap::{report::get_collateral, Quote, SgxV30QuoteCollateral};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Fetches DCAP collateral from a list of PCCS servers, failing over to the next one if a
+/// server is unavailable.
+///
+/// The fetched collateral can be cached on disk, keyed by the FMSPC of the quote, so that
+/// re-registrations don't need to hit the PCCS again.
+pub struct CollateralFetcher {
+    urls: Vec<String>,
+    timeout: Duration,
+    cache: Option<(PathBuf, Duration)>,
+}
+
+impl CollateralFetcher {
+    /// Creates a fetcher from a comma separated list of PCCS URLs.
+    pub fn new(pccs_urls: &str, timeout_secs: u64) -> Self {
+        let urls = pccs_urls
+            .split(',')
+            .map(|url| url.trim())
+            .filter(|url| !url.is_empty())
+            .map(Into::into)
+            .collect();
+        Self {
+            urls,
+            timeout: Duration::from_secs(timeout_secs),
+            cache: None,
+        }
+    }
+
+    /// Enables the on-disk cache. The cache is disabled if `dir` is empty or `ttl` is zero.
+    pub fn with_cache(mut self, dir: &str, ttl: Duration) -> Self {
+        if !dir.is_empty() && !ttl.is_zero() {
+            self.cache = Some((dir.into(), ttl));
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+    }
+
+    pub async fn get_collateral(&self, quote: &[u8]) -> Result<SgxV30QuoteCollateral> {
+        let cache_file = match &self.cache {
+            Some((dir, _)) => Some(dir.join(format!("{}.collateral", fmspc_of(quote)?))),
+            None => None,
+        };
+        if let Some(file) = &cache_file {
+            if let Some(collateral) = self.load_cached(file) {
+                info!("Using cached collateral {}", file.display());
+                return Ok(collateral);
+            }
+        }
+
+        let collateral = self.fetch(quote).await?;
+        if let Some(file) = &cache_file {
+            if let Err(err) = save_cached(file, &collateral) {
+                warn!("Failed to cache collateral to {}: {err:?}", file.display());
+            }
+        }
+        Ok(collateral)
+    }
+
+    async fn fetch(&self, quote: &[u8]) -> Result<SgxV30QuoteCollateral> {
+        if self.urls.is_empty() {
+            bail!("pccs_url is required when using dcap");
+        }
+        let mut last_err = None;
+        for url in &self.urls {
+            match get_collateral(url, quote, self.timeout).await {
+                Ok(collateral) => return Ok(collateral),
+                Err(err) => {
+                    warn!("Failed to get collateral from {url}: {err:?}");
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err
+            .unwrap_or_else(|| anyhow!("No PCCS available"))
+            .context("Failed to get collateral from all PCCS servers"))
+    }
+
+    fn load_cached(&self, file: &PathBuf) -> Option<SgxV30QuoteCollateral> {
+        let (_, ttl) = self.cache.as_ref()?;
+        let modified = std::fs::metadata(file).ok()?.modified().ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age >= *ttl {
+            return None;
+        }
+        let data = std::fs::read(file).ok()?;
+        SgxV30QuoteCollateral::decode(&mut &data[..]).ok()
+    }
+}
+
+fn fmspc_of(quote: &[u8]) -> Result<String> {
+    let quote = Quote::decode(&mut &quote[..]).context("Failed to decode the quote")?;
+    let fmspc = quote
+        .fmspc()
+        .map_err(|_| anyhow!("Failed to get fmspc from the quote"))?;
+    Ok(hex::encode_upper(fmspc))
+}
+
+fn save_cached(file: &PathBuf, collateral: &SgxV30QuoteCollateral) -> Result<()> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first, in case other pherry instances share the cache.
+    let tmp_file = file.with_extension(format!("tmp.{}", std::process::id()));
+    std::fs::write(&tmp_file, collateral.encode())?;
+    std::fs::rename(&tmp_file, file)?;
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use crate::types::{PhasePercentiles, SyncTiming};
+use log::info;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// The phases a sync round spends its time in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting for the chain node or the headers cache.
+    Fetch,
+    /// Decoding and checking what was fetched.
+    Decode,
+    /// Waiting for pRuntime to take the headers or the blocks.
+    Dispatch,
+}
+
+/// The time a sync round spent in each phase.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PhaseTimes {
+    pub fetch: Duration,
+    pub decode: Duration,
+    pub dispatch: Duration,
+}
+
+impl PhaseTimes {
+    fn get(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Fetch => self.fetch,
+            Phase::Decode => self.decode,
+            Phase::Dispatch => self.dispatch,
+        }
+    }
+
+    fn get_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Fetch => &mut self.fetch,
+            Phase::Decode => &mut self.decode,
+            Phase::Dispatch => &mut self.dispatch,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.fetch.is_zero() && self.decode.is_zero() && self.dispatch.is_zero()
+    }
+}
+
+impl fmt::Display for PhaseTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fetch {:?}, decode {:?}, dispatch {:?}",
+            self.fetch, self.decode, self.dispatch
+        )
+    }
+}
+
+/// Accounts the time to the innermost phase running, so that the decoding done inside a fetch is
+/// not counted as fetching.
+#[derive(Default)]
+struct Recorder {
+    times: PhaseTimes,
+    running: Vec<(Phase, Instant)>,
+}
+
+impl Recorder {
+    fn pause(&mut self, now: Instant) {
+        if let Some((phase, since)) = self.running.last_mut() {
+            *self.times.get_mut(*phase) += now - *since;
+            *since = now;
+        }
+    }
+
+    fn enter(&mut self, phase: Phase) {
+        let now = Instant::now();
+        self.pause(now);
+        self.running.push((phase, now));
+    }
+
+    fn exit(&mut self) {
+        let now = Instant::now();
+        self.pause(now);
+        self.running.pop();
+        if let Some((_, since)) = self.running.last_mut() {
+            *since = now;
+        }
+    }
+}
+
+tokio::task_local! {
+    static RECORDER: RefCell<Recorder>;
+}
+
+fn with_recorder(f: impl FnOnce(&mut Recorder)) {
+    // Nothing is recorded out of the bridge, e.g. in the prefetching tasks.
+    let _ = RECORDER.try_with(|recorder| f(&mut recorder.borrow_mut()));
+}
+
+struct Guard;
+
+impl Guard {
+    fn enter(phase: Phase) -> Self {
+        with_recorder(|recorder| recorder.enter(phase));
+        Guard
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        with_recorder(Recorder::exit);
+    }
+}
+
+/// Runs `f` with the phases measured in it recorded, to be taken by [`take`].
+pub async fn scope<F: Future>(f: F) -> F::Output {
+    RECORDER.scope(RefCell::new(Recorder::default()), f).await
+}
+
+/// Accounts the time `f` takes to `phase`.
+pub async fn measure<F: Future>(phase: Phase, f: F) -> F::Output {
+    let _guard = Guard::enter(phase);
+    f.await
+}
+
+pub fn measure_sync<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let _guard = Guard::enter(phase);
+    f()
+}
+
+/// Takes the times recorded since the last call.
+pub fn take() -> PhaseTimes {
+    RECORDER
+        .try_with(|recorder| std::mem::take(&mut recorder.borrow_mut().times))
+        .unwrap_or_default()
+}
+
+/// Keeps the phase times of the recent sync rounds to tell where the sync is slow.
+pub struct PhaseStats {
+    window: VecDeque<PhaseTimes>,
+    capacity: usize,
+    rounds: u64,
+}
+
+impl PhaseStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            window: VecDeque::new(),
+            capacity: capacity.max(1),
+            rounds: 0,
+        }
+    }
+
+    /// Records the times of a round, logging the percentiles each time the window is renewed.
+    pub fn push(&mut self, operation: &str, times: PhaseTimes) {
+        if times.is_zero() {
+            return;
+        }
+        info!("{operation} took {times}");
+        if self.window.len() == self.capacity {
+            self.window.pop_front();
+        }
+        self.window.push_back(times);
+        self.rounds += 1;
+        if self.rounds % self.capacity as u64 == 0 {
+            if let Some(timing) = self.report() {
+                info!(
+                    "Sync phase timing over the last {} rounds: {timing:?}",
+                    timing.rounds
+                );
+            }
+        }
+    }
+
+    /// The percentiles over the window, None if no round is recorded yet.
+    pub fn report(&self) -> Option<SyncTiming> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let percentiles = |phase: Phase| {
+            let mut durations: Vec<_> = self.window.iter().map(|times| times.get(phase)).collect();
+            durations.sort();
+            PhasePercentiles {
+                p50_ms: percentile(&durations, 50),
+                p95_ms: percentile(&durations, 95),
+            }
+        };
+        Some(SyncTiming {
+            rounds: self.window.len() as u32,
+            fetch: percentiles(Phase::Fetch),
+            decode: percentiles(Phase::Decode),
+            dispatch: percentiles(Phase::Dispatch),
+        })
+    }
+}
+
+/// The nearest-rank percentile of the sorted durations, in milliseconds.
+fn percentile(sorted: &[Duration], p: usize) -> u64 {
+    let rank = (sorted.len() * p + 99) / 100;
+    sorted[rank.max(1) - 1].as_millis() as u64
+}
@@ -1,66 +1,199 @@
 use anyhow::Result;
+use codec::Encode;
 use phactory_api::blocks::BlockHeaderWithChanges;
-use phaxt::{BlockNumber, RpcClient};
+use phaxt::BlockNumber;
+use std::collections::VecDeque;
 use tokio::task::JoinHandle;
 
+use crate::block_source::BlockSources;
+
 struct StoragePrefetchState {
     from: BlockNumber,
     to: BlockNumber,
     handle: JoinHandle<Result<Vec<BlockHeaderWithChanges>>>,
 }
 
+/// Fetches the storage changes to dispatch, prefetching the next batch in the background.
+///
+/// The fetched blocks are kept until dispatched, and the client lives across the sync rounds and
+/// the restarts of the bridge, so that a round interrupted in the middle of a batch resumes from
+/// the blocks already fetched instead of downloading them again.
+///
+/// The fetched blocks are capped by their encoded size. The ones beyond the cap, i.e. the furthest
+/// ahead, are dropped and fetched again once reached.
 pub struct PrefetchClient {
     prefetching_storage_changes: Option<StoragePrefetchState>,
+    /// Fetched blocks not dispatched yet, in ascending order without gaps.
+    fetched: VecDeque<BlockHeaderWithChanges>,
+    /// The encoded size of the fetched blocks.
+    fetched_bytes: usize,
+    /// Cap of `fetched_bytes`, 0 for no cap.
+    memory_limit: usize,
+    with_root: bool,
 }
 
 impl PrefetchClient {
-    pub fn new() -> Self {
+    pub fn new(with_root: bool, memory_limit: usize) -> Self {
         Self {
             prefetching_storage_changes: None,
+            fetched: VecDeque::new(),
+            fetched_bytes: 0,
+            memory_limit,
+            with_root,
+        }
+    }
+
+    /// Drops the fetched blocks and cancels the prefetch, to release the memory between the sync
+    /// rounds.
+    pub fn prune(&mut self) {
+        if let Some(state) = self.prefetching_storage_changes.take() {
+            state.handle.abort();
+        }
+        if !self.fetched.is_empty() {
+            log::info!(
+                "pruned {} fetched blocks ({} bytes)",
+                self.fetched.len(),
+                self.fetched_bytes
+            );
+        }
+        self.fetched.clear();
+        self.fetched_bytes = 0;
+    }
+
+    /// The encoded size of the blocks fetched but not dispatched yet.
+    pub fn fetched_bytes(&self) -> usize {
+        self.fetched_bytes
+    }
+
+    fn is_full(&self) -> bool {
+        self.memory_limit > 0 && self.fetched_bytes >= self.memory_limit
+    }
+
+    fn pop_front(&mut self) -> Option<BlockHeaderWithChanges> {
+        let block = self.fetched.pop_front()?;
+        self.fetched_bytes -= block.encoded_size();
+        Some(block)
+    }
+
+    fn push_front(&mut self, block: BlockHeaderWithChanges) {
+        self.fetched_bytes += block.encoded_size();
+        self.fetched.push_front(block);
+    }
+
+    /// Appends the blocks, dropping the ones that do not fit in the memory limit.
+    fn extend(&mut self, blocks: Vec<BlockHeaderWithChanges>) {
+        let mut blocks = blocks.into_iter();
+        for block in blocks.by_ref() {
+            let size = block.encoded_size();
+            if self.memory_limit > 0 && self.fetched_bytes + size > self.memory_limit {
+                log::info!(
+                    "prefetch memory limit ({} bytes) reached, dropping {} blocks from {}",
+                    self.memory_limit,
+                    blocks.len() + 1,
+                    block.block_header.number
+                );
+                break;
+            }
+            self.fetched_bytes += size;
+            self.fetched.push_back(block);
+        }
+    }
+
+    fn next_fetched(&self) -> Option<BlockNumber> {
+        self.fetched.back().map(|b| b.block_header.number + 1)
+    }
+
+    /// Takes the fetched blocks of `from..=to`, keeps the ones beyond. The ones before `from` are
+    /// dispatched already and dropped.
+    fn take_fetched(&mut self, from: BlockNumber, to: BlockNumber) -> Vec<BlockHeaderWithChanges> {
+        while matches!(self.fetched.front(), Some(b) if b.block_header.number < from) {
+            self.pop_front();
+        }
+        if !matches!(self.fetched.front(), Some(b) if b.block_header.number == from) {
+            self.fetched.clear();
+            self.fetched_bytes = 0;
+            return vec![];
+        }
+        let mut taken = vec![];
+        while matches!(self.fetched.front(), Some(b) if b.block_header.number <= to) {
+            taken.extend(self.pop_front());
+        }
+        taken
+    }
+
+    /// Returns the tail of the blocks fetched last, so that they are taken again by the next fetch.
+    pub fn put_back(&mut self, blocks: Vec<BlockHeaderWithChanges>) {
+        for block in blocks.into_iter().rev() {
+            self.push_front(block);
         }
     }
 
     pub async fn fetch_storage_changes(
         &mut self,
-        client: &RpcClient,
-        cache: Option<&crate::CacheClient>,
+        sources: &BlockSources,
         from: BlockNumber,
         to: BlockNumber,
     ) -> Result<Vec<BlockHeaderWithChanges>> {
         let count = to + 1 - from;
-        let result = if let Some(state) = self.prefetching_storage_changes.take() {
-            if state.from == from && state.to == to {
-                log::info!("use prefetched storage changes ({from}-{to})",);
-                state.handle.await?.ok()
-            } else {
-                log::info!(
-                    "cancelling the prefetch ({}-{}), requesting ({from}-{to})",
-                    state.from,
-                    state.to,
-                );
-                state.handle.abort();
-                None
+        let mut result = self.take_fetched(from, to);
+        if !result.is_empty() {
+            log::info!(
+                "use fetched storage changes ({from}-{})",
+                from + result.len() as BlockNumber - 1
+            );
+        }
+        let next = from + result.len() as BlockNumber;
+        if next <= to {
+            if let Some(state) = self.prefetching_storage_changes.take() {
+                if state.from == next {
+                    log::info!(
+                        "use prefetched storage changes ({}-{})",
+                        state.from,
+                        state.to
+                    );
+                    if let Ok(Ok(prefetched)) = state.handle.await {
+                        self.extend(prefetched);
+                    }
+                } else {
+                    log::info!(
+                        "cancelling the prefetch ({}-{}), requesting ({next}-{to})",
+                        state.from,
+                        state.to,
+                    );
+                    state.handle.abort();
+                }
             }
-        } else {
-            None
-        };
+            result.extend(self.take_fetched(next, to));
+        }
+        let next = from + result.len() as BlockNumber;
+        if next <= to {
+            let fetched = sources.storage_changes(next, to, self.with_root).await?;
+            result.extend(fetched);
+        }
 
-        let result = if let Some(result) = result {
-            result
-        } else {
-            crate::fetch_storage_changes(client, cache, from, to).await?
-        };
-        let next_from = from + count;
-        let next_to = next_from + count - 1;
-        let client = client.clone();
-        let cache = cache.cloned();
+        // Prefetch the next batch beyond what is fetched already.
+        let next_from = self.next_fetched().unwrap_or(to + 1);
+        let next_to = to + count;
+        if next_from > next_to {
+            return Ok(result);
+        }
+        if matches!(&self.prefetching_storage_changes, Some(state) if state.from == next_from) {
+            return Ok(result);
+        }
+        if self.is_full() {
+            return Ok(result);
+        }
+        if let Some(state) = self.prefetching_storage_changes.take() {
+            state.handle.abort();
+        }
+        let sources = sources.clone();
+        let with_root = self.with_root;
         self.prefetching_storage_changes = Some(StoragePrefetchState {
             from: next_from,
             to: next_to,
             handle: tokio::spawn(async move {
                 log::info!("prefetching ({next_from}-{next_to})");
-                crate::fetch_storage_changes(&client, cache.as_ref(), next_from, next_to)
-                    .await
+                sources.storage_changes(next_from, next_to, with_root).await
             }),
         });
         Ok(result)
@@ -0,0 +1,107 @@
+use crate::pccs::CollateralFetcher;
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use phactory_api::pruntime_client::CertVerifier;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use x509_cert::der::{asn1::ObjectIdentifier, Decode, Encode};
+use x509_cert::Certificate;
+
+/// The certificate extension carrying the SGX quote in the RA-TLS certificates made by Gramine.
+const SGX_QUOTE_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1.6");
+
+/// Verifies the RA-TLS certificate of a pRuntime server.
+///
+/// The certificate is self-signed, and carries a DCAP quote of the enclave whose report data
+/// starts with the SHA256 of the public key of the certificate. So the quote is verified with
+/// the collateral from PCCS, and the certificate checked to be the one it attests. The enclave
+/// must run one of the allowed pRuntime builds, a genuine enclave running anything else being
+/// rejected. The verified certificates are remembered, as pRuntime presents the same one at each
+/// connection.
+pub struct RaTlsVerifier {
+    collateral_fetcher: CollateralFetcher,
+    allowed_hashes: Vec<Vec<u8>>,
+    verified: Mutex<HashSet<[u8; 32]>>,
+}
+
+impl RaTlsVerifier {
+    pub fn new(
+        collateral_fetcher: CollateralFetcher,
+        allowed_hashes: Vec<Vec<u8>>,
+    ) -> Result<Self> {
+        if collateral_fetcher.is_empty() {
+            bail!("--pccs-url is required to verify the RA-TLS certificates");
+        }
+        if allowed_hashes.is_empty() {
+            bail!("--pruntime-allowed-hash is required to verify the RA-TLS certificates");
+        }
+        Ok(Self {
+            collateral_fetcher,
+            allowed_hashes,
+            verified: Default::default(),
+        })
+    }
+
+    /// Fails unless the pRuntime build attested is one of the allowed ones.
+    pub fn check_pruntime_hash(&self, pruntime_hash: &[u8]) -> Result<()> {
+        if !self.allowed_hashes.iter().any(|hash| hash == pruntime_hash) {
+            bail!(
+                "pRuntime build not allowed: 0x{}",
+                hex::encode(pruntime_hash)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CertVerifier for RaTlsVerifier {
+    fn is_verified(&self, cert: &[u8]) -> bool {
+        let cert_hash: [u8; 32] = Sha256::digest(cert).into();
+        self.verified.lock().unwrap().contains(&cert_hash)
+    }
+
+    async fn verify(&self, cert: &[u8]) -> Result<()> {
+        if self.is_verified(cert) {
+            return Ok(());
+        }
+        let cert_hash: [u8; 32] = Sha256::digest(cert).into();
+
+        let parsed = Certificate::from_der(cert).context("Invalid pRuntime certificate")?;
+        let quote = parsed
+            .tbs_certificate
+            .extensions
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .find(|ext| ext.extn_id == SGX_QUOTE_OID)
+            .ok_or_else(|| anyhow!("No SGX quote in the pRuntime certificate"))?
+            .extn_value
+            .as_bytes();
+        let collateral = self.collateral_fetcher.get_collateral(quote).await?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let (report_data, pruntime_hash, tcb_status, _) =
+            sgx_attestation::dcap::verify(quote, &collateral, now)
+                .map_err(|err| anyhow!("Invalid quote in the pRuntime certificate: {err:?}"))?;
+
+        let public_key = parsed
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()
+            .context("Invalid public key in the pRuntime certificate")?;
+        if report_data[..32] != Sha256::digest(public_key)[..] {
+            bail!("The pRuntime certificate is not the one attested by its quote");
+        }
+        self.check_pruntime_hash(&pruntime_hash)?;
+        info!(
+            "Verified the RA-TLS certificate of pRuntime 0x{}, tcb status: {tcb_status}",
+            hex::encode(pruntime_hash)
+        );
+        self.verified.lock().unwrap().insert(cert_hash);
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! Reloads the controller key and the operator on SIGHUP, re-reading the config file, so that they
+//! can be rotated without restarting pherry in the middle of a sync.
+
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
+use sp_core::crypto::AccountId32;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::sync::watch;
+
+use crate::keystore;
+use crate::signer::{KeyScheme, SignerKind};
+use crate::types::SrSigner;
+use crate::{config, preprocess_args, Args};
+
+/// The options reloaded on SIGHUP.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    operator: Option<String>,
+    mnemonic: String,
+    keystore: Option<String>,
+    scheme: KeyScheme,
+    signer: SignerKind,
+    signer_url: String,
+}
+
+impl Credentials {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            operator: args.operator.clone(),
+            mnemonic: args.mnemonic.clone(),
+            keystore: args.keystore.clone(),
+            scheme: args.scheme,
+            signer: args.signer,
+            signer_url: args.signer_url.clone(),
+        }
+    }
+
+    pub fn operator(&self) -> Result<Option<AccountId32>> {
+        self.operator
+            .as_deref()
+            .map(|operator| {
+                AccountId32::from_str(operator)
+                    .map_err(|e| anyhow!("Failed to parse operator address: {}", e))
+            })
+            .transpose()
+    }
+
+    /// Whether the signer depends on the public key of the worker, looked up in the keystore.
+    pub fn keyed_by_worker(&self) -> bool {
+        self.signer == SignerKind::Local && self.keystore.is_some()
+    }
+
+    /// The signer of the worker with `pubkey`, if known yet, see [`keystore`](crate::keystore).
+    pub async fn signer(&self, args: &Args, pubkey: Option<&str>) -> Result<SrSigner> {
+        match self.signer {
+            SignerKind::Local => match &self.keystore {
+                Some(dir) => {
+                    let (file, key) = keystore::read_key(dir, &args.pruntime_endpoint, pubkey)?;
+                    info!("Using the controller key in {}", file.display());
+                    SrSigner::from_string(self.scheme, &key)
+                }
+                None => SrSigner::from_string(self.scheme, &self.mnemonic),
+            },
+            SignerKind::Remote => {
+                if self.signer_url.is_empty() {
+                    bail!("--signer-url is required for --signer remote");
+                }
+                if args.heartbeat_interval > 0 {
+                    bail!("--heartbeat-interval is not supported with --signer remote");
+                }
+                SrSigner::remote(&self.signer_url).await
+            }
+        }
+    }
+
+    /// Checks what can be checked without reaching the remote signer.
+    fn validate(&self) -> Result<()> {
+        self.operator()?;
+        match (self.signer, &self.keystore) {
+            (SignerKind::Local, Some(dir)) => {
+                if !Path::new(dir).is_dir() {
+                    bail!("The keystore {dir} is not a directory");
+                }
+            }
+            (SignerKind::Local, None) => {
+                SrSigner::from_string(self.scheme, &self.mnemonic)?;
+            }
+            (SignerKind::Remote, _) => (),
+        }
+        Ok(())
+    }
+}
+
+fn reload() -> Result<Credentials> {
+    let (mut args, _) = config::parse_args(std::env::args_os())?;
+    preprocess_args(&mut args);
+    let credentials = Credentials::from_args(&args);
+    credentials.validate()?;
+    Ok(credentials)
+}
+
+/// Watches the credentials, reloaded on each SIGHUP until the receiver is dropped.
+pub fn listen(args: &Args) -> watch::Receiver<Credentials> {
+    let (tx, rx) = watch::channel(Credentials::from_args(args));
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                warn!("Failed to listen to SIGHUP, credentials reloading disabled: {err:?}");
+                return rx;
+            }
+        };
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    received = hangup.recv() => if received.is_none() {
+                        break;
+                    },
+                    () = tx.closed() => break,
+                }
+                info!("SIGHUP received, reloading the controller key and the operator");
+                match reload() {
+                    Ok(credentials) => {
+                        let changed = tx.send_if_modified(|current| {
+                            let changed = *current != credentials;
+                            *current = credentials;
+                            changed
+                        });
+                        if !changed {
+                            info!("The controller key and the operator are unchanged");
+                        }
+                    }
+                    Err(err) => warn!("Failed to reload, keeping the current ones: {err:?}"),
+                }
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    drop(tx);
+    rx
+}
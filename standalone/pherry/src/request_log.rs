@@ -0,0 +1,145 @@
+//! A replay log of the requests pherry sends to pRuntime, to reproduce a sync issue by feeding the
+//! exact same requests into a fresh pRuntime.
+//!
+//! Only the requests changing the pRuntime state are logged: the runtime init, the headers, the
+//! blocks and the storage proofs. Each session of pherry goes to its own file in the log directory,
+//! as a sequence of frames, each made of the method path then the encoded request body, both
+//! prefixed with their length as a little endian `u32`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use phactory_api::prpc::client::RequestClient;
+use phactory_api::pruntime_client::RequestRecorder;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The methods of the requests logged.
+pub const RECORDED_METHODS: &[&str] = &[
+    "PhactoryAPI.InitRuntime",
+    "PhactoryAPI.SyncHeader",
+    "PhactoryAPI.SyncParaHeader",
+    "PhactoryAPI.SyncCombinedHeaders",
+    "PhactoryAPI.DispatchBlocks",
+    "PhactoryAPI.LoadChainState",
+    "PhactoryAPI.LoadStorageProof",
+    "PhactoryAPI.LoadStorageProofAt",
+];
+
+/// A frame of the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// Appends the requests recorded to a session file.
+pub struct RequestLog {
+    file: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl RequestLog {
+    /// Creates the file of a new session in `dir`, named after the time it starts.
+    pub fn create(dir: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create the request log directory {dir}"))?;
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let file = Path::new(dir).join(format!("session-{started}-{}.log", std::process::id()));
+        let writer = File::options()
+            .create_new(true)
+            .write(true)
+            .open(&file)
+            .with_context(|| format!("Failed to create the request log {}", file.display()))?;
+        info!("Recording the requests to pRuntime in {}", file.display());
+        Ok(Self {
+            file,
+            writer: Mutex::new(BufWriter::new(writer)),
+        })
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+}
+
+impl RequestRecorder for RequestLog {
+    fn record(&self, path: &str, body: &[u8]) {
+        if !RECORDED_METHODS.contains(&path) {
+            return;
+        }
+        let mut writer = self.writer.lock().unwrap();
+        // Flushed on each frame, for the log to be complete up to the request pRuntime choked on.
+        let written = write_frame(&mut *writer, path, body).and_then(|()| writer.flush());
+        if let Err(err) = written {
+            warn!("Failed to record the request {path}: {err:?}");
+        }
+    }
+}
+
+fn write_frame(writer: &mut impl Write, path: &str, body: &[u8]) -> std::io::Result<()> {
+    for field in [path.as_bytes(), body] {
+        let len = u32::try_from(field.len())
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "Frame too large"))?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(field)?;
+    }
+    Ok(())
+}
+
+/// Reads a length prefixed field, `None` at the end of the log.
+fn read_field(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let mut field = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut field)?;
+    Ok(Some(field))
+}
+
+/// Reads all the frames of a session file, in order.
+pub fn read_frames(file: impl AsRef<Path>) -> Result<Vec<Frame>> {
+    let file = file.as_ref();
+    let mut reader = std::io::BufReader::new(
+        File::open(file).with_context(|| format!("Failed to open {}", file.display()))?,
+    );
+    let mut frames = vec![];
+    while let Some(path) = read_field(&mut reader)? {
+        let path = String::from_utf8(path)
+            .map_err(|_| anyhow!("Invalid method path in frame {}", frames.len()))?;
+        let Some(body) = read_field(&mut reader)? else {
+            bail!("Truncated frame {} of {path}", frames.len());
+        };
+        frames.push(Frame { path, body });
+    }
+    Ok(frames)
+}
+
+/// Sends the requests of a session file to pRuntime in order. Stops at the first one failing,
+/// unless `keep_going`, the requests rejected in the recorded session failing again on replay.
+/// Returns the number of the requests replayed and the number of them failed.
+pub async fn replay(
+    file: impl AsRef<Path>,
+    client: &impl RequestClient,
+    keep_going: bool,
+) -> Result<(usize, usize)> {
+    let frames = read_frames(file)?;
+    let mut failed = 0;
+    for (i, frame) in frames.iter().enumerate() {
+        if let Err(err) = client.request(&frame.path, frame.body.clone()).await {
+            if !keep_going {
+                bail!("Request {i} to {} failed: {err:?}", frame.path);
+            }
+            warn!("Request {i} to {} failed: {err:?}", frame.path);
+            failed += 1;
+        }
+    }
+    Ok((frames.len(), failed))
+}
@@ -0,0 +1,115 @@
+use crate::types::{ParachainApi, RoundSummary, SrSigner};
+use log::{info, warn};
+use phactory_api::prpc::PhactoryInfo;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct RoundStart {
+    at: Instant,
+    headernum: u32,
+    para_headernum: u32,
+    blocknum: u32,
+}
+
+/// Rounds dispatching more blocks are summarized without the fees, not to scan the events of
+/// all the blocks at once while catching up.
+const MAX_FEE_SCAN_BLOCKS: u32 = 2000;
+
+/// Accounts what pherry does in a round, for the round summaries posted to the notify endpoint.
+///
+/// A round starts at the first pRuntime state observed after the previous summary, and ends at the
+/// chain tip once `interval` has passed, so that consecutive summaries add up to the whole work.
+/// It outlives the bridge restarts, which are counted as errors of the round.
+pub struct RoundTracker {
+    interval: Duration,
+    start: Option<RoundStart>,
+    messages_submitted: u64,
+    errors: Arc<AtomicU64>,
+}
+
+impl RoundTracker {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            start: None,
+            messages_submitted: 0,
+            errors: Default::default(),
+        }
+    }
+
+    /// The error counter of the round, shared with the tasks reporting the transaction errors.
+    pub fn error_counter(&self) -> Arc<AtomicU64> {
+        self.errors.clone()
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_messages_submitted(&mut self, count: usize) {
+        self.messages_submitted += count as u64;
+    }
+
+    /// Starts a round at the pRuntime state if there is none going on.
+    pub fn observe(&mut self, info: &PhactoryInfo) {
+        if self.interval.is_zero() || self.start.is_some() {
+            return;
+        }
+        self.start = Some(RoundStart {
+            at: Instant::now(),
+            headernum: info.headernum,
+            para_headernum: info.para_headernum,
+            blocknum: info.blocknum,
+        });
+    }
+
+    /// Ends the round at the pRuntime state if it has lasted for the interval, returning its
+    /// summary.
+    pub async fn maybe_finish(
+        &mut self,
+        para_api: &ParachainApi,
+        signer: &SrSigner,
+        info: &PhactoryInfo,
+    ) -> Option<RoundSummary> {
+        if !matches!(&self.start, Some(start) if start.at.elapsed() >= self.interval) {
+            return None;
+        }
+        let start = self.start.take()?;
+        let fees_spent = fees_paid(para_api, signer, start.blocknum, info.blocknum).await;
+        let summary = RoundSummary {
+            duration_secs: start.at.elapsed().as_secs(),
+            headers_synced: info.headernum.saturating_sub(start.headernum),
+            para_headers_synced: info.para_headernum.saturating_sub(start.para_headernum),
+            blocks_dispatched: info.blocknum.saturating_sub(start.blocknum),
+            messages_submitted: std::mem::take(&mut self.messages_submitted),
+            errors: self.errors.swap(0, Ordering::Relaxed),
+            fees_spent,
+            headernum: info.headernum,
+            para_headernum: info.para_headernum,
+            blocknum: info.blocknum,
+        };
+        info!("Round summary: {summary:?}");
+        // The next round starts right here.
+        self.start = Some(RoundStart {
+            at: Instant::now(),
+            headernum: info.headernum,
+            para_headernum: info.para_headernum,
+            blocknum: info.blocknum,
+        });
+        Some(summary)
+    }
+}
+
+async fn fees_paid(para_api: &ParachainApi, signer: &SrSigner, from: u32, to: u32) -> Option<u128> {
+    if to.saturating_sub(from) > MAX_FEE_SCAN_BLOCKS {
+        return None;
+    }
+    match para_api.fees_paid(signer.account_id(), from, to).await {
+        Ok(fees) => Some(fees),
+        Err(err) => {
+            warn!("Failed to get the fees paid by the controller account: {err:?}");
+            None
+        }
+    }
+}
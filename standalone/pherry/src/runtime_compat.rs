@@ -0,0 +1,103 @@
+use crate::types::ParachainApi;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use phaxt::dynamic::tx::{call_index, CALLS};
+use std::time::{Duration, Instant};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Watches the parachain runtime version and keeps the client metadata up to date, so that the
+/// dynamic extrinsics keep encoding correctly after a runtime upgrade.
+pub struct RuntimeWatcher {
+    spec_version: u32,
+    transaction_version: u32,
+    last_checked_at: Instant,
+}
+
+impl RuntimeWatcher {
+    pub fn new(api: &ParachainApi) -> Self {
+        let version = api.runtime_version();
+        info!(
+            "Parachain runtime spec_version={}, transaction_version={}",
+            version.spec_version, version.transaction_version
+        );
+        log_compatibility_report(api);
+        Self {
+            spec_version: version.spec_version,
+            transaction_version: version.transaction_version,
+            last_checked_at: Instant::now(),
+        }
+    }
+
+    /// Checks the on-chain runtime version at most once per `CHECK_INTERVAL`. Refreshes the
+    /// metadata in place and logs a compatibility report if the runtime has been upgraded.
+    pub async fn check(&mut self, api: &ParachainApi) -> Result<()> {
+        if self.last_checked_at.elapsed() < CHECK_INTERVAL {
+            return Ok(());
+        }
+        self.last_checked_at = Instant::now();
+
+        let onchain = api
+            .rpc()
+            .runtime_version(None)
+            .await
+            .context("Failed to get the runtime version")?;
+        if onchain.spec_version == self.spec_version
+            && onchain.transaction_version == self.transaction_version
+        {
+            return Ok(());
+        }
+        info!(
+            "Parachain runtime upgraded, spec_version: {} -> {}, transaction_version: {} -> {}",
+            self.spec_version,
+            onchain.spec_version,
+            self.transaction_version,
+            onchain.transaction_version
+        );
+
+        // The background updater spawned in `phaxt::connect` normally has refreshed the client
+        // already. Do it ourselves in case it lags behind or has exited.
+        let local = api.runtime_version();
+        if local.spec_version != onchain.spec_version
+            || local.transaction_version != onchain.transaction_version
+        {
+            info!("Refreshing the parachain metadata");
+            let metadata = api
+                .rpc()
+                .metadata()
+                .await
+                .context("Failed to fetch the metadata")?;
+            api.set_metadata(metadata);
+            api.set_runtime_version(onchain.clone());
+        }
+
+        self.spec_version = onchain.spec_version;
+        self.transaction_version = onchain.transaction_version;
+        log_compatibility_report(api);
+        Ok(())
+    }
+}
+
+/// Checks that all the calls pherry constructs exist in the current metadata.
+///
+/// Returns false if any of them is missing.
+pub fn log_compatibility_report(api: &ParachainApi) -> bool {
+    let metadata = api.metadata();
+    let mut compatible = true;
+    info!("Runtime compatibility report:");
+    for (pallet_name, call_name) in CALLS {
+        match call_index(&metadata, pallet_name, call_name) {
+            Ok((pallet_index, call_index)) => {
+                info!("  {pallet_name}.{call_name}: ok ({pallet_index}, {call_index})");
+            }
+            Err(err) => {
+                compatible = false;
+                warn!("  {pallet_name}.{call_name}: unavailable, {err}");
+            }
+        }
+    }
+    if !compatible {
+        warn!("Some calls are unavailable in the current runtime, submitting them would fail");
+    }
+    compatible
+}
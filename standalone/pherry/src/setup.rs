@@ -0,0 +1,377 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use phactory_api::pruntime_client;
+use std::io::{BufRead, IsTerminal, Write};
+use std::time::Duration;
+
+use crate::{
+    inspect::Report,
+    resolve_allowed_start_header, resolve_start_header, subxt_connect,
+    types::{BlockNumber, ParachainApi, RelaychainApi, SrSigner},
+    Args, RaOption, DEV_MNEMONIC,
+};
+
+/// Asks the questions of the wizard on the terminal, or takes the defaults if there is none.
+struct Prompter {
+    interactive: bool,
+}
+
+impl Prompter {
+    fn ask(&self, question: &str, default: &str) -> Result<String> {
+        if !self.interactive {
+            return Ok(default.to_string());
+        }
+        if default.is_empty() {
+            print!("{question}: ");
+        } else {
+            print!("{question} [{default}]: ");
+        }
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer)?;
+        let answer = answer.trim();
+        Ok(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer.to_string()
+        })
+    }
+
+    /// Asks for a secret without echoing it. The default is not shown either.
+    fn ask_secret(&self, question: &str, default: &str) -> Result<String> {
+        if !self.interactive {
+            return Ok(default.to_string());
+        }
+        let hint = if default.is_empty() {
+            ""
+        } else {
+            " [as given on the command line]"
+        };
+        let answer = rpassword::prompt_password(format!("{question}{hint}: "))?;
+        let answer = answer.trim();
+        Ok(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer.to_string()
+        })
+    }
+
+    fn confirm(&self, question: &str) -> Result<bool> {
+        let answer = self.ask(&format!("{question} (y/n)"), "n")?;
+        Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+    }
+}
+
+/// The options found by the wizard, written to the config file.
+#[derive(Default)]
+struct Setup {
+    relaychain_ws_endpoint: String,
+    parachain: bool,
+    parachain_ws_endpoint: Option<String>,
+    pruntime_endpoint: String,
+    start_header: Option<BlockNumber>,
+    attestation_provider: Option<RaOption>,
+    pccs_url: Option<String>,
+    mnemonic: String,
+}
+
+impl Setup {
+    fn render(&self, args: &Args) -> Result<String> {
+        let mut table = toml::value::Table::new();
+        let mut set = |key: &str, value: toml::Value| {
+            table.insert(key.into(), value);
+        };
+        set(
+            "relaychain_ws_endpoint",
+            self.relaychain_ws_endpoint.clone().into(),
+        );
+        set("parachain", self.parachain.into());
+        if let Some(endpoint) = &self.parachain_ws_endpoint {
+            set("parachain_ws_endpoint", endpoint.clone().into());
+        }
+        set("pruntime_endpoint", self.pruntime_endpoint.clone().into());
+        if let Some(start_header) = self.start_header {
+            set("start_header", i64::from(start_header).into());
+        }
+        if let Some(provider) = &self.attestation_provider {
+            set("attestation_provider", value_name(provider)?.into());
+        }
+        if let Some(url) = &self.pccs_url {
+            set("pccs_url", url.clone().into());
+        }
+        set("mnemonic", self.mnemonic.clone().into());
+        set("scheme", value_name(&args.scheme)?.into());
+        Ok(toml::to_string(&table)?)
+    }
+}
+
+fn value_name(value: &impl ValueEnum) -> Result<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .ok_or_else(|| anyhow!("Option value has no name"))
+}
+
+/// Walks a new operator through the options of pherry, probing the nodes, the pRuntime and the
+/// PCCS on the way, and writes the result to a config file to run pherry with `--config`.
+///
+/// The options given on the command line are the defaults of the questions. Without a terminal
+/// no question is asked, the defaults are probed and written as they are. `yes` takes the defaults
+/// as well, and overwrites `output` if it exists.
+pub async fn setup(args: &Args, output: &str, yes: bool) -> Result<()> {
+    let prompter = Prompter {
+        interactive: !yes && std::io::stdin().is_terminal(),
+    };
+    let mut report = Report::default();
+    let mut setup = Setup::default();
+
+    let para_uri = prompter.ask(
+        "Phala node (parachain or solo chain) websocket endpoint",
+        if args.parachain {
+            &args.parachain_ws_endpoint
+        } else {
+            &args.relaychain_ws_endpoint
+        },
+    )?;
+    let para_api = subxt_connect(&para_uri)
+        .await
+        .with_context(|| format!("Failed to connect to {para_uri}"))?;
+    let chain_info = phaxt::chain_info(&para_api).await?;
+    let api = match chain_info.para_id {
+        Some(para_id) => {
+            report.ok(format!("{para_uri} is a parachain node, para id {para_id}"));
+            let relay_uri = prompter.ask(
+                "Relaychain websocket endpoint",
+                &args.relaychain_ws_endpoint,
+            )?;
+            let api = subxt_connect(&relay_uri)
+                .await
+                .with_context(|| format!("Failed to connect to {relay_uri}"))?;
+            report.ok(format!("Connected to relaychain node {relay_uri}"));
+            setup.parachain = true;
+            setup.relaychain_ws_endpoint = relay_uri;
+            setup.parachain_ws_endpoint = Some(para_uri);
+            api
+        }
+        None => {
+            report.ok(format!("{para_uri} is a solo chain node"));
+            setup.relaychain_ws_endpoint = para_uri;
+            para_api.clone()
+        }
+    };
+
+    setup.start_header = suggest_start_header(&mut report, &api, &para_api, setup.parachain).await;
+
+    setup.pruntime_endpoint = prompter.ask("pRuntime endpoint", &args.pruntime_endpoint)?;
+    let methods = check_pruntime(&mut report, &setup.pruntime_endpoint).await;
+    if let Some(methods) = methods {
+        let provider = pick_attestation_provider(&mut report, &methods);
+        if let Some(RaOption::Dcap) = provider {
+            let url = prompter.ask("PCCS url", &args.pccs_url)?;
+            check_pccs(&mut report, &url, args.pccs_timeout).await;
+            setup.pccs_url = Some(url);
+        }
+        setup.attestation_provider = provider;
+    }
+
+    // The dev key is the default of --mnemonic, not to end up in the config of a real worker.
+    let mnemonic = if args.mnemonic == DEV_MNEMONIC && !args.dev {
+        ""
+    } else {
+        &args.mnemonic
+    };
+    setup.mnemonic = prompter.ask_secret("Controller account mnemonic or seed", mnemonic)?;
+    if setup.mnemonic.is_empty() {
+        bail!("No controller key given, give it with --mnemonic");
+    }
+    check_balance(&mut report, &para_api, args, &setup.mnemonic).await;
+
+    report.print();
+    if report.has_error() && !prompter.confirm("Some checks failed, write the config anyway?")? {
+        bail!("Setup aborted");
+    }
+    if std::path::Path::new(output).exists()
+        && !yes
+        && !prompter.confirm(&format!("{output} exists, overwrite it?"))?
+    {
+        bail!("{output} exists, not overwriting it");
+    }
+    write_config(output, &setup.render(args)?)?;
+    println!("Config written to {output}, run pherry with `--config {output}`");
+    Ok(())
+}
+
+/// Suggests the start header the worker would register with, None to let pherry decide at start.
+async fn suggest_start_header(
+    report: &mut Report,
+    api: &RelaychainApi,
+    para_api: &ParachainApi,
+    is_parachain: bool,
+) -> Option<BlockNumber> {
+    let allowed = match para_api.relaychain_genesis_allowlist().await {
+        Ok(allowlist) if !allowlist.is_empty() => {
+            resolve_allowed_start_header(api, para_api, None, None).await
+        }
+        _ => resolve_start_header(para_api, is_parachain, None).await,
+    };
+    match allowed {
+        Ok(number) => {
+            report.ok(format!("Suggested start header {number}"));
+            Some(number)
+        }
+        Err(err) => {
+            report.warn(
+                format!("Failed to resolve the start header: {err:#}"),
+                "pherry resolves it at start, or set --start-header in the config",
+            );
+            None
+        }
+    }
+}
+
+/// Returns the attestation methods supported by the pRuntime, None if it's not reachable.
+async fn check_pruntime(report: &mut Report, endpoint: &str) -> Option<Vec<String>> {
+    let pr = pruntime_client::new_pruntime_client(endpoint.to_string());
+    match pr.get_info(()).await {
+        Ok(info) => {
+            report.ok(format!(
+                "pRuntime {} ({}) at {endpoint}{}",
+                info.version,
+                info.git_revision,
+                if info.initialized {
+                    ", initialized already"
+                } else {
+                    ""
+                }
+            ));
+            Some(info.supported_attestation_methods)
+        }
+        Err(err) => {
+            report.error(
+                format!("Failed to reach pRuntime at {endpoint}: {err}"),
+                "start pRuntime first, or check the endpoint",
+            );
+            None
+        }
+    }
+}
+
+fn pick_attestation_provider(report: &mut Report, methods: &[String]) -> Option<RaOption> {
+    let has = |method: &str| methods.iter().any(|m| m == method);
+    // pRuntime versions lower than 2.2.0 always return an empty list.
+    if methods.is_empty() || has("epid") {
+        report.ok("pRuntime attests with IAS");
+        Some(RaOption::Ias)
+    } else if has("dcap") {
+        report.ok("pRuntime attests with DCAP");
+        Some(RaOption::Dcap)
+    } else {
+        report.error(
+            format!("pRuntime supports no remote attestation, only {methods:?}"),
+            "run pRuntime in an SGX enclave to register the worker",
+        );
+        None
+    }
+}
+
+async fn check_pccs(report: &mut Report, urls: &str, timeout_secs: u64) {
+    let urls: Vec<_> = urls
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .collect();
+    if urls.is_empty() {
+        report.error(
+            "No PCCS url given",
+            "DCAP attestation requires a PCCS server to get the collateral from",
+        );
+        return;
+    }
+    let client = match reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_secs(timeout_secs))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            report.error(
+                format!("Failed to create http client: {err}"),
+                "check the TLS support of the system",
+            );
+            return;
+        }
+    };
+    for url in urls {
+        let probe = format!("{}/rootcacrl", url.trim_end_matches('/'));
+        match client.get(&probe).send().await {
+            Ok(response) if response.status().is_success() => {
+                report.ok(format!("PCCS {url} is reachable"))
+            }
+            Ok(response) => report.error(
+                format!("PCCS {url} responded {}", response.status()),
+                "check the PCCS url, it should be like https://<host>:8081/sgx/certification/v4",
+            ),
+            Err(err) => report.error(
+                format!("Failed to reach PCCS {url}: {err}"),
+                "check the PCCS server is running and reachable",
+            ),
+        }
+    }
+}
+
+async fn check_balance(report: &mut Report, para_api: &ParachainApi, args: &Args, secret: &str) {
+    let signer = match SrSigner::from_string(args.scheme, secret) {
+        Ok(signer) => signer,
+        Err(err) => {
+            report.error(
+                format!("Invalid controller key: {err:#}"),
+                "give a mnemonic, a hex seed or a derive path like //Alice",
+            );
+            return;
+        }
+    };
+    let chain_info = match phaxt::chain_info(para_api).await {
+        Ok(chain_info) => chain_info,
+        Err(err) => {
+            report.error(
+                format!("Failed to get the chain info: {err:#}"),
+                "check the node is synced and reachable",
+            );
+            return;
+        }
+    };
+    match para_api.free_balance(signer.account_id()).await {
+        Ok(free) if free <= chain_info.existential_deposit => report.error(
+            format!(
+                "Controller account {} has no balance to pay the fees",
+                signer.account_id()
+            ),
+            "transfer some tokens to the controller account",
+        ),
+        Ok(free) => report.ok(format!(
+            "Controller account {} has {}",
+            signer.account_id(),
+            chain_info.format_balance(free)
+        )),
+        Err(err) => report.error(
+            format!("Failed to get the balance: {err:#}"),
+            "check the node is synced and reachable",
+        ),
+    }
+}
+
+/// Writes the config, readable by the owner only as it holds the controller key.
+fn write_config(path: &str, content: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {path}"))?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
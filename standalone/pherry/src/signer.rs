@@ -0,0 +1,304 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use phaxt::{
+    subxt::{
+        config::ExtrinsicParams as _,
+        tx::{PairSigner, Signer, SubmittableExtrinsic, TxPayload},
+        utils::{MultiAddress, MultiSignature},
+    },
+    AccountId, Config, ExtrinsicParams, ExtrinsicParamsBuilder, Index, RpcClient,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sp_core::{blake2_256, ecdsa, ed25519, sr25519, Pair, H256};
+use std::time::Duration;
+
+use crate::types::ParachainApi;
+
+const REMOTE_SIGN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the extrinsics get signed.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignerKind {
+    /// With the key given by `--mnemonic`.
+    Local,
+    /// By the remote signing service at `--signer-url`.
+    Remote,
+}
+
+/// The signature scheme of the controller key given by `--mnemonic`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyScheme {
+    Sr25519,
+    Ed25519,
+    /// A secp256k1 key, such as a private key of an Ethereum account given as a 0x prefixed hex
+    /// seed. The account id is the blake2_256 hash of the compressed public key, as usual in
+    /// Substrate, not the Ethereum address.
+    Ecdsa,
+}
+
+enum Backend {
+    Sr25519(PairSigner<Config, sr25519::Pair>),
+    Ed25519(PairSigner<Config, ed25519::Pair>),
+    Ecdsa(PairSigner<Config, ecdsa::Pair>),
+    Remote(RemoteSigner),
+}
+
+/// The signer of the extrinsics sent by pherry, tracking the nonce of its account.
+pub struct SrSigner {
+    account_id: AccountId,
+    nonce: Index,
+    backend: Backend,
+}
+
+impl SrSigner {
+    pub fn new(pair: sr25519::Pair) -> Self {
+        Self::local(pair, Backend::Sr25519)
+    }
+
+    /// Creates a signer with the key of `scheme` from a mnemonic, seed or derive path.
+    pub fn from_string(scheme: KeyScheme, secret: &str) -> Result<Self> {
+        let signer = match scheme {
+            KeyScheme::Sr25519 => Self::local(parse_pair(secret)?, Backend::Sr25519),
+            KeyScheme::Ed25519 => Self::local(parse_pair(secret)?, Backend::Ed25519),
+            KeyScheme::Ecdsa => Self::local(parse_pair(secret)?, Backend::Ecdsa),
+        };
+        info!("Using {scheme:?} controller account {}", signer.account_id);
+        Ok(signer)
+    }
+
+    fn local<P>(pair: P, backend: impl FnOnce(PairSigner<Config, P>) -> Backend) -> Self
+    where
+        P: Pair,
+        PairSigner<Config, P>: Signer<Config>,
+    {
+        let signer = PairSigner::new(pair);
+        Self {
+            account_id: signer.account_id().clone(),
+            nonce: 0,
+            backend: backend(signer),
+        }
+    }
+
+    /// Creates a signer backed by a remote signing service, asking it for the account to sign with.
+    pub async fn remote(url: &str) -> Result<Self> {
+        let remote = RemoteSigner::new(url);
+        let account_id = remote.account().await?;
+        info!("Using remote signer {url}, account {account_id}");
+        Ok(Self {
+            account_id,
+            nonce: 0,
+            backend: Backend::Remote(remote),
+        })
+    }
+
+    pub fn increment_nonce(&mut self) {
+        self.nonce += 1;
+    }
+
+    pub fn nonce(&self) -> Index {
+        self.nonce
+    }
+
+    pub fn set_nonce(&mut self, nonce: Index) {
+        self.nonce = nonce;
+    }
+
+    pub fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    /// Creates the extrinsic of `call` signed with the current nonce.
+    pub async fn create_signed<Call: TxPayload>(
+        &self,
+        api: &ParachainApi,
+        call: &Call,
+        params: ExtrinsicParamsBuilder,
+    ) -> Result<SubmittableExtrinsic<Config, RpcClient>> {
+        let tx = api.tx();
+        let remote = match &self.backend {
+            Backend::Sr25519(signer) => {
+                return Ok(tx.create_signed_with_nonce(call, signer, self.nonce, params)?)
+            }
+            Backend::Ed25519(signer) => {
+                return Ok(tx.create_signed_with_nonce(call, signer, self.nonce, params)?)
+            }
+            Backend::Ecdsa(signer) => {
+                return Ok(tx.create_signed_with_nonce(call, signer, self.nonce, params)?)
+            }
+            Backend::Remote(remote) => remote,
+        };
+        let call_data = call.encode_call_data(&api.metadata())?;
+        // The payload before the long ones get hashed, for the service to check the call in it.
+        let runtime = api.runtime_version();
+        let extra = <ExtrinsicParams as phaxt::subxt::config::ExtrinsicParams<Index, H256>>::new(
+            runtime.spec_version,
+            runtime.transaction_version,
+            self.nonce,
+            api.genesis_hash(),
+            params.clone(),
+        );
+        let mut payload = call_data.clone();
+        extra.encode_extra_to(&mut payload);
+        extra.encode_additional_to(&mut payload);
+        let partial = tx.create_partial_signed_with_nonce(call, self.nonce, params)?;
+        if signed_message(&payload) != partial.signer_payload() {
+            bail!("BUG: signer payload mismatch");
+        }
+        let signature = remote.sign(&self.account_id, &call_data, &payload).await?;
+        let address = MultiAddress::Id(self.account_id.clone());
+        Ok(partial.sign_with_address_and_signature(&address, &MultiSignature::Sr25519(signature)))
+    }
+}
+
+/// A signature of a message off chain, with the public key it checks against.
+pub struct MessageSignature {
+    pub scheme: KeyScheme,
+    pub public: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl SrSigner {
+    /// Signs `message` off chain with the controller key. The remote signer is not supported, as
+    /// it only signs the allowlisted calls.
+    pub fn sign_message(&self, message: &[u8]) -> Result<MessageSignature> {
+        fn sign<P: Pair>(scheme: KeyScheme, pair: &P, message: &[u8]) -> MessageSignature {
+            MessageSignature {
+                scheme,
+                public: pair.public().as_ref().to_vec(),
+                signature: pair.sign(message).as_ref().to_vec(),
+            }
+        }
+        Ok(match &self.backend {
+            Backend::Sr25519(signer) => sign(KeyScheme::Sr25519, signer.signer(), message),
+            Backend::Ed25519(signer) => sign(KeyScheme::Ed25519, signer.signer(), message),
+            Backend::Ecdsa(signer) => sign(KeyScheme::Ecdsa, signer.signer(), message),
+            Backend::Remote(_) => bail!("The remote signer doesn't sign messages off chain"),
+        })
+    }
+}
+
+fn parse_pair<P: Pair>(secret: &str) -> Result<P> {
+    P::from_string(secret, None).map_err(|err| anyhow!("Bad controller key: {err:?}"))
+}
+
+/// The message actually signed for the signer `payload` of an extrinsic, which is its blake2_256
+/// hash if longer than 256 bytes.
+pub fn signed_message(payload: &[u8]) -> Vec<u8> {
+    if payload.len() > 256 {
+        blake2_256(payload).to_vec()
+    } else {
+        payload.to_vec()
+    }
+}
+
+/// A JSON-RPC 2.0 over HTTP client of a remote signing service, which holds the key of the
+/// controller account so that it never needs to be on the worker host.
+///
+/// The service implements two methods:
+/// - `signer_account`, taking no params and returning the hex encoded sr25519 public key of the
+///   account it signs for.
+/// - `signer_sign`, taking `{ account, call, call_hash, payload }` and returning the hex encoded
+///   sr25519 signature of the [`signed_message`] of `payload`. `call` is the SCALE encoded call
+///   and `call_hash` its blake2_256 hash, against which the service checks its allowlist.
+///   `payload` is the signer payload in full, the call followed by the signed extensions, so the
+///   service checks it starts with `call` before signing it.
+pub struct RemoteSigner {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct SignParams {
+    account: String,
+    call: String,
+    call_hash: String,
+    payload: String,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Vec<u8>> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let response = self
+            .client
+            .post(&self.url)
+            .header("content-type", "application/json")
+            .body(serde_json::to_vec(&body)?)
+            .timeout(REMOTE_SIGN_TIMEOUT)
+            .send()
+            .await
+            .with_context(|| format!("Failed to request {method} from the remote signer"))?;
+        if !response.status().is_success() {
+            bail!("Remote signer responded {} to {method}", response.status());
+        }
+        let response: RpcResponse = serde_json::from_slice(&response.bytes().await?)
+            .with_context(|| format!("Invalid response of {method} from the remote signer"))?;
+        if let Some(error) = response.error {
+            bail!("Remote signer refused {method}: {error}");
+        }
+        let result = match response.result {
+            Some(Value::String(result)) => result,
+            other => bail!("Unexpected result of {method} from the remote signer: {other:?}"),
+        };
+        hex::decode(result.trim_start_matches("0x"))
+            .with_context(|| format!("Invalid hex in the result of {method}"))
+    }
+
+    pub async fn account(&self) -> Result<AccountId> {
+        let public: [u8; 32] = self
+            .request("signer_account", json!([]))
+            .await?
+            .try_into()
+            .map_err(|_| anyhow!("Invalid account from the remote signer"))?;
+        Ok(public.into())
+    }
+
+    /// Has the service sign the signer `payload` of `call`, checking the signature returned.
+    pub async fn sign(&self, account: &AccountId, call: &[u8], payload: &[u8]) -> Result<[u8; 64]> {
+        if !payload.starts_with(call) {
+            bail!("The signer payload doesn't start with the call");
+        }
+        let params = SignParams {
+            account: format!("0x{}", hex::encode(account.0)),
+            call: format!("0x{}", hex::encode(call)),
+            call_hash: format!("0x{}", hex::encode(blake2_256(call))),
+            payload: format!("0x{}", hex::encode(payload)),
+        };
+        let signature = self
+            .request("signer_sign", serde_json::to_value(params)?)
+            .await?;
+        let signature: [u8; 64] = signature
+            .try_into()
+            .map_err(|_| anyhow!("Invalid signature from the remote signer"))?;
+        let public = sr25519::Public::from_raw(account.0);
+        let valid = <sr25519::Pair as sp_core::Pair>::verify(
+            &sr25519::Signature::from_raw(signature),
+            signed_message(payload),
+            &public,
+        );
+        if !valid {
+            bail!("Bad signature from the remote signer");
+        }
+        Ok(signature)
+    }
+}
@@ -0,0 +1,124 @@
+use crate::types::{BlockNumber, StallStatus};
+use log::error;
+use phactory_api::prpc::PhactoryInfo;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What to do when the sync stalls.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StallAction {
+    /// Log the diagnosis and report it to the notify endpoint.
+    Warn,
+    /// Also restart the bridge, counted as a failure by `--auto-restart`.
+    Restart,
+    /// Also exit with code 3.
+    Halt,
+}
+
+/// The error the bridge exits with when the sync stalls and `--stall-action` is not `warn`.
+#[derive(Debug)]
+pub struct Stalled(pub StallStatus);
+
+impl fmt::Display for Stalled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pRuntime made no progress in {}s despite {} dispatches, last dispatched: {}",
+            self.0.stalled_secs, self.0.dispatches, self.0.last_operation
+        )
+    }
+}
+
+impl std::error::Error for Stalled {}
+
+type Progress = (BlockNumber, BlockNumber, BlockNumber);
+
+fn progress_of(info: &PhactoryInfo) -> Progress {
+    (info.headernum, info.para_headernum, info.blocknum)
+}
+
+/// Detects pRuntime accepting the dispatched headers or blocks without advancing.
+///
+/// The progress counts as stalled if none of headernum, para_headernum and blocknum have advanced
+/// within the timeout, while headers or blocks were dispatched. Idling at the chain tip is not a
+/// stall.
+pub struct StallDetector {
+    timeout: Duration,
+    progress: Option<Progress>,
+    progressed_at: Instant,
+    dispatches: u32,
+    last_operation: String,
+}
+
+impl StallDetector {
+    /// A zero `timeout` disables the detection.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            progress: None,
+            progressed_at: Instant::now(),
+            dispatches: 0,
+            last_operation: String::new(),
+        }
+    }
+
+    /// Records the progress reported by pRuntime. Returns the stall if the timeout is reached,
+    /// after which the window starts over.
+    pub fn observe(&mut self, info: &PhactoryInfo) -> Option<StallStatus> {
+        let progress = progress_of(info);
+        let advanced = match self.progress {
+            Some(last) => progress.0 > last.0 || progress.1 > last.1 || progress.2 > last.2,
+            None => true,
+        };
+        self.progress = Some(progress);
+        if advanced {
+            self.reset();
+            return None;
+        }
+        let stalled = self.progressed_at.elapsed();
+        if self.timeout.is_zero() || self.dispatches == 0 || stalled < self.timeout {
+            return None;
+        }
+        let status = StallStatus {
+            stalled_secs: stalled.as_secs(),
+            dispatches: self.dispatches,
+            last_operation: self.last_operation.clone(),
+            headernum: info.headernum,
+            para_headernum: info.para_headernum,
+            blocknum: info.blocknum,
+        };
+        self.reset();
+        Some(status)
+    }
+
+    /// Records a dispatch of headers or blocks.
+    pub fn dispatched(&mut self, operation: impl fmt::Display) {
+        self.dispatches += 1;
+        self.last_operation = operation.to_string();
+    }
+
+    /// Starts the window over, e.g. when idling at the chain tip.
+    pub fn reset(&mut self) {
+        self.progressed_at = Instant::now();
+        self.dispatches = 0;
+    }
+}
+
+/// Logs what the stall looks like, and where to look.
+pub fn log_diagnosis(status: &StallStatus) {
+    error!("Sync stalled: {}", Stalled(status.clone()));
+    error!(
+        "pRuntime stays at headernum={} para_headernum={} blocknum={}",
+        status.headernum, status.para_headernum, status.blocknum
+    );
+    let hint = if status.last_operation == "Block" {
+        format!(
+            "The blocks are accepted but not applied, check the storage changes of block {} and the pRuntime log",
+            status.blocknum
+        )
+    } else {
+        "The headers are accepted but not applied, check the justifications and the pRuntime log"
+            .to_string()
+    };
+    error!("{hint}");
+}
@@ -0,0 +1,93 @@
+use crate::types::{BlockNumber, ParachainApi};
+use anyhow::{bail, Result};
+use log::info;
+use phactory_api::blocks::{BlockHeaderWithChanges, RuntimeHasher};
+use phala_trie_storage::TrieStorage;
+use sp_core::H256;
+
+/// The number of headers fetched at a time from the node.
+const HEADERS_IN_FLIGHT: usize = 8;
+
+/// Replays the storage changes on a local copy of the parachain state before they are
+/// dispatched, refusing any block whose resulting state root differs from the one in its
+/// on-chain header.
+///
+/// The state is fetched from the node at the block before the first one verified, so the node
+/// must still hold the state of that block. It is fetched again whenever the blocks to verify
+/// don't follow the last verified one, e.g. after a failed dispatch.
+///
+/// The headers of the blocks verified are fetched from the node, never from the headers cache the
+/// storage changes may come from.
+#[derive(Default)]
+pub struct StorageChangesVerifier {
+    storage: TrieStorage<RuntimeHasher>,
+    next_block: Option<BlockNumber>,
+}
+
+impl StorageChangesVerifier {
+    async fn load_state(&mut self, api: &ParachainApi, next_block: BlockNumber) -> Result<()> {
+        let at = next_block.saturating_sub(1);
+        info!("Loading the parachain state at block {at} to verify the storage changes");
+        let hash = crate::get_header_hash(api, Some(at)).await?;
+        let pairs = crate::chain_client::fetch_storage_at(api, Some(hash)).await?;
+        let children = crate::chain_client::fetch_child_storage_at(api, hash, &pairs).await?;
+        self.storage
+            .load_with_children(pairs.into_iter(), children.into_iter());
+        let (header, _) = crate::get_header_at(api, Some(at)).await?;
+        if *self.storage.root() != header.state_root {
+            self.next_block = None;
+            bail!(
+                "Loaded state at block {at} mismatches its header, root={:?}, expected={:?}",
+                self.storage.root(),
+                header.state_root
+            );
+        }
+        info!("Loaded the parachain state at block {at}");
+        self.next_block = Some(next_block);
+        Ok(())
+    }
+
+    /// Checks the storage changes of consecutive blocks against their on-chain headers.
+    pub async fn verify(
+        &mut self,
+        api: &ParachainApi,
+        blocks: &[BlockHeaderWithChanges],
+    ) -> Result<()> {
+        let first = match blocks.first() {
+            Some(block) => block.block_header.number,
+            None => return Ok(()),
+        };
+        if self.next_block != Some(first) {
+            self.load_state(api, first).await?;
+        }
+        let last = first + blocks.len() as BlockNumber - 1;
+        let headers =
+            crate::get_parachain_headers(api, None, first, last, HEADERS_IN_FLIGHT).await?;
+        for (offset, block) in blocks.iter().enumerate() {
+            let number = block.block_header.number;
+            if self.next_block != Some(number) {
+                self.next_block = None;
+                bail!("Storage changes not consecutive, got block {number}");
+            }
+            let Some(header) = headers.get(offset) else {
+                bail!("Header of block {number} not found");
+            };
+            let changes = &block.storage_changes;
+            let (root, transaction) = self.storage.calc_root_if_changes(
+                &changes.main_storage_changes,
+                &changes.child_storage_changes,
+            );
+            let reported = block.block_header.state_root;
+            if root != header.state_root || (reported != H256::zero() && reported != root) {
+                // Keep the verified state, the block is fetched again in the next round.
+                bail!(
+                    "State root mismatch at block {number}, computed={root:?}, reported={reported:?}, on-chain={:?}",
+                    header.state_root
+                );
+            }
+            self.storage.apply_changes(root, transaction);
+            self.next_block = Some(number + 1);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use crate::types::{BlockNumber, ParachainApi, SyncProgress};
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Samples older than this are dropped from the throughput window.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(600);
+const TIP_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const MIN_LOG_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_LOG_INTERVAL: Duration = Duration::from_secs(1800);
+
+/// Estimates how long the block sync takes to reach the chain tip from the rolling throughput.
+///
+/// The ETA is logged with a progressive backoff: frequently right after startup, then less and
+/// less often during a long catch-up. The interval is reset once the chain tip is reached.
+pub struct SyncEta {
+    samples: VecDeque<(Instant, BlockNumber)>,
+    chain_tip: BlockNumber,
+    tip_checked_at: Option<Instant>,
+    logged_at: Instant,
+    log_interval: Duration,
+}
+
+impl SyncEta {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            chain_tip: 0,
+            tip_checked_at: None,
+            logged_at: Instant::now(),
+            log_interval: MIN_LOG_INTERVAL,
+        }
+    }
+
+    /// Records the number of the next block to sync, and returns the current progress.
+    pub async fn update(
+        &mut self,
+        api: &ParachainApi,
+        blocknum: BlockNumber,
+    ) -> Result<SyncProgress> {
+        let now = Instant::now();
+        if self
+            .tip_checked_at
+            .map_or(true, |t| t.elapsed() >= TIP_REFRESH_INTERVAL)
+        {
+            let (tip, _) = crate::get_header_at(api, None)
+                .await
+                .context("Failed to get the chain tip")?;
+            self.chain_tip = tip.number;
+            self.tip_checked_at = Some(now);
+        }
+
+        if self.samples.back().map_or(false, |(_, n)| *n > blocknum) {
+            // pRuntime restarted from an older checkpoint.
+            self.samples.clear();
+        }
+        self.samples.push_back((now, blocknum));
+        while self
+            .samples
+            .front()
+            .map_or(false, |(t, _)| now.duration_since(*t) > THROUGHPUT_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let progress = self.progress(blocknum);
+        self.maybe_log(&progress);
+        Ok(progress)
+    }
+
+    fn progress(&self, blocknum: BlockNumber) -> SyncProgress {
+        let remaining_blocks = (self.chain_tip + 1).saturating_sub(blocknum);
+        let blocks_per_sec = match (self.samples.front(), self.samples.back()) {
+            (Some((t0, n0)), Some((t1, n1))) if t1 > t0 => {
+                (n1 - n0) as f64 / t1.duration_since(*t0).as_secs_f64()
+            }
+            _ => 0.0,
+        };
+        let eta_secs = if remaining_blocks == 0 {
+            Some(0)
+        } else if blocks_per_sec > 0.0 {
+            Some((remaining_blocks as f64 / blocks_per_sec) as u64)
+        } else {
+            None
+        };
+        SyncProgress {
+            chain_tip: self.chain_tip,
+            remaining_blocks,
+            blocks_per_sec,
+            eta_secs,
+        }
+    }
+
+    fn maybe_log(&mut self, progress: &SyncProgress) {
+        if progress.remaining_blocks == 0 {
+            self.log_interval = MIN_LOG_INTERVAL;
+            return;
+        }
+        if self.logged_at.elapsed() < self.log_interval {
+            return;
+        }
+        let eta = match progress.eta_secs {
+            Some(secs) => format_duration(secs),
+            None => "unknown".into(),
+        };
+        info!(
+            "Sync progress: {} blocks behind the tip at {}, {:.2} blocks/s, ETA {}",
+            progress.remaining_blocks, progress.chain_tip, progress.blocks_per_sec, eta
+        );
+        self.logged_at = Instant::now();
+        self.log_interval = (self.log_interval * 2).min(MAX_LOG_INTERVAL);
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{days}d{hours}h")
+    } else if hours > 0 {
+        format!("{hours}h{mins}m")
+    } else {
+        format!("{mins}m{}s", secs % 60)
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+
+use crate::{subxt_connect, Args};
+
+/// What kind of chain a node runs, told by the pallets in its metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A Phala parachain node.
+    Parachain,
+    /// A Phala standalone chain node.
+    Solo,
+    /// A relaychain node, or any other chain without the Phala pallets.
+    Relaychain,
+}
+
+async fn probe(uri: &str) -> Result<NodeKind> {
+    let api = subxt_connect(uri)
+        .await
+        .with_context(|| format!("Failed to connect to {uri}"))?;
+    let metadata = api.metadata();
+    let kind = if metadata.pallet_by_name("ParachainSystem").is_some() {
+        NodeKind::Parachain
+    } else if metadata.pallet_by_name("PhalaRegistry").is_some() {
+        NodeKind::Solo
+    } else {
+        NodeKind::Relaychain
+    };
+    Ok(kind)
+}
+
+/// Checks `--parachain` against the chains of the endpoints, enabling it if it's forgotten with
+/// a relaychain and a parachain endpoint given, and failing if the flags contradict the chains.
+///
+/// The detection is skipped if an endpoint is not reachable yet, to leave the retries to the
+/// bridge.
+pub async fn detect_mode(args: &mut Args) -> Result<()> {
+    if args.no_auto_detect {
+        return Ok(());
+    }
+    let relay_uri = &args.relaychain_ws_endpoint;
+    let para_uri = &args.parachain_ws_endpoint;
+    let relay = match probe(relay_uri).await {
+        Ok(kind) => kind,
+        Err(err) => {
+            warn!("Skipped the parachain mode detection: {err:?}");
+            return Ok(());
+        }
+    };
+    // The parachain endpoint only matters along with a relaychain node.
+    let para = if relay == NodeKind::Relaychain {
+        match probe(para_uri).await {
+            Ok(kind) => Some(kind),
+            Err(err) => {
+                warn!("Failed to probe the parachain node: {err:?}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    args.parachain = resolve_mode(args.parachain, relay, para, relay_uri, para_uri)?;
+    Ok(())
+}
+
+/// Tells whether to run in parachain mode from the kinds of the nodes found at the endpoints, or
+/// why the flags given are wrong. `para` is `None` if the parachain node is not reachable, in
+/// which case `parachain` is kept as is.
+pub fn resolve_mode(
+    parachain: bool,
+    relay: NodeKind,
+    para: Option<NodeKind>,
+    relay_uri: &str,
+    para_uri: &str,
+) -> Result<bool> {
+    if parachain {
+        if relay != NodeKind::Relaychain {
+            bail!(
+                "--parachain is set but {relay_uri} is a Phala {} node rather than a relaychain \
+                 node, set --relaychain-ws-endpoint to the relaychain node",
+                if relay == NodeKind::Parachain {
+                    "parachain"
+                } else {
+                    "standalone chain"
+                }
+            );
+        }
+        match para {
+            Some(NodeKind::Parachain) => info!("Running in parachain mode"),
+            Some(NodeKind::Solo) => bail!(
+                "--parachain is set but {para_uri} is a standalone chain node, run without \
+                 --parachain and set --relaychain-ws-endpoint to it"
+            ),
+            Some(NodeKind::Relaychain) => bail!(
+                "--parachain is set but {para_uri} is not a Phala parachain node, set \
+                 --parachain-ws-endpoint to the parachain node"
+            ),
+            None => warn!("Skipped the parachain mode detection, {para_uri} is not reachable"),
+        }
+        return Ok(true);
+    }
+    match relay {
+        NodeKind::Solo => info!("Running in standalone chain mode"),
+        NodeKind::Parachain => bail!(
+            "{relay_uri} is a parachain node, run with --parachain, setting \
+             --parachain-ws-endpoint to it and --relaychain-ws-endpoint to the relaychain node"
+        ),
+        NodeKind::Relaychain => match para {
+            Some(NodeKind::Parachain) => {
+                warn!(
+                    "{relay_uri} is a relaychain node and {para_uri} a parachain node, \
+                     enabling parachain mode as --parachain is not set"
+                );
+                return Ok(true);
+            }
+            Some(_) => bail!(
+                "{relay_uri} is not a Phala node, set --relaychain-ws-endpoint to the Phala \
+                 standalone chain node, or run with --parachain and --parachain-ws-endpoint"
+            ),
+            None => warn!(
+                "{relay_uri} is a relaychain node, but {para_uri} is not reachable to tell \
+                 whether to run in parachain mode, set --parachain if it's meant to"
+            ),
+        },
+    }
+    Ok(false)
+}
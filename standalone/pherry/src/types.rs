@@ -1,8 +1,5 @@
 use core::fmt;
-use phactory_api::{
-    blocks::{BlockHeader, StorageProof},
-    pruntime_client,
-};
+use phactory_api::{blocks::BlockHeader, pruntime_client};
 use serde::{Deserialize, Serialize};
 use sp_runtime::{generic::SignedBlock as SpSignedBlock, Justifications, OpaqueExtrinsic};
 
@@ -19,7 +16,7 @@ use codec::{Decode, Encode};
 use crate::headers_cache::BlockInfo;
 
 pub type PrClient = pruntime_client::PRuntimeClient;
-pub type SrSigner = phaxt::PairSigner;
+pub use crate::signer::SrSigner;
 
 pub type SignedBlock<Hdr, Ext> = SpSignedBlock<sp_runtime::generic::Block<Hdr, Ext>>;
 
@@ -37,13 +34,185 @@ pub struct NotifyReq {
     pub pruntime_initialized: bool,
     pub pruntime_new_init: bool,
     pub initial_sync_finished: bool,
+    #[serde(default)]
+    pub sync_progress: Option<SyncProgress>,
+    #[serde(default)]
+    pub balance: Option<BalanceStatus>,
+    #[serde(default)]
+    pub stall: Option<StallStatus>,
+    #[serde(default)]
+    pub timing: Option<SyncTiming>,
+    #[serde(default)]
+    pub endpoint_drift: Option<EndpointDrift>,
+    #[serde(default)]
+    pub sources: Vec<SourceQuality>,
 }
 
-pub mod utils {
-    use super::StorageProof;
-    use phaxt::subxt::rpc::types::ReadProof;
-    pub fn raw_proof<T>(read_proof: ReadProof<T>) -> StorageProof {
-        read_proof.proof.into_iter().map(|p| p.0).collect()
+/// How a source of the synced data, e.g. the headers cache, has been serving since the bridge
+/// started.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceQuality {
+    pub name: String,
+    pub requests: u64,
+    /// Number of the requests failed, served by the next source instead if there is one.
+    pub failures: u64,
+    /// Number of the blocks or headers served.
+    pub items: u64,
+    /// Time spent in the requests, in milliseconds.
+    pub busy_ms: u64,
+}
+
+/// The endpoints bound on chain differ from the ones pRuntime is configured with, e.g. after the
+/// public IP of the worker changed. It's reported until the endpoints are bound again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EndpointDrift {
+    pub bound: Vec<String>,
+    pub configured: Vec<String>,
+}
+
+/// The free balance of the controller account, in the smallest unit.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BalanceStatus {
+    pub free: u128,
+    /// Whether it's below `--min-balance-warn`.
+    pub low: bool,
+}
+
+/// The block sync progress towards the parachain tip, estimated from the rolling throughput.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SyncProgress {
+    pub chain_tip: BlockNumber,
+    pub remaining_blocks: BlockNumber,
+    pub blocks_per_sec: f64,
+    /// Estimated seconds to reach the tip, `None` if no throughput has been measured yet.
+    pub eta_secs: Option<u64>,
+}
+
+/// The sync progress stalled as detected by the watchdog.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StallStatus {
+    /// Seconds since pRuntime last made progress.
+    pub stalled_secs: u64,
+    /// Number of dispatches of headers or blocks since then.
+    pub dispatches: u32,
+    pub last_operation: String,
+    pub headernum: BlockNumber,
+    pub para_headernum: BlockNumber,
+    pub blocknum: BlockNumber,
+}
+
+/// How long the recent sync rounds took in each phase, to tell whether the chain node, the network
+/// or pRuntime is slow.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SyncTiming {
+    /// Number of the rounds the percentiles are over.
+    pub rounds: u32,
+    /// Fetching from the chain node or the headers cache.
+    pub fetch: PhasePercentiles,
+    /// Decoding and checking what was fetched.
+    pub decode: PhasePercentiles,
+    /// Dispatching to pRuntime.
+    pub dispatch: PhasePercentiles,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PhasePercentiles {
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+}
+
+/// What pherry has done in a round, posted to the notify endpoint apart from the status pings.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RoundSummary {
+    /// Seconds since the start of the round.
+    pub duration_secs: u64,
+    pub headers_synced: BlockNumber,
+    pub para_headers_synced: BlockNumber,
+    pub blocks_dispatched: BlockNumber,
+    pub messages_submitted: u64,
+    /// Number of the errors that restarted the bridge or failed a transaction.
+    pub errors: u64,
+    /// Fees paid by the controller account in the blocks dispatched in the round, from the
+    /// `TransactionFeePaid` events. `None` if they could not be fetched.
+    pub fees_spent: Option<u128>,
+    pub headernum: BlockNumber,
+    pub para_headernum: BlockNumber,
+    pub blocknum: BlockNumber,
+}
+
+/// A compact status of the worker, posted to the notify endpoint signed with the controller key, so
+/// that the fleet services can tell which operator a status comes from and reject the spoofed ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// The hex encoded public key of the worker, as reported by pRuntime.
+    pub worker_pubkey: String,
+    pub headernum: BlockNumber,
+    pub blocknum: BlockNumber,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl Heartbeat {
+    /// The message signed, the SCALE encoding of
+    /// `(b"pherry/heartbeat", worker_pubkey, headernum, blocknum, timestamp)` wrapped in
+    /// `<Bytes>`..`</Bytes>` as the wallets do when signing raw bytes, so that a heartbeat can't
+    /// pass for a transaction payload signed by the controller key.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let message = (
+            b"pherry/heartbeat",
+            &self.worker_pubkey,
+            self.headernum,
+            self.blocknum,
+            self.timestamp,
+        )
+            .encode();
+        [b"<Bytes>", &message[..], b"</Bytes>"].concat()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedHeartbeat {
+    #[serde(flatten)]
+    pub heartbeat: Heartbeat,
+    /// The SS58 address of the controller account.
+    pub controller: String,
+    /// `sr25519`, `ed25519` or `ecdsa`.
+    pub scheme: String,
+    /// The hex encoded public key to check the signature with, which is not the account id with
+    /// an ecdsa key.
+    pub public: String,
+    /// The hex encoded signature of [`Heartbeat::signing_payload`].
+    pub signature: String,
+}
+
+impl SignedHeartbeat {
+    pub fn sign(heartbeat: Heartbeat, signer: &SrSigner) -> anyhow::Result<Self> {
+        let signature = signer.sign_message(&heartbeat.signing_payload())?;
+        Ok(Self {
+            controller: signer.account_id().to_string(),
+            scheme: format!("{:?}", signature.scheme).to_lowercase(),
+            public: hex::encode(signature.public),
+            signature: hex::encode(signature.signature),
+            heartbeat,
+        })
+    }
+
+    /// Checks the signature against `public`. Whether `public` belongs to `controller` is up to
+    /// the receiver, with the ecdsa keys whose account id is a hash of it.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        use phactory_api::crypto::verify;
+
+        let public = hex::decode(&self.public)?;
+        let signature = hex::decode(&self.signature)?;
+        let message = self.heartbeat.signing_payload();
+        let valid = match self.scheme.as_str() {
+            "sr25519" => verify::<sp_core::sr25519::Pair>(&public, &signature, &message),
+            "ed25519" => verify::<sp_core::ed25519::Pair>(&public, &signature, &message),
+            "ecdsa" => verify::<sp_core::ecdsa::Pair>(&public, &signature, &message),
+            scheme => anyhow::bail!("Unknown signature scheme {scheme}"),
+        };
+        anyhow::ensure!(valid, "Bad heartbeat signature");
+        Ok(())
     }
 }
 
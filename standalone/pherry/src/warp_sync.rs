@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+use crate::finality_stream::has_authority_set_change;
+use crate::types::{BlockNumber, ConvertTo, Header, ParachainApi, RelaychainApi};
+
+/// How far back from the finalized head to look for an authority set change, comfortably more
+/// than an epoch of the relaychains we run on.
+const MAX_SEARCH_DEPTH: BlockNumber = 4096;
+
+/// Picks the relaychain block to initialize pRuntime at for warp sync.
+///
+/// Returns the latest finalized block enacting a GRANDPA authority set change, so pRuntime starts
+/// with the authority set that justifies all the following headers, skipping the headers since
+/// `full_sync_start`. Returns `None` if warp sync is not applicable, in which case pRuntime should
+/// be initialized at `full_sync_start`.
+///
+/// After the init, the parachain state must be loaded with [`para_block_at`], see there.
+pub async fn find_start_header(
+    api: &RelaychainApi,
+    is_parachain: bool,
+    can_load_chain_state: bool,
+    full_sync_start: BlockNumber,
+) -> Option<BlockNumber> {
+    if !is_parachain {
+        // The solochain blocks are dispatched along with their headers, so all the headers since
+        // the genesis are needed anyway.
+        warn!("Warp sync is only supported in parachain mode, fallback to full header sync");
+        return None;
+    }
+    if !can_load_chain_state {
+        warn!("Warp sync needs to load the chain state, which pRuntime refuses, fallback to full header sync");
+        return None;
+    }
+    match find_latest_set_change(api).await {
+        Ok(Some(number)) if number > full_sync_start => {
+            info!("Warp sync from the authority set change at relaychain block {number}");
+            Some(number)
+        }
+        Ok(_) => {
+            info!("No authority set change found to warp sync to, fallback to full header sync");
+            None
+        }
+        Err(err) => {
+            warn!("Failed to search the warp sync point, fallback to full header sync: {err:?}");
+            None
+        }
+    }
+}
+
+/// The parachain block finalized at the relaychain block pRuntime is initialized at for warp sync.
+///
+/// pRuntime must load the parachain state at this block or before, right after the init.
+/// Otherwise it would still dispatch the parachain blocks from the genesis, with the first
+/// parachain headers synced carrying all of the headers since then.
+pub async fn para_block_at(
+    api: &RelaychainApi,
+    para_api: &ParachainApi,
+    relay_number: BlockNumber,
+) -> Result<BlockNumber> {
+    let hash = crate::get_header_hash(api, Some(relay_number)).await?;
+    let (header, _) = crate::get_finalized_header(api, para_api, hash)
+        .await?
+        .ok_or_else(|| anyhow!("No parachain head at relaychain block {relay_number}"))?;
+    Ok(header.number)
+}
+
+async fn find_latest_set_change(api: &RelaychainApi) -> Result<Option<BlockNumber>> {
+    let (mut header, _) = crate::get_header_at(api, None).await?;
+    let tip = header.number;
+    while tip - header.number < MAX_SEARCH_DEPTH {
+        if has_authority_set_change(&header) {
+            return Ok(Some(header.number));
+        }
+        if header.number == 0 {
+            break;
+        }
+        let parent: Header = api
+            .rpc()
+            .header(Some(header.parent_hash))
+            .await?
+            .ok_or_else(|| anyhow!("Header {} not found", header.parent_hash))?
+            .convert_to();
+        header = parent;
+    }
+    Ok(None)
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use phala_pallets::pallet_computation::SessionInfo;
+use phala_pallets::pallet_registry::WorkerInfoV2;
+use phala_types::WorkerPublicKey;
+use sp_core::crypto::AccountId32;
+use std::fmt::Display;
+
+use crate::{subxt_connect, Args};
+
+fn parse_pubkey(pubkey: &str) -> Result<WorkerPublicKey> {
+    let raw: [u8; 32] = hex::decode(pubkey.trim_start_matches("0x"))
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| anyhow!("Invalid worker public key: {pubkey}"))?;
+    Ok(WorkerPublicKey::from_raw(raw))
+}
+
+fn field(name: &str, value: impl Display) {
+    println!("  {:<20}{value}", format!("{name}:"));
+}
+
+/// Decodes a `U64F64` in bits as stored on chain.
+fn fixed_u64f64(bits: u128) -> f64 {
+    bits as f64 / (1u128 << 64) as f64
+}
+
+/// The pRuntime version packed as `major << 16 | minor << 8 | patch`.
+fn runtime_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        version >> 16,
+        (version >> 8) & 0xff,
+        version & 0xff
+    )
+}
+
+/// Prints the on-chain state of the worker decoded from PhalaRegistry and PhalaComputation, as
+/// found at the latest block of the parachain.
+pub async fn print_worker_info(args: &Args, pubkey: &str) -> Result<()> {
+    let pubkey = parse_pubkey(pubkey)?;
+    let endpoint = if args.parachain {
+        &args.parachain_ws_endpoint
+    } else {
+        &args.relaychain_ws_endpoint
+    };
+    let api = subxt_connect(endpoint).await?;
+    let chain_info = crate::chain_info_or_default(&api).await;
+
+    println!("Worker 0x{}", hex::encode(pubkey));
+    let worker: Option<WorkerInfoV2<AccountId32>> =
+        api.fetch("PhalaRegistry", "Workers", Some(&pubkey)).await?;
+    let Some(worker) = worker else {
+        field("Registered", "no");
+        return Ok(());
+    };
+
+    println!("Registration");
+    match api.worker_added_at(pubkey.as_ref()).await? {
+        Some(block) => field("Registered", format!("yes, at block {block}")),
+        None => field("Registered", "yes"),
+    }
+    field(
+        "Operator",
+        worker
+            .operator
+            .map_or_else(|| "none".into(), |operator| operator.to_string()),
+    );
+    field(
+        "Attestation",
+        worker
+            .attestation_provider
+            .map_or_else(|| "none".into(), |provider| format!("{provider:?}")),
+    );
+    field("Confidence level", worker.confidence_level);
+    field(
+        "Initial score",
+        worker
+            .initial_score
+            .map_or_else(|| "benchmarking".into(), |score| score.to_string()),
+    );
+    field("pRuntime version", runtime_version(worker.runtime_version));
+    field("Last updated", format!("{} (unix)", worker.last_updated));
+    field(
+        "ECDH public key",
+        format!("0x{}", hex::encode(worker.ecdh_pubkey)),
+    );
+    let gatekeeper = api.get_gatekeepers().await?.contains(&pubkey);
+    field("Gatekeeper", if gatekeeper { "yes" } else { "no" });
+
+    println!("Endpoints");
+    let endpoints = api.get_endpoints(&pubkey).await?;
+    if endpoints.is_empty() {
+        field("Bound", "no");
+    }
+    for endpoint in endpoints {
+        field("Bound", endpoint);
+    }
+
+    println!("Session");
+    let session: Option<AccountId32> = api
+        .fetch("PhalaComputation", "WorkerBindings", Some(&pubkey))
+        .await?;
+    let Some(session) = session else {
+        field("Bound", "no");
+        return Ok(());
+    };
+    field("Account", &session);
+    let info: Option<SessionInfo> = api
+        .fetch("PhalaComputation", "Sessions", Some(&session))
+        .await?;
+    match info {
+        Some(info) => {
+            field("State", format!("{:?}", info.state));
+            field("Initial V", format!("{:.4}", fixed_u64f64(info.ve)));
+            field("Current V", format!("{:.4}", fixed_u64f64(info.v)));
+        }
+        None => field("State", "unknown"),
+    }
+    let stake: Option<u128> = api
+        .fetch("PhalaComputation", "Stakes", Some(&session))
+        .await?;
+    field(
+        "Stake",
+        stake.map_or_else(|| "none".into(), |stake| chain_info.format_balance(stake)),
+    );
+    Ok(())
+}
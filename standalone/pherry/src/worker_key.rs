@@ -0,0 +1,66 @@
+//! The identity key injected into pRuntime in place of the one it generates, for the dev and test
+//! deployments.
+
+use anyhow::{anyhow, Context, Result};
+use sp_core::{sr25519, Pair};
+use std::fmt;
+use std::str::FromStr;
+
+/// The key injected by `--use-dev-key`.
+const DEV_KEY: [u8; 32] = {
+    let mut key = [0; 32];
+    key[31] = 1;
+    key
+};
+
+/// A 32 bytes sr25519 seed, given in hex with or without the `0x` prefix.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WorkerKey([u8; 32]);
+
+impl WorkerKey {
+    pub fn dev() -> Self {
+        Self(DEV_KEY)
+    }
+
+    /// Reads the key from a file holding it in hex, surrounding whitespace ignored.
+    pub fn read_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the key to inject from {path}"))?;
+        content
+            .parse()
+            .with_context(|| format!("Bad key to inject in {path}"))
+    }
+
+    /// The hex encoded public key of the worker identity derived from the key.
+    pub fn public(&self) -> String {
+        hex::encode(sr25519::Pair::from_seed(&self.0).public())
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+impl FromStr for WorkerKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let bytes = hex::decode(s.strip_prefix("0x").unwrap_or(s))
+            .map_err(|err| anyhow!("The key to inject must be hex encoded: {err}"))?;
+        let key = <[u8; 32]>::try_from(bytes).map_err(|bytes| {
+            anyhow!(
+                "The key to inject must be 32 bytes, got {} bytes",
+                bytes.len()
+            )
+        })?;
+        Ok(Self(key))
+    }
+}
+
+/// Never reveals the key in the logs.
+impl fmt::Debug for WorkerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WorkerKey(0x{})", self.public())
+    }
+}
@@ -0,0 +1,52 @@
+use pherry::balance::{validate_thresholds, BalanceLevel, BalanceMonitor};
+use std::time::Duration;
+
+#[test]
+fn goes_through_ok_warn_and_abort() {
+    let mut monitor = BalanceMonitor::new(100, 10, Duration::ZERO);
+    assert!(monitor.status().is_none());
+
+    assert_eq!(monitor.observe(100), BalanceLevel::Ok);
+    assert!(!monitor.status().unwrap().low);
+    assert_eq!(monitor.observe(99), BalanceLevel::Low);
+    assert!(monitor.status().unwrap().low);
+    assert_eq!(monitor.observe(10), BalanceLevel::Low);
+    assert_eq!(monitor.observe(9), BalanceLevel::Abort);
+    let status = monitor.status().unwrap();
+    assert_eq!(status.free, 9);
+    assert!(status.low);
+    // Topped up.
+    assert_eq!(monitor.observe(1000), BalanceLevel::Ok);
+    assert!(!monitor.status().unwrap().low);
+}
+
+#[test]
+fn aborts_without_warning_threshold() {
+    let mut monitor = BalanceMonitor::new(0, 10, Duration::ZERO);
+    assert_eq!(monitor.observe(10), BalanceLevel::Ok);
+    assert_eq!(monitor.observe(0), BalanceLevel::Abort);
+    assert!(!monitor.status().unwrap().low);
+}
+
+#[test]
+fn rejects_abort_threshold_not_below_warn() {
+    validate_thresholds(100, 10).unwrap();
+    validate_thresholds(0, 10).unwrap();
+    validate_thresholds(100, 0).unwrap();
+    for (warn, abort) in [(100, 100), (100, 200)] {
+        let err = validate_thresholds(warn, abort).unwrap_err();
+        assert!(err.to_string().contains("must be below"), "{err:?}");
+    }
+}
+
+#[test]
+fn renders_balance_metrics() {
+    let mut monitor = BalanceMonitor::new(100, 10, Duration::ZERO);
+    assert_eq!(monitor.render_metrics("alice"), "");
+    monitor.observe(42);
+    let metrics = monitor.render_metrics("alice");
+    let lines: Vec<_> = metrics.lines().collect();
+    assert!(lines.contains(&r#"pherry_controller_free_balance{account="alice"} 42"#));
+    assert!(lines.contains(&r#"pherry_controller_balance_low{account="alice"} 1"#));
+    assert!(lines.contains(&"# TYPE pherry_controller_free_balance gauge"));
+}
@@ -0,0 +1,248 @@
+use anyhow::{bail, Result};
+use phactory_api::blocks::BlockHeaderWithChanges;
+use pherry::block_source::{BlockSource, BlockSources};
+use pherry::headers_cache::{CacheKind, PlannedRange};
+use pherry::types::{BlockNumber, Header, SourceQuality};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// Serves the headers of the blocks it has, as the headers cache does.
+struct FakeSource {
+    name: &'static str,
+    has: RangeInclusive<BlockNumber>,
+    failing: bool,
+}
+
+impl FakeSource {
+    fn new(name: &'static str, has: RangeInclusive<BlockNumber>) -> Self {
+        Self {
+            name,
+            has,
+            failing: false,
+        }
+    }
+
+    fn failing(mut self) -> Self {
+        self.failing = true;
+        self
+    }
+}
+
+fn header(number: BlockNumber) -> Header {
+    Header {
+        parent_hash: Default::default(),
+        number,
+        state_root: Default::default(),
+        extrinsics_root: Default::default(),
+        digest: Default::default(),
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockSource for FakeSource {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn plan(
+        &self,
+        _kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Vec<PlannedRange> {
+        let (has_from, has_to) = (*self.has.start(), *self.has.end());
+        let mut planned = vec![];
+        let mut push = |from, to, cached: bool| {
+            if from <= to {
+                planned.push(PlannedRange {
+                    from,
+                    to,
+                    cached: cached.then(String::new),
+                });
+            }
+        };
+        if has_from > 0 {
+            push(from, to.min(has_from - 1), false);
+        }
+        push(from.max(has_from), to.min(has_to), true);
+        push(from.max(has_to + 1), to, false);
+        planned
+    }
+
+    async fn storage_changes(
+        &self,
+        _range: &PlannedRange,
+        _with_root: bool,
+    ) -> Result<Vec<BlockHeaderWithChanges>> {
+        Ok(vec![])
+    }
+
+    async fn parachain_headers(&self, range: &PlannedRange) -> Result<Vec<Header>> {
+        if self.failing {
+            bail!("{} is down", self.name);
+        }
+        Ok((range.from..=range.to).map(header).collect())
+    }
+
+    async fn parachain_header_at(
+        &self,
+        relay_number: BlockNumber,
+    ) -> Result<Option<(BlockNumber, Vec<Vec<u8>>)>> {
+        if self.failing {
+            bail!("{} is down", self.name);
+        }
+        Ok(self
+            .has
+            .contains(&relay_number)
+            .then(|| (relay_number * 2, vec![])))
+    }
+}
+
+/// The node, having the blocks up to `tip` and stopping there.
+struct FakeNode {
+    tip: BlockNumber,
+    failing: bool,
+}
+
+#[async_trait::async_trait]
+impl BlockSource for FakeNode {
+    fn name(&self) -> &'static str {
+        "node"
+    }
+
+    async fn plan(
+        &self,
+        _kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Vec<PlannedRange> {
+        vec![PlannedRange {
+            from,
+            to,
+            cached: Some(String::new()),
+        }]
+    }
+
+    async fn storage_changes(
+        &self,
+        _range: &PlannedRange,
+        _with_root: bool,
+    ) -> Result<Vec<BlockHeaderWithChanges>> {
+        Ok(vec![])
+    }
+
+    async fn parachain_headers(&self, range: &PlannedRange) -> Result<Vec<Header>> {
+        if self.failing {
+            bail!("node is down");
+        }
+        Ok((range.from..=range.to.min(self.tip)).map(header).collect())
+    }
+
+    async fn parachain_header_at(
+        &self,
+        relay_number: BlockNumber,
+    ) -> Result<Option<(BlockNumber, Vec<Vec<u8>>)>> {
+        Ok(Some((relay_number, vec![])))
+    }
+}
+
+fn node(tip: BlockNumber) -> FakeNode {
+    FakeNode {
+        tip,
+        failing: false,
+    }
+}
+
+fn numbers(headers: &[Header]) -> Vec<BlockNumber> {
+    headers.iter().map(|h| h.number).collect()
+}
+
+fn quality(sources: &BlockSources, name: &str) -> SourceQuality {
+    sources
+        .quality()
+        .into_iter()
+        .find(|q| q.name == name)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn takes_each_part_from_the_first_source_having_it() {
+    let sources = BlockSources::new(vec![
+        Arc::new(FakeSource::new("cache", 10..=19)),
+        Arc::new(node(100)),
+    ]);
+    let headers = sources.parachain_headers(5, 25).await.unwrap();
+    assert_eq!(numbers(&headers), (5..=25).collect::<Vec<_>>());
+
+    let cache = quality(&sources, "cache");
+    assert_eq!((cache.requests, cache.failures, cache.items), (1, 0, 10));
+    let node = quality(&sources, "node");
+    assert_eq!((node.requests, node.failures, node.items), (2, 0, 11));
+}
+
+#[tokio::test]
+async fn falls_back_to_the_next_source_on_failure() {
+    let sources = BlockSources::new(vec![
+        Arc::new(FakeSource::new("cache", 0..=50).failing()),
+        Arc::new(FakeSource::new("archive", 0..=50)),
+        Arc::new(node(100)),
+    ]);
+    let headers = sources.parachain_headers(40, 59).await.unwrap();
+    assert_eq!(numbers(&headers), (40..=59).collect::<Vec<_>>());
+
+    assert_eq!(quality(&sources, "cache").failures, 1);
+    assert_eq!(quality(&sources, "archive").items, 11);
+    assert_eq!(quality(&sources, "node").items, 9);
+}
+
+#[tokio::test]
+async fn stops_before_the_first_block_not_available() {
+    let sources = BlockSources::new(vec![
+        Arc::new(FakeSource::new("cache", 15..=19)),
+        Arc::new(node(12)),
+    ]);
+    let headers = sources.parachain_headers(10, 19).await.unwrap();
+    assert_eq!(numbers(&headers), vec![10, 11, 12]);
+}
+
+#[tokio::test]
+async fn fails_if_the_last_source_fails() {
+    let sources = BlockSources::new(vec![
+        Arc::new(FakeSource::new("cache", 0..=4)),
+        Arc::new(FakeNode {
+            tip: 100,
+            failing: true,
+        }),
+    ]);
+    assert!(sources.parachain_headers(0, 9).await.is_err());
+    assert_eq!(quality(&sources, "node").failures, 1);
+    assert!(sources.parachain_headers(0, 4).await.is_ok());
+}
+
+#[tokio::test]
+async fn finalized_parachain_header_from_the_first_source_knowing_it() {
+    let sources = BlockSources::new(vec![
+        Arc::new(FakeSource::new("cache", 10..=19)),
+        Arc::new(node(100)),
+    ]);
+    assert_eq!(
+        sources.parachain_header_at(12).await.unwrap().unwrap().0,
+        24
+    );
+    assert_eq!(
+        sources.parachain_header_at(30).await.unwrap().unwrap().0,
+        30
+    );
+    // No source serves the relaychain headers as a whole, they are synced from the node.
+    assert!(sources.headers_to_next_set_change(12).await.is_none());
+
+    let sources = BlockSources::new(vec![
+        Arc::new(FakeSource::new("cache", 10..=19).failing()),
+        Arc::new(node(100)),
+    ]);
+    assert_eq!(
+        sources.parachain_header_at(12).await.unwrap().unwrap().0,
+        12
+    );
+    assert_eq!(quality(&sources, "cache").failures, 1);
+}
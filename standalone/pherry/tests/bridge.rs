@@ -0,0 +1,414 @@
+mod harness;
+
+use codec::Encode;
+use harness::{MockChain, MockPRuntime, Registration};
+use phactory_api::storage_sync::SyncErrorCode;
+use phala_types::{
+    AttestationReport, VersionedWorkerEndpoints, WorkerEndpointPayload, WorkerPublicKey,
+};
+use pherry::chain_client;
+use sp_core::{sr25519, storage::well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX, Pair as _};
+
+const CHAIN_LEN: u32 = 20;
+
+#[tokio::test]
+async fn syncs_headers_then_blocks_to_target() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    let args = harness::args(&chain, &pruntime, &["--to-block", "20"]);
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+
+    let calls = pruntime.calls();
+    let first_sync_header = calls.iter().position(|c| c == "SyncHeader");
+    let first_dispatch = calls.iter().position(|c| c == "DispatchBlocks");
+    assert!(
+        first_sync_header < first_dispatch,
+        "headers should be synced before blocks: {calls:?}"
+    );
+    // The headers come in a single finality proof, the blocks in batches of 4.
+    assert_eq!(calls.iter().filter(|c| *c == "SyncHeader").count(), 1);
+    assert_eq!(calls.iter().filter(|c| *c == "DispatchBlocks").count(), 5);
+}
+
+#[tokio::test]
+async fn respects_sync_batch_size() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    let args = harness::args(
+        &chain,
+        &pruntime,
+        &["--to-block", "20", "--sync-blocks", "7"],
+    );
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    let calls = pruntime.calls();
+    assert_eq!(calls.iter().filter(|c| *c == "DispatchBlocks").count(), 3);
+}
+
+#[tokio::test]
+async fn shrinks_sync_batches_under_memory_pressure() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.set_memory_usage(90, 10);
+    let args = harness::args(
+        &chain,
+        &pruntime,
+        &[
+            "--to-block",
+            "20",
+            "--sync-blocks",
+            "8",
+            "--min-sync-blocks",
+            "2",
+        ],
+    );
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+    let calls = pruntime.calls();
+    assert_eq!(calls.iter().filter(|c| *c == "DispatchBlocks").count(), 10);
+}
+
+#[tokio::test]
+async fn dispatches_child_storage_changes_intact() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    let args = harness::args(&chain, &pruntime, &["--to-block", "20"]);
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    let expected = (1..=CHAIN_LEN)
+        .map(|number| (number, harness::child_storage_changes(number)))
+        .collect::<Vec<_>>();
+    assert_eq!(pruntime.child_storage_changes(), expected);
+}
+
+#[tokio::test]
+async fn reads_back_child_storage_written_by_an_extrinsic() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let remark = phaxt::dynamic::tx::EncodedPayload::new(
+        "System",
+        "remark",
+        b"round trip".to_vec().encode(),
+    );
+    let child_key = b"contract:round-trip".to_vec();
+    let written = vec![(b"greeting".to_vec(), b"hello".to_vec())];
+    chain.on_included_in_child(&harness::call_data(&remark), &child_key, written.clone());
+
+    let api = pherry::subxt_connect(&chain.url).await.unwrap();
+    let signer = phaxt::PairSigner::new(sr25519::Pair::from_string("//Alice", None).unwrap());
+    let block = api
+        .tx()
+        .create_signed(&remark, &signer.signer, Default::default())
+        .await
+        .unwrap()
+        .submit_and_watch()
+        .await
+        .unwrap()
+        .wait_for_finalized()
+        .await
+        .unwrap()
+        .block_hash();
+
+    // The child delta of the block including the extrinsic, with the child key intact.
+    let changes = chain_client::fetch_storage_changes(&api, &block, &block)
+        .await
+        .unwrap();
+    assert_eq!(changes.len(), 1);
+    let delta = (
+        child_key.clone(),
+        vec![(b"greeting".to_vec(), Some(b"hello".to_vec()))],
+    );
+    assert!(
+        changes[0].child_storage_changes.contains(&delta),
+        "child delta missing: {:?}",
+        changes[0].child_storage_changes
+    );
+
+    // The child trie read back at the same block.
+    let storage_key = [DEFAULT_CHILD_STORAGE_KEY_PREFIX, &child_key].concat();
+    let children = chain_client::fetch_child_storage_at(&api, block, &[(storage_key, vec![])])
+        .await
+        .unwrap();
+    assert_eq!(children, vec![(child_key, written)]);
+}
+
+#[tokio::test]
+async fn coalesces_empty_blocks_if_supported() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let extra = &["--to-block", "20", "--coalesce-empty-blocks", "100"];
+    let dispatches = |pruntime: &MockPRuntime| {
+        let calls = pruntime.calls();
+        calls.iter().filter(|c| *c == "DispatchBlocks").count()
+    };
+
+    let pruntime = MockPRuntime::start();
+    let args = harness::args(&chain, &pruntime, extra);
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(dispatches(&pruntime), 5);
+
+    // The blocks 9 to 16, only changing the System and Timestamp bookkeeping, go in one dispatch
+    // instead of two.
+    let pruntime = MockPRuntime::start();
+    pruntime.coalesce_empty_blocks();
+    let args = harness::args(&chain, &pruntime, extra);
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+    assert_eq!(dispatches(&pruntime), 4);
+    assert_eq!(
+        pruntime.bookkeeping_only_blocks(),
+        (9..=16).collect::<Vec<_>>()
+    );
+
+    // Nothing is coalesced once the memory pressure brings the batches down to the min size.
+    let pruntime = MockPRuntime::start();
+    pruntime.coalesce_empty_blocks();
+    pruntime.set_memory_usage(90, 10);
+    let args = harness::args(&chain, &pruntime, extra);
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+    assert_eq!(dispatches(&pruntime), CHAIN_LEN as usize);
+}
+
+#[tokio::test]
+async fn refuses_to_dispatch_storage_changes_mismatching_state_root() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let extra = &["--to-block", "20", "--verify-storage-changes"];
+
+    let pruntime = MockPRuntime::start();
+    let args = harness::args(&chain, &pruntime, extra);
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+
+    // The batch of blocks 9 to 12 is refused, the blocks before it are dispatched.
+    chain.tamper_storage_changes(10);
+    let pruntime = MockPRuntime::start();
+    let args = harness::args(&chain, &pruntime, extra);
+    assert_eq!(pherry::run_bridge(&args).await, 2);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, 9));
+}
+
+#[tokio::test]
+async fn splits_header_sync_and_block_dispatch() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.set_public_key("01");
+    let header_url = pruntime.listen();
+    let block_url = pruntime.listen();
+    let args = harness::args(
+        &chain,
+        &pruntime,
+        &[
+            "--to-block",
+            "20",
+            "--pruntime-header-endpoint",
+            &header_url,
+            "--pruntime-block-endpoint",
+            &block_url,
+        ],
+    );
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+    let header_calls = pruntime.calls_via(&header_url);
+    let block_calls = pruntime.calls_via(&block_url);
+    assert!(header_calls.iter().any(|c| c == "SyncHeader"));
+    assert!(!header_calls.iter().any(|c| c == "DispatchBlocks"));
+    assert!(block_calls.iter().any(|c| c == "DispatchBlocks"));
+    assert!(!block_calls.iter().any(|c| c == "SyncHeader"));
+    let calls = pruntime.calls_via(&pruntime.url);
+    assert!(calls.iter().all(|c| c == "GetInfo"), "{calls:?}");
+}
+
+#[tokio::test]
+async fn rejects_split_endpoint_of_another_worker() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.set_public_key("01");
+    let other = MockPRuntime::start();
+    other.set_public_key("02");
+    let args = harness::args(
+        &chain,
+        &pruntime,
+        &["--to-block", "20", "--pruntime-block-endpoint", &other.url],
+    );
+
+    assert_eq!(pherry::run_bridge(&args).await, 2);
+    assert_eq!(pruntime.progress(), (1, 1));
+    assert_eq!(other.calls(), ["GetInfo"]);
+}
+
+#[tokio::test]
+async fn no_sync_exits_without_syncing() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    let args = harness::args(&chain, &pruntime, &["--no-sync"]);
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (1, 1));
+    assert_eq!(pruntime.calls(), ["GetInfo"]);
+}
+
+#[tokio::test]
+async fn registers_at_chain_tip_until_registered() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    let pubkey = WorkerPublicKey::from_raw([1; 32]);
+    let endpoints = VersionedWorkerEndpoints::V1(vec!["http://10.0.0.1:8000".into()]);
+    let registration = Registration {
+        encoded_runtime_info: b"runtime info".to_vec(),
+        encoded_endpoint_payload: WorkerEndpointPayload {
+            pubkey,
+            versioned_endpoints: endpoints.clone(),
+            signing_time: 0,
+        }
+        .encode(),
+        signature: vec![2; 64],
+    };
+    pruntime.set_public_key(&hex::encode(pubkey));
+    pruntime.set_registration(registration.clone());
+
+    let register = harness::call_data(&phaxt::dynamic::tx::register_worker(
+        registration.encoded_runtime_info,
+        None::<AttestationReport>.encode(),
+        true,
+    ));
+    let bind = harness::call_data(&phaxt::dynamic::tx::update_worker_endpoint(
+        registration.encoded_endpoint_payload,
+        registration.signature,
+    ));
+    chain.on_included(
+        &register,
+        vec![(
+            harness::map_key("PhalaRegistry", "WorkerAddedAt", pubkey.as_ref()),
+            CHAIN_LEN.encode(),
+        )],
+    );
+    chain.on_included(
+        &bind,
+        vec![(
+            harness::map_key("PhalaRegistry", "Endpoints", pubkey.as_ref()),
+            endpoints.encode(),
+        )],
+    );
+    let args = harness::registering_args(&chain, &pruntime, &["--until-registered"]);
+
+    // Exits once both extrinsics are included, rather than following the chain.
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+    let extrinsics = chain.extrinsics();
+    assert_eq!(extrinsics.len(), 2);
+    assert!(
+        extrinsics[0].ends_with(&register),
+        "register_worker not submitted"
+    );
+    assert!(
+        extrinsics[1].ends_with(&bind),
+        "update_worker_endpoint not submitted"
+    );
+}
+
+#[tokio::test]
+async fn exits_on_pruntime_error_without_auto_restart() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.fail("SyncHeader", 1);
+    let args = harness::args(&chain, &pruntime, &["--to-block", "20"]);
+
+    // Exits with 2 since the worker is not registered.
+    assert_eq!(pherry::run_bridge(&args).await, 2);
+    assert_eq!(pruntime.progress(), (1, 1));
+}
+
+#[tokio::test]
+async fn restarts_after_pruntime_error() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.fail("SyncHeader", 1);
+    pruntime.fail("DispatchBlocks", 1);
+    let args = harness::args(&chain, &pruntime, &["--to-block", "20", "--auto-restart"]);
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+}
+
+#[tokio::test]
+async fn gives_up_after_max_restart_retries() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.fail("SyncHeader", u32::MAX);
+    let args = harness::args(
+        &chain,
+        &pruntime,
+        &[
+            "--to-block",
+            "20",
+            "--auto-restart",
+            "--max-restart-retries",
+            "1",
+        ],
+    );
+
+    assert_eq!(pherry::run_bridge(&args).await, 2);
+    let calls = pruntime.calls();
+    assert_eq!(calls.iter().filter(|c| *c == "SyncHeader").count(), 2);
+}
+
+#[tokio::test]
+async fn transient_rejections_do_not_count_as_failures() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.fail_with_code("DispatchBlocks", 2, SyncErrorCode::OutOfOrder as u32);
+    let args = harness::args(
+        &chain,
+        &pruntime,
+        &[
+            "--to-block",
+            "20",
+            "--auto-restart",
+            "--max-restart-retries",
+            "0",
+        ],
+    );
+
+    assert_eq!(pherry::run_bridge(&args).await, 0);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, CHAIN_LEN + 1));
+}
+
+#[tokio::test]
+async fn gives_up_in_safe_mode() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.fail_with_code("SyncHeader", u32::MAX, SyncErrorCode::SafeMode as u32);
+    let args = harness::args(&chain, &pruntime, &["--to-block", "20", "--auto-restart"]);
+
+    assert_eq!(pherry::run_bridge(&args).await, 2);
+    let calls = pruntime.calls();
+    assert_eq!(calls.iter().filter(|c| *c == "SyncHeader").count(), 1);
+}
+
+#[tokio::test]
+async fn halts_when_blocks_stall() {
+    let chain = MockChain::start(CHAIN_LEN).await;
+    let pruntime = MockPRuntime::start();
+    pruntime.get_stuck();
+    let args = harness::args(
+        &chain,
+        &pruntime,
+        &[
+            "--to-block",
+            "20",
+            "--auto-restart",
+            "--stall-timeout",
+            "1",
+            "--stall-action",
+            "halt",
+        ],
+    );
+
+    assert_eq!(pherry::run_bridge(&args).await, pherry::STALL_EXIT_CODE);
+    assert_eq!(pruntime.progress(), (CHAIN_LEN + 1, 1));
+    let calls = pruntime.calls();
+    assert!(calls.iter().any(|c| c == "DispatchBlocks"));
+}
@@ -0,0 +1,119 @@
+use pherry::config::{parse_args, render_config};
+use std::path::PathBuf;
+
+fn write_config(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("pherry-{}-{name}.toml", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn command_line_overrides_config_file() {
+    let path = write_config(
+        "overrides",
+        r#"
+            pruntime_endpoint = "http://10.0.0.1:8000"
+            tip = 5
+            no-register = true
+            mnemonic = "//Bob"
+        "#,
+    );
+    let (_, matches) =
+        parse_args(["pherry", "--config", path.to_str().unwrap(), "--tip", "7"]).unwrap();
+    let rendered = render_config(&matches);
+    let lines: Vec<_> = rendered.lines().collect();
+    assert!(lines.contains(&r#"pruntime_endpoint = "http://10.0.0.1:8000""#));
+    assert!(lines.contains(&"tip = 7"));
+    assert!(lines.contains(&"no_register = true"));
+    assert!(lines.contains(&"no_bind = false"));
+    assert!(lines.contains(&"# mnemonic is set but not shown"));
+    assert!(!rendered.contains("//Bob"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn round_trips_list_options() {
+    let path = write_config("list", "pruntime_allowed_hash = [\"aa\", \"bb\"]\n");
+    let (_, matches) = parse_args([
+        "pherry",
+        "--config",
+        path.to_str().unwrap(),
+        "--pruntime-allowed-hash",
+        "cc",
+    ])
+    .unwrap();
+    let rendered = render_config(&matches);
+    let line = rendered
+        .lines()
+        .find(|line| line.starts_with("pruntime_allowed_hash = "))
+        .unwrap();
+    assert_eq!(line, r#"pruntime_allowed_hash = ["aa", "bb", "cc"]"#);
+
+    std::fs::write(&path, format!("{line}\n")).unwrap();
+    let (_, matches) = parse_args(["pherry", "--config", path.to_str().unwrap()]).unwrap();
+    assert!(render_config(&matches)
+        .lines()
+        .any(|rendered| rendered == line));
+
+    let (_, matches) = parse_args(["pherry"]).unwrap();
+    assert!(render_config(&matches)
+        .lines()
+        .any(|line| line == "pruntime_allowed_hash = []"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rejects_unknown_options_in_config_file() {
+    let path = write_config("unknown", "no_such_option = 1\n");
+    let err = parse_args(["pherry", "--config", path.to_str().unwrap()]).unwrap_err();
+    assert!(err.to_string().contains("no_such_option"), "{err:?}");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rejects_malformed_inject_key() {
+    let err = parse_args(["pherry", "--inject-key", "0x1234"]).unwrap_err();
+    assert!(err.to_string().contains("must be 32 bytes"), "{err:?}");
+    let err = parse_args(["pherry", "--inject-key", &"zz".repeat(32)]).unwrap_err();
+    assert!(err.to_string().contains("hex encoded"), "{err:?}");
+}
+
+#[test]
+fn rejects_inject_key_with_remote_attestation() {
+    let key = format!("0x{}01", "00".repeat(31));
+    let (args, _) = parse_args(["pherry", "--inject-key", &key]).unwrap();
+    let err = args.worker_key().unwrap_err();
+    assert!(err.to_string().contains("remote attestation"), "{err:?}");
+
+    let (args, _) = parse_args([
+        "pherry",
+        "--inject-key",
+        &key,
+        "--attestation-provider",
+        "none",
+    ])
+    .unwrap();
+    assert!(args.worker_key().unwrap().is_some());
+}
+
+#[test]
+fn reads_inject_key_from_file() {
+    let path = write_config("inject-key", &format!("{}\n", "11".repeat(32)));
+    let (args, _) = parse_args([
+        "pherry",
+        "--inject-key-file",
+        path.to_str().unwrap(),
+        "--attestation-provider",
+        "none",
+    ])
+    .unwrap();
+    assert!(args.worker_key().unwrap().is_some());
+    assert!(parse_args([
+        "pherry",
+        "--inject-key-file",
+        path.to_str().unwrap(),
+        "--use-dev-key",
+    ])
+    .is_err());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,558 @@
+use codec::{Decode, Encode};
+use jsonrpsee::{
+    core::Error as RpcError,
+    server::{ServerBuilder, ServerHandle},
+    types::Params,
+    RpcModule,
+};
+use phactory_api::blocks::RuntimeHasher;
+use phala_trie_storage::TrieStorage;
+use phaxt::subxt::{tx::TxPayload, Metadata};
+use sc_consensus_grandpa::FinalityProof;
+use serde_json::{json, Value};
+use sp_consensus_grandpa::{Commit, GrandpaJustification};
+use sp_core::{storage::well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX, Bytes, H256};
+use sp_runtime::traits::{BlakeTwo256, Hash as _, Header as _};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
+pub type Header = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+const METADATA: &[u8] = include_bytes!("../../../prb/artifacts/khala_metadata.scale");
+
+pub type ChildStorageChanges = Vec<(Vec<u8>, Vec<(Vec<u8>, Option<Vec<u8>>)>)>;
+
+/// The child storage changes made by each block, spread over a few child tries and mixing
+/// insertions with deletions. Blocks 9 to 16 change nothing but the bookkeeping, as if the chain
+/// was idle.
+pub fn child_storage_changes(number: u32) -> ChildStorageChanges {
+    if (9..=16).contains(&number) {
+        return vec![];
+    }
+    let child_key = format!("contract:{}", number % 3).into_bytes();
+    let value = if number % 4 == 0 {
+        None
+    } else {
+        Some(number.to_le_bytes().to_vec())
+    };
+    vec![(child_key, vec![(b"key".to_vec(), value)])]
+}
+
+/// The storage key of the value `pallet.item`.
+fn value_key(pallet: &str, item: &str) -> Vec<u8> {
+    [pallet, item]
+        .map(|name| sp_core::twox_128(name.as_bytes()))
+        .concat()
+}
+
+/// The storage key of `key` in the map `pallet.item` hashed with `Twox64Concat`.
+pub fn map_key(pallet: &str, item: &str, key: &[u8]) -> Vec<u8> {
+    let mut storage_key = value_key(pallet, item);
+    storage_key.extend_from_slice(&sp_core::twox_64(key));
+    storage_key.extend_from_slice(key);
+    storage_key
+}
+
+/// Encodes the call data of `call` against the metadata of the mock chain.
+pub fn call_data(call: &impl TxPayload) -> Vec<u8> {
+    let metadata = Metadata::decode(&mut &METADATA[..]).expect("Invalid metadata");
+    call.encode_call_data(&metadata)
+        .expect("Failed to encode the call data")
+}
+
+/// The main storage changes made by each block, only the bookkeeping of the System and Timestamp
+/// pallets done in every block.
+fn main_storage_changes(number: u32) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+    let now = number as u64 * 12_000;
+    vec![
+        (value_key("System", "Number"), Some(number.encode())),
+        (value_key("System", "Events"), Some(vec![0])),
+        (value_key("Timestamp", "Now"), Some(now.encode())),
+        (value_key("Timestamp", "DidUpdate"), None),
+    ]
+}
+
+fn to_bytes(changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Vec<(Bytes, Option<Bytes>)> {
+    changes
+        .into_iter()
+        .map(|(key, value)| (Bytes(key), value.map(Bytes)))
+        .collect()
+}
+
+/// The state after a block, with the default child tries by their keys without the prefix.
+#[derive(Clone, Default)]
+struct State {
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    children: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl State {
+    /// The child trie stored at `child_key`, with the `:child_storage:default:` prefix.
+    fn child(&self, child_key: &[u8]) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        child_key
+            .strip_prefix(DEFAULT_CHILD_STORAGE_KEY_PREFIX)
+            .and_then(|child_key| self.children.get(child_key))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// The extrinsics submitted to the chain, each included right away.
+#[derive(Default)]
+struct Pool {
+    /// The extrinsics submitted so far, in order.
+    extrinsics: Vec<Vec<u8>>,
+    /// The storage written by the extrinsics ending with the given call data.
+    writes: Vec<(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)>,
+    /// The child storage written by the extrinsics ending with the given call data, by the keys
+    /// of the child tries without the prefix.
+    child_writes: Vec<(Vec<u8>, Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)>,
+    /// The storage written by the included extrinsics.
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// The child storage written by the included extrinsics.
+    children: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl Pool {
+    fn submit(&mut self, extrinsic: Vec<u8>) -> H256 {
+        for (call, writes) in &self.writes {
+            if extrinsic.ends_with(call) {
+                self.storage.extend(writes.iter().cloned());
+            }
+        }
+        for (call, child_key, writes) in &self.child_writes {
+            if extrinsic.ends_with(call) {
+                let child = self.children.entry(child_key.clone()).or_default();
+                child.extend(writes.iter().cloned());
+            }
+        }
+        let hash = BlakeTwo256::hash(&extrinsic);
+        self.extrinsics.push(extrinsic);
+        hash
+    }
+}
+
+/// A chain of empty blocks, all of which are finalized, with the state roots of the storage
+/// changes made by each block.
+struct Chain {
+    headers: Vec<Header>,
+    /// The state after each block, by block number.
+    states: Vec<State>,
+    pool: Arc<Mutex<Pool>>,
+    /// The blocks whose storage changes are served with an extra change, leading to another
+    /// state root than the one in their header.
+    tampered: Arc<Mutex<BTreeSet<u32>>>,
+}
+
+impl Chain {
+    fn new(len: u32) -> Self {
+        let mut headers: Vec<Header> = Vec::with_capacity(len as usize + 1);
+        let mut states = Vec::with_capacity(len as usize + 1);
+        let mut trie = TrieStorage::<RuntimeHasher>::default();
+        trie.load(std::iter::empty::<(Vec<u8>, Vec<u8>)>());
+        let mut children = BTreeMap::<_, BTreeMap<_, _>>::new();
+        for number in 0..=len {
+            if number > 0 {
+                let child_changes = child_storage_changes(number);
+                let (root, transaction) =
+                    trie.calc_root_if_changes(&main_storage_changes(number), &child_changes);
+                trie.apply_changes(root, transaction);
+                for (child_key, changes) in child_changes {
+                    let child = children.entry(child_key).or_default();
+                    for (key, value) in changes {
+                        match value {
+                            Some(value) => child.insert(key, value),
+                            None => child.remove(&key),
+                        };
+                    }
+                }
+            }
+            states.push(State {
+                pairs: trie.pairs(b""),
+                children: children.clone(),
+            });
+            let parent_hash = headers.last().map(|h| h.hash()).unwrap_or_default();
+            headers.push(Header::new(
+                number,
+                Default::default(),
+                *trie.root(),
+                parent_hash,
+                Default::default(),
+            ));
+        }
+        Self {
+            headers,
+            states,
+            pool: Default::default(),
+            tampered: Default::default(),
+        }
+    }
+
+    fn tip(&self) -> &Header {
+        self.headers
+            .last()
+            .expect("the chain always has the genesis")
+    }
+
+    fn by_number(&self, number: u32) -> Option<&Header> {
+        self.headers.get(number as usize)
+    }
+
+    fn by_hash(&self, hash: &H256) -> Option<&Header> {
+        self.headers.iter().find(|h| h.hash() == *hash)
+    }
+
+    fn number(&self, hash: H256) -> Result<u32, RpcError> {
+        self.by_hash(&hash)
+            .map(|h| h.number)
+            .ok_or_else(|| RpcError::Custom(format!("Unknown block {hash}")))
+    }
+
+    fn number_or_tip(&self, hash: Option<H256>) -> Result<u32, RpcError> {
+        match hash {
+            Some(hash) => self.number(hash),
+            None => Ok(self.tip().number),
+        }
+    }
+
+    /// The state at the block `hash`, at the tip if None.
+    fn state_at(&self, hash: Option<H256>) -> Result<&State, RpcError> {
+        Ok(&self.states[self.number_or_tip(hash)? as usize])
+    }
+
+    /// The child trie stored at `child_key` at the block `hash`, at the tip if None. The tip
+    /// includes the child storage written by the included extrinsics.
+    fn child_at(
+        &self,
+        hash: Option<H256>,
+        child_key: &[u8],
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, RpcError> {
+        let number = self.number_or_tip(hash)?;
+        let mut child = self.states[number as usize].child(child_key);
+        if number == self.tip().number {
+            let pool = self.pool.lock().unwrap();
+            let writes = child_key
+                .strip_prefix(DEFAULT_CHILD_STORAGE_KEY_PREFIX)
+                .and_then(|child_key| pool.children.get(child_key));
+            if let Some(writes) = writes {
+                child.extend(writes.clone());
+            }
+        }
+        Ok(child)
+    }
+
+    /// The storage changes made by the block, as returned by `pha_getStorageChanges`.
+    ///
+    /// The child storage written by the included extrinsics is changed by the tip, though not
+    /// reflected in its state root.
+    fn storage_changes(&self, number: u32) -> Value {
+        let mut main_changes = main_storage_changes(number);
+        if self.tampered.lock().unwrap().contains(&number) {
+            main_changes.push((value_key("System", "Tampered"), Some(vec![1])));
+        }
+        let mut child_changes = child_storage_changes(number);
+        if number == self.tip().number {
+            let pool = self.pool.lock().unwrap();
+            child_changes.extend(pool.children.iter().map(|(child_key, child)| {
+                let changes = child
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Some(v.clone())))
+                    .collect();
+                (child_key.clone(), changes)
+            }));
+        }
+        let child_changes = child_changes
+            .into_iter()
+            .map(|(child_key, changes)| (Bytes(child_key), to_bytes(changes)))
+            .collect::<Vec<_>>();
+        json!({
+            "mainStorageChanges": to_bytes(main_changes),
+            "childStorageChanges": child_changes,
+        })
+    }
+
+    /// A finality proof justifying the tip, with the headers after `from` as unknown headers.
+    ///
+    /// The justification targets the tip but carries no precommit, the mock pRuntime doesn't
+    /// verify it.
+    fn prove_finality(&self, from: u32) -> Option<Vec<u8>> {
+        let unknown_headers = self.headers.get(from as usize + 1..)?.to_vec();
+        let justification = GrandpaJustification::<Header> {
+            round: 1,
+            commit: Commit::<Header> {
+                target_hash: self.tip().hash(),
+                target_number: self.tip().number,
+                precommits: vec![],
+            },
+            votes_ancestries: vec![],
+        };
+        let proof = FinalityProof {
+            block: self.tip().hash(),
+            justification: justification.encode(),
+            unknown_headers,
+        };
+        Some(proof.encode())
+    }
+}
+
+/// An in-process substrate node serving the RPC methods pherry relies on, backed by a chain of
+/// `len` finalized empty blocks.
+///
+/// The extrinsics submitted are included in the tip right away, and only change the storage as
+/// told by [`MockChain::on_included`] and [`MockChain::on_included_in_child`].
+pub struct MockChain {
+    pub url: String,
+    pool: Arc<Mutex<Pool>>,
+    tampered: Arc<Mutex<BTreeSet<u32>>>,
+    _handle: ServerHandle,
+}
+
+impl MockChain {
+    pub async fn start(len: u32) -> Self {
+        let server = ServerBuilder::default()
+            .build("127.0.0.1:0")
+            .await
+            .expect("Failed to start the mock chain");
+        let url = format!("ws://{}", server.local_addr().unwrap());
+        let chain = Chain::new(len);
+        let pool = chain.pool.clone();
+        let tampered = chain.tampered.clone();
+        let handle = server
+            .start(rpc_module(chain))
+            .expect("Failed to start the mock chain");
+        Self {
+            url,
+            pool,
+            tampered,
+            _handle: handle,
+        }
+    }
+
+    /// Serves the storage changes of the block with an extra change, as if corrupted.
+    pub fn tamper_storage_changes(&self, number: u32) {
+        self.tampered.lock().unwrap().insert(number);
+    }
+
+    /// Writes `storage` once an extrinsic ending with `call_data` is included.
+    pub fn on_included(&self, call_data: &[u8], storage: Vec<(Vec<u8>, Vec<u8>)>) {
+        let mut pool = self.pool.lock().unwrap();
+        pool.writes.push((call_data.to_vec(), storage));
+    }
+
+    /// Writes `storage` to the default child trie at `child_key`, without the prefix, once an
+    /// extrinsic ending with `call_data` is included.
+    pub fn on_included_in_child(
+        &self,
+        call_data: &[u8],
+        child_key: &[u8],
+        storage: Vec<(Vec<u8>, Vec<u8>)>,
+    ) {
+        let mut pool = self.pool.lock().unwrap();
+        pool.child_writes
+            .push((call_data.to_vec(), child_key.to_vec(), storage));
+    }
+
+    /// The extrinsics submitted so far, in order.
+    pub fn extrinsics(&self) -> Vec<Vec<u8>> {
+        self.pool.lock().unwrap().extrinsics.clone()
+    }
+}
+
+fn rpc_module(chain: Chain) -> RpcModule<Chain> {
+    let mut module = RpcModule::new(chain);
+    module
+        .register_method("chain_getBlockHash", |params, chain| {
+            let number = match first_param(params)? {
+                Some(number) => parse_number(&number)?,
+                None => chain.tip().number,
+            };
+            Ok(chain.by_number(number).map(|h| h.hash()))
+        })
+        .unwrap();
+    module
+        .register_method("chain_getFinalizedHead", |_, chain| Ok(chain.tip().hash()))
+        .unwrap();
+    module
+        .register_method("chain_getHeader", |params, chain| {
+            let header = match first_param(params)? {
+                Some(hash) => chain.by_hash(&parse(hash)?),
+                None => Some(chain.tip()),
+            };
+            Ok(header.cloned())
+        })
+        .unwrap();
+    module
+        .register_method("grandpa_proveFinality", |params, chain| {
+            let from = parse_number(&first_param(params)?.unwrap_or_default())?;
+            Ok(chain.prove_finality(from).map(Bytes))
+        })
+        .unwrap();
+    module
+        .register_method("pha_getStorageChanges", |params, chain| {
+            let mut params = params.sequence();
+            let from = chain.number(params.next()?)?;
+            let to = chain.number(params.next()?)?;
+            let changes = (from..=to)
+                .map(|number| chain.storage_changes(number))
+                .collect::<Vec<_>>();
+            Ok(changes)
+        })
+        .unwrap();
+    module
+        .register_method("pha_getStorageChangesWithRoot", |params, chain| {
+            let mut params = params.sequence();
+            let from = chain.number(params.next()?)?;
+            let to = chain.number(params.next()?)?;
+            let changes = (from..=to)
+                .map(|number| {
+                    let state_root = chain.headers[number as usize].state_root;
+                    json!({
+                        "changes": chain.storage_changes(number),
+                        "stateRoot": state_root.as_bytes(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            Ok(changes)
+        })
+        .unwrap();
+    module
+        .register_method("state_getRuntimeVersion", |_, _| {
+            Ok(json!({
+                "specName": "khala",
+                "implName": "khala",
+                "authoringVersion": 1,
+                "specVersion": 1260,
+                "implVersion": 0,
+                "apis": [],
+                "transactionVersion": 7,
+                "stateVersion": 0,
+            }))
+        })
+        .unwrap();
+    module
+        .register_method("state_getMetadata", |_, _| Ok(Bytes(METADATA.to_vec())))
+        .unwrap();
+    // Runtime APIs are not supported, this makes the client fall back to `state_getMetadata`.
+    module
+        .register_method("state_call", |_, _| -> Result<(), _> {
+            Err(RpcError::Custom("Runtime API not supported".into()))
+        })
+        .unwrap();
+    module
+        .register_method("state_getStorage", |params, chain| {
+            let key: Bytes = params.sequence().next()?;
+            let pool = chain.pool.lock().unwrap();
+            Ok(pool.storage.get(&key.0).cloned().map(Bytes))
+        })
+        .unwrap();
+    module
+        .register_method("state_getPairs", |params, chain| {
+            let mut params = params.sequence();
+            let prefix: Bytes = params.next()?;
+            let pairs = chain
+                .state_at(params.optional_next()?)?
+                .pairs
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix.0))
+                .map(|(key, value)| (Bytes(key.clone()), Bytes(value.clone())))
+                .collect::<Vec<_>>();
+            Ok(pairs)
+        })
+        .unwrap();
+    module
+        .register_method("childstate_getKeysPaged", |params, chain| {
+            let mut params = params.sequence();
+            let child_key: Bytes = params.next()?;
+            let prefix: Bytes = params.next()?;
+            let count: usize = params.next()?;
+            let start_key: Option<Bytes> = params.optional_next()?;
+            let child = chain.child_at(params.optional_next()?, &child_key)?;
+            let keys = child
+                .into_keys()
+                .filter(|key| key.starts_with(&prefix.0))
+                .filter(|key| start_key.as_ref().map_or(true, |start| *key > start.0))
+                .take(count)
+                .map(Bytes)
+                .collect::<Vec<_>>();
+            Ok(keys)
+        })
+        .unwrap();
+    module
+        .register_method("childstate_getStorageEntries", |params, chain| {
+            let mut params = params.sequence();
+            let child_key: Bytes = params.next()?;
+            let keys: Vec<Bytes> = params.next()?;
+            let child = chain.child_at(params.optional_next()?, &child_key)?;
+            let values = keys
+                .iter()
+                .map(|key| child.get(&key.0).cloned().map(Bytes))
+                .collect::<Vec<_>>();
+            Ok(values)
+        })
+        .unwrap();
+    module
+        .register_method("system_accountNextIndex", |_, chain| {
+            Ok(chain.pool.lock().unwrap().extrinsics.len() as u32)
+        })
+        .unwrap();
+    module
+        .register_method("author_submitExtrinsic", |params, chain| {
+            let extrinsic: Bytes = params.one()?;
+            Ok(chain.pool.lock().unwrap().submit(extrinsic.0))
+        })
+        .unwrap();
+    module
+        .register_subscription(
+            "author_submitAndWatchExtrinsic",
+            "author_extrinsicUpdate",
+            "author_unwatchExtrinsic",
+            |params, mut sink, chain| {
+                let extrinsic: Bytes = match params.one() {
+                    Ok(extrinsic) => extrinsic,
+                    Err(err) => {
+                        let _ = sink.reject(RpcError::from(err));
+                        return Ok(());
+                    }
+                };
+                chain.pool.lock().unwrap().submit(extrinsic.0);
+                let block = chain.tip().hash();
+                let statuses = [
+                    json!("ready"),
+                    json!({ "inBlock": block }),
+                    json!({ "finalized": block }),
+                ];
+                for status in statuses {
+                    let _ = sink.send(&status);
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+    module
+        .register_method("system_properties", |_, _| {
+            Ok(json!({ "ss58Format": 30, "tokenDecimals": 12, "tokenSymbol": "PHA" }))
+        })
+        .unwrap();
+    module
+}
+
+fn first_param(params: Params) -> Result<Option<Value>, RpcError> {
+    Ok(params.sequence().optional_next::<Value>()?)
+}
+
+fn parse<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, RpcError> {
+    serde_json::from_value(value).map_err(|err| RpcError::Custom(err.to_string()))
+}
+
+/// Parses a block number sent either as a number or as a hex string.
+fn parse_number(value: &Value) -> Result<u32, RpcError> {
+    let number = match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => u64::from_str_radix(s.trim_start_matches("0x"), 16).ok(),
+        _ => None,
+    };
+    number
+        .and_then(|n| n.try_into().ok())
+        .ok_or_else(|| RpcError::Custom(format!("Invalid block number {value}")))
+}
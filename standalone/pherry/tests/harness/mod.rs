@@ -0,0 +1,36 @@
+//! In-process mocks of a substrate node and pRuntime, to run the bridge end to end without any
+//! external service.
+
+mod chain;
+mod pruntime;
+
+pub use chain::{call_data, child_storage_changes, map_key, MockChain};
+pub use pruntime::{MockPRuntime, Registration};
+
+use clap::Parser;
+use pherry::Args;
+
+/// Builds the pherry arguments to bridge the mocks. Registration, endpoint binding and message
+/// submission are disabled.
+pub fn args(chain: &MockChain, pruntime: &MockPRuntime, extra: &[&str]) -> Args {
+    let no_register = ["--no-register", "--no-bind"];
+    registering_args(chain, pruntime, &[&no_register[..], extra].concat())
+}
+
+/// Builds the pherry arguments to bridge the mocks, registering the worker and binding its
+/// endpoint at the chain tip. Message submission is disabled.
+pub fn registering_args(chain: &MockChain, pruntime: &MockPRuntime, extra: &[&str]) -> Args {
+    let base = [
+        "pherry",
+        "--substrate-ws-endpoint",
+        &chain.url,
+        "--pruntime-endpoint",
+        &pruntime.url,
+        "--no-init",
+        "--no-msg-submit",
+        "--no-wait",
+        "--dev-wait-block-ms",
+        "100",
+    ];
+    Args::parse_from(base.iter().chain(extra).copied())
+}
@@ -0,0 +1,301 @@
+use super::chain::ChildStorageChanges;
+use codec::Encode;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use phactory_api::prpc::{self, server::ProtoError, Message};
+use phala_types::AttestationReport;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+/// What the worker submits to register on chain.
+#[derive(Clone)]
+pub struct Registration {
+    pub encoded_runtime_info: Vec<u8>,
+    pub encoded_endpoint_payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    headernum: u32,
+    blocknum: u32,
+    /// The methods called so far, in order.
+    calls: Vec<String>,
+    /// The methods called so far through each url, in order.
+    calls_by_url: BTreeMap<String, Vec<String>>,
+    public_key: Option<String>,
+    /// The runtime info to register and the signed endpoint payload to bind.
+    registration: Option<Registration>,
+    memory_usage: Option<prpc::MemoryUsage>,
+    /// The child storage changes of the dispatched blocks, by block number.
+    child_storage_changes: Vec<(u32, ChildStorageChanges)>,
+    /// The dispatched blocks only changing the bookkeeping, which pRuntime fast-forwards.
+    bookkeeping_only: Vec<u32>,
+    /// Number of upcoming calls to fail and the error code to fail with, by method.
+    failures: BTreeMap<String, (u32, u32)>,
+    /// Whether the dispatched blocks are accepted without being applied.
+    stuck: bool,
+    /// Whether long runs of empty blocks can be dispatched at a time.
+    can_coalesce_empty_blocks: bool,
+}
+
+impl State {
+    fn handle(&mut self, url: &str, method: &str, body: &[u8]) -> Result<Vec<u8>, ProtoError> {
+        self.calls.push(method.into());
+        self.calls_by_url
+            .entry(url.into())
+            .or_default()
+            .push(method.into());
+        if let Some((count, code)) = self.failures.get_mut(method).filter(|(n, _)| *n > 0) {
+            *count -= 1;
+            let message = format!("Injected failure of {method}");
+            return Err(ProtoError::with_code(*code, message));
+        }
+        self.dispatch(method, body).map_err(ProtoError::new)
+    }
+
+    fn dispatch(&mut self, method: &str, body: &[u8]) -> Result<Vec<u8>, String> {
+        match method {
+            "GetInfo" => Ok(prpc::PhactoryInfo {
+                initialized: true,
+                headernum: self.headernum,
+                blocknum: self.blocknum,
+                memory_usage: self.memory_usage.clone(),
+                public_key: self.public_key.clone(),
+                system: self.public_key.clone().map(|public_key| prpc::SystemInfo {
+                    public_key,
+                    ..Default::default()
+                }),
+                can_coalesce_empty_blocks: self.can_coalesce_empty_blocks,
+                ..Default::default()
+            }
+            .encode_to_vec()),
+            "GetRuntimeInfo" => {
+                let registration = self.registration.as_ref().ok_or("Not registering")?;
+                // Attested in dev mode, i.e. without any report.
+                let attestation = prpc::Attestation {
+                    encoded_report: None::<AttestationReport>.encode(),
+                    ..Default::default()
+                };
+                Ok(prpc::InitRuntimeResponse {
+                    encoded_runtime_info: registration.encoded_runtime_info.clone(),
+                    attestation: Some(attestation),
+                    ..Default::default()
+                }
+                .encode_to_vec())
+            }
+            "GetEndpointInfo" => {
+                let registration = self.registration.as_ref();
+                Ok(prpc::GetEndpointResponse {
+                    encoded_endpoint_payload: registration
+                        .map(|r| r.encoded_endpoint_payload.clone()),
+                    signature: registration.map(|r| r.signature.clone()),
+                }
+                .encode_to_vec())
+            }
+            "SyncHeader" => {
+                let request = prpc::HeadersToSync::decode(body).map_err(|e| e.to_string())?;
+                let headers = request.decode_headers().map_err(|e| e.to_string())?;
+                let (first, last) = match (headers.first(), headers.last()) {
+                    (Some(first), Some(last)) => (first, last),
+                    _ => return Err("No headers to sync".into()),
+                };
+                if first.header.number != self.headernum {
+                    return Err(format!(
+                        "Expected header {}, got {}",
+                        self.headernum, first.header.number
+                    ));
+                }
+                if last.justification.is_none() {
+                    return Err("The last header is not justified".into());
+                }
+                self.headernum = last.header.number + 1;
+                Ok(synced_to(last.header.number))
+            }
+            "DispatchBlocks" => {
+                let request = prpc::Blocks::decode(body).map_err(|e| e.to_string())?;
+                let blocks = request.decode_blocks().map_err(|e| e.to_string())?;
+                if self.stuck {
+                    let last = blocks.last().map(|b| b.block_header.number);
+                    return Ok(synced_to(last.unwrap_or(self.blocknum)));
+                }
+                for block in &blocks {
+                    let number = block.block_header.number;
+                    if number != self.blocknum {
+                        return Err(format!("Expected block {}, got {number}", self.blocknum));
+                    }
+                    if number >= self.headernum {
+                        return Err(format!("Header of block {number} not synced"));
+                    }
+                    self.child_storage_changes
+                        .push((number, block.storage_changes.child_storage_changes.clone()));
+                    if block.is_bookkeeping_only() {
+                        self.bookkeeping_only.push(number);
+                    }
+                    self.blocknum += 1;
+                }
+                Ok(synced_to(self.blocknum - 1))
+            }
+            _ => Err(format!("Method {method} not mocked")),
+        }
+    }
+}
+
+fn synced_to(number: u32) -> Vec<u8> {
+    prpc::SyncedTo { synced_to: number }.encode_to_vec()
+}
+
+/// An in-process pRuntime, initialized at the genesis, that tracks the sync progress and checks
+/// the headers and blocks arrive in order.
+pub struct MockPRuntime {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+    servers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl MockPRuntime {
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(State {
+            headernum: 1,
+            blocknum: 1,
+            ..Default::default()
+        }));
+        let (url, server) = serve(state.clone());
+        Self {
+            url,
+            state,
+            servers: Mutex::new(vec![server]),
+        }
+    }
+
+    /// Serves the same pRuntime on another url, as if reached through another network path.
+    pub fn listen(&self) -> String {
+        let (url, server) = serve(self.state.clone());
+        self.servers.lock().unwrap().push(server);
+        url
+    }
+
+    /// Reports the given worker public key in `GetInfo`.
+    pub fn set_public_key(&self, public_key: &str) {
+        self.state.lock().unwrap().public_key = Some(public_key.into());
+    }
+
+    /// Provides what the worker submits to register on chain and bind its endpoint.
+    pub fn set_registration(&self, registration: Registration) {
+        self.state.lock().unwrap().registration = Some(registration);
+    }
+
+    /// Makes the next `count` calls of `method` fail.
+    pub fn fail(&self, method: &str, count: u32) {
+        self.fail_with_code(method, count, 0);
+    }
+
+    /// Makes the next `count` calls of `method` fail with the given error code.
+    pub fn fail_with_code(&self, method: &str, count: u32, code: u32) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .insert(method.into(), (count, code));
+    }
+
+    /// Makes the dispatched blocks accepted without being applied, so the block sync stalls.
+    pub fn get_stuck(&self) {
+        self.state.lock().unwrap().stuck = true;
+    }
+
+    /// Advertises the support of coalesced empty blocks in `GetInfo`.
+    pub fn coalesce_empty_blocks(&self) {
+        self.state.lock().unwrap().can_coalesce_empty_blocks = true;
+    }
+
+    /// Reports the given enclave memory usage in `GetInfo`.
+    pub fn set_memory_usage(&self, used: u64, free: u64) {
+        self.state.lock().unwrap().memory_usage = Some(prpc::MemoryUsage {
+            rust_used: used,
+            free,
+            ..Default::default()
+        });
+    }
+
+    /// Returns the next header and block number expected.
+    pub fn progress(&self) -> (u32, u32) {
+        let state = self.state.lock().unwrap();
+        (state.headernum, state.blocknum)
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    pub fn calls_via(&self, url: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.calls_by_url.get(url).cloned().unwrap_or_default()
+    }
+
+    pub fn child_storage_changes(&self) -> Vec<(u32, ChildStorageChanges)> {
+        self.state.lock().unwrap().child_storage_changes.clone()
+    }
+
+    pub fn bookkeeping_only_blocks(&self) -> Vec<u32> {
+        self.state.lock().unwrap().bookkeeping_only.clone()
+    }
+}
+
+impl Drop for MockPRuntime {
+    fn drop(&mut self) {
+        for server in self.servers.lock().unwrap().iter() {
+            server.abort();
+        }
+    }
+}
+
+fn serve(state: Arc<Mutex<State>>) -> (String, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the mock pRuntime");
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let service_url = url.clone();
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        let url = service_url.clone();
+        let service = service_fn(move |request| handle(state.clone(), url.clone(), request));
+        async move { Ok::<_, Infallible>(service) }
+    });
+    let server = Server::from_tcp(listener)
+        .expect("Failed to start the mock pRuntime")
+        .serve(make_service);
+    let server = tokio::spawn(async move {
+        let _ = server.await;
+    });
+    (url, server)
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    url: String,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = request
+        .uri()
+        .path()
+        .trim_start_matches("/prpc/PhactoryAPI.")
+        .to_string();
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .unwrap_or_default();
+    let result = state.lock().unwrap().handle(&url, &method, &body);
+    let response = match result {
+        Ok(body) => Response::new(Body::from(body)),
+        Err(err) => {
+            let body = err.encode_to_vec();
+            let mut response = Response::new(Body::from(body));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            response
+        }
+    };
+    Ok(response)
+}
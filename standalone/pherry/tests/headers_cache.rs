@@ -0,0 +1,126 @@
+use pherry::headers_cache::{
+    plan_ranges, sign_request, signed_path, verify_request, verify_token, CachedRange, PlannedRange,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn cached(from: u32, to: u32, hash: &str) -> CachedRange {
+    CachedRange {
+        from,
+        to,
+        hash: hash.into(),
+    }
+}
+
+fn planned(from: u32, to: u32, cached: Option<&str>) -> PlannedRange {
+    PlannedRange {
+        from,
+        to,
+        cached: cached.map(Into::into),
+    }
+}
+
+#[test]
+fn plans_the_gaps_around_the_cached_ranges() {
+    let available = [
+        cached(12, 15, "a"),
+        cached(16, 20, "b"),
+        cached(25, 27, "c"),
+    ];
+    assert_eq!(
+        plan_ranges(&available, 10, 30),
+        [
+            planned(10, 11, None),
+            planned(12, 15, Some("a")),
+            planned(16, 20, Some("b")),
+            planned(21, 24, None),
+            planned(25, 27, Some("c")),
+            planned(28, 30, None),
+        ]
+    );
+    assert_eq!(
+        plan_ranges(&[cached(10, 30, "a")], 10, 30),
+        [planned(10, 30, Some("a"))]
+    );
+    assert_eq!(plan_ranges(&[], 10, 30), [planned(10, 30, None)]);
+}
+
+#[test]
+fn skips_the_cached_ranges_not_fitting_the_request() {
+    let available = [
+        // Starting before the requested range.
+        cached(5, 12, "a"),
+        // Ending after it.
+        cached(28, 35, "b"),
+        // Inverted.
+        cached(20, 18, "c"),
+        cached(14, 16, "d"),
+        // Overlapping the previous one.
+        cached(16, 18, "e"),
+    ];
+    assert_eq!(
+        plan_ranges(&available, 10, 30),
+        [
+            planned(10, 13, None),
+            planned(14, 16, Some("d")),
+            planned(17, 30, None),
+        ]
+    );
+}
+
+#[test]
+fn accepts_only_the_exact_token() {
+    assert!(verify_token("secret", "secret"));
+    assert!(!verify_token("secret", "secreT"));
+    assert!(!verify_token("secret", "secret1"));
+    assert!(!verify_token("secret", "secre"));
+    assert!(!verify_token("secret", ""));
+}
+
+#[test]
+fn signs_the_path_below_the_base_uri() {
+    let cases = [
+        (
+            "http://cache:8002",
+            "http://cache:8002/headers/1?until=3",
+            "/headers/1?until=3",
+        ),
+        (
+            "https://proxy/cache",
+            "https://proxy/cache/headers/1",
+            "/headers/1",
+        ),
+        (
+            "https://proxy/cache/",
+            "https://proxy/cache//headers/1",
+            "//headers/1",
+        ),
+        (
+            "https://proxy/cache",
+            "https://proxy/cachex/headers/1",
+            "/cachex/headers/1",
+        ),
+    ];
+    for (base_uri, url, path) in cases {
+        assert_eq!(signed_path(base_uri, url), path, "{url}");
+    }
+
+    // Signed by the client through the proxy, verified against the path the server sees.
+    let key = b"key";
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let path = signed_path(
+        "https://proxy/cache/",
+        "https://proxy/cache//headers/1?until=3",
+    );
+    let signature = sign_request(key, now, &path);
+    assert!(verify_request(key, now, "/headers/1?until=3", &signature));
+    assert!(!verify_request(key, now, "/headers/2?until=3", &signature));
+    assert!(!verify_request(
+        key,
+        now,
+        "/cache/headers/1?until=3",
+        &signature
+    ));
+}
@@ -0,0 +1,45 @@
+use pherry::types::{Heartbeat, SignedHeartbeat, SrSigner};
+use sp_core::{sr25519, Pair};
+
+fn heartbeat() -> Heartbeat {
+    Heartbeat {
+        worker_pubkey: "abcd".into(),
+        headernum: 10,
+        blocknum: 8,
+        timestamp: 1_700_000_000,
+    }
+}
+
+fn signer() -> SrSigner {
+    SrSigner::new(sr25519::Pair::from_string("//Alice", None).unwrap())
+}
+
+#[test]
+fn signs_the_wrapped_payload() {
+    let payload = heartbeat().signing_payload();
+    assert!(payload.starts_with(b"<Bytes>"));
+    assert!(payload.ends_with(b"</Bytes>"));
+
+    let signed = SignedHeartbeat::sign(heartbeat(), &signer()).unwrap();
+    assert_eq!(signed.scheme, "sr25519");
+    signed.verify().unwrap();
+    let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+    assert_eq!(signed.public, hex::encode(pair.public()));
+}
+
+#[test]
+fn rejects_the_tampered_heartbeats() {
+    let signed = SignedHeartbeat::sign(heartbeat(), &signer()).unwrap();
+
+    let mut tampered = signed.clone();
+    tampered.heartbeat.blocknum += 1;
+    tampered.verify().unwrap_err();
+
+    let mut tampered = signed.clone();
+    tampered.public = hex::encode(sr25519::Pair::from_string("//Bob", None).unwrap().public());
+    tampered.verify().unwrap_err();
+
+    let mut tampered = signed;
+    tampered.scheme = "ed25519".into();
+    tampered.verify().unwrap_err();
+}
@@ -0,0 +1,55 @@
+use pherry::keystore::{endpoint_file_name, read_key};
+use std::path::PathBuf;
+
+fn keystore_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pherry-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn names_the_files_after_the_endpoints() {
+    assert_eq!(endpoint_file_name("http://10.0.0.1:8000/"), "10.0.0.1_8000");
+    assert_eq!(
+        endpoint_file_name("https://worker-1.example.com:8443/prpc"),
+        "worker-1.example.com_8443_prpc"
+    );
+}
+
+#[test]
+fn prefers_the_pubkey_over_the_endpoint() {
+    let dir = keystore_dir("prefers-pubkey");
+    std::fs::write(dir.join("10.0.0.1_8000"), "//Bob\n").unwrap();
+    std::fs::write(dir.join("abcd"), "//Charlie\n").unwrap();
+    let dir_str = dir.to_str().unwrap();
+
+    let (file, key) = read_key(dir_str, "http://10.0.0.1:8000", None).unwrap();
+    assert_eq!((file, key.as_str()), (dir.join("10.0.0.1_8000"), "//Bob"));
+    let (_, key) = read_key(dir_str, "http://10.0.0.1:8000", Some("0xABCD")).unwrap();
+    assert_eq!(key, "//Charlie");
+    // Falls back to the endpoint for the workers not in the keystore by pubkey.
+    let (_, key) = read_key(dir_str, "http://10.0.0.1:8000", Some("ef01")).unwrap();
+    assert_eq!(key, "//Bob");
+
+    let err = read_key(dir_str, "http://10.0.0.2:8000", Some("ef01")).unwrap_err();
+    assert!(err.to_string().contains("10.0.0.2_8000"), "{err:?}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn tells_a_new_worker_needs_the_endpoint_file() {
+    let dir = keystore_dir("new-worker");
+    std::fs::write(dir.join("abcd"), "//Charlie\n").unwrap();
+    let dir_str = dir.to_str().unwrap();
+
+    // Keyed by the pubkey only, a worker not initialized yet has no key.
+    let err = read_key(dir_str, "http://10.0.0.1:8000", None).unwrap_err();
+    assert!(
+        err.to_string().contains("named after its endpoint"),
+        "{err:?}"
+    );
+    let (_, key) = read_key(dir_str, "http://10.0.0.1:8000", Some("abcd")).unwrap();
+    assert_eq!(key, "//Charlie");
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,116 @@
+use anyhow::bail;
+use futures::FutureExt;
+use pherry::notify_spool::{NotifySpool, Post, Rejected};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An endpoint failing the first `failures` posts, recording the delivered notifications.
+fn endpoint(failures: u32) -> (Post, Arc<Mutex<Vec<Value>>>) {
+    let delivered = Arc::new(Mutex::new(vec![]));
+    let failures = Arc::new(Mutex::new(failures));
+    let post: Post = {
+        let delivered = delivered.clone();
+        Box::new(move |notification: Value| {
+            let delivered = delivered.clone();
+            let failures = failures.clone();
+            async move {
+                {
+                    let mut failures = failures.lock().unwrap();
+                    if *failures > 0 {
+                        *failures -= 1;
+                        bail!("The endpoint is down");
+                    }
+                }
+                delivered.lock().unwrap().push(notification);
+                Ok(())
+            }
+            .boxed()
+        })
+    };
+    (post, delivered)
+}
+
+async fn drain(spool: &NotifySpool) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while spool.pending() > 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("the spool should be drained");
+}
+
+fn spool_file(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("pherry-{}-{name}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.display().to_string()
+}
+
+#[tokio::test]
+async fn replays_in_order_once_the_endpoint_recovers() {
+    let (post, delivered) = endpoint(1);
+    let spool = NotifySpool::start("", 10, post);
+    for n in 0..3 {
+        spool.push(json!({ "n": n }));
+    }
+    drain(&spool).await;
+    assert_eq!(
+        *delivered.lock().unwrap(),
+        vec![json!({ "n": 0 }), json!({ "n": 1 }), json!({ "n": 2 })]
+    );
+    assert_eq!(spool.dropped(), 0);
+}
+
+#[tokio::test]
+async fn keeps_the_latest_ones_over_a_restart() {
+    let file = spool_file("keeps-the-latest");
+    let (post, delivered) = endpoint(u32::MAX);
+    let spool = NotifySpool::start(&file, 3, post);
+    for n in 0..5 {
+        spool.push(json!({ "n": n }));
+    }
+    assert_eq!((spool.pending(), spool.dropped()), (3, 2));
+    drop(spool);
+    assert!(delivered.lock().unwrap().is_empty());
+
+    let (post, delivered) = endpoint(0);
+    let spool = NotifySpool::start(&file, 3, post);
+    drain(&spool).await;
+    assert_eq!(
+        *delivered.lock().unwrap(),
+        vec![json!({ "n": 2 }), json!({ "n": 3 }), json!({ "n": 4 })]
+    );
+    let left: Vec<Value> = serde_json::from_slice(&std::fs::read(&file).unwrap()).unwrap();
+    assert!(left.is_empty());
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[tokio::test]
+async fn drops_the_rejected_ones() {
+    let delivered = Arc::new(Mutex::new(vec![]));
+    let post: Post = {
+        let delivered = delivered.clone();
+        Box::new(move |notification: Value| {
+            let delivered = delivered.clone();
+            async move {
+                if notification["n"] == 1 {
+                    return Err(Rejected("400 Bad Request".into()).into());
+                }
+                delivered.lock().unwrap().push(notification);
+                Ok(())
+            }
+            .boxed()
+        })
+    };
+    let spool = NotifySpool::start("", 10, post);
+    for n in 0..3 {
+        spool.push(json!({ "n": n }));
+    }
+    drain(&spool).await;
+    assert_eq!(
+        *delivered.lock().unwrap(),
+        vec![json!({ "n": 0 }), json!({ "n": 2 })]
+    );
+    assert_eq!((spool.rejected(), spool.dropped()), (1, 0));
+}
@@ -0,0 +1,53 @@
+use pherry::check_parachain_headers;
+use pherry::types::{BlockNumber, Header};
+
+/// A chain of headers from `from` to `to`, forked by `fork` in their state roots.
+fn chain(from: BlockNumber, to: BlockNumber, fork: u8) -> Vec<Header> {
+    let mut parent_hash = Default::default();
+    (from..=to)
+        .map(|number| {
+            let header = Header {
+                parent_hash,
+                number,
+                state_root: [fork; 32].into(),
+                extrinsics_root: Default::default(),
+                digest: Default::default(),
+            };
+            parent_hash = header.hash();
+            header
+        })
+        .collect()
+}
+
+#[test]
+fn accepts_the_headers_up_to_the_finalized_head() {
+    let headers = chain(10, 14, 0);
+    let finalized = headers.last().unwrap().hash();
+    check_parachain_headers(&headers, finalized).unwrap();
+    check_parachain_headers(&headers[4..], finalized).unwrap();
+    check_parachain_headers(&[], finalized).unwrap();
+}
+
+#[test]
+fn rejects_the_headers_off_the_finalized_chain() {
+    let headers = chain(10, 14, 0);
+    let finalized = headers.last().unwrap().hash();
+
+    // A reorged tip.
+    let forked = chain(10, 14, 1);
+    let err = check_parachain_headers(&forked, finalized).unwrap_err();
+    assert!(err.contains("header 14"), "{err}");
+    assert!(err.contains("expected the finalized"), "{err}");
+
+    // Linking up to the finalized head, from a fork below it.
+    let mut mixed = forked[..2].to_vec();
+    mixed.extend_from_slice(&headers[2..]);
+    let err = check_parachain_headers(&mixed, finalized).unwrap_err();
+    assert_eq!(err, "header 11 is not the parent of header 12");
+
+    // A gap in the numbers.
+    let mut gapped = headers.clone();
+    gapped.remove(2);
+    let err = check_parachain_headers(&gapped, finalized).unwrap_err();
+    assert_eq!(err, "header 11 is not the parent of header 13");
+}
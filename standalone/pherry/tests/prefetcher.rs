@@ -0,0 +1,130 @@
+use anyhow::Result;
+use codec::Encode;
+use phactory_api::blocks::BlockHeaderWithChanges;
+use pherry::block_source::{BlockSource, BlockSources};
+use pherry::headers_cache::{CacheKind, PlannedRange};
+use pherry::types::{BlockNumber, Header};
+use pherry::PrefetchClient;
+use std::sync::{Arc, Mutex};
+
+/// Serves the storage changes of any block, recording the ranges requested.
+#[derive(Default)]
+struct FakeNode {
+    requested: Mutex<Vec<(BlockNumber, BlockNumber)>>,
+}
+
+impl FakeNode {
+    fn requested(&self) -> Vec<(BlockNumber, BlockNumber)> {
+        self.requested.lock().unwrap().clone()
+    }
+}
+
+fn block(number: BlockNumber) -> BlockHeaderWithChanges {
+    let header = Header {
+        parent_hash: Default::default(),
+        number,
+        state_root: Default::default(),
+        extrinsics_root: Default::default(),
+        digest: Default::default(),
+    };
+    BlockHeaderWithChanges::new(header, Default::default())
+}
+
+/// The encoded size of each of the blocks, as the small numbers encode to the same size.
+fn block_size() -> usize {
+    block(1).encoded_size()
+}
+
+fn numbers(blocks: &[BlockHeaderWithChanges]) -> Vec<BlockNumber> {
+    blocks.iter().map(|b| b.block_header.number).collect()
+}
+
+#[async_trait::async_trait]
+impl BlockSource for FakeNode {
+    fn name(&self) -> &'static str {
+        "node"
+    }
+
+    async fn plan(
+        &self,
+        _kind: CacheKind,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Vec<PlannedRange> {
+        vec![PlannedRange {
+            from,
+            to,
+            cached: Some(String::new()),
+        }]
+    }
+
+    async fn storage_changes(
+        &self,
+        range: &PlannedRange,
+        _with_root: bool,
+    ) -> Result<Vec<BlockHeaderWithChanges>> {
+        self.requested.lock().unwrap().push((range.from, range.to));
+        Ok((range.from..=range.to).map(block).collect())
+    }
+
+    async fn parachain_headers(&self, _range: &PlannedRange) -> Result<Vec<Header>> {
+        Ok(vec![])
+    }
+
+    async fn parachain_header_at(
+        &self,
+        _relay_number: BlockNumber,
+    ) -> Result<Option<(BlockNumber, Vec<Vec<u8>>)>> {
+        Ok(None)
+    }
+}
+
+fn sources(node: &Arc<FakeNode>) -> BlockSources {
+    BlockSources::new(vec![node.clone() as Arc<dyn BlockSource>])
+}
+
+#[tokio::test]
+async fn counts_the_fetched_bytes() {
+    let node = Arc::new(FakeNode::default());
+    let sources = sources(&node);
+    let mut fetcher = PrefetchClient::new(false, 0);
+
+    let blocks = fetcher.fetch_storage_changes(&sources, 1, 4).await.unwrap();
+    assert_eq!(numbers(&blocks), vec![1, 2, 3, 4]);
+    assert_eq!(fetcher.fetched_bytes(), 0);
+
+    // Takes 5 and 6 of the prefetched 5 to 8, keeping the others.
+    let blocks = fetcher.fetch_storage_changes(&sources, 5, 6).await.unwrap();
+    assert_eq!(numbers(&blocks), vec![5, 6]);
+    assert_eq!(fetcher.fetched_bytes(), 2 * block_size());
+
+    fetcher.put_back(blocks);
+    assert_eq!(fetcher.fetched_bytes(), 4 * block_size());
+    let blocks = fetcher.fetch_storage_changes(&sources, 5, 8).await.unwrap();
+    assert_eq!(numbers(&blocks), vec![5, 6, 7, 8]);
+    assert_eq!(fetcher.fetched_bytes(), 0);
+    assert_eq!(node.requested()[..2], [(1, 4), (5, 8)]);
+
+    fetcher.put_back(blocks);
+    fetcher.prune();
+    assert_eq!(fetcher.fetched_bytes(), 0);
+}
+
+#[tokio::test]
+async fn drops_the_blocks_beyond_the_limit() {
+    let node = Arc::new(FakeNode::default());
+    let sources = sources(&node);
+    let mut fetcher = PrefetchClient::new(false, 3 * block_size());
+
+    fetcher.fetch_storage_changes(&sources, 1, 4).await.unwrap();
+    // Only 5 to 7 of the prefetched 5 to 8 fit, 5 is taken.
+    let blocks = fetcher.fetch_storage_changes(&sources, 5, 5).await.unwrap();
+    assert_eq!(numbers(&blocks), vec![5]);
+    assert_eq!(fetcher.fetched_bytes(), 2 * block_size());
+
+    // The dropped 8 is fetched again.
+    let blocks = fetcher.fetch_storage_changes(&sources, 6, 8).await.unwrap();
+    assert_eq!(numbers(&blocks), vec![6, 7, 8]);
+    assert_eq!(fetcher.fetched_bytes(), 0);
+    assert_eq!(node.requested()[..3], [(1, 4), (5, 8), (8, 8)]);
+}
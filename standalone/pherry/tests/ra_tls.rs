@@ -0,0 +1,37 @@
+use phactory_api::pruntime_client::CertVerifier;
+use pherry::pccs::CollateralFetcher;
+use pherry::ra_tls::RaTlsVerifier;
+
+fn verifier(allowed_hashes: Vec<Vec<u8>>) -> anyhow::Result<RaTlsVerifier> {
+    RaTlsVerifier::new(
+        CollateralFetcher::new("http://127.0.0.1:1", 1),
+        allowed_hashes,
+    )
+}
+
+#[test]
+fn requires_an_allowlist() {
+    let err = verifier(vec![]).err().unwrap();
+    assert!(
+        err.to_string().contains("--pruntime-allowed-hash"),
+        "{err:?}"
+    );
+}
+
+#[test]
+fn accepts_only_the_allowed_builds() {
+    let verifier = verifier(vec![vec![1; 32], vec![2; 32]]).unwrap();
+    verifier.check_pruntime_hash(&[2; 32]).unwrap();
+    let err = verifier.check_pruntime_hash(&[3; 32]).unwrap_err();
+    assert!(err.to_string().contains(&hex::encode([3; 32])), "{err:?}");
+    // Not a prefix match.
+    verifier.check_pruntime_hash(&[1; 16]).unwrap_err();
+}
+
+#[tokio::test]
+async fn rejects_an_invalid_certificate() {
+    let verifier = verifier(vec![vec![1; 32]]).unwrap();
+    let cert = b"not a certificate";
+    verifier.verify(cert).await.unwrap_err();
+    assert!(!verifier.is_verified(cert));
+}
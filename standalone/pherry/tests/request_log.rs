@@ -0,0 +1,78 @@
+use phactory_api::prpc::client::{Error as ClientError, RequestClient};
+use phactory_api::pruntime_client::RequestRecorder;
+use pherry::request_log::{read_frames, replay, Frame, RequestLog};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pherry-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn frame(path: &str, body: &[u8]) -> Frame {
+    Frame {
+        path: path.into(),
+        body: body.to_vec(),
+    }
+}
+
+/// A pRuntime taking the requests in, failing the ones with an empty body.
+#[derive(Default)]
+struct MockPRuntime {
+    received: Mutex<Vec<Frame>>,
+}
+
+#[async_trait::async_trait]
+impl RequestClient for MockPRuntime {
+    async fn request(&self, path: &str, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        if body.is_empty() {
+            return Err(ClientError::RpcError("Empty request".into()));
+        }
+        self.received.lock().unwrap().push(frame(path, &body));
+        Ok(vec![])
+    }
+}
+
+#[tokio::test]
+async fn replays_the_recorded_requests_in_order() {
+    let dir = log_dir("request-log");
+    let log = RequestLog::create(dir.to_str().unwrap()).unwrap();
+    log.record("PhactoryAPI.InitRuntime", b"init");
+    log.record("PhactoryAPI.GetInfo", b"");
+    log.record("PhactoryAPI.SyncHeader", b"headers");
+    log.record("PhactoryAPI.DispatchBlocks", b"");
+    log.record("PhactoryAPI.DispatchBlocks", b"blocks");
+
+    let recorded = vec![
+        frame("PhactoryAPI.InitRuntime", b"init"),
+        frame("PhactoryAPI.SyncHeader", b"headers"),
+        frame("PhactoryAPI.DispatchBlocks", b""),
+        frame("PhactoryAPI.DispatchBlocks", b"blocks"),
+    ];
+    assert_eq!(read_frames(log.file()).unwrap(), recorded);
+
+    let pruntime = MockPRuntime::default();
+    let err = replay(log.file(), &pruntime, false).await.unwrap_err();
+    assert!(err.to_string().contains("Request 2"), "{err:?}");
+
+    let pruntime = MockPRuntime::default();
+    assert_eq!(replay(log.file(), &pruntime, true).await.unwrap(), (4, 1));
+    let received = pruntime.received.into_inner().unwrap();
+    assert_eq!(received, [&recorded[..2], &recorded[3..]].concat());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rejects_a_truncated_log() {
+    let dir = log_dir("request-log-truncated");
+    let log = RequestLog::create(dir.to_str().unwrap()).unwrap();
+    log.record("PhactoryAPI.SyncHeader", b"headers");
+    let mut bytes = std::fs::read(log.file()).unwrap();
+    bytes.truncate(4 + "PhactoryAPI.SyncHeader".len());
+    std::fs::write(log.file(), bytes).unwrap();
+
+    let err = read_frames(log.file()).unwrap_err();
+    assert!(err.to_string().contains("Truncated frame 0"), "{err:?}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
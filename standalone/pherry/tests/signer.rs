@@ -0,0 +1,97 @@
+use jsonrpsee::{
+    core::Error as RpcError,
+    server::{ServerBuilder, ServerHandle},
+    RpcModule,
+};
+use pherry::signer::{signed_message, RemoteSigner};
+use serde::Deserialize;
+use sp_core::{blake2_256, sr25519, Pair};
+
+#[derive(Deserialize)]
+struct SignParams {
+    call: String,
+    call_hash: String,
+    payload: String,
+}
+
+fn decode(hex: &str) -> Vec<u8> {
+    hex::decode(hex.trim_start_matches("0x")).unwrap()
+}
+
+/// A signing service for `key`, signing the calls in `allowed` only. A `forged` service signs
+/// whatever call it is asked to sign instead of the payload.
+async fn start_service(key: &str, allowed: Vec<Vec<u8>>, forged: bool) -> (String, ServerHandle) {
+    let pair = sr25519::Pair::from_string(key, None).unwrap();
+    let mut module = RpcModule::new(());
+    let public = pair.public();
+    module
+        .register_method("signer_account", move |_, _| {
+            Ok(format!("0x{}", hex::encode(public)))
+        })
+        .unwrap();
+    module
+        .register_method("signer_sign", move |params, _| {
+            let params: SignParams = params.parse()?;
+            let call = decode(&params.call);
+            let payload = decode(&params.payload);
+            if decode(&params.call_hash) != blake2_256(&call) || !allowed.contains(&call) {
+                return Err(RpcError::Custom("Call not allowed".into()));
+            }
+            if !payload.starts_with(&call) {
+                return Err(RpcError::Custom("Payload of another call".into()));
+            }
+            let message = if forged {
+                call
+            } else {
+                signed_message(&payload)
+            };
+            Ok(format!("0x{}", hex::encode(pair.sign(&message))))
+        })
+        .unwrap();
+    let server = ServerBuilder::default()
+        .build("127.0.0.1:0")
+        .await
+        .expect("Failed to start the mock signer");
+    let url = format!("http://{}", server.local_addr().unwrap());
+    (url, server.start(module).unwrap())
+}
+
+#[tokio::test]
+async fn signs_the_allowed_calls() {
+    let call = vec![1u8, 2, 3];
+    let (url, _handle) = start_service("//Alice", vec![call.clone()], false).await;
+    let signer = RemoteSigner::new(&url);
+    let account = signer.account().await.unwrap();
+    let alice = sr25519::Pair::from_string("//Alice", None).unwrap();
+    assert_eq!(&account.0[..], alice.public().as_ref());
+
+    // Long payloads are signed by hash, still checked against the call in full.
+    for extra_len in [8, 300] {
+        let payload = [&call[..], &vec![0; extra_len]].concat();
+        let signature = signer.sign(&account, &call, &payload).await.unwrap();
+        assert!(sr25519::Pair::verify(
+            &sr25519::Signature::from_raw(signature),
+            signed_message(&payload),
+            &alice.public(),
+        ));
+    }
+
+    let err = signer.sign(&account, &[4], &[4, 0]).await.unwrap_err();
+    assert!(err.to_string().contains("refused"), "{err:?}");
+    // Never sent a payload of another call.
+    let err = signer.sign(&account, &call, &[4, 0]).await.unwrap_err();
+    assert!(err.to_string().contains("doesn't start"), "{err:?}");
+}
+
+#[tokio::test]
+async fn rejects_the_signatures_not_of_the_payload() {
+    let call = vec![1u8, 2, 3];
+    let (url, _handle) = start_service("//Alice", vec![call.clone()], true).await;
+    let signer = RemoteSigner::new(&url);
+    let account = signer.account().await.unwrap();
+    let err = signer
+        .sign(&account, &call, &[1, 2, 3, 0])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Bad signature"), "{err:?}");
+}
@@ -0,0 +1,48 @@
+use clap::Parser;
+use pherry::topology::{detect_mode, resolve_mode, NodeKind};
+use pherry::Args;
+
+const RELAY: &str = "ws://relay:9944";
+const PARA: &str = "ws://para:9944";
+
+fn resolve(parachain: bool, relay: NodeKind, para: Option<NodeKind>) -> anyhow::Result<bool> {
+    resolve_mode(parachain, relay, para, RELAY, PARA)
+}
+
+#[test]
+fn enables_a_forgotten_parachain_flag() {
+    let para = Some(NodeKind::Parachain);
+    assert!(resolve(false, NodeKind::Relaychain, para).unwrap());
+    assert!(resolve(true, NodeKind::Relaychain, para).unwrap());
+    assert!(!resolve(false, NodeKind::Solo, None).unwrap());
+}
+
+#[test]
+fn rejects_the_flags_contradicting_the_chains() {
+    let err = resolve(false, NodeKind::Parachain, None).unwrap_err();
+    assert!(err.to_string().contains("run with --parachain"), "{err:?}");
+    let err = resolve(true, NodeKind::Solo, None).unwrap_err();
+    assert!(err.to_string().contains("standalone chain node"), "{err:?}");
+    let err = resolve(true, NodeKind::Relaychain, Some(NodeKind::Relaychain)).unwrap_err();
+    assert!(err.to_string().contains(PARA), "{err:?}");
+    let err = resolve(false, NodeKind::Relaychain, Some(NodeKind::Solo)).unwrap_err();
+    assert!(err.to_string().contains("not a Phala node"), "{err:?}");
+}
+
+#[test]
+fn keeps_the_mode_if_the_parachain_node_is_unreachable() {
+    assert!(!resolve(false, NodeKind::Relaychain, None).unwrap());
+    assert!(resolve(true, NodeKind::Relaychain, None).unwrap());
+}
+
+#[tokio::test]
+async fn skips_the_detection_if_the_nodes_are_unreachable() {
+    let mut args = Args::parse_from([
+        "pherry",
+        "--substrate-ws-endpoint",
+        "ws://127.0.0.1:1",
+        "--collator-ws-endpoint",
+        "ws://127.0.0.1:1",
+    ]);
+    detect_mode(&mut args).await.unwrap();
+}
@@ -0,0 +1,190 @@
+use crate::pool_operator::DB;
+use anyhow::Result;
+use chrono::{Duration, NaiveDate, Utc};
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+static FEE_BY_DAY: &str = "fee:day:";
+static DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The extrinsics submitted by PRB, by their purpose.
+#[derive(
+    Serialize, Deserialize, Encode, Decode, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum TxKind {
+    RegisterWorker,
+    UpdateEndpoint,
+    SyncMessage,
+    AddWorker,
+    StartComputing,
+    StopComputing,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, Default)]
+pub struct FeeTotal {
+    pub count: u64,
+    pub fee: u128,
+}
+
+impl FeeTotal {
+    fn add(&mut self, other: &FeeTotal) {
+        self.count += other.count;
+        self.fee += other.fee;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WorkerFeeReport {
+    pub worker_id: String,
+    pub total: FeeTotal,
+    pub by_kind: BTreeMap<TxKind, FeeTotal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PoolFeeReport {
+    pub pid: u64,
+    pub total: FeeTotal,
+    pub workers: Vec<WorkerFeeReport>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DailyFeeReport {
+    pub date: String,
+    pub total: FeeTotal,
+    pub pools: Vec<PoolFeeReport>,
+}
+
+/// Daily totals of the transaction fees paid for each worker, kept in the pool operator db.
+pub trait FeeLedgerAccess {
+    /// Adds the fee of an extrinsic to today's totals of the worker.
+    ///
+    /// The totals are read and written back without a lock, the callers must not record
+    /// concurrently.
+    fn record_fee(&self, pid: u64, worker_id: &str, kind: TxKind, fee: u128) -> Result<()>;
+    /// Returns the reports of the last `days` days, the latest first.
+    fn get_fee_reports(&self, days: u32) -> Result<Vec<DailyFeeReport>>;
+}
+
+fn day_prefix(date: &NaiveDate) -> String {
+    format!("{FEE_BY_DAY}{}:", date.format(DATE_FORMAT))
+}
+
+fn fee_key(date: &NaiveDate, pid: u64, worker_id: &str) -> String {
+    format!("{}{pid}:{worker_id}", day_prefix(date))
+}
+
+impl FeeLedgerAccess for DB {
+    fn record_fee(&self, pid: u64, worker_id: &str, kind: TxKind, fee: u128) -> Result<()> {
+        let key = fee_key(&Utc::now().date_naive(), pid, worker_id);
+        let mut totals = match self.get(&key)? {
+            Some(b) => BTreeMap::<TxKind, FeeTotal>::decode(&mut &b[..])?,
+            None => BTreeMap::new(),
+        };
+        totals
+            .entry(kind)
+            .or_default()
+            .add(&FeeTotal { count: 1, fee });
+        self.put(key, totals.encode())?;
+        Ok(())
+    }
+
+    fn get_fee_reports(&self, days: u32) -> Result<Vec<DailyFeeReport>> {
+        let today = Utc::now().date_naive();
+        let mut ret = Vec::new();
+        for i in 0..days {
+            let date = today - Duration::days(i as i64);
+            let prefix = day_prefix(&date);
+            let mut pools: BTreeMap<u64, PoolFeeReport> = BTreeMap::new();
+            for result in self.iterator(rocksdb::IteratorMode::From(
+                prefix.as_bytes(),
+                rocksdb::Direction::Forward,
+            )) {
+                let (key, value) = result?;
+                let key = match std::str::from_utf8(&key)?.strip_prefix(&prefix) {
+                    Some(key) => key.to_string(),
+                    None => break,
+                };
+                let (pid, worker_id) = match key.split_once(':') {
+                    Some((pid, worker_id)) => (pid.parse::<u64>()?, worker_id.to_string()),
+                    None => continue,
+                };
+                let by_kind = BTreeMap::<TxKind, FeeTotal>::decode(&mut &value[..])?;
+                let mut total = FeeTotal::default();
+                by_kind.values().for_each(|t| total.add(t));
+                let pool = pools.entry(pid).or_insert_with(|| PoolFeeReport {
+                    pid,
+                    total: FeeTotal::default(),
+                    workers: Vec::new(),
+                });
+                pool.total.add(&total);
+                pool.workers.push(WorkerFeeReport {
+                    worker_id,
+                    total,
+                    by_kind,
+                });
+            }
+            let mut total = FeeTotal::default();
+            pools.values().for_each(|p| total.add(&p.total));
+            ret.push(DailyFeeReport {
+                date: date.format(DATE_FORMAT).to_string(),
+                total,
+                pools: pools.into_values().collect(),
+            });
+        }
+        Ok(ret)
+    }
+}
+
+/// Splits the fee of a batch evenly across its calls, the remainder goes to the first call.
+pub fn split_fee(fee: u128, count: usize) -> Vec<u128> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let share = fee / count as u128;
+    let mut ret = vec![share; count];
+    ret[0] += fee - share * count as u128;
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool_operator::get_options;
+
+    #[test]
+    fn splits_the_batch_fee_without_losing_any() {
+        assert_eq!(split_fee(10, 3), vec![4, 3, 3]);
+        assert_eq!(split_fee(2, 3), vec![2, 0, 0]);
+        assert!(split_fee(10, 0).is_empty());
+    }
+
+    #[test]
+    fn reports_the_recorded_fees_by_pool_and_worker() {
+        let path = std::env::temp_dir().join(format!("prb-fee-ledger-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let db = DB::open(&get_options(None), &path).unwrap();
+        db.record_fee(1, "w1", TxKind::RegisterWorker, 100).unwrap();
+        // A failed tx is recorded the same, the fee is paid anyway.
+        db.record_fee(1, "w1", TxKind::RegisterWorker, 50).unwrap();
+        db.record_fee(1, "w2", TxKind::SyncMessage, 7).unwrap();
+        db.record_fee(2, "w3", TxKind::AddWorker, 3).unwrap();
+
+        let reports = db.get_fee_reports(2).unwrap();
+        assert_eq!(reports.len(), 2);
+        let today = &reports[0];
+        assert_eq!((today.total.count, today.total.fee), (4, 160));
+        assert_eq!(today.pools.len(), 2);
+        let pool = &today.pools[0];
+        assert_eq!((pool.pid, pool.total.count, pool.total.fee), (1, 3, 157));
+        let w1 = &pool.workers[0];
+        assert_eq!(w1.worker_id, "w1");
+        let registered = &w1.by_kind[&TxKind::RegisterWorker];
+        assert_eq!((registered.count, registered.fee), (2, 150));
+        assert_eq!(today.pools[1].workers[0].worker_id, "w3");
+        assert_eq!(reports[1].total.count, 0);
+
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
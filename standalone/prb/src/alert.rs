@@ -0,0 +1,541 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+const PAGER_DUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+const SINK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, derive_more::Display)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// A worker ran into an error in its lifecycle.
+    #[display(fmt = "worker_error")]
+    WorkerError,
+    /// A worker failed to be registered on chain.
+    #[display(fmt = "registration_failed")]
+    RegistrationFailed,
+    /// A worker behind the chaintip made no sync progress for a while.
+    #[display(fmt = "sync_stalled")]
+    SyncStalled,
+    /// A worker entered the unresponsive state on chain.
+    #[display(fmt = "worker_unresponsive")]
+    WorkerUnresponsive,
+    /// The stake of a worker was slashed on chain when reclaimed.
+    #[display(fmt = "worker_slashed")]
+    WorkerSlashed,
+    /// A worker was unbound from its session on chain, i.e. removed from its pool.
+    #[display(fmt = "session_unbound")]
+    SessionUnbound,
+}
+
+impl AlertKind {
+    fn default_rule(&self) -> Rule {
+        let (severity, min_interval_secs) = match self {
+            AlertKind::WorkerError => (Severity::Warning, 600),
+            AlertKind::RegistrationFailed => (Severity::Critical, 600),
+            AlertKind::SyncStalled => (Severity::Warning, 1800),
+            AlertKind::WorkerUnresponsive => (Severity::Critical, 600),
+            AlertKind::WorkerSlashed => (Severity::Critical, 0),
+            AlertKind::SessionUnbound => (Severity::Warning, 600),
+        };
+        Rule {
+            severity,
+            min_interval_secs,
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Default,
+    derive_more::Display,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    #[default]
+    #[display(fmt = "info")]
+    Info,
+    #[display(fmt = "warning")]
+    Warning,
+    #[display(fmt = "critical")]
+    Critical,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub worker_id: String,
+    pub worker_name: String,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+pub type AlertRx = mpsc::UnboundedReceiver<Alert>;
+pub type AlertTx = mpsc::UnboundedSender<Alert>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sink {
+    /// Posts the alert as JSON.
+    Webhook { url: String },
+    /// Posts the alert as a message to a Slack-compatible incoming webhook.
+    Slack { webhook_url: String },
+    /// Triggers an incident with the Events API v2.
+    PagerDuty {
+        routing_key: String,
+        #[serde(default = "default_pager_duty_events_url")]
+        events_url: String,
+    },
+    /// Mails the alert through an SMTP relay, without TLS nor authentication, so the relay is
+    /// expected to be on a trusted network, e.g. a local MTA.
+    Email {
+        /// `host:port` of the relay
+        smtp_server: String,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+impl Sink {
+    fn name(&self) -> &'static str {
+        match self {
+            Sink::Webhook { .. } => "webhook",
+            Sink::Slack { .. } => "slack",
+            Sink::PagerDuty { .. } => "pager_duty",
+            Sink::Email { .. } => "email",
+        }
+    }
+}
+
+fn default_pager_duty_events_url() -> String {
+    PAGER_DUTY_EVENTS_URL.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub sink: Sink,
+    /// The alerts less severe than this are not delivered to the sink
+    #[serde(default)]
+    pub min_severity: Severity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub severity: Severity,
+    /// Alerts of the same kind for the same worker are not repeated within this interval
+    pub min_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct RuleConfig {
+    pub severity: Option<Severity>,
+    pub min_interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct AlertConfig {
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// Overrides of the default rule of each kind of alerts
+    #[serde(default)]
+    pub rules: HashMap<AlertKind, RuleConfig>,
+}
+
+impl AlertConfig {
+    pub fn read_from_file(path: &str) -> Result<Self> {
+        let reader = std::fs::File::open(path).with_context(|| format!("Failed to open {path}"))?;
+        serde_yaml::from_reader(reader).with_context(|| format!("Failed to parse {path}"))
+    }
+
+    pub fn rule(&self, kind: AlertKind) -> Rule {
+        let mut rule = kind.default_rule();
+        if let Some(config) = self.rules.get(&kind) {
+            rule.severity = config.severity.unwrap_or(rule.severity);
+            rule.min_interval_secs = config.min_interval_secs.unwrap_or(rule.min_interval_secs);
+        }
+        rule
+    }
+}
+
+/// Drops the alerts repeating within the interval of their rule, counting them to be mentioned
+/// in the next one delivered.
+#[derive(Default)]
+struct RateLimiter {
+    last: HashMap<(AlertKind, String), (DateTime<Utc>, u32)>,
+}
+
+impl RateLimiter {
+    /// Returns the number of alerts suppressed since the last one if the alert is to be
+    /// delivered, None if it's suppressed.
+    fn check(&mut self, alert: &Alert, interval: Duration) -> Option<u32> {
+        let key = (alert.kind, alert.worker_id.clone());
+        match self.last.get_mut(&key) {
+            Some((sent_at, suppressed)) if alert.at - *sent_at < interval => {
+                *suppressed += 1;
+                None
+            }
+            Some(last) => {
+                let suppressed = last.1;
+                *last = (alert.at, 0);
+                Some(suppressed)
+            }
+            None => {
+                self.last.insert(key, (alert.at, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+struct Delivery {
+    alert: Alert,
+    severity: Severity,
+    suppressed: u32,
+}
+
+impl Delivery {
+    fn summary(&self) -> String {
+        format!(
+            "[prb][{}] {} {}: {}",
+            self.severity, self.alert.kind, self.alert.worker_name, self.alert.message
+        )
+    }
+
+    fn body(&self) -> String {
+        let mut body = format!(
+            "Kind: {}\nSeverity: {}\nWorker: {} ({})\nAt: {}\n\n{}\n",
+            self.alert.kind,
+            self.severity,
+            self.alert.worker_name,
+            self.alert.worker_id,
+            self.alert.at.to_rfc3339(),
+            self.alert.message,
+        );
+        if self.suppressed > 0 {
+            body.push_str(&format!(
+                "\n{} similar alerts were suppressed since the last one.\n",
+                self.suppressed
+            ));
+        }
+        body
+    }
+}
+
+async fn post_json(client: &reqwest::Client, url: &str, body: &serde_json::Value) -> Result<()> {
+    client
+        .post(url)
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+async fn deliver(client: &reqwest::Client, sink: &Sink, delivery: &Delivery) -> Result<()> {
+    let alert = &delivery.alert;
+    match sink {
+        Sink::Webhook { url } => {
+            let body = serde_json::json!({
+                "kind": alert.kind,
+                "severity": delivery.severity,
+                "worker_id": alert.worker_id,
+                "worker_name": alert.worker_name,
+                "message": alert.message,
+                "at": alert.at,
+                "suppressed": delivery.suppressed,
+            });
+            post_json(client, url, &body).await
+        }
+        Sink::Slack { webhook_url } => {
+            let body = serde_json::json!({
+                "text": format!("*{}*\n```{}```", delivery.summary(), delivery.body()),
+            });
+            post_json(client, webhook_url, &body).await
+        }
+        Sink::PagerDuty {
+            routing_key,
+            events_url,
+        } => {
+            let body = serde_json::json!({
+                "routing_key": routing_key,
+                "event_action": "trigger",
+                "dedup_key": format!("prb-{}-{}", alert.kind, alert.worker_id),
+                "payload": {
+                    "summary": delivery.summary(),
+                    "source": "prb",
+                    "severity": delivery.severity,
+                    "timestamp": alert.at,
+                    "custom_details": {
+                        "worker_id": alert.worker_id,
+                        "worker_name": alert.worker_name,
+                        "message": alert.message,
+                        "suppressed": delivery.suppressed,
+                    },
+                },
+            });
+            post_json(client, events_url, &body).await
+        }
+        Sink::Email {
+            smtp_server,
+            from,
+            to,
+        } => tokio::time::timeout(
+            SINK_TIMEOUT,
+            send_mail(smtp_server, from, to, &delivery.summary(), &delivery.body()),
+        )
+        .await
+        .map_err(|_| anyhow!("Timed out"))?,
+    }
+}
+
+struct SmtpSession {
+    reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: tokio::net::tcp::OwnedWriteHalf,
+}
+
+impl SmtpSession {
+    /// Reads a possibly multiline reply, failing if its code is not the expected one.
+    async fn expect(&mut self, code: &str) -> Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                bail!("Connection closed by the SMTP server");
+            }
+            if !line.starts_with(code) {
+                bail!("Unexpected SMTP reply: {}", line.trim_end());
+            }
+            // `250-` continues the reply while `250 ` ends it.
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn command(&mut self, command: &str, code: &str) -> Result<()> {
+        self.writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        self.expect(code).await
+    }
+}
+
+async fn send_mail(
+    server: &str,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> Result<()> {
+    let stream = TcpStream::connect(server)
+        .await
+        .with_context(|| format!("Failed to connect to {server}"))?;
+    let (reader, writer) = stream.into_split();
+    let mut session = SmtpSession {
+        reader: BufReader::new(reader),
+        writer,
+    };
+    session.expect("220").await?;
+    session.command("EHLO prb", "250").await?;
+    session
+        .command(&format!("MAIL FROM:<{from}>"), "250")
+        .await?;
+    for rcpt in to {
+        session.command(&format!("RCPT TO:<{rcpt}>"), "25").await?;
+    }
+    session.command("DATA", "354").await?;
+    session
+        .command(&compose_mail(from, to, subject, body), "250")
+        .await?;
+    session.command("QUIT", "221").await
+}
+
+/// The DATA of a mail, ending with the terminating dot.
+fn compose_mail(from: &str, to: &[String], subject: &str, body: &str) -> String {
+    // The subject comes from the alerts, a line break in it would inject headers.
+    let subject = subject.replace(['\r', '\n'], " ");
+    let mut data = format!(
+        "From: {from}\r\nTo: {}\r\nSubject: {subject}\r\nDate: {}\r\n\r\n",
+        to.join(", "),
+        Utc::now().to_rfc2822(),
+    );
+    for line in body.lines() {
+        // Dot-stuffing, so a line of the body never ends the data.
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push('.');
+    data
+}
+
+/// Delivers the alerts to the sinks of the config, along with a webhook sink to `webhook_url`
+/// if given.
+pub async fn alert_loop(config: AlertConfig, webhook_url: Option<String>, mut rx: AlertRx) {
+    let mut sinks = config.sinks.clone();
+    if let Some(url) = webhook_url {
+        sinks.push(SinkConfig {
+            sink: Sink::Webhook { url },
+            min_severity: Severity::default(),
+        });
+    }
+    if sinks.is_empty() {
+        info!("No alert sink configured, alerts are only logged");
+    }
+    let sinks = Arc::new(sinks);
+    let client = reqwest::Client::builder()
+        .timeout(SINK_TIMEOUT)
+        .build()
+        .expect("Should build reqwest client");
+    let mut limiter = RateLimiter::default();
+
+    while let Some(alert) = rx.recv().await {
+        let rule = config.rule(alert.kind);
+        let interval = Duration::seconds(rule.min_interval_secs as i64);
+        let Some(suppressed) = limiter.check(&alert, interval) else {
+            continue;
+        };
+        let delivery = Delivery {
+            alert,
+            severity: rule.severity,
+            suppressed,
+        };
+        warn!("ALERT: {}", delivery.summary());
+
+        let sinks = sinks.clone();
+        let client = client.clone();
+        // A slow sink should not hold the alerts back.
+        tokio::spawn(async move {
+            for config in sinks.iter() {
+                if delivery.severity < config.min_severity {
+                    continue;
+                }
+                if let Err(err) = deliver(&client, &config.sink, &delivery).await {
+                    warn!("Failed to deliver alert to {}: {err:?}", config.sink.name());
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(kind: AlertKind, worker_id: &str, at: DateTime<Utc>) -> Alert {
+        Alert {
+            kind,
+            worker_id: worker_id.into(),
+            worker_name: worker_id.into(),
+            message: "error".into(),
+            at,
+        }
+    }
+
+    #[test]
+    fn rate_limiter_suppresses_repeated_alerts() {
+        let mut limiter = RateLimiter::default();
+        let interval = Duration::seconds(60);
+        let t0 = Utc::now();
+        let at = |secs| t0 + Duration::seconds(secs);
+
+        assert_eq!(
+            limiter.check(&alert(AlertKind::WorkerError, "a", at(0)), interval),
+            Some(0)
+        );
+        assert_eq!(
+            limiter.check(&alert(AlertKind::WorkerError, "a", at(10)), interval),
+            None
+        );
+        assert_eq!(
+            limiter.check(&alert(AlertKind::WorkerError, "a", at(20)), interval),
+            None
+        );
+        // Other workers and kinds are limited on their own.
+        assert_eq!(
+            limiter.check(&alert(AlertKind::WorkerError, "b", at(20)), interval),
+            Some(0)
+        );
+        assert_eq!(
+            limiter.check(&alert(AlertKind::SyncStalled, "a", at(20)), interval),
+            Some(0)
+        );
+        assert_eq!(
+            limiter.check(&alert(AlertKind::WorkerError, "a", at(60)), interval),
+            Some(2)
+        );
+        assert_eq!(
+            limiter.check(&alert(AlertKind::WorkerError, "a", at(70)), interval),
+            None
+        );
+    }
+
+    #[test]
+    fn config_overrides_default_rules() {
+        let config: AlertConfig = serde_yaml::from_str(
+            r#"
+sinks:
+  - type: slack
+    webhook_url: https://hooks.slack.com/services/x
+    min_severity: critical
+  - type: pager_duty
+    routing_key: key
+rules:
+  sync_stalled:
+    severity: critical
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.sinks.len(), 2);
+        assert_eq!(config.sinks[0].min_severity, Severity::Critical);
+        assert_eq!(
+            config.sinks[1].sink,
+            Sink::PagerDuty {
+                routing_key: "key".into(),
+                events_url: PAGER_DUTY_EVENTS_URL.into(),
+            }
+        );
+        assert_eq!(
+            config.rule(AlertKind::SyncStalled),
+            Rule {
+                severity: Severity::Critical,
+                min_interval_secs: 1800,
+            }
+        );
+        assert_eq!(
+            config.rule(AlertKind::WorkerError),
+            AlertKind::WorkerError.default_rule()
+        );
+    }
+
+    #[test]
+    fn mail_subject_stays_one_header() {
+        let to = vec!["ops@example.com".to_string()];
+        let data = compose_mail(
+            "prb@example.com",
+            &to,
+            "worker a failed\r\nBcc: evil@example.com",
+            "error\n.\nmore",
+        );
+        let (headers, body) = data.split_once("\r\n\r\n").unwrap();
+        assert!(headers.contains("Subject: worker a failed  Bcc: evil@example.com\r\n"));
+        assert!(!headers.contains("\r\nBcc:"));
+        assert_eq!(body, "error\r\n..\r\nmore\r\n.");
+    }
+}
@@ -1,17 +1,25 @@
+use crate::accounting::{DailyFeeReport, FeeLedgerAccess};
 use crate::cli::{ConfigCommands, WorkerManagerCliArgs};
 use crate::configurator::api_handler;
 use crate::inv_db::Worker;
+use crate::local_cache::CompressionStatus;
 use crate::processor::WorkerEvent;
+use crate::handover::{start_handover, HandoverRequest, HandoverStatus};
+use crate::rollout::{abort_rollout, start_rollout, RolloutRequest, RolloutStatus};
 use crate::tx::Transaction;
 use crate::wm::WrappedWorkerManagerContext;
-use crate::worker::{WorkerLifecycleCommand, WorkerLifecycleState};
-use axum::extract::State;
+use crate::worker::{StateTransition, WorkerLifecycleCommand, WorkerLifecycleState};
+use crate::worker_status::{VersionMatrix, WorkerMetricsSample, WorkerStatusStreamItem};
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::routing::*;
 use axum::{Json, Router};
+use chrono::{DateTime, Utc};
 use futures::future::try_join_all;
-use log::{error, info};
+use futures::Stream;
+use log::{error, info, warn};
 use phactory_api::prpc::PhactoryInfo;
 use phala_git_revision::git_revision_with_ts;
 use phala_pallets::pallet_computation::SessionInfo;
@@ -19,9 +27,14 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
 
 type AppContext = State<WrappedWorkerManagerContext>;
 
+const MAX_TX_FEES_DAYS: u32 = 90;
+
 #[derive(thiserror::Error, Debug)]
 pub enum ApiError {
     #[error("Server error")]
@@ -41,6 +54,21 @@ pub enum ApiError {
 
     #[error("met inconsistent data, this is a bug, please report with full backtrace")]
     InconsistentData,
+
+    #[error("no enabled worker in group: {0}")]
+    EmptyGroup(String),
+
+    #[error("a rollout is already running for group: {0}")]
+    RolloutConflict(String),
+
+    #[error("rollout not found: {0}")]
+    RolloutNotFound(String),
+
+    #[error("a handover is already running for worker: {0}")]
+    HandoverConflict(String),
+
+    #[error("local cache is not enabled")]
+    LocalCacheDisabled,
 }
 
 type ApiResult<T> = Result<T, ApiError>;
@@ -52,6 +80,9 @@ pub struct WorkerStatus {
     pub phactory_info: Option<PhactoryInfo>,
     pub last_message: String,
     pub session_info: Option<SessionInfo>,
+    /// The latest lifecycle state transitions, the oldest first.
+    #[serde(default)]
+    pub transitions: Vec<StateTransition>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -59,6 +90,21 @@ pub struct WorkerStatusResponse {
     workers: Vec<WorkerStatus>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkerHistoryQuery {
+    pub id: String,
+    /// RFC 3339 time of the first sample, the oldest kept if absent.
+    pub from: Option<DateTime<Utc>>,
+    /// RFC 3339 time of the last sample, the latest if absent.
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkerHistoryResponse {
+    pub id: String,
+    pub samples: Vec<WorkerMetricsSample>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TxStatusResponse {
     pub tx_count: usize,
@@ -67,6 +113,16 @@ pub struct TxStatusResponse {
     pub past_txs: Vec<Transaction>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxFeesQuery {
+    pub days: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxFeesResponse {
+    pub days: Vec<DailyFeeReport>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WmStatusResponse {
     pub git_revision: String,
@@ -138,6 +194,9 @@ pub async fn start_api_server(
         .route("/wm/restart", put(handle_restart_wm))
         .route("/wm/config", post(handle_config_wm))
         .route("/workers/status", get(handle_get_worker_status))
+        .route("/workers/status/stream", get(handle_stream_worker_status))
+        .route("/workers/history", get(handle_get_worker_history))
+        .route("/workers/versions", get(handle_get_worker_versions))
         .route("/workers/restart", put(handle_restart_specific_workers))
         .route(
             "/workers/force_register",
@@ -145,7 +204,14 @@ pub async fn start_api_server(
         )
         .route("/workers/update_endpoints", put(handle_update_endpoints))
         .route("/workers/take_checkpoint", put(handle_take_checkpoint))
+        .route("/workers/rollout", put(handle_start_rollout))
+        .route("/rollouts/status", get(handle_get_rollout_status))
+        .route("/rollouts/abort", put(handle_abort_rollouts))
+        .route("/workers/handover", put(handle_start_handover))
+        .route("/handovers/status", get(handle_get_handover_status))
         .route("/tx/status", get(handle_get_tx_status))
+        .route("/tx/fees", get(handle_get_tx_fees))
+        .route("/local_cache/stats", get(handle_get_local_cache_stats))
         .fallback(handle_get_root)
         .with_state(ctx);
 
@@ -187,6 +253,53 @@ async fn handle_get_worker_status(
     Ok((StatusCode::OK, Json(WorkerStatusResponse { workers })))
 }
 
+async fn handle_stream_worker_status(
+    State(ctx): AppContext,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    // Subscribe before taking the snapshot so that no update in between gets lost.
+    let rx = ctx.worker_status_stream_tx.subscribe();
+    let snapshot = {
+        let map = ctx.worker_status_map.lock().await;
+        map.iter()
+            .map(|(id, status)| WorkerStatusStreamItem {
+                id: id.clone(),
+                status: Some(status.clone()),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let updates = BroadcastStream::new(rx).filter_map(|item| match item {
+        Ok(item) => Some(item),
+        Err(BroadcastStreamRecvError::Lagged(count)) => {
+            warn!("Worker status stream subscriber lagged, {} updates skipped", count);
+            None
+        },
+    });
+    let stream = tokio_stream::iter(snapshot)
+        .chain(updates)
+        .map(|item| Event::default().event("status").json_data(item));
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn handle_get_worker_history(
+    State(ctx): AppContext,
+    Query(query): Query<WorkerHistoryQuery>,
+) -> ApiResult<(StatusCode, Json<WorkerHistoryResponse>)> {
+    let history = ctx.worker_history.lock().await;
+    let samples = history
+        .query(&query.id, query.from, query.to)
+        .ok_or_else(|| ApiError::WorkerNotFound(query.id.clone()))?;
+    Ok((StatusCode::OK, Json(WorkerHistoryResponse { id: query.id, samples })))
+}
+
+async fn handle_get_worker_versions(
+    State(ctx): AppContext,
+) -> ApiResult<(StatusCode, Json<VersionMatrix>)> {
+    let map = ctx.worker_status_map.lock().await;
+    Ok((StatusCode::OK, Json(VersionMatrix::new(map.values()))))
+}
+
 async fn handle_restart_specific_workers(
     State(ctx): State<WrappedWorkerManagerContext>,
     Json(payload): Json<IdsRequest>,
@@ -262,6 +375,56 @@ async fn handle_take_checkpoint(
     Ok((StatusCode::OK, Json(OkResponse::default())))
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RolloutStatusResponse {
+    pub rollouts: Vec<RolloutStatus>,
+}
+
+async fn handle_start_rollout(
+    State(ctx): State<WrappedWorkerManagerContext>,
+    Json(payload): Json<RolloutRequest>,
+) -> ApiResult<(StatusCode, Json<RolloutStatus>)> {
+    let status = start_rollout(ctx, payload).await?;
+    Ok((StatusCode::OK, Json(status)))
+}
+
+async fn handle_get_rollout_status(
+    State(ctx): AppContext,
+) -> ApiResult<(StatusCode, Json<RolloutStatusResponse>)> {
+    let rollouts = ctx.rollouts.lock().await.values().cloned().collect();
+    Ok((StatusCode::OK, Json(RolloutStatusResponse { rollouts })))
+}
+
+async fn handle_abort_rollouts(
+    State(ctx): State<WrappedWorkerManagerContext>,
+    Json(payload): Json<IdsRequest>,
+) -> ApiResult<(StatusCode, Json<OkResponse>)> {
+    for id in payload.ids {
+        abort_rollout(ctx.clone(), &id).await?;
+    }
+    Ok((StatusCode::OK, Json(OkResponse::default())))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HandoverStatusResponse {
+    pub handovers: Vec<HandoverStatus>,
+}
+
+async fn handle_start_handover(
+    State(ctx): State<WrappedWorkerManagerContext>,
+    Json(payload): Json<HandoverRequest>,
+) -> ApiResult<(StatusCode, Json<HandoverStatus>)> {
+    let status = start_handover(ctx, payload).await?;
+    Ok((StatusCode::OK, Json(status)))
+}
+
+async fn handle_get_handover_status(
+    State(ctx): AppContext,
+) -> ApiResult<(StatusCode, Json<HandoverStatusResponse>)> {
+    let handovers = ctx.handovers.lock().await.values().cloned().collect();
+    Ok((StatusCode::OK, Json(HandoverStatusResponse { handovers })))
+}
+
 async fn handle_get_tx_status(
     State(ctx): AppContext,
 ) -> ApiResult<(StatusCode, Json<TxStatusResponse>)> {
@@ -269,6 +432,22 @@ async fn handle_get_tx_status(
     Ok((StatusCode::OK, Json(txm.dump().await?)))
 }
 
+async fn handle_get_tx_fees(
+    State(ctx): AppContext,
+    Query(query): Query<TxFeesQuery>,
+) -> ApiResult<(StatusCode, Json<TxFeesResponse>)> {
+    let days = query.days.unwrap_or(7).min(MAX_TX_FEES_DAYS);
+    let days = ctx.txm.db.get_fee_reports(days)?;
+    Ok((StatusCode::OK, Json(TxFeesResponse { days })))
+}
+
+async fn handle_get_local_cache_stats(
+    State(ctx): AppContext,
+) -> ApiResult<(StatusCode, Json<CompressionStatus>)> {
+    let local_cache = ctx.dsm.local_cache.as_ref().ok_or(ApiError::LocalCacheDisabled)?;
+    Ok((StatusCode::OK, Json(local_cache.compression_status())))
+}
+
 async fn handle_config_wm(
     State(ctx): State<WrappedWorkerManagerContext>,
     Json(payload): Json<ConfigCommands>,
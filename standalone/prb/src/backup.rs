@@ -0,0 +1,398 @@
+use crate::inv_db::{dump_inventory, restore_inventory, InventoryDump, WrappedDb};
+use crate::pool_operator::{get_options, DB};
+use anyhow::{bail, Context, Result};
+use indradb::RocksdbDatastore;
+use log::info;
+use parity_scale_codec::{Decode, Encode, IoReader};
+use phala_git_revision::git_revision_with_ts;
+use rocksdb::{IteratorMode, WriteBatch};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"PRBSTATE";
+const VERSION: u32 = 1;
+const BATCH_SIZE: usize = 4096;
+
+/// The key-value stores under the database path, besides the inventory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+enum Store {
+    /// The pool operators and the fee ledger
+    Po,
+    Headers,
+    LocalCache,
+}
+
+impl Store {
+    const ALL: [Store; 3] = [Store::Po, Store::Headers, Store::LocalCache];
+
+    fn dir(&self) -> &'static str {
+        match self {
+            Store::Po => "po",
+            Store::Headers => "headers",
+            Store::LocalCache => "cache",
+        }
+    }
+
+    fn is_chain_data(&self) -> bool {
+        matches!(self, Store::Headers | Store::LocalCache)
+    }
+}
+
+/// The archive is the magic followed by a stream of entries, starting with the manifest and
+/// closed by `End`, so that a truncated archive is told from a complete one.
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+enum Entry {
+    Manifest {
+        version: u32,
+        created_at: String,
+        git_revision: String,
+    },
+    /// The inventory as JSON, its properties being JSON values
+    Inventory(Vec<u8>),
+    Kv {
+        store: Store,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    End,
+    /// The persisted index of the local cache, dumped like the inventory
+    Index(Vec<u8>),
+}
+
+/// The directories of the state under the database path.
+const STATE_DIRS: [&str; 5] = ["inventory", "index", "po", "headers", "cache"];
+
+/// Opens the graph database in `dir` under `db_path`, the inventory or the cache index.
+fn open_graph(db_path: &Path, dir: &str) -> Result<WrappedDb> {
+    let path = db_path.join(dir);
+    let db = RocksdbDatastore::new(&path, None)
+        .with_context(|| format!("Failed to open {path:?}, is prb-wm still running?"))?;
+    Ok(Arc::new(db))
+}
+
+/// Creates the archive file, readable by the owner only as it holds the pool operator keys.
+fn create_archive(path: &str) -> Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .with_context(|| format!("Failed to create {path}"))
+}
+
+fn write_entry(writer: &mut impl Write, entry: &Entry) -> Result<()> {
+    writer.write_all(&entry.encode())?;
+    Ok(())
+}
+
+fn write_entries(
+    writer: &mut impl Write,
+    inventory: &InventoryDump,
+    index: Option<&InventoryDump>,
+    stores: &[(Store, DB)],
+) -> Result<()> {
+    writer.write_all(MAGIC)?;
+    write_entry(
+        writer,
+        &Entry::Manifest {
+            version: VERSION,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            git_revision: git_revision_with_ts().to_string(),
+        },
+    )?;
+    write_entry(writer, &Entry::Inventory(serde_json::to_vec(inventory)?))?;
+    if let Some(index) = index {
+        write_entry(writer, &Entry::Index(serde_json::to_vec(index)?))?;
+    }
+    for (store, db) in stores {
+        let mut count = 0u64;
+        for kv in db.iterator(IteratorMode::Start) {
+            let (key, value) = kv?;
+            write_entry(
+                writer,
+                &Entry::Kv {
+                    store: *store,
+                    key: key.into(),
+                    value: value.into(),
+                },
+            )?;
+            count += 1;
+        }
+        info!("Exported {count} entries of {}", store.dir());
+    }
+    write_entry(writer, &Entry::End)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Snapshots the state of the deployment at `db_path` into an archive at `output`.
+///
+/// The inventory is locked by prb-wm while it runs, so prb-wm has to be stopped first. The worker
+/// statuses are not persisted by prb-wm and get rebuilt once it starts on the restored state.
+pub fn export(db_path: &str, output: &str, skip_chain_data: bool) -> Result<()> {
+    let inventory = dump_inventory(open_graph(Path::new(db_path), "inventory")?)?;
+    info!(
+        "Exporting {} vertices and {} edges of the inventory",
+        inventory.vertices.len(),
+        inventory.edges.len()
+    );
+    // Only there with a persisted cache index, and it goes along with the local cache.
+    let index = if !skip_chain_data && Path::new(db_path).join("index").exists() {
+        let index = dump_inventory(open_graph(Path::new(db_path), "index")?)?;
+        info!(
+            "Exporting {} vertices of the cache index",
+            index.vertices.len()
+        );
+        Some(index)
+    } else {
+        None
+    };
+    let mut stores = vec![];
+    for store in Store::ALL {
+        if skip_chain_data && store.is_chain_data() {
+            continue;
+        }
+        let path = Path::new(db_path).join(store.dir());
+        if !path.exists() {
+            info!("Skipped {}, it does not exist", store.dir());
+            continue;
+        }
+        let db = DB::open_for_read_only(&get_options(None), &path, false)
+            .with_context(|| format!("Failed to open {path:?}"))?;
+        stores.push((store, db));
+    }
+
+    let file = create_archive(output)?;
+    let result = write_entries(
+        &mut BufWriter::new(file),
+        &inventory,
+        index.as_ref(),
+        &stores,
+    );
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result?;
+    info!("State exported to {output}");
+    Ok(())
+}
+
+fn read_entry(reader: &mut impl Read) -> Result<Entry> {
+    Entry::decode(&mut IoReader(reader)).context("Archive is corrupted or truncated")
+}
+
+/// Restores an archive written by [`export`] into `db_path`, which must not hold any state yet.
+///
+/// The state is imported into a staging directory first and moved into place once complete, so
+/// that a failed import leaves nothing behind to block a retry.
+pub fn import(db_path: &str, input: &str) -> Result<()> {
+    for dir in STATE_DIRS {
+        let path = Path::new(db_path).join(dir);
+        if path.exists() {
+            bail!("{path:?} exists, refusing to import over an existing deployment");
+        }
+    }
+
+    let staging = Path::new(db_path).join(format!(".import-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging).with_context(|| format!("Failed to create {staging:?}"))?;
+    let result = import_into(&staging, input).and_then(|()| {
+        for dir in STATE_DIRS {
+            let from = staging.join(dir);
+            if from.exists() {
+                std::fs::rename(&from, Path::new(db_path).join(dir))
+                    .with_context(|| format!("Failed to move {from:?} into {db_path}"))?;
+            }
+        }
+        Ok(())
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    result?;
+    info!("State imported into {db_path}, prb-wm can be started on it");
+    Ok(())
+}
+
+fn restore_graph(db_path: &Path, dir: &str, json: &[u8]) -> Result<()> {
+    let dump: InventoryDump = serde_json::from_slice(json)?;
+    info!(
+        "Importing {} vertices and {} edges of the {dir}",
+        dump.vertices.len(),
+        dump.edges.len()
+    );
+    restore_inventory(open_graph(db_path, dir)?, dump)
+}
+
+/// Imports the archive into the empty `db_path`, closing the databases before it returns.
+fn import_into(db_path: &Path, input: &str) -> Result<()> {
+    let file = File::open(input).with_context(|| format!("Failed to open {input}"))?;
+    let mut reader = BufReader::new(file);
+    let mut magic = [0u8; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("{input} is not a PRB state archive");
+    }
+    match read_entry(&mut reader)? {
+        Entry::Manifest {
+            version,
+            created_at,
+            git_revision,
+        } => {
+            if version != VERSION {
+                bail!("Unsupported archive version {version}");
+            }
+            info!("Importing state exported at {created_at} by prb {git_revision}");
+        }
+        _ => bail!("Archive has no manifest"),
+    }
+    match read_entry(&mut reader)? {
+        Entry::Inventory(json) => restore_graph(db_path, "inventory", &json)?,
+        _ => bail!("Archive has no inventory"),
+    }
+
+    let mut stores: Vec<(Store, DB, WriteBatch, u64)> = vec![];
+    loop {
+        match read_entry(&mut reader)? {
+            Entry::Index(json) => restore_graph(db_path, "index", &json)?,
+            Entry::Kv { store, key, value } => {
+                let index = match stores.iter().position(|(s, ..)| *s == store) {
+                    Some(index) => index,
+                    None => {
+                        let path = db_path.join(store.dir());
+                        let db = DB::open(&get_options(None), &path)
+                            .with_context(|| format!("Failed to open {path:?}"))?;
+                        stores.push((store, db, WriteBatch::default(), 0));
+                        stores.len() - 1
+                    }
+                };
+                let (_, db, batch, count) = &mut stores[index];
+                batch.put(key, value);
+                *count += 1;
+                if batch.len() >= BATCH_SIZE {
+                    db.write(std::mem::take(batch))?;
+                }
+            }
+            Entry::End => break,
+            _ => bail!("Unexpected entry in the archive"),
+        }
+    }
+    for (store, db, batch, count) in stores {
+        db.write(batch)?;
+        db.flush()?;
+        info!("Imported {count} entries of {}", store.dir());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indradb::{Identifier, SpecificVertexQuery, VertexPropertyQuery, VertexQuery};
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("prb-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn add_vertex(db: &WrappedDb, t: &str, name: &str) {
+        let id = db
+            .create_vertex_from_type(Identifier::new(t).unwrap())
+            .unwrap();
+        let query: VertexQuery = SpecificVertexQuery { ids: vec![id] }.into();
+        db.set_vertex_properties(
+            VertexPropertyQuery {
+                inner: query,
+                name: Identifier::new("name").unwrap(),
+            },
+            serde_json::Value::String(name.into()),
+        )
+        .unwrap();
+    }
+
+    /// The directories left in `db_path`, besides the state ones.
+    fn leftovers(db_path: &Path) -> Vec<String> {
+        std::fs::read_dir(db_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| !STATE_DIRS.contains(&name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn state_roundtrips_through_an_archive() {
+        let src = temp_path("backup-src");
+        let dst = temp_path("backup-dst");
+        let archive = temp_path("backup-archive");
+        std::fs::create_dir_all(&src).unwrap();
+        {
+            add_vertex(&open_graph(&src, "inventory").unwrap(), "worker", "w1");
+            add_vertex(&open_graph(&src, "index").unwrap(), "cached", "c1");
+            let po = DB::open(&get_options(None), src.join("po")).unwrap();
+            po.put(b"po_list", [1, 2, 3]).unwrap();
+        }
+        let archive = archive.to_str().unwrap();
+        export(src.to_str().unwrap(), archive, false).unwrap();
+
+        // A failed import leaves nothing behind to block the retry.
+        let bytes = std::fs::read(archive).unwrap();
+        let truncated = temp_path("backup-truncated");
+        std::fs::write(&truncated, &bytes[..bytes.len() - 1]).unwrap();
+        std::fs::create_dir_all(&dst).unwrap();
+        import(dst.to_str().unwrap(), truncated.to_str().unwrap()).unwrap_err();
+        assert_eq!(std::fs::read_dir(&dst).unwrap().count(), 0);
+
+        import(dst.to_str().unwrap(), archive).unwrap();
+        assert!(leftovers(&dst).is_empty());
+        for dir in ["inventory", "index"] {
+            let expected = dump_inventory(open_graph(&src, dir).unwrap()).unwrap();
+            let imported = dump_inventory(open_graph(&dst, dir).unwrap()).unwrap();
+            assert_eq!(imported, expected);
+            assert_eq!(imported.vertices.len(), 1);
+        }
+        let po = DB::open_for_read_only(&get_options(None), dst.join("po"), false).unwrap();
+        assert_eq!(po.get(b"po_list").unwrap(), Some(vec![1, 2, 3]));
+        drop(po);
+
+        // Not over an existing deployment.
+        import(dst.to_str().unwrap(), archive).unwrap_err();
+        for path in [src, dst, truncated, PathBuf::from(archive)] {
+            let _ = std::fs::remove_dir_all(&path);
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn entries_roundtrip_through_a_stream() {
+        let entries = vec![
+            Entry::Manifest {
+                version: VERSION,
+                created_at: "2024-01-01T00:00:00+00:00".into(),
+                git_revision: "deadbeef".into(),
+            },
+            Entry::Inventory(b"{}".to_vec()),
+            Entry::Kv {
+                store: Store::Po,
+                key: b"po_list".to_vec(),
+                value: vec![1, 2, 3],
+            },
+            Entry::End,
+        ];
+        let mut buf = vec![];
+        for entry in &entries {
+            write_entry(&mut buf, entry).unwrap();
+        }
+        let mut reader = &buf[..];
+        for entry in entries {
+            assert_eq!(read_entry(&mut reader).unwrap(), entry);
+        }
+        assert!(read_entry(&mut reader).is_err());
+    }
+}
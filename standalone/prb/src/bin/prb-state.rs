@@ -0,0 +1,3 @@
+fn main() {
+    prb::cli::start_state()
+}
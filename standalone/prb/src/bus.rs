@@ -2,6 +2,7 @@ use log::error;
 use std::sync::mpsc::SendError as StdSendError;
 use tokio::sync::mpsc::error::SendError;
 
+use crate::alert::{Alert, AlertTx};
 use crate::processor::{PRuntimeRequest, ProcessorEvent, ProcessorTx, WorkerEvent};
 use crate::messages::{MessagesEvent, MessagesTx};
 use crate::worker_status::{WorkerStatusEvent, WorkerStatusTx};
@@ -11,6 +12,7 @@ pub struct Bus {
     pub processor_tx: ProcessorTx,
     pub messages_tx: MessagesTx,
     pub worker_status_tx: WorkerStatusTx,
+    pub alert_tx: AlertTx,
 }
 
 impl Bus {
@@ -64,4 +66,12 @@ impl Bus {
         }
         result
     }
+
+    pub fn send_alert(&self, alert: Alert) -> Result<(), SendError<Alert>> {
+        let result = self.alert_tx.send(alert);
+        if let Err(err) = &result {
+            error!("Fail to send message to alert_tx. {}", err);
+        }
+        result
+    }
 }
\ No newline at end of file
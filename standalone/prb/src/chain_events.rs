@@ -0,0 +1,124 @@
+//! The on-chain events about the workers, picked from the events of each parachain block applied
+//! to the chain state cache of the processor, for the ones to react to. The session info, changed
+//! by the settlements and the benchmarks, is refreshed after each batch of blocks anyway.
+
+use chain::RuntimeEvent;
+use phala_pallets::pallet_computation;
+use phala_types::WorkerPublicKey;
+use sp_core::crypto::AccountId32;
+
+/// Whom an event is about, the workers bound to a session being mostly referred to by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subject {
+    Session(AccountId32),
+    Worker(WorkerPublicKey),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkerChainEvent {
+    /// The worker missed its heartbeats and stopped earning.
+    EnterUnresponsive,
+    /// The worker sent its heartbeats again.
+    ExitUnresponsive,
+    /// The worker is reclaimed after the cool down, with the stake slashed for it if any.
+    Reclaimed { original_stake: u128, slashed: u128 },
+    /// The worker is unbound from its session, i.e. removed from its pool.
+    SessionUnbound,
+}
+
+/// What to do with a worker entering the unresponsive state on chain, besides alerting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum UnresponsiveAction {
+    /// Nothing more
+    Alert,
+    /// Restart the worker
+    Restart,
+    /// Register the worker again
+    Register,
+}
+
+impl WorkerChainEvent {
+    /// Picks the events about the workers out of the events of a block.
+    pub fn pick(events: impl IntoIterator<Item = RuntimeEvent>) -> Vec<(Subject, Self)> {
+        events.into_iter().filter_map(Self::from_event).collect()
+    }
+
+    fn from_event(event: RuntimeEvent) -> Option<(Subject, Self)> {
+        use pallet_computation::Event as Computation;
+        use Subject::{Session, Worker};
+
+        let RuntimeEvent::PhalaComputation(event) = event else {
+            return None;
+        };
+        let picked = match event {
+            Computation::WorkerEnterUnresponsive { session } => {
+                (Session(session), Self::EnterUnresponsive)
+            }
+            Computation::WorkerExitUnresponsive { session } => {
+                (Session(session), Self::ExitUnresponsive)
+            }
+            Computation::WorkerReclaimed {
+                session,
+                original_stake,
+                slashed,
+            } => (
+                Session(session),
+                Self::Reclaimed {
+                    original_stake,
+                    slashed,
+                },
+            ),
+            Computation::SessionUnbound { worker, .. } => (Worker(worker), Self::SessionUnbound),
+            _ => return None,
+        };
+        Some(picked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Computation = pallet_computation::Event<chain::Runtime>;
+
+    #[test]
+    fn picks_the_worker_events() {
+        let session = AccountId32::new([1; 32]);
+        let worker = WorkerPublicKey::from_raw([2; 32]);
+        let events = vec![
+            RuntimeEvent::PhalaComputation(Computation::WorkerEnterUnresponsive {
+                session: session.clone(),
+            }),
+            RuntimeEvent::PhalaComputation(Computation::SubsidyBudgetHalved),
+            RuntimeEvent::PhalaComputation(Computation::WorkerReclaimed {
+                session: session.clone(),
+                original_stake: 100,
+                slashed: 3,
+            }),
+            RuntimeEvent::PhalaComputation(Computation::SessionUnbound {
+                session: session.clone(),
+                worker,
+            }),
+            RuntimeEvent::PhalaRegistry(
+                phala_pallets::registry::Event::MinimumPRuntimeVersionChangedTo(2, 0, 0),
+            ),
+        ];
+        assert_eq!(
+            WorkerChainEvent::pick(events),
+            vec![
+                (
+                    Subject::Session(session.clone()),
+                    WorkerChainEvent::EnterUnresponsive
+                ),
+                (
+                    Subject::Session(session),
+                    WorkerChainEvent::Reclaimed {
+                        original_stake: 100,
+                        slashed: 3
+                    }
+                ),
+                (Subject::Worker(worker), WorkerChainEvent::SessionUnbound),
+            ]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::backup;
+use crate::chain_events::UnresponsiveAction;
 use crate::configurator;
 use crate::wm::wm;
 use clap::{Parser, Subcommand, ValueEnum};
@@ -27,15 +29,34 @@ pub struct WorkerManagerCliArgs {
     #[arg(long, env)]
     pub disable_fast_sync: bool,
 
+    /// Max age in seconds of the chain state cloned from a synced and unregistered worker to
+    /// fast-sync new workers, 0 to always load the chain state from the parachain node
+    #[arg(long, env, default_value_t = 3600)]
+    pub fast_sync_clone_max_age: u64,
+
     /// Size of in-memory cache, default to 1 GiB
     #[arg(short = 'c', long, env, default_value_t = 1073741824)]
     pub cache_size: usize,
 
-    /// URL of webhook endpoint
+    /// URL of webhook endpoint to post the alerts to, besides the sinks of the alert config
     #[arg(short = 'w', long, env)]
     pub webhook_url: Option<String>,
 
-    /// URL of PCCS server to get collateral
+    /// Path to the config of the alert sinks and rules, alerts are only logged if neither it nor
+    /// the webhook URL is given
+    #[arg(long, env)]
+    pub alert_config_path: Option<String>,
+
+    /// Time in seconds a worker behind the chaintip can go without sync progress before it's
+    /// alerted as stalled, 0 to disable
+    #[arg(long, env, default_value_t = 600)]
+    pub alert_sync_stall_timeout: u64,
+
+    /// What to do with a worker entering the unresponsive state on chain, besides alerting
+    #[arg(long, env, value_enum, default_value_t = UnresponsiveAction::Alert)]
+    pub on_unresponsive: UnresponsiveAction,
+
+    /// URL of PCCS server to get collateral, multiple servers separated by commas are tried in order
     #[arg(long, env, default_value = "")]
     pub pccs_url: String,
 
@@ -43,12 +64,128 @@ pub struct WorkerManagerCliArgs {
     #[arg(long, env, default_value = "10")]
     pub pccs_timeout: u64,
 
+    /// Directory to cache the collateral fetched from PCCS, caching is disabled if empty
+    #[arg(long, env, default_value = "")]
+    pub pccs_cache_dir: String,
+
+    /// Time in seconds that the cached collateral is valid for
+    #[arg(long, env, default_value = "3600")]
+    pub pccs_cache_ttl: u64,
+
+    /// Min number of blocks per sync request
+    #[arg(long, env, default_value_t = 4)]
+    pub min_block_batch_size: u32,
+
+    /// Max number of blocks per sync request
+    #[arg(long, env, default_value_t = 64)]
+    pub max_block_batch_size: u32,
+
+    /// Target dispatch latency in milliseconds of a sync request, the number of blocks per sync
+    /// request is adjusted between the min and max to meet it
+    #[arg(long, env, default_value_t = 2000)]
+    pub block_batch_target_ms: u64,
+
+    /// Parachain block to stop syncing the workers at, unless a worker sets its own, e.g. to
+    /// prepare snapshots or reproduce issues at an exact height
+    #[arg(long, env)]
+    pub to_block: Option<u32>,
+
+    /// Number of times in a row the sync of a worker can fail at the same block before the
+    /// worker is paused, the pause doubling for each further failure, 0 to mark the worker as
+    /// failed instead
+    #[arg(long, env, default_value_t = 5)]
+    pub sync_failure_pause_threshold: u32,
+
+    /// Number of sync requests to prefetch ahead of the slowest worker, 0 to disable prefetching
+    #[arg(long, env, default_value_t = 16)]
+    pub prefetch_window: u32,
+
+    /// Memory budget in bytes of the prefetched sync requests
+    #[arg(long, env, default_value_t = 268435456)]
+    pub prefetch_memory_budget: usize,
+
+    /// Maximum number of events the processor handles in a tick, the events left over are
+    /// rescheduled along with the newly received ones, sync events first
+    #[arg(long, env, default_value_t = 256)]
+    pub processor_tick_max_events: usize,
+
+    /// Time budget in milliseconds of a processor tick
+    #[arg(long, env, default_value_t = 50)]
+    pub processor_tick_budget_ms: u64,
+
+    /// Max number of transactions of the pool operators in flight, the registrations are let in
+    /// first, then the endpoint updates, then the offchain messages, 0 for no limit
+    #[arg(long, env, default_value_t = 16)]
+    pub max_in_flight_txs: usize,
+
+    /// Max number of metric samples kept per worker for `/workers/history`, 0 to disable
+    #[arg(long, env, default_value_t = 2880)]
+    pub worker_history_size: usize,
+
+    /// Interval in seconds between the metric samples of a worker, the changes of its lifecycle
+    /// state are sampled right away
+    #[arg(long, env, default_value_t = 30)]
+    pub worker_history_interval: u64,
+
+    /// Interval in seconds to fetch the egress messages from the registered workers at the chain
+    /// tip, besides fetching them after each dispatch, 0 to disable
+    #[arg(long, env, default_value_t = 30)]
+    pub get_egress_interval: u64,
+
+    /// Continuously grab the finalized headers, justifications, parachain proofs and storage
+    /// changes from the full nodes of the data sources into the local database, so that no
+    /// standalone headers cache is needed
+    #[arg(long, env)]
+    pub local_cache: bool,
+
+    /// Relaychain block to start grabbing headers from into the local cache, default to the
+    /// finalized one at the first start
+    #[arg(long, env)]
+    pub local_cache_relay_start: Option<u32>,
+
+    /// Parachain block to start grabbing headers and storage changes from into the local cache
+    #[arg(long, env, default_value_t = 0)]
+    pub local_cache_para_start: u32,
+
+    /// Preferred min number of relaychain blocks between justifications grabbed into the local cache
+    #[arg(long, env, default_value_t = 1000)]
+    pub local_cache_justification_interval: u32,
+
+    /// Number of blocks per request when grabbing storage changes into the local cache
+    #[arg(long, env, default_value_t = 10)]
+    pub local_cache_storage_changes_batch: u32,
+
+    /// Interval in seconds between the rounds of grabbing into the local cache
+    #[arg(long, env, default_value_t = 30)]
+    pub local_cache_interval: u64,
+
     /// download headers db only
     #[arg(long, env)]
     pub download_headers_only: bool,
 
     #[arg(long, env)]
     pub verify_saved_headers: bool,
+
+    /// Run the given number of headless fake workers instead of the workers in the inventory, to
+    /// load-test the worker manager without real enclaves
+    #[arg(long, env, default_value_t = 0)]
+    pub simulate: usize,
+
+    /// Base latency in milliseconds of the requests to the simulated pRuntimes
+    #[arg(long, env, default_value_t = 50)]
+    pub simulate_latency_ms: u64,
+
+    /// Max random latency in milliseconds added to the requests to the simulated pRuntimes
+    #[arg(long, env, default_value_t = 50)]
+    pub simulate_latency_jitter_ms: u64,
+
+    /// Extra latency in milliseconds of the simulated pRuntimes for each block dispatched
+    #[arg(long, env, default_value_t = 20)]
+    pub simulate_block_ms: u64,
+
+    /// Probability between 0 and 1 of a request to a simulated pRuntime to fail
+    #[arg(long, env, default_value_t = 0.0)]
+    pub simulate_failure_rate: f64,
 }
 
 pub async fn start_wm() {
@@ -180,6 +317,21 @@ pub enum ConfigCommands {
         /// Whether the worker should be a gatekeeper
         #[arg(short, long, default_value_t = false)]
         gatekeeper: bool,
+
+        /// Groups of the worker, separated by commas
+        #[arg(long, value_delimiter = ',')]
+        #[serde(default)]
+        groups: Vec<String>,
+
+        /// Region of the data sources to prefer syncing from, falling back to the others
+        #[arg(long)]
+        #[serde(default)]
+        sync_affinity: Option<String>,
+
+        /// Parachain block to stop syncing at, overriding the global --to-block
+        #[arg(long)]
+        #[serde(default)]
+        to_block: Option<u32>,
     },
 
     /// Update a worker
@@ -215,6 +367,21 @@ pub enum ConfigCommands {
         /// Whether the worker should be a gatekeeper
         #[arg(short, long, default_value_t = false)]
         gatekeeper: bool,
+
+        /// Groups of the worker, separated by commas
+        #[arg(long, value_delimiter = ',')]
+        #[serde(default)]
+        groups: Vec<String>,
+
+        /// Region of the data sources to prefer syncing from, falling back to the others
+        #[arg(long)]
+        #[serde(default)]
+        sync_affinity: Option<String>,
+
+        /// Parachain block to stop syncing at, overriding the global --to-block
+        #[arg(long)]
+        #[serde(default)]
+        to_block: Option<u32>,
     },
 
     /// Remove a worker
@@ -286,3 +453,55 @@ pub async fn start_config() {
         }
     }
 }
+
+#[derive(Parser, Debug)]
+#[command(name="prb-state", version, about="Back up and restore the state of a PRB deployment", long_about = None)]
+pub struct StateCliArgs {
+    /// Path to the local database
+    #[arg(short = 'd', long, env, default_value = "/var/data/prb-wm")]
+    pub db_path: String,
+
+    #[command(subcommand)]
+    pub command: StateCommands,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum StateCommands {
+    /// Export the inventory, the pool operators, the fee ledger and the synced chain data into an archive, prb-wm must be stopped
+    Export {
+        /// Path of the archive to write
+        #[arg(short, long)]
+        output: String,
+
+        /// Leave out the headers and the local cache, which can be downloaded again
+        #[arg(long, default_value_t = false)]
+        skip_chain_data: bool,
+    },
+
+    /// Restore an archive into an empty database path
+    Import {
+        /// Path of the archive to read
+        #[arg(short, long)]
+        input: String,
+    },
+}
+
+pub fn start_state() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Info)
+        .format_timestamp_micros()
+        .parse_default_env()
+        .init();
+    let args = StateCliArgs::parse();
+    let result = match &args.command {
+        StateCommands::Export {
+            output,
+            skip_chain_data,
+        } => backup::export(&args.db_path, output, *skip_chain_data),
+        StateCommands::Import { input } => backup::import(&args.db_path, input),
+    };
+    if let Err(e) = result {
+        eprintln!("{e:?}");
+        std::process::exit(1);
+    }
+}
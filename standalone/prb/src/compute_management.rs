@@ -4,6 +4,7 @@ use crate::tx::TxManager;
 use crate::worker::WorkerLifecycleState;
 use chrono::Utc;
 use log::{error, info, trace};
+use pherry::pccs::CollateralFetcher;
 use phactory_api::prpc::InitRuntimeResponse;
 use sp_core::sr25519::Public as Sr25519Public;
 use std::fmt;
@@ -193,8 +194,7 @@ pub async fn do_register(
     worker_id: String,
     pool_id: u64,
     response: InitRuntimeResponse,
-    pccs_url: String,
-    pccs_timeout_secs: u64,
+    collateral_fetcher: Arc<CollateralFetcher>,
 ) {
     let attestation = match response.attestation {
         Some(attestation) => attestation,
@@ -210,8 +210,7 @@ pub async fn do_register(
     let v2 = attestation.payload.is_none();
     let attestation = pherry::attestation_to_report(
         attestation,
-        &pccs_url,
-        pccs_timeout_secs,
+        &collateral_fetcher,
     )
     .await;
     let attestation = match attestation {
@@ -226,7 +225,7 @@ pub async fn do_register(
         },
     };
 
-    let result = txm.register_worker(pool_id, response.encoded_runtime_info, attestation, v2).await;
+    let result = txm.register_worker(pool_id, worker_id.clone(), response.encoded_runtime_info, attestation, v2).await;
     match result {
         Ok(_) => {
             info!("[{}] Worker Register Completed.", worker_id);
@@ -256,7 +255,7 @@ async fn do_add_worker_to_pool(
     pool_id: u64,
     worker_public_key: Sr25519Public,
 ) {
-    let result = txm.add_worker(pool_id, worker_public_key).await;
+    let result = txm.add_worker(pool_id, worker_id.clone(), worker_public_key).await;
     if let Err(err) = result {
         let err_msg = format!("Failed to add_worker_to_pool. {}", err);
         error!("[{}] {}", worker_id, err_msg);
@@ -272,7 +271,7 @@ async fn do_start_computing(
     worker_public_key: Sr25519Public,
     stake: String,
 ) {
-    let result = txm.start_computing(pool_id, worker_public_key, stake).await;
+    let result = txm.start_computing(pool_id, worker_id.clone(), worker_public_key, stake).await;
     if let Err(err) = result {
         let err_msg = format!("Failed to start computing. {}", err);
         error!("[{}] {}", worker_id, err_msg);
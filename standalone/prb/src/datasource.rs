@@ -1,4 +1,5 @@
 use crate::datasource::DataSourceError::*;
+use crate::local_cache::WrappedLocalCache;
 use anyhow::{anyhow, Context, Result};
 use jsonrpsee::{
     async_client::ClientBuilder,
@@ -79,6 +80,9 @@ pub struct SubstrateWebSocketSource {
     pub pruned: bool,
     #[serde(default = "default_max_concurrent_requests")]
     pub max_concurrent_requests: usize,
+    /// Where the source is, preferred by the workers with the same sync affinity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
 }
 
 fn default_max_concurrent_requests() -> usize {
@@ -88,6 +92,12 @@ fn default_max_concurrent_requests() -> usize {
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct HeadersCacheHttpSource {
     pub endpoint: String,
+    /// Credentials of the headers cache, `token:<token>` or `hmac:<hex key>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<String>,
+    /// Where the source is, preferred by the workers with the same sync affinity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
 }
 
 pub struct SubstrateWebSocketSourceInstance {
@@ -96,6 +106,7 @@ pub struct SubstrateWebSocketSourceInstance {
     pub client: ChainApi,
     pub endpoint: String,
     pub pruned: bool,
+    pub region: Option<String>,
 }
 
 pub struct HeadersCacheHttpSourceInstance {
@@ -103,6 +114,7 @@ pub struct HeadersCacheHttpSourceInstance {
     pub uuid_str: String,
     pub client: CacheClient,
     pub endpoint: String,
+    pub region: Option<String>,
 }
 
 pub type WrappedSubstrateWebSocketSourceInstance = Arc<SubstrateWebSocketSourceInstance>;
@@ -191,6 +203,7 @@ pub struct DataSourceManager {
     pub is_relaychain_full: bool,
     pub is_parachain_full: bool,
     pub cache: Cache<String, Arc<DataSourceCacheItem>>,
+    pub local_cache: Option<WrappedLocalCache>,
 }
 
 macro_rules! dump_ds_ids_from_config {
@@ -253,103 +266,106 @@ macro_rules! ds_loop {
     };
 }
 
+/// Picks the first source of `ids` online, preferring the ones in `region`, so that a worker
+/// fails over to the other regions once the sources in its own are down.
+fn select_source<T>(
+    ids: DataSourceIdList,
+    policy: &SelectPolicy,
+    map: &HashMap<String, Arc<T>>,
+    region: Option<&str>,
+    region_of: impl Fn(&T) -> Option<&str>,
+) -> Option<Arc<T>> {
+    let mut ids = ids;
+    match policy {
+        SelectPolicy::Random => ids.shuffle(&mut thread_rng()),
+        SelectPolicy::Failover => {}
+    };
+    let mut online = ids.iter().filter_map(|id| map.get(id));
+    match region {
+        Some(region) => online
+            .clone()
+            .find(|i| region_of(i) == Some(region))
+            .or_else(|| online.next())
+            .cloned(),
+        None => online.next().cloned(),
+    }
+}
+
 impl DataSourceManager {
     pub async fn current_relaychain_rpc_client(
         self: Arc<Self>,
         full: bool,
+        region: Option<&str>,
     ) -> Option<WrappedSubstrateWebSocketSourceInstance> {
         let ids = if full {
             &self.relaychain_full_rpc_client_ids
         } else {
             &self.relaychain_rpc_client_ids
         };
-        let mut ids = ids.clone();
-        match &self.config.relaychain.select_policy {
-            SelectPolicy::Random => ids.shuffle(&mut thread_rng()),
-            SelectPolicy::Failover => {}
-        };
-
-        let map = self.relaychain_rpc_client_map.clone();
-        let map = map.read().await;
-        for i in ids {
-            if let Some(i) = map.get(&i) {
-                return Some(i.clone());
-            }
-        }
-        None
+        let map = self.relaychain_rpc_client_map.read().await;
+        select_source(
+            ids.clone(),
+            &self.config.relaychain.select_policy,
+            &map,
+            region,
+            |i| i.region.as_deref(),
+        )
     }
 
     pub async fn current_parachain_rpc_client(
         self: Arc<Self>,
         full: bool,
+        region: Option<&str>,
     ) -> Option<WrappedSubstrateWebSocketSourceInstance> {
         let ids = if full {
             &self.parachain_full_rpc_client_ids
         } else {
             &self.parachain_rpc_client_ids
         };
-        let mut ids = ids.clone();
-        match &self.config.parachain.select_policy {
-            SelectPolicy::Random => ids.shuffle(&mut thread_rng()),
-            SelectPolicy::Failover => {}
-        };
-
-        let map = self.parachain_rpc_client_map.clone();
-        let map = map.read().await;
-        for i in ids {
-            if let Some(i) = map.get(&i) {
-                return Some(i.clone());
-            }
-        }
-        None
+        let map = self.parachain_rpc_client_map.read().await;
+        select_source(
+            ids.clone(),
+            &self.config.parachain.select_policy,
+            &map,
+            region,
+            |i| i.region.as_deref(),
+        )
     }
 
     pub async fn current_relaychain_headers_cache(
         self: Arc<Self>,
+        region: Option<&str>,
     ) -> Option<WrappedHeadersCacheHttpSourceInstance> {
-        let ids = &self.relaychain_headers_cache_ids;
-        let mut ids = ids.clone();
-        match &self.config.relaychain.select_policy {
-            SelectPolicy::Random => ids.shuffle(&mut thread_rng()),
-            SelectPolicy::Failover => {}
-        };
-
-        let map = self.relaychain_headers_cache_map.clone();
-        let map = map.read().await;
-        for i in ids {
-            if let Some(i) = map.get(&i) {
-                return Some(i.clone());
-            }
-        }
-        None
+        let map = self.relaychain_headers_cache_map.read().await;
+        select_source(
+            self.relaychain_headers_cache_ids.clone(),
+            &self.config.relaychain.select_policy,
+            &map,
+            region,
+            |i| i.region.as_deref(),
+        )
     }
 
     pub async fn current_parachain_headers_cache(
         self: Arc<Self>,
+        region: Option<&str>,
     ) -> Option<WrappedHeadersCacheHttpSourceInstance> {
-        let ids = &self.parachain_headers_cache_ids;
-        let mut ids = ids.clone();
-        match &self.config.parachain.select_policy {
-            SelectPolicy::Random => ids.shuffle(&mut thread_rng()),
-            SelectPolicy::Failover => {}
-        };
-
-        let map = self.parachain_headers_cache_map.clone();
-        let map = map.read().await;
-        for i in ids {
-            if let Some(i) = map.get(&i) {
-                return Some(i.clone());
-            }
-        }
-        None
+        let map = self.parachain_headers_cache_map.read().await;
+        select_source(
+            self.parachain_headers_cache_ids.clone(),
+            &self.config.parachain.select_policy,
+            &map,
+            region,
+            |i| i.region.as_deref(),
+        )
     }
 
     pub async fn wait_until_rpc_avail(self: Arc<Self>, full: bool) {
         info!("Waiting for Substrate RPC clients to be available...");
         loop {
-            if (self.clone().current_relaychain_rpc_client(full).await).is_some()
-                && (self.clone().current_parachain_rpc_client(full).await).is_some()
-                && (self.is_relaychain_full || self.clone().current_relaychain_headers_cache().await.is_some())
+            if (self.clone().current_relaychain_rpc_client(full, None).await).is_some()
+                && (self.clone().current_parachain_rpc_client(full, None).await).is_some()
+                && (self.is_relaychain_full || self.clone().current_relaychain_headers_cache(None).await.is_some())
             {
                 break;
             }
@@ -360,6 +376,7 @@ impl DataSourceManager {
     pub async fn from_config(
         config: DataSourceConfig,
         cache_size: usize,
+        local_cache: Option<WrappedLocalCache>,
     ) -> Result<(WrappedDataSourceManager, Vec<JoinHandle<()>>)> {
         let relaychain_rpc_client_map: SubstrateWebSocketSourceMap = HashMap::new();
         let relaychain_rpc_client_map = Arc::new(RwLock::new(relaychain_rpc_client_map));
@@ -409,6 +426,7 @@ impl DataSourceManager {
             is_relaychain_full,
             is_parachain_full,
             cache,
+            local_cache,
         };
         let dsm = Arc::new(dsm);
         let ret = dsm.clone();
@@ -436,12 +454,20 @@ impl DataSourceManager {
     ) {
         let uuid = Uuid::new_v5(&Uuid::NAMESPACE_URL, config.endpoint.as_bytes());
         let uuid_str = uuid.to_string();
-        let client = CacheClient::new(config.endpoint.as_str());
+        let auth = match config.auth.as_deref().map(str::parse).transpose() {
+            Ok(auth) => auth,
+            Err(err) => {
+                error!("Invalid credentials of headers cache {}: {err}", &config.endpoint);
+                return;
+            }
+        };
+        let client = CacheClient::new(config.endpoint.as_str()).with_auth(auth);
         let instance = HeadersCacheHttpSourceInstance {
             uuid,
             uuid_str: uuid_str.clone(),
             client: client.clone(),
             endpoint: config.endpoint.clone(),
+            region: config.region.clone(),
         };
         let instance = Arc::new(instance);
 
@@ -557,6 +583,7 @@ impl DataSourceManager {
             client,
             endpoint: config.endpoint.clone(),
             pruned: config.pruned,
+            region: config.region.clone(),
         };
 
         map.write().await.insert(uuid_str, Arc::new(instance));
@@ -571,17 +598,21 @@ pub type WrappedDataSourceManager = Arc<DataSourceManager>;
 pub async fn setup_data_source_manager(
     config_path: &str,
     cache_size: usize,
+    local_cache: Option<WrappedLocalCache>,
 ) -> Result<(WrappedDataSourceManager, Vec<JoinHandle<()>>)> {
     let path = std::path::PathBuf::from(config_path);
     let config = DataSourceConfig::read_from_file(path);
-    DataSourceManager::from_config(config, cache_size).await
+    DataSourceManager::from_config(config, cache_size, local_cache).await
 }
 
 #[macro_export]
 macro_rules! use_relaychain_api {
     ($dsm:expr, $full:ident) => {
+        $crate::use_relaychain_api!($dsm, $full, None)
+    };
+    ($dsm:expr, $full:ident, $region:expr) => {
         $dsm.clone()
-            .current_relaychain_rpc_client($full)
+            .current_relaychain_rpc_client($full, $region)
             .await
             .map(|i| i.client.clone())
     };
@@ -590,8 +621,11 @@ macro_rules! use_relaychain_api {
 #[macro_export]
 macro_rules! use_parachain_api {
     ($dsm:expr, $full:ident) => {
+        $crate::use_parachain_api!($dsm, $full, None)
+    };
+    ($dsm:expr, $full:ident, $region:expr) => {
         $dsm.clone()
-            .current_parachain_rpc_client($full)
+            .current_parachain_rpc_client($full, $region)
             .await
             .map(|i| i.client.clone())
     };
@@ -600,8 +634,11 @@ macro_rules! use_parachain_api {
 #[macro_export]
 macro_rules! use_relaychain_hc {
     ($dsm:expr) => {
+        $crate::use_relaychain_hc!($dsm, None)
+    };
+    ($dsm:expr, $region:expr) => {
         $dsm.clone()
-            .current_relaychain_headers_cache()
+            .current_relaychain_headers_cache($region)
             .await
             .map(|i| i.client.clone())
     };
@@ -610,8 +647,11 @@ macro_rules! use_relaychain_hc {
 #[macro_export]
 macro_rules! use_parachain_hc {
     ($dsm:expr) => {
+        $crate::use_parachain_hc!($dsm, None)
+    };
+    ($dsm:expr, $region:expr) => {
         $dsm.clone()
-            .current_parachain_headers_cache()
+            .current_parachain_headers_cache($region)
             .await
             .map(|i| i.client.clone())
     };
@@ -653,7 +693,10 @@ impl DataSourceManager {
             });
         }
         let genesis_info = genesis_info.unwrap();
-        let genesis_state = chain_client::fetch_genesis_storage(&para_api).await?;
+        let genesis_state = match self.local_cache.as_ref().and_then(|c| c.get_genesis_state()) {
+            Some(genesis_state) => genesis_state,
+            None => chain_client::fetch_genesis_storage(&para_api).await?,
+        };
         let ret = InitRuntimeRequest::new(
             false,
             genesis_info.clone(),
@@ -686,16 +729,24 @@ impl DataSourceManager {
         }
     }
 
+    /// Fetches the storage changes from the sources in `region` if any online, the cached ones
+    /// are shared by the workers regardless of the region.
     pub async fn do_fetch_storage_changes(
         self: Arc<Self>,
         from: u32,
         to: u32,
+        region: Option<String>,
     ) -> Result<Arc<DataSourceCacheItem>> {
-        let hc = self.clone().current_parachain_headers_cache().await;
+        if let Some(ret) = self.local_cache.as_ref().and_then(|c| c.get_storage_changes(from, to)) {
+            let ret = ret.into_iter().map(Arc::new).collect::<Vec<_>>();
+            return Ok(Arc::new(DataSourceCacheItem::StorageChanges(ret)));
+        }
+        let region = region.as_deref();
+        let hc = self.clone().current_parachain_headers_cache(region).await;
         let para_api = if hc.is_some() {
-            use_parachain_api!(self, false)
+            use_parachain_api!(self, false, region)
         } else {
-            use_parachain_api!(self, true)
+            use_parachain_api!(self, true, region)
         }
         .ok_or(NoValidDataSource)?;
         let ret = if let Some(hc) = hc {
@@ -707,11 +758,11 @@ impl DataSourceManager {
         let ret = ret.into_iter().map(Arc::new).collect::<Vec<_>>();
         Ok(Arc::new(DataSourceCacheItem::StorageChanges(ret)))
     }
-    pub async fn fetch_storage_changes(self: Arc<Self>, from: u32, to: u32) -> Result<Vec<Arc<phactory_api::blocks::BlockHeaderWithChanges>>> {
+    pub async fn fetch_storage_changes(self: Arc<Self>, from: u32, to: u32, region: Option<String>) -> Result<Vec<Arc<phactory_api::blocks::BlockHeaderWithChanges>>> {
         let key = format!("sc:{from}:{to}");
         let cache = self.cache.clone();
         match cache
-            .try_get_with(key, self.clone().do_fetch_storage_changes(from, to))
+            .try_get_with(key, self.clone().do_fetch_storage_changes(from, to, region))
             .await
         {
             Ok(ret) => match *ret {
@@ -725,8 +776,18 @@ impl DataSourceManager {
     pub async fn do_get_para_header_by_relay_header(
         self: Arc<Self>,
         height: u32,
+        region: Option<String>,
     ) -> Result<Arc<DataSourceCacheItem>> {
-        let hc = use_relaychain_hc!(self);
+        let region = region.as_deref();
+        if let Some(block_info) = self.local_cache.as_ref().and_then(|c| c.get_header(height)) {
+            if let Some(para_header) = block_info.para_header {
+                return Ok(Arc::new(DataSourceCacheItem::ParaHeaderByRelayHeight(
+                    Some((para_header.fin_header_num, para_header.proof))
+                )));
+            }
+        }
+
+        let hc = use_relaychain_hc!(self, region);
         if let Some(hc) = hc {
             if let Ok(block_info) = hc.get_header(height).await {
                 if let Some(para_header) = block_info.para_header {
@@ -737,8 +798,8 @@ impl DataSourceManager {
             }
         }
 
-        let relay_api = use_relaychain_api!(self, false).ok_or(NoValidDataSource)?;
-        let para_api = use_parachain_api!(self, true).ok_or(NoValidDataSource)?;
+        let relay_api = use_relaychain_api!(self, false, region).ok_or(NoValidDataSource)?;
+        let para_api = use_parachain_api!(self, true, region).ok_or(NoValidDataSource)?;
 
         let last_header_hash = get_header_hash(&relay_api, Some(height)).await?;
         let header = get_finalized_header(&relay_api, &para_api, last_header_hash)
@@ -751,13 +812,15 @@ impl DataSourceManager {
     pub async fn get_para_header_by_relay_header(
         self: Arc<Self>,
         height: u32,
+        region: Option<String>,
     ) -> Result<Option<(u32, Vec<Vec<u8>>)>> {
         let key = format!("ph:rh:{height}");
         let cache = self.cache.clone();
         match cache
             .try_get_with(
                 key.clone(),
-                self.clone().do_get_para_header_by_relay_header(height),
+                self.clone()
+                    .do_get_para_header_by_relay_header(height, region),
             )
             .await
         {
@@ -772,8 +835,10 @@ impl DataSourceManager {
     pub async fn do_get_para_header(
         self: Arc<Self>,
         num: u32,
+        region: Option<String>,
     ) -> Result<Arc<DataSourceCacheItem>> {
-        let para_api = use_parachain_api!(self, true).ok_or(NoValidDataSource)?;
+        let para_api =
+            use_parachain_api!(self, true, region.as_deref()).ok_or(NoValidDataSource)?;
         let block_num = subxt_types::BlockNumber::from(subxt_types::NumberOrHex::Number(num as _));
         let hash = para_api
             .rpc()
@@ -794,6 +859,7 @@ impl DataSourceManager {
         self: Arc<Self>,
         from: u32,
         to: u32,
+        region: Option<String>,
     ) -> Result<Vec<phactory_api::blocks::BlockHeader>> {
         let cache = self.cache.clone();
 
@@ -809,12 +875,26 @@ impl DataSourceManager {
             }
         }
 
+        let from = headers.last().map(|h| h.number + 1).unwrap_or(from);
+        if let Some(local_cache) = &self.local_cache {
+            for b in from..=to {
+                match local_cache.get_para_header(b) {
+                    Some(header) => {
+                        let key = format!("ph:{b}");
+                        cache.insert(key, DataSourceCacheItem::ParaHeader(header.clone()).into()).await;
+                        headers.push(header);
+                    },
+                    None => break,
+                }
+            }
+        }
+
         let from = headers.last().map(|h| h.number + 1).unwrap_or(from);
         if from > to {
             return Ok(headers);
         }
 
-        if let Some(hc) = use_parachain_hc!(self) {
+        if let Some(hc) = use_parachain_hc!(self, region.as_deref()) {
             let count = to - from + 1;
             if let Ok(remain_headers) = hc.get_parachain_headers(from, count).await {
                 for header in &remain_headers {
@@ -829,7 +909,7 @@ impl DataSourceManager {
         for b in from..=to {
             let key = format!("ph:{b}");
             match cache
-                .try_get_with(key, self.clone().do_get_para_header(b))
+                .try_get_with(key, self.clone().do_get_para_header(b, region.clone()))
                 .await
             {
                 Ok(item) => match *item {
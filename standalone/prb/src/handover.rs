@@ -0,0 +1,355 @@
+use crate::api::ApiError;
+use crate::cli::ConfigCommands;
+use crate::inv_db::{
+    get_all_workers, get_worker_by_name, update_worker, validate_endpoint, Worker,
+};
+use crate::processor::WorkerEvent;
+use crate::pruntime::{create_client, PRuntimeClient};
+use crate::wm::WrappedWorkerManagerContext;
+use crate::worker::WorkerLifecycleCommand;
+use crate::{use_parachain_api, use_relaychain_api};
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const CHAINTIP_CHECK_INTERVAL: Duration = Duration::from_secs(6);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HandoverPolicy {
+    /// Seconds for the source pRuntime to reach the chain tip before the handover is given up
+    pub chaintip_timeout_secs: u64,
+}
+
+impl Default for HandoverPolicy {
+    fn default() -> Self {
+        Self {
+            chaintip_timeout_secs: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandoverRequest {
+    /// Id of the worker whose key is handed over
+    pub worker: String,
+    /// Endpoint of the new pRuntime receiving the key
+    pub endpoint: String,
+    /// Whether to attest the new pRuntime with DCAP instead of IAS
+    #[serde(default)]
+    pub dcap: bool,
+    #[serde(default)]
+    pub policy: HandoverPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandoverStep {
+    WaitingForChaintip,
+    Challenge,
+    Accept,
+    Transfer,
+    Receive,
+    UpdateInventory,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandoverState {
+    Running(HandoverStep),
+    Succeeded,
+    /// The handover failed at the step and the worker is left on the source pRuntime.
+    RolledBack(HandoverStep, String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandoverStatus {
+    pub id: String,
+    pub request: HandoverRequest,
+    pub source_endpoint: String,
+    pub state: HandoverState,
+}
+
+pub async fn start_handover(
+    ctx: WrappedWorkerManagerContext,
+    request: HandoverRequest,
+) -> Result<HandoverStatus, ApiError> {
+    let worker = get_all_workers(ctx.inv_db.clone())?
+        .into_iter()
+        .find(|w| w.id == request.worker)
+        .ok_or_else(|| ApiError::WorkerNotFound(request.worker.clone()))?;
+    let endpoint = validate_endpoint(request.endpoint.clone())?;
+    if endpoint == worker.endpoint {
+        return Err(anyhow!("The worker is already at {endpoint}").into());
+    }
+    // Checked before any key is transferred, the inventory can't be updated without the pool.
+    pool_of(&worker)?;
+
+    let mut handovers = ctx.handovers.lock().await;
+    let running = handovers
+        .values()
+        .any(|h| matches!(h.state, HandoverState::Running(_)) && h.request.worker == worker.id);
+    if running {
+        return Err(ApiError::HandoverConflict(worker.id));
+    }
+    let status = HandoverStatus {
+        id: Uuid::new_v4().to_string(),
+        request: HandoverRequest {
+            endpoint,
+            ..request
+        },
+        source_endpoint: worker.endpoint.clone(),
+        state: HandoverState::Running(HandoverStep::WaitingForChaintip),
+    };
+    handovers.insert(status.id.clone(), status.clone());
+    drop(handovers);
+
+    tokio::spawn(run_handover(ctx, status.clone(), worker));
+    Ok(status)
+}
+
+async fn run_handover(ctx: WrappedWorkerManagerContext, status: HandoverStatus, worker: Worker) {
+    let id = status.id;
+    let request = status.request;
+    info!(
+        "Handover {id}: worker {} from {} to {}",
+        worker.name, worker.endpoint, request.endpoint
+    );
+    let source = create_client(worker.endpoint.clone());
+    let target = create_client(request.endpoint.clone());
+
+    let mut step = HandoverStep::WaitingForChaintip;
+    let result = async {
+        wait_for_chaintip(&ctx, &source, &request.policy).await?;
+        transfer_key(&ctx, &id, &mut step, &source, &target, request.dcap).await?;
+        step = HandoverStep::UpdateInventory;
+        set_state(&ctx, &id, HandoverState::Running(step)).await;
+        switch_endpoint(&ctx, &worker, &request.endpoint)
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            info!("Handover {id} finished");
+            set_state(&ctx, &id, HandoverState::Succeeded).await;
+        }
+        Err(err) => {
+            warn!("Handover {id} failed at {step:?}: {err:?}");
+            set_state(
+                &ctx,
+                &id,
+                HandoverState::RolledBack(step, format!("{err:#}")),
+            )
+            .await;
+        }
+    }
+}
+
+/// Waits until the source pRuntime has synced beyond the finalized blocks of both chains.
+async fn wait_for_chaintip(
+    ctx: &WrappedWorkerManagerContext,
+    source: &PRuntimeClient,
+    policy: &HandoverPolicy,
+) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(policy.chaintip_timeout_secs);
+    loop {
+        let info = source
+            .get_info(())
+            .await
+            .context("Failed to get info of the source pRuntime")?;
+        let relaychain = use_relaychain_api!(ctx.dsm, false)
+            .ok_or_else(|| anyhow!("No relaychain API available"))?
+            .latest_finalized_block_number()
+            .await?;
+        let parachain = use_parachain_api!(ctx.dsm, false)
+            .ok_or_else(|| anyhow!("No parachain API available"))?
+            .latest_finalized_block_number()
+            .await?;
+        if info.blocknum == info.para_headernum
+            && info.headernum > relaychain
+            && info.para_headernum > parachain
+        {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "Source pRuntime not at the chain tip after {}s, synced to block {}",
+                policy.chaintip_timeout_secs,
+                info.blocknum
+            );
+        }
+        tokio::time::sleep(CHAINTIP_CHECK_INTERVAL).await;
+    }
+}
+
+async fn transfer_key(
+    ctx: &WrappedWorkerManagerContext,
+    id: &str,
+    step: &mut HandoverStep,
+    source: &PRuntimeClient,
+    target: &PRuntimeClient,
+    dcap: bool,
+) -> Result<()> {
+    let mut enter = |next| {
+        *step = next;
+        set_state(ctx, id, HandoverState::Running(next))
+    };
+    if dcap {
+        enter(HandoverStep::Challenge).await;
+        let challenge = source.dcap_handover_create_challenge(()).await?;
+        enter(HandoverStep::Accept).await;
+        let response = target.dcap_handover_accept_challenge(challenge).await?;
+        enter(HandoverStep::Transfer).await;
+        let encrypted_key = source.dcap_handover_start(response).await?;
+        enter(HandoverStep::Receive).await;
+        target.dcap_handover_receive(encrypted_key).await?;
+    } else {
+        enter(HandoverStep::Challenge).await;
+        let challenge = source.handover_create_challenge(()).await?;
+        enter(HandoverStep::Accept).await;
+        let response = target.handover_accept_challenge(challenge).await?;
+        enter(HandoverStep::Transfer).await;
+        let encrypted_key = source.handover_start(response).await?;
+        enter(HandoverStep::Receive).await;
+        target.handover_receive(encrypted_key).await?;
+    }
+    Ok(())
+}
+
+fn pool_of(worker: &Worker) -> Result<u64> {
+    worker.pid.ok_or_else(|| {
+        anyhow!(
+            "Worker {} is not in a pool, add it to one before handing it over",
+            worker.name
+        )
+    })
+}
+
+fn update_endpoint_command(worker: &Worker, endpoint: &str) -> Result<ConfigCommands> {
+    Ok(ConfigCommands::UpdateWorker {
+        name: worker.name.clone(),
+        new_name: None,
+        endpoint: endpoint.to_string(),
+        stake: worker.stake.clone(),
+        pid: pool_of(worker)?,
+        disabled: !worker.enabled,
+        sync_only: worker.sync_only,
+        gatekeeper: worker.gatekeeper,
+        groups: worker.groups.clone(),
+        sync_affinity: worker.sync_affinity.clone(),
+        to_block: worker.to_block,
+    })
+}
+
+/// Points the worker at the new pRuntime in the inventory and restarts it there. The inventory is
+/// restored if the worker can't be restarted.
+fn switch_endpoint(
+    ctx: &WrappedWorkerManagerContext,
+    worker: &Worker,
+    endpoint: &str,
+) -> Result<()> {
+    update_worker(
+        ctx.inv_db.clone(),
+        update_endpoint_command(worker, endpoint)?,
+    )?;
+    let result = get_worker_by_name(ctx.inv_db.clone(), worker.name.clone())
+        .and_then(|updated| updated.ok_or_else(|| anyhow!("Worker not found after update")))
+        .and_then(|updated| {
+            ctx.bus
+                .send_worker_event(worker.id.clone(), WorkerEvent::UpdateWorker(updated))?;
+            ctx.bus.send_worker_event(
+                worker.id.clone(),
+                WorkerEvent::WorkerLifecycleCommand(WorkerLifecycleCommand::ShouldRestart),
+            )?;
+            Ok(())
+        });
+    if let Err(err) = result {
+        update_worker(
+            ctx.inv_db.clone(),
+            update_endpoint_command(worker, &worker.endpoint)?,
+        )
+        .context("Failed to restore the worker in the inventory")?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+async fn set_state(ctx: &WrappedWorkerManagerContext, id: &str, state: HandoverState) {
+    if let Some(status) = ctx.handovers.lock().await.get_mut(id) {
+        status.state = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(pid: Option<u64>) -> Worker {
+        Worker {
+            id: "id".into(),
+            name: "w1".into(),
+            endpoint: "http://10.0.0.1:8000".into(),
+            stake: "1000".into(),
+            pid,
+            enabled: false,
+            sync_only: true,
+            gatekeeper: false,
+            groups: vec!["g1".into()],
+            sync_affinity: Some("eu".into()),
+            to_block: Some(100),
+        }
+    }
+
+    #[test]
+    fn moves_only_the_endpoint() {
+        let cmd = update_endpoint_command(&worker(Some(3)), "http://10.0.0.2:8000").unwrap();
+        let ConfigCommands::UpdateWorker {
+            name,
+            new_name,
+            endpoint,
+            stake,
+            pid,
+            disabled,
+            sync_only,
+            gatekeeper,
+            groups,
+            sync_affinity,
+            to_block,
+        } = cmd
+        else {
+            panic!("Not an update command");
+        };
+        assert_eq!(name, "w1");
+        assert_eq!(new_name, None);
+        assert_eq!(endpoint, "http://10.0.0.2:8000");
+        assert_eq!(stake, "1000");
+        assert_eq!(pid, 3);
+        assert!(disabled);
+        assert!(sync_only);
+        assert!(!gatekeeper);
+        assert_eq!(groups, vec!["g1".to_string()]);
+        assert_eq!(sync_affinity.as_deref(), Some("eu"));
+        assert_eq!(to_block, Some(100));
+    }
+
+    #[test]
+    fn rejects_the_workers_not_in_a_pool() {
+        let err = update_endpoint_command(&worker(None), "http://10.0.0.2:8000").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Worker w1 is not in a pool, add it to one before handing it over"
+        );
+    }
+
+    #[test]
+    fn defaults_the_request_options() {
+        let request: HandoverRequest =
+            serde_json::from_str(r#"{"worker":"id","endpoint":"http://10.0.0.2:8000"}"#).unwrap();
+        assert!(!request.dcap);
+        assert_eq!(request.policy.chaintip_timeout_secs, 600);
+        let request: HandoverRequest = serde_json::from_str(
+            r#"{"worker":"id","endpoint":"e","policy":{"chaintip_timeout_secs":60}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.policy.chaintip_timeout_secs, 60);
+    }
+}
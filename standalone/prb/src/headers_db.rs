@@ -2,7 +2,7 @@ use crate::pool_operator::DB;
 use anyhow::{anyhow, Result};
 use log::{debug, error, warn};
 use parity_scale_codec::{Decode, Encode};
-use phactory_api::blocks::HeadersToSync;
+use phactory_api::blocks::{HeaderToSync, HeadersToSync};
 use sp_consensus_grandpa::AuthorityList;
 use subxt::ext::sp_runtime::traits::Header;
 use std::sync::Arc;
@@ -89,6 +89,21 @@ pub fn get_current_point(db: Arc<DB>, num: u32) -> Option<HeadersToSync> {
     None
 }
 
+/// Cuts the minimal span to sync from `from`: the headers from `from` up to the first one
+/// bearing a justification, which is where pRuntime's finality advances to.
+///
+/// The headers already synced are skipped, and the ones after the justification are left to
+/// the next span. Returns None if no header at or after `from` bears a justification.
+pub fn justified_span(headers: &[HeaderToSync], from: u32) -> Option<(HeadersToSync, u32)> {
+    let start = headers.iter().position(|h| h.header.number >= from)?;
+    if headers[start].header.number != from {
+        return None;
+    }
+    let end = start + headers[start..].iter().position(|h| h.justification.is_some())?;
+    let to = headers[end].header.number;
+    Some((headers[start..=end].to_vec(), to))
+}
+
 pub fn get_previous_authority_set_change_number(db: Arc<DB>, num:u32) -> Option<u32> {
     let mut iter = db.iterator(rocksdb::IteratorMode::From(&encode_u32(num), rocksdb::Direction::Reverse));
     if let Some(Ok((_, value))) = iter.next() {
@@ -178,4 +193,52 @@ pub fn delete_from(
         count += 1;
     }
     count
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phactory_api::blocks::BlockHeader;
+
+    /// Headers from `from` to `to`, with the justifications at `justified`.
+    fn headers(from: u32, to: u32, justified: &[u32]) -> HeadersToSync {
+        (from..=to)
+            .map(|number| HeaderToSync {
+                header: BlockHeader {
+                    parent_hash: Default::default(),
+                    number,
+                    state_root: Default::default(),
+                    extrinsics_root: Default::default(),
+                    digest: Default::default(),
+                },
+                justification: justified.contains(&number).then(|| vec![1]),
+            })
+            .collect()
+    }
+
+    fn span(headers: &[HeaderToSync], from: u32) -> Option<(u32, u32)> {
+        let (span, to) = justified_span(headers, from)?;
+        assert_eq!(span.last().unwrap().header.number, to);
+        Some((span[0].header.number, to))
+    }
+
+    #[test]
+    fn cuts_spans_at_the_first_justification() {
+        let headers = headers(10, 15, &[12, 15]);
+        assert_eq!(span(&headers, 10), Some((10, 12)));
+        assert_eq!(span(&headers, 11), Some((11, 12)));
+        assert_eq!(span(&headers, 12), Some((12, 12)));
+        assert_eq!(span(&headers, 13), Some((13, 15)));
+        assert_eq!(span(&headers, 15), Some((15, 15)));
+        // Not covered by the headers.
+        assert_eq!(span(&headers, 9), None);
+        assert_eq!(span(&headers, 16), None);
+    }
+
+    #[test]
+    fn no_span_without_justification() {
+        assert_eq!(span(&headers(10, 15, &[]), 10), None);
+        assert_eq!(span(&headers(10, 15, &[12]), 13), None);
+        assert_eq!(span(&[], 10), None);
+    }
+}
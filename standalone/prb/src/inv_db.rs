@@ -3,7 +3,7 @@ use anyhow::{anyhow, Context, Result};
 use indradb::{
     Datastore, EdgeDirection, EdgeKey, Identifier, MemoryDatastore, PipeEdgeQuery,
     PropertyValueVertexQuery, RangeVertexQuery, RocksdbDatastore, SpecificEdgeQuery,
-    SpecificVertexQuery, VertexProperties, VertexPropertyQuery, VertexQuery,
+    SpecificVertexQuery, Vertex, VertexProperties, VertexPropertyQuery, VertexQuery,
 };
 use log::{debug, warn};
 use reqwest::Url;
@@ -25,6 +25,9 @@ pub const ID_PROP_WORKER_STAKE: &str = "stake";
 pub const ID_PROP_WORKER_ENABLED: &str = "enabled";
 pub const ID_PROP_WORKER_SYNC_ONLY: &str = "sync_only";
 pub const ID_PROP_WORKER_GATEKEEPER: &str = "gatekeeper";
+pub const ID_PROP_WORKER_GROUPS: &str = "groups";
+pub const ID_PROP_WORKER_SYNC_AFFINITY: &str = "sync_affinity";
+pub const ID_PROP_WORKER_TO_BLOCK: &str = "to_block";
 
 // Account-related settings moved to trade service
 pub const ID_PROP_POOL_NAME: &str = "name";
@@ -57,6 +60,21 @@ pub struct Worker {
     pub enabled: bool,
     pub sync_only: bool,
     pub gatekeeper: bool,
+    /// Labels to address the worker in group-scoped operations
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Region of the data sources to sync from first
+    #[serde(default)]
+    pub sync_affinity: Option<String>,
+    /// Parachain block to stop syncing at
+    #[serde(default)]
+    pub to_block: Option<u32>,
+}
+
+impl Worker {
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g == group)
+    }
 }
 
 impl From<VertexProperties> for Pool {
@@ -99,6 +117,9 @@ impl From<VertexProperties> for Worker {
             enabled: true,
             sync_only: false,
             gatekeeper: false,
+            groups: vec![],
+            sync_affinity: None,
+            to_block: None,
         };
         value.props.iter().for_each(|p| match p.name.as_str() {
             ID_PROP_WORKER_NAME => {
@@ -119,6 +140,15 @@ impl From<VertexProperties> for Worker {
             ID_PROP_WORKER_GATEKEEPER => {
                 ret.gatekeeper = p.value.as_bool().unwrap();
             }
+            ID_PROP_WORKER_GROUPS => {
+                ret.groups = serde_json::from_value(p.value.clone()).unwrap_or_default();
+            }
+            ID_PROP_WORKER_SYNC_AFFINITY => {
+                ret.sync_affinity = p.value.as_str().map(|s| s.to_string());
+            }
+            ID_PROP_WORKER_TO_BLOCK => {
+                ret.to_block = p.value.as_u64().map(|n| n as u32);
+            }
             &_ => {}
         });
         ret
@@ -158,6 +188,20 @@ pub fn validate_endpoint(s: String) -> Result<String> {
     Ok(url.as_str().to_string())
 }
 
+pub fn validate_groups(groups: Vec<String>) -> Result<Vec<String>> {
+    let mut groups = groups
+        .into_iter()
+        .map(|g| g.trim().to_string())
+        .filter(|g| !g.is_empty())
+        .collect::<Vec<_>>();
+    if let Some(g) = groups.iter().find(|g| g.contains(char::is_whitespace)) {
+        return Err(anyhow!("Invalid group name: {g:?}"));
+    }
+    groups.sort();
+    groups.dedup();
+    Ok(groups)
+}
+
 pub fn setup_inventory_db(db_path: &str) -> WrappedDb {
     let db_path = Path::new(db_path).join("inventory");
     let db = RocksdbDatastore::new(&db_path, None).expect("Failed to open inventory database.");
@@ -265,6 +309,14 @@ pub fn get_all_workers(db: WrappedDb) -> Result<Vec<Worker>> {
     Ok(workers)
 }
 
+pub fn get_workers_by_group(db: WrappedDb, group: &str) -> Result<Vec<Worker>> {
+    let workers = get_all_workers(db)?
+        .into_iter()
+        .filter(|w| w.in_group(group))
+        .collect::<Vec<_>>();
+    Ok(workers)
+}
+
 pub fn add_pool(db: WrappedDb, cmd: ConfigCommands) -> Result<Uuid> {
     match cmd {
         ConfigCommands::AddPool {
@@ -440,8 +492,12 @@ pub fn add_worker(db: WrappedDb, cmd: ConfigCommands) -> Result<Uuid> {
             disabled,
             sync_only,
             gatekeeper,
+            groups,
+            sync_affinity,
+            to_block,
         } => {
             let stake = validate_bn_string(stake)?;
+            let groups = validate_groups(groups)?;
             let name = validate_worker_name_existence(db.clone(), name)?;
             let endpoint = validate_endpoint(endpoint)?;
 
@@ -494,6 +550,27 @@ pub fn add_worker(db: WrappedDb, cmd: ConfigCommands) -> Result<Uuid> {
                     },
                     serde_json::Value::Bool(gatekeeper),
                 )?;
+                db.set_vertex_properties(
+                    VertexPropertyQuery {
+                        inner: uq.clone(),
+                        name: Identifier::new(ID_PROP_WORKER_GROUPS).unwrap(),
+                    },
+                    serde_json::to_value(groups)?,
+                )?;
+                db.set_vertex_properties(
+                    VertexPropertyQuery {
+                        inner: uq.clone(),
+                        name: Identifier::new(ID_PROP_WORKER_SYNC_AFFINITY).unwrap(),
+                    },
+                    serde_json::to_value(sync_affinity)?,
+                )?;
+                db.set_vertex_properties(
+                    VertexPropertyQuery {
+                        inner: uq.clone(),
+                        name: Identifier::new(ID_PROP_WORKER_TO_BLOCK).unwrap(),
+                    },
+                    serde_json::to_value(to_block)?,
+                )?;
                 let e = EdgeKey {
                     outbound_id: id,
                     t: Identifier::new(ID_EDGE_BELONG_TO)?,
@@ -525,7 +602,11 @@ pub fn update_worker(db: WrappedDb, cmd: ConfigCommands) -> Result<Uuid> {
             disabled,
             sync_only,
             gatekeeper,
+            groups,
+            sync_affinity,
+            to_block,
         } => {
+            let groups = validate_groups(groups)?;
             let worker =
                 get_raw_worker_by_name(db.clone(), name.clone())?.context("Worker not found!")?;
             let id = worker.vertex.id;
@@ -608,11 +689,32 @@ pub fn update_worker(db: WrappedDb, cmd: ConfigCommands) -> Result<Uuid> {
             )?;
             db.set_vertex_properties(
                 VertexPropertyQuery {
-                    inner: uq,
+                    inner: uq.clone(),
                     name: Identifier::new(ID_PROP_WORKER_GATEKEEPER).unwrap(),
                 },
                 serde_json::Value::Bool(gatekeeper),
             )?;
+            db.set_vertex_properties(
+                VertexPropertyQuery {
+                    inner: uq.clone(),
+                    name: Identifier::new(ID_PROP_WORKER_GROUPS).unwrap(),
+                },
+                serde_json::to_value(groups)?,
+            )?;
+            db.set_vertex_properties(
+                VertexPropertyQuery {
+                    inner: uq.clone(),
+                    name: Identifier::new(ID_PROP_WORKER_SYNC_AFFINITY).unwrap(),
+                },
+                serde_json::to_value(sync_affinity)?,
+            )?;
+            db.set_vertex_properties(
+                VertexPropertyQuery {
+                    inner: uq,
+                    name: Identifier::new(ID_PROP_WORKER_TO_BLOCK).unwrap(),
+                },
+                serde_json::to_value(to_block)?,
+            )?;
 
             Ok(id)
         }
@@ -630,6 +732,98 @@ pub fn remove_worker(db: WrappedDb, name: String) -> Result<()> {
     Ok(())
 }
 
+/// A vertex of the inventory with all of its properties.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DumpedVertex {
+    pub id: Uuid,
+    pub t: String,
+    pub props: Vec<(String, serde_json::Value)>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DumpedEdge {
+    pub outbound_id: Uuid,
+    pub t: String,
+    pub inbound_id: Uuid,
+}
+
+/// The whole inventory, keeping the vertex ids which the other stores refer to the workers by.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct InventoryDump {
+    pub vertices: Vec<DumpedVertex>,
+    pub edges: Vec<DumpedEdge>,
+}
+
+pub fn dump_inventory(db: WrappedDb) -> Result<InventoryDump> {
+    let all: VertexQuery = RangeVertexQuery {
+        limit: 4_294_967_294u32,
+        t: None,
+        start_id: None,
+    }
+    .into();
+    let vertices = db
+        .get_all_vertex_properties(all.clone())?
+        .into_iter()
+        .map(|v| DumpedVertex {
+            id: v.vertex.id,
+            t: v.vertex.t.to_string(),
+            props: v
+                .props
+                .into_iter()
+                .map(|p| (p.name.to_string(), p.value))
+                .collect(),
+        })
+        .collect();
+    let edges = PipeEdgeQuery {
+        inner: Box::new(all),
+        direction: EdgeDirection::Outbound,
+        limit: 4_294_967_294u32,
+        t: None,
+        high: None,
+        low: None,
+    }
+    .into();
+    let edges = db
+        .get_edges(edges)?
+        .into_iter()
+        .map(|e| DumpedEdge {
+            outbound_id: e.key.outbound_id,
+            t: e.key.t.to_string(),
+            inbound_id: e.key.inbound_id,
+        })
+        .collect();
+    Ok(InventoryDump { vertices, edges })
+}
+
+pub fn restore_inventory(db: WrappedDb, dump: InventoryDump) -> Result<()> {
+    for v in dump.vertices {
+        if !db.create_vertex(&Vertex::with_id(v.id, Identifier::new(v.t)?))? {
+            return Err(anyhow!("Vertex {} already exists!", v.id));
+        }
+        let uq: VertexQuery = SpecificVertexQuery { ids: vec![v.id] }.into();
+        for (name, value) in v.props {
+            db.set_vertex_properties(
+                VertexPropertyQuery {
+                    inner: uq.clone(),
+                    name: Identifier::new(name)?,
+                },
+                value,
+            )?;
+        }
+    }
+    for e in dump.edges {
+        let e = EdgeKey {
+            outbound_id: e.outbound_id,
+            t: Identifier::new(e.t)?,
+            inbound_id: e.inbound_id,
+        };
+        if !db.create_edge(&e)? {
+            return Err(anyhow!("Edge {:?} refers to a missing vertex!", e));
+        }
+    }
+    Ok(())
+}
+
 pub fn setup_cache_index_db(db_path: &str, use_persisted_cache_index: bool) -> WrappedDb {
     let db: WrappedDb = if use_persisted_cache_index {
         let db_path = Path::new(db_path).join("index");
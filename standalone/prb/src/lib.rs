@@ -1,15 +1,26 @@
+pub mod accounting;
+pub mod alert;
 pub mod api;
+pub mod backup;
 pub mod bus;
+pub mod chain_events;
 pub mod cli;
 pub mod configurator;
 pub mod datasource;
+pub mod handover;
 pub mod headers_db;
 pub mod inv_db;
+pub mod local_cache;
 pub mod messages;
 pub mod pool_operator;
+pub mod prefetch;
 pub mod processor;
 pub mod pruntime;
 pub mod repository;
+pub mod rollout;
+pub mod scheduler;
+pub mod simulator;
+pub mod submission;
 pub mod tx;
 pub mod utils;
 pub mod wm;
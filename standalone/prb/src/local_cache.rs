@@ -0,0 +1,721 @@
+use crate::datasource::WrappedDataSourceManager;
+use crate::pool_operator::DB;
+use crate::{use_parachain_api, use_relaychain_api};
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use parity_scale_codec::{Decode, Encode};
+use phactory_api::blocks::{BlockHeader, BlockHeaderWithChanges, StorageChanges};
+use phaxt::ChainApi;
+use pherry::headers_cache::{self as hc, BlockInfo};
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+use serde::{Deserialize, Serialize};
+use sp_core::hashing::blake2_256;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::sleep;
+
+static GENESIS_STATE_KEY: &[u8] = b"m-genesis-state";
+static CHANGES_STATS_KEY: &[u8] = b"m-changes-stats";
+static CURRENT_DICT_KEY: &[u8] = b"m-zstd-dict-current";
+/// Followed by the id of the dictionary, big endian.
+static DICT_KEY_PREFIX: &[u8] = b"m-zstd-dict:";
+/// The next blob to recompress with the current dictionary.
+static RECOMPRESS_CURSOR_KEY: &[u8] = b"m-recompress-cursor";
+
+const COMPRESSION_LEVEL: i32 = 3;
+/// Max size of the zstd dictionary trained on the storage changes.
+const DICT_SIZE: usize = 64 * 1024;
+/// Number of the latest blocks the dictionary is trained on, once that many are stored.
+const DICT_SAMPLES: usize = 1024;
+/// Max number of the blobs recompressed, or the legacy entries converted, per round.
+const RECOMPRESS_BATCH: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct LocalCacheConfig {
+    pub relay_start: Option<u32>,
+    pub para_start: u32,
+    pub justification_interval: u32,
+    pub storage_changes_batch: u32,
+    pub interval: Duration,
+}
+
+/// The storage changes of a block, under `s`. The changes themselves are kept under `d`, keyed by
+/// their hash, so that the blocks with identical changes share them.
+#[derive(Encode, Decode)]
+struct StoredChanges {
+    block_header: BlockHeader,
+    hash: [u8; 32],
+}
+
+/// The SCALE encoded storage changes compressed with zstd, with the dictionary `dict` unless it's
+/// 0.
+#[derive(Encode, Decode)]
+struct Blob {
+    dict: u32,
+    raw_len: u32,
+    data: Vec<u8>,
+}
+
+/// The space taken by the storage changes in the compressed layout.
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct ChangesStats {
+    pub blocks: u64,
+    /// Blocks with the same changes as a block stored before
+    pub deduplicated: u64,
+    /// Size of the changes of all the blocks, SCALE encoded
+    pub raw_bytes: u64,
+    /// Size of the compressed changes stored
+    pub stored_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionStatus {
+    #[serde(flatten)]
+    pub stats: ChangesStats,
+    /// The dictionary compressing with, None until enough blocks are stored to train it
+    pub dictionary: Option<u32>,
+    /// Share of the raw size saved by the deduplication and the compression
+    pub savings: f64,
+}
+
+#[derive(Default)]
+struct Compression {
+    /// The dictionary to compress with, 0 for none.
+    dict: u32,
+    dicts: HashMap<u32, Arc<Vec<u8>>>,
+    stats: ChangesStats,
+}
+
+/// The embedded counterpart of a headers-cache server, laid out the same way: relaychain
+/// headers with their justifications and parachain proofs, parachain headers and storage changes,
+/// each keyed by block number under its own prefix.
+///
+/// The storage changes, taking most of the space, are deduplicated and compressed. The ones
+/// stored uncompressed under `c` by the earlier versions are converted in the background.
+pub struct LocalCache {
+    db: Arc<DB>,
+    compression: Mutex<Compression>,
+}
+
+pub type WrappedLocalCache = Arc<LocalCache>;
+
+fn mk_key(prefix: u8, block_number: u32) -> [u8; size_of::<u32>() + 1] {
+    let mut key = [prefix; size_of::<u32>() + 1];
+    key[1..].copy_from_slice(&block_number.to_be_bytes());
+    key
+}
+
+fn blob_key(hash: &[u8; 32]) -> Vec<u8> {
+    [&[b'd'][..], hash].concat()
+}
+
+fn dict_key(id: u32) -> Vec<u8> {
+    [DICT_KEY_PREFIX, &id.to_be_bytes()].concat()
+}
+
+fn savings(stats: &ChangesStats) -> f64 {
+    if stats.raw_bytes == 0 {
+        return 0.0;
+    }
+    1.0 - stats.stored_bytes as f64 / stats.raw_bytes as f64
+}
+
+impl LocalCache {
+    pub fn new(db: Arc<DB>) -> Self {
+        let mut compression = Compression::default();
+        let load = |key: &[u8]| db.get(key).ok().flatten();
+        if let Some(stats) = load(CHANGES_STATS_KEY) {
+            compression.stats = Decode::decode(&mut &stats[..]).unwrap_or_default();
+        }
+        if let Some(id) = load(CURRENT_DICT_KEY) {
+            compression.dict = Decode::decode(&mut &id[..]).unwrap_or_default();
+        }
+        for kv in db.prefix_iterator(DICT_KEY_PREFIX) {
+            let Ok((key, dict)) = kv else { break };
+            let Some(id) = key.strip_prefix(DICT_KEY_PREFIX) else {
+                break;
+            };
+            let Ok(id) = id.try_into().map(u32::from_be_bytes) else {
+                continue;
+            };
+            compression.dicts.insert(id, Arc::new(dict.into()));
+        }
+        Self {
+            db,
+            compression: Mutex::new(compression),
+        }
+    }
+
+    fn get<T: Decode>(&self, prefix: u8, block: u32) -> Option<T> {
+        let value = self.db.get(mk_key(prefix, block)).ok().flatten()?;
+        match T::decode(&mut &value[..]) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                error!(
+                    "Failed to decode local cache item {}:{}. {err}",
+                    prefix as char, block
+                );
+                None
+            }
+        }
+    }
+
+    fn put(&self, prefix: u8, block: u32, value: &impl Encode) -> Result<()> {
+        self.db.put(mk_key(prefix, block), value.encode())?;
+        Ok(())
+    }
+
+    /// The block following the highest one stored under `prefix`.
+    fn next_block(&self, prefix: u8) -> Option<u32> {
+        let mut iter = self.db.iterator(rocksdb::IteratorMode::From(
+            &mk_key(prefix, u32::MAX),
+            rocksdb::Direction::Reverse,
+        ));
+        let (key, _) = iter.next()?.ok()?;
+        if key.first() != Some(&prefix) {
+            return None;
+        }
+        let number = u32::from_be_bytes(key[1..].try_into().ok()?);
+        Some(number + 1)
+    }
+
+    pub fn get_header(&self, block: u32) -> Option<BlockInfo> {
+        self.get(b'h', block)
+    }
+
+    pub fn get_para_header(&self, block: u32) -> Option<BlockHeader> {
+        self.get(b'p', block)
+    }
+
+    /// Returns the storage changes of `from..=to`, None unless all of them are stored.
+    pub fn get_storage_changes(&self, from: u32, to: u32) -> Option<Vec<BlockHeaderWithChanges>> {
+        (from..=to).map(|b| self.get_changes(b)).collect()
+    }
+
+    fn get_changes(&self, block: u32) -> Option<BlockHeaderWithChanges> {
+        let Some(stored) = self.get::<StoredChanges>(b's', block) else {
+            return self.get(b'c', block);
+        };
+        let changes = self.load_raw_changes(&stored.hash).and_then(|raw| {
+            StorageChanges::decode(&mut &raw[..]).context("Failed to decode storage changes")
+        });
+        match changes {
+            Ok(storage_changes) => Some(BlockHeaderWithChanges {
+                block_header: stored.block_header,
+                storage_changes,
+            }),
+            Err(err) => {
+                error!("Failed to load storage changes of block {block}. {err:?}");
+                None
+            }
+        }
+    }
+
+    /// The block following the highest one with the storage changes stored.
+    fn next_changes_block(&self) -> Option<u32> {
+        self.next_block(b's').max(self.next_block(b'c'))
+    }
+
+    pub fn put_storage_changes(&self, changes: &BlockHeaderWithChanges) -> Result<()> {
+        let mut state = self.compression.lock().unwrap();
+        let mut batch = WriteBatch::default();
+        self.stage_changes(&mut state, &mut batch, changes)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn stage_changes(
+        &self,
+        state: &mut Compression,
+        batch: &mut WriteBatch,
+        changes: &BlockHeaderWithChanges,
+    ) -> Result<()> {
+        let stored_key = mk_key(b's', changes.block_header.number);
+        // Put again, e.g. grabbed again after a restart, and accounted already.
+        if self.db.get_pinned(stored_key)?.is_some() {
+            return Ok(());
+        }
+        let raw = changes.storage_changes.encode();
+        let hash = blake2_256(&raw);
+        let key = blob_key(&hash);
+        state.stats.blocks += 1;
+        state.stats.raw_bytes += raw.len() as u64;
+        if self.db.get_pinned(&key)?.is_some() {
+            state.stats.deduplicated += 1;
+        } else {
+            let blob = compress(state, &raw)?;
+            state.stats.stored_bytes += blob.data.len() as u64;
+            batch.put(key, blob.encode());
+        }
+        let stored = StoredChanges {
+            block_header: changes.block_header.clone(),
+            hash,
+        };
+        batch.put(stored_key, stored.encode());
+        batch.put(CHANGES_STATS_KEY, state.stats.encode());
+        Ok(())
+    }
+
+    fn load_blob(&self, key: &[u8]) -> Result<Blob> {
+        let value = self
+            .db
+            .get(key)?
+            .context("Storage changes blob not found")?;
+        Ok(Blob::decode(&mut &value[..])?)
+    }
+
+    fn load_raw_changes(&self, hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let blob = self.load_blob(&blob_key(hash))?;
+        let dict = match blob.dict {
+            0 => None,
+            id => {
+                let state = self.compression.lock().unwrap();
+                let dict = state.dicts.get(&id).cloned();
+                Some(dict.ok_or_else(|| anyhow!("Dictionary {id} not found"))?)
+            }
+        };
+        decompress(&blob, dict.as_deref().map(|d| &d[..]))
+    }
+
+    pub fn get_genesis_state(&self) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+        let value = self.db.get(GENESIS_STATE_KEY).ok().flatten()?;
+        Decode::decode(&mut &value[..]).ok()
+    }
+
+    pub fn put_genesis_state(&self, state: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        self.db.put(GENESIS_STATE_KEY, state.encode())?;
+        Ok(())
+    }
+
+    pub fn compression_status(&self) -> CompressionStatus {
+        let state = self.compression.lock().unwrap();
+        CompressionStatus {
+            stats: state.stats.clone(),
+            dictionary: (state.dict != 0).then_some(state.dict),
+            savings: savings(&state.stats),
+        }
+    }
+
+    /// Converts the storage changes stored uncompressed by the earlier versions, trains the
+    /// dictionary once enough blocks are stored, and recompresses the blobs compressed without it,
+    /// a batch at a time so that it doesn't hold the grabbing up for long.
+    fn compact(&self) -> Result<()> {
+        let converted = self.convert_legacy_changes()?;
+        self.maybe_train_dict()?;
+        let recompressed = self.recompress()?;
+        if converted > 0 || recompressed > 0 {
+            let status = self.compression_status();
+            info!(
+                "Local cache: converted {converted} legacy blocks, recompressed {recompressed} \
+                 blobs, storage changes of {} blocks take {} bytes, {:.1}% saved",
+                status.stats.blocks,
+                status.stats.stored_bytes,
+                status.savings * 100.0
+            );
+        }
+        Ok(())
+    }
+
+    fn convert_legacy_changes(&self) -> Result<usize> {
+        let mut converted = 0;
+        for kv in self.db.prefix_iterator([b'c']).take(RECOMPRESS_BATCH) {
+            let (key, value) = kv?;
+            if key.first() != Some(&b'c') {
+                break;
+            }
+            let changes = BlockHeaderWithChanges::decode(&mut &value[..])
+                .context("Failed to decode legacy storage changes")?;
+            let mut state = self.compression.lock().unwrap();
+            let mut batch = WriteBatch::default();
+            self.stage_changes(&mut state, &mut batch, &changes)?;
+            batch.delete(key);
+            self.db.write(batch)?;
+            converted += 1;
+        }
+        Ok(converted)
+    }
+
+    fn maybe_train_dict(&self) -> Result<()> {
+        {
+            let state = self.compression.lock().unwrap();
+            if state.dict != 0 || state.stats.blocks < DICT_SAMPLES as u64 {
+                return Ok(());
+            }
+        }
+        let mut samples = vec![];
+        let iter = self.db.iterator(IteratorMode::From(
+            &mk_key(b's', u32::MAX),
+            Direction::Reverse,
+        ));
+        for kv in iter.take(DICT_SAMPLES) {
+            let (key, value) = kv?;
+            if key.first() != Some(&b's') {
+                break;
+            }
+            let stored = StoredChanges::decode(&mut &value[..])?;
+            samples.push(self.load_raw_changes(&stored.hash)?);
+        }
+        if samples.len() < DICT_SAMPLES {
+            return Ok(());
+        }
+        let dict = zstd::dict::from_samples(&samples, DICT_SIZE)
+            .context("Failed to train the storage changes dictionary")?;
+        info!(
+            "Local cache: trained storage changes dictionary of {} bytes",
+            dict.len()
+        );
+        self.install_dict(dict)?;
+        Ok(())
+    }
+
+    /// Compresses the storage changes with `dict` from now on, recompressing the stored ones in
+    /// the background.
+    fn install_dict(&self, dict: Vec<u8>) -> Result<u32> {
+        let mut state = self.compression.lock().unwrap();
+        let id = state.dicts.keys().max().copied().unwrap_or_default() + 1;
+        let mut batch = WriteBatch::default();
+        batch.put(dict_key(id), &dict);
+        batch.put(CURRENT_DICT_KEY, id.encode());
+        batch.delete(RECOMPRESS_CURSOR_KEY);
+        self.db.write(batch)?;
+        state.dicts.insert(id, Arc::new(dict));
+        state.dict = id;
+        Ok(id)
+    }
+
+    fn recompress(&self) -> Result<usize> {
+        if self.compression.lock().unwrap().dict == 0 {
+            return Ok(0);
+        }
+        // Past the last blob, once all of them are compressed with the current dictionary.
+        let done = vec![b'd' + 1];
+        let start = match self.db.get(RECOMPRESS_CURSOR_KEY)? {
+            Some(cursor) if cursor == done => return Ok(0),
+            Some(cursor) => cursor,
+            None => vec![b'd'],
+        };
+        let mut next = done;
+        let mut visited = 0;
+        let mut recompressed = 0;
+        for kv in self
+            .db
+            .iterator(IteratorMode::From(&start, Direction::Forward))
+        {
+            let (key, value) = kv?;
+            if key.first() != Some(&b'd') {
+                break;
+            }
+            if visited == RECOMPRESS_BATCH {
+                next = key.to_vec();
+                break;
+            }
+            visited += 1;
+            let blob = Blob::decode(&mut &value[..])?;
+            let mut state = self.compression.lock().unwrap();
+            if blob.dict == state.dict {
+                continue;
+            }
+            let dict = match blob.dict {
+                0 => None,
+                id => state.dicts.get(&id).cloned(),
+            };
+            let raw = decompress(&blob, dict.as_deref().map(|d| &d[..]))?;
+            let new_blob = compress(&state, &raw)?;
+            state.stats.stored_bytes = state
+                .stats
+                .stored_bytes
+                .saturating_sub(blob.data.len() as u64)
+                + new_blob.data.len() as u64;
+            let mut batch = WriteBatch::default();
+            batch.put(&key, new_blob.encode());
+            batch.put(CHANGES_STATS_KEY, state.stats.encode());
+            self.db.write(batch)?;
+            recompressed += 1;
+        }
+        self.db.put(RECOMPRESS_CURSOR_KEY, next)?;
+        Ok(recompressed)
+    }
+}
+
+fn compress(state: &Compression, raw: &[u8]) -> Result<Blob> {
+    let (dict, data) = match state.dicts.get(&state.dict) {
+        Some(dict) => {
+            let mut compressor = zstd::bulk::Compressor::with_dictionary(COMPRESSION_LEVEL, dict)?;
+            (state.dict, compressor.compress(raw)?)
+        }
+        None => (0, zstd::bulk::compress(raw, COMPRESSION_LEVEL)?),
+    };
+    Ok(Blob {
+        dict,
+        raw_len: raw.len() as u32,
+        data,
+    })
+}
+
+fn decompress(blob: &Blob, dict: Option<&[u8]>) -> Result<Vec<u8>> {
+    let capacity = blob.raw_len as usize;
+    let raw = match dict {
+        Some(dict) => {
+            zstd::bulk::Decompressor::with_dictionary(dict)?.decompress(&blob.data, capacity)?
+        }
+        None => zstd::bulk::decompress(&blob.data, capacity)?,
+    };
+    Ok(raw)
+}
+
+async fn finalized_number(api: &ChainApi) -> Result<u32> {
+    let hash = api.rpc().finalized_head().await?;
+    let header = api.rpc().header(Some(hash)).await?;
+    Ok(header.map(|h| h.number).unwrap_or_default())
+}
+
+async fn grab_headers(
+    cache: &LocalCache,
+    config: &LocalCacheConfig,
+    relay_api: &ChainApi,
+    para_api: &ChainApi,
+) -> Result<()> {
+    let finalized = finalized_number(relay_api).await?;
+    let next = match cache.next_block(b'h') {
+        Some(next) => next,
+        None => config.relay_start.unwrap_or(finalized),
+    };
+    if finalized < next {
+        return Ok(());
+    }
+    info!(
+        "Local cache: grabbing relaychain headers from {} to {}",
+        next, finalized
+    );
+    hc::grab_headers(
+        relay_api,
+        Some(para_api),
+        next,
+        finalized - next + 1,
+        config.justification_interval,
+        |info| cache.put(b'h', info.header.number, &info),
+    )
+    .await
+    .context("Failed to grab relaychain headers")?;
+    Ok(())
+}
+
+async fn grab_para_headers(
+    cache: &LocalCache,
+    config: &LocalCacheConfig,
+    para_api: &ChainApi,
+) -> Result<()> {
+    let finalized = finalized_number(para_api).await?;
+    let next = cache.next_block(b'p').unwrap_or(config.para_start);
+    if finalized < next {
+        return Ok(());
+    }
+    info!(
+        "Local cache: grabbing parachain headers from {} to {}",
+        next, finalized
+    );
+    hc::grab_para_headers(para_api, next, finalized - next + 1, |header| {
+        cache.put(b'p', header.number, &header)
+    })
+    .await
+    .context("Failed to grab parachain headers")?;
+    Ok(())
+}
+
+async fn grab_storage_changes(
+    cache: &LocalCache,
+    config: &LocalCacheConfig,
+    para_api: &ChainApi,
+) -> Result<()> {
+    if cache.get_genesis_state().is_none() {
+        info!("Local cache: grabbing parachain genesis state");
+        let state = pherry::chain_client::fetch_genesis_storage(para_api)
+            .await
+            .context("Failed to grab genesis state")?;
+        cache.put_genesis_state(&state)?;
+    }
+    let finalized = finalized_number(para_api).await?;
+    let next = cache.next_changes_block().unwrap_or(config.para_start);
+    if finalized < next {
+        return Ok(());
+    }
+    info!(
+        "Local cache: grabbing storage changes from {} to {}",
+        next, finalized
+    );
+    hc::grab_storage_changes(
+        para_api,
+        next,
+        finalized - next + 1,
+        config.storage_changes_batch.max(1),
+        true,
+        |changes| cache.put_storage_changes(&changes),
+    )
+    .await
+    .context("Failed to grab storage changes")?;
+    Ok(())
+}
+
+/// Keeps pulling the finalized data from the full nodes of the data sources into the local cache.
+pub async fn grab_loop(
+    cache: WrappedLocalCache,
+    dsm: WrappedDataSourceManager,
+    config: LocalCacheConfig,
+) {
+    loop {
+        let relay_api = use_relaychain_api!(dsm, true);
+        let para_api = use_parachain_api!(dsm, true);
+        match (relay_api, para_api) {
+            (Some(relay_api), Some(para_api)) => {
+                if let Err(err) = grab_headers(&cache, &config, &relay_api, &para_api).await {
+                    error!("Local cache: {err:?}");
+                }
+                if let Err(err) = grab_para_headers(&cache, &config, &para_api).await {
+                    error!("Local cache: {err:?}");
+                }
+                if let Err(err) = grab_storage_changes(&cache, &config, &para_api).await {
+                    error!("Local cache: {err:?}");
+                }
+                let compacting = cache.clone();
+                match tokio::task::spawn_blocking(move || compacting.compact()).await {
+                    Ok(Err(err)) => {
+                        error!("Local cache: failed to compact storage changes: {err:?}")
+                    }
+                    Err(err) => error!("Local cache: compaction panicked: {err:?}"),
+                    Ok(Ok(())) => {}
+                }
+            }
+            _ => warn!("Local cache: no full node available in data sources, waiting..."),
+        }
+        sleep(config.interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool_operator::get_options;
+
+    struct TempCache {
+        cache: LocalCache,
+        path: std::path::PathBuf,
+    }
+
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn temp_cache(name: &str) -> TempCache {
+        let path = std::env::temp_dir().join(format!("prb-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let db = DB::open(&get_options(None), &path).unwrap();
+        TempCache {
+            cache: LocalCache::new(Arc::new(db)),
+            path,
+        }
+    }
+
+    fn changes(number: u32, value: u32) -> BlockHeaderWithChanges {
+        BlockHeaderWithChanges {
+            block_header: BlockHeader {
+                parent_hash: Default::default(),
+                number,
+                state_root: Default::default(),
+                extrinsics_root: Default::default(),
+                digest: Default::default(),
+            },
+            storage_changes: StorageChanges {
+                main_storage_changes: vec![(b"key".to_vec(), Some(value.encode().repeat(64)))],
+                child_storage_changes: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn deduplicates_and_compresses_storage_changes() {
+        let temp = temp_cache("dedup");
+        let cache = &temp.cache;
+        for (number, value) in [(1, 1), (2, 2), (3, 1)] {
+            cache.put_storage_changes(&changes(number, value)).unwrap();
+        }
+        let stored = cache.get_storage_changes(1, 3).unwrap();
+        for (block, value) in stored.iter().zip([1, 2, 1]) {
+            assert_eq!(
+                block.encode(),
+                changes(block.block_header.number, value).encode()
+            );
+        }
+        assert!(cache.get_storage_changes(1, 4).is_none());
+        assert_eq!(cache.next_changes_block(), Some(4));
+
+        let status = cache.compression_status();
+        assert_eq!(status.stats.blocks, 3);
+        assert_eq!(status.stats.deduplicated, 1);
+        assert!(status.stats.stored_bytes < status.stats.raw_bytes);
+        assert_eq!(status.dictionary, None);
+    }
+
+    #[test]
+    fn counts_the_blocks_put_again_once() {
+        let temp = temp_cache("reput");
+        let cache = &temp.cache;
+        cache.put_storage_changes(&changes(1, 1)).unwrap();
+        let stats = cache.compression_status().stats;
+        cache.put_storage_changes(&changes(1, 1)).unwrap();
+        let again = cache.compression_status().stats;
+        assert_eq!(again.blocks, 1);
+        assert_eq!(again.raw_bytes, stats.raw_bytes);
+        assert_eq!(again.deduplicated, 0);
+    }
+
+    #[test]
+    fn converts_legacy_storage_changes() {
+        let temp = temp_cache("legacy");
+        let cache = &temp.cache;
+        cache.put(b'c', 1, &changes(1, 1)).unwrap();
+        cache.put_storage_changes(&changes(2, 2)).unwrap();
+        assert_eq!(cache.get_storage_changes(1, 2).unwrap().len(), 2);
+
+        cache.compact().unwrap();
+        assert!(cache.get::<BlockHeaderWithChanges>(b'c', 1).is_none());
+        let stored = cache.get_storage_changes(1, 2).unwrap();
+        assert_eq!(stored[0].encode(), changes(1, 1).encode());
+        assert_eq!(cache.compression_status().stats.blocks, 2);
+    }
+
+    #[test]
+    fn recompresses_with_a_new_dictionary() {
+        let temp = temp_cache("dict");
+        let cache = &temp.cache;
+        for number in 0..3 {
+            cache.put_storage_changes(&changes(number, number)).unwrap();
+        }
+        // Not trained, but zstd takes any bytes as a raw content dictionary.
+        let dict = changes(0, 0).storage_changes.encode();
+        assert_eq!(cache.install_dict(dict).unwrap(), 1);
+        cache.put_storage_changes(&changes(3, 3)).unwrap();
+        cache.compact().unwrap();
+        assert_eq!(cache.compression_status().dictionary, Some(1));
+        for kv in cache.db.prefix_iterator([b'd']) {
+            let (key, value) = kv.unwrap();
+            if key.first() != Some(&b'd') {
+                break;
+            }
+            assert_eq!(Blob::decode(&mut &value[..]).unwrap().dict, 1);
+        }
+
+        // The dictionaries are loaded again with the database.
+        let reopened = LocalCache::new(cache.db.clone());
+        assert_eq!(reopened.compression_status().dictionary, Some(1));
+        for number in 0..4 {
+            assert_eq!(
+                reopened.get_storage_changes(number, number).unwrap()[0].encode(),
+                changes(number, number).encode()
+            );
+        }
+    }
+}
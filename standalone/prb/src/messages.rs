@@ -358,7 +358,7 @@ async fn do_sync_message(
     message: SignedMessage,
 ) {
     let sequence = message.sequence;
-    let result = txm.sync_offchain_message(pool_id, message).await;
+    let result = txm.sync_offchain_message(pool_id, worker_id.clone(), message).await;
     let _ = bus.send_messages_event(
         MessagesEvent::Completed((worker_id, sender.clone(), sequence, result))
     );
@@ -0,0 +1,242 @@
+use crate::datasource::{DataSourceCacheItem, DataSourceManager};
+use crate::headers_db::{get_current_point, justified_span};
+use crate::pool_operator::DB;
+use crate::repository::WorkerSyncInfo;
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use parity_scale_codec::Encode;
+use phactory_api::blocks::HeaderToSync;
+use phactory_api::prpc::{Blocks, HeadersToSync, Message};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+
+/// Workers not requesting a sync for this long are left out of the frontier.
+const FRONTIER_TTL: Duration = Duration::from_secs(300);
+const REFILL_INTERVAL: Duration = Duration::from_secs(6);
+
+/// The sync position of the slowest workers.
+#[derive(Clone, Debug)]
+struct Frontier {
+    headernum: u32,
+    blocknum: u32,
+    block_batch_size: u32,
+    /// The highest para header synced by any worker, no block can be synced beyond it.
+    para_headernum: u32,
+}
+
+#[derive(Default)]
+struct Window {
+    /// Justification-bearing spans of relaychain headers by the first header number, with the
+    /// last header number. The spans are aligned to the entries of the headers DB, so that the
+    /// workers anywhere within a span share it.
+    headers: BTreeMap<u32, (Vec<HeaderToSync>, u32)>,
+    /// Parachain blocks by the first and the last block number
+    blocks: BTreeMap<(u32, u32), Blocks>,
+    resident_size: usize,
+}
+
+impl Window {
+    fn prune(&mut self, frontier: &Frontier) {
+        self.headers.retain(|_, (_, to)| *to >= frontier.headernum);
+        self.blocks.retain(|(_, to), _| *to >= frontier.blocknum);
+        self.resident_size = self
+            .headers
+            .values()
+            .map(|(h, _)| h.encoded_size())
+            .sum::<usize>()
+            + self.blocks.values().map(|b| b.encoded_len()).sum::<usize>();
+    }
+}
+
+/// Prefetches the sync payloads in a sliding window ahead of the slowest worker.
+///
+/// The relaychain headers and the parachain blocks are fetched, SCALE encoded and kept ready to
+/// send, so the workers catching up behind each other get their sync requests without touching
+/// the data sources. The window is bounded by a memory budget, and slides forward as the slowest
+/// worker moves on.
+pub struct SyncPrefetcher {
+    dsm: Arc<DataSourceManager>,
+    headers_db: Arc<DB>,
+    window_batches: u32,
+    memory_budget: usize,
+    workers: Mutex<HashMap<String, (Instant, WorkerSyncInfo)>>,
+    window: Mutex<Window>,
+    wakeup: Notify,
+}
+
+impl SyncPrefetcher {
+    pub fn new(
+        dsm: Arc<DataSourceManager>,
+        headers_db: Arc<DB>,
+        window_batches: u32,
+        memory_budget: usize,
+    ) -> Self {
+        Self {
+            dsm,
+            headers_db,
+            window_batches,
+            memory_budget,
+            workers: Default::default(),
+            window: Default::default(),
+            wakeup: Notify::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.window_batches > 0 && self.memory_budget > 0
+    }
+
+    /// Records the sync position of a worker, sliding the window if it was the slowest one.
+    pub fn report(&self, info: &WorkerSyncInfo) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.workers
+            .lock()
+            .unwrap()
+            .insert(info.worker_id.clone(), (Instant::now(), info.clone()));
+        self.wakeup.notify_one();
+    }
+
+    /// Cuts the headers to sync from `from` out of the prefetched span covering it.
+    pub fn get_headers(&self, from: u32) -> Option<(HeadersToSync, u32)> {
+        let window = self.window.lock().unwrap();
+        let (_, (headers, to)) = window.headers.range(..=from).next_back()?;
+        if *to < from {
+            return None;
+        }
+        let (headers, to) = justified_span(headers, from)?;
+        Some((HeadersToSync::new(headers, None), to))
+    }
+
+    fn covered_headers(&self, from: u32) -> Option<u32> {
+        let window = self.window.lock().unwrap();
+        let (_, (_, to)) = window.headers.range(..=from).next_back()?;
+        (*to >= from).then_some(*to)
+    }
+
+    pub fn get_blocks(&self, from: u32, to: u32) -> Option<Blocks> {
+        self.window.lock().unwrap().blocks.get(&(from, to)).cloned()
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        if !self.is_enabled() {
+            return;
+        }
+        loop {
+            tokio::select! {
+                _ = self.wakeup.notified() => {}
+                _ = sleep(REFILL_INTERVAL) => {}
+            }
+            if let Err(err) = self.refill().await {
+                warn!("Failed to prefetch sync payloads. {err:#}");
+                sleep(REFILL_INTERVAL).await;
+            }
+        }
+    }
+
+    fn frontier(&self) -> Option<Frontier> {
+        let mut workers = self.workers.lock().unwrap();
+        workers.retain(|_, (reported_at, _)| reported_at.elapsed() < FRONTIER_TTL);
+        let headernum = workers.values().map(|(_, w)| w.headernum).min()?;
+        let para_headernum = workers.values().map(|(_, w)| w.para_headernum).max()?;
+        let (blocknum, block_batch_size) = workers
+            .values()
+            .filter(|(_, w)| w.blocknum < w.para_headernum)
+            .map(|(_, w)| (w.blocknum, w.block_batch_size))
+            .min()
+            .unwrap_or((para_headernum, 1));
+        Some(Frontier {
+            headernum,
+            blocknum,
+            block_batch_size: block_batch_size.max(1),
+            para_headernum,
+        })
+    }
+
+    /// Reserves room for a payload of `size` bytes, returns false if it exceeds the budget.
+    fn reserve(&self, size: usize) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if window.resident_size + size > self.memory_budget {
+            return false;
+        }
+        window.resident_size += size;
+        true
+    }
+
+    async fn refill(&self) -> Result<()> {
+        let frontier = match self.frontier() {
+            Some(frontier) => frontier,
+            None => return Ok(()),
+        };
+        self.window.lock().unwrap().prune(&frontier);
+
+        let mut headernum = frontier.headernum;
+        for _ in 0..self.window_batches {
+            if let Some(to) = self.covered_headers(headernum) {
+                headernum = to + 1;
+                continue;
+            }
+            // Keep the whole DB entry, the span is then shared by the workers behind the
+            // frontier as well as the ones within it.
+            let headers = match get_current_point(self.headers_db.clone(), headernum) {
+                Some(headers) => headers,
+                None => break,
+            };
+            let (from, to) = match (headers.first(), headers.last()) {
+                (Some(first), Some(last)) if last.justification.is_some() => {
+                    (first.header.number, last.header.number)
+                }
+                _ => break,
+            };
+            if !self.reserve(headers.encoded_size()) {
+                break;
+            }
+            debug!("Prefetched relaychain headers #{from}-{to}.");
+            self.window
+                .lock()
+                .unwrap()
+                .headers
+                .insert(from, (headers, to));
+            headernum = to + 1;
+        }
+
+        let batch = frontier.block_batch_size;
+        let mut from = frontier.blocknum;
+        for _ in 0..self.window_batches {
+            if from >= frontier.para_headernum {
+                break;
+            }
+            // Same alignment as the sync requests, so the workers hit the prefetched batches.
+            let to = std::cmp::min(
+                (from + batch - 1) / batch * batch,
+                frontier.para_headernum - 1,
+            );
+            if self.get_blocks(from, to).is_none() {
+                let item = self.dsm.clone().do_fetch_storage_changes(from, to, None).await?;
+                let blocks = match *item {
+                    DataSourceCacheItem::StorageChanges(ref blocks) => blocks
+                        .iter()
+                        .map(|b| b.as_ref().clone())
+                        .collect::<Vec<_>>(),
+                    _ => return Err(anyhow!("Unexpected storage changes from data source")),
+                };
+                let blocks = Blocks::new(blocks);
+                if !self.reserve(blocks.encoded_len()) {
+                    break;
+                }
+                debug!("Prefetched parachain blocks #{from}-{to}.");
+                self.window
+                    .lock()
+                    .unwrap()
+                    .blocks
+                    .insert((from, to), blocks);
+            }
+            from = to + 1;
+        }
+        Ok(())
+    }
+}
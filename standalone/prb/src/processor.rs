@@ -1,27 +1,34 @@
+use crate::alert::{Alert, AlertKind};
 use crate::api::WorkerStatus;
 use crate::bus::Bus;
+use crate::chain_events::{Subject, UnresponsiveAction, WorkerChainEvent};
 use crate::compute_management::*;
 use crate::datasource::DataSourceManager;
-use crate::repository::{do_request_next_sync, get_load_state_request, ChaintipInfo, SyncRequest, SyncRequestManifest, WorkerSyncInfo};
+use crate::repository::{do_request_next_sync, get_load_state_request, ChaintipInfo, ClonedChainState, SyncRequest, SyncRequestManifest, WorkerSyncInfo};
 use crate::messages::MessagesEvent;
 use crate::pool_operator::DB;
+use crate::prefetch::SyncPrefetcher;
 use crate::pruntime::PRuntimeClient;
+use crate::scheduler::{Prioritized, Priority, SystemClock, TickBudget, TickScheduler};
 use crate::tx::TxManager;
 use crate::{use_parachain_api, use_relaychain_api};
-use crate::worker::{WorkerLifecycleCommand, WorkerLifecycleState};
+use crate::worker::{
+    push_transition, StateTransition, SyncFailures, WorkerLifecycle, WorkerLifecycleCommand, WorkerLifecycleState,
+};
 use crate::worker_status::WorkerStatusUpdate;
 use anyhow::Result;
 use chrono::{DateTime, Duration, Utc};
 use derive_more::Display;
 use log::{debug, error, info, trace, warn};
 use phactory_api::prpc::{
-    self, ChainState, GetEgressMessagesResponse, GetEndpointResponse, GetRuntimeInfoRequest,
-    InitRuntimeRequest, InitRuntimeResponse, PhactoryInfo, SignEndpointsRequest,
+    self, Capability, ChainState, EgressCursor, GatekeeperRole, GetEgressMessagesPagedRequest,
+    GetEgressMessagesPagedResponse, GetEndpointResponse, GetRuntimeInfoRequest, InitRuntimeRequest, InitRuntimeResponse, PhactoryInfo, SignEndpointsRequest,
 };
 use phala_pallets::pallet_computation::{SessionInfo, WorkerState};
 use phala_pallets::registry::WorkerInfoV2;
 use phala_trie_storage::TrieStorage;
 use phala_types::messaging::MessageOrigin;
+use pherry::pccs::CollateralFetcher;
 use sp_core::crypto::{AccountId32, ByteArray};
 use sp_core::sr25519::Public as Sr25519Public;
 use std::collections::{HashMap, VecDeque};
@@ -34,6 +41,12 @@ const UPDATE_PHACTORY_INFO_INTERVAL: Duration = Duration::seconds(5);
 #[allow(deprecated)]
 const RESTART_WORKER_COOL_PERIOD: Duration = Duration::seconds(15);
 
+const CLONE_DONOR_MAX_LAG: u32 = 10;
+
+/// The egress messages are fetched in pages of this size, so that a worker catching up with lots
+/// of pending messages doesn't produce a response beyond the HTTP limits.
+const EGRESS_PAGE_BYTES: u32 = 1024 * 1024;
+
 pub enum SyncStage {
     NotStart,
     Init,
@@ -67,6 +80,7 @@ pub struct WorkerContext {
     pub pending_requests: VecDeque<PRuntimeRequest>,
     pub pruntime_recent_error_count: usize,
     pub last_worker_lifecycle: Option<WorkerLifecycleState>,
+    pub lifecycle: WorkerLifecycle,
 
     pub phactory_info_requested: bool,
     pub phactory_info_requested_at: DateTime<Utc>,
@@ -75,6 +89,21 @@ pub struct WorkerContext {
 
     pub compute_management_context: Option<ComputeManagementContext>,
     pub session_updated: bool,
+
+    pub block_batch_size: u32,
+    pub dispatch_latency_ms: Option<f64>,
+
+    pub sync_paused: bool,
+    pub sync_failures: SyncFailures,
+    /// The parachain block to stop syncing at, the worker's own or the global one
+    pub to_block: Option<u32>,
+    /// Set once loading the chain state failed, the worker then syncs all the blocks instead.
+    pub fast_sync_failed: bool,
+
+    /// The sync progress last seen, to tell a stalled sync
+    pub sync_progress: (u32, u32, u32),
+    pub sync_progressed_at: DateTime<Utc>,
+    pub sync_stall_alerted: bool,
 }
 
 impl WorkerContext {
@@ -107,6 +136,7 @@ impl WorkerContext {
                 phactory_info: None,
                 last_message: String::new(),
                 session_info: None,
+                transitions: Vec::new(),
             },
             worker_info: None,
             session_id: None,
@@ -119,6 +149,7 @@ impl WorkerContext {
             pending_requests: VecDeque::new(),
             pruntime_recent_error_count: 0,
             last_worker_lifecycle: None,
+            lifecycle: WorkerLifecycle::default(),
 
             phactory_info_requested: false,
             phactory_info_requested_at: DateTime::<Utc>::MIN_UTC,
@@ -127,6 +158,18 @@ impl WorkerContext {
 
             compute_management_context: None,
             session_updated: false,
+
+            block_batch_size: 0,
+            dispatch_latency_ms: None,
+
+            sync_paused: false,
+            sync_failures: SyncFailures::default(),
+            to_block: worker.to_block,
+            fast_sync_failed: false,
+
+            sync_progress: (0, 0, 0),
+            sync_progressed_at: Utc::now(),
+            sync_stall_alerted: false,
         }
     }
 
@@ -160,6 +203,20 @@ impl WorkerContext {
             .and_then(|result| result.ok())
     }
     
+    /// Whether the pRuntime of the worker advertises the capability, as of its latest `get_info`.
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.worker_status.phactory_info
+            .as_ref()
+            .map(|info| info.has_capability(capability))
+            .unwrap_or(false)
+    }
+
+    /// Whether the pRuntime of the worker serves `SyncCombinedHeaders`, see
+    /// [`serves_combined_headers`].
+    pub fn syncs_combined_headers(&self) -> bool {
+        self.worker_status.phactory_info.as_ref().map_or(true, serves_combined_headers)
+    }
+
     pub fn is_registered(&self) -> bool {
         self.worker_status.phactory_info
             .as_ref()
@@ -196,6 +253,14 @@ impl WorkerContext {
         true
     }
 
+    /// Whether the sync request would take the worker beyond its target block.
+    pub fn is_beyond_to_block(&self, manifest: &SyncRequestManifest) -> bool {
+        match (self.to_block, manifest.blocks) {
+            (Some(to_block), Some((_, to))) => to >= to_block,
+            _ => false,
+        }
+    }
+
     pub fn is_reached_chaintip(
         &self,
         chaintip: &ChaintipInfo,
@@ -209,17 +274,75 @@ impl WorkerContext {
         self.worker_sync_only || self.pool_sync_only
     }
 
+    /// Returns why syncing the worker should be paused, if it should.
+    ///
+    /// Like the `--to-block` of pherry, the worker stops once its next block to sync reaches the
+    /// target one.
+    ///
+    /// Also paused is a gatekeeper waiting for the master key handover, see
+    /// [`handover_pause_reason`]. Both pauses are lifted as soon as the condition is gone, on the
+    /// regular refresh of the pRuntime info.
+    pub fn sync_pause_reason(&self) -> Option<String> {
+        if let Some(to_block) = self.to_block {
+            if self.blocknum >= to_block {
+                return Some(format!("Reached the target block {}.", to_block));
+            }
+        }
+        handover_pause_reason(self.worker_status.phactory_info.as_ref()?)
+    }
+
+    /// Whether the egress messages should be fetched on the timer. Only the registered workers at
+    /// the chain tip are polled, and not while they are calling pRuntime or recovering from a
+    /// failure, the messages are fetched after each dispatch anyway.
+    pub fn is_egress_polling_due(&self, chaintip: &ChaintipInfo) -> bool {
+        !self.stopped
+            && self.is_registered()
+            && self.is_reached_chaintip(chaintip)
+            && !self.pruntime_lock
+            && self.pruntime_recent_error_count == 0
+            && !matches!(self.worker_status.state, WorkerLifecycleState::HasError(_))
+            && !self.pending_requests.iter().any(|r| matches!(r, PRuntimeRequest::GetEgressMessages(_)))
+    }
+
     pub fn is_updating_phactory_info_due(&self) -> bool {
         !self.phactory_info_requested
             && Utc::now().signed_duration_since(self.phactory_info_requested_at) >= UPDATE_PHACTORY_INFO_INTERVAL
     }
 }
 
+/// Whether the pRuntime serves `SyncCombinedHeaders`. The ones older than the capability
+/// negotiation advertise nothing but serve it, so only the ones advertising other capabilities
+/// but this one are synced with the headers split.
+pub fn serves_combined_headers(info: &PhactoryInfo) -> bool {
+    info.capabilities.is_empty() || info.has_capability(Capability::CombinedHeaderSync)
+}
+
+/// Returns why a gatekeeper should wait for the master key handover, if it should.
+///
+/// A pRuntime in safe mode feeds the blocks without dispatching the messages. A gatekeeper there
+/// without the master key yet would never receive it, so pushing it beyond the key handover point
+/// leaves its state inconsistent with the chain. The other workers in safe mode are synced as
+/// usual, that's what the safe mode is for.
+pub fn handover_pause_reason(info: &PhactoryInfo) -> Option<String> {
+    if info.safe_mode_level == 0 {
+        return None;
+    }
+    let gatekeeper = info.system.as_ref()?.gatekeeper.as_ref()?;
+    if gatekeeper.role == GatekeeperRole::None as i32 || !gatekeeper.master_public_key.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Gatekeeper is in safe mode level {} without the master key, waiting for the master key handover.",
+        info.safe_mode_level
+    ))
+}
+
 #[derive(Default)]
 pub struct SyncInfo {
     pub headernum: Option<u32>,
     pub para_headernum: Option<u32>,
     pub blocknum: Option<u32>,
+    pub dispatch_elapsed: Option<std::time::Duration>,
 }
 
 #[derive(Debug)]
@@ -230,7 +353,7 @@ pub enum PRuntimeRequest {
     Sync(SyncRequest),
     RegularGetInfo,
     PrepareRegister((bool, Option<sp_core::crypto::AccountId32>, bool)),
-    GetEgressMessages,
+    GetEgressMessages(Option<EgressCursor>),
     SignEndpoints(Vec<String>),
     TakeCheckpoint,
 }
@@ -242,7 +365,7 @@ pub enum PRuntimeResponse {
     Sync(SyncInfo),
     RegularGetInfo(PhactoryInfo),
     PrepareRegister(InitRuntimeResponse),
-    GetEgressMessages(GetEgressMessagesResponse),
+    GetEgressMessages(GetEgressMessagesPagedResponse),
     SignEndpoints(GetEndpointResponse),
     TakeCheckpoint(u32),
 }
@@ -269,7 +392,7 @@ impl fmt::Display for PRuntimeRequest {
             },
             PRuntimeRequest::RegularGetInfo => write!(f, "RegularGetInfo"),
             PRuntimeRequest::PrepareRegister(_) => write!(f, "PrepareRegister"),
-            PRuntimeRequest::GetEgressMessages => write!(f, "GetEgressMessages"),
+            PRuntimeRequest::GetEgressMessages(_) => write!(f, "GetEgressMessages"),
             PRuntimeRequest::SignEndpoints(_) => write!(f, "SignEndpoints"),
             PRuntimeRequest::TakeCheckpoint => write!(f, "TakeCheckpoint"),
         }
@@ -315,6 +438,8 @@ pub enum WorkerEvent {
     PRuntimeResponse(Result<PRuntimeResponse, prpc::client::Error>),
     #[display(fmt = "RepositoryLoadStateRequest")]
     RepositoryLoadStateRequest,
+    #[display(fmt = "FastSyncFailed")]
+    FastSyncFailed(String),
     #[display(fmt = "RepositoryPreloadRequest")]
     RepositoryPreloadRequest(SyncRequest),
     #[display(fmt = "RepositorySyncRequest")]
@@ -325,6 +450,13 @@ pub enum WorkerEvent {
     UpdateMessage((DateTime<Utc>, String)),
     #[display(fmt = "MarkError")]
     MarkError((DateTime<Utc>, String)),
+    /// Generating the sync request at `(headernum, para_headernum, blocknum)` kept failing.
+    #[display(fmt = "SyncFailed")]
+    SyncFailed(((u32, u32, u32), String)),
+    #[display(fmt = "ResumeSync")]
+    ResumeSync,
+    #[display(fmt = "Recover")]
+    Recover,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -342,6 +474,8 @@ pub enum ProcessorEvent {
     WorkerEvent((String, WorkerEvent)),
     #[display(fmt = "Heartbeat")]
     Heartbeat,
+    #[display(fmt = "GetEgressMsgTimerReceived")]
+    GetEgressMsgTimerReceived,
     #[display(fmt = "BroadcastSync")]
     BroadcastSync((SyncRequest, ChaintipInfo)),
     #[display(fmt = "RequestUpdateSessionInfo")]
@@ -352,6 +486,23 @@ pub enum ProcessorEvent {
     ReceivedParaStorageChanges(phactory_api::blocks::StorageChanges),
 }
 
+impl Prioritized for ProcessorEvent {
+    fn priority(&self) -> Priority {
+        match self {
+            ProcessorEvent::WorkerEvent((_, event)) => match event {
+                WorkerEvent::PRuntimeResponse(Ok(PRuntimeResponse::Sync(_)))
+                | WorkerEvent::PRuntimeResponse(Err(_)) => Priority::SyncResponse,
+                WorkerEvent::PRuntimeRequest(PRuntimeRequest::Sync(_))
+                | WorkerEvent::RepositoryPreloadRequest(_)
+                | WorkerEvent::RepositorySyncRequest(_) => Priority::SyncRequest,
+                _ => Priority::Other,
+            },
+            ProcessorEvent::BroadcastSync(_) => Priority::SyncRequest,
+            _ => Priority::Other,
+        }
+    }
+}
+
 pub type ProcessorRx = mpsc::Receiver<ProcessorEvent>;
 pub type ProcessorTx = mpsc::Sender<ProcessorEvent>;
 
@@ -381,6 +532,14 @@ impl Storage {
     pub fn session_info(&self, session: &AccountId32) -> Option<SessionInfo> {
         self.execute_with(|| phala_pallets::pallet_computation::Sessions::<chain::Runtime>::get(session))
     }
+
+    /// The events of the latest block applied.
+    pub fn events(&self) -> Vec<chain::RuntimeEvent> {
+        self.execute_with(chain::System::events)
+            .into_iter()
+            .map(|record| record.event)
+            .collect()
+    }
 }
 
 pub struct Processor {
@@ -392,14 +551,33 @@ pub struct Processor {
     pub headers_db: Arc<DB>,
 
     pub allow_fast_sync: bool,
-    pub pccs_url: String,
-    pub pccs_timeout_secs: u64,
+    pub cloned_chain_state: Arc<ClonedChainState>,
+    pub clone_donor: Option<(String, Arc<PRuntimeClient>)>,
+    pub collateral_fetcher: Arc<CollateralFetcher>,
+    pub prefetcher: Arc<SyncPrefetcher>,
 
     pub init_runtime_request_ias: InitRuntimeRequest,
     pub init_runtime_request_dcap: InitRuntimeRequest,
 
     pub chaintip: ChaintipInfo,
 
+    pub min_block_batch_size: u32,
+    pub max_block_batch_size: u32,
+    pub block_batch_target_ms: f64,
+    pub to_block: Option<u32>,
+    /// The failures in a row to sync a worker at the same block before it's paused
+    pub sync_failure_threshold: Option<u32>,
+
+    pub tick_budget: TickBudget,
+
+    /// How long a worker behind the chaintip can go without sync progress before it's alerted
+    pub sync_stall_timeout: Option<Duration>,
+
+    pub on_unresponsive: UnresponsiveAction,
+
+    /// The lifecycles of the workers being restarted, handed over to the re-added contexts.
+    restarting_lifecycles: HashMap<String, (WorkerLifecycle, Vec<StateTransition>)>,
+
     storage: Storage,
 }
 
@@ -422,6 +600,14 @@ impl Processor {
         ).await.unwrap();
         storage.0.load(pairs.into_iter());
 
+        let prefetcher = Arc::new(SyncPrefetcher::new(
+            dsm.clone(),
+            headers_db.clone(),
+            args.prefetch_window,
+            args.prefetch_memory_budget,
+        ));
+        tokio::spawn(prefetcher.clone().run());
+
         Self {
             rx,
 
@@ -431,8 +617,17 @@ impl Processor {
             headers_db,
 
             allow_fast_sync: !args.disable_fast_sync,
-            pccs_url: args.pccs_url.clone(),
-            pccs_timeout_secs: args.pccs_timeout,
+            cloned_chain_state: Arc::new(ClonedChainState::new(
+                std::time::Duration::from_secs(args.fast_sync_clone_max_age),
+            )),
+            clone_donor: None,
+            collateral_fetcher: Arc::new(
+                CollateralFetcher::new(&args.pccs_url, args.pccs_timeout).with_cache(
+                    &args.pccs_cache_dir,
+                    std::time::Duration::from_secs(args.pccs_cache_ttl),
+                ),
+            ),
+            prefetcher,
 
             init_runtime_request_ias: ias_init_runtime_request,
             init_runtime_request_dcap: dcap_init_runtime_request,
@@ -442,163 +637,255 @@ impl Processor {
                 parachain: use_parachain_api!(dsm, false).unwrap().latest_finalized_block_number().await.unwrap(),
             },
 
+            min_block_batch_size: args.min_block_batch_size.max(1),
+            max_block_batch_size: args.max_block_batch_size.max(args.min_block_batch_size).max(1),
+            block_batch_target_ms: args.block_batch_target_ms as f64,
+            to_block: args.to_block,
+            sync_failure_threshold: (args.sync_failure_pause_threshold > 0)
+                .then_some(args.sync_failure_pause_threshold),
+
+            tick_budget: TickBudget {
+                max_events: args.processor_tick_max_events,
+                max_duration: std::time::Duration::from_millis(args.processor_tick_budget_ms),
+            },
+
+            sync_stall_timeout: (args.alert_sync_stall_timeout > 0)
+                .then(|| Duration::seconds(args.alert_sync_stall_timeout as i64)),
+
+            on_unresponsive: args.on_unresponsive,
+
+            restarting_lifecycles: HashMap::new(),
+
             storage,
         }
     }
 
+    /// Handles the events in ticks, see [`TickScheduler`] for the order they are handled in.
     pub fn master_loop(&mut self) {
         let _ = thread_priority::set_current_thread_priority(thread_priority::ThreadPriority::Max);
 
         let mut workers = HashMap::<String, WorkerContext>::new();
+        let mut scheduler = TickScheduler::new(self.tick_budget, SystemClock);
+        let mut disconnected = false;
 
         loop {
-            let event = match self.rx.recv() {
-                Ok(event) => event,
-                Err(_) => break,
-            };
-
-            let start_time = Instant::now();
-            let event_display = format!("{event}");
-            match event {
-                ProcessorEvent::AddWorker((added_worker, pool_sync_only, operator, pruntime_client)) => {
-                    let worker_id = added_worker.id.clone();
-                    let worker_context = WorkerContext::create(added_worker, pool_sync_only, operator, pruntime_client);
-                    if workers.contains_key(&worker_id) {
-                        error!("[{}] Failed to add worker because the UUID is existed.", worker_id);
-                    } else {
-                        workers.insert(worker_id.clone(), worker_context);
-                        self.send_worker_status(workers.get_mut(&worker_id).unwrap());
-                        trace!("[{}] Added worker into processor. Starting", worker_id);
-                        self.add_pruntime_request(
-                            workers.get_mut(&worker_id).unwrap(),
-                            PRuntimeRequest::PrepareLifecycle
-                        );
+            if scheduler.is_empty() {
+                if disconnected {
+                    break;
+                }
+                match self.rx.recv() {
+                    Ok(event) => scheduler.push(event),
+                    Err(_) => break,
+                }
+            }
+            loop {
+                match self.rx.try_recv() {
+                    Ok(event) => scheduler.push(event),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        disconnected = true;
+                        break;
                     }
-                },
-                ProcessorEvent::DeleteWorker(worker_id) => {
-                    match workers.remove(&worker_id) {
-                        Some(removed_worker) => {
-                            if let Some(public_key) = removed_worker.public_key() {
-                                trace!("[{}] Requesting remove MessageOrigin::Worker({})", worker_id, public_key);
-                                let _ = self.bus.send_messages_event(
-                                    MessagesEvent::RemoveSender(MessageOrigin::Worker(public_key))
-                                );
-                                let _ = self.bus.send_worker_status_event((
-                                    worker_id.clone(),
-                                    WorkerStatusUpdate::Delete
-                                ));
-                            }
-                        },
-                        None => {
-                            error!("[{}] Failed to delete worker because the UUID is not existed.", worker_id);
+                }
+            }
+
+            let mut tick = scheduler.start_tick();
+            while let Some(event) = scheduler.next(&mut tick) {
+                self.handle_event(&mut workers, event);
+            }
+            if !scheduler.is_empty() {
+                trace!("tick budget ran out, pending events by priority: {:?}", scheduler.pending());
+            }
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        workers: &mut HashMap<String, WorkerContext>,
+        event: ProcessorEvent,
+    ) {
+        let start_time = Instant::now();
+        let event_display = format!("{event}");
+        match event {
+            ProcessorEvent::AddWorker((added_worker, pool_sync_only, operator, pruntime_client)) => {
+                let worker_id = added_worker.id.clone();
+                let mut worker_context = WorkerContext::create(added_worker, pool_sync_only, operator, pruntime_client);
+                worker_context.block_batch_size = self.min_block_batch_size;
+                worker_context.to_block = worker_context.to_block.or(self.to_block);
+                if let Some((mut lifecycle, transitions)) = self.restarting_lifecycles.remove(&worker_id) {
+                    // A recovery scheduled before the restart has been dropped with the old context.
+                    lifecycle.recovery_pending = false;
+                    worker_context.lifecycle = lifecycle;
+                    worker_context.worker_status.transitions = transitions;
+                    push_transition(
+                        &mut worker_context.worker_status.transitions,
+                        StateTransition {
+                            from: WorkerLifecycleState::Restarting,
+                            to: worker_context.worker_status.state.clone(),
+                            at: Utc::now(),
                         },
-                    }
-                },
-                ProcessorEvent::UpdatePool((pool_id, pool)) => {
-                    let pool_sync_only = pool.map(|p| p.sync_only).unwrap_or(true);
-                    for worker in workers.values_mut() {
-                        if worker.pool_id == pool_id && worker.pool_sync_only != pool_sync_only {
-                            worker.pool_sync_only = pool_sync_only;
+                    );
+                }
+                if workers.contains_key(&worker_id) {
+                    error!("[{}] Failed to add worker because the UUID is existed.", worker_id);
+                } else {
+                    workers.insert(worker_id.clone(), worker_context);
+                    self.send_worker_status(workers.get_mut(&worker_id).unwrap());
+                    trace!("[{}] Added worker into processor. Starting", worker_id);
+                    self.add_pruntime_request(
+                        workers.get_mut(&worker_id).unwrap(),
+                        PRuntimeRequest::PrepareLifecycle
+                    );
+                }
+            },
+            ProcessorEvent::DeleteWorker(worker_id) => {
+                if self.clone_donor.as_ref().is_some_and(|(id, _)| *id == worker_id) {
+                    self.clone_donor = None;
+                }
+                match workers.remove(&worker_id) {
+                    Some(removed_worker) => {
+                        if matches!(removed_worker.worker_status.state, WorkerLifecycleState::Restarting) {
+                            self.restarting_lifecycles.insert(
+                                worker_id.clone(),
+                                (removed_worker.lifecycle.clone(), removed_worker.worker_status.transitions.clone()),
+                            );
                         }
-                    }
-                },
-                ProcessorEvent::UpdatePoolOperator((pool_id, operator)) => {
-                    for worker in workers.values_mut() {
-                        if worker.pool_id == pool_id && worker.operator != operator {
-                            worker.operator = operator.clone();
-                            self.add_pruntime_request(
-                                worker,
-                                PRuntimeRequest::PrepareRegister((
-                                    true,
-                                    worker.operator.clone(),
-                                    false,
-                                ))
+                        if let Some(public_key) = removed_worker.public_key() {
+                            trace!("[{}] Requesting remove MessageOrigin::Worker({})", worker_id, public_key);
+                            let _ = self.bus.send_messages_event(
+                                MessagesEvent::RemoveSender(MessageOrigin::Worker(public_key))
                             );
+                            let _ = self.bus.send_worker_status_event((
+                                worker_id.clone(),
+                                WorkerStatusUpdate::Delete
+                            ));
                         }
+                    },
+                    None => {
+                        error!("[{}] Failed to delete worker because the UUID is not existed.", worker_id);
+                    },
+                }
+            },
+            ProcessorEvent::UpdatePool((pool_id, pool)) => {
+                let pool_sync_only = pool.map(|p| p.sync_only).unwrap_or(true);
+                for worker in workers.values_mut() {
+                    if worker.pool_id == pool_id && worker.pool_sync_only != pool_sync_only {
+                        worker.pool_sync_only = pool_sync_only;
                     }
-                },
-                ProcessorEvent::WorkerEvent((worker_id, worker_event)) => {
-                    match workers.get_mut(&worker_id) {
-                        Some(worker_context) => {
-                            self.handle_worker_event(worker_context, worker_event);
-                        },
-                        None => {
-                            warn!("[{}] Worker does not found.", worker_id);
-                        },
+                }
+            },
+            ProcessorEvent::UpdatePoolOperator((pool_id, operator)) => {
+                for worker in workers.values_mut() {
+                    if worker.pool_id == pool_id && worker.operator != operator {
+                        worker.operator = operator.clone();
+                        self.add_pruntime_request(
+                            worker,
+                            PRuntimeRequest::PrepareRegister((
+                                true,
+                                worker.operator.clone(),
+                                false,
+                            ))
+                        );
                     }
-                },
-                ProcessorEvent::Heartbeat => {
-                    for worker in workers.values_mut() {
-                        if worker.is_updating_phactory_info_due() {
-                            worker.phactory_info_requested = true;
-                            worker.phactory_info_requested_at = Utc::now();
-                            self.add_pruntime_request(worker, PRuntimeRequest::RegularGetInfo);
-                        }
-
+                }
+            },
+            ProcessorEvent::WorkerEvent((worker_id, worker_event)) => {
+                match workers.get_mut(&worker_id) {
+                    Some(worker_context) => {
+                        self.handle_worker_event(worker_context, worker_event);
+                    },
+                    None => {
+                        warn!("[{}] Worker does not found.", worker_id);
+                    },
+                }
+            },
+            ProcessorEvent::Heartbeat => {
+                for worker in workers.values_mut() {
+                    if worker.is_updating_phactory_info_due() {
+                        worker.phactory_info_requested = true;
+                        worker.phactory_info_requested_at = Utc::now();
+                        self.add_pruntime_request(worker, PRuntimeRequest::RegularGetInfo);
+                    }
+                    self.check_sync_stall(worker);
+                }
+            },
+            ProcessorEvent::GetEgressMsgTimerReceived => {
+                for worker in workers.values_mut() {
+                    if worker.is_egress_polling_due(&self.chaintip) {
+                        trace!("[{}] Timer triggered, requesting EgressMessages", worker.uuid);
+                        self.add_pruntime_request(worker, PRuntimeRequest::GetEgressMessages(None));
+                    }
+                }
+            },
+            ProcessorEvent::BroadcastSync((request, info)) => {
+                for worker in workers.values_mut() {
+                    if !worker.pending_broadcast || worker.sync_paused {
+                        continue;
                     }
-                },
-                ProcessorEvent::BroadcastSync((request, info)) => {
-                    for worker in workers.values_mut() {
-                        if !worker.pending_broadcast {
-                            continue;
-                        }
 
-                        if worker.is_reached_chaintip(&self.chaintip) {
-                            if worker.is_match(&request.manifest) {
-                                worker.pending_broadcast = false;
-                                trace!("[{}] Accepted BroadcastSyncRequest", worker.uuid);
-                                self.add_pruntime_request(worker, PRuntimeRequest::Sync(request.clone()));
+                    if worker.is_reached_chaintip(&self.chaintip) && !worker.is_beyond_to_block(&request.manifest) {
+                        if worker.is_match(&request.manifest) {
+                            worker.pending_broadcast = false;
+                            trace!("[{}] Accepted BroadcastSyncRequest", worker.uuid);
+                            let request = if worker.syncs_combined_headers() {
+                                request.clone()
                             } else {
-                                debug!("[{}] Worker is at chaintip but not match the incoming BroadcastSync request.", worker.uuid);
-                            }
+                                request.clone().split_combined_headers()
+                            };
+                            self.add_pruntime_request(worker, PRuntimeRequest::Sync(request));
                         } else {
-                            worker.pending_broadcast = false;
-                            trace!("[{}] Not at chaintip but pending for broadcase. Need to re-trigger sync.", worker.uuid);
-                            self.request_next_sync(worker);
+                            debug!("[{}] Worker is at chaintip but not match the incoming BroadcastSync request.", worker.uuid);
                         }
+                    } else {
+                        worker.pending_broadcast = false;
+                        trace!("[{}] Not at chaintip but pending for broadcase. Need to re-trigger sync.", worker.uuid);
+                        self.request_next_sync(worker);
                     }
-                    self.chaintip = info;
-                },
-                ProcessorEvent::RequestUpdateSessionInfo => {
-                    for worker in workers.values_mut() {
-                        if !worker.is_registered() {
-                            continue;
-                        }
-                        let public_key = match worker.public_key() {
-                            Some(key) => key,
-                            None => continue,
-                        };
-
-                        if let Some(worker_info) = self.storage.worker_info(&public_key) {
-                            worker.worker_info = Some(worker_info);
-                            if let Some(session_id) = self.storage.session_id(&public_key) {
-                                worker.worker_status.session_info = self.storage.session_info(&session_id);
-                                worker.session_id = Some(session_id);
-                            } else {
-                                worker.worker_status.session_info = None;
-                                worker.session_id = None;
-                            }
-                            worker.session_updated = true;
+                }
+                self.chaintip = info;
+            },
+            ProcessorEvent::RequestUpdateSessionInfo => {
+                for worker in workers.values_mut() {
+                    if !worker.is_registered() {
+                        continue;
+                    }
+                    let public_key = match worker.public_key() {
+                        Some(key) => key,
+                        None => continue,
+                    };
+
+                    if let Some(worker_info) = self.storage.worker_info(&public_key) {
+                        worker.worker_info = Some(worker_info);
+                        if let Some(session_id) = self.storage.session_id(&public_key) {
+                            worker.worker_status.session_info = self.storage.session_info(&session_id);
+                            worker.session_id = Some(session_id);
+                        } else {
+                            worker.worker_status.session_info = None;
+                            worker.session_id = None;
                         }
-                        self.send_worker_status(worker);
+                        worker.session_updated = true;
                     }
-                },
-                ProcessorEvent::ReceivedParaChainState(pairs) => {
-                    self.storage.0.load(pairs.into_iter());
-                    debug!("Applied new full set for processor chain state cache.");
-                },
-                ProcessorEvent::ReceivedParaStorageChanges(changes) => {
-                    let (state_root, transaction) = self.storage.0.calc_root_if_changes(
-                        &changes.main_storage_changes,
-                        &changes.child_storage_changes,
-                    );
-                    self.storage.0.apply_changes(state_root, transaction);
-                    debug!("Applied delta set for processor chain state cache.");
-                },
-            }
-            let cost = start_time.elapsed().as_micros();
-            debug!("measuring {event_display} cost {cost} microseconds.");
+                    self.send_worker_status(worker);
+                }
+            },
+            ProcessorEvent::ReceivedParaChainState(pairs) => {
+                self.storage.0.load(pairs.into_iter());
+                debug!("Applied new full set for processor chain state cache.");
+            },
+            ProcessorEvent::ReceivedParaStorageChanges(changes) => {
+                let (state_root, transaction) = self.storage.0.calc_root_if_changes(
+                    &changes.main_storage_changes,
+                    &changes.child_storage_changes,
+                );
+                self.storage.0.apply_changes(state_root, transaction);
+                debug!("Applied delta set for processor chain state cache.");
+                for (subject, event) in WorkerChainEvent::pick(self.storage.events()) {
+                    self.handle_chain_event(workers, subject, event);
+                }
+            },
         }
+        let cost = start_time.elapsed().as_micros();
+        debug!("measuring {event_display} cost {cost} microseconds.");
     }
 
     fn handle_worker_event(
@@ -635,6 +922,7 @@ impl Processor {
                         updated_worker,
                         worker.pool_sync_only,
                         worker.operator.clone(),
+                        crate::pruntime::recreate_client(&worker.client),
                     ));
                 } else {
                     worker.worker_status.worker = updated_worker;
@@ -720,6 +1008,11 @@ impl Processor {
                 tokio::spawn(get_load_state_request(
                     self.bus.clone(),
                     self.dsm.clone(),
+                    self.cloned_chain_state.clone(),
+                    self.clone_donor
+                        .as_ref()
+                        .filter(|(id, _)| *id != worker.uuid)
+                        .map(|(_, client)| client.clone()),
                     worker.uuid.clone(),
                     worker.public_key().unwrap(),
                     // Cannot register before dispatching at least one block.
@@ -728,6 +1021,18 @@ impl Processor {
                 ));
                 self.update_worker_message(worker, "LoadChainState Starting...", None);
             },
+            WorkerEvent::FastSyncFailed(err_msg) => {
+                worker.fast_sync_failed = true;
+                let message = format!("Fast sync failed, fallback to sync all the blocks. {}", err_msg);
+                warn!("[{}] {}", worker.uuid, message);
+                self.update_worker_state_and_message(
+                    worker,
+                    WorkerLifecycleState::Synchronizing,
+                    &message,
+                    None,
+                );
+                self.add_pruntime_request(worker, PRuntimeRequest::PrepareLifecycle);
+            },
             WorkerEvent::RepositoryPreloadRequest(_) => todo!(),
             WorkerEvent::RepositorySyncRequest(_) => todo!(),
             WorkerEvent::WorkerLifecycleCommand(command) => {
@@ -748,8 +1053,209 @@ impl Processor {
                     Some(timestamp),
                 );
             },
+            WorkerEvent::Recover => {
+                worker.lifecycle.recovery_pending = false;
+                if !worker.stopped && matches!(worker.worker_status.state, WorkerLifecycleState::HasError(_)) {
+                    info!(
+                        "[{}] Recovering from error, attempt {}",
+                        worker.uuid,
+                        worker.lifecycle.recovery_attempts,
+                    );
+                    self.handle_worker_lifecycle_command(worker, WorkerLifecycleCommand::ShouldRestart);
+                }
+            },
+            WorkerEvent::SyncFailed((position, err_msg)) => {
+                self.handle_sync_failed(worker, position, &err_msg);
+            },
+            WorkerEvent::ResumeSync => {
+                let Some(state) = worker.sync_failures.paused_from.take() else {
+                    return;
+                };
+                if worker.stopped || matches!(worker.worker_status.state, WorkerLifecycleState::HasError(_)) {
+                    return;
+                }
+                self.update_worker_state_and_message(worker, state, "Pause is over, retrying the sync.", None);
+                self.request_next_sync(worker);
+            },
+        }
+
+    }
+
+    /// Moves the worker to `state`, the only place the lifecycle state is changed.
+    ///
+    /// A transition not allowed by [`WorkerLifecycleState::can_transition_to`] is rejected and
+    /// the state is left unchanged. Entering an error schedules a restart with backoff, unless
+    /// the worker has been stopped.
+    fn set_worker_state(
+        &mut self,
+        worker: &mut WorkerContext,
+        state: WorkerLifecycleState,
+    ) -> Option<StateTransition> {
+        let current = &worker.worker_status.state;
+        if !current.can_transition_to(&state) {
+            warn!("[{}] Rejected lifecycle state transition {:?} -> {:?}", worker.uuid, current, state);
+            return None;
+        }
+        let transition = (std::mem::discriminant(current) != std::mem::discriminant(&state))
+            .then(|| StateTransition {
+                from: current.clone(),
+                to: state.clone(),
+                at: Utc::now(),
+            });
+        let has_error = matches!(state, WorkerLifecycleState::HasError(_));
+        worker.worker_status.state = state;
+        if let Some(transition) = &transition {
+            debug!("[{}] Lifecycle state {:?} -> {:?}", worker.uuid, transition.from, transition.to);
+            push_transition(&mut worker.worker_status.transitions, transition.clone());
+            if has_error {
+                self.alert_worker_error(worker);
+                self.schedule_recovery(worker);
+            }
+        }
+        transition
+    }
+
+    fn alert_worker_error(
+        &self,
+        worker: &WorkerContext,
+    ) {
+        let WorkerLifecycleState::HasError(message) = &worker.worker_status.state else {
+            return;
+        };
+        let registering = matches!(
+            worker.compute_management_context.as_ref().map(|c| &c.stage),
+            Some(ComputeManagementStage::Register)
+        );
+        let kind = if registering && !worker.is_registered() {
+            AlertKind::RegistrationFailed
+        } else {
+            AlertKind::WorkerError
+        };
+        self.send_alert(worker, kind, message.clone());
+    }
+
+    /// Alerts once a worker syncing behind the chaintip makes no progress for the stall timeout.
+    fn check_sync_stall(
+        &mut self,
+        worker: &mut WorkerContext,
+    ) {
+        let Some(timeout) = self.sync_stall_timeout else {
+            return;
+        };
+        let now = Utc::now();
+        let progress = (worker.headernum, worker.para_headernum, worker.blocknum);
+        let syncing = matches!(
+            worker.worker_status.state,
+            WorkerLifecycleState::Synchronizing
+                | WorkerLifecycleState::Preparing
+                | WorkerLifecycleState::Working
+                | WorkerLifecycleState::GatekeeperWorking
+        );
+        if progress != worker.sync_progress
+            || !syncing
+            || worker.stopped
+            || worker.sync_paused
+            || worker.is_reached_chaintip(&self.chaintip)
+        {
+            worker.sync_progress = progress;
+            worker.sync_progressed_at = now;
+            worker.sync_stall_alerted = false;
+            return;
+        }
+        if worker.sync_stall_alerted || now - worker.sync_progressed_at < timeout {
+            return;
+        }
+        worker.sync_stall_alerted = true;
+        let message = format!(
+            "No sync progress for {} seconds, at relaychain #{}, parachain #{}, block #{}, chaintip at relaychain #{}, parachain #{}",
+            (now - worker.sync_progressed_at).num_seconds(),
+            worker.headernum,
+            worker.para_headernum,
+            worker.blocknum,
+            self.chaintip.relaychain,
+            self.chaintip.parachain,
+        );
+        self.send_alert(worker, AlertKind::SyncStalled, message);
+    }
+
+    /// Reacts to an on-chain event about one of the workers.
+    fn handle_chain_event(
+        &mut self,
+        workers: &mut HashMap<String, WorkerContext>,
+        subject: Subject,
+        event: WorkerChainEvent,
+    ) {
+        let worker = workers.values_mut().find(|worker| match &subject {
+            Subject::Session(session) => worker.session_id.as_ref() == Some(session),
+            Subject::Worker(public_key) => worker.public_key().as_ref() == Some(public_key),
+        });
+        let Some(worker) = worker else {
+            return;
+        };
+        match event {
+            WorkerChainEvent::EnterUnresponsive => {
+                let message = "Entered the unresponsive state on chain".to_string();
+                self.update_worker_message(worker, &message, None);
+                self.send_alert(worker, AlertKind::WorkerUnresponsive, message);
+                match self.on_unresponsive {
+                    UnresponsiveAction::Alert => (),
+                    UnresponsiveAction::Restart => {
+                        self.handle_worker_lifecycle_command(worker, WorkerLifecycleCommand::ShouldRestart);
+                    },
+                    UnresponsiveAction::Register => {
+                        self.handle_worker_lifecycle_command(worker, WorkerLifecycleCommand::ShouldForceRegister);
+                    },
+                }
+            },
+            WorkerChainEvent::ExitUnresponsive => {
+                self.update_worker_message(worker, "Exited the unresponsive state on chain", None);
+            },
+            WorkerChainEvent::Reclaimed { original_stake, slashed } => {
+                if slashed > 0 {
+                    let message = format!("Reclaimed on chain with {slashed} of the stake {original_stake} slashed");
+                    self.update_worker_message(worker, &message, None);
+                    self.send_alert(worker, AlertKind::WorkerSlashed, message);
+                }
+            },
+            WorkerChainEvent::SessionUnbound => {
+                let message = "Unbound from its session on chain".to_string();
+                self.update_worker_message(worker, &message, None);
+                self.send_alert(worker, AlertKind::SessionUnbound, message);
+            },
         }
+    }
+
+    fn send_alert(
+        &self,
+        worker: &WorkerContext,
+        kind: AlertKind,
+        message: String,
+    ) {
+        let _ = self.bus.send_alert(Alert {
+            kind,
+            worker_id: worker.uuid.clone(),
+            worker_name: worker.worker_status.worker.name.clone(),
+            message,
+            at: Utc::now(),
+        });
+    }
 
+    fn schedule_recovery(
+        &mut self,
+        worker: &mut WorkerContext,
+    ) {
+        if worker.stopped || worker.lifecycle.recovery_pending {
+            return;
+        }
+        let delay = worker.lifecycle.next_recovery_delay(Utc::now());
+        worker.lifecycle.recovery_pending = true;
+        info!(
+            "[{}] Scheduled recovery attempt {} in {} seconds",
+            worker.uuid,
+            worker.lifecycle.recovery_attempts,
+            delay.num_seconds(),
+        );
+        tokio::spawn(do_recover(self.bus.clone(), worker.uuid.clone(), delay));
     }
 
     fn update_worker_state(
@@ -757,12 +1263,13 @@ impl Processor {
         worker: &mut WorkerContext,
         state: WorkerLifecycleState,
     ) {
-        worker.worker_status.state = state;
+        let transition = self.set_worker_state(worker, state);
         let _ = self.bus.send_worker_status_event((
             worker.uuid.clone(),
             WorkerStatusUpdate::UpdateStateAndMessage((
                 worker.worker_status.state.clone(),
                 worker.worker_status.last_message.clone(),
+                transition,
             )),
         ));
     }
@@ -775,13 +1282,14 @@ impl Processor {
         updated_at: Option<DateTime<Utc>>,
     ) {
         info!("[{}] WORKER_MESSAGE: {}", worker.uuid, message);
-        worker.worker_status.state = state;
+        let transition = self.set_worker_state(worker, state);
         worker.update_message(message, updated_at);
         let _ = self.bus.send_worker_status_event((
             worker.uuid.clone(),
             WorkerStatusUpdate::UpdateStateAndMessage((
                 worker.worker_status.state.clone(),
                 worker.worker_status.last_message.clone(),
+                transition,
             )),
         ));
     }
@@ -918,12 +1426,20 @@ impl Processor {
         trace!("[{}] Received OK {}", worker.uuid, response);
         match response {
             PRuntimeResponse::PrepareLifecycle(info) => {
+                info!(
+                    "[{}] pRuntime {} ({}), capabilities: {:?}",
+                    worker.uuid,
+                    info.version,
+                    info.git_revision,
+                    info.capabilities,
+                );
                 worker.worker_status.phactory_info = Some(info.clone());
                 self.send_worker_status(worker);
 
                 worker.headernum = info.headernum;
                 worker.para_headernum = info.para_headernum;
                 worker.blocknum = info.blocknum;
+                self.update_clone_donor(worker);
 
                 self.request_prepare_lifecycle(worker);
             },
@@ -936,6 +1452,7 @@ impl Processor {
                 self.add_pruntime_request(worker, PRuntimeRequest::PrepareLifecycle);
             },
             PRuntimeResponse::Sync(info) => {
+                worker.sync_failures.reset();
                 self.handle_pruntime_sync_response(worker, &info);
                 self.send_worker_sync_info(worker);
             },
@@ -954,14 +1471,25 @@ impl Processor {
                         phactory_info.para_headernum,
                         phactory_info.blocknum,
                     );
+                    // Stopped before marking the error, so that no recovery is scheduled.
+                    worker.stopped = true;
                     self.update_worker_state(
                         worker,
                         WorkerLifecycleState::HasError("Need Restart Manually! Worker Info is not matching prb internal status.".into())
                     );
-                    worker.stopped = true;
                 }
                 worker.worker_status.phactory_info = Some(phactory_info);
                 self.send_worker_status(worker);
+                self.update_clone_donor(worker);
+                if worker.sync_paused && worker.sync_pause_reason().is_none() {
+                    self.update_worker_state_and_message(
+                        worker,
+                        WorkerLifecycleState::Synchronizing,
+                        "Sync resumed.",
+                        None,
+                    );
+                    self.request_next_sync(worker);
+                }
             },
             PRuntimeResponse::PrepareRegister(response) => {
                 self.update_worker_message(worker, "Register Starting...", None);
@@ -971,8 +1499,7 @@ impl Processor {
                     worker.uuid.clone(),
                     worker.pool_id,
                     response,
-                    self.pccs_url.clone(),
-                    self.pccs_timeout_secs,
+                    self.collateral_fetcher.clone(),
                 ));
             },
             PRuntimeResponse::GetEgressMessages(response) => {
@@ -1012,13 +1539,17 @@ impl Processor {
             trace!("[{}] Synced para_headernum, next: {}", worker.uuid, worker.para_headernum);
         }
         if let Some(blocknum) = info.blocknum {
+            if let Some(elapsed) = info.dispatch_elapsed {
+                let dispatched = (blocknum + 1).saturating_sub(worker.blocknum);
+                self.update_block_batch_size(worker, dispatched, elapsed);
+            }
             worker.blocknum = blocknum + 1;
             trace!("[{}] Synced updated, next: {}", worker.uuid, worker.blocknum);
         }
 
         if worker.is_registered() && info.blocknum.is_some() && worker.para_headernum == worker.blocknum {
             trace!("[{}] Dispatched a block, requesting EgressMessages", worker.uuid);
-            self.add_pruntime_request(worker, PRuntimeRequest::GetEgressMessages);
+            self.add_pruntime_request(worker, PRuntimeRequest::GetEgressMessages(None));
         }
 
         if !worker.is_reached_chaintip(&self.chaintip) {
@@ -1034,6 +1565,24 @@ impl Processor {
         }
     }
 
+    fn update_clone_donor(&mut self, worker: &WorkerContext) {
+        if !self.allow_fast_sync || !self.cloned_chain_state.is_enabled() {
+            return;
+        }
+        let is_donor = self.clone_donor.as_ref().is_some_and(|(id, _)| *id == worker.uuid);
+        let is_eligible = !worker.stopped
+            && !worker.is_registered()
+            && worker.blocknum > 1
+            && worker.blocknum + CLONE_DONOR_MAX_LAG > self.chaintip.parachain;
+        if is_eligible && self.clone_donor.is_none() {
+            info!("[{}] Worker is chosen as the donor of the cloned chain state.", worker.uuid);
+            self.clone_donor = Some((worker.uuid.clone(), worker.client.clone()));
+        } else if !is_eligible && is_donor {
+            info!("[{}] Worker is no longer the donor of the cloned chain state.", worker.uuid);
+            self.clone_donor = None;
+        }
+    }
+
     fn request_prepare_lifecycle(
         &mut self,
         worker: &mut WorkerContext,
@@ -1052,7 +1601,11 @@ impl Processor {
         }
 
         trace!("[{}] checking worker fast sync", worker.uuid);
-        if self.allow_fast_sync && info.can_load_chain_state && self.chaintip.parachain > 1 {
+        if self.allow_fast_sync
+            && !worker.fast_sync_failed
+            && info.can_load_chain_state
+            && self.chaintip.parachain > 1
+        {
             let _ = self.bus.send_worker_event(
                 worker.uuid.clone(),
                 WorkerEvent::RepositoryLoadStateRequest
@@ -1062,23 +1615,24 @@ impl Processor {
 
         if worker.is_reached_chaintip(&self.chaintip) {
             worker.pending_broadcast = true;
-            trace!("[{}] Already at chaintip. Requesting compute management.", worker.uuid);
-            self.request_compute_management(worker);
+            // Preparing first, the compute management moves the worker on to Working.
             self.update_worker_state_and_message(
                 worker,
                 WorkerLifecycleState::Preparing,
                 "Start Preparing...",
                 None,
             );
+            trace!("[{}] Already at chaintip. Requesting compute management.", worker.uuid);
+            self.request_compute_management(worker);
         } else {
-            trace!("[{}] requesting next sync", worker.uuid);
-            self.request_next_sync(worker);
             self.update_worker_state_and_message(
                 worker,
                 WorkerLifecycleState::Synchronizing,
                 "Start Synchronizing...",
                 None,
             );
+            trace!("[{}] requesting next sync", worker.uuid);
+            self.request_next_sync(worker);
         }
     }
 
@@ -1109,35 +1663,145 @@ impl Processor {
         self.add_pruntime_request(worker, PRuntimeRequest::InitRuntime(request));
     }
 
+    /// Grows or shrinks the number of blocks per sync request of the worker, to keep the rolling
+    /// dispatch latency around `block_batch_target_ms`.
+    fn update_block_batch_size(
+        &self,
+        worker: &mut WorkerContext,
+        dispatched: u32,
+        elapsed: std::time::Duration,
+    ) {
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+        let latency = match worker.dispatch_latency_ms {
+            Some(latency) => latency * 0.8 + elapsed_ms * 0.2,
+            None => elapsed_ms,
+        };
+        worker.dispatch_latency_ms = Some(latency);
+
+        let current = worker.block_batch_size;
+        let next = if latency > self.block_batch_target_ms {
+            (current / 2).max(self.min_block_batch_size)
+        } else if latency < self.block_batch_target_ms / 2.0 && dispatched >= current {
+            current.saturating_mul(2).min(self.max_block_batch_size)
+        } else {
+            current
+        };
+        if next != current {
+            debug!(
+                "[{}] Block batch size {} -> {}, dispatch latency {:.0}ms",
+                worker.uuid, current, next, latency,
+            );
+            worker.block_batch_size = next;
+            // Measure the new batch size from scratch.
+            worker.dispatch_latency_ms = None;
+        }
+    }
+
     fn request_next_sync(
         &mut self,
-        worker: &WorkerContext,
+        worker: &mut WorkerContext,
     ) {
+        if worker.sync_failures.paused_from.is_some() {
+            // Requested again once the pause is over.
+            return;
+        }
+        if let Some(reason) = worker.sync_pause_reason() {
+            if !worker.sync_paused {
+                worker.sync_paused = true;
+                self.update_worker_state_and_message(
+                    worker,
+                    WorkerLifecycleState::SyncPaused,
+                    &format!("Sync paused: {}", reason),
+                    None,
+                );
+            }
+            return;
+        }
+        worker.sync_paused = false;
+
         tokio::spawn(do_request_next_sync(
             self.bus.clone(),
             self.dsm.clone(),
             self.headers_db.clone(),
+            self.prefetcher.clone(),
             WorkerSyncInfo {
                 worker_id: worker.uuid.clone(),
                 headernum: worker.headernum,
                 para_headernum: worker.para_headernum,
                 blocknum: worker.blocknum,
+                block_batch_size: worker.block_batch_size,
+                sync_affinity: worker.worker_status.worker.sync_affinity.clone(),
+                to_block: worker.to_block,
+                split_combined_headers: !worker.syncs_combined_headers(),
             }
         ));
     }
 
+    /// Retries a failed sync, or pauses the worker for a while once it failed too many times in a
+    /// row at the same position. Without a threshold, the worker is marked as failed right away,
+    /// to go through the recovery.
+    fn handle_sync_failed(
+        &mut self,
+        worker: &mut WorkerContext,
+        position: (u32, u32, u32),
+        err_msg: &str,
+    ) {
+        if worker.stopped || position != (worker.headernum, worker.para_headernum, worker.blocknum) {
+            trace!("[{}] Ignoring the sync failure at {:?}, the worker has moved on", worker.uuid, position);
+            return;
+        }
+        let Some(threshold) = self.sync_failure_threshold else {
+            self.update_worker_state_and_message(
+                worker,
+                WorkerLifecycleState::HasError(err_msg.to_string()),
+                err_msg,
+                None,
+            );
+            return;
+        };
+        let Some(pause) = worker.sync_failures.record(position, threshold) else {
+            self.update_worker_message(worker, &format!("Sync failed, retrying. {}", err_msg), None);
+            self.request_next_sync(worker);
+            return;
+        };
+        let message = format!(
+            "Sync failed {} times in a row at relaychain #{}, parachain #{}, block #{}, paused for {} seconds. {}",
+            worker.sync_failures.count,
+            worker.headernum,
+            worker.para_headernum,
+            worker.blocknum,
+            pause.num_seconds(),
+            err_msg,
+        );
+        warn!("[{}] {}", worker.uuid, message);
+        if worker.sync_failures.paused_from.is_none() {
+            worker.sync_failures.paused_from = Some(worker.worker_status.state.clone());
+        }
+        self.update_worker_state_and_message(
+            worker,
+            WorkerLifecycleState::SyncPaused,
+            &message,
+            None,
+        );
+        tokio::spawn(do_resume_sync(self.bus.clone(), worker.uuid.clone(), pause));
+    }
+
     fn handle_pruntime_egress_messages(
         &mut self,
-        worker: &WorkerContext,
-        response: GetEgressMessagesResponse,
+        worker: &mut WorkerContext,
+        response: GetEgressMessagesPagedResponse,
     ) {
-        let messages = match response.decode_messages() {
-            Ok(messages) => messages,
-            Err(err) => {
+        let (messages, next_cursor) = match (response.decode_messages(), response.decode_next_cursor()) {
+            (Ok(messages), Ok(next_cursor)) => (messages, next_cursor),
+            (Err(err), _) | (_, Err(err)) => {
                 error!("[{}] failed to decode egress messages. {}", worker.uuid, err);
                 return;
             },
         };
+        if let Some(next_cursor) = next_cursor {
+            trace!("[{}] Requesting next page of EgressMessages from {}", worker.uuid, next_cursor.sender);
+            self.add_pruntime_request(worker, PRuntimeRequest::GetEgressMessages(Some(next_cursor)));
+        }
 
         for (sender, mut messages) in messages {
             if messages.is_empty() {
@@ -1184,6 +1848,7 @@ impl Processor {
                     worker.worker_status.worker.clone(),
                     worker.pool_sync_only,
                     worker.operator.clone(),
+                    crate::pruntime::recreate_client(&worker.client),
                 ));
             },
             WorkerLifecycleCommand::ShouldForceRegister => {
@@ -1219,9 +1884,9 @@ async fn dispatch_pruntime_request(
         &request,
         PRuntimeRequest::PrepareLifecycle
             | PRuntimeRequest::InitRuntime(_)
-            | PRuntimeRequest::LoadChainState(_)
             | PRuntimeRequest::PrepareRegister(_)
     );
+    let is_load_state = matches!(&request, PRuntimeRequest::LoadChainState(_));
     let result = match request {
         PRuntimeRequest::PrepareLifecycle => {
             client.get_info(())
@@ -1254,12 +1919,24 @@ async fn dispatch_pruntime_request(
                 .await
                 .map(PRuntimeResponse::PrepareRegister)
         },
-        PRuntimeRequest::GetEgressMessages => {
-            client.get_egress_messages(())
-                .await
-                .map(|response| {
-                    PRuntimeResponse::GetEgressMessages(response)
-                })
+        PRuntimeRequest::GetEgressMessages(cursor) => {
+            let first_page = cursor.is_none();
+            let request = GetEgressMessagesPagedRequest::new(cursor, EGRESS_PAGE_BYTES);
+            match client.get_egress_messages_paged(request).await {
+                // Fallback for the pRuntime not serving the paged messages yet.
+                Err(err) if first_page => {
+                    debug!("[{}] Failed to get paged egress messages, fallback to get all at once. {}", worker_id, err);
+                    client.get_egress_messages(())
+                        .await
+                        .map(|response| {
+                            PRuntimeResponse::GetEgressMessages(GetEgressMessagesPagedResponse {
+                                encoded_messages: response.encoded_messages,
+                                encoded_next_cursor: None,
+                            })
+                        })
+                },
+                result => result.map(PRuntimeResponse::GetEgressMessages),
+            }
         },
         PRuntimeRequest::SignEndpoints(endpoints) => {
             client.sign_endpoint_info(SignEndpointsRequest::new(endpoints))
@@ -1277,16 +1954,22 @@ async fn dispatch_pruntime_request(
         },
     };
 
+    let mut fast_sync_failed = None;
     if let Err(err) = &result {
         let msg = format!("pRuntime returned an error: {}", err);
         error!("[{}] {}", worker_id, msg);
         if is_critical {
             let _ = bus.send_worker_mark_error(worker_id.clone(), msg);
+        } else if is_load_state {
+            fast_sync_failed = Some(msg);
         } else {
             let _ = bus.send_worker_update_message(worker_id.clone(), msg);
         }
     }
     let _ = bus.send_processor_event(ProcessorEvent::WorkerEvent((worker_id.clone(), WorkerEvent::PRuntimeResponse(result))));
+    if let Some(msg) = fast_sync_failed {
+        let _ = bus.send_worker_event(worker_id.clone(), WorkerEvent::FastSyncFailed(msg));
+    }
     debug!("[{}] Completed {}. Cost {} microseconds", worker_id, request_display, start_time.elapsed().as_micros());
 }
 
@@ -1331,9 +2014,11 @@ async fn do_sync_request(
     }
 
     if let Some(blocks) = request.blocks {
+        let start_time = Instant::now();
         match client.dispatch_blocks(blocks).await {
             Ok(synced_to) => {
                 response.blocknum = Some(synced_to.synced_to);
+                response.dispatch_elapsed = Some(start_time.elapsed());
             },
             Err(err) => {
                 return Err(err);
@@ -1349,14 +2034,13 @@ async fn do_restart(
     worker: crate::inv_db::Worker,
     pool_sync_only: bool,
     operator: Option<AccountId32>,
-
+    client: PRuntimeClient,
 ) {
     let worker_id = worker.id.clone();
     let _ = bus.send_processor_event(ProcessorEvent::DeleteWorker(worker_id.clone()));
     info!("[{}] Restarting: Remove WorkerContext command sent, wait {} seconds and then add back",
         worker_id, RESTART_WORKER_COOL_PERIOD.num_seconds());
     tokio::time::sleep(RESTART_WORKER_COOL_PERIOD.to_std().unwrap()).await;
-    let client = crate::pruntime::create_client(worker.endpoint.clone());
     let _ = bus.send_processor_event(ProcessorEvent::AddWorker((
         worker,
         Some(pool_sync_only),
@@ -1367,6 +2051,24 @@ async fn do_restart(
 
 }
 
+async fn do_recover(
+    bus: Arc<Bus>,
+    worker_id: String,
+    delay: Duration,
+) {
+    tokio::time::sleep(delay.to_std().unwrap_or_default()).await;
+    let _ = bus.send_worker_event(worker_id, WorkerEvent::Recover);
+}
+
+async fn do_resume_sync(
+    bus: Arc<Bus>,
+    worker_id: String,
+    delay: Duration,
+) {
+    tokio::time::sleep(delay.to_std().unwrap_or_default()).await;
+    let _ = bus.send_worker_event(worker_id, WorkerEvent::ResumeSync);
+}
+
 async fn do_update_endpoints(
     bus: Arc<Bus>,
     txm: Arc<TxManager>,
@@ -1374,7 +2076,7 @@ async fn do_update_endpoints(
     pool_id: u64,
     response: GetEndpointResponse,
 ) {
-    let result = txm.update_worker_endpoint(pool_id, response).await;
+    let result = txm.update_worker_endpoint(pool_id, worker_id.clone(), response).await;
     match result {
         Ok(_) => {
             let _ = bus.send_worker_event(
@@ -1397,4 +2099,53 @@ async fn do_update_endpoints(
             );
         },
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phactory_api::prpc::{GatekeeperStatus, SystemInfo};
+
+    fn info(safe_mode_level: u32, role: GatekeeperRole, master_public_key: &str) -> PhactoryInfo {
+        PhactoryInfo {
+            safe_mode_level,
+            system: Some(SystemInfo {
+                gatekeeper: Some(GatekeeperStatus {
+                    role: role as i32,
+                    master_public_key: master_public_key.into(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn syncs_the_legacy_pruntimes_with_combined_headers() {
+        let info = |capabilities: &[Capability]| PhactoryInfo {
+            capabilities: capabilities.iter().map(|c| c.as_str().into()).collect(),
+            ..Default::default()
+        };
+        assert!(serves_combined_headers(&info(&[])));
+        assert!(serves_combined_headers(&info(&[Capability::CombinedHeaderSync])));
+        assert!(!serves_combined_headers(&info(&[Capability::PagedEgress])));
+    }
+
+    #[test]
+    fn pauses_only_the_gatekeepers_waiting_for_the_master_key() {
+        assert!(handover_pause_reason(&info(1, GatekeeperRole::Active, "")).is_some());
+        assert!(handover_pause_reason(&info(2, GatekeeperRole::Dummy, "")).is_some());
+        // The handover is over once the master key is received, or the safe mode is left.
+        assert_eq!(handover_pause_reason(&info(1, GatekeeperRole::Active, "0x01")), None);
+        assert_eq!(handover_pause_reason(&info(0, GatekeeperRole::Active, "")), None);
+        // The other workers are synced in safe mode as usual.
+        assert_eq!(handover_pause_reason(&info(1, GatekeeperRole::None, "")), None);
+        assert_eq!(
+            handover_pause_reason(&PhactoryInfo {
+                safe_mode_level: 1,
+                ..Default::default()
+            }),
+            None
+        );
+    }
+}
@@ -15,6 +15,8 @@ pub struct RpcRequest {
     base_url: String,
     client: Client,
     semaphore: Arc<Semaphore>,
+    /// Serves the requests instead of the pRuntime at `base_url`, e.g. a simulated pRuntime.
+    backend: Option<Arc<dyn RequestClient + Send + Sync>>,
 }
 
 #[async_trait::async_trait]
@@ -46,14 +48,39 @@ impl RpcRequest {
             base_url,
             client,
             semaphore: Arc::new(Semaphore::new(1)),
+            backend: None,
         }
     }
+
+    /// Sends the requests to `backend` instead of the pRuntime at `base_url`.
+    pub fn with_backend(mut self, backend: Arc<dyn RequestClient + Send + Sync>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
 }
 
 pub fn create_client(base_url: String) -> PRuntimeClient {
     PhactoryApiClient::new(RpcRequest::new(base_url))
 }
 
+/// Creates a client to the pRuntime served by `backend`, known as `base_url`.
+pub fn create_client_with_backend(
+    base_url: String,
+    backend: Arc<dyn RequestClient + Send + Sync>,
+) -> PRuntimeClient {
+    PhactoryApiClient::new(RpcRequest::new(base_url).with_backend(backend))
+}
+
+/// Creates a new client to the same pRuntime as `client`, through the same backend if any.
+pub fn recreate_client(client: &PRuntimeClient) -> PRuntimeClient {
+    let request = RpcRequest::new(client.client.base_url.clone());
+    let request = match &client.client.backend {
+        Some(backend) => request.with_backend(backend.clone()),
+        None => request,
+    };
+    PhactoryApiClient::new(request)
+}
+
 fn from_display(err: impl core::fmt::Display) -> ClientError {
     ClientError::RpcError(err.to_string())
 }
@@ -61,6 +88,9 @@ fn from_display(err: impl core::fmt::Display) -> ClientError {
 #[async_trait::async_trait]
 impl RequestClient for RpcRequest {
     async fn request(&self, path: &str, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        if let Some(backend) = &self.backend {
+            return backend.request(path, body).await;
+        }
         let url = format!("{}/prpc/{path}", self.base_url);
         let res = self
             .client
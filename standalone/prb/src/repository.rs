@@ -6,13 +6,16 @@ use log::{debug, error, info, trace, warn};
 use phaxt::ChainApi;
 use sp_core::sr25519::Public as Sr25519Public;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::time::sleep;
 
 use crate::bus::Bus;
 use crate::datasource::DataSourceManager;
 use crate::headers_db::*;
-use crate::processor::{PRuntimeRequest, ProcessorEvent};
+use crate::processor::{PRuntimeRequest, ProcessorEvent, WorkerEvent};
 use crate::pool_operator::DB;
+use crate::prefetch::SyncPrefetcher;
+use crate::pruntime::PRuntimeClient;
 use crate::{use_parachain_api, use_relaychain_api};
 
 use phactory_api::prpc::{Blocks, ChainState, CombinedHeadersToSync, HeadersToSync, ParaHeadersToSync};
@@ -104,7 +107,14 @@ impl SyncRequest {
             .into_iter()
             .map(|b| phactory_api::blocks::BlockHeaderWithChanges::clone(&b))
             .collect::<Vec<_>>();
-        let blocks = Blocks::new(blocks);
+        Self::create_from_encoded_blocks(Blocks::new(blocks), from, to)
+    }
+
+    pub fn create_from_encoded_blocks(
+        blocks: Blocks,
+        from: u32,
+        to: u32
+    ) -> Self {
         Self {
             blocks: Some(blocks),
             manifest: SyncRequestManifest {
@@ -115,6 +125,22 @@ impl SyncRequest {
         }
     }
 
+    /// The same request for a pRuntime not serving `SyncCombinedHeaders`, the relaychain headers
+    /// and then the parachain headers synced one after the other.
+    pub fn split_combined_headers(mut self) -> Self {
+        if let Some(combined) = self.combined_headers.take() {
+            self.headers = Some(HeadersToSync {
+                encoded_headers: combined.encoded_relaychain_headers,
+                encoded_authority_set_change: combined.authority_set_change,
+            });
+            self.para_headers = Some(ParaHeadersToSync {
+                encoded_headers: combined.encoded_parachain_headers,
+                proof: combined.proof,
+            });
+        }
+        self
+    }
+
     pub fn is_empty(&self) -> bool {
         self.headers.is_none()
             && self.para_headers.is_none()
@@ -129,6 +155,13 @@ pub struct WorkerSyncInfo {
     pub headernum: u32,
     pub para_headernum: u32,
     pub blocknum: u32,
+    pub block_batch_size: u32,
+    /// Region of the data sources to prefer
+    pub sync_affinity: Option<String>,
+    /// The parachain block to stop syncing at
+    pub to_block: Option<u32>,
+    /// Whether the pRuntime doesn't serve `SyncCombinedHeaders`
+    pub split_combined_headers: bool,
 }
 
 pub struct Repository {
@@ -348,13 +381,103 @@ impl Repository {
     }
 }
 
+/// A chain state cloned from a synced, unregistered worker.
+///
+/// The state is shared among the newly provisioned workers, so that they can skip the initial
+/// sync without loading the whole state from the parachain node one by one.
+pub struct ClonedChainState {
+    max_age: Duration,
+    cached: tokio::sync::Mutex<Option<(Instant, ChainState)>>,
+}
+
+impl ClonedChainState {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            cached: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.max_age.is_zero()
+    }
+
+    async fn get_or_clone(
+        &self,
+        dsm: &Arc<DataSourceManager>,
+        donor: &PRuntimeClient,
+    ) -> Result<ChainState> {
+        let mut cached = self.cached.lock().await;
+        if let Some((cloned_at, state)) = cached.as_ref() {
+            if cloned_at.elapsed() < self.max_age {
+                return Ok(state.clone());
+            }
+        }
+
+        let state = donor
+            .get_chain_state(())
+            .await
+            .context("Failed to get chain state from the donor worker")?;
+        let para_api = use_parachain_api!(dsm, false)
+            .ok_or_else(|| anyhow!("No valid parachain connection"))?;
+        let block_hash = para_api
+            .rpc()
+            .block_hash(Some(state.block_number.into()))
+            .await
+            .context("Failed to resolve block number")?
+            .ok_or_else(|| anyhow!("Block number {} not found", state.block_number))?;
+        pherry::chain_client::verify_storage_at(&para_api, block_hash, &state.decode_state()?)
+            .await
+            .context("The chain state of the donor worker is not trusted")?;
+        info!("Cloned chain state at #{} from the donor worker.", state.block_number);
+
+        *cached = Some((Instant::now(), state.clone()));
+        Ok(state)
+    }
+}
+
+async fn clone_chain_state_for_worker(
+    dsm: &Arc<DataSourceManager>,
+    cloned_state: &ClonedChainState,
+    donor: &PRuntimeClient,
+    public_key: &Sr25519Public,
+    prefer_number: u32,
+) -> Result<ChainState> {
+    let state = cloned_state.get_or_clone(dsm, donor).await?;
+    if state.block_number > prefer_number {
+        return Err(anyhow!("Cloned chain state at #{} is too new", state.block_number));
+    }
+    let para_api = use_parachain_api!(dsm, false)
+        .ok_or_else(|| anyhow!("No valid parachain connection"))?;
+    if let Some(added_at) = para_api.worker_added_at(public_key.as_ref()).await? {
+        if added_at <= state.block_number {
+            return Err(anyhow!("Worker was registered at #{added_at}"));
+        }
+    }
+    Ok(state)
+}
+
 pub async fn get_load_state_request(
     bus: Arc<Bus>,
     dsm: Arc<DataSourceManager>,
+    cloned_state: Arc<ClonedChainState>,
+    donor: Option<Arc<PRuntimeClient>>,
     worker_id: String,
     public_key: Sr25519Public,
     prefer_number: u32,
 ) {
+    if let Some(donor) = donor {
+        match clone_chain_state_for_worker(&dsm, &cloned_state, &donor, &public_key, prefer_number).await {
+            Ok(request) => {
+                let _ = bus.send_pruntime_request(worker_id, PRuntimeRequest::LoadChainState(request));
+                return;
+            },
+            Err(err) => {
+                warn!("[{}] Failed to use cloned chain state, fallback to load from chain. {:#}", worker_id, err);
+            },
+        }
+    }
+
     let para_api = match use_parachain_api!(dsm, true) {
         Some(api) => api,
         None => {
@@ -368,7 +491,7 @@ pub async fn get_load_state_request(
             let _ = bus.send_pruntime_request(worker_id, PRuntimeRequest::LoadChainState(request));
         },
         Err(err) => {
-            let _ = bus.send_worker_mark_error(worker_id, err.to_string());
+            let _ = bus.send_worker_event(worker_id, WorkerEvent::FastSyncFailed(format!("{err:#}")));
         },
     }
 }
@@ -377,21 +500,26 @@ pub async fn do_request_next_sync(
     bus: Arc<Bus>,
     dsm: Arc<DataSourceManager>,
     headers_db: Arc<DB>,
+    prefetcher: Arc<SyncPrefetcher>,
     info: WorkerSyncInfo,
 ) {
     trace!("[{}] Received next sync. {}-{}-{}", info.worker_id, info.headernum, info.para_headernum, info.blocknum);
+    prefetcher.report(&info);
     let mut try_count = 0;
     let request = loop {
-        match generate_sync_request(dsm.clone(), headers_db.clone(), info.clone()).await {
+        match generate_sync_request(dsm.clone(), headers_db.clone(), &prefetcher, info.clone()).await {
             Ok(request) => break request,
             Err(err) => {
                 try_count += 1;
-                let err_msg = format!("Fail to generate_sync_request for {} times. Retrying... Last Error: {}", try_count, err);
+                let err_msg = format!("Fail to generate_sync_request for {} times. Last Error: {}", try_count, err);
                 error!("[{}] {}", info.worker_id, err_msg);
+                tokio::time::sleep(std::time::Duration::from_secs(6)).await;
                 if try_count >= 3 {
-                    let _ = bus.send_worker_mark_error(info.worker_id.clone(), err_msg);
+                    // The processor decides whether to retry or to pause the worker.
+                    let position = (info.headernum, info.para_headernum, info.blocknum);
+                    let _ = bus.send_worker_event(info.worker_id.clone(), WorkerEvent::SyncFailed((position, err_msg)));
+                    return;
                 }
-                tokio::time::sleep(std::time::Duration::from_secs(6)).await;
             },
         }
     };
@@ -400,14 +528,20 @@ pub async fn do_request_next_sync(
     } else {
         trace!("[{}] sending empty sync request.", info.worker_id);
     }
+    let request = if info.split_combined_headers {
+        request.split_combined_headers()
+    } else {
+        request
+    };
     let manifest = request.manifest.clone();
     let _ = bus.send_pruntime_request(info.worker_id.clone(), PRuntimeRequest::Sync(request));
-    preload_next_sync(dsm, headers_db, info, &manifest).await;
+    preload_next_sync(dsm, headers_db, &prefetcher, info, &manifest).await;
 }
 
 async fn preload_next_sync(
     dsm: Arc<DataSourceManager>,
     headers_db: Arc<DB>,
+    prefetcher: &SyncPrefetcher,
     info: WorkerSyncInfo,
     manifest: &SyncRequestManifest,
 ) {
@@ -421,28 +555,43 @@ async fn preload_next_sync(
     if let Some((_, to)) = &manifest.blocks {
         next_info.blocknum = to + 1;
     }
-    let _ = generate_sync_request(dsm, headers_db.clone(), next_info).await;
+    let _ = generate_sync_request(dsm, headers_db.clone(), prefetcher, next_info).await;
 }
 
 async fn generate_sync_request(
     dsm: Arc<DataSourceManager>,
     headers_db: Arc<DB>,
+    prefetcher: &SyncPrefetcher,
     info: WorkerSyncInfo,
 ) -> Result<SyncRequest> {
     if info.blocknum < info.para_headernum {
         trace!("[{}] Requesting blocks, # {} < {}", info.worker_id, info.blocknum, info.para_headernum);
-        let to = std::cmp::min((info.blocknum + 3) / 4 * 4, info.para_headernum - 1);
+        // Align the batches to share the cached storage changes among workers.
+        let batch = info.block_batch_size.max(1);
+        let mut to = std::cmp::min(
+            (info.blocknum + batch - 1) / batch * batch,
+            info.para_headernum - 1,
+        );
+        if let Some(to_block) = info.to_block {
+            // Not beyond the block before the target, so the worker lands right on it.
+            if to_block > info.blocknum {
+                to = to.min(to_block - 1);
+            }
+        }
+        if let Some(blocks) = prefetcher.get_blocks(info.blocknum, to) {
+            return Ok(SyncRequest::create_from_encoded_blocks(blocks, info.blocknum, to));
+        }
         return dsm
-            .fetch_storage_changes(info.blocknum, to)
+            .fetch_storage_changes(info.blocknum, to, info.sync_affinity.clone())
             .await
             .map(|blocks| SyncRequest::create_from_blocks(blocks, info.blocknum, to));
     }
 
-    if let Some((para_headernum, proof)) = get_para_headernum(dsm.clone(), info.headernum - 1).await.unwrap_or(None) {
+    if let Some((para_headernum, proof)) = get_para_headernum(dsm.clone(), info.headernum - 1, info.sync_affinity.clone()).await.unwrap_or(None) {
         if para_headernum > 0 && info.para_headernum <= para_headernum {
             trace!("[{}] Requesting para headers, # {} to {}", info.worker_id, info.para_headernum, para_headernum);
             return dsm
-                .get_para_headers(info.para_headernum, para_headernum)
+                .get_para_headers(info.para_headernum, para_headernum, info.sync_affinity.clone())
                 .await
                 .map(|headers| {
                     SyncRequest::create_from_para_headers(
@@ -458,14 +607,12 @@ async fn generate_sync_request(
         warn!("Failed to get para headernum at {}", info.headernum - 1);
     }
 
+    if let Some((headers, to)) = prefetcher.get_headers(info.headernum) {
+        return Ok(SyncRequest::create_from_headers(headers, info.headernum, to));
+    }
     trace!("[{}] Getting from headers_db: {}", info.worker_id, info.headernum);
     if let Some(headers) = get_current_point(headers_db, info.headernum) {
-        let headers = headers
-            .into_iter()
-            .filter(|header| header.header.number >= info.headernum)
-            .collect::<Vec<_>>();
-        if let Some(last_header) = headers.last() {
-            let to = last_header.header.number;
+        if let Some((headers, to)) = justified_span(&headers, info.headernum) {
             let headers = phactory_api::prpc::HeadersToSync::new(headers, None);
             return Ok(SyncRequest::create_from_headers(headers, info.headernum, to));
         }
@@ -478,8 +625,9 @@ async fn generate_sync_request(
 async fn get_para_headernum(
     dsm: Arc<DataSourceManager>,
     relay_headernum: u32,
+    region: Option<String>,
 ) -> Result<Option<(u32, Vec<Vec<u8>>)>> {
-    dsm.get_para_header_by_relay_header(relay_headernum).await
+    dsm.get_para_header_by_relay_header(relay_headernum, region).await
 }
 
 async fn prepare_and_broadcast(
@@ -494,22 +642,18 @@ async fn prepare_and_broadcast(
     let para_api = use_parachain_api!(dsm, false).expect("should have parachain api");
 
     let relay_from = prev_relaychain_finalized_at + 1;
-    let headers = get_current_point(headers_db.clone(), relay_from)
-        .expect("should already put headers into db")
-        .into_iter()
-        .filter(|h| prev_relaychain_finalized_at < h.header.number)
-        .collect::<Vec<_>>();
-
     let relay_to = curr_relaychain_finalized_at;
-    if headers.first().expect("should have headers").header.number != relay_from {
-        return Err(anyhow!("first header from DB is not match the prev one"));
-    }
-    if headers.last().expect("should have headers").header.number != relay_to {
-        return Err(anyhow!("first header from DB is not match the prev one"));
+    let headers = get_current_point(headers_db.clone(), relay_from)
+        .ok_or_else(|| anyhow!("No headers from #{relay_from} in DB"))?;
+    // Only the span up to the justification advances the finality of the workers.
+    let (headers, span_to) = justified_span(&headers, relay_from)
+        .ok_or_else(|| anyhow!("No justified span from #{relay_from} in DB"))?;
+    if span_to != relay_to {
+        return Err(anyhow!("Justified span from #{relay_from} ends at #{span_to}, not at the finalized #{relay_to}"));
     }
     let relay_to_hash = headers.last().unwrap().header.hash();
 
-    let (para_prev, _) = get_para_headernum(dsm.clone(), prev_relaychain_finalized_at).await?
+    let (para_prev, _) = get_para_headernum(dsm.clone(), prev_relaychain_finalized_at, None).await?
         .unwrap_or_else(|| panic!("Unknown para header for relay #{prev_relaychain_finalized_at}"));
     let (para_header, proof) = pherry::get_finalized_header_with_paraid(&relay_api, para_id, relay_to_hash)
         .await?
@@ -529,7 +673,13 @@ async fn prepare_and_broadcast(
             relay_to
         )
     } else {
-        let para_headers = pherry::get_parachain_headers(&para_api, None, para_from, para_to).await?;
+        let para_headers = pherry::get_parachain_headers(
+            &para_api,
+            None,
+            para_from,
+            para_to,
+            pherry::DEFAULT_PARA_HEADER_CONCURRENCY,
+        ).await?;
         info!("Broadcasting header: relaychain from {} to {}, parachain from {} to {}.",
             relay_from, relay_to, para_from, para_to);
         let headers = CombinedHeadersToSync::new(
@@ -559,7 +709,7 @@ async fn send_storage_changes(
     full_dispatched: bool,
 ) -> Result<()> {
     if full_dispatched {
-        let changes = dsm.fetch_storage_changes(from, to).await?
+        let changes = dsm.fetch_storage_changes(from, to, None).await?
             .into_iter()
             .map(|b| b.storage_changes.clone())
             .collect::<Vec<_>>();
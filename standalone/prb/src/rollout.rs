@@ -0,0 +1,234 @@
+use crate::api::ApiError;
+use crate::inv_db::{get_workers_by_group, Worker};
+use crate::processor::WorkerEvent;
+use crate::wm::WrappedWorkerManagerContext;
+use crate::worker::{WorkerLifecycleCommand, WorkerLifecycleState};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RolloutOperation {
+    Restart,
+    ForceRegister,
+    UpdateEndpoints(Vec<String>),
+}
+
+impl RolloutOperation {
+    fn command(&self) -> WorkerLifecycleCommand {
+        match self {
+            RolloutOperation::Restart => WorkerLifecycleCommand::ShouldRestart,
+            RolloutOperation::ForceRegister => WorkerLifecycleCommand::ShouldForceRegister,
+            RolloutOperation::UpdateEndpoints(endpoints) => {
+                WorkerLifecycleCommand::ShouldUpdateEndpoint(endpoints.clone())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RolloutPolicy {
+    /// Percentage of the workers in the group to operate on in each wave
+    pub wave_percent: u8,
+    /// Seconds to wait after issuing a wave before checking its health, to let the workers leave
+    /// their previous state
+    pub settle_secs: u64,
+    /// Seconds for the workers of a wave to become healthy before the rollout is aborted
+    pub health_check_timeout_secs: u64,
+    /// Number of unhealthy workers tolerated in a wave
+    pub max_unhealthy: usize,
+}
+
+impl Default for RolloutPolicy {
+    fn default() -> Self {
+        Self {
+            wave_percent: 10,
+            settle_secs: 30,
+            health_check_timeout_secs: 600,
+            max_unhealthy: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RolloutRequest {
+    pub group: String,
+    pub operation: RolloutOperation,
+    #[serde(default)]
+    pub policy: RolloutPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RolloutState {
+    Running,
+    Succeeded,
+    Aborted(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RolloutStatus {
+    pub id: String,
+    pub request: RolloutRequest,
+    pub state: RolloutState,
+    /// Worker ids of each wave
+    pub waves: Vec<Vec<String>>,
+    pub finished_waves: usize,
+}
+
+fn is_healthy(state: &WorkerLifecycleState) -> bool {
+    matches!(
+        state,
+        WorkerLifecycleState::Synchronizing
+            | WorkerLifecycleState::Preparing
+            | WorkerLifecycleState::Working
+            | WorkerLifecycleState::GatekeeperWorking
+    )
+}
+
+/// Splits the enabled workers into waves of `wave_percent` of them, ordered by name.
+pub fn plan_waves(workers: Vec<Worker>, wave_percent: u8) -> Vec<Vec<String>> {
+    let mut workers = workers
+        .into_iter()
+        .filter(|w| w.enabled)
+        .collect::<Vec<_>>();
+    workers.sort_by(|a, b| a.name.cmp(&b.name));
+    let percent = wave_percent.clamp(1, 100) as usize;
+    let wave_size = ((workers.len() * percent + 99) / 100).max(1);
+    workers
+        .chunks(wave_size)
+        .map(|wave| wave.iter().map(|w| w.id.clone()).collect())
+        .collect()
+}
+
+pub async fn start_rollout(
+    ctx: WrappedWorkerManagerContext,
+    request: RolloutRequest,
+) -> Result<RolloutStatus, ApiError> {
+    let workers = get_workers_by_group(ctx.inv_db.clone(), &request.group)?;
+    let waves = plan_waves(workers, request.policy.wave_percent);
+    if waves.is_empty() {
+        return Err(ApiError::EmptyGroup(request.group));
+    }
+
+    let mut rollouts = ctx.rollouts.lock().await;
+    let running = rollouts
+        .values()
+        .any(|r| r.state == RolloutState::Running && r.request.group == request.group);
+    if running {
+        return Err(ApiError::RolloutConflict(request.group));
+    }
+    let status = RolloutStatus {
+        id: Uuid::new_v4().to_string(),
+        request,
+        state: RolloutState::Running,
+        waves,
+        finished_waves: 0,
+    };
+    rollouts.insert(status.id.clone(), status.clone());
+    drop(rollouts);
+
+    tokio::spawn(run_rollout(ctx, status.clone()));
+    Ok(status)
+}
+
+pub async fn abort_rollout(ctx: WrappedWorkerManagerContext, id: &str) -> Result<(), ApiError> {
+    let mut rollouts = ctx.rollouts.lock().await;
+    let status = rollouts
+        .get_mut(id)
+        .ok_or_else(|| ApiError::RolloutNotFound(id.to_string()))?;
+    if status.state == RolloutState::Running {
+        status.state = RolloutState::Aborted("aborted by user".to_string());
+    }
+    Ok(())
+}
+
+async fn run_rollout(ctx: WrappedWorkerManagerContext, status: RolloutStatus) {
+    let RolloutStatus {
+        id, request, waves, ..
+    } = status;
+    let policy = &request.policy;
+    for (index, wave) in waves.iter().enumerate() {
+        if !is_running(&ctx, &id).await {
+            info!(
+                "Rollout {id} stopped before wave {}/{}",
+                index + 1,
+                waves.len()
+            );
+            return;
+        }
+        info!(
+            "Rollout {id}: {:?} on group {}, wave {}/{} with {} workers",
+            request.operation,
+            request.group,
+            index + 1,
+            waves.len(),
+            wave.len()
+        );
+        for worker_id in wave {
+            let _ = ctx.bus.send_worker_event(
+                worker_id.clone(),
+                WorkerEvent::WorkerLifecycleCommand(request.operation.command()),
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(policy.settle_secs)).await;
+        if let Err(reason) = wait_until_healthy(&ctx, wave, policy).await {
+            warn!("Rollout {id} aborted at wave {}: {reason}", index + 1);
+            set_state(&ctx, &id, RolloutState::Aborted(reason)).await;
+            return;
+        }
+        if let Some(status) = ctx.rollouts.lock().await.get_mut(&id) {
+            status.finished_waves = index + 1;
+        }
+    }
+    info!("Rollout {id} finished");
+    set_state(&ctx, &id, RolloutState::Succeeded).await;
+}
+
+async fn wait_until_healthy(
+    ctx: &WrappedWorkerManagerContext,
+    wave: &[String],
+    policy: &RolloutPolicy,
+) -> Result<(), String> {
+    let deadline = Instant::now() + Duration::from_secs(policy.health_check_timeout_secs);
+    loop {
+        let unhealthy = {
+            let map = ctx.worker_status_map.lock().await;
+            wave.iter()
+                .filter(|id| !map.get(*id).map_or(false, |s| is_healthy(&s.state)))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if unhealthy.len() <= policy.max_unhealthy {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "{} workers unhealthy after {}s: {:?}",
+                unhealthy.len(),
+                policy.health_check_timeout_secs,
+                unhealthy
+            ));
+        }
+        tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+    }
+}
+
+async fn is_running(ctx: &WrappedWorkerManagerContext, id: &str) -> bool {
+    ctx.rollouts
+        .lock()
+        .await
+        .get(id)
+        .map_or(false, |r| r.state == RolloutState::Running)
+}
+
+async fn set_state(ctx: &WrappedWorkerManagerContext, id: &str, state: RolloutState) {
+    if let Some(status) = ctx.rollouts.lock().await.get_mut(id) {
+        if status.state == RolloutState::Running {
+            status.state = state;
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The classes of the processor events, in the order they are served.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Responses of the sync requests, which let the workers move on to their next sync.
+    SyncResponse = 0,
+    /// Sync requests to be dispatched to the workers.
+    SyncRequest = 1,
+    /// Status updates and housekeeping.
+    Other = 2,
+}
+
+const NUM_PRIORITIES: usize = 3;
+
+pub trait Prioritized {
+    fn priority(&self) -> Priority;
+}
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TickBudget {
+    /// The maximum number of events handled in a tick.
+    pub max_events: usize,
+    /// The time after which no more events are handled in a tick.
+    pub max_duration: Duration,
+}
+
+/// Schedules the processor events in ticks.
+///
+/// The events received are queued by priority. Each tick handles the queued events highest
+/// priority first until the budget of the tick runs out, and the events left over wait for the
+/// next tick, where they compete with the events received in the meantime. So a flood of status
+/// events can hold back a sync by no more than a tick. The events of the same priority are
+/// handled in the order received. The lower priorities are not starved as long as the sync events
+/// are bounded by the number of workers, each of them having one sync in flight at most.
+pub struct TickScheduler<E, C = SystemClock> {
+    queues: [VecDeque<E>; NUM_PRIORITIES],
+    budget: TickBudget,
+    clock: C,
+}
+
+/// The state of the running tick.
+pub struct Tick {
+    deadline: Instant,
+    events_left: usize,
+}
+
+impl<E: Prioritized, C: Clock> TickScheduler<E, C> {
+    pub fn new(budget: TickBudget, clock: C) -> Self {
+        Self {
+            queues: Default::default(),
+            budget,
+            clock,
+        }
+    }
+
+    pub fn push(&mut self, event: E) {
+        self.queues[event.priority() as usize].push_back(event);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|q| q.is_empty())
+    }
+
+    /// The number of queued events of each priority.
+    pub fn pending(&self) -> [usize; NUM_PRIORITIES] {
+        let mut pending = [0; NUM_PRIORITIES];
+        for (n, q) in pending.iter_mut().zip(&self.queues) {
+            *n = q.len();
+        }
+        pending
+    }
+
+    pub fn start_tick(&self) -> Tick {
+        Tick {
+            deadline: self.clock.now() + self.budget.max_duration,
+            events_left: self.budget.max_events.max(1),
+        }
+    }
+
+    /// The next event to handle in `tick`, None if the budget of the tick has run out or nothing
+    /// is queued.
+    pub fn next(&mut self, tick: &mut Tick) -> Option<E> {
+        if tick.events_left == 0 || self.clock.now() >= tick.deadline {
+            return None;
+        }
+        let event = self.queues.iter_mut().find_map(|q| q.pop_front())?;
+        tick.events_left -= 1;
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct MockClock(Rc<Cell<Instant>>);
+
+    impl MockClock {
+        fn new() -> Self {
+            Self(Rc::new(Cell::new(Instant::now())))
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Event(Priority, u32);
+
+    impl Prioritized for Event {
+        fn priority(&self) -> Priority {
+            self.0
+        }
+    }
+
+    fn scheduler(max_events: usize, clock: MockClock) -> TickScheduler<Event, MockClock> {
+        TickScheduler::new(
+            TickBudget {
+                max_events,
+                max_duration: Duration::from_millis(50),
+            },
+            clock,
+        )
+    }
+
+    #[test]
+    fn serves_higher_priorities_first_in_order_received() {
+        let mut scheduler = scheduler(10, MockClock::new());
+        scheduler.push(Event(Priority::Other, 0));
+        scheduler.push(Event(Priority::SyncRequest, 1));
+        scheduler.push(Event(Priority::Other, 2));
+        scheduler.push(Event(Priority::SyncResponse, 3));
+        scheduler.push(Event(Priority::SyncRequest, 4));
+
+        let mut tick = scheduler.start_tick();
+        let order: Vec<_> = std::iter::from_fn(|| scheduler.next(&mut tick))
+            .map(|e| e.1)
+            .collect();
+        assert_eq!(order, vec![3, 1, 4, 0, 2]);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn sync_is_not_held_back_by_status_storm() {
+        let mut scheduler = scheduler(8, MockClock::new());
+        for i in 0..100 {
+            scheduler.push(Event(Priority::Other, i));
+        }
+        let mut tick = scheduler.start_tick();
+        for _ in 0..8 {
+            assert!(scheduler.next(&mut tick).is_some());
+        }
+        assert!(scheduler.next(&mut tick).is_none());
+
+        // A sync response arriving amid the storm is handled first thing in the next tick.
+        scheduler.push(Event(Priority::SyncResponse, 1000));
+        let mut tick = scheduler.start_tick();
+        assert_eq!(
+            scheduler.next(&mut tick),
+            Some(Event(Priority::SyncResponse, 1000))
+        );
+        assert_eq!(scheduler.pending(), [0, 0, 92]);
+    }
+
+    #[test]
+    fn tick_ends_when_time_budget_runs_out() {
+        let clock = MockClock::new();
+        let mut scheduler = scheduler(100, clock.clone());
+        for i in 0..10 {
+            scheduler.push(Event(Priority::Other, i));
+        }
+        let mut tick = scheduler.start_tick();
+        assert_eq!(scheduler.next(&mut tick), Some(Event(Priority::Other, 0)));
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(scheduler.next(&mut tick), Some(Event(Priority::Other, 1)));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(scheduler.next(&mut tick), None);
+
+        let mut tick = scheduler.start_tick();
+        assert_eq!(scheduler.next(&mut tick), Some(Event(Priority::Other, 2)));
+    }
+}
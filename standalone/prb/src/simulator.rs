@@ -0,0 +1,306 @@
+//! Headless fake workers, to load-test and profile the processor, the data provider and the
+//! surrounding subsystems without real enclaves.
+//!
+//! A simulated pRuntime tracks the sync progress like a real one and checks the headers and blocks
+//! arrive in order, but validates nothing and runs no contracts. The simulated workers are
+//! sync-only and not in any pool, so they never register or submit transactions on chain.
+
+use crate::inv_db::Worker;
+use crate::pruntime::{create_client_with_backend, PRuntimeClient};
+use log::info;
+use phactory_api::prpc::client::{Error as ClientError, RequestClient};
+use phactory_api::prpc::server::ProtoError;
+use phactory_api::prpc::{self, Capability, Message};
+use phactory_api::storage_sync::SyncErrorCode;
+use rand::Rng;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Prefix of the endpoints of the simulated pRuntimes, which are only names since they are
+/// served in-process.
+pub const SIMULATED_ENDPOINT_PREFIX: &str = "sim://";
+
+const SIMULATED_GROUP: &str = "simulated";
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    /// Base latency of every request
+    pub latency: Duration,
+    /// Max random latency added to the base latency
+    pub latency_jitter: Duration,
+    /// Extra latency of a DispatchBlocks request for each block dispatched
+    pub dispatch_latency_per_block: Duration,
+    /// Probability of a request to fail, between 0 and 1
+    pub failure_rate: f64,
+}
+
+#[derive(Default)]
+struct State {
+    initialized: bool,
+    headernum: u32,
+    para_headernum: u32,
+    blocknum: u32,
+}
+
+pub struct SimulatedPRuntime {
+    config: SimulationConfig,
+    state: Mutex<State>,
+}
+
+/// Creates `count` simulated workers, each with a client to its own simulated pRuntime.
+pub fn simulated_workers(count: usize, config: SimulationConfig) -> Vec<(Worker, PRuntimeClient)> {
+    info!("Simulating {count} workers with {config:?}");
+    (0..count)
+        .map(|i| {
+            let name = format!("simulated-{i}");
+            let endpoint = format!("{SIMULATED_ENDPOINT_PREFIX}{name}");
+            let client = create_client_with_backend(
+                endpoint.clone(),
+                Arc::new(SimulatedPRuntime::new(config.clone())),
+            );
+            let worker = Worker {
+                id: Uuid::new_v5(&Uuid::NAMESPACE_URL, endpoint.as_bytes()).to_string(),
+                name,
+                endpoint,
+                stake: "0".to_string(),
+                pid: None,
+                enabled: true,
+                sync_only: true,
+                gatekeeper: false,
+                groups: vec![SIMULATED_GROUP.to_string()],
+                sync_affinity: None,
+                to_block: None,
+            };
+            (worker, client)
+        })
+        .collect()
+}
+
+fn sync_error(code: SyncErrorCode, message: impl Into<String>) -> ProtoError {
+    ProtoError::with_code(code as u32, message)
+}
+
+fn decode_error(err: impl std::fmt::Display) -> ProtoError {
+    sync_error(SyncErrorCode::InvalidRequest, err.to_string())
+}
+
+/// Advances `next` over the headers or blocks of `first..=last`, the ones before `next` are
+/// skipped as synced already.
+fn advance(next: &mut u32, first: u32, last: u32, what: &str) -> Result<u32, ProtoError> {
+    if last < *next {
+        return Err(sync_error(
+            SyncErrorCode::HeaderTooOld,
+            format!("The {what} {first}-{last} are synced already, expected {next}"),
+        ));
+    }
+    if first > *next {
+        return Err(sync_error(
+            SyncErrorCode::OutOfOrder,
+            format!("Expected {what} from {next}, got {first}"),
+        ));
+    }
+    *next = last + 1;
+    Ok(last)
+}
+
+impl SimulatedPRuntime {
+    pub fn new(config: SimulationConfig) -> Self {
+        Self {
+            config,
+            state: Default::default(),
+        }
+    }
+
+    fn handle(&self, method: &str, body: &[u8]) -> Result<Vec<u8>, ProtoError> {
+        let mut state = self.state.lock().unwrap();
+        if method != "GetInfo" && method != "InitRuntime" && !state.initialized {
+            return Err(sync_error(
+                SyncErrorCode::NotInitialized,
+                "Runtime not initialized",
+            ));
+        }
+        let response = match method {
+            "GetInfo" => prpc::PhactoryInfo {
+                initialized: state.initialized,
+                headernum: state.headernum,
+                para_headernum: state.para_headernum,
+                blocknum: state.blocknum,
+                version: "simulated".to_string(),
+                capabilities: [Capability::CombinedHeaderSync, Capability::TypedSyncErrors]
+                    .iter()
+                    .map(|capability| capability.as_str().to_string())
+                    .collect(),
+                ..Default::default()
+            }
+            .encode_to_vec(),
+            "InitRuntime" => {
+                let request = prpc::InitRuntimeRequest::decode(body).map_err(decode_error)?;
+                let genesis = request.decode_genesis_info().map_err(decode_error)?;
+                *state = State {
+                    initialized: true,
+                    headernum: genesis.block_header.number + 1,
+                    para_headernum: 0,
+                    blocknum: 1,
+                };
+                prpc::InitRuntimeResponse::default().encode_to_vec()
+            }
+            "SyncHeader" => {
+                let request = prpc::HeadersToSync::decode(body).map_err(decode_error)?;
+                let headers = request.decode_headers().map_err(decode_error)?;
+                let (Some(first), Some(last)) = (headers.first(), headers.last()) else {
+                    return Err(decode_error("No headers to sync"));
+                };
+                let synced_to = advance(
+                    &mut state.headernum,
+                    first.header.number,
+                    last.header.number,
+                    "headers",
+                )?;
+                prpc::SyncedTo { synced_to }.encode_to_vec()
+            }
+            "SyncParaHeader" => {
+                let request = prpc::ParaHeadersToSync::decode(body).map_err(decode_error)?;
+                let headers = request.decode_headers().map_err(decode_error)?;
+                let (Some(first), Some(last)) = (headers.first(), headers.last()) else {
+                    return Err(decode_error("No parachain headers to sync"));
+                };
+                let synced_to = advance(
+                    &mut state.para_headernum,
+                    first.number,
+                    last.number,
+                    "parachain headers",
+                )?;
+                prpc::SyncedTo { synced_to }.encode_to_vec()
+            }
+            "SyncCombinedHeaders" => {
+                let request = prpc::CombinedHeadersToSync::decode(body).map_err(decode_error)?;
+                let headers = request.decode_relaychain_headers().map_err(decode_error)?;
+                let para_headers = request.decode_parachain_headers().map_err(decode_error)?;
+                let relaychain_synced_to = match (headers.first(), headers.last()) {
+                    (Some(first), Some(last)) => advance(
+                        &mut state.headernum,
+                        first.header.number,
+                        last.header.number,
+                        "headers",
+                    )?,
+                    _ => state.headernum - 1,
+                };
+                let parachain_synced_to = match (para_headers.first(), para_headers.last()) {
+                    (Some(first), Some(last)) => advance(
+                        &mut state.para_headernum,
+                        first.number,
+                        last.number,
+                        "parachain headers",
+                    )?,
+                    _ => state.para_headernum.saturating_sub(1),
+                };
+                prpc::HeadersSyncedTo {
+                    relaychain_synced_to,
+                    parachain_synced_to,
+                }
+                .encode_to_vec()
+            }
+            "DispatchBlocks" => {
+                let request = prpc::Blocks::decode(body).map_err(decode_error)?;
+                let blocks = request.decode_blocks().map_err(decode_error)?;
+                let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+                    return Err(decode_error("No blocks to dispatch"));
+                };
+                let last = last.block_header.number;
+                if last >= state.para_headernum {
+                    return Err(sync_error(
+                        SyncErrorCode::OutOfOrder,
+                        format!("Header of block {last} not synced"),
+                    ));
+                }
+                let first = first.block_header.number;
+                let synced_to = advance(&mut state.blocknum, first, last, "blocks")?;
+                prpc::SyncedTo { synced_to }.encode_to_vec()
+            }
+            "TakeCheckpoint" => prpc::SyncedTo {
+                synced_to: state.blocknum - 1,
+            }
+            .encode_to_vec(),
+            _ => return Err(ProtoError::new(format!("{method} is not simulated"))),
+        };
+        Ok(response)
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestClient for SimulatedPRuntime {
+    async fn request(&self, path: &str, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        let method = path.trim_start_matches("PhactoryAPI.");
+        let mut latency = self.config.latency;
+        if !self.config.latency_jitter.is_zero() {
+            latency += rand::thread_rng().gen_range(Duration::ZERO..=self.config.latency_jitter);
+        }
+        if method == "DispatchBlocks" {
+            let blocks = prpc::Blocks::decode(&body[..])
+                .ok()
+                .and_then(|request| request.decode_blocks().ok())
+                .map_or(0, |blocks| blocks.len() as u32);
+            latency += self.config.dispatch_latency_per_block * blocks;
+        }
+        tokio::time::sleep(latency).await;
+
+        if rand::thread_rng().gen_bool(self.config.failure_rate.clamp(0.0, 1.0)) {
+            return Err(ClientError::RpcError(format!(
+                "Simulated failure of {method}"
+            )));
+        }
+        self.handle(method, &body).map_err(ClientError::ServerError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pruntime::recreate_client;
+    use std::future::Future;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn config() -> SimulationConfig {
+        SimulationConfig {
+            latency: Duration::ZERO,
+            latency_jitter: Duration::ZERO,
+            dispatch_latency_per_block: Duration::ZERO,
+            failure_rate: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_simulated_workers_are_served_by_their_own_backend() {
+        let workers = simulated_workers(2, config());
+        assert_eq!(workers.len(), 2);
+        assert_ne!(workers[0].0.endpoint, workers[1].0.endpoint);
+        for (worker, client) in &workers {
+            assert!(worker.endpoint.starts_with(SIMULATED_ENDPOINT_PREFIX));
+            let info = block_on(client.get_info(())).unwrap();
+            assert_eq!(info.version, "simulated");
+            assert!(!info.initialized);
+        }
+    }
+
+    #[test]
+    fn test_recreated_client_keeps_the_backend() {
+        let (_, client) = simulated_workers(1, config()).pop().unwrap();
+        let client = recreate_client(&client);
+        let info = block_on(client.get_info(())).unwrap();
+        assert_eq!(info.version, "simulated");
+        match block_on(client.take_checkpoint(())) {
+            Err(ClientError::ServerError(err)) => {
+                assert_eq!(err.code, SyncErrorCode::NotInitialized as u32)
+            }
+            other => panic!("Unexpected response: {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Coordinates the submission of the transactions of the pool operators.
+//!
+//! A single operator account may sign the transactions of hundreds of workers, of several pools
+//! even. The coordinator allocates the nonces of each signing account one submission at a time,
+//! and limits the number of transactions in flight, letting the urgent ones in first.
+
+use crate::accounting::TxKind;
+use phaxt::Index;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, Mutex as TokioMutex, OwnedMutexGuard};
+
+/// The classes of the transactions, in the order they are let in flight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TxTier {
+    /// Registrations and the pool operations to get the workers computing.
+    Registration = 0,
+    /// Updates of the worker endpoints.
+    Endpoint = 1,
+    /// Offchain messages of the workers.
+    Mq = 2,
+}
+
+const NUM_TIERS: usize = 3;
+
+impl From<TxKind> for TxTier {
+    fn from(kind: TxKind) -> Self {
+        match kind {
+            TxKind::RegisterWorker
+            | TxKind::AddWorker
+            | TxKind::StartComputing
+            | TxKind::StopComputing => TxTier::Registration,
+            TxKind::UpdateEndpoint => TxTier::Endpoint,
+            TxKind::SyncMessage => TxTier::Mq,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    in_flight: usize,
+    waiters: [VecDeque<oneshot::Sender<()>>; NUM_TIERS],
+}
+
+/// The nonce after the last one used by an account, None if it's to be taken from the chain.
+type AccountNonce = Arc<TokioMutex<Option<Index>>>;
+
+pub struct SubmissionCoordinator {
+    /// Max number of transactions in flight, 0 for no limit.
+    max_in_flight: usize,
+    slots: Mutex<Slots>,
+    nonces: Mutex<HashMap<Vec<u8>, AccountNonce>>,
+}
+
+/// A transaction slot in flight, freed on drop.
+pub struct InFlightPermit {
+    coordinator: Arc<SubmissionCoordinator>,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        self.coordinator.release();
+    }
+}
+
+/// Exclusive access to the nonces of a signing account, held while submitting a transaction.
+pub struct NonceGuard(OwnedMutexGuard<Option<Index>>);
+
+impl NonceGuard {
+    /// The nonce to sign the next transaction with, given the next one known by the chain.
+    ///
+    /// The node may not count the transactions just submitted yet, so the nonce is never lower
+    /// than the one after the last used.
+    pub fn next(&self, chain_nonce: Index) -> Index {
+        self.0.map_or(chain_nonce, |next| next.max(chain_nonce))
+    }
+
+    pub fn used(&mut self, nonce: Index) {
+        *self.0 = Some(nonce + 1);
+    }
+
+    /// Forgets the nonces used, to take the chain's at the next submission. To be called once a
+    /// nonce is known to be left unused, e.g. its tx not submitted or dropped from the tx pool.
+    pub fn reset(&mut self) {
+        *self.0 = None;
+    }
+}
+
+impl SubmissionCoordinator {
+    pub fn new(max_in_flight: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max_in_flight,
+            slots: Default::default(),
+            nonces: Default::default(),
+        })
+    }
+
+    /// Waits for a slot in flight. The waiting transactions get the slots freed in the order of
+    /// their tiers, and in the order they arrived in the same tier.
+    pub async fn acquire(self: &Arc<Self>, tier: TxTier) -> InFlightPermit {
+        let rx = {
+            let mut slots = self.slots.lock().unwrap();
+            if self.max_in_flight == 0 || slots.in_flight < self.max_in_flight {
+                slots.in_flight += 1;
+                None
+            } else {
+                let (tx, rx) = oneshot::channel();
+                slots.waiters[tier as usize].push_back(tx);
+                Some(rx)
+            }
+        };
+        if let Some(rx) = rx {
+            // The slot is handed over by the permit released.
+            let _ = rx.await;
+        }
+        InFlightPermit {
+            coordinator: self.clone(),
+        }
+    }
+
+    fn release(&self) {
+        let mut slots = self.slots.lock().unwrap();
+        for waiters in slots.waiters.iter_mut() {
+            while let Some(waiter) = waiters.pop_front() {
+                if waiter.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        slots.in_flight -= 1;
+    }
+
+    /// Locks the nonces of the account, which is identified by its encoded id.
+    pub async fn lock_nonce(&self, account: Vec<u8>) -> NonceGuard {
+        let nonce = self
+            .nonces
+            .lock()
+            .unwrap()
+            .entry(account)
+            .or_default()
+            .clone();
+        NonceGuard(nonce.lock_owned().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_slots_freed_by_tier() {
+        block_on(async {
+            let coordinator = SubmissionCoordinator::new(1);
+            let permit = coordinator.acquire(TxTier::Mq).await;
+
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            for tier in [TxTier::Mq, TxTier::Endpoint, TxTier::Registration] {
+                let coordinator = coordinator.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let _permit = coordinator.acquire(tier).await;
+                    tx.send(tier).unwrap();
+                });
+                tokio::task::yield_now().await;
+            }
+            drop(permit);
+
+            let mut order = vec![];
+            for _ in 0..3 {
+                order.push(rx.recv().await.unwrap());
+            }
+            assert_eq!(order, [TxTier::Registration, TxTier::Endpoint, TxTier::Mq]);
+            assert_eq!(coordinator.slots.lock().unwrap().in_flight, 0);
+        });
+    }
+
+    #[test]
+    fn test_nonce_never_goes_back() {
+        block_on(async {
+            let coordinator = SubmissionCoordinator::new(0);
+            let mut nonce = coordinator.lock_nonce(vec![1]).await;
+            assert_eq!(nonce.next(5), 5);
+            nonce.used(5);
+            // The node hasn't seen the transaction submitted yet.
+            assert_eq!(nonce.next(5), 6);
+            assert_eq!(nonce.next(8), 8);
+            nonce.reset();
+            assert_eq!(nonce.next(5), 5);
+            drop(nonce);
+
+            // The tx of nonce 5 is dropped from the pool after the one of nonce 6 is submitted.
+            let mut nonce = coordinator.lock_nonce(vec![1]).await;
+            nonce.used(6);
+            drop(nonce);
+            coordinator.lock_nonce(vec![1]).await.reset();
+            assert_eq!(coordinator.lock_nonce(vec![1]).await.next(5), 5);
+
+            let other = coordinator.lock_nonce(vec![2]).await;
+            assert_eq!(other.next(0), 0);
+        });
+    }
+}
@@ -1,16 +1,18 @@
+use crate::accounting::{split_fee, FeeLedgerAccess, TxKind};
 use crate::api::TxStatusResponse;
 use crate::datasource::WrappedDataSourceManager;
 pub use crate::khala;
 use crate::khala::runtime_types::khala_parachain_runtime::ProxyType;
 use crate::khala::utility::events::ItemFailed;
 use crate::pool_operator::*;
+use crate::submission::{SubmissionCoordinator, TxTier};
 use crate::tx::TxManagerError::*;
 use crate::use_parachain_api;
 use anyhow::{anyhow, Error, Result};
 use chrono::{DateTime, Utc};
 use futures::future::BoxFuture;
 use hex::ToHex;
-use log::{debug, error};
+use log::{debug, error, warn};
 use moka_cht::HashMap;
 use parity_scale_codec::Encode;
 use phactory_api::prpc::GetEndpointResponse;
@@ -21,7 +23,7 @@ use pherry::mk_params;
 use serde::{Deserialize, Serialize};
 use sp_core::crypto::AccountId32;
 use sp_core::sr25519::Public as Sr25519Public;
-use std::collections::{HashMap as StdHashMap, VecDeque};
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
 use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -40,6 +42,8 @@ static TX_TIP: u128 = 0;
 static TX_QUEUE_CHUNK_SIZE: usize = 30;
 static TX_QUEUE_CHUNK_TIMEOUT_IN_MS: u64 = 1000;
 static TX_TIMEOUT_SECS: u64 = 60;
+/// Times to retry with the next nonce when the nonce is taken by another transaction in the pool.
+static TX_PRIORITY_RETRIES: u32 = 3;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum TransactionState {
@@ -120,6 +124,8 @@ pub struct Transaction {
     pub state: TransactionState,
     pub desc: String,
     pub pid: u64,
+    pub worker_id: String,
+    pub kind: TxKind,
     pub created_at: DateTime<Utc>,
     #[serde(skip)]
     pub tx_payload: Option<EncodedPayload>,
@@ -131,6 +137,8 @@ impl Transaction {
     pub fn new(
         id: usize,
         pid: u64,
+        worker_id: String,
+        kind: TxKind,
         tx_payload: EncodedPayload,
         desc: String,
         shot: oneshot::Sender<Result<()>>,
@@ -140,6 +148,8 @@ impl Transaction {
             state: TransactionState::Pending,
             desc,
             pid,
+            worker_id,
+            kind,
             created_at: Utc::now(),
             tx_payload: Some(tx_payload),
             shot: Some(shot),
@@ -151,6 +161,8 @@ impl Transaction {
             state: self.state.clone(),
             desc: self.desc.clone(),
             pid: self.pid,
+            worker_id: self.worker_id.clone(),
+            kind: self.kind,
             created_at: self.created_at,
             tx_payload: None,
             shot: None,
@@ -173,6 +185,9 @@ pub struct TxManager {
     running_txs: Mutex<Vec<usize>>,
     past_txs: Mutex<VecDeque<usize>>,
     channel_tx: mpsc::UnboundedSender<usize>,
+    coordinator: Arc<SubmissionCoordinator>,
+    /// Serializes the updates of the fee ledger.
+    fee_ledger_lock: Mutex<()>,
 }
 
 impl TxManager {
@@ -217,6 +232,7 @@ impl TxManager {
     pub fn new(
         path_base: &str,
         dsm: WrappedDataSourceManager,
+        max_in_flight: usize,
     ) -> Result<(Arc<Self>, BoxFuture<'static, Result<()>>)> {
         let opts = get_options(None);
         let path = Path::new(path_base).join("po");
@@ -233,6 +249,8 @@ impl TxManager {
             running_txs: Mutex::new(Vec::new()),
             past_txs: Mutex::new(VecDeque::new()),
             channel_tx: tx,
+            coordinator: SubmissionCoordinator::new(max_in_flight),
+            fee_ledger_lock: Mutex::new(()),
         });
         let handle = Box::pin(txm.clone().start_trader(rx));
 
@@ -264,22 +282,21 @@ impl TxManager {
             drop(running_txs);
             drop(pending_txs);
 
-            let mut tx_map: StdHashMap<u64, Vec<usize>> = StdHashMap::new();
+            // Batches the txs of a pool by tier, so that the messages don't hold back the
+            // registrations in flight.
+            let mut tx_map: BTreeMap<(TxTier, u64), Vec<usize>> = BTreeMap::new();
             for i in current_txs {
                 let tx = self.tx_map.get(&i).ok_or(UnknownDataMismatch)?;
-                let pid = tx.lock().await.pid;
-                if let Some(group) = tx_map.get_mut(&pid) {
-                    group.push(i);
-                } else {
-                    let group = vec![i];
-                    let _ = tx_map.insert(pid, group);
-                };
+                let tx = tx.lock().await;
+                let key = (TxTier::from(tx.kind), tx.pid);
+                drop(tx);
+                tx_map.entry(key).or_default().push(i);
             }
 
-            for (pid, v) in tx_map {
+            for ((tier, pid), v) in tx_map {
                 let txm = self.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = txm.wrap_send_tx_group(pid, v).await {
+                    if let Err(e) = txm.wrap_send_tx_group(pid, tier, v).await {
                         error!("wrap_send_tx_group: {e}");
                         std::process::exit(255);
                     }
@@ -300,7 +317,12 @@ impl TxManager {
         error!("Unexpected exit of start_trader!");
         std::process::exit(255);
     }
-    async fn wrap_send_tx_group(self: Arc<Self>, pid: u64, ids: Vec<usize>) -> Result<()> {
+    async fn wrap_send_tx_group(
+        self: Arc<Self>,
+        pid: u64,
+        tier: TxTier,
+        ids: Vec<usize>,
+    ) -> Result<()> {
         if ids.is_empty() {
             anyhow::bail!("TxGroup can't be empty!");
         }
@@ -312,7 +334,7 @@ impl TxManager {
             drop(tx);
         }
 
-        match self.clone().send_tx_group(pid, ids.clone()).await {
+        match self.clone().send_tx_group(pid, tier, ids.clone()).await {
             Ok(ret) => {
                 for (idx, r) in ret.into_iter().enumerate() {
                     let id = ids.get(idx).ok_or(UnknownDataMismatch)?;
@@ -345,7 +367,12 @@ impl TxManager {
         }
         Ok(())
     }
-    async fn send_tx_group(self: Arc<Self>, pid: u64, ids: Vec<usize>) -> Result<Vec<Result<()>>> {
+    async fn send_tx_group(
+        self: Arc<Self>,
+        pid: u64,
+        tier: TxTier,
+        ids: Vec<usize>,
+    ) -> Result<Vec<Result<()>>> {
         debug!("send_tx_group: {:?}", &ids);
         let po = self.db.get_po(pid)?.ok_or(InvalidPoolOperator)?;
         let proxied = po.proxied.is_some();
@@ -398,25 +425,86 @@ impl TxManager {
 
         let mut encoded = Vec::new();
         call.encode_call_data_to(&metadata, &mut encoded)?;
-        let nonce = api.extra_rpc().account_nonce(signer.account_id()).await?;
-        debug!("sending tx: 0x{}, with nonce={}", hex::encode(&encoded), nonce);
 
-        let params = mk_params(&api, TX_LONGEVITY, TX_TIP).await?;
-        let tx_progress = api
-            .tx()
-            .create_signed_with_nonce(&call, &signer, nonce, params)?
-            .submit_and_watch()
-            .await?;
+        // Held until the tx is finalized or timed out.
+        let _permit = self.coordinator.acquire(tier).await;
+        let mut nonce_guard = self
+            .coordinator
+            .lock_nonce(signer.account_id().encode())
+            .await;
+        let mut nonce =
+            nonce_guard.next(api.extra_rpc().account_nonce(signer.account_id()).await?);
+        let mut retries = 0;
+        let tx_progress = loop {
+            debug!("sending tx: 0x{}, with nonce={}", hex::encode(&encoded), nonce);
+            let params = mk_params(&api, TX_LONGEVITY, TX_TIP).await?;
+            let result = api
+                .tx()
+                .create_signed_with_nonce(&call, &signer, nonce, params)?
+                .submit_and_watch()
+                .await;
+            match result {
+                Ok(tx_progress) => {
+                    nonce_guard.used(nonce);
+                    break tx_progress;
+                }
+                Err(e) if is_priority_too_low(&e) && retries < TX_PRIORITY_RETRIES => {
+                    warn!(
+                        "Nonce {nonce} of pool #{pid} taken in the tx pool, retrying with the next one"
+                    );
+                    retries += 1;
+                    nonce += 1;
+                }
+                Err(e) => {
+                    // The nonce is left unused, take the chain's next time to fill the gap.
+                    nonce_guard.reset();
+                    return Err(e.into());
+                }
+            }
+        };
+        drop(nonce_guard);
 
         let tx_and_timeout = tokio::spawn(tokio::time::timeout(
             Duration::from_secs(TX_TIMEOUT_SECS),
             tx_progress.wait_for_finalized()
         )).await?;
         let tx = match tx_and_timeout {
-            Ok(tx) => tx,
-            Err(_) => anyhow::bail!("Tx timed out!"),
+            Ok(Ok(tx)) => tx,
+            result => {
+                // Not included, e.g. dropped from the tx pool, the later txs would wait for the
+                // nonce forever. Take the chain's next time to fill the gap.
+                self.coordinator
+                    .lock_nonce(signer.account_id().encode())
+                    .await
+                    .reset();
+                match result {
+                    Ok(Err(e)) => return Err(e.into()),
+                    _ => anyhow::bail!("Tx timed out!"),
+                }
+            }
         };
-        let tx = tx?.wait_for_success().await?;
+        let tx = tx.fetch_events().await?;
+
+        // Paid even if the tx failed, so recorded before looking into the result.
+        match tx.find_first::<khala::transaction_payment::events::TransactionFeePaid>() {
+            Ok(Some(event)) => self.record_fees(pid, &ids, event.actual_fee).await,
+            Ok(None) => warn!("TransactionFeePaid event not found for txs {:?}", &ids),
+            Err(e) => warn!("Failed to decode TransactionFeePaid event: {e}"),
+        }
+
+        for event in tx.iter() {
+            let event = event?;
+            if event.pallet_name() == "System" && event.variant_name() == "ExtrinsicFailed" {
+                match SubxtDispatchError::decode_from(event.field_bytes(), api.metadata())? {
+                    SubxtDispatchError::Module(e) => {
+                        anyhow::bail!("{}", &e);
+                    }
+                    e => {
+                        anyhow::bail!("NotAModuleError: {:?}", &e);
+                    }
+                };
+            }
+        }
 
         if proxied {
             let event_proxy = tx
@@ -480,9 +568,27 @@ impl TxManager {
         Ok(ret)
     }
 
+    async fn record_fees(&self, pid: u64, ids: &[usize], fee: u128) {
+        let _lock = self.fee_ledger_lock.lock().await;
+        for (id, fee) in ids.iter().zip(split_fee(fee, ids.len())) {
+            let (worker_id, kind) = match self.tx_map.get(id) {
+                Some(tx) => {
+                    let tx = tx.lock().await;
+                    (tx.worker_id.clone(), tx.kind)
+                }
+                None => continue,
+            };
+            if let Err(e) = self.db.record_fee(pid, &worker_id, kind, fee) {
+                warn!("Failed to record the fee of tx #{id}: {e}");
+            }
+        }
+    }
+
     pub async fn send_to_queue(
         &self,
         pid: u64,
+        worker_id: String,
+        kind: TxKind,
         tx_payload: EncodedPayload,
         desc: String,
     ) -> Result<()> {
@@ -500,7 +606,7 @@ impl TxManager {
         self.tx_map.insert(
             id,
             Arc::new(Mutex::new(Transaction::new(
-                id, pid, tx_payload, desc, shot,
+                id, pid, worker_id, kind, tx_payload, desc, shot,
             ))),
         );
         self.channel_tx.clone().send(id)?;
@@ -508,10 +614,16 @@ impl TxManager {
     }
 }
 
+/// Whether the tx is rejected by the tx pool for another tx of the same nonce is there already.
+fn is_priority_too_low(e: &subxt::Error) -> bool {
+    e.to_string().contains("Priority is too low")
+}
+
 impl TxManager {
     pub async fn register_worker(
         self: Arc<Self>,
         pid: u64,
+        worker_id: String,
         pruntime_info: Vec<u8>,
         attestation: Vec<u8>,
         v2: bool,
@@ -524,11 +636,14 @@ impl TxManager {
         };
 
         let desc = format!("Register worker for pool #{pid}");
-        self.clone().send_to_queue(pid, tx_payload, desc).await
+        self.clone()
+            .send_to_queue(pid, worker_id, TxKind::RegisterWorker, tx_payload, desc)
+            .await
     }
     pub async fn update_worker_endpoint(
         self: Arc<Self>,
         pid: u64,
+        worker_id: String,
         signed: GetEndpointResponse,
     ) -> Result<()> {
         let endpoint_payload = signed
@@ -541,20 +656,30 @@ impl TxManager {
             (Encoded(endpoint_payload), signature).encode(),
         );
         let desc = "Update endpoint of worker.".to_string();
-        self.clone().send_to_queue(pid, tx_payload, desc).await
+        self.clone()
+            .send_to_queue(pid, worker_id, TxKind::UpdateEndpoint, tx_payload, desc)
+            .await
     }
     pub async fn sync_offchain_message(
         self: Arc<Self>,
         pid: u64,
+        worker_id: String,
         signed_message: SignedMessage,
     ) -> Result<()> {
         let encoded = signed_message.encode();
         let tx_payload = EncodedPayload::new("PhalaMq", "sync_offchain_message", encoded);
         let desc = format!("Sync offchain message #{} from {}.",
             signed_message.sequence, signed_message.message.sender);
-        self.clone().send_to_queue(pid, tx_payload, desc).await
+        self.clone()
+            .send_to_queue(pid, worker_id, TxKind::SyncMessage, tx_payload, desc)
+            .await
     }
-    pub async fn add_worker(self: Arc<Self>, pid: u64, pubkey: Sr25519Public) -> Result<()> {
+    pub async fn add_worker(
+        self: Arc<Self>,
+        pid: u64,
+        worker_id: String,
+        pubkey: Sr25519Public,
+    ) -> Result<()> {
         let desc = format!(
             "Add worker 0x{} to pool #{pid}.",
             pubkey.encode_hex::<String>()
@@ -564,11 +689,14 @@ impl TxManager {
             "add_worker",
             (pid, Encoded(pubkey.encode())).encode(),
         );
-        self.clone().send_to_queue(pid, tx_payload, desc).await
+        self.clone()
+            .send_to_queue(pid, worker_id, TxKind::AddWorker, tx_payload, desc)
+            .await
     }
     pub async fn start_computing(
         self: Arc<Self>,
         pid: u64,
+        worker_id: String,
         worker: Sr25519Public,
         stake: String,
     ) -> Result<()> {
@@ -582,9 +710,16 @@ impl TxManager {
             "start_computing",
             (pid, Encoded(worker.encode()), stake.parse::<u128>()?).encode(),
         );
-        self.clone().send_to_queue(pid, tx_payload, desc).await
+        self.clone()
+            .send_to_queue(pid, worker_id, TxKind::StartComputing, tx_payload, desc)
+            .await
     }
-    pub async fn stop_computing(self: Arc<Self>, pid: u64, worker: Sr25519Public) -> Result<()> {
+    pub async fn stop_computing(
+        self: Arc<Self>,
+        pid: u64,
+        worker_id: String,
+        worker: Sr25519Public,
+    ) -> Result<()> {
         let desc = format!(
             "Stop computing for 0x{} in pool #{pid}.",
             worker.encode_hex::<String>()
@@ -594,6 +729,8 @@ impl TxManager {
             "stop_computing",
             (pid, Encoded(worker.encode())).encode(),
         );
-        self.clone().send_to_queue(pid, tx_payload, desc).await
+        self.clone()
+            .send_to_queue(pid, worker_id, TxKind::StopComputing, tx_payload, desc)
+            .await
     }
 }
\ No newline at end of file
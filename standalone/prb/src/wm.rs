@@ -1,27 +1,41 @@
+use crate::alert::{alert_loop, Alert, AlertConfig};
 use crate::api::{start_api_server, WorkerStatus};
 use crate::bus::Bus;
 use crate::cli::WorkerManagerCliArgs;
 use crate::repository::Repository;
-use crate::datasource::setup_data_source_manager;
+use crate::rollout::RolloutStatus;
+use crate::simulator::{simulated_workers, SimulationConfig};
+use crate::handover::HandoverStatus;
+use crate::datasource::{setup_data_source_manager, WrappedDataSourceManager};
+use crate::local_cache::{grab_loop as local_cache_grab_loop, LocalCache, LocalCacheConfig};
 use crate::inv_db::{get_all_workers, setup_inventory_db, WrappedDb};
 use crate::messages::{master_loop as message_master_loop, MessagesEvent};
 use crate::pool_operator::PoolOperatorAccess;
 use crate::processor::{Processor, ProcessorEvent};
 use crate::tx::TxManager;
-use crate::worker_status::{update_worker_status, WorkerStatusEvent};
+use crate::worker_status::{
+    update_worker_status, WorkerHistory, WorkerStatusEvent, WorkerStatusStreamTx,
+    WORKER_STATUS_STREAM_CAPACITY,
+};
 use chrono::{Timelike, Utc};
 use futures::future::{try_join4, try_join_all};
-use log::{error, info};
+use log::{error, info, warn};
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex as TokioMutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Mutex as TokioMutex};
 
 pub struct WorkerManagerContext {
     pub inv_db: WrappedDb,
     pub worker_status_map: Arc<TokioMutex<HashMap<String, WorkerStatus>>>,
+    pub worker_status_stream_tx: WorkerStatusStreamTx,
+    pub worker_history: Arc<TokioMutex<WorkerHistory>>,
     pub txm: Arc<TxManager>,
     pub bus: Arc<Bus>,
+    pub rollouts: Arc<TokioMutex<HashMap<String, RolloutStatus>>>,
+    pub handovers: Arc<TokioMutex<HashMap<String, HandoverStatus>>>,
+    pub dsm: WrappedDataSourceManager,
 }
 
 pub type WrappedWorkerManagerContext = Arc<WorkerManagerContext>;
@@ -29,10 +43,27 @@ pub type WrappedWorkerManagerContext = Arc<WorkerManagerContext>;
 pub async fn wm(args: WorkerManagerCliArgs) {
     info!("Staring prb-wm with {:?}", &args);
 
-    let (dsm, ds_handles) =
-        setup_data_source_manager(&args.data_source_config_path, args.cache_size)
+    let local_cache = args.local_cache.then(|| {
+        let opts = crate::pool_operator::get_options(None);
+        let path = std::path::Path::new(&args.db_path).join("cache");
+        let db = crate::pool_operator::DB::open(&opts, path).unwrap();
+        Arc::new(LocalCache::new(Arc::new(db)))
+    });
+
+    let (dsm, mut ds_handles) =
+        setup_data_source_manager(&args.data_source_config_path, args.cache_size, local_cache.clone())
             .await
             .expect("Initialize data source manager");
+    if let Some(local_cache) = local_cache {
+        let config = LocalCacheConfig {
+            relay_start: args.local_cache_relay_start,
+            para_start: args.local_cache_para_start,
+            justification_interval: args.local_cache_justification_interval,
+            storage_changes_batch: args.local_cache_storage_changes_batch,
+            interval: std::time::Duration::from_secs(args.local_cache_interval),
+        };
+        ds_handles.push(tokio::spawn(local_cache_grab_loop(local_cache, dsm.clone(), config)));
+    }
     let ds_join_handle = tokio::spawn(try_join_all(ds_handles));
 
     dsm.clone().wait_until_rpc_avail(false).await;
@@ -40,13 +71,21 @@ pub async fn wm(args: WorkerManagerCliArgs) {
     let (processor_tx, processor_rx) = std::sync::mpsc::channel::<ProcessorEvent>();
     let (messages_tx, messages_rx) = mpsc::unbounded_channel::<MessagesEvent>();
     let (worker_status_tx, worker_status_rx) = mpsc::unbounded_channel::<WorkerStatusEvent>();
+    let (alert_tx, alert_rx) = mpsc::unbounded_channel::<Alert>();
 
     let bus = Arc::new(Bus {
         processor_tx: processor_tx.clone(),
         messages_tx: messages_tx.clone(),
         worker_status_tx: worker_status_tx.clone(),
+        alert_tx,
     });
 
+    let alert_config = match &args.alert_config_path {
+        Some(path) => AlertConfig::read_from_file(path).expect("Failed to read alert config"),
+        None => AlertConfig::default(),
+    };
+    tokio::spawn(alert_loop(alert_config, args.webhook_url.clone(), alert_rx));
+
     let headers_db = {
         let opts = crate::pool_operator::get_options(None);
         let path = std::path::Path::new(&args.db_path).join("headers");
@@ -68,19 +107,47 @@ pub async fn wm(args: WorkerManagerCliArgs) {
     }
 
     let inv_db = setup_inventory_db(&args.db_path);
-    let (txm, txm_handle) = TxManager::new(&args.db_path, dsm.clone()).expect("TxManager");
+    let (txm, txm_handle) =
+        TxManager::new(&args.db_path, dsm.clone(), args.max_in_flight_txs).expect("TxManager");
     let ctx = Arc::new(WorkerManagerContext {
         inv_db: inv_db.clone(),
         txm: txm.clone(),
         worker_status_map: Arc::new(TokioMutex::new(HashMap::new())),
+        worker_status_stream_tx: broadcast::channel(WORKER_STATUS_STREAM_CAPACITY).0,
+        worker_history: Arc::new(TokioMutex::new(WorkerHistory::new(
+            args.worker_history_size,
+            chrono::Duration::seconds(args.worker_history_interval as i64),
+        ))),
         bus: bus.clone(),
+        rollouts: Arc::new(TokioMutex::new(HashMap::new())),
+        handovers: Arc::new(TokioMutex::new(HashMap::new())),
+        dsm: dsm.clone(),
     });
 
-    let workers = get_all_workers(inv_db.clone()).unwrap();
+    let workers = if args.simulate > 0 {
+        warn!("Simulation mode, the workers in the inventory are not started");
+        simulated_workers(
+            args.simulate,
+            SimulationConfig {
+                latency: Duration::from_millis(args.simulate_latency_ms),
+                latency_jitter: Duration::from_millis(args.simulate_latency_jitter_ms),
+                dispatch_latency_per_block: Duration::from_millis(args.simulate_block_ms),
+                failure_rate: args.simulate_failure_rate,
+            },
+        )
+    } else {
+        get_all_workers(inv_db.clone())
+            .unwrap()
+            .into_iter()
+            .map(|worker| {
+                let client = crate::pruntime::create_client(worker.endpoint.clone());
+                (worker, client)
+            })
+            .collect()
+    };
     let workers = workers
         .into_par_iter()
-        .map(|worker| {
-            let client = crate::pruntime::create_client(worker.endpoint.clone());
+        .map(|(worker, client)| {
             match worker.pid {
                 Some(pid) => {
                     let pool = match crate::inv_db::get_pool_by_pid(inv_db.clone(), pid) {
@@ -115,9 +182,15 @@ pub async fn wm(args: WorkerManagerCliArgs) {
 
     let timer_future = {
         let bus = bus.clone();
+        let get_egress_interval = Duration::from_secs(args.get_egress_interval);
         tokio::spawn(async move {
+            let mut egress_timer = std::time::Instant::now();
             loop {
                 let _ = bus.send_processor_event(ProcessorEvent::Heartbeat);
+                if !get_egress_interval.is_zero() && egress_timer.elapsed() >= get_egress_interval {
+                    egress_timer = std::time::Instant::now();
+                    let _ = bus.send_processor_event(ProcessorEvent::GetEgressMsgTimerReceived);
+                }
                 let nanos = 1_000_000_000 - Utc::now().nanosecond() % 1_000_000_000;
                 tokio::time::sleep(std::time::Duration::from_nanos(nanos.into())).await;
             };
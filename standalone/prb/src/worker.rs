@@ -1,5 +1,21 @@
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
+/// Number of the latest state transitions kept in the worker status.
+pub const MAX_STATE_TRANSITIONS: usize = 32;
+
+#[allow(deprecated)]
+const MIN_RECOVERY_BACKOFF: Duration = Duration::seconds(30);
+#[allow(deprecated)]
+const MAX_RECOVERY_BACKOFF: Duration = Duration::minutes(30);
+/// The recovery backoff starts over if the worker has not failed for this long.
+#[allow(deprecated)]
+const RECOVERY_RESET_PERIOD: Duration = Duration::hours(1);
+#[allow(deprecated)]
+const MIN_SYNC_PAUSE: Duration = Duration::minutes(1);
+#[allow(deprecated)]
+const MAX_SYNC_PAUSE: Duration = Duration::hours(1);
+
 pub enum WorkerLifecycleCommand {
     ShouldRestart,
     ShouldForceRegister,
@@ -14,8 +30,156 @@ pub enum WorkerLifecycleState {
     Preparing,
     Working,
     GatekeeperWorking,
+    SyncPaused,
 
     HasError(String),
     Restarting,
     Disabled,
-}
\ No newline at end of file
+}
+
+impl WorkerLifecycleState {
+    fn is_same_state(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// Whether the worker is allowed to move from this state to `next`.
+    ///
+    /// Staying in the same state is always allowed. A worker can error out or be restarted from
+    /// anywhere, and recovers from an error to whatever state it should be in.
+    pub fn can_transition_to(&self, next: &Self) -> bool {
+        use WorkerLifecycleState::*;
+        if self.is_same_state(next) {
+            return true;
+        }
+        match (self, next) {
+            (_, Restarting) => true,
+            // A restarted worker is added back from scratch.
+            (Restarting, Starting | Disabled) => true,
+            (Restarting, _) | (Disabled, _) => false,
+            (_, HasError(_)) | (HasError(_), _) => true,
+            (Starting, Synchronizing | Preparing | SyncPaused) => true,
+            (
+                Synchronizing | Preparing,
+                Synchronizing | Preparing | Working | GatekeeperWorking | SyncPaused,
+            ) => true,
+            (Working | GatekeeperWorking, Working | GatekeeperWorking | SyncPaused) => true,
+            (SyncPaused, Synchronizing | Preparing | Working | GatekeeperWorking) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateTransition {
+    pub from: WorkerLifecycleState,
+    pub to: WorkerLifecycleState,
+    pub at: DateTime<Utc>,
+}
+
+/// Appends a transition to the history, dropping the oldest ones beyond the limit.
+pub fn push_transition(transitions: &mut Vec<StateTransition>, transition: StateTransition) {
+    transitions.push(transition);
+    if transitions.len() > MAX_STATE_TRANSITIONS {
+        let excess = transitions.len() - MAX_STATE_TRANSITIONS;
+        transitions.drain(..excess);
+    }
+}
+
+/// The automatic recovery of a worker from errors, kept across restarts.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerLifecycle {
+    pub recovery_attempts: u32,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub recovery_pending: bool,
+}
+
+impl WorkerLifecycle {
+    /// Returns how long to wait before the next recovery, doubling for each attempt since the
+    /// worker last stayed healthy for the reset period.
+    pub fn next_recovery_delay(&mut self, now: DateTime<Utc>) -> Duration {
+        let healthy_since = self.last_error_at.map(|at| now.signed_duration_since(at));
+        if healthy_since.map_or(true, |d| d >= RECOVERY_RESET_PERIOD) {
+            self.recovery_attempts = 0;
+        }
+        let delay = MIN_RECOVERY_BACKOFF
+            .checked_mul(1 << self.recovery_attempts.min(16))
+            .unwrap_or(MAX_RECOVERY_BACKOFF)
+            .min(MAX_RECOVERY_BACKOFF);
+        self.recovery_attempts += 1;
+        self.last_error_at = Some(now);
+        delay
+    }
+}
+
+/// The consecutive failures to generate the sync request of a worker at the same position, so
+/// that a worker which can't make progress is paused instead of retrying forever.
+#[derive(Debug, Clone, Default)]
+pub struct SyncFailures {
+    /// The `(headernum, para_headernum, blocknum)` the sync failed at
+    pub position: (u32, u32, u32),
+    pub count: u32,
+    /// The state to go back to once the pause is over
+    pub paused_from: Option<WorkerLifecycleState>,
+}
+
+impl SyncFailures {
+    /// Records a failure, returning how long to pause the sync once `threshold` failures in a row
+    /// happened at the same position, doubling for each failure beyond it.
+    pub fn record(&mut self, position: (u32, u32, u32), threshold: u32) -> Option<Duration> {
+        if self.position != position {
+            self.position = position;
+            self.count = 0;
+        }
+        self.count += 1;
+        if self.count < threshold {
+            return None;
+        }
+        let pause = MIN_SYNC_PAUSE
+            .checked_mul(1 << (self.count - threshold).min(16))
+            .unwrap_or(MAX_SYNC_PAUSE)
+            .min(MAX_SYNC_PAUSE);
+        Some(pause)
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_failures_pause_with_backoff() {
+        let mut failures = SyncFailures::default();
+        let at = (10, 20, 20);
+        assert_eq!(failures.record(at, 3), None);
+        assert_eq!(failures.record(at, 3), None);
+        assert_eq!(failures.record(at, 3), Some(Duration::minutes(1)));
+        assert_eq!(failures.record(at, 3), Some(Duration::minutes(2)));
+        assert_eq!(failures.record(at, 3), Some(Duration::minutes(4)));
+        for _ in 0..10 {
+            failures.record(at, 3);
+        }
+        assert_eq!(failures.record(at, 3), Some(Duration::hours(1)));
+
+        // Failing somewhere else, or after a success, counts from scratch.
+        assert_eq!(failures.record((11, 20, 20), 3), None);
+        failures.record((11, 20, 20), 3);
+        failures.reset();
+        assert_eq!(failures.record((11, 20, 20), 3), None);
+    }
+
+    #[test]
+    fn paused_worker_resumes_where_it_was() {
+        use WorkerLifecycleState::*;
+        let paused = SyncPaused;
+        for state in [Synchronizing, Preparing, Working, GatekeeperWorking] {
+            assert!(state.can_transition_to(&paused));
+            assert!(paused.can_transition_to(&state));
+        }
+        assert!(!paused.can_transition_to(&Disabled));
+        assert!(paused.can_transition_to(&Restarting));
+    }
+}
@@ -1,14 +1,18 @@
 use crate::api::WorkerStatus;
-use crate::worker::WorkerLifecycleState;
+use crate::worker::{push_transition, StateTransition, WorkerLifecycleState};
 use crate::wm::WorkerManagerContext;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use anyhow::Result;
-use tokio::sync::mpsc;
+use phactory_api::prpc::Capability;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
 
 pub enum WorkerStatusUpdate {
     Update(Box<WorkerStatus>),
     UpdateMessage(String),
-    UpdateStateAndMessage((WorkerLifecycleState, String)),
+    UpdateStateAndMessage((WorkerLifecycleState, String, Option<StateTransition>)),
     UpdateSyncInfo((u32, u32, u32)),
     Delete,
 }
@@ -18,6 +22,203 @@ pub type WorkerStatusEvent = (String, WorkerStatusUpdate);
 pub type WorkerStatusRx = mpsc::UnboundedReceiver<WorkerStatusEvent>;
 pub type WorkerStatusTx = mpsc::UnboundedSender<WorkerStatusEvent>;
 
+pub const WORKER_STATUS_STREAM_CAPACITY: usize = 4096;
+
+/// The latest status of a worker, pushed to live subscribers after every change.
+/// `status` is `None` when the worker has been deleted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkerStatusStreamItem {
+    pub id: String,
+    pub status: Option<WorkerStatus>,
+}
+
+pub type WorkerStatusStreamTx = broadcast::Sender<WorkerStatusStreamItem>;
+pub type WorkerStatusStreamRx = broadcast::Receiver<WorkerStatusStreamItem>;
+
+/// A sample of the sync progress and the lifecycle state of a worker.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkerMetricsSample {
+    pub at: DateTime<Utc>,
+    pub headernum: u32,
+    pub para_headernum: u32,
+    pub blocknum: u32,
+    pub state: WorkerLifecycleState,
+    /// The number of times the worker ran into an error since it's recorded.
+    pub error_count: u32,
+}
+
+#[derive(Default)]
+struct WorkerSeries {
+    samples: VecDeque<WorkerMetricsSample>,
+    last_state: Option<WorkerLifecycleState>,
+    error_count: u32,
+}
+
+/// The recent metrics of each worker, kept in memory in a ring buffer per worker.
+///
+/// A sample is taken at the changes of the lifecycle state, and at most once per interval
+/// otherwise, so the buffer covers `capacity * interval` of a worker syncing steadily.
+pub struct WorkerHistory {
+    capacity: usize,
+    interval: Duration,
+    series: HashMap<String, WorkerSeries>,
+}
+
+impl WorkerHistory {
+    pub fn new(capacity: usize, interval: Duration) -> Self {
+        Self {
+            capacity,
+            interval,
+            series: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, worker_id: &str, status: &WorkerStatus, now: DateTime<Utc>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let series = self.series.entry(worker_id.to_string()).or_default();
+        let state_changed = !series
+            .last_state
+            .as_ref()
+            .is_some_and(|last| same_state(last, &status.state));
+        if state_changed && matches!(status.state, WorkerLifecycleState::HasError(_)) {
+            series.error_count += 1;
+        }
+        series.last_state = Some(status.state.clone());
+
+        let due = series
+            .samples
+            .back()
+            .map_or(true, |last| now - last.at >= self.interval);
+        if !state_changed && !due {
+            return;
+        }
+        let (headernum, para_headernum, blocknum) = status
+            .phactory_info
+            .as_ref()
+            .map_or((0, 0, 0), |info| (info.headernum, info.para_headernum, info.blocknum));
+        if series.samples.len() >= self.capacity {
+            series.samples.pop_front();
+        }
+        series.samples.push_back(WorkerMetricsSample {
+            at: now,
+            headernum,
+            para_headernum,
+            blocknum,
+            state: status.state.clone(),
+            error_count: series.error_count,
+        });
+    }
+
+    pub fn remove(&mut self, worker_id: &str) {
+        self.series.remove(worker_id);
+    }
+
+    /// The samples of the worker taken in `[from, to]`, the oldest first. None if the worker has
+    /// no history.
+    pub fn query(
+        &self,
+        worker_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Option<Vec<WorkerMetricsSample>> {
+        let series = self.series.get(worker_id)?;
+        let samples = series
+            .samples
+            .iter()
+            .skip_while(|sample| from.is_some_and(|from| sample.at < from))
+            .take_while(|sample| to.map_or(true, |to| sample.at <= to))
+            .cloned()
+            .collect();
+        Some(samples)
+    }
+}
+
+/// The workers running the same pRuntime build.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RuntimeVersionGroup {
+    pub version: String,
+    pub git_revision: String,
+    /// The capabilities advertised by the build, including the ones unknown to prb.
+    pub capabilities: Vec<String>,
+    pub workers: Vec<String>,
+}
+
+/// The pRuntime versions across the fleet, to plan the upgrades.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VersionMatrix {
+    /// The builds running, the most common first.
+    pub versions: Vec<RuntimeVersionGroup>,
+    /// The workers not advertising each capability known to prb.
+    pub missing_capabilities: BTreeMap<String, Vec<String>>,
+    /// The workers whose pRuntime hasn't answered yet.
+    pub unknown: Vec<String>,
+}
+
+impl VersionMatrix {
+    pub fn new<'a>(statuses: impl IntoIterator<Item = &'a WorkerStatus>) -> Self {
+        let mut versions: Vec<RuntimeVersionGroup> = vec![];
+        let mut missing_capabilities: BTreeMap<String, Vec<String>> = Capability::ALL
+            .iter()
+            .map(|capability| (capability.as_str().to_string(), vec![]))
+            .collect();
+        let mut unknown = vec![];
+        for status in statuses {
+            let id = status.worker.id.clone();
+            let Some(info) = &status.phactory_info else {
+                unknown.push(id);
+                continue;
+            };
+            for capability in Capability::ALL {
+                if !info.has_capability(capability) {
+                    missing_capabilities
+                        .entry(capability.as_str().to_string())
+                        .or_default()
+                        .push(id.clone());
+                }
+            }
+            let group = versions.iter_mut().find(|group| {
+                group.version == info.version && group.git_revision == info.git_revision
+            });
+            match group {
+                Some(group) => group.workers.push(id),
+                None => versions.push(RuntimeVersionGroup {
+                    version: info.version.clone(),
+                    git_revision: info.git_revision.clone(),
+                    capabilities: info.capabilities.clone(),
+                    workers: vec![id],
+                }),
+            }
+        }
+        for group in versions.iter_mut() {
+            group.workers.sort();
+        }
+        versions.sort_by(|a, b| {
+            b.workers
+                .len()
+                .cmp(&a.workers.len())
+                .then_with(|| a.version.cmp(&b.version))
+        });
+        for workers in missing_capabilities.values_mut() {
+            workers.sort();
+        }
+        unknown.sort();
+        Self {
+            versions,
+            missing_capabilities,
+            unknown,
+        }
+    }
+}
+
+fn same_state(a: &WorkerLifecycleState, b: &WorkerLifecycleState) -> bool {
+    match (a, b) {
+        (WorkerLifecycleState::HasError(a), WorkerLifecycleState::HasError(b)) => a == b,
+        _ => std::mem::discriminant(a) == std::mem::discriminant(b),
+    }
+}
+
 pub async fn update_worker_status(
     ctx: Arc<WorkerManagerContext>,
     mut rx: WorkerStatusRx,
@@ -32,7 +233,16 @@ pub async fn update_worker_status(
         let status_map = ctx.worker_status_map.clone();
         let mut status_map = status_map.lock().await;
 
+        let mut history = ctx.worker_history.lock().await;
+
+        let has_subscribers = ctx.worker_status_stream_tx.receiver_count() > 0;
+        let mut changed_ids = vec![];
+        let mut changed_set = HashSet::new();
+
         for (worker_id, update) in events {
+            if changed_set.insert(worker_id.clone()) {
+                changed_ids.push(worker_id.clone());
+            }
             match update {
                 WorkerStatusUpdate::Update(status) => {
                     status_map.insert(worker_id, *status);
@@ -42,10 +252,13 @@ pub async fn update_worker_status(
                         status.last_message = message;
                     });
                 },
-                WorkerStatusUpdate::UpdateStateAndMessage((state, message)) => {
+                WorkerStatusUpdate::UpdateStateAndMessage((state, message, transition)) => {
                     status_map.entry(worker_id).and_modify(|status| {
                         status.state = state;
                         status.last_message = message;
+                        if let Some(transition) = transition {
+                            push_transition(&mut status.transitions, transition);
+                        }
                     });
                 },
                 WorkerStatusUpdate::UpdateSyncInfo((headernum, para_headernum, blocknum)) => {
@@ -59,12 +272,150 @@ pub async fn update_worker_status(
 
                 },
                 WorkerStatusUpdate::Delete => {
-                    status_map.remove(&worker_id);
+                    // The history of a restarting worker is kept for the one restarted.
+                    if let Some(status) = status_map.remove(&worker_id) {
+                        if !matches!(status.state, WorkerLifecycleState::Restarting) {
+                            history.remove(&worker_id);
+                        }
+                    }
                 },
             }
         }
+
+        let now = Utc::now();
+        for worker_id in &changed_ids {
+            if let Some(status) = status_map.get(worker_id) {
+                history.record(worker_id, status, now);
+            }
+        }
+        drop(history);
+
+        if !has_subscribers {
+            continue;
+        }
+        for worker_id in changed_ids {
+            let status = status_map.get(&worker_id).cloned();
+            // Sending only fails when all subscribers have gone away, which is fine.
+            let _ = ctx.worker_status_stream_tx.send(WorkerStatusStreamItem {
+                id: worker_id,
+                status,
+            });
+        }
         drop(status_map);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inv_db::Worker;
+    use phactory_api::prpc::PhactoryInfo;
+
+    fn status(state: WorkerLifecycleState, headernum: u32) -> WorkerStatus {
+        WorkerStatus {
+            worker: Worker {
+                id: "w".into(),
+                name: "w".into(),
+                endpoint: String::new(),
+                stake: String::new(),
+                pid: None,
+                enabled: true,
+                sync_only: false,
+                gatekeeper: false,
+                groups: vec![],
+                sync_affinity: None,
+                to_block: None,
+            },
+            state,
+            phactory_info: Some(PhactoryInfo {
+                headernum,
+                ..Default::default()
+            }),
+            last_message: String::new(),
+            session_info: None,
+            transitions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_samples_per_interval_and_state_change() {
+        let mut history = WorkerHistory::new(16, Duration::seconds(30));
+        let t0 = Utc::now();
+        let at = |secs| t0 + Duration::seconds(secs);
+        let error = || WorkerLifecycleState::HasError("e".into());
+        history.record("w", &status(WorkerLifecycleState::Synchronizing, 1), at(0));
+        history.record("w", &status(WorkerLifecycleState::Synchronizing, 2), at(10));
+        history.record("w", &status(WorkerLifecycleState::Synchronizing, 3), at(30));
+        history.record("w", &status(error(), 3), at(31));
+        history.record("w", &status(WorkerLifecycleState::Restarting, 3), at(32));
+        history.record("w", &status(error(), 3), at(33));
+
+        let samples = history.query("w", None, None).unwrap();
+        let headers: Vec<_> = samples.iter().map(|s| s.headernum).collect();
+        assert_eq!(headers, [1, 3, 3, 3, 3]);
+        let errors: Vec<_> = samples.iter().map(|s| s.error_count).collect();
+        assert_eq!(errors, [0, 0, 1, 1, 2]);
+        assert!(history.query("other", None, None).is_none());
+    }
+
+    #[test]
+    fn test_bounded_range_query() {
+        let mut history = WorkerHistory::new(4, Duration::seconds(1));
+        let t0 = Utc::now();
+        for i in 0..10 {
+            let state = WorkerLifecycleState::Synchronizing;
+            history.record("w", &status(state, i), t0 + Duration::seconds(i as i64));
+        }
+        let all = history.query("w", None, None).unwrap();
+        assert_eq!(all.iter().map(|s| s.headernum).collect::<Vec<_>>(), [6, 7, 8, 9]);
+
+        let range = history
+            .query("w", Some(t0 + Duration::seconds(7)), Some(t0 + Duration::seconds(8)))
+            .unwrap();
+        assert_eq!(range.iter().map(|s| s.headernum).collect::<Vec<_>>(), [7, 8]);
+
+        history.remove("w");
+        assert!(history.query("w", None, None).is_none());
+    }
+
+    #[test]
+    fn test_version_matrix() {
+        let running = |id: &str, version: &str, capabilities: &[Capability]| {
+            let mut status = status(WorkerLifecycleState::Synchronizing, 1);
+            status.worker.id = id.into();
+            status.phactory_info = Some(PhactoryInfo {
+                version: version.into(),
+                capabilities: capabilities.iter().map(|c| c.as_str().into()).collect(),
+                ..Default::default()
+            });
+            status
+        };
+        let mut starting = status(WorkerLifecycleState::Starting, 0);
+        starting.worker.id = "d".into();
+        starting.phactory_info = None;
+        let statuses = [
+            running("c", "2.2.0", &[Capability::CombinedHeaderSync]),
+            running("a", "2.1.0", &[]),
+            running("b", "2.2.0", &[Capability::CombinedHeaderSync]),
+            starting,
+        ];
+
+        let matrix = VersionMatrix::new(&statuses);
+        let versions: Vec<_> = matrix
+            .versions
+            .iter()
+            .map(|group| (group.version.as_str(), group.workers.clone()))
+            .collect();
+        assert_eq!(
+            versions,
+            [
+                ("2.2.0", vec!["b".to_string(), "c".into()]),
+                ("2.1.0", vec!["a".into()])
+            ]
+        );
+        assert_eq!(matrix.missing_capabilities["combined-header-sync"], ["a"]);
+        assert_eq!(matrix.missing_capabilities["paged-egress"], ["a", "b", "c"]);
+        assert_eq!(matrix.unknown, ["d"]);
+    }
+}
@@ -178,6 +178,7 @@ fn rpc_type(method: &str) -> RpcType {
             InitRuntime => Private,
             GetRuntimeInfo => Private,
             GetEgressMessages => Private,
+            GetEgressMessagesPaged => Private,
             GetWorkerState => Private,
             AddEndpoint => Private,
             RefreshEndpointSigningTime => Private,
@@ -197,8 +198,10 @@ fn rpc_type(method: &str) -> RpcType {
             HttpFetch => Private,
             GetNetworkConfig => Private,
             LoadChainState => Private,
+            GetChainState => Private,
             Stop => Private,
             LoadStorageProof => Private,
+            LoadStorageProofAt => Private,
             TakeCheckpoint => Private,
 
             GetInfo => Public,
@@ -229,6 +232,7 @@ fn default_payload_limit_for_method(method: PhactoryAPIMethod) -> ByteUnit {
         InitRuntime => 10.mebibytes(),
         GetRuntimeInfo => 1.kibibytes(),
         GetEgressMessages => 1.kibibytes(),
+        GetEgressMessagesPaged => 1.kibibytes(),
         ContractQuery => 500.kibibytes(),
         GetWorkerState => 1.kibibytes(),
         AddEndpoint => 10.kibibytes(),
@@ -253,8 +257,10 @@ fn default_payload_limit_for_method(method: PhactoryAPIMethod) -> ByteUnit {
         CalculateContractId => 1.kibibytes(),
         GetNetworkConfig => 1.kibibytes(),
         LoadChainState => 500.mebibytes(),
+        GetChainState => 1.kibibytes(),
         Stop => 1.kibibytes(),
         LoadStorageProof => 10.mebibytes(),
+        LoadStorageProofAt => 10.mebibytes(),
         TakeCheckpoint => 1.kibibytes(),
         Statistics => 100.kibibytes(),
 